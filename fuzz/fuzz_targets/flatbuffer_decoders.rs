@@ -0,0 +1,22 @@
+#![no_main]
+
+use hyperlight_common::flatbuffer_wrappers::function_call::{
+    validate_guest_function_call_buffer, validate_host_function_call_buffer, FunctionCall,
+};
+use hyperlight_common::flatbuffer_wrappers::function_types::ReturnValue;
+use hyperlight_common::flatbuffer_wrappers::guest_error::GuestError;
+use hyperlight_common::flatbuffer_wrappers::guest_log_data::GuestLogData;
+use hyperlight_common::flatbuffer_wrappers::host_function_details::HostFunctionDetails;
+use libfuzzer_sys::fuzz_target;
+
+// This fuzz target feeds arbitrary bytes to every decoder used on buffers read from
+// shared memory. A corrupted or malicious buffer must produce an error, never a panic or UB.
+fuzz_target!(|data: &[u8]| {
+    let _ = validate_guest_function_call_buffer(data);
+    let _ = validate_host_function_call_buffer(data);
+    let _ = FunctionCall::try_from(data);
+    let _ = ReturnValue::try_from(data);
+    let _ = GuestError::try_from(data);
+    let _ = GuestLogData::try_from(data);
+    let _ = HostFunctionDetails::try_from(data);
+});
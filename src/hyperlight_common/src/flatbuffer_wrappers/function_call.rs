@@ -14,23 +14,78 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
 use anyhow::{bail, Error, Result};
-use flatbuffers::{size_prefixed_root, WIPOffset};
+use flatbuffers::{FlatBufferBuilder, Vector, WIPOffset};
 #[cfg(feature = "tracing")]
 use tracing::{instrument, Span};
 
-use super::function_types::{ParameterValue, ReturnType};
+use super::function_types::{ParameterRef, ParameterValue, ReturnType};
+use super::util::verified_size_prefixed_root;
 use crate::flatbuffers::hyperlight::generated::{
-    hlbool, hlboolArgs, hldouble, hldoubleArgs, hlfloat, hlfloatArgs, hlint, hlintArgs, hllong,
-    hllongArgs, hlstring, hlstringArgs, hluint, hluintArgs, hlulong, hlulongArgs, hlvecbytes,
+    hlbool, hlboolArgs, hlbyte, hlbyteArgs, hldouble, hldoubleArgs, hlfloat, hlfloatArgs, hlint,
+    hlintArgs, hllong, hllongArgs, hlshort, hlshortArgs, hlstring, hlstringArgs, hlubyte,
+    hlubyteArgs, hluint, hluintArgs, hlulong, hlulongArgs, hlushort, hlushortArgs, hlvecbytes,
     hlvecbytesArgs, FunctionCall as FbFunctionCall, FunctionCallArgs as FbFunctionCallArgs,
     FunctionCallType as FbFunctionCallType, Parameter, ParameterArgs,
     ParameterValue as FbParameterValue,
 };
 
+/// The name of the guest function, provided by the guest library, that
+/// returns the signatures of the guest's registered functions as a
+/// `HostFunctionDetails` flatbuffer, which the host uses to check the
+/// parameters of guest function calls before making them.
+pub const GUEST_FUNCTION_DETAILS_FUNCTION_NAME: &str = "GetGuestFunctionDetails";
+
+/// The name of the optional guest function, taking no parameters and
+/// returning `Void`, that the host calls between guest function calls to
+/// ask the guest to release its caches and compact its heap. The memory it
+/// leaves behind becomes the state the sandbox is restored to, and the
+/// host takes back the pages it leaves zeroed.
+pub const TRIM_MEMORY_FUNCTION_NAME: &str = "hl_trim_memory";
+
+/// The separator between the namespaces and the name of a namespaced
+/// guest function, as in `math::add`.
+pub const FUNCTION_NAMESPACE_SEPARATOR: &str = "::";
+
+/// The name of `function_name` in `namespace`, e.g. `math::add` for the
+/// function `add` in the namespace `math`.
+pub fn namespaced_function_name(namespace: &str, function_name: &str) -> String {
+    format!("{namespace}{FUNCTION_NAMESPACE_SEPARATOR}{function_name}")
+}
+
+/// Whether `function_name` is in `namespace`, or in a namespace nested in
+/// it: `math::trig::sin` is in both `math::trig` and `math`.
+pub fn is_in_namespace(function_name: &str, namespace: &str) -> bool {
+    function_name
+        .strip_prefix(namespace)
+        .is_some_and(|rest| rest.starts_with(FUNCTION_NAMESPACE_SEPARATOR))
+}
+
+/// The separator between the name and the version of a versioned guest
+/// function, as in `Compute@v2`.
+pub const FUNCTION_VERSION_SEPARATOR: &str = "@v";
+
+/// The name of `version` of `function_name`, e.g. `Compute@v2` for version
+/// 2 of `Compute`.
+pub fn versioned_function_name(function_name: &str, version: u32) -> String {
+    format!("{function_name}{FUNCTION_VERSION_SEPARATOR}{version}")
+}
+
+/// Split the name of a versioned guest function, such as `Compute@v2`,
+/// into the function's name and its version, or return `None` if
+/// `function_name` has no version.
+pub fn split_function_version(function_name: &str) -> Option<(&str, u32)> {
+    let (name, version) = function_name.rsplit_once(FUNCTION_VERSION_SEPARATOR)?;
+    if name.is_empty() || !version.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((name, version.parse().ok()?))
+}
+
 /// The type of function call.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FunctionCallType {
@@ -76,8 +131,9 @@ impl FunctionCall {
 
 #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
 pub fn validate_guest_function_call_buffer(function_call_buffer: &[u8]) -> Result<()> {
-    let guest_function_call_fb = size_prefixed_root::<FbFunctionCall>(function_call_buffer)
-        .map_err(|e| anyhow::anyhow!("Error reading function call buffer: {:?}", e))?;
+    let guest_function_call_fb =
+        verified_size_prefixed_root::<FbFunctionCall>(function_call_buffer)
+            .map_err(|e| anyhow::anyhow!("Error reading function call buffer: {:?}", e))?;
     match guest_function_call_fb.function_call_type() {
         FbFunctionCallType::guest => Ok(()),
         other => {
@@ -88,7 +144,7 @@ pub fn validate_guest_function_call_buffer(function_call_buffer: &[u8]) -> Resul
 
 #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
 pub fn validate_host_function_call_buffer(function_call_buffer: &[u8]) -> Result<()> {
-    let host_function_call_fb = size_prefixed_root::<FbFunctionCall>(function_call_buffer)
+    let host_function_call_fb = verified_size_prefixed_root::<FbFunctionCall>(function_call_buffer)
         .map_err(|e| anyhow::anyhow!("Error reading function call buffer: {:?}", e))?;
     match host_function_call_fb.function_call_type() {
         FbFunctionCallType::host => Ok(()),
@@ -102,7 +158,7 @@ impl TryFrom<&[u8]> for FunctionCall {
     type Error = Error;
     #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
     fn try_from(value: &[u8]) -> Result<Self> {
-        let function_call_fb = size_prefixed_root::<FbFunctionCall>(value)
+        let function_call_fb = verified_size_prefixed_root::<FbFunctionCall>(value)
             .map_err(|e| anyhow::anyhow!("Error reading function call buffer: {:?}", e))?;
         let function_name = function_call_fb.function_name();
         let function_call_type = match function_call_fb.function_call_type() {
@@ -136,162 +192,177 @@ impl TryFrom<FunctionCall> for Vec<u8> {
     type Error = Error;
     #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
     fn try_from(value: FunctionCall) -> Result<Vec<u8>> {
-        let mut builder = flatbuffers::FlatBufferBuilder::new();
-        let function_name = builder.create_string(&value.function_name);
+        let parameters: Vec<ParameterRef<'_>> = value
+            .parameters
+            .iter()
+            .flatten()
+            .map(ParameterRef::from)
+            .collect();
+        serialize_function_call(
+            &value.function_name,
+            &parameters,
+            value.function_call_type,
+            value.expected_return_type,
+        )
+    }
+}
 
-        let function_call_type = match value.function_call_type {
-            FunctionCallType::Guest => FbFunctionCallType::guest,
-            FunctionCallType::Host => FbFunctionCallType::host,
-        };
+/// Serialize a call to `function_name` with the borrowed `parameters`,
+/// exactly as the equivalent `FunctionCall` is serialized, but copying the
+/// data of string and byte array parameters straight into the flatbuffer.
+#[cfg_attr(feature = "tracing", instrument(err(Debug), skip(parameters), parent = Span::current(), level= "Trace"))]
+pub fn serialize_function_call(
+    function_name: &str,
+    parameters: &[ParameterRef<'_>],
+    function_call_type: FunctionCallType,
+    expected_return_type: ReturnType,
+) -> Result<Vec<u8>> {
+    let mut builder = flatbuffers::FlatBufferBuilder::new();
+    let function_name = builder.create_string(function_name);
 
-        let expected_return_type = value.expected_return_type.into();
-
-        let parameters = match &value.parameters {
-            Some(p) => {
-                let num_items = p.len();
-                let mut parameters: Vec<WIPOffset<Parameter>> = Vec::with_capacity(num_items);
-
-                for param in p {
-                    match param {
-                        ParameterValue::Int(i) => {
-                            let hlint = hlint::create(&mut builder, &hlintArgs { value: *i });
-                            let parameter = Parameter::create(
-                                &mut builder,
-                                &ParameterArgs {
-                                    value_type: FbParameterValue::hlint,
-                                    value: Some(hlint.as_union_value()),
-                                },
-                            );
-                            parameters.push(parameter);
-                        }
-                        ParameterValue::UInt(ui) => {
-                            let hluint = hluint::create(&mut builder, &hluintArgs { value: *ui });
-                            let parameter = Parameter::create(
-                                &mut builder,
-                                &ParameterArgs {
-                                    value_type: FbParameterValue::hluint,
-                                    value: Some(hluint.as_union_value()),
-                                },
-                            );
-                            parameters.push(parameter);
-                        }
-                        ParameterValue::Long(l) => {
-                            let hllong = hllong::create(&mut builder, &hllongArgs { value: *l });
-                            let parameter = Parameter::create(
-                                &mut builder,
-                                &ParameterArgs {
-                                    value_type: FbParameterValue::hllong,
-                                    value: Some(hllong.as_union_value()),
-                                },
-                            );
-                            parameters.push(parameter);
-                        }
-                        ParameterValue::ULong(ul) => {
-                            let hlulong =
-                                hlulong::create(&mut builder, &hlulongArgs { value: *ul });
-                            let parameter = Parameter::create(
-                                &mut builder,
-                                &ParameterArgs {
-                                    value_type: FbParameterValue::hlulong,
-                                    value: Some(hlulong.as_union_value()),
-                                },
-                            );
-                            parameters.push(parameter);
-                        }
-                        ParameterValue::Float(f) => {
-                            let hlfloat = hlfloat::create(&mut builder, &hlfloatArgs { value: *f });
-                            let parameter = Parameter::create(
-                                &mut builder,
-                                &ParameterArgs {
-                                    value_type: FbParameterValue::hlfloat,
-                                    value: Some(hlfloat.as_union_value()),
-                                },
-                            );
-                            parameters.push(parameter);
-                        }
-                        ParameterValue::Double(d) => {
-                            let hldouble =
-                                hldouble::create(&mut builder, &hldoubleArgs { value: *d });
-                            let parameter = Parameter::create(
-                                &mut builder,
-                                &ParameterArgs {
-                                    value_type: FbParameterValue::hldouble,
-                                    value: Some(hldouble.as_union_value()),
-                                },
-                            );
-                            parameters.push(parameter);
-                        }
-                        ParameterValue::Bool(b) => {
-                            let hlbool: WIPOffset<hlbool<'_>> =
-                                hlbool::create(&mut builder, &hlboolArgs { value: *b });
-                            let parameter = Parameter::create(
-                                &mut builder,
-                                &ParameterArgs {
-                                    value_type: FbParameterValue::hlbool,
-                                    value: Some(hlbool.as_union_value()),
-                                },
-                            );
-                            parameters.push(parameter);
-                        }
-                        ParameterValue::String(s) => {
-                            let hlstring = {
-                                let val = builder.create_string(s.as_str());
-                                hlstring::create(&mut builder, &hlstringArgs { value: Some(val) })
-                            };
-                            let parameter = Parameter::create(
-                                &mut builder,
-                                &ParameterArgs {
-                                    value_type: FbParameterValue::hlstring,
-                                    value: Some(hlstring.as_union_value()),
-                                },
-                            );
-                            parameters.push(parameter);
-                        }
-                        ParameterValue::VecBytes(v) => {
-                            let vec_bytes = builder.create_vector(v);
-
-                            let hlvecbytes = hlvecbytes::create(
-                                &mut builder,
-                                &hlvecbytesArgs {
-                                    value: Some(vec_bytes),
-                                },
-                            );
-                            let parameter = Parameter::create(
-                                &mut builder,
-                                &ParameterArgs {
-                                    value_type: FbParameterValue::hlvecbytes,
-                                    value: Some(hlvecbytes.as_union_value()),
-                                },
-                            );
-                            parameters.push(parameter);
-                        }
-                    }
-                }
-                parameters
-            }
-            None => Vec::new(),
-        };
+    let function_call_type = match function_call_type {
+        FunctionCallType::Guest => FbFunctionCallType::guest,
+        FunctionCallType::Host => FbFunctionCallType::host,
+    };
 
-        let parameters = if !parameters.is_empty() {
-            Some(builder.create_vector(&parameters))
-        } else {
-            None
-        };
+    let expected_return_type = expected_return_type.into();
 
-        let function_call = FbFunctionCall::create(
-            &mut builder,
-            &FbFunctionCallArgs {
-                function_name: Some(function_name),
-                parameters,
-                function_call_type,
-                expected_return_type,
-            },
-        );
-        builder.finish_size_prefixed(function_call, None);
-        let res = builder.finished_data().to_vec();
+    let parameters: Vec<WIPOffset<Parameter>> = parameters
+        .iter()
+        .map(|param| create_parameter(&mut builder, param))
+        .collect();
+
+    let parameters = if !parameters.is_empty() {
+        Some(builder.create_vector(&parameters))
+    } else {
+        None
+    };
 
-        Ok(res)
+    let function_call = FbFunctionCall::create(
+        &mut builder,
+        &FbFunctionCallArgs {
+            function_name: Some(function_name),
+            parameters,
+            function_call_type,
+            expected_return_type,
+        },
+    );
+    builder.finish_size_prefixed(function_call, None);
+    let res = builder.finished_data().to_vec();
+
+    Ok(res)
+}
+
+/// Create the flatbuffer `Parameter` holding `param`
+fn create_parameter<'a>(
+    builder: &mut FlatBufferBuilder<'a>,
+    param: &ParameterRef<'_>,
+) -> WIPOffset<Parameter<'a>> {
+    let (value_type, value) = match *param {
+        ParameterRef::Int(i) => (
+            FbParameterValue::hlint,
+            hlint::create(builder, &hlintArgs { value: i }).as_union_value(),
+        ),
+        ParameterRef::UInt(ui) => (
+            FbParameterValue::hluint,
+            hluint::create(builder, &hluintArgs { value: ui }).as_union_value(),
+        ),
+        ParameterRef::Long(l) => (
+            FbParameterValue::hllong,
+            hllong::create(builder, &hllongArgs { value: l }).as_union_value(),
+        ),
+        ParameterRef::ULong(ul) => (
+            FbParameterValue::hlulong,
+            hlulong::create(builder, &hlulongArgs { value: ul }).as_union_value(),
+        ),
+        ParameterRef::Float(f) => (
+            FbParameterValue::hlfloat,
+            hlfloat::create(builder, &hlfloatArgs { value: f }).as_union_value(),
+        ),
+        ParameterRef::Double(d) => (
+            FbParameterValue::hldouble,
+            hldouble::create(builder, &hldoubleArgs { value: d }).as_union_value(),
+        ),
+        ParameterRef::Bool(b) => (
+            FbParameterValue::hlbool,
+            hlbool::create(builder, &hlboolArgs { value: b }).as_union_value(),
+        ),
+        ParameterRef::Short(x) => (
+            FbParameterValue::hlshort,
+            hlshort::create(builder, &hlshortArgs { value: x }).as_union_value(),
+        ),
+        ParameterRef::UShort(x) => (
+            FbParameterValue::hlushort,
+            hlushort::create(builder, &hlushortArgs { value: x }).as_union_value(),
+        ),
+        ParameterRef::Byte(x) => (
+            FbParameterValue::hlbyte,
+            hlbyte::create(builder, &hlbyteArgs { value: x }).as_union_value(),
+        ),
+        ParameterRef::UByte(x) => (
+            FbParameterValue::hlubyte,
+            hlubyte::create(builder, &hlubyteArgs { value: x }).as_union_value(),
+        ),
+        ParameterRef::Str(s) => {
+            let val = builder.create_string(s);
+            (
+                FbParameterValue::hlstring,
+                hlstring::create(builder, &hlstringArgs { value: Some(val) }).as_union_value(),
+            )
+        }
+        ParameterRef::Bytes(v) => {
+            let vec_bytes = builder.create_vector(v);
+            (
+                FbParameterValue::hlvecbytes,
+                hlvecbytes::create(
+                    builder,
+                    &hlvecbytesArgs {
+                        value: Some(vec_bytes),
+                    },
+                )
+                .as_union_value(),
+            )
+        }
+        ParameterRef::BytesSegments(segments) => {
+            let vec_bytes = create_vector_from_segments(builder, segments);
+            (
+                FbParameterValue::hlvecbytes,
+                hlvecbytes::create(
+                    builder,
+                    &hlvecbytesArgs {
+                        value: Some(vec_bytes),
+                    },
+                )
+                .as_union_value(),
+            )
+        }
+    };
+    Parameter::create(
+        builder,
+        &ParameterArgs {
+            value_type,
+            value: Some(value),
+        },
+    )
+}
+
+/// Create a single flatbuffer vector holding the contents of each of
+/// `segments` in order, copying each segment into the builder directly
+fn create_vector_from_segments<'a>(
+    builder: &mut FlatBufferBuilder<'a>,
+    segments: &[Vec<u8>],
+) -> WIPOffset<Vector<'a, u8>> {
+    let len = segments.iter().map(Vec::len).sum();
+    builder.start_vector::<u8>(len);
+    // flatbuffers are built back to front
+    for byte in segments
+        .iter()
+        .rev()
+        .flat_map(|segment| segment.iter().rev())
+    {
+        builder.push(*byte);
     }
+    builder.end_vector(len)
 }
 
 #[cfg(test)]
@@ -349,4 +420,136 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn vec_bytes_segments_are_received_contiguously() -> Result<()> {
+        let test_data: Vec<u8> = FunctionCall::new(
+            "SetByteArrayToZero".to_string(),
+            Some(vec![ParameterValue::VecBytesSegments(vec![
+                vec![1, 2, 3],
+                vec![],
+                vec![4],
+                vec![5, 6],
+            ])]),
+            FunctionCallType::Guest,
+            ReturnType::VecBytes,
+        )
+        .try_into()?;
+
+        let function_call = FunctionCall::try_from(test_data.as_slice())?;
+        assert_eq!(
+            Some(vec![ParameterValue::VecBytes(vec![1, 2, 3, 4, 5, 6])]),
+            function_call.parameters
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn borrowed_parameters_serialize_like_owned_ones() -> Result<()> {
+        let bytes = [1u8, 2, 3];
+        let owned: Vec<u8> = FunctionCall::new(
+            "Echo".to_string(),
+            Some(vec![
+                ParameterValue::String("hello".to_string()),
+                ParameterValue::VecBytes(bytes.to_vec()),
+                ParameterValue::Int(4),
+            ]),
+            FunctionCallType::Guest,
+            ReturnType::String,
+        )
+        .try_into()?;
+        let borrowed = serialize_function_call(
+            "Echo",
+            &[
+                "hello".into(),
+                bytes.as_slice().into(),
+                ParameterRef::Int(4),
+            ],
+            FunctionCallType::Guest,
+            ReturnType::String,
+        )?;
+        assert_eq!(owned, borrowed);
+
+        let function_call = FunctionCall::try_from(borrowed.as_slice())?;
+        assert_eq!(
+            Some(vec![
+                ParameterValue::String("hello".to_string()),
+                ParameterValue::VecBytes(bytes.to_vec()),
+                ParameterValue::Int(4),
+            ]),
+            function_call.parameters
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn small_integer_parameters() -> Result<()> {
+        let parameters = vec![
+            ParameterValue::Short(i16::MIN),
+            ParameterValue::UShort(u16::MAX),
+            ParameterValue::Byte(-1),
+            ParameterValue::UByte(u8::MAX),
+        ];
+        let buffer: Vec<u8> = FunctionCall::new(
+            "ReadSensor".to_string(),
+            Some(parameters.clone()),
+            FunctionCallType::Guest,
+            ReturnType::UShort,
+        )
+        .try_into()?;
+        let function_call = FunctionCall::try_from(buffer.as_slice())?;
+        assert_eq!(Some(parameters), function_call.parameters);
+        assert_eq!(ReturnType::UShort, function_call.expected_return_type);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_truncated_or_corrupted_flatbuffer() {
+        let test_data: Vec<u8> = FunctionCall::new(
+            "PrintOutput".to_string(),
+            Some(vec![ParameterValue::String("hello".to_string())]),
+            FunctionCallType::Guest,
+            ReturnType::Int,
+        )
+        .try_into()
+        .unwrap();
+
+        assert!(FunctionCall::try_from(&test_data[..0]).is_err());
+        assert!(FunctionCall::try_from(&test_data[..test_data.len() - 1]).is_err());
+
+        let mut corrupted = test_data.clone();
+        corrupted[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(FunctionCall::try_from(corrupted.as_slice()).is_err());
+
+        let mut corrupted = test_data;
+        let root_offset = 4 + u32::from_le_bytes(corrupted[4..8].try_into().unwrap()) as usize;
+        corrupted[root_offset..root_offset + 4].copy_from_slice(&i32::MAX.to_le_bytes());
+        assert!(FunctionCall::try_from(corrupted.as_slice()).is_err());
+    }
+
+    #[test]
+    fn namespaces() {
+        assert_eq!("math::add", namespaced_function_name("math", "add"));
+        assert!(is_in_namespace("math::add", "math"));
+        assert!(is_in_namespace("math::trig::sin", "math"));
+        assert!(is_in_namespace("math::trig::sin", "math::trig"));
+        assert!(!is_in_namespace("math", "math"));
+        assert!(!is_in_namespace("mathematics::add", "math"));
+        assert!(!is_in_namespace("add", "math"));
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!("Compute@v2", versioned_function_name("Compute", 2));
+        assert_eq!(Some(("Compute", 2)), split_function_version("Compute@v2"));
+        assert_eq!(
+            Some(("math::add", 10)),
+            split_function_version("math::add@v10")
+        );
+        assert_eq!(None, split_function_version("Compute"));
+        assert_eq!(None, split_function_version("Compute@v"));
+        assert_eq!(None, split_function_version("Compute@v+1"));
+        assert_eq!(None, split_function_version("Compute@vnext"));
+        assert_eq!(None, split_function_version("@v1"));
+    }
 }
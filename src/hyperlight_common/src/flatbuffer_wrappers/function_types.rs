@@ -18,14 +18,15 @@ use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
 use anyhow::{anyhow, bail, Error, Result};
-use flatbuffers::size_prefixed_root;
 #[cfg(feature = "tracing")]
 use tracing::{instrument, Span};
 
+use super::util::verified_size_prefixed_root;
 use crate::flatbuffers::hyperlight::generated::{
-    hlbool, hlboolArgs, hldouble, hldoubleArgs, hlfloat, hlfloatArgs, hlint, hlintArgs, hllong,
-    hllongArgs, hlsizeprefixedbuffer, hlsizeprefixedbufferArgs, hlstring, hlstringArgs, hluint,
-    hluintArgs, hlulong, hlulongArgs, hlvoid, hlvoidArgs,
+    hlbool, hlboolArgs, hlbyte, hlbyteArgs, hldouble, hldoubleArgs, hlfloat, hlfloatArgs, hlint,
+    hlintArgs, hllong, hllongArgs, hlshort, hlshortArgs, hlsizeprefixedbuffer,
+    hlsizeprefixedbufferArgs, hlstring, hlstringArgs, hlubyte, hlubyteArgs, hluint, hluintArgs,
+    hlulong, hlulongArgs, hlushort, hlushortArgs, hlvoid, hlvoidArgs,
     FunctionCallResult as FbFunctionCallResult, FunctionCallResultArgs as FbFunctionCallResultArgs,
     Parameter, ParameterType as FbParameterType, ParameterValue as FbParameterValue,
     ReturnType as FbReturnType, ReturnValue as FbReturnValue,
@@ -53,6 +54,110 @@ pub enum ParameterValue {
     Bool(bool),
     /// Vec<u8>
     VecBytes(Vec<u8>),
+    /// A Vec<u8> made up of several segments. The segments are written
+    /// one after the other into the serialized function call, so the guest
+    /// receives them as a single contiguous `VecBytes`, without the host
+    /// having to concatenate them first.
+    VecBytesSegments(Vec<Vec<u8>>),
+    /// i16
+    Short(i16),
+    /// u16
+    UShort(u16),
+    /// i8
+    Byte(i8),
+    /// u8
+    UByte(u8),
+}
+
+/// A parameter value for function calling that borrows its data, so that
+/// string and byte array parameters can be serialized into a function call
+/// without first being copied into a `ParameterValue`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParameterRef<'a> {
+    /// i32
+    Int(i32),
+    /// u32
+    UInt(u32),
+    /// i64
+    Long(i64),
+    /// u64
+    ULong(u64),
+    /// f32
+    Float(f32),
+    /// f64
+    Double(f64),
+    /// &str, sent as a `String`
+    Str(&'a str),
+    /// bool
+    Bool(bool),
+    /// &[u8], sent as a `VecBytes`
+    Bytes(&'a [u8]),
+    /// The segments of a `VecBytesSegments`, sent as a single `VecBytes`
+    BytesSegments(&'a [Vec<u8>]),
+    /// i16
+    Short(i16),
+    /// u16
+    UShort(u16),
+    /// i8
+    Byte(i8),
+    /// u8
+    UByte(u8),
+}
+
+impl<'a> From<&'a ParameterValue> for ParameterRef<'a> {
+    fn from(value: &'a ParameterValue) -> Self {
+        match value {
+            ParameterValue::Int(i) => ParameterRef::Int(*i),
+            ParameterValue::UInt(ui) => ParameterRef::UInt(*ui),
+            ParameterValue::Long(l) => ParameterRef::Long(*l),
+            ParameterValue::ULong(ul) => ParameterRef::ULong(*ul),
+            ParameterValue::Float(f) => ParameterRef::Float(*f),
+            ParameterValue::Double(d) => ParameterRef::Double(*d),
+            ParameterValue::String(s) => ParameterRef::Str(s),
+            ParameterValue::Bool(b) => ParameterRef::Bool(*b),
+            ParameterValue::VecBytes(v) => ParameterRef::Bytes(v),
+            ParameterValue::VecBytesSegments(segments) => ParameterRef::BytesSegments(segments),
+            ParameterValue::Short(x) => ParameterRef::Short(*x),
+            ParameterValue::UShort(x) => ParameterRef::UShort(*x),
+            ParameterValue::Byte(x) => ParameterRef::Byte(*x),
+            ParameterValue::UByte(x) => ParameterRef::UByte(*x),
+        }
+    }
+}
+
+impl From<ParameterRef<'_>> for ParameterValue {
+    fn from(value: ParameterRef<'_>) -> Self {
+        match value {
+            ParameterRef::Int(i) => ParameterValue::Int(i),
+            ParameterRef::UInt(ui) => ParameterValue::UInt(ui),
+            ParameterRef::Long(l) => ParameterValue::Long(l),
+            ParameterRef::ULong(ul) => ParameterValue::ULong(ul),
+            ParameterRef::Float(f) => ParameterValue::Float(f),
+            ParameterRef::Double(d) => ParameterValue::Double(d),
+            ParameterRef::Str(s) => ParameterValue::String(s.to_string()),
+            ParameterRef::Bool(b) => ParameterValue::Bool(b),
+            ParameterRef::Bytes(v) => ParameterValue::VecBytes(v.to_vec()),
+            ParameterRef::BytesSegments(segments) => {
+                ParameterValue::VecBytesSegments(segments.to_vec())
+            }
+            ParameterRef::Short(x) => ParameterValue::Short(x),
+            ParameterRef::UShort(x) => ParameterValue::UShort(x),
+            ParameterRef::Byte(x) => ParameterValue::Byte(x),
+            ParameterRef::UByte(x) => ParameterValue::UByte(x),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for ParameterRef<'a> {
+    fn from(value: &'a str) -> Self {
+        ParameterRef::Str(value)
+    }
+}
+
+impl<'a> From<&'a [u8]> for ParameterRef<'a> {
+    fn from(value: &'a [u8]) -> Self {
+        ParameterRef::Bytes(value)
+    }
 }
 
 /// Supported parameter types for function calling.
@@ -77,6 +182,14 @@ pub enum ParameterType {
     Bool,
     /// Vec<u8>
     VecBytes,
+    /// i16
+    Short,
+    /// u16
+    UShort,
+    /// i8
+    Byte,
+    /// u8
+    UByte,
 }
 
 /// Supported return types with values from function calling.
@@ -102,6 +215,14 @@ pub enum ReturnValue {
     Void,
     /// Vec<u8>
     VecBytes(Vec<u8>),
+    /// i16
+    Short(i16),
+    /// u16
+    UShort(u16),
+    /// i8
+    Byte(i8),
+    /// u8
+    UByte(u8),
 }
 
 /// Supported return types from function calling.
@@ -130,6 +251,14 @@ pub enum ReturnType {
     Void,
     /// Vec<u8>
     VecBytes,
+    /// i16
+    Short,
+    /// u16
+    UShort,
+    /// i8
+    Byte,
+    /// u8
+    UByte,
 }
 
 impl From<&ParameterValue> for ParameterType {
@@ -144,7 +273,56 @@ impl From<&ParameterValue> for ParameterType {
             ParameterValue::Double(_) => ParameterType::Double,
             ParameterValue::String(_) => ParameterType::String,
             ParameterValue::Bool(_) => ParameterType::Bool,
-            ParameterValue::VecBytes(_) => ParameterType::VecBytes,
+            ParameterValue::VecBytes(_) | ParameterValue::VecBytesSegments(_) => {
+                ParameterType::VecBytes
+            }
+            ParameterValue::Short(_) => ParameterType::Short,
+            ParameterValue::UShort(_) => ParameterType::UShort,
+            ParameterValue::Byte(_) => ParameterType::Byte,
+            ParameterValue::UByte(_) => ParameterType::UByte,
+        }
+    }
+}
+
+impl From<&ParameterRef<'_>> for ParameterType {
+    #[cfg_attr(feature = "tracing", instrument(skip_all, parent = Span::current(), level= "Trace"))]
+    fn from(value: &ParameterRef<'_>) -> Self {
+        match *value {
+            ParameterRef::Int(_) => ParameterType::Int,
+            ParameterRef::UInt(_) => ParameterType::UInt,
+            ParameterRef::Long(_) => ParameterType::Long,
+            ParameterRef::ULong(_) => ParameterType::ULong,
+            ParameterRef::Float(_) => ParameterType::Float,
+            ParameterRef::Double(_) => ParameterType::Double,
+            ParameterRef::Str(_) => ParameterType::String,
+            ParameterRef::Bool(_) => ParameterType::Bool,
+            ParameterRef::Bytes(_) | ParameterRef::BytesSegments(_) => ParameterType::VecBytes,
+            ParameterRef::Short(_) => ParameterType::Short,
+            ParameterRef::UShort(_) => ParameterType::UShort,
+            ParameterRef::Byte(_) => ParameterType::Byte,
+            ParameterRef::UByte(_) => ParameterType::UByte,
+        }
+    }
+}
+
+impl From<&ReturnValue> for ReturnType {
+    #[cfg_attr(feature = "tracing", instrument(skip_all, parent = Span::current(), level= "Trace"))]
+    fn from(value: &ReturnValue) -> Self {
+        match *value {
+            ReturnValue::Int(_) => ReturnType::Int,
+            ReturnValue::UInt(_) => ReturnType::UInt,
+            ReturnValue::Long(_) => ReturnType::Long,
+            ReturnValue::ULong(_) => ReturnType::ULong,
+            ReturnValue::Float(_) => ReturnType::Float,
+            ReturnValue::Double(_) => ReturnType::Double,
+            ReturnValue::String(_) => ReturnType::String,
+            ReturnValue::Bool(_) => ReturnType::Bool,
+            ReturnValue::Void => ReturnType::Void,
+            ReturnValue::VecBytes(_) => ReturnType::VecBytes,
+            ReturnValue::Short(_) => ReturnType::Short,
+            ReturnValue::UShort(_) => ReturnType::UShort,
+            ReturnValue::Byte(_) => ReturnType::Byte,
+            ReturnValue::UByte(_) => ReturnType::UByte,
         }
     }
 }
@@ -183,6 +361,18 @@ impl TryFrom<Parameter<'_>> for ParameterValue {
             FbParameterValue::hlvecbytes => param.value_as_hlvecbytes().map(|hlvecbytes| {
                 ParameterValue::VecBytes(hlvecbytes.value().unwrap_or_default().iter().collect())
             }),
+            FbParameterValue::hlshort => param
+                .value_as_hlshort()
+                .map(|hlshort| ParameterValue::Short(hlshort.value())),
+            FbParameterValue::hlushort => param
+                .value_as_hlushort()
+                .map(|hlushort| ParameterValue::UShort(hlushort.value())),
+            FbParameterValue::hlbyte => param
+                .value_as_hlbyte()
+                .map(|hlbyte| ParameterValue::Byte(hlbyte.value())),
+            FbParameterValue::hlubyte => param
+                .value_as_hlubyte()
+                .map(|hlubyte| ParameterValue::UByte(hlubyte.value())),
             other => {
                 bail!("Unexpected flatbuffer parameter value type: {:?}", other);
             }
@@ -204,6 +394,10 @@ impl From<ParameterType> for FbParameterType {
             ParameterType::String => FbParameterType::hlstring,
             ParameterType::Bool => FbParameterType::hlbool,
             ParameterType::VecBytes => FbParameterType::hlvecbytes,
+            ParameterType::Short => FbParameterType::hlshort,
+            ParameterType::UShort => FbParameterType::hlushort,
+            ParameterType::Byte => FbParameterType::hlbyte,
+            ParameterType::UByte => FbParameterType::hlubyte,
         }
     }
 }
@@ -222,6 +416,10 @@ impl From<ReturnType> for FbReturnType {
             ReturnType::Bool => FbReturnType::hlbool,
             ReturnType::Void => FbReturnType::hlvoid,
             ReturnType::VecBytes => FbReturnType::hlsizeprefixedbuffer,
+            ReturnType::Short => FbReturnType::hlshort,
+            ReturnType::UShort => FbReturnType::hlushort,
+            ReturnType::Byte => FbReturnType::hlbyte,
+            ReturnType::UByte => FbReturnType::hlubyte,
         }
     }
 }
@@ -240,6 +438,10 @@ impl TryFrom<FbParameterType> for ParameterType {
             FbParameterType::hlstring => Ok(ParameterType::String),
             FbParameterType::hlbool => Ok(ParameterType::Bool),
             FbParameterType::hlvecbytes => Ok(ParameterType::VecBytes),
+            FbParameterType::hlshort => Ok(ParameterType::Short),
+            FbParameterType::hlushort => Ok(ParameterType::UShort),
+            FbParameterType::hlbyte => Ok(ParameterType::Byte),
+            FbParameterType::hlubyte => Ok(ParameterType::UByte),
             _ => {
                 bail!("Unexpected flatbuffer parameter type: {:?}", value)
             }
@@ -262,6 +464,10 @@ impl TryFrom<FbReturnType> for ReturnType {
             FbReturnType::hlbool => Ok(ReturnType::Bool),
             FbReturnType::hlvoid => Ok(ReturnType::Void),
             FbReturnType::hlsizeprefixedbuffer => Ok(ReturnType::VecBytes),
+            FbReturnType::hlshort => Ok(ReturnType::Short),
+            FbReturnType::hlushort => Ok(ReturnType::UShort),
+            FbReturnType::hlbyte => Ok(ReturnType::Byte),
+            FbReturnType::hlubyte => Ok(ReturnType::UByte),
             _ => {
                 bail!("Unexpected flatbuffer return type: {:?}", value)
             }
@@ -347,6 +553,58 @@ impl TryFrom<ParameterValue> for f64 {
     }
 }
 
+impl TryFrom<ParameterValue> for i16 {
+    type Error = Error;
+    #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
+    fn try_from(value: ParameterValue) -> Result<Self> {
+        match value {
+            ParameterValue::Short(v) => Ok(v),
+            _ => {
+                bail!("Unexpected parameter value type: {:?}", value)
+            }
+        }
+    }
+}
+
+impl TryFrom<ParameterValue> for u16 {
+    type Error = Error;
+    #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
+    fn try_from(value: ParameterValue) -> Result<Self> {
+        match value {
+            ParameterValue::UShort(v) => Ok(v),
+            _ => {
+                bail!("Unexpected parameter value type: {:?}", value)
+            }
+        }
+    }
+}
+
+impl TryFrom<ParameterValue> for i8 {
+    type Error = Error;
+    #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
+    fn try_from(value: ParameterValue) -> Result<Self> {
+        match value {
+            ParameterValue::Byte(v) => Ok(v),
+            _ => {
+                bail!("Unexpected parameter value type: {:?}", value)
+            }
+        }
+    }
+}
+
+impl TryFrom<ParameterValue> for u8 {
+    type Error = Error;
+    #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
+    fn try_from(value: ParameterValue) -> Result<Self> {
+        match value {
+            ParameterValue::UByte(v) => Ok(v),
+            _ => {
+                bail!("Unexpected parameter value type: {:?}", value)
+            }
+        }
+    }
+}
+
 impl TryFrom<ParameterValue> for String {
     type Error = Error;
     #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
@@ -379,6 +637,7 @@ impl TryFrom<ParameterValue> for Vec<u8> {
     fn try_from(value: ParameterValue) -> Result<Self> {
         match value {
             ParameterValue::VecBytes(v) => Ok(v),
+            ParameterValue::VecBytesSegments(segments) => Ok(segments.concat()),
             _ => {
                 bail!("Unexpected parameter value type: {:?}", value)
             }
@@ -464,6 +723,58 @@ impl TryFrom<ReturnValue> for f64 {
     }
 }
 
+impl TryFrom<ReturnValue> for i16 {
+    type Error = Error;
+    #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
+    fn try_from(value: ReturnValue) -> Result<Self> {
+        match value {
+            ReturnValue::Short(v) => Ok(v),
+            _ => {
+                bail!("Unexpected return value type: {:?}", value)
+            }
+        }
+    }
+}
+
+impl TryFrom<ReturnValue> for u16 {
+    type Error = Error;
+    #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
+    fn try_from(value: ReturnValue) -> Result<Self> {
+        match value {
+            ReturnValue::UShort(v) => Ok(v),
+            _ => {
+                bail!("Unexpected return value type: {:?}", value)
+            }
+        }
+    }
+}
+
+impl TryFrom<ReturnValue> for i8 {
+    type Error = Error;
+    #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
+    fn try_from(value: ReturnValue) -> Result<Self> {
+        match value {
+            ReturnValue::Byte(v) => Ok(v),
+            _ => {
+                bail!("Unexpected return value type: {:?}", value)
+            }
+        }
+    }
+}
+
+impl TryFrom<ReturnValue> for u8 {
+    type Error = Error;
+    #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
+    fn try_from(value: ReturnValue) -> Result<Self> {
+        match value {
+            ReturnValue::UByte(v) => Ok(v),
+            _ => {
+                bail!("Unexpected return value type: {:?}", value)
+            }
+        }
+    }
+}
+
 impl TryFrom<ReturnValue> for String {
     type Error = Error;
     #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
@@ -581,6 +892,30 @@ impl TryFrom<FbFunctionCallResult<'_>> for ReturnValue {
                     };
                 Ok(ReturnValue::VecBytes(hlvecbytes.unwrap_or(Vec::new())))
             }
+            FbReturnValue::hlshort => {
+                let hlshort = function_call_result_fb
+                    .return_value_as_hlshort()
+                    .ok_or_else(|| anyhow!("Failed to get hlshort from return value"))?;
+                Ok(ReturnValue::Short(hlshort.value()))
+            }
+            FbReturnValue::hlushort => {
+                let hlushort = function_call_result_fb
+                    .return_value_as_hlushort()
+                    .ok_or_else(|| anyhow!("Failed to get hlushort from return value"))?;
+                Ok(ReturnValue::UShort(hlushort.value()))
+            }
+            FbReturnValue::hlbyte => {
+                let hlbyte = function_call_result_fb
+                    .return_value_as_hlbyte()
+                    .ok_or_else(|| anyhow!("Failed to get hlbyte from return value"))?;
+                Ok(ReturnValue::Byte(hlbyte.value()))
+            }
+            FbReturnValue::hlubyte => {
+                let hlubyte = function_call_result_fb
+                    .return_value_as_hlubyte()
+                    .ok_or_else(|| anyhow!("Failed to get hlubyte from return value"))?;
+                Ok(ReturnValue::UByte(hlubyte.value()))
+            }
             other => {
                 bail!("Unexpected flatbuffer return value type: {:?}", other)
             }
@@ -592,12 +927,27 @@ impl TryFrom<&[u8]> for ReturnValue {
     type Error = Error;
     #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
     fn try_from(value: &[u8]) -> Result<Self> {
-        let function_call_result_fb = size_prefixed_root::<FbFunctionCallResult>(value)
+        let function_call_result_fb = verified_size_prefixed_root::<FbFunctionCallResult>(value)
             .map_err(|e| anyhow!("Failed to get ReturnValue from bytes: {:?}", e))?;
         function_call_result_fb.try_into()
     }
 }
 
+/// The bytes of a serialized `ReturnValue::VecBytes`, borrowed from
+/// `value` rather than copied out of it
+#[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
+pub fn vec_bytes_return_value(value: &[u8]) -> Result<&[u8]> {
+    let function_call_result_fb = verified_size_prefixed_root::<FbFunctionCallResult>(value)
+        .map_err(|e| anyhow!("Failed to get ReturnValue from bytes: {:?}", e))?;
+    match function_call_result_fb.return_value_type() {
+        FbReturnValue::hlsizeprefixedbuffer => Ok(function_call_result_fb
+            .return_value_as_hlsizeprefixedbuffer()
+            .and_then(|hlvecbytes| hlvecbytes.value())
+            .map_or(&[], |val| val.bytes())),
+        other => bail!("Expected a VecBytes return value, got {:?}", other),
+    }
+}
+
 impl TryFrom<&ReturnValue> for Vec<u8> {
     type Error = Error;
     #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
@@ -724,6 +1074,54 @@ impl TryFrom<&ReturnValue> for Vec<u8> {
                 builder.finish_size_prefixed(function_call_result, None);
                 builder.finished_data().to_vec()
             }
+            ReturnValue::Short(x) => {
+                let hlshort = hlshort::create(&mut builder, &hlshortArgs { value: *x });
+                let function_call_result = FbFunctionCallResult::create(
+                    &mut builder,
+                    &FbFunctionCallResultArgs {
+                        return_value: Some(hlshort.as_union_value()),
+                        return_value_type: FbReturnValue::hlshort,
+                    },
+                );
+                builder.finish_size_prefixed(function_call_result, None);
+                builder.finished_data().to_vec()
+            }
+            ReturnValue::UShort(x) => {
+                let hlushort = hlushort::create(&mut builder, &hlushortArgs { value: *x });
+                let function_call_result = FbFunctionCallResult::create(
+                    &mut builder,
+                    &FbFunctionCallResultArgs {
+                        return_value: Some(hlushort.as_union_value()),
+                        return_value_type: FbReturnValue::hlushort,
+                    },
+                );
+                builder.finish_size_prefixed(function_call_result, None);
+                builder.finished_data().to_vec()
+            }
+            ReturnValue::Byte(x) => {
+                let hlbyte = hlbyte::create(&mut builder, &hlbyteArgs { value: *x });
+                let function_call_result = FbFunctionCallResult::create(
+                    &mut builder,
+                    &FbFunctionCallResultArgs {
+                        return_value: Some(hlbyte.as_union_value()),
+                        return_value_type: FbReturnValue::hlbyte,
+                    },
+                );
+                builder.finish_size_prefixed(function_call_result, None);
+                builder.finished_data().to_vec()
+            }
+            ReturnValue::UByte(x) => {
+                let hlubyte = hlubyte::create(&mut builder, &hlubyteArgs { value: *x });
+                let function_call_result = FbFunctionCallResult::create(
+                    &mut builder,
+                    &FbFunctionCallResultArgs {
+                        return_value: Some(hlubyte.as_union_value()),
+                        return_value_type: FbReturnValue::hlubyte,
+                    },
+                );
+                builder.finish_size_prefixed(function_call_result, None);
+                builder.finished_data().to_vec()
+            }
             ReturnValue::Void => {
                 let hlvoid = hlvoid::create(&mut builder, &hlvoidArgs {});
                 let function_call_result = FbFunctionCallResult::create(
@@ -741,3 +1139,60 @@ impl TryFrom<&ReturnValue> for Vec<u8> {
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::{vec_bytes_return_value, ParameterType, ParameterValue, ReturnType, ReturnValue};
+
+    #[test]
+    fn borrow_vec_bytes_return_value() {
+        let bytes = vec![1, 2, 3];
+        let buffer: Vec<u8> = (&ReturnValue::VecBytes(bytes.clone())).try_into().unwrap();
+        let borrowed = vec_bytes_return_value(&buffer).unwrap();
+        assert_eq!(bytes, borrowed);
+        // the bytes are read in place
+        assert!(buffer.as_ptr_range().contains(&borrowed.as_ptr()));
+
+        let empty: Vec<u8> = (&ReturnValue::VecBytes(Vec::new())).try_into().unwrap();
+        assert!(vec_bytes_return_value(&empty).unwrap().is_empty());
+
+        let string: Vec<u8> = (&ReturnValue::String("abc".to_string()))
+            .try_into()
+            .unwrap();
+        assert!(vec_bytes_return_value(&string).is_err());
+    }
+
+    #[test]
+    fn return_value_types() {
+        assert_eq!(ReturnType::Int, (&ReturnValue::Int(1)).into());
+        assert_eq!(ReturnType::Void, (&ReturnValue::Void).into());
+        assert_eq!(
+            ReturnType::VecBytes,
+            (&ReturnValue::VecBytes(vec![1])).into()
+        );
+        assert_ne!(ReturnType::UInt, (&ReturnValue::Int(1)).into());
+    }
+
+    #[test]
+    fn small_integer_return_values() {
+        for value in [
+            ReturnValue::Short(-2),
+            ReturnValue::UShort(u16::MAX),
+            ReturnValue::Byte(i8::MIN),
+            ReturnValue::UByte(7),
+        ] {
+            let buffer: Vec<u8> = (&value).try_into().unwrap();
+            assert_eq!(value, ReturnValue::try_from(buffer.as_slice()).unwrap());
+        }
+        assert_eq!(7u8, u8::try_from(ReturnValue::UByte(7)).unwrap());
+        assert!(u8::try_from(ReturnValue::Int(7)).is_err());
+        assert_eq!(
+            ParameterType::Byte,
+            ParameterType::from(&ParameterValue::Byte(1))
+        );
+    }
+}
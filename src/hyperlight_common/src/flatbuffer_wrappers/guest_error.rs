@@ -20,10 +20,10 @@ use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
 use anyhow::{Error, Result};
-use flatbuffers::size_prefixed_root;
 #[cfg(feature = "tracing")]
 use tracing::{instrument, Span};
 
+use super::util::verified_size_prefixed_root;
 use crate::flatbuffers::hyperlight::generated::{
     ErrorCode as FbErrorCode, GuestError as FbGuestError, GuestErrorArgs,
 };
@@ -48,6 +48,13 @@ pub enum ErrorCode {
     GuestFunctionParameterTypeMismatch = 14,
     GuestError = 15,
     ArrayLengthParamIsMissing = 16,
+    PayloadTooLarge = 17,
+    HostFunctionError = 18,
+    EpochInterrupted = 19,
+    ControlFlowViolation = 20,
+    HostFunctionArgumentInvalid = 21,
+    DoubleFault = 22,
+    PageProtectionViolation = 23,
 }
 
 impl From<ErrorCode> for FbErrorCode {
@@ -73,6 +80,13 @@ impl From<ErrorCode> for FbErrorCode {
             }
             ErrorCode::GuestError => Self::GuestError,
             ErrorCode::ArrayLengthParamIsMissing => Self::ArrayLengthParamIsMissing,
+            ErrorCode::PayloadTooLarge => Self::PayloadTooLarge,
+            ErrorCode::HostFunctionError => Self::HostFunctionError,
+            ErrorCode::EpochInterrupted => Self::EpochInterrupted,
+            ErrorCode::ControlFlowViolation => Self::ControlFlowViolation,
+            ErrorCode::HostFunctionArgumentInvalid => Self::HostFunctionArgumentInvalid,
+            ErrorCode::DoubleFault => Self::DoubleFault,
+            ErrorCode::PageProtectionViolation => Self::PageProtectionViolation,
         }
     }
 }
@@ -99,6 +113,13 @@ impl From<FbErrorCode> for ErrorCode {
             }
             FbErrorCode::GuestError => Self::GuestError,
             FbErrorCode::ArrayLengthParamIsMissing => Self::ArrayLengthParamIsMissing,
+            FbErrorCode::PayloadTooLarge => Self::PayloadTooLarge,
+            FbErrorCode::HostFunctionError => Self::HostFunctionError,
+            FbErrorCode::EpochInterrupted => Self::EpochInterrupted,
+            FbErrorCode::ControlFlowViolation => Self::ControlFlowViolation,
+            FbErrorCode::HostFunctionArgumentInvalid => Self::HostFunctionArgumentInvalid,
+            FbErrorCode::DoubleFault => Self::DoubleFault,
+            FbErrorCode::PageProtectionViolation => Self::PageProtectionViolation,
             _ => Self::UnknownError,
         }
     }
@@ -123,6 +144,13 @@ impl From<u64> for ErrorCode {
             14 => Self::GuestFunctionParameterTypeMismatch,
             15 => Self::GuestError,
             16 => Self::ArrayLengthParamIsMissing,
+            17 => Self::PayloadTooLarge,
+            18 => Self::HostFunctionError,
+            19 => Self::EpochInterrupted,
+            20 => Self::ControlFlowViolation,
+            21 => Self::HostFunctionArgumentInvalid,
+            22 => Self::DoubleFault,
+            23 => Self::PageProtectionViolation,
             _ => Self::UnknownError,
         }
     }
@@ -147,6 +175,13 @@ impl From<ErrorCode> for u64 {
             ErrorCode::GuestFunctionParameterTypeMismatch => 14,
             ErrorCode::GuestError => 15,
             ErrorCode::ArrayLengthParamIsMissing => 16,
+            ErrorCode::PayloadTooLarge => 17,
+            ErrorCode::HostFunctionError => 18,
+            ErrorCode::EpochInterrupted => 19,
+            ErrorCode::ControlFlowViolation => 20,
+            ErrorCode::HostFunctionArgumentInvalid => 21,
+            ErrorCode::DoubleFault => 22,
+            ErrorCode::PageProtectionViolation => 23,
         }
     }
 }
@@ -174,6 +209,13 @@ impl From<ErrorCode> for String {
             }
             ErrorCode::GuestError => "GuestError".to_string(),
             ErrorCode::ArrayLengthParamIsMissing => "ArrayLengthParamIsMissing".to_string(),
+            ErrorCode::PayloadTooLarge => "PayloadTooLarge".to_string(),
+            ErrorCode::HostFunctionError => "HostFunctionError".to_string(),
+            ErrorCode::EpochInterrupted => "EpochInterrupted".to_string(),
+            ErrorCode::ControlFlowViolation => "ControlFlowViolation".to_string(),
+            ErrorCode::HostFunctionArgumentInvalid => "HostFunctionArgumentInvalid".to_string(),
+            ErrorCode::DoubleFault => "DoubleFault".to_string(),
+            ErrorCode::PageProtectionViolation => "PageProtectionViolation".to_string(),
         }
     }
 }
@@ -198,7 +240,7 @@ impl TryFrom<&[u8]> for GuestError {
     type Error = Error;
     #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
     fn try_from(value: &[u8]) -> Result<Self> {
-        let guest_error_fb = size_prefixed_root::<FbGuestError>(value)
+        let guest_error_fb = verified_size_prefixed_root::<FbGuestError>(value)
             .map_err(|e| anyhow::anyhow!("Error while reading GuestError: {:?}", e))?;
         let code = guest_error_fb.code();
         let message = match guest_error_fb.message() {
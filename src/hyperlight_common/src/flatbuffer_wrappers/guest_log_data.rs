@@ -18,11 +18,11 @@ use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
 use anyhow::{anyhow, Error, Result};
-use flatbuffers::size_prefixed_root;
 #[cfg(feature = "tracing")]
 use tracing::{instrument, Span};
 
 use super::guest_log_level::LogLevel;
+use super::util::verified_size_prefixed_root;
 use crate::flatbuffers::hyperlight::generated::{
     GuestLogData as FbGuestLogData, GuestLogDataArgs as FbGuestLogDataArgs, LogLevel as FbLogLevel,
 };
@@ -64,7 +64,7 @@ impl TryFrom<&[u8]> for GuestLogData {
     type Error = Error;
     #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
     fn try_from(raw_bytes: &[u8]) -> Result<Self> {
-        let gld_gen = size_prefixed_root::<FbGuestLogData>(raw_bytes)
+        let gld_gen = verified_size_prefixed_root::<FbGuestLogData>(raw_bytes)
             .map_err(|e| anyhow!("Error while reading GuestLogData: {:?}", e))?;
         let message = convert_generated_option("message", gld_gen.message())?;
         let source = convert_generated_option("source", gld_gen.source())?;
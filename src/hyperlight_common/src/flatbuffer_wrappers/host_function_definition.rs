@@ -28,6 +28,64 @@ use crate::flatbuffers::hyperlight::generated::{
     HostFunctionDefinitionArgs as FbHostFunctionDefinitionArgs, ParameterType as FbParameterType,
 };
 
+/// What a function declares about its behaviour, so that callers can
+/// pick safe ways to call it, e.g. whether its results can be cached or
+/// failed calls retried
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FunctionFlags(u8);
+
+impl FunctionFlags {
+    /// No flags, which is what is assumed of functions that declare none
+    pub const NONE: Self = Self(0);
+    /// The function's result depends only on its parameters, and calling
+    /// it has no side effects. Pure functions are also idempotent and
+    /// read-only.
+    pub const PURE: Self = Self(1);
+    /// Calling the function more than once with the same parameters has
+    /// the same effect as calling it once
+    pub const IDEMPOTENT: Self = Self(1 << 1);
+    /// The function doesn't change the guest's state
+    pub const READ_ONLY: Self = Self(1 << 2);
+
+    /// The flags with the bits in `bits`, ignoring unknown bits
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits & (Self::PURE.0 | Self::IDEMPOTENT.0 | Self::READ_ONLY.0))
+    }
+
+    /// The bits of the flags
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Whether all of the flags in `other` are set
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Whether the function is pure
+    pub const fn is_pure(self) -> bool {
+        self.contains(Self::PURE)
+    }
+
+    /// Whether the function is idempotent, which pure functions are
+    pub const fn is_idempotent(self) -> bool {
+        self.is_pure() || self.contains(Self::IDEMPOTENT)
+    }
+
+    /// Whether the function is read-only, which pure functions are
+    pub const fn is_read_only(self) -> bool {
+        self.is_pure() || self.contains(Self::READ_ONLY)
+    }
+}
+
+impl core::ops::BitOr for FunctionFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
 /// The definition of a function exposed from the host to the guest
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct HostFunctionDefinition {
@@ -37,6 +95,11 @@ pub struct HostFunctionDefinition {
     pub parameter_types: Option<Vec<ParameterType>>,
     /// The type of the return value from the host function call
     pub return_type: ReturnType,
+    /// What the function declares about its behaviour
+    pub flags: FunctionFlags,
+    /// The largest size in bytes the function declares its serialized
+    /// return value can be, if it declares one
+    pub max_result_size: Option<u64>,
 }
 
 impl HostFunctionDefinition {
@@ -51,9 +114,24 @@ impl HostFunctionDefinition {
             function_name,
             parameter_types,
             return_type,
+            flags: FunctionFlags::NONE,
+            max_result_size: None,
         }
     }
 
+    /// Set what the function declares about its behaviour
+    pub fn with_flags(mut self, flags: FunctionFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Set the largest size in bytes the function's serialized return
+    /// value can be
+    pub fn with_max_result_size(mut self, max_result_size: u64) -> Self {
+        self.max_result_size = Some(max_result_size);
+        self
+    }
+
     /// Convert this `HostFunctionDefinition` into a `WIPOffset<FbHostFunctionDefinition>`.
     #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
     pub(crate) fn convert_to_flatbuffer_def<'a>(
@@ -82,6 +160,8 @@ impl HostFunctionDefinition {
                     function_name: Some(host_function_name),
                     return_type: return_value_type,
                     parameters: vec_parameters,
+                    flags: self.flags.bits(),
+                    max_result_size: self.max_result_size.unwrap_or(0),
                 },
             );
 
@@ -134,7 +214,13 @@ impl TryFrom<&FbHostFunctionDefinition<'_>> for HostFunctionDefinition {
             None => None,
         };
 
-        Ok(Self::new(function_name, parameter_types, return_type))
+        let mut definition = Self::new(function_name, parameter_types, return_type)
+            .with_flags(FunctionFlags::from_bits(value.flags()));
+        // 0 is the default, for functions that declare no size
+        if value.max_result_size() != 0 {
+            definition = definition.with_max_result_size(value.max_result_size());
+        }
+        Ok(definition)
     }
 }
 
@@ -14,14 +14,16 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
 use anyhow::{Error, Result};
-use flatbuffers::{size_prefixed_root, WIPOffset};
+use flatbuffers::WIPOffset;
 #[cfg(feature = "tracing")]
 use tracing::{instrument, Span};
 
 use super::host_function_definition::HostFunctionDefinition;
+use super::util::verified_size_prefixed_root;
 use crate::flatbuffers::hyperlight::generated::{
     HostFunctionDefinition as FbHostFunctionDefinition,
     HostFunctionDetails as FbHostFunctionDetails,
@@ -33,13 +35,36 @@ use crate::flatbuffers::hyperlight::generated::{
 pub struct HostFunctionDetails {
     /// The host functions.
     pub host_functions: Option<Vec<HostFunctionDefinition>>,
+    /// The names of the optional capabilities the host declared, such as
+    /// "network", sorted.
+    pub features: Vec<String>,
 }
 
 impl HostFunctionDetails {
     /// Create a new `HostFunctionDetails`.
     #[cfg_attr(feature = "tracing", instrument(skip_all, parent = Span::current(), level= "Trace"))]
     pub fn new(host_functions: Option<Vec<HostFunctionDefinition>>) -> Self {
-        Self { host_functions }
+        Self {
+            host_functions,
+            features: Vec::new(),
+        }
+    }
+
+    /// Declare the feature `name`, keeping the features sorted. Declaring a
+    /// feature again does nothing.
+    #[cfg_attr(feature = "tracing", instrument(skip_all, parent = Span::current(), level= "Trace"))]
+    pub fn insert_feature(&mut self, name: &str) {
+        if let Err(index) = self.features.binary_search_by(|f| f.as_str().cmp(name)) {
+            self.features.insert(index, name.to_string());
+        }
+    }
+
+    /// Whether the host declared the feature `name`.
+    #[cfg_attr(feature = "tracing", instrument(skip_all, parent = Span::current(), level= "Trace"))]
+    pub fn has_feature(&self, name: &str) -> bool {
+        self.features
+            .binary_search_by(|f| f.as_str().cmp(name))
+            .is_ok()
     }
 
     /// Insert a host function into the host function details.
@@ -87,7 +112,7 @@ impl TryFrom<&[u8]> for HostFunctionDetails {
     type Error = Error;
     #[cfg_attr(feature = "tracing", instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace"))]
     fn try_from(value: &[u8]) -> Result<Self> {
-        let host_function_details_fb = size_prefixed_root::<FbHostFunctionDetails>(value)
+        let host_function_details_fb = verified_size_prefixed_root::<FbHostFunctionDetails>(value)
             .map_err(|e| anyhow::anyhow!("Error while reading HostFunctionDetails: {:?}", e))?;
 
         let host_function_definitions = match host_function_details_fb.functions() {
@@ -106,8 +131,19 @@ impl TryFrom<&[u8]> for HostFunctionDetails {
             None => None,
         };
 
+        let mut features: Vec<String> = host_function_details_fb
+            .features()
+            .iter()
+            .flatten()
+            .map(|f| f.to_string())
+            .collect();
+        // a guest may be handed details it can't trust to be sorted
+        features.sort_unstable();
+        features.dedup();
+
         Ok(Self {
             host_functions: host_function_definitions,
+            features,
         })
     }
 }
@@ -136,10 +172,23 @@ impl TryFrom<&HostFunctionDetails> for Vec<u8> {
         let fb_host_function_definitions =
             vec_host_function_definitions.map(|v| builder.create_vector(&v));
 
+        let fb_features = match value.features.is_empty() {
+            true => None,
+            false => {
+                let features: Vec<_> = value
+                    .features
+                    .iter()
+                    .map(|f| builder.create_string(f))
+                    .collect();
+                Some(builder.create_vector(&features))
+            }
+        };
+
         let host_function_details = FbHostFunctionDetails::create(
             &mut builder,
             &FbHostFunctionDetailsArgs {
                 functions: fb_host_function_definitions,
+                features: fb_features,
             },
         );
         builder.finish_size_prefixed(host_function_details, None);
@@ -148,3 +197,34 @@ impl TryFrom<&HostFunctionDetails> for Vec<u8> {
         Ok(res)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::HostFunctionDetails;
+
+    #[test]
+    fn features_round_trip() {
+        let mut details = HostFunctionDetails::new(None);
+        details.insert_feature("vfs");
+        details.insert_feature("network");
+        details.insert_feature("vfs");
+        assert_eq!(
+            vec!["network".to_string(), "vfs".to_string()],
+            details.features
+        );
+
+        let buffer: Vec<u8> = (&details).try_into().unwrap();
+        let details = HostFunctionDetails::try_from(buffer.as_slice()).unwrap();
+        assert!(details.has_feature("network"));
+        assert!(details.has_feature("vfs"));
+        assert!(!details.has_feature("kv"));
+
+        let buffer: Vec<u8> = (&HostFunctionDetails::new(None)).try_into().unwrap();
+        let details = HostFunctionDetails::try_from(buffer.as_slice()).unwrap();
+        assert!(details.features.is_empty());
+    }
+}
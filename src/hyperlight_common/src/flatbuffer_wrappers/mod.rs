@@ -25,4 +25,6 @@ pub mod guest_log_level;
 pub mod host_function_definition;
 /// cbindgen:ignore
 pub mod host_function_details;
+/// cbindgen:ignore
+pub mod payload_limits;
 pub mod util;
@@ -0,0 +1,209 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use core::fmt;
+
+use super::function_types::{ParameterRef, ParameterValue, ReturnValue};
+
+/// Limits on the size of the function calls and return values exchanged
+/// between the host and the guest.
+///
+/// The host sets these from its `SandboxConfiguration` and passes them to
+/// the guest in the PEB so that both sides enforce the same limits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(C)]
+pub struct PayloadLimits {
+    /// The maximum size in bytes of a serialized function call or return
+    /// value. 0 means there is no limit beyond the size of the shared buffer.
+    pub max_payload_size: u64,
+    /// The maximum size in bytes of any single `String` or `VecBytes`
+    /// parameter or return value. 0 means there is no limit.
+    pub max_parameter_size: u64,
+}
+
+/// The error returned when a payload exceeds one of the `PayloadLimits`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadLimitExceeded {
+    /// What exceeded the limit, e.g. "function call payload"
+    pub what: &'static str,
+    /// The actual size in bytes
+    pub size: usize,
+    /// The maximum size in bytes that was allowed
+    pub max_size: usize,
+}
+
+impl fmt::Display for PayloadLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} size {} exceeds the maximum allowed size {}",
+            self.what, self.size, self.max_size
+        )
+    }
+}
+
+impl PayloadLimits {
+    /// Check the size of a serialized function call or return value that is
+    /// going to be written to (or was read from) a shared buffer of
+    /// `buffer_size` bytes.
+    pub fn check_payload_size(
+        &self,
+        payload_size: usize,
+        buffer_size: usize,
+    ) -> Result<(), PayloadLimitExceeded> {
+        let max_size = match self.max_payload_size {
+            0 => buffer_size,
+            max => (max as usize).min(buffer_size),
+        };
+        if payload_size > max_size {
+            return Err(PayloadLimitExceeded {
+                what: "function call payload",
+                size: payload_size,
+                max_size,
+            });
+        }
+        Ok(())
+    }
+
+    /// Check the size of each `String` and `VecBytes` parameter in a
+    /// function call
+    pub fn check_parameters(
+        &self,
+        parameters: Option<&[ParameterValue]>,
+    ) -> Result<(), PayloadLimitExceeded> {
+        for parameter in parameters.into_iter().flatten() {
+            self.check_parameter(parameter.into())?;
+        }
+        Ok(())
+    }
+
+    /// Check the size of each string and byte array parameter in a
+    /// function call made with borrowed parameters
+    pub fn check_parameter_refs(
+        &self,
+        parameters: &[ParameterRef<'_>],
+    ) -> Result<(), PayloadLimitExceeded> {
+        for parameter in parameters {
+            self.check_parameter(*parameter)?;
+        }
+        Ok(())
+    }
+
+    fn check_parameter(&self, parameter: ParameterRef<'_>) -> Result<(), PayloadLimitExceeded> {
+        match parameter {
+            ParameterRef::Str(s) => self.check_parameter_size("string parameter", s.len()),
+            ParameterRef::Bytes(v) => self.check_parameter_size("byte array parameter", v.len()),
+            ParameterRef::BytesSegments(segments) => self.check_parameter_size(
+                "byte array parameter",
+                segments.iter().map(|s| s.len()).sum(),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the size of a `String` or `VecBytes` return value
+    pub fn check_return_value(
+        &self,
+        return_value: &ReturnValue,
+    ) -> Result<(), PayloadLimitExceeded> {
+        match return_value {
+            ReturnValue::String(s) => self.check_parameter_size("string return value", s.len()),
+            ReturnValue::VecBytes(v) => self.check_vec_bytes_return_value_size(v.len()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check the size of a `VecBytes` return value that is read in place,
+    /// rather than into a `ReturnValue`
+    pub fn check_vec_bytes_return_value_size(
+        &self,
+        size: usize,
+    ) -> Result<(), PayloadLimitExceeded> {
+        self.check_parameter_size("byte array return value", size)
+    }
+
+    fn check_parameter_size(
+        &self,
+        what: &'static str,
+        size: usize,
+    ) -> Result<(), PayloadLimitExceeded> {
+        let max_size = self.max_parameter_size as usize;
+        if max_size != 0 && size > max_size {
+            return Err(PayloadLimitExceeded {
+                what,
+                size,
+                max_size,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use super::*;
+
+    #[test]
+    fn payload_size_is_limited_by_buffer_size() {
+        let limits = PayloadLimits::default();
+        assert!(limits.check_payload_size(100, 100).is_ok());
+        let err = limits.check_payload_size(101, 100).unwrap_err();
+        assert_eq!(101, err.size);
+        assert_eq!(100, err.max_size);
+
+        let limits = PayloadLimits {
+            max_payload_size: 50,
+            max_parameter_size: 0,
+        };
+        assert!(limits.check_payload_size(50, 100).is_ok());
+        assert_eq!(50, limits.check_payload_size(51, 100).unwrap_err().max_size);
+    }
+
+    #[test]
+    fn parameter_sizes_are_limited() {
+        let limits = PayloadLimits {
+            max_payload_size: 0,
+            max_parameter_size: 4,
+        };
+        let ok = [
+            ParameterValue::String("abcd".to_string()),
+            ParameterValue::VecBytes(vec![0; 4]),
+            ParameterValue::Int(1),
+        ];
+        assert!(limits.check_parameters(Some(&ok)).is_ok());
+        assert!(limits.check_parameters(None).is_ok());
+
+        let too_big = [ParameterValue::VecBytes(vec![0; 5])];
+        let err = limits.check_parameters(Some(&too_big)).unwrap_err();
+        assert_eq!("byte array parameter", err.what);
+
+        let too_big = [ParameterValue::VecBytesSegments(vec![
+            vec![0; 2],
+            vec![0; 3],
+        ])];
+        assert!(limits.check_parameters(Some(&too_big)).is_err());
+
+        assert!(limits
+            .check_return_value(&ReturnValue::String("abcde".to_string()))
+            .is_err());
+        assert!(PayloadLimits::default()
+            .check_return_value(&ReturnValue::String("abcde".to_string()))
+            .is_ok());
+    }
+}
@@ -16,20 +16,60 @@ limitations under the License.
 
 use alloc::vec::Vec;
 
-use flatbuffers::FlatBufferBuilder;
+use anyhow::{anyhow, bail, Result};
+use flatbuffers::{
+    size_prefixed_root_with_opts, FlatBufferBuilder, Follow, Verifiable, VerifierOptions,
+    SIZE_SIZEPREFIX,
+};
 
 use crate::flatbuffers::hyperlight::generated::{
-    hlbool as Fbhlbool, hlboolArgs as FbhlboolArgs, hldouble as Fbhldouble,
-    hldoubleArgs as FbhldoubleArgs, hlfloat as Fbhlfloat, hlfloatArgs as FbhlfloatArgs,
-    hlint as Fbhlint, hlintArgs as FbhlintArgs, hllong as Fbhllong, hllongArgs as FbhllongArgs,
+    hlbool as Fbhlbool, hlboolArgs as FbhlboolArgs, hlbyte as Fbhlbyte, hlbyteArgs as FbhlbyteArgs,
+    hldouble as Fbhldouble, hldoubleArgs as FbhldoubleArgs, hlfloat as Fbhlfloat,
+    hlfloatArgs as FbhlfloatArgs, hlint as Fbhlint, hlintArgs as FbhlintArgs, hllong as Fbhllong,
+    hllongArgs as FbhllongArgs, hlshort as Fbhlshort, hlshortArgs as FbhlshortArgs,
     hlsizeprefixedbuffer as Fbhlsizeprefixedbuffer,
     hlsizeprefixedbufferArgs as FbhlsizeprefixedbufferArgs, hlstring as Fbhlstring,
-    hlstringArgs as FbhlstringArgs, hluint as Fbhluint, hluintArgs as FbhluintArgs,
-    hlulong as Fbhlulong, hlulongArgs as FbhlulongArgs, hlvoid as Fbhlvoid,
-    hlvoidArgs as FbhlvoidArgs, FunctionCallResult as FbFunctionCallResult,
+    hlstringArgs as FbhlstringArgs, hlubyte as Fbhlubyte, hlubyteArgs as FbhlubyteArgs,
+    hluint as Fbhluint, hluintArgs as FbhluintArgs, hlulong as Fbhlulong,
+    hlulongArgs as FbhlulongArgs, hlushort as Fbhlushort, hlushortArgs as FbhlushortArgs,
+    hlvoid as Fbhlvoid, hlvoidArgs as FbhlvoidArgs, FunctionCallResult as FbFunctionCallResult,
     FunctionCallResultArgs as FbFunctionCallResultArgs, ReturnValue as FbReturnValue,
 };
 
+/// Runs the flatbuffer verifier over a size prefixed buffer and returns its root.
+///
+/// The size prefix is checked against the length of `buffer` and only the bytes it
+/// covers are verified, so a corrupted or malicious prefix can't make the verifier
+/// (or the accessors used afterwards) look outside of the flatbuffer.
+/// This must be used for every buffer read from shared memory before any
+/// wrapper type is constructed from it.
+pub fn verified_size_prefixed_root<'buf, T>(buffer: &'buf [u8]) -> Result<T::Inner>
+where
+    T: 'buf + Follow<'buf> + Verifiable,
+{
+    if buffer.len() < SIZE_SIZEPREFIX {
+        bail!(
+            "Buffer of {} bytes is too small to contain a flatbuffer size prefix",
+            buffer.len()
+        );
+    }
+    let mut prefix = [0u8; SIZE_SIZEPREFIX];
+    prefix.copy_from_slice(&buffer[..SIZE_SIZEPREFIX]);
+    let end = (u32::from_le_bytes(prefix) as usize)
+        .checked_add(SIZE_SIZEPREFIX)
+        .filter(|end| *end <= buffer.len())
+        .ok_or_else(|| {
+            anyhow!(
+                "Flatbuffer size prefix {} exceeds the buffer length {}",
+                u32::from_le_bytes(prefix),
+                buffer.len()
+            )
+        })?;
+
+    size_prefixed_root_with_opts::<T>(&VerifierOptions::default(), &buffer[..end])
+        .map_err(|e| anyhow!("Flatbuffer verification failed: {}", e))
+}
+
 /// Flatbuffer-encodes the given value
 pub fn get_flatbuffer_result<T: FlatbufferSerializable>(val: T) -> Vec<u8> {
     let mut builder = FlatBufferBuilder::new();
@@ -159,6 +199,50 @@ impl FlatbufferSerializable for u64 {
     }
 }
 
+impl FlatbufferSerializable for i16 {
+    fn serialize(&self, builder: &mut FlatBufferBuilder) -> FbFunctionCallResultArgs {
+        FbFunctionCallResultArgs {
+            return_value: Some(
+                Fbhlshort::create(builder, &FbhlshortArgs { value: *self }).as_union_value(),
+            ),
+            return_value_type: FbReturnValue::hlshort,
+        }
+    }
+}
+
+impl FlatbufferSerializable for u16 {
+    fn serialize(&self, builder: &mut FlatBufferBuilder) -> FbFunctionCallResultArgs {
+        FbFunctionCallResultArgs {
+            return_value: Some(
+                Fbhlushort::create(builder, &FbhlushortArgs { value: *self }).as_union_value(),
+            ),
+            return_value_type: FbReturnValue::hlushort,
+        }
+    }
+}
+
+impl FlatbufferSerializable for i8 {
+    fn serialize(&self, builder: &mut FlatBufferBuilder) -> FbFunctionCallResultArgs {
+        FbFunctionCallResultArgs {
+            return_value: Some(
+                Fbhlbyte::create(builder, &FbhlbyteArgs { value: *self }).as_union_value(),
+            ),
+            return_value_type: FbReturnValue::hlbyte,
+        }
+    }
+}
+
+impl FlatbufferSerializable for u8 {
+    fn serialize(&self, builder: &mut FlatBufferBuilder) -> FbFunctionCallResultArgs {
+        FbFunctionCallResultArgs {
+            return_value: Some(
+                Fbhlubyte::create(builder, &FbhlubyteArgs { value: *self }).as_union_value(),
+            ),
+            return_value_type: FbReturnValue::hlubyte,
+        }
+    }
+}
+
 impl FlatbufferSerializable for bool {
     fn serialize(&self, builder: &mut FlatBufferBuilder) -> FbFunctionCallResultArgs {
         FbFunctionCallResultArgs {
@@ -19,13 +19,13 @@ pub const ENUM_MIN_ERROR_CODE: u64 = 0;
     since = "2.0.0",
     note = "Use associated constants instead. This will no longer be generated in 2021."
 )]
-pub const ENUM_MAX_ERROR_CODE: u64 = 16;
+pub const ENUM_MAX_ERROR_CODE: u64 = 23;
 #[deprecated(
     since = "2.0.0",
     note = "Use associated constants instead. This will no longer be generated in 2021."
 )]
 #[allow(non_camel_case_types)]
-pub const ENUM_VALUES_ERROR_CODE: [ErrorCode; 16] = [
+pub const ENUM_VALUES_ERROR_CODE: [ErrorCode; 23] = [
     ErrorCode::NoError,
     ErrorCode::UnsupportedParameterType,
     ErrorCode::GuestFunctionNameNotProvided,
@@ -42,6 +42,13 @@ pub const ENUM_VALUES_ERROR_CODE: [ErrorCode; 16] = [
     ErrorCode::GuestFunctionParameterTypeMismatch,
     ErrorCode::GuestError,
     ErrorCode::ArrayLengthParamIsMissing,
+    ErrorCode::PayloadTooLarge,
+    ErrorCode::HostFunctionError,
+    ErrorCode::EpochInterrupted,
+    ErrorCode::ControlFlowViolation,
+    ErrorCode::HostFunctionArgumentInvalid,
+    ErrorCode::DoubleFault,
+    ErrorCode::PageProtectionViolation,
 ];
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
@@ -65,9 +72,16 @@ impl ErrorCode {
     pub const GuestFunctionParameterTypeMismatch: Self = Self(14);
     pub const GuestError: Self = Self(15);
     pub const ArrayLengthParamIsMissing: Self = Self(16);
+    pub const PayloadTooLarge: Self = Self(17);
+    pub const HostFunctionError: Self = Self(18);
+    pub const EpochInterrupted: Self = Self(19);
+    pub const ControlFlowViolation: Self = Self(20);
+    pub const HostFunctionArgumentInvalid: Self = Self(21);
+    pub const DoubleFault: Self = Self(22);
+    pub const PageProtectionViolation: Self = Self(23);
 
     pub const ENUM_MIN: u64 = 0;
-    pub const ENUM_MAX: u64 = 16;
+    pub const ENUM_MAX: u64 = 23;
     pub const ENUM_VALUES: &'static [Self] = &[
         Self::NoError,
         Self::UnsupportedParameterType,
@@ -85,6 +99,13 @@ impl ErrorCode {
         Self::GuestFunctionParameterTypeMismatch,
         Self::GuestError,
         Self::ArrayLengthParamIsMissing,
+        Self::PayloadTooLarge,
+        Self::HostFunctionError,
+        Self::EpochInterrupted,
+        Self::ControlFlowViolation,
+        Self::HostFunctionArgumentInvalid,
+        Self::DoubleFault,
+        Self::PageProtectionViolation,
     ];
     /// Returns the variant's name or "" if unknown.
     pub fn variant_name(self) -> Option<&'static str> {
@@ -107,6 +128,13 @@ impl ErrorCode {
             Self::GuestFunctionParameterTypeMismatch => Some("GuestFunctionParameterTypeMismatch"),
             Self::GuestError => Some("GuestError"),
             Self::ArrayLengthParamIsMissing => Some("ArrayLengthParamIsMissing"),
+            Self::PayloadTooLarge => Some("PayloadTooLarge"),
+            Self::HostFunctionError => Some("HostFunctionError"),
+            Self::EpochInterrupted => Some("EpochInterrupted"),
+            Self::ControlFlowViolation => Some("ControlFlowViolation"),
+            Self::HostFunctionArgumentInvalid => Some("HostFunctionArgumentInvalid"),
+            Self::DoubleFault => Some("DoubleFault"),
+            Self::PageProtectionViolation => Some("PageProtectionViolation"),
             _ => None,
         }
     }
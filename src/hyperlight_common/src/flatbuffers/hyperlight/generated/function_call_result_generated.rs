@@ -215,6 +215,62 @@ impl<'a> FunctionCallResult<'a> {
             None
         }
     }
+
+    #[inline]
+    #[allow(non_snake_case)]
+    pub fn return_value_as_hlshort(&self) -> Option<hlshort<'a>> {
+        if self.return_value_type() == ReturnValue::hlshort {
+            let u = self.return_value();
+            // Safety:
+            // Created from a valid Table for this object
+            // Which contains a valid union in this slot
+            Some(unsafe { hlshort::init_from_table(u) })
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    #[allow(non_snake_case)]
+    pub fn return_value_as_hlushort(&self) -> Option<hlushort<'a>> {
+        if self.return_value_type() == ReturnValue::hlushort {
+            let u = self.return_value();
+            // Safety:
+            // Created from a valid Table for this object
+            // Which contains a valid union in this slot
+            Some(unsafe { hlushort::init_from_table(u) })
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    #[allow(non_snake_case)]
+    pub fn return_value_as_hlbyte(&self) -> Option<hlbyte<'a>> {
+        if self.return_value_type() == ReturnValue::hlbyte {
+            let u = self.return_value();
+            // Safety:
+            // Created from a valid Table for this object
+            // Which contains a valid union in this slot
+            Some(unsafe { hlbyte::init_from_table(u) })
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    #[allow(non_snake_case)]
+    pub fn return_value_as_hlubyte(&self) -> Option<hlubyte<'a>> {
+        if self.return_value_type() == ReturnValue::hlubyte {
+            let u = self.return_value();
+            // Safety:
+            // Created from a valid Table for this object
+            // Which contains a valid union in this slot
+            Some(unsafe { hlubyte::init_from_table(u) })
+        } else {
+            None
+        }
+    }
 }
 
 impl flatbuffers::Verifiable for FunctionCallResult<'_> {
@@ -282,6 +338,26 @@ impl flatbuffers::Verifiable for FunctionCallResult<'_> {
                             "ReturnValue::hlsizeprefixedbuffer",
                             pos,
                         ),
+                    ReturnValue::hlshort => v
+                        .verify_union_variant::<flatbuffers::ForwardsUOffset<hlshort>>(
+                            "ReturnValue::hlshort",
+                            pos,
+                        ),
+                    ReturnValue::hlushort => v
+                        .verify_union_variant::<flatbuffers::ForwardsUOffset<hlushort>>(
+                            "ReturnValue::hlushort",
+                            pos,
+                        ),
+                    ReturnValue::hlbyte => v
+                        .verify_union_variant::<flatbuffers::ForwardsUOffset<hlbyte>>(
+                            "ReturnValue::hlbyte",
+                            pos,
+                        ),
+                    ReturnValue::hlubyte => v
+                        .verify_union_variant::<flatbuffers::ForwardsUOffset<hlubyte>>(
+                            "ReturnValue::hlubyte",
+                            pos,
+                        ),
                     _ => Ok(()),
                 },
             )?
@@ -450,6 +526,46 @@ impl core::fmt::Debug for FunctionCallResult<'_> {
                     )
                 }
             }
+            ReturnValue::hlshort => {
+                if let Some(x) = self.return_value_as_hlshort() {
+                    ds.field("return_value", &x)
+                } else {
+                    ds.field(
+                        "return_value",
+                        &"InvalidFlatbuffer: Union discriminant does not match value.",
+                    )
+                }
+            }
+            ReturnValue::hlushort => {
+                if let Some(x) = self.return_value_as_hlushort() {
+                    ds.field("return_value", &x)
+                } else {
+                    ds.field(
+                        "return_value",
+                        &"InvalidFlatbuffer: Union discriminant does not match value.",
+                    )
+                }
+            }
+            ReturnValue::hlbyte => {
+                if let Some(x) = self.return_value_as_hlbyte() {
+                    ds.field("return_value", &x)
+                } else {
+                    ds.field(
+                        "return_value",
+                        &"InvalidFlatbuffer: Union discriminant does not match value.",
+                    )
+                }
+            }
+            ReturnValue::hlubyte => {
+                if let Some(x) = self.return_value_as_hlubyte() {
+                    ds.field("return_value", &x)
+                } else {
+                    ds.field(
+                        "return_value",
+                        &"InvalidFlatbuffer: Union discriminant does not match value.",
+                    )
+                }
+            }
             _ => {
                 let x: Option<()> = None;
                 ds.field("return_value", &x)
@@ -31,6 +31,8 @@ impl<'a> HostFunctionDefinition<'a> {
     pub const VT_FUNCTION_NAME: flatbuffers::VOffsetT = 4;
     pub const VT_PARAMETERS: flatbuffers::VOffsetT = 6;
     pub const VT_RETURN_TYPE: flatbuffers::VOffsetT = 8;
+    pub const VT_FLAGS: flatbuffers::VOffsetT = 10;
+    pub const VT_MAX_RESULT_SIZE: flatbuffers::VOffsetT = 12;
 
     #[inline]
     pub unsafe fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
@@ -42,12 +44,14 @@ impl<'a> HostFunctionDefinition<'a> {
         args: &'args HostFunctionDefinitionArgs<'args>,
     ) -> flatbuffers::WIPOffset<HostFunctionDefinition<'bldr>> {
         let mut builder = HostFunctionDefinitionBuilder::new(_fbb);
+        builder.add_max_result_size(args.max_result_size);
         if let Some(x) = args.parameters {
             builder.add_parameters(x);
         }
         if let Some(x) = args.function_name {
             builder.add_function_name(x);
         }
+        builder.add_flags(args.flags);
         builder.add_return_type(args.return_type);
         builder.finish()
     }
@@ -103,6 +107,28 @@ impl<'a> HostFunctionDefinition<'a> {
                 .unwrap()
         }
     }
+    #[inline]
+    pub fn flags(&self) -> u8 {
+        // Safety:
+        // Created from valid Table for this object
+        // which contains a valid value in this slot
+        unsafe {
+            self._tab
+                .get::<u8>(HostFunctionDefinition::VT_FLAGS, Some(0))
+                .unwrap()
+        }
+    }
+    #[inline]
+    pub fn max_result_size(&self) -> u64 {
+        // Safety:
+        // Created from valid Table for this object
+        // which contains a valid value in this slot
+        unsafe {
+            self._tab
+                .get::<u64>(HostFunctionDefinition::VT_MAX_RESULT_SIZE, Some(0))
+                .unwrap()
+        }
+    }
 }
 
 impl flatbuffers::Verifiable for HostFunctionDefinition<'_> {
@@ -124,6 +150,8 @@ impl flatbuffers::Verifiable for HostFunctionDefinition<'_> {
                 false,
             )?
             .visit_field::<ReturnType>("return_type", Self::VT_RETURN_TYPE, false)?
+            .visit_field::<u8>("flags", Self::VT_FLAGS, false)?
+            .visit_field::<u64>("max_result_size", Self::VT_MAX_RESULT_SIZE, false)?
             .finish();
         Ok(())
     }
@@ -132,6 +160,8 @@ pub struct HostFunctionDefinitionArgs<'a> {
     pub function_name: Option<flatbuffers::WIPOffset<&'a str>>,
     pub parameters: Option<flatbuffers::WIPOffset<flatbuffers::Vector<'a, ParameterType>>>,
     pub return_type: ReturnType,
+    pub flags: u8,
+    pub max_result_size: u64,
 }
 impl<'a> Default for HostFunctionDefinitionArgs<'a> {
     #[inline]
@@ -140,6 +170,8 @@ impl<'a> Default for HostFunctionDefinitionArgs<'a> {
             function_name: None, // required field
             parameters: None,
             return_type: ReturnType::hlint,
+            flags: 0,
+            max_result_size: 0,
         }
     }
 }
@@ -175,6 +207,19 @@ impl<'a: 'b, 'b, A: flatbuffers::Allocator + 'a> HostFunctionDefinitionBuilder<'
         );
     }
     #[inline]
+    pub fn add_flags(&mut self, flags: u8) {
+        self.fbb_
+            .push_slot::<u8>(HostFunctionDefinition::VT_FLAGS, flags, 0);
+    }
+    #[inline]
+    pub fn add_max_result_size(&mut self, max_result_size: u64) {
+        self.fbb_.push_slot::<u64>(
+            HostFunctionDefinition::VT_MAX_RESULT_SIZE,
+            max_result_size,
+            0,
+        );
+    }
+    #[inline]
     pub fn new(
         _fbb: &'b mut flatbuffers::FlatBufferBuilder<'a, A>,
     ) -> HostFunctionDefinitionBuilder<'a, 'b, A> {
@@ -199,6 +244,8 @@ impl core::fmt::Debug for HostFunctionDefinition<'_> {
         ds.field("function_name", &self.function_name());
         ds.field("parameters", &self.parameters());
         ds.field("return_type", &self.return_type());
+        ds.field("flags", &self.flags());
+        ds.field("max_result_size", &self.max_result_size());
         ds.finish()
     }
 }
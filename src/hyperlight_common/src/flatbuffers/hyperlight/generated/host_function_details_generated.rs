@@ -29,6 +29,7 @@ impl<'a> flatbuffers::Follow<'a> for HostFunctionDetails<'a> {
 
 impl<'a> HostFunctionDetails<'a> {
     pub const VT_FUNCTIONS: flatbuffers::VOffsetT = 4;
+    pub const VT_FEATURES: flatbuffers::VOffsetT = 6;
 
     #[inline]
     pub unsafe fn init_from_table(table: flatbuffers::Table<'a>) -> Self {
@@ -40,6 +41,9 @@ impl<'a> HostFunctionDetails<'a> {
         args: &'args HostFunctionDetailsArgs<'args>,
     ) -> flatbuffers::WIPOffset<HostFunctionDetails<'bldr>> {
         let mut builder = HostFunctionDetailsBuilder::new(_fbb);
+        if let Some(x) = args.features {
+            builder.add_features(x);
+        }
         if let Some(x) = args.functions {
             builder.add_functions(x);
         }
@@ -60,6 +64,19 @@ impl<'a> HostFunctionDetails<'a> {
             >>(HostFunctionDetails::VT_FUNCTIONS, None)
         }
     }
+    #[inline]
+    pub fn features(
+        &self,
+    ) -> Option<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<&'a str>>> {
+        // Safety:
+        // Created from valid Table for this object
+        // which contains a valid value in this slot
+        unsafe {
+            self._tab.get::<flatbuffers::ForwardsUOffset<
+                flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<&'a str>>,
+            >>(HostFunctionDetails::VT_FEATURES, None)
+        }
+    }
 }
 
 impl flatbuffers::Verifiable for HostFunctionDetails<'_> {
@@ -73,6 +90,9 @@ impl flatbuffers::Verifiable for HostFunctionDetails<'_> {
             .visit_field::<flatbuffers::ForwardsUOffset<
                 flatbuffers::Vector<'_, flatbuffers::ForwardsUOffset<HostFunctionDefinition>>,
             >>("functions", Self::VT_FUNCTIONS, false)?
+            .visit_field::<flatbuffers::ForwardsUOffset<
+                flatbuffers::Vector<'_, flatbuffers::ForwardsUOffset<&'_ str>>,
+            >>("features", Self::VT_FEATURES, false)?
             .finish();
         Ok(())
     }
@@ -83,11 +103,17 @@ pub struct HostFunctionDetailsArgs<'a> {
             flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<HostFunctionDefinition<'a>>>,
         >,
     >,
+    pub features: Option<
+        flatbuffers::WIPOffset<flatbuffers::Vector<'a, flatbuffers::ForwardsUOffset<&'a str>>>,
+    >,
 }
 impl<'a> Default for HostFunctionDetailsArgs<'a> {
     #[inline]
     fn default() -> Self {
-        HostFunctionDetailsArgs { functions: None }
+        HostFunctionDetailsArgs {
+            functions: None,
+            features: None,
+        }
     }
 }
 
@@ -109,6 +135,18 @@ impl<'a: 'b, 'b, A: flatbuffers::Allocator + 'a> HostFunctionDetailsBuilder<'a,
         );
     }
     #[inline]
+    pub fn add_features(
+        &mut self,
+        features: flatbuffers::WIPOffset<
+            flatbuffers::Vector<'b, flatbuffers::ForwardsUOffset<&'b str>>,
+        >,
+    ) {
+        self.fbb_.push_slot_always::<flatbuffers::WIPOffset<_>>(
+            HostFunctionDetails::VT_FEATURES,
+            features,
+        );
+    }
+    #[inline]
     pub fn new(
         _fbb: &'b mut flatbuffers::FlatBufferBuilder<'a, A>,
     ) -> HostFunctionDetailsBuilder<'a, 'b, A> {
@@ -129,6 +167,7 @@ impl core::fmt::Debug for HostFunctionDetails<'_> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let mut ds = f.debug_struct("HostFunctionDetails");
         ds.field("functions", &self.functions());
+        ds.field("features", &self.features());
         ds.finish()
     }
 }
@@ -198,6 +198,62 @@ impl<'a> Parameter<'a> {
             None
         }
     }
+
+    #[inline]
+    #[allow(non_snake_case)]
+    pub fn value_as_hlshort(&self) -> Option<hlshort<'a>> {
+        if self.value_type() == ParameterValue::hlshort {
+            let u = self.value();
+            // Safety:
+            // Created from a valid Table for this object
+            // Which contains a valid union in this slot
+            Some(unsafe { hlshort::init_from_table(u) })
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    #[allow(non_snake_case)]
+    pub fn value_as_hlushort(&self) -> Option<hlushort<'a>> {
+        if self.value_type() == ParameterValue::hlushort {
+            let u = self.value();
+            // Safety:
+            // Created from a valid Table for this object
+            // Which contains a valid union in this slot
+            Some(unsafe { hlushort::init_from_table(u) })
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    #[allow(non_snake_case)]
+    pub fn value_as_hlbyte(&self) -> Option<hlbyte<'a>> {
+        if self.value_type() == ParameterValue::hlbyte {
+            let u = self.value();
+            // Safety:
+            // Created from a valid Table for this object
+            // Which contains a valid union in this slot
+            Some(unsafe { hlbyte::init_from_table(u) })
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    #[allow(non_snake_case)]
+    pub fn value_as_hlubyte(&self) -> Option<hlubyte<'a>> {
+        if self.value_type() == ParameterValue::hlubyte {
+            let u = self.value();
+            // Safety:
+            // Created from a valid Table for this object
+            // Which contains a valid union in this slot
+            Some(unsafe { hlubyte::init_from_table(u) })
+        } else {
+            None
+        }
+    }
 }
 
 impl flatbuffers::Verifiable for Parameter<'_> {
@@ -260,6 +316,26 @@ impl flatbuffers::Verifiable for Parameter<'_> {
                             "ParameterValue::hlvecbytes",
                             pos,
                         ),
+                    ParameterValue::hlshort => v
+                        .verify_union_variant::<flatbuffers::ForwardsUOffset<hlshort>>(
+                            "ParameterValue::hlshort",
+                            pos,
+                        ),
+                    ParameterValue::hlushort => v
+                        .verify_union_variant::<flatbuffers::ForwardsUOffset<hlushort>>(
+                            "ParameterValue::hlushort",
+                            pos,
+                        ),
+                    ParameterValue::hlbyte => v
+                        .verify_union_variant::<flatbuffers::ForwardsUOffset<hlbyte>>(
+                            "ParameterValue::hlbyte",
+                            pos,
+                        ),
+                    ParameterValue::hlubyte => v
+                        .verify_union_variant::<flatbuffers::ForwardsUOffset<hlubyte>>(
+                            "ParameterValue::hlubyte",
+                            pos,
+                        ),
                     _ => Ok(()),
                 },
             )?
@@ -410,6 +486,46 @@ impl core::fmt::Debug for Parameter<'_> {
                     )
                 }
             }
+            ParameterValue::hlshort => {
+                if let Some(x) = self.value_as_hlshort() {
+                    ds.field("value", &x)
+                } else {
+                    ds.field(
+                        "value",
+                        &"InvalidFlatbuffer: Union discriminant does not match value.",
+                    )
+                }
+            }
+            ParameterValue::hlushort => {
+                if let Some(x) = self.value_as_hlushort() {
+                    ds.field("value", &x)
+                } else {
+                    ds.field(
+                        "value",
+                        &"InvalidFlatbuffer: Union discriminant does not match value.",
+                    )
+                }
+            }
+            ParameterValue::hlbyte => {
+                if let Some(x) = self.value_as_hlbyte() {
+                    ds.field("value", &x)
+                } else {
+                    ds.field(
+                        "value",
+                        &"InvalidFlatbuffer: Union discriminant does not match value.",
+                    )
+                }
+            }
+            ParameterValue::hlubyte => {
+                if let Some(x) = self.value_as_hlubyte() {
+                    ds.field("value", &x)
+                } else {
+                    ds.field(
+                        "value",
+                        &"InvalidFlatbuffer: Union discriminant does not match value.",
+                    )
+                }
+            }
             _ => {
                 let x: Option<()> = None;
                 ds.field("value", &x)
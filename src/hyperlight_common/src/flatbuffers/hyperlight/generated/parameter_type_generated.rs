@@ -19,13 +19,13 @@ pub const ENUM_MIN_PARAMETER_TYPE: u8 = 0;
     since = "2.0.0",
     note = "Use associated constants instead. This will no longer be generated in 2021."
 )]
-pub const ENUM_MAX_PARAMETER_TYPE: u8 = 8;
+pub const ENUM_MAX_PARAMETER_TYPE: u8 = 12;
 #[deprecated(
     since = "2.0.0",
     note = "Use associated constants instead. This will no longer be generated in 2021."
 )]
 #[allow(non_camel_case_types)]
-pub const ENUM_VALUES_PARAMETER_TYPE: [ParameterType; 9] = [
+pub const ENUM_VALUES_PARAMETER_TYPE: [ParameterType; 13] = [
     ParameterType::hlint,
     ParameterType::hluint,
     ParameterType::hllong,
@@ -35,6 +35,10 @@ pub const ENUM_VALUES_PARAMETER_TYPE: [ParameterType; 9] = [
     ParameterType::hlstring,
     ParameterType::hlbool,
     ParameterType::hlvecbytes,
+    ParameterType::hlshort,
+    ParameterType::hlushort,
+    ParameterType::hlbyte,
+    ParameterType::hlubyte,
 ];
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
@@ -51,9 +55,13 @@ impl ParameterType {
     pub const hlstring: Self = Self(6);
     pub const hlbool: Self = Self(7);
     pub const hlvecbytes: Self = Self(8);
+    pub const hlshort: Self = Self(9);
+    pub const hlushort: Self = Self(10);
+    pub const hlbyte: Self = Self(11);
+    pub const hlubyte: Self = Self(12);
 
     pub const ENUM_MIN: u8 = 0;
-    pub const ENUM_MAX: u8 = 8;
+    pub const ENUM_MAX: u8 = 12;
     pub const ENUM_VALUES: &'static [Self] = &[
         Self::hlint,
         Self::hluint,
@@ -64,6 +72,10 @@ impl ParameterType {
         Self::hlstring,
         Self::hlbool,
         Self::hlvecbytes,
+        Self::hlshort,
+        Self::hlushort,
+        Self::hlbyte,
+        Self::hlubyte,
     ];
     /// Returns the variant's name or "" if unknown.
     pub fn variant_name(self) -> Option<&'static str> {
@@ -77,6 +89,10 @@ impl ParameterType {
             Self::hlstring => Some("hlstring"),
             Self::hlbool => Some("hlbool"),
             Self::hlvecbytes => Some("hlvecbytes"),
+            Self::hlshort => Some("hlshort"),
+            Self::hlushort => Some("hlushort"),
+            Self::hlbyte => Some("hlbyte"),
+            Self::hlubyte => Some("hlubyte"),
             _ => None,
         }
     }
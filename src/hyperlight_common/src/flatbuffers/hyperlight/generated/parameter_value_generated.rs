@@ -19,13 +19,13 @@ pub const ENUM_MIN_PARAMETER_VALUE: u8 = 0;
     since = "2.0.0",
     note = "Use associated constants instead. This will no longer be generated in 2021."
 )]
-pub const ENUM_MAX_PARAMETER_VALUE: u8 = 9;
+pub const ENUM_MAX_PARAMETER_VALUE: u8 = 13;
 #[deprecated(
     since = "2.0.0",
     note = "Use associated constants instead. This will no longer be generated in 2021."
 )]
 #[allow(non_camel_case_types)]
-pub const ENUM_VALUES_PARAMETER_VALUE: [ParameterValue; 10] = [
+pub const ENUM_VALUES_PARAMETER_VALUE: [ParameterValue; 14] = [
     ParameterValue::NONE,
     ParameterValue::hlint,
     ParameterValue::hluint,
@@ -36,6 +36,10 @@ pub const ENUM_VALUES_PARAMETER_VALUE: [ParameterValue; 10] = [
     ParameterValue::hlstring,
     ParameterValue::hlbool,
     ParameterValue::hlvecbytes,
+    ParameterValue::hlshort,
+    ParameterValue::hlushort,
+    ParameterValue::hlbyte,
+    ParameterValue::hlubyte,
 ];
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
@@ -53,9 +57,13 @@ impl ParameterValue {
     pub const hlstring: Self = Self(7);
     pub const hlbool: Self = Self(8);
     pub const hlvecbytes: Self = Self(9);
+    pub const hlshort: Self = Self(10);
+    pub const hlushort: Self = Self(11);
+    pub const hlbyte: Self = Self(12);
+    pub const hlubyte: Self = Self(13);
 
     pub const ENUM_MIN: u8 = 0;
-    pub const ENUM_MAX: u8 = 9;
+    pub const ENUM_MAX: u8 = 13;
     pub const ENUM_VALUES: &'static [Self] = &[
         Self::NONE,
         Self::hlint,
@@ -67,6 +75,10 @@ impl ParameterValue {
         Self::hlstring,
         Self::hlbool,
         Self::hlvecbytes,
+        Self::hlshort,
+        Self::hlushort,
+        Self::hlbyte,
+        Self::hlubyte,
     ];
     /// Returns the variant's name or "" if unknown.
     pub fn variant_name(self) -> Option<&'static str> {
@@ -81,6 +93,10 @@ impl ParameterValue {
             Self::hlstring => Some("hlstring"),
             Self::hlbool => Some("hlbool"),
             Self::hlvecbytes => Some("hlvecbytes"),
+            Self::hlshort => Some("hlshort"),
+            Self::hlushort => Some("hlushort"),
+            Self::hlbyte => Some("hlbyte"),
+            Self::hlubyte => Some("hlubyte"),
             _ => None,
         }
     }
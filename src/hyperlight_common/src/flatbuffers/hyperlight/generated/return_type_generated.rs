@@ -19,13 +19,13 @@ pub const ENUM_MIN_RETURN_TYPE: u8 = 0;
     since = "2.0.0",
     note = "Use associated constants instead. This will no longer be generated in 2021."
 )]
-pub const ENUM_MAX_RETURN_TYPE: u8 = 9;
+pub const ENUM_MAX_RETURN_TYPE: u8 = 13;
 #[deprecated(
     since = "2.0.0",
     note = "Use associated constants instead. This will no longer be generated in 2021."
 )]
 #[allow(non_camel_case_types)]
-pub const ENUM_VALUES_RETURN_TYPE: [ReturnType; 10] = [
+pub const ENUM_VALUES_RETURN_TYPE: [ReturnType; 14] = [
     ReturnType::hlint,
     ReturnType::hluint,
     ReturnType::hllong,
@@ -36,6 +36,10 @@ pub const ENUM_VALUES_RETURN_TYPE: [ReturnType; 10] = [
     ReturnType::hlbool,
     ReturnType::hlvoid,
     ReturnType::hlsizeprefixedbuffer,
+    ReturnType::hlshort,
+    ReturnType::hlushort,
+    ReturnType::hlbyte,
+    ReturnType::hlubyte,
 ];
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
@@ -53,9 +57,13 @@ impl ReturnType {
     pub const hlbool: Self = Self(7);
     pub const hlvoid: Self = Self(8);
     pub const hlsizeprefixedbuffer: Self = Self(9);
+    pub const hlshort: Self = Self(10);
+    pub const hlushort: Self = Self(11);
+    pub const hlbyte: Self = Self(12);
+    pub const hlubyte: Self = Self(13);
 
     pub const ENUM_MIN: u8 = 0;
-    pub const ENUM_MAX: u8 = 9;
+    pub const ENUM_MAX: u8 = 13;
     pub const ENUM_VALUES: &'static [Self] = &[
         Self::hlint,
         Self::hluint,
@@ -67,6 +75,10 @@ impl ReturnType {
         Self::hlbool,
         Self::hlvoid,
         Self::hlsizeprefixedbuffer,
+        Self::hlshort,
+        Self::hlushort,
+        Self::hlbyte,
+        Self::hlubyte,
     ];
     /// Returns the variant's name or "" if unknown.
     pub fn variant_name(self) -> Option<&'static str> {
@@ -81,6 +93,10 @@ impl ReturnType {
             Self::hlbool => Some("hlbool"),
             Self::hlvoid => Some("hlvoid"),
             Self::hlsizeprefixedbuffer => Some("hlsizeprefixedbuffer"),
+            Self::hlshort => Some("hlshort"),
+            Self::hlushort => Some("hlushort"),
+            Self::hlbyte => Some("hlbyte"),
+            Self::hlubyte => Some("hlubyte"),
             _ => None,
         }
     }
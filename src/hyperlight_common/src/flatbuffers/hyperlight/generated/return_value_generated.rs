@@ -19,13 +19,13 @@ pub const ENUM_MIN_RETURN_VALUE: u8 = 0;
     since = "2.0.0",
     note = "Use associated constants instead. This will no longer be generated in 2021."
 )]
-pub const ENUM_MAX_RETURN_VALUE: u8 = 10;
+pub const ENUM_MAX_RETURN_VALUE: u8 = 14;
 #[deprecated(
     since = "2.0.0",
     note = "Use associated constants instead. This will no longer be generated in 2021."
 )]
 #[allow(non_camel_case_types)]
-pub const ENUM_VALUES_RETURN_VALUE: [ReturnValue; 11] = [
+pub const ENUM_VALUES_RETURN_VALUE: [ReturnValue; 15] = [
     ReturnValue::NONE,
     ReturnValue::hlint,
     ReturnValue::hluint,
@@ -37,6 +37,10 @@ pub const ENUM_VALUES_RETURN_VALUE: [ReturnValue; 11] = [
     ReturnValue::hlbool,
     ReturnValue::hlvoid,
     ReturnValue::hlsizeprefixedbuffer,
+    ReturnValue::hlshort,
+    ReturnValue::hlushort,
+    ReturnValue::hlbyte,
+    ReturnValue::hlubyte,
 ];
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
@@ -55,9 +59,13 @@ impl ReturnValue {
     pub const hlbool: Self = Self(8);
     pub const hlvoid: Self = Self(9);
     pub const hlsizeprefixedbuffer: Self = Self(10);
+    pub const hlshort: Self = Self(11);
+    pub const hlushort: Self = Self(12);
+    pub const hlbyte: Self = Self(13);
+    pub const hlubyte: Self = Self(14);
 
     pub const ENUM_MIN: u8 = 0;
-    pub const ENUM_MAX: u8 = 10;
+    pub const ENUM_MAX: u8 = 14;
     pub const ENUM_VALUES: &'static [Self] = &[
         Self::NONE,
         Self::hlint,
@@ -70,6 +78,10 @@ impl ReturnValue {
         Self::hlbool,
         Self::hlvoid,
         Self::hlsizeprefixedbuffer,
+        Self::hlshort,
+        Self::hlushort,
+        Self::hlbyte,
+        Self::hlubyte,
     ];
     /// Returns the variant's name or "" if unknown.
     pub fn variant_name(self) -> Option<&'static str> {
@@ -85,6 +97,10 @@ impl ReturnValue {
             Self::hlbool => Some("hlbool"),
             Self::hlvoid => Some("hlvoid"),
             Self::hlsizeprefixedbuffer => Some("hlsizeprefixedbuffer"),
+            Self::hlshort => Some("hlshort"),
+            Self::hlushort => Some("hlushort"),
+            Self::hlbyte => Some("hlbyte"),
+            Self::hlubyte => Some("hlubyte"),
             _ => None,
         }
     }
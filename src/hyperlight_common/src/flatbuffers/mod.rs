@@ -20,6 +20,14 @@ pub mod hyperlight {
         pub use self::hllong_generated::*;
         mod hlulong_generated;
         pub use self::hlulong_generated::*;
+        mod hlshort_generated;
+        pub use self::hlshort_generated::*;
+        mod hlushort_generated;
+        pub use self::hlushort_generated::*;
+        mod hlbyte_generated;
+        pub use self::hlbyte_generated::*;
+        mod hlubyte_generated;
+        pub use self::hlubyte_generated::*;
         mod hlfloat_generated;
         pub use self::hlfloat_generated::*;
         mod hldouble_generated;
@@ -0,0 +1,227 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! The record of a failed assertion or a trap that a guest aborts with,
+//! written to the guest panic context buffer by `hl_assert!` and
+//! `hl_trap!` in place of a message.
+//!
+//! The encoded record is `GUEST_DIAGNOSTIC_MAGIC`, then:
+//!
+//! - the kind of the record, a `u8`
+//! - the little-endian `u32` code the guest trapped with
+//! - the little-endian `u32` line and `u32` column of the macro
+//! - the source file, the asserted condition and the message, each a
+//!   little-endian `u16` length followed by the string as UTF-8
+//!
+//! The guest panic context buffer isn't cleared between aborts, so any
+//! bytes after the record are ignored.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use anyhow::{bail, Result};
+
+/// The first bytes of an encoded diagnostic record
+pub const GUEST_DIAGNOSTIC_MAGIC: &[u8; 8] = b"HLDIAG01";
+
+/// Why the guest aborted
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[repr(u8)]
+pub enum DiagnosticKind {
+    /// An `hl_assert!` failed
+    Assertion = 0,
+    /// The guest called `hl_trap!`
+    Trap = 1,
+}
+
+/// Where and why a guest aborted with `hl_assert!` or `hl_trap!`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GuestDiagnostic {
+    /// Whether an assertion failed or the guest trapped
+    pub kind: DiagnosticKind,
+    /// The code the guest trapped with, 0 for assertions
+    pub code: u32,
+    /// The source file of the macro
+    pub file: String,
+    /// The line of the macro
+    pub line: u32,
+    /// The column of the macro
+    pub column: u32,
+    /// The condition that was false, empty for traps
+    pub condition: String,
+    /// The message formatted by the macro, if any
+    pub message: String,
+}
+
+impl GuestDiagnostic {
+    /// The size of the record without any of its strings
+    const FIXED_LEN: usize = GUEST_DIAGNOSTIC_MAGIC.len() + 1 + 4 + 4 + 4 + 3 * 2;
+
+    /// Encode the record in at most `max_len` bytes, shortening the
+    /// message, then the condition, and then the file name if it doesn't
+    /// fit. Returns `None` if `max_len` is too small for even an empty
+    /// record.
+    pub fn to_bytes(&self, max_len: usize) -> Option<Vec<u8>> {
+        let mut room = max_len.checked_sub(Self::FIXED_LEN)?;
+        let file = take(&self.file, &mut room);
+        let condition = take(&self.condition, &mut room);
+        let message = take(&self.message, &mut room);
+
+        let mut bytes = Vec::from(&GUEST_DIAGNOSTIC_MAGIC[..]);
+        bytes.push(self.kind as u8);
+        bytes.extend_from_slice(&self.code.to_le_bytes());
+        bytes.extend_from_slice(&self.line.to_le_bytes());
+        bytes.extend_from_slice(&self.column.to_le_bytes());
+        for s in [file, condition, message] {
+            bytes.extend_from_slice(&(s.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(s.as_bytes());
+        }
+        Some(bytes)
+    }
+
+    /// Decode a record encoded with `to_bytes` at the start of `bytes`, or
+    /// return `None` if `bytes` doesn't start with one
+    pub fn from_bytes(bytes: &[u8]) -> Result<Option<Self>> {
+        let Some(mut bytes) = bytes.strip_prefix(&GUEST_DIAGNOSTIC_MAGIC[..]) else {
+            return Ok(None);
+        };
+        let kind = match take_array::<1>(&mut bytes)? {
+            [0] => DiagnosticKind::Assertion,
+            [1] => DiagnosticKind::Trap,
+            [kind] => bail!("Unknown guest diagnostic kind {}", kind),
+        };
+        let code = u32::from_le_bytes(take_array(&mut bytes)?);
+        let line = u32::from_le_bytes(take_array(&mut bytes)?);
+        let column = u32::from_le_bytes(take_array(&mut bytes)?);
+        let file = take_string(&mut bytes)?;
+        let condition = take_string(&mut bytes)?;
+        let message = take_string(&mut bytes)?;
+        Ok(Some(Self {
+            kind,
+            code,
+            file,
+            line,
+            column,
+            condition,
+            message,
+        }))
+    }
+}
+
+impl fmt::Display for GuestDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            DiagnosticKind::Assertion => write!(f, "assertion `{}` failed", self.condition)?,
+            DiagnosticKind::Trap => write!(f, "trap {}", self.code)?,
+        }
+        write!(f, " at {}:{}:{}", self.file, self.line, self.column)?;
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        Ok(())
+    }
+}
+
+/// The longest prefix of `s` that fits in `room` bytes along with its
+/// length, and in a `u16`, ending on a character boundary
+fn take<'a>(s: &'a str, room: &mut usize) -> &'a str {
+    let mut len = s.len().min(*room).min(u16::MAX as usize);
+    while !s.is_char_boundary(len) {
+        len -= 1;
+    }
+    *room -= len;
+    &s[..len]
+}
+
+fn take_array<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N]> {
+    if bytes.len() < N {
+        bail!("Guest diagnostic record is truncated");
+    }
+    let (taken, rest) = bytes.split_at(N);
+    *bytes = rest;
+    let mut array = [0; N];
+    array.copy_from_slice(taken);
+    Ok(array)
+}
+
+fn take_string(bytes: &mut &[u8]) -> Result<String> {
+    let len = u16::from_le_bytes(take_array(bytes)?) as usize;
+    if bytes.len() < len {
+        bail!("Guest diagnostic record is truncated");
+    }
+    let (taken, rest) = bytes.split_at(len);
+    *bytes = rest;
+    match core::str::from_utf8(taken) {
+        Ok(s) => Ok(s.to_string()),
+        Err(_) => bail!("Guest diagnostic string is not UTF-8"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::{DiagnosticKind, GuestDiagnostic};
+
+    fn diagnostic() -> GuestDiagnostic {
+        GuestDiagnostic {
+            kind: DiagnosticKind::Assertion,
+            code: 0,
+            file: "src/main.rs".to_string(),
+            line: 12,
+            column: 5,
+            condition: "x > 0".to_string(),
+            message: "x is négative".to_string(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let diagnostic = diagnostic();
+        let mut bytes = diagnostic.to_bytes(1024).unwrap();
+        // whatever follows the record in the buffer is ignored
+        bytes.extend_from_slice(&[0, 0, 7]);
+        assert_eq!(
+            Some(diagnostic.clone()),
+            GuestDiagnostic::from_bytes(&bytes).unwrap()
+        );
+        assert_eq!(
+            "assertion `x > 0` failed at src/main.rs:12:5: x is négative",
+            diagnostic.to_string()
+        );
+
+        // anything else, e.g. a panic message, isn't a record
+        assert_eq!(None, GuestDiagnostic::from_bytes(b"panicked at").unwrap());
+        assert!(GuestDiagnostic::from_bytes(&bytes[..20]).is_err());
+    }
+
+    #[test]
+    fn long_records_are_shortened() {
+        let diagnostic = diagnostic();
+        // room for the file, the condition and "x is n"
+        let len = GuestDiagnostic::FIXED_LEN + 11 + 5 + 7;
+        let bytes = diagnostic.to_bytes(len).unwrap();
+        assert!(bytes.len() <= len);
+        let decoded = GuestDiagnostic::from_bytes(&bytes).unwrap().unwrap();
+        // the message is cut before the multi-byte character
+        assert_eq!("x is n", decoded.message);
+        assert_eq!(diagnostic.condition, decoded.condition);
+        assert!(diagnostic
+            .to_bytes(GuestDiagnostic::FIXED_LEN - 1)
+            .is_none());
+    }
+}
@@ -0,0 +1,215 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! The metadata the guest build tooling embeds in a guest binary, in the
+//! `METADATA_SECTION` section, as the NUL terminated key and value of every
+//! entry. The linker may pad the section with zeroes.
+//!
+//! Most entries only describe the guest, but the host reads some of them
+//! as limits that travel with the guest binary, which it applies unless
+//! its own configuration overrides them:
+//!
+//! - `HEAP_SIZE_KEY` and `STACK_SIZE_KEY`, the heap and stack sizes in
+//!   bytes the guest expects, instead of those in the binary's headers
+//! - `function_timeout_key(name)`, how many milliseconds calls to the guest
+//!   function `name` may run for by default
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+
+/// The section guest metadata is embedded in. This is short enough to be
+/// kept whole in a PE image.
+pub const METADATA_SECTION: &str = ".hlmeta";
+/// The key of the heap size, in bytes, the guest expects
+pub const HEAP_SIZE_KEY: &str = "memory.heap_size";
+/// The key of the stack size, in bytes, the guest expects
+pub const STACK_SIZE_KEY: &str = "memory.stack_size";
+/// The key of the version of the host-guest interface the guest was built
+/// against
+pub const ABI_VERSION_KEY: &str = "abi_version";
+/// The version of the interface between the host and the guest, such as
+/// the layout of the PEB, that guests built with this crate declare in
+/// their metadata. It is incremented whenever a change stops guests and
+/// hosts built with different versions from working together.
+pub const ABI_VERSION: u32 = 1;
+/// The prefix of the keys of the default timeouts of guest functions
+pub const FUNCTION_TIMEOUT_KEY_PREFIX: &str = "timeout.";
+
+/// The key of the default timeout, in milliseconds, of calls to the guest
+/// function `function_name`
+pub fn function_timeout_key(function_name: &str) -> String {
+    alloc::format!("{FUNCTION_TIMEOUT_KEY_PREFIX}{function_name}")
+}
+
+/// The metadata of a guest binary
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct GuestMetadata {
+    entries: Vec<(String, String)>,
+}
+
+impl GuestMetadata {
+    /// Metadata with `entries`
+    pub fn new(entries: Vec<(String, String)>) -> Self {
+        Self { entries }
+    }
+
+    /// The key and value of every entry
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.entries
+    }
+
+    /// The value of `key`, if the metadata has it
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The heap size in bytes the guest expects, if it declared one
+    pub fn heap_size(&self) -> Option<u64> {
+        self.get(HEAP_SIZE_KEY).and_then(|v| v.parse().ok())
+    }
+
+    /// The stack size in bytes the guest expects, if it declared one
+    pub fn stack_size(&self) -> Option<u64> {
+        self.get(STACK_SIZE_KEY).and_then(|v| v.parse().ok())
+    }
+
+    /// The version of the host-guest interface the guest was built against,
+    /// if it declared one
+    pub fn abi_version(&self) -> Option<u32> {
+        self.get(ABI_VERSION_KEY).and_then(|v| v.parse().ok())
+    }
+
+    /// The name and default timeout of every guest function the guest
+    /// declared a timeout for
+    pub fn function_timeouts(&self) -> impl Iterator<Item = (&str, Duration)> {
+        self.entries.iter().filter_map(|(key, value)| {
+            let function_name = key.strip_prefix(FUNCTION_TIMEOUT_KEY_PREFIX)?;
+            let millis = value.parse().ok()?;
+            Some((function_name, Duration::from_millis(millis)))
+        })
+    }
+
+    /// Check that the values of the keys the host reads are numbers
+    fn validate(&self) -> Result<()> {
+        for (key, value) in &self.entries {
+            let numeric = key == HEAP_SIZE_KEY
+                || key == STACK_SIZE_KEY
+                || key == ABI_VERSION_KEY
+                || key.starts_with(FUNCTION_TIMEOUT_KEY_PREFIX);
+            if numeric && value.parse::<u64>().is_err() {
+                bail!("guest metadata {:?} is not a number: {:?}", key, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Encode the metadata as it is embedded in `METADATA_SECTION`
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        self.validate()?;
+        let mut bytes = Vec::new();
+        for (key, value) in &self.entries {
+            if key.is_empty() || key.contains('\0') || value.contains('\0') {
+                bail!("invalid guest metadata entry {:?}", key);
+            }
+            bytes.extend_from_slice(key.as_bytes());
+            bytes.push(0);
+            bytes.extend_from_slice(value.as_bytes());
+            bytes.push(0);
+        }
+        Ok(bytes)
+    }
+
+    /// Decode metadata encoded with `to_bytes`, ignoring any padding
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let utf8 = |s: &[u8]| {
+            core::str::from_utf8(s)
+                .map(ToString::to_string)
+                .map_err(|_| anyhow!("guest metadata is not UTF-8"))
+        };
+        let mut strings = bytes.split(|b| *b == 0);
+        let mut entries = Vec::new();
+        while let Some(key) = strings.next() {
+            // Keys are never empty, so an empty key is the start of the padding
+            if key.is_empty() {
+                break;
+            }
+            let value = strings
+                .next()
+                .ok_or_else(|| anyhow!("guest metadata ends with a key"))?;
+            entries.push((utf8(key)?, utf8(value)?));
+        }
+        let metadata = Self { entries };
+        metadata.validate()?;
+        Ok(metadata)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::time::Duration;
+
+    use super::{function_timeout_key, GuestMetadata, ABI_VERSION_KEY, HEAP_SIZE_KEY};
+
+    #[test]
+    fn round_trip() {
+        let metadata = GuestMetadata::new(vec![
+            ("description".to_string(), "the guest".to_string()),
+            ("empty".to_string(), "".to_string()),
+            (HEAP_SIZE_KEY.to_string(), "65536".to_string()),
+            (function_timeout_key("math::add"), "250".to_string()),
+            (ABI_VERSION_KEY.to_string(), "1".to_string()),
+        ]);
+        let mut padded = metadata.to_bytes().unwrap();
+        padded.extend_from_slice(&[0; 8]);
+        let decoded = GuestMetadata::from_bytes(&padded).unwrap();
+        assert_eq!(metadata, decoded);
+        assert_eq!(Some("the guest"), decoded.get("description"));
+        assert_eq!(Some(65536), decoded.heap_size());
+        assert_eq!(None, decoded.stack_size());
+        assert_eq!(Some(1), decoded.abi_version());
+        assert_eq!(
+            vec![("math::add", Duration::from_millis(250))],
+            decoded.function_timeouts().collect::<Vec<_>>()
+        );
+        assert!(GuestMetadata::from_bytes(&[0; 16])
+            .unwrap()
+            .entries()
+            .is_empty());
+    }
+
+    #[test]
+    fn invalid_entries() {
+        let entry = |key: &str, value: &str| {
+            GuestMetadata::new(vec![(key.to_string(), value.to_string())]).to_bytes()
+        };
+        assert!(entry("", "value").is_err());
+        assert!(entry("key", "a\0b").is_err());
+        assert!(entry(HEAP_SIZE_KEY, "a lot").is_err());
+        assert!(entry(ABI_VERSION_KEY, "v1").is_err());
+        assert!(entry(&function_timeout_key("Spin"), "-1").is_err());
+        assert!(GuestMetadata::from_bytes(b"memory.stack_size\0big\0").is_err());
+        assert!(GuestMetadata::from_bytes(b"key").is_err());
+    }
+}
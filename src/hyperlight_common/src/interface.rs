@@ -0,0 +1,620 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Interface definitions, which describe the functions a guest exposes to
+//! the host in one place, so that the guest's registration code and the
+//! host's typed client are both generated from them.
+//!
+//! An interface definition lists one function per line, with Rust
+//! parameter and return types, and `//` comments:
+//!
+//! ```text
+//! // The functions of the simple guest
+//! fn Echo(message: String) -> String;
+//! fn math::Add(a: i32, b: i32) -> i32;
+//! fn Reset();
+//! ```
+//!
+//! The supported types are `i8`, `u8`, `i16`, `u16`, `i32`, `u32`, `i64`,
+//! `u64`, `f32`, `f64`, `String`, `bool` and `Vec<u8>`, and `()` as a
+//! return type. Guests
+//! generate their registration code with `generate_guest_skeleton`, and
+//! hosts generate a client with
+//! `hyperlight_host::func::client_gen::generate_guest_client_from_interface`.
+//! See `docs/interface-definitions.md`.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::flatbuffer_wrappers::function_call::FUNCTION_NAMESPACE_SEPARATOR;
+use crate::flatbuffer_wrappers::function_types::{ParameterType, ReturnType};
+
+/// Rust keywords, which can't be used as parameter names, and can only be
+/// used as method names as raw identifiers, except for those in
+/// `PATH_KEYWORDS`
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while", "abstract", "become", "box", "do", "final", "gen", "macro",
+    "override", "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
+/// Keywords that can't be raw identifiers either
+const PATH_KEYWORDS: &[&str] = &["crate", "self", "Self", "super"];
+
+/// A function in an interface definition
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InterfaceFunction {
+    /// The function name, which may be namespaced, as in `math::Add`
+    pub name: String,
+    /// The names and types of the function's parameters
+    pub parameters: Vec<(String, ParameterType)>,
+    /// The type of the function's return value
+    pub return_type: ReturnType,
+}
+
+/// The functions a guest exposes to the host, parsed from an interface
+/// definition
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct InterfaceDefinition {
+    /// The functions, in the order they were defined
+    pub functions: Vec<InterfaceFunction>,
+}
+
+impl InterfaceDefinition {
+    /// Parse an interface definition
+    pub fn parse(definition: &str) -> Result<Self> {
+        let mut functions: Vec<InterfaceFunction> = Vec::new();
+        for (i, line) in definition.lines().enumerate() {
+            let line = line.split("//").next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let function = parse_function(line).map_err(|e| anyhow!("line {}: {}", i + 1, e))?;
+            if functions.iter().any(|f| f.name == function.name) {
+                bail!(
+                    "line {}: function {} is defined twice",
+                    i + 1,
+                    function.name
+                );
+            }
+            functions.push(function);
+        }
+        Ok(Self { functions })
+    }
+
+    /// Generate the guest side of the interface: a trait named
+    /// `trait_name` with a method for each function, for the guest to
+    /// implement, and a `register_<trait_name>` function that registers a
+    /// guest function for each method of an implementation.
+    ///
+    /// The generated code uses the `hyperlight_guest`, `hyperlight_common`
+    /// and `alloc` crates.
+    pub fn generate_guest_skeleton(&self, trait_name: &str) -> Result<String> {
+        check_type_name(trait_name)?;
+        let mut out = String::new();
+        write_guest_skeleton(&mut out, trait_name, &self.functions)
+            .map_err(|e| anyhow!("Failed to generate {}: {}", trait_name, e))?;
+        Ok(out)
+    }
+}
+
+/// The snake case method name for the function `function_name`, with
+/// namespaces joined by underscores, so `math::AddInts` becomes
+/// `math_add_ints`
+pub fn method_name(function_name: &str) -> String {
+    let chars: Vec<char> = function_name.chars().collect();
+    let mut name = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() {
+            let prev = i.checked_sub(1).map(|p| chars[p]);
+            let next = chars.get(i + 1);
+            // start a new word at `AddInts` and at the end of an acronym,
+            // as in `GetHTTPStatus`
+            let new_word = prev.is_some_and(|p| p.is_ascii_lowercase() || p.is_ascii_digit())
+                || (prev.is_some_and(|p| p.is_ascii_uppercase())
+                    && next.is_some_and(|n| n.is_ascii_lowercase()));
+            if new_word && !name.ends_with('_') {
+                name.push('_');
+            }
+            name.push(c.to_ascii_lowercase());
+        } else if c.is_ascii_alphanumeric() {
+            name.push(*c);
+        } else if !name.is_empty() && !name.ends_with('_') {
+            // namespace separators and anything else that can't be in an
+            // identifier
+            name.push('_');
+        }
+    }
+    let mut name = name.trim_end_matches('_').to_string();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        name.insert(0, '_');
+    }
+    if PATH_KEYWORDS.contains(&name.as_str()) {
+        name.push('_');
+    } else if KEYWORDS.contains(&name.as_str()) {
+        name.insert_str(0, "r#");
+    }
+    name
+}
+
+/// Fail unless `name` can be used as the name of a generated type
+pub fn check_type_name(name: &str) -> Result<()> {
+    if !is_identifier(name) || KEYWORDS.contains(&name) {
+        bail!("{:?} is not a valid type name", name);
+    }
+    Ok(())
+}
+
+/// The Rust type of values of `parameter_type`
+pub fn parameter_rust_type(parameter_type: &ParameterType) -> &'static str {
+    match parameter_type {
+        ParameterType::Int => "i32",
+        ParameterType::UInt => "u32",
+        ParameterType::Long => "i64",
+        ParameterType::ULong => "u64",
+        ParameterType::Float => "f32",
+        ParameterType::Double => "f64",
+        ParameterType::String => "String",
+        ParameterType::Bool => "bool",
+        ParameterType::VecBytes => "Vec<u8>",
+        ParameterType::Short => "i16",
+        ParameterType::UShort => "u16",
+        ParameterType::Byte => "i8",
+        ParameterType::UByte => "u8",
+    }
+}
+
+/// The Rust type of values of `return_type`
+pub fn return_rust_type(return_type: &ReturnType) -> &'static str {
+    match return_type {
+        ReturnType::Int => "i32",
+        ReturnType::UInt => "u32",
+        ReturnType::Long => "i64",
+        ReturnType::ULong => "u64",
+        ReturnType::Float => "f32",
+        ReturnType::Double => "f64",
+        ReturnType::String => "String",
+        ReturnType::Bool => "bool",
+        ReturnType::Void => "()",
+        ReturnType::VecBytes => "Vec<u8>",
+        ReturnType::Short => "i16",
+        ReturnType::UShort => "u16",
+        ReturnType::Byte => "i8",
+        ReturnType::UByte => "u8",
+    }
+}
+
+fn parse_function(line: &str) -> Result<InterfaceFunction> {
+    let line = line
+        .strip_prefix("fn ")
+        .ok_or_else(|| anyhow!("expected `fn`"))?;
+    let line = line
+        .strip_suffix(';')
+        .ok_or_else(|| anyhow!("expected `;` at the end of the function"))?;
+    let (name, rest) = line
+        .split_once('(')
+        .ok_or_else(|| anyhow!("expected `(` after the function name"))?;
+    let name = name.trim();
+    if name.is_empty()
+        || !name
+            .split(FUNCTION_NAMESPACE_SEPARATOR)
+            .all(|segment| is_identifier(segment.trim()) && segment == segment.trim())
+    {
+        bail!("{:?} is not a valid function name", name);
+    }
+    let (parameters, rest) = rest
+        .split_once(')')
+        .ok_or_else(|| anyhow!("expected `)` after the parameters"))?;
+
+    let mut parsed_parameters: Vec<(String, ParameterType)> = Vec::new();
+    for parameter in parameters.split(',').map(str::trim) {
+        if parameter.is_empty() && parameters.trim().is_empty() {
+            break;
+        }
+        let (parameter_name, parameter_type) = parameter
+            .split_once(':')
+            .ok_or_else(|| anyhow!("expected `name: type`, got {:?}", parameter))?;
+        let parameter_name = parameter_name.trim();
+        if !is_identifier(parameter_name) || KEYWORDS.contains(&parameter_name) {
+            bail!("{:?} is not a valid parameter name", parameter_name);
+        }
+        if parsed_parameters.iter().any(|(n, _)| n == parameter_name) {
+            bail!("parameter {} is defined twice", parameter_name);
+        }
+        parsed_parameters.push((
+            parameter_name.to_string(),
+            parse_parameter_type(parameter_type.trim())?,
+        ));
+    }
+
+    let rest = rest.trim();
+    let return_type = if rest.is_empty() {
+        ReturnType::Void
+    } else {
+        let return_type = rest
+            .strip_prefix("->")
+            .ok_or_else(|| anyhow!("expected `->` or `;` after the parameters"))?;
+        parse_return_type(return_type.trim())?
+    };
+
+    Ok(InterfaceFunction {
+        name: name.to_string(),
+        parameters: parsed_parameters,
+        return_type,
+    })
+}
+
+fn parse_parameter_type(rust_type: &str) -> Result<ParameterType> {
+    Ok(match rust_type {
+        "i32" => ParameterType::Int,
+        "u32" => ParameterType::UInt,
+        "i64" => ParameterType::Long,
+        "u64" => ParameterType::ULong,
+        "f32" => ParameterType::Float,
+        "f64" => ParameterType::Double,
+        "String" => ParameterType::String,
+        "bool" => ParameterType::Bool,
+        "Vec<u8>" => ParameterType::VecBytes,
+        "i16" => ParameterType::Short,
+        "u16" => ParameterType::UShort,
+        "i8" => ParameterType::Byte,
+        "u8" => ParameterType::UByte,
+        other => bail!("unsupported parameter type {:?}", other),
+    })
+}
+
+fn parse_return_type(rust_type: &str) -> Result<ReturnType> {
+    Ok(match rust_type {
+        "()" => ReturnType::Void,
+        other => match parse_parameter_type(other) {
+            Ok(ParameterType::Int) => ReturnType::Int,
+            Ok(ParameterType::UInt) => ReturnType::UInt,
+            Ok(ParameterType::Long) => ReturnType::Long,
+            Ok(ParameterType::ULong) => ReturnType::ULong,
+            Ok(ParameterType::Float) => ReturnType::Float,
+            Ok(ParameterType::Double) => ReturnType::Double,
+            Ok(ParameterType::String) => ReturnType::String,
+            Ok(ParameterType::Bool) => ReturnType::Bool,
+            Ok(ParameterType::VecBytes) => ReturnType::VecBytes,
+            Ok(ParameterType::Short) => ReturnType::Short,
+            Ok(ParameterType::UShort) => ReturnType::UShort,
+            Ok(ParameterType::Byte) => ReturnType::Byte,
+            Ok(ParameterType::UByte) => ReturnType::UByte,
+            Err(_) => bail!("unsupported return type {:?}", other),
+        },
+    })
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+const COMMON: &str = "hyperlight_common::flatbuffer_wrappers";
+
+/// `rust_type` with the types that aren't in a `no_std` crate's prelude
+/// qualified, so the generated code doesn't depend on the guest's imports
+fn guest_rust_type(rust_type: &str) -> &str {
+    match rust_type {
+        "String" => "alloc::string::String",
+        "Vec<u8>" => "alloc::vec::Vec<u8>",
+        other => other,
+    }
+}
+
+fn write_guest_skeleton(
+    out: &mut String,
+    trait_name: &str,
+    functions: &[InterfaceFunction],
+) -> core::fmt::Result {
+    let register_name = format!("register_{}", method_name(trait_name));
+    writeln!(
+        out,
+        "// Generated by hyperlight_common::interface. Do not edit."
+    )?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "/// The functions of the `{trait_name}` interface, implemented by the guest"
+    )?;
+    writeln!(out, "pub trait {trait_name} {{")?;
+    for function in functions {
+        let parameters: Vec<String> = function
+            .parameters
+            .iter()
+            .map(|(name, t)| format!("{name}: {}", guest_rust_type(parameter_rust_type(t))))
+            .collect();
+        writeln!(
+            out,
+            "    /// Implements the guest function `{}`",
+            function.name
+        )?;
+        writeln!(
+            out,
+            "    fn {}({}) -> hyperlight_guest::error::Result<{}>;",
+            method_name(&function.name),
+            parameters.join(", "),
+            guest_rust_type(return_rust_type(&function.return_type))
+        )?;
+    }
+    writeln!(out, "}}")?;
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "/// Register a guest function for each method of `T`'s implementation of `{trait_name}`"
+    )?;
+    writeln!(out, "pub fn {register_name}<T: {trait_name}>() {{")?;
+    for function in functions {
+        let parameter_types: Vec<String> = function
+            .parameters
+            .iter()
+            .map(|(_, t)| format!("{COMMON}::function_types::ParameterType::{:?}", t))
+            .collect();
+        writeln!(
+            out,
+            "    hyperlight_guest::guest_function_register::register_function("
+        )?;
+        writeln!(
+            out,
+            "        hyperlight_guest::guest_function_definition::GuestFunctionDefinition::new("
+        )?;
+        writeln!(
+            out,
+            "            alloc::string::String::from({:?}),",
+            function.name
+        )?;
+        writeln!(
+            out,
+            "            alloc::vec![{}],",
+            parameter_types.join(", ")
+        )?;
+        writeln!(
+            out,
+            "            {COMMON}::function_types::ReturnType::{:?},",
+            function.return_type
+        )?;
+        writeln!(
+            out,
+            "            __{}::<T> as usize,",
+            method_name(&format!("{register_name}_{}", function.name))
+        )?;
+        writeln!(out, "        ),")?;
+        writeln!(out, "    );")?;
+    }
+    writeln!(out, "}}")?;
+
+    for function in functions {
+        write_guest_wrapper(out, trait_name, &register_name, function)?;
+    }
+    Ok(())
+}
+
+/// Write the guest function that unpacks the parameters of a call to
+/// `function`, and calls the trait method implementing it
+fn write_guest_wrapper(
+    out: &mut String,
+    trait_name: &str,
+    register_name: &str,
+    function: &InterfaceFunction,
+) -> core::fmt::Result {
+    let patterns: Vec<String> = function
+        .parameters
+        .iter()
+        .map(|(name, t)| format!("{COMMON}::function_types::ParameterValue::{:?}({name})", t))
+        .collect();
+    let arguments: Vec<String> = function
+        .parameters
+        .iter()
+        .map(|(name, t)| match t {
+            ParameterType::String | ParameterType::VecBytes => format!("{name}.clone()"),
+            _ => format!("*{name}"),
+        })
+        .collect();
+    let result = match function.return_type {
+        ReturnType::String => "__result.as_str()",
+        ReturnType::VecBytes => "__result.as_slice()",
+        ReturnType::Void => "()",
+        _ => "__result",
+    };
+    // functions that return nothing aren't bound to `__result`, which
+    // clippy's `let_unit_value` lint would warn about
+    let binding = match function.return_type {
+        ReturnType::Void => "",
+        _ => "let __result = ",
+    };
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "fn __{}<T: {trait_name}>(",
+        method_name(&format!("{register_name}_{}", function.name))
+    )?;
+    writeln!(
+        out,
+        "    __function_call: &{COMMON}::function_call::FunctionCall,"
+    )?;
+    writeln!(
+        out,
+        ") -> hyperlight_guest::error::Result<alloc::vec::Vec<u8>> {{"
+    )?;
+    writeln!(
+        out,
+        "    match __function_call.parameters.as_deref().unwrap_or_default() {{"
+    )?;
+    writeln!(out, "        [{}] => {{", patterns.join(", "))?;
+    writeln!(
+        out,
+        "            {binding}T::{}({})?;",
+        method_name(&function.name),
+        arguments.join(", ")
+    )?;
+    writeln!(
+        out,
+        "            Ok({COMMON}::util::get_flatbuffer_result({result}))"
+    )?;
+    writeln!(out, "        }}")?;
+    writeln!(
+        out,
+        "        _ => Err(hyperlight_guest::error::HyperlightGuestError::new("
+    )?;
+    writeln!(
+        out,
+        "            {COMMON}::guest_error::ErrorCode::GuestFunctionParameterTypeMismatch,"
+    )?;
+    writeln!(
+        out,
+        "            alloc::string::String::from({:?}),",
+        format!("Invalid parameters passed to {}", function.name)
+    )?;
+    writeln!(out, "        )),")?;
+    writeln!(out, "    }}")?;
+    writeln!(out, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use super::{method_name, InterfaceDefinition, InterfaceFunction};
+    use crate::flatbuffer_wrappers::function_types::{ParameterType, ReturnType};
+
+    const DEFINITION: &str = "
+        // The functions of the simple guest
+        fn Echo(message: String) -> String;
+        fn math::Add(a: i32, b: i64) -> i64; // namespaced
+        fn Reset();
+    ";
+
+    #[test]
+    fn parse() {
+        let interface = InterfaceDefinition::parse(DEFINITION).unwrap();
+        assert_eq!(
+            vec![
+                InterfaceFunction {
+                    name: "Echo".to_string(),
+                    parameters: vec![("message".to_string(), ParameterType::String)],
+                    return_type: ReturnType::String,
+                },
+                InterfaceFunction {
+                    name: "math::Add".to_string(),
+                    parameters: vec![
+                        ("a".to_string(), ParameterType::Int),
+                        ("b".to_string(), ParameterType::Long),
+                    ],
+                    return_type: ReturnType::Long,
+                },
+                InterfaceFunction {
+                    name: "Reset".to_string(),
+                    parameters: vec![],
+                    return_type: ReturnType::Void,
+                },
+            ],
+            interface.functions
+        );
+
+        let interface =
+            InterfaceDefinition::parse("fn ReadSensor(channel: u8, offset: i16) -> u16;").unwrap();
+        assert_eq!(
+            vec![
+                ("channel".to_string(), ParameterType::UByte),
+                ("offset".to_string(), ParameterType::Short),
+            ],
+            interface.functions[0].parameters
+        );
+        assert_eq!(ReturnType::UShort, interface.functions[0].return_type);
+    }
+
+    #[test]
+    fn parse_errors_name_the_line() {
+        for (definition, error) in [
+            ("fn Echo(message: String) -> String", "line 1: expected `;`"),
+            (
+                "\nfn Echo(message: str);",
+                "line 2: unsupported parameter type",
+            ),
+            (
+                "fn Echo() -> Option<i32>;",
+                "line 1: unsupported return type",
+            ),
+            (
+                "fn Echo(fn: i32);",
+                "line 1: \"fn\" is not a valid parameter name",
+            ),
+            (
+                "fn Echo();\nfn Echo();",
+                "line 2: function Echo is defined twice",
+            ),
+            (
+                "fn Echo(a: i32, a: i32);",
+                "line 1: parameter a is defined twice",
+            ),
+            (
+                "fn math:: Add();",
+                "line 1: \"math:: Add\" is not a valid function name",
+            ),
+            ("Echo();", "line 1: expected `fn`"),
+        ] {
+            let e = InterfaceDefinition::parse(definition).unwrap_err();
+            assert!(
+                e.to_string().starts_with(error),
+                "{:?} failed with {}",
+                definition,
+                e
+            );
+        }
+    }
+
+    #[test]
+    fn method_names() {
+        assert_eq!("echo", method_name("Echo"));
+        assert_eq!("add_to_static", method_name("AddToStatic"));
+        assert_eq!("get_http_status", method_name("GetHTTPStatus"));
+        assert_eq!("math_echo_double", method_name("math::EchoDouble"));
+        assert_eq!("print_output", method_name("print_output"));
+        assert_eq!("r#loop", method_name("Loop"));
+        assert_eq!("self_", method_name("Self"));
+        assert_eq!("_1st", method_name("1st"));
+    }
+
+    #[test]
+    fn generate_guest_skeleton() {
+        let interface = InterfaceDefinition::parse(DEFINITION).unwrap();
+        let skeleton = interface.generate_guest_skeleton("SimpleGuest").unwrap();
+        assert!(skeleton.contains("pub trait SimpleGuest {"));
+        assert!(skeleton.contains(
+            "    fn echo(message: alloc::string::String) -> hyperlight_guest::error::Result<alloc::string::String>;"
+        ));
+        assert!(skeleton
+            .contains("    fn math_add(a: i32, b: i64) -> hyperlight_guest::error::Result<i64>;"));
+        assert!(skeleton.contains("pub fn register_simple_guest<T: SimpleGuest>() {"));
+        assert!(skeleton.contains("            __register_simple_guest_math_add::<T> as usize,"));
+        assert!(skeleton.contains("            let __result = T::math_add(*a, *b)?;"));
+        assert!(skeleton.contains("            let __result = T::echo(message.clone())?;"));
+        assert!(skeleton.contains("get_flatbuffer_result(__result.as_str())"));
+        assert!(skeleton.contains("            T::reset()?;\n"));
+        assert!(skeleton.contains("get_flatbuffer_result(())"));
+
+        assert!(interface.generate_guest_skeleton("not a trait").is_err());
+    }
+}
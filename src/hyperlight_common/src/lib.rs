@@ -34,5 +34,13 @@ pub mod flatbuffer_wrappers;
     non_camel_case_types
 )]
 mod flatbuffers;
+pub mod guest_diagnostic;
+pub mod guest_metadata;
+pub mod interface;
+pub mod log_ring;
 /// cbindgen:ignore
 pub mod mem;
+pub mod payload_compression;
+pub mod secrets;
+pub mod symbol_map;
+pub mod transport;
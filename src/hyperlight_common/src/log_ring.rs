@@ -0,0 +1,199 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! A ring buffer in guest memory that the guest writes its log records
+//! into, so that logging doesn't stop the guest to call the host for every
+//! record. The host drains the ring whenever the guest exits to it.
+//!
+//! The ring starts with three little-endian `u64` counters, followed by the
+//! record data:
+//!
+//! - the total number of bytes the guest has written, which only the guest
+//!   changes
+//! - the total number of bytes the host has read, which only the host
+//!   changes
+//! - the number of records the guest dropped because the ring was full,
+//!   which the host resets once it has seen them
+//!
+//! Each record is a little-endian `u32` length followed by that many bytes,
+//! and wraps around the end of the data. The guest and host never access
+//! the ring at the same time, as the host only drains it while the guest
+//! is stopped.
+
+use alloc::vec::Vec;
+
+/// The size of the counters at the start of the ring
+pub const LOG_RING_HEADER_SIZE: usize = 3 * size_of::<u64>();
+
+const WRITE_POSITION: usize = 0;
+const READ_POSITION: usize = size_of::<u64>();
+const DROPPED_RECORDS: usize = 2 * size_of::<u64>();
+const RECORD_LENGTH_SIZE: usize = size_of::<u32>();
+
+/// A log ring buffer over the bytes of its memory region
+pub struct LogRing<'a> {
+    buffer: &'a mut [u8],
+}
+
+impl<'a> LogRing<'a> {
+    /// Use `buffer`, which must be longer than `LOG_RING_HEADER_SIZE`, as a
+    /// log ring. A zeroed buffer is an empty ring.
+    pub fn new(buffer: &'a mut [u8]) -> Option<Self> {
+        if buffer.len() <= LOG_RING_HEADER_SIZE {
+            return None;
+        }
+        Some(Self { buffer })
+    }
+
+    fn capacity(&self) -> u64 {
+        (self.buffer.len() - LOG_RING_HEADER_SIZE) as u64
+    }
+
+    fn counter(&self, offset: usize) -> u64 {
+        let mut bytes = [0; size_of::<u64>()];
+        bytes.copy_from_slice(&self.buffer[offset..offset + size_of::<u64>()]);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn set_counter(&mut self, offset: usize, value: u64) {
+        self.buffer[offset..offset + size_of::<u64>()].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// Copy `data` into the ring at the byte `position`, wrapping around
+    /// the end of the data
+    fn write_at(&mut self, position: u64, data: &[u8]) {
+        let capacity = self.capacity();
+        let start = (position % capacity) as usize;
+        let first = data.len().min(capacity as usize - start);
+        let ring = &mut self.buffer[LOG_RING_HEADER_SIZE..];
+        ring[start..start + first].copy_from_slice(&data[..first]);
+        ring[..data.len() - first].copy_from_slice(&data[first..]);
+    }
+
+    /// Copy the ring's bytes from the byte `position` into `data`, wrapping
+    /// around the end of the data
+    fn read_at(&self, position: u64, data: &mut [u8]) {
+        let capacity = self.capacity();
+        let start = (position % capacity) as usize;
+        let first = data.len().min(capacity as usize - start);
+        let ring = &self.buffer[LOG_RING_HEADER_SIZE..];
+        data[..first].copy_from_slice(&ring[start..start + first]);
+        let rest = data.len() - first;
+        data[first..].copy_from_slice(&ring[..rest]);
+    }
+
+    /// Append `record` to the ring. If it doesn't fit in the free space,
+    /// it is dropped and counted, and `false` is returned.
+    pub fn push(&mut self, record: &[u8]) -> bool {
+        let write = self.counter(WRITE_POSITION);
+        let read = self.counter(READ_POSITION);
+        let free = self.capacity() - (write - read);
+        let needed = (RECORD_LENGTH_SIZE + record.len()) as u64;
+        if needed > free || record.len() > u32::MAX as usize {
+            let dropped = self.counter(DROPPED_RECORDS);
+            self.set_counter(DROPPED_RECORDS, dropped + 1);
+            return false;
+        }
+        self.write_at(write, &(record.len() as u32).to_le_bytes());
+        self.write_at(write + RECORD_LENGTH_SIZE as u64, record);
+        self.set_counter(WRITE_POSITION, write + needed);
+        true
+    }
+
+    /// Remove the oldest record from the ring, or return `None` if it is
+    /// empty or its counters are inconsistent
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let write = self.counter(WRITE_POSITION);
+        let read = self.counter(READ_POSITION);
+        let used = write.checked_sub(read)?;
+        if used < RECORD_LENGTH_SIZE as u64 || used > self.capacity() {
+            return None;
+        }
+        let mut length = [0; RECORD_LENGTH_SIZE];
+        self.read_at(read, &mut length);
+        let length = u32::from_le_bytes(length) as u64;
+        if length > used - RECORD_LENGTH_SIZE as u64 {
+            return None;
+        }
+        let mut record = alloc::vec![0; length as usize];
+        self.read_at(read + RECORD_LENGTH_SIZE as u64, &mut record);
+        self.set_counter(READ_POSITION, read + RECORD_LENGTH_SIZE as u64 + length);
+        Some(record)
+    }
+
+    /// Return the number of records dropped because the ring was full
+    /// since this was last called, and reset it
+    pub fn take_dropped_records(&mut self) -> u64 {
+        let dropped = self.counter(DROPPED_RECORDS);
+        self.set_counter(DROPPED_RECORDS, 0);
+        dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::{LogRing, LOG_RING_HEADER_SIZE};
+
+    #[test]
+    fn push_and_pop() {
+        let mut buffer = vec![0; LOG_RING_HEADER_SIZE + 32];
+        let mut ring = LogRing::new(&mut buffer).unwrap();
+        assert_eq!(None, ring.pop());
+        assert!(ring.push(b"first"));
+        assert!(ring.push(b"second"));
+        assert_eq!(Some(b"first".to_vec()), ring.pop());
+        assert_eq!(Some(b"second".to_vec()), ring.pop());
+        assert_eq!(None, ring.pop());
+        assert_eq!(0, ring.take_dropped_records());
+    }
+
+    #[test]
+    fn records_wrap_around() {
+        let mut buffer = vec![0; LOG_RING_HEADER_SIZE + 32];
+        let mut ring = LogRing::new(&mut buffer).unwrap();
+        for i in 0..20u8 {
+            let record = [i; 11];
+            assert!(ring.push(&record));
+            assert_eq!(Some(record.to_vec()), ring.pop());
+        }
+    }
+
+    #[test]
+    fn full_ring_drops_records() {
+        let mut buffer = vec![0; LOG_RING_HEADER_SIZE + 32];
+        let mut ring = LogRing::new(&mut buffer).unwrap();
+        assert!(ring.push(&[1; 12]));
+        assert!(ring.push(&[2; 12]));
+        assert!(!ring.push(&[3; 1]));
+        assert!(!ring.push(&[4; 40]));
+        assert_eq!(2, ring.take_dropped_records());
+        assert_eq!(0, ring.take_dropped_records());
+
+        // draining the ring makes room again
+        assert_eq!(Some(vec![1; 12]), ring.pop());
+        assert!(ring.push(&[3; 1]));
+        assert_eq!(Some(vec![2; 12]), ring.pop());
+        assert_eq!(Some(vec![3; 1]), ring.pop());
+    }
+
+    #[test]
+    fn too_small() {
+        let mut buffer = vec![0; LOG_RING_HEADER_SIZE];
+        assert!(LogRing::new(&mut buffer).is_none());
+    }
+}
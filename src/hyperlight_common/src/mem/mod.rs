@@ -22,6 +22,10 @@ pub const PAGE_SIZE_USIZE: usize = 1 << 12;
 
 use core::ffi::{c_char, c_void};
 
+use crate::flatbuffer_wrappers::payload_limits::PayloadLimits;
+use crate::payload_compression::PayloadCompressionData;
+use crate::transport::{HostCallTransport, PortMap};
+
 #[repr(C)]
 pub struct HostFunctionDefinitions {
     pub fbHostFunctionDetailsSize: u64,
@@ -49,6 +53,20 @@ pub enum RunMode {
     Invalid = 4,
 }
 
+/// Where the random numbers the guest gets from `RDRAND` and `RDSEED`
+/// come from. The host chooses it, and tells the guest in the PEB.
+#[repr(u64)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum EntropyMode {
+    /// The processor's `RDRAND` and `RDSEED`
+    #[default]
+    Hardware = 0,
+    /// None, `RDRAND` and `RDSEED` fail the guest function call
+    Trap = 1,
+    /// A generator seeded by the host, which emulates `RDRAND` and `RDSEED`
+    Deterministic = 2,
+}
+
 #[repr(C)]
 pub struct InputData {
     pub inputDataSize: u64,
@@ -61,6 +79,63 @@ pub struct OutputData {
     pub outputDataBuffer: *mut c_void,
 }
 
+/// A buffer guest functions can write large results into, for the host to
+/// read in place rather than having them copied out of the output data
+/// buffer. `resultBufferSize` is 0 if the host did not configure one.
+#[repr(C)]
+pub struct ResultBufferData {
+    pub resultBufferSize: u64,
+    pub resultBuffer: *mut c_void,
+}
+
+/// Data the host publishes for the guest to read, which is mapped read-only
+/// in the guest. `readOnlyDataSize` is 0 if the host did not configure a
+/// read-only data region, and `readOnlyDataLength` is the length of the
+/// data the host last published.
+#[repr(C)]
+pub struct ReadOnlyData {
+    pub readOnlyDataSize: u64,
+    pub readOnlyDataLength: u64,
+    pub readOnlyData: *const c_void,
+}
+
+/// The ring buffer the guest writes log records into, see
+/// `crate::log_ring`. `guestLogRingSize` is 0 if the host did not configure
+/// one, in which case the guest calls the host for every log record.
+#[repr(C)]
+pub struct GuestLogRingData {
+    pub guestLogRingSize: u64,
+    pub guestLogRing: *mut c_void,
+}
+
+/// The secrets the host wrote for the guest to take, see `crate::secrets`.
+/// `secretsSize` is 0 if the host did not configure a secrets region.
+#[repr(C)]
+pub struct SecretsData {
+    pub secretsSize: u64,
+    pub secrets: *mut c_void,
+}
+
+/// A region the guest can write code into and run it, e.g. code it
+/// compiled at runtime. `jitScratchSize` is 0 if the host did not
+/// configure one.
+#[repr(C)]
+pub struct JitScratchData {
+    pub jitScratchSize: u64,
+    pub jitScratch: *mut c_void,
+}
+
+/// The epoch the host increments, and the epoch at which the guest must
+/// stop the guest function call in progress. The host writes both before
+/// every call and keeps the epoch up to date while the guest runs, so the
+/// guest must read them with volatile reads. `epochDeadline` is 0 if the
+/// call has no deadline.
+#[repr(C)]
+pub struct EpochData {
+    pub epoch: u64,
+    pub epochDeadline: u64,
+}
+
 #[repr(C)]
 pub struct GuestHeapData {
     pub guestHeapSize: u64,
@@ -77,6 +152,9 @@ pub struct GuestStackData {
     pub kernelStackAddress: u64,
     /// This is the initial stack pointer when init is called its used before the TSS is set up
     pub bootStackAddress: u64,
+    /// The address of the supervisor shadow stack token at the top of the
+    /// shadow stack, or 0 if the guest runs without a shadow stack
+    pub shadowStackToken: u64,
 }
 
 #[repr(C)]
@@ -101,4 +179,26 @@ pub struct HyperlightPEB {
     pub guestPanicContextData: GuestPanicContextData,
     pub guestheapData: GuestHeapData,
     pub gueststackData: GuestStackData,
+    /// The max log level the host wants the guest to use for the next
+    /// guest function call. Any value that does not map to a
+    /// `LevelFilter` leaves the guest's current max log level unchanged.
+    pub guest_max_log_level: u64,
+    /// The limits on the size of function calls and return values that
+    /// the guest must enforce on the payloads it sends to the host
+    pub payload_limits: PayloadLimits,
+    pub resultBufferData: ResultBufferData,
+    /// How the guest signals the host when running under a hypervisor
+    pub host_call_transport: HostCallTransport,
+    pub readOnlyData: ReadOnlyData,
+    pub guestLogRingData: GuestLogRingData,
+    pub secretsData: SecretsData,
+    pub epochData: EpochData,
+    /// Where the guest's random numbers come from
+    pub entropy_mode: EntropyMode,
+    /// The port the guest signals each of the host's channels on
+    pub port_map: PortMap,
+    /// The algorithm large guest function calls and results are
+    /// compressed with
+    pub payload_compression: PayloadCompressionData,
+    pub jitScratchData: JitScratchData,
 }
@@ -0,0 +1,342 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Compression of the guest function calls and results the host and the
+//! guest exchange through the input and output data buffers, so that
+//! payloads that compress well can be larger than the buffers.
+//!
+//! The host asks for an algorithm and a threshold in the PEB, and a guest
+//! built with the `payload_compression` feature accepts the algorithm when
+//! it is initialised. From then on the host compresses the guest function
+//! calls, and the guest the results, whose serialized size is at least the
+//! threshold, unless compressing them doesn't make them smaller. Host
+//! function calls and their results are never compressed.
+//!
+//! A compressed payload takes the place of the size-prefixed flatbuffer in
+//! the buffer, and is:
+//!
+//! - the little-endian `u32` size of the rest of the payload, as for a
+//!   size-prefixed flatbuffer
+//! - `COMPRESSED_PAYLOAD_MAGIC`, where a flatbuffer has the offset of its
+//!   root table, which is larger than any buffer so that the two can't be
+//!   mistaken for each other
+//! - the little-endian `u32` algorithm, see `PayloadCompression`
+//! - the little-endian `u32` size of the uncompressed flatbuffer, size
+//!   prefix included
+//! - the compressed flatbuffer
+
+#[cfg(feature = "payload_compression")]
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::flatbuffer_wrappers::payload_limits::PayloadLimits;
+
+/// The second `u32` of a compressed payload
+pub const COMPRESSED_PAYLOAD_MAGIC: u32 = 0xFFFF_5A50;
+/// The size of the fields before the compressed flatbuffer
+pub const COMPRESSED_PAYLOAD_HEADER_SIZE: usize = 4 * size_of::<u32>();
+/// The largest payload that is decompressed when the payload limits have
+/// no maximum payload size
+pub const DEFAULT_MAX_UNCOMPRESSED_SIZE: usize = 0x100_0000;
+
+// the index of each `u32` field of the header
+#[cfg(feature = "payload_compression")]
+const SIZE: usize = 0;
+const MAGIC: usize = 1;
+#[cfg(feature = "payload_compression")]
+const ALGORITHM: usize = 2;
+#[cfg(feature = "payload_compression")]
+const UNCOMPRESSED_SIZE: usize = 3;
+
+/// The algorithm large payloads are compressed with
+#[repr(u64)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum PayloadCompression {
+    /// Payloads are not compressed
+    #[default]
+    None = 0,
+    /// LZ4, which is the fastest
+    Lz4 = 1,
+    /// Zstandard, which compresses better
+    Zstd = 2,
+}
+
+impl PayloadCompression {
+    /// The `PayloadCompression` with the value `value`, if there is one
+    pub fn from_u64(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(Self::None),
+            1 => Some(Self::Lz4),
+            2 => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+}
+
+/// The payload compression the host asked for and the guest accepted,
+/// which is in the PEB
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct PayloadCompressionData {
+    /// The `PayloadCompression` the host asks for
+    pub requested: u64,
+    /// The smallest serialized payload that is compressed
+    pub threshold: u64,
+    /// The `PayloadCompression` the guest accepted when it was
+    /// initialised, which is 0 if it doesn't support the one requested
+    pub accepted: u64,
+}
+
+impl PayloadCompressionData {
+    /// The algorithm payloads are compressed with, which is `None` unless
+    /// the guest accepted the one the host asked for
+    pub fn negotiated(&self) -> PayloadCompression {
+        match PayloadCompression::from_u64(self.accepted) {
+            Some(compression) if self.accepted == self.requested => compression,
+            _ => PayloadCompression::None,
+        }
+    }
+}
+
+/// The largest uncompressed payload that is decompressed under `limits`:
+/// their maximum payload size, or `DEFAULT_MAX_UNCOMPRESSED_SIZE` if they
+/// have none
+pub fn max_uncompressed_size(limits: &PayloadLimits) -> usize {
+    match limits.max_payload_size {
+        0 => DEFAULT_MAX_UNCOMPRESSED_SIZE,
+        max => max as usize,
+    }
+}
+
+/// Whether the payload read from a buffer is compressed, rather than a
+/// size-prefixed flatbuffer
+pub fn is_compressed(payload: &[u8]) -> bool {
+    field(payload, MAGIC) == Some(COMPRESSED_PAYLOAD_MAGIC)
+}
+
+fn field(payload: &[u8], index: usize) -> Option<u32> {
+    let bytes = payload.get(index * size_of::<u32>()..(index + 1) * size_of::<u32>())?;
+    let mut le_bytes = [0; size_of::<u32>()];
+    le_bytes.copy_from_slice(bytes);
+    Some(u32::from_le_bytes(le_bytes))
+}
+
+/// Why a compressed payload couldn't be decompressed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PayloadCompressionError {
+    /// The payload is shorter than its header says
+    Truncated,
+    /// The payload was compressed with an algorithm that isn't supported
+    UnsupportedAlgorithm(u32),
+    /// The uncompressed payload is larger than the maximum allowed size
+    TooLarge {
+        /// The size in bytes of the uncompressed payload
+        size: usize,
+        /// The maximum size in bytes that was allowed
+        max_size: usize,
+    },
+    /// The compressed data is invalid, or doesn't decompress to the size
+    /// in the header
+    Corrupt,
+}
+
+impl fmt::Display for PayloadCompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Truncated => write!(f, "compressed payload is truncated"),
+            Self::UnsupportedAlgorithm(algorithm) => write!(
+                f,
+                "payload is compressed with unsupported algorithm {}",
+                algorithm
+            ),
+            Self::TooLarge { size, max_size } => write!(
+                f,
+                "uncompressed payload size {} exceeds the maximum allowed size {}",
+                size, max_size
+            ),
+            Self::Corrupt => write!(f, "compressed payload is corrupt"),
+        }
+    }
+}
+
+/// Compress the serialized `payload` with `compression`, returning `None`
+/// if it isn't compressed or compressing it doesn't make it smaller
+#[cfg(feature = "payload_compression")]
+pub fn compress(compression: PayloadCompression, payload: &[u8]) -> Option<Vec<u8>> {
+    let uncompressed_size = u32::try_from(payload.len()).ok()?;
+    let compressed = match compression {
+        PayloadCompression::None => return None,
+        PayloadCompression::Lz4 => lz4_flex::block::compress(payload),
+        PayloadCompression::Zstd => {
+            ruzstd::encoding::compress_to_vec(payload, ruzstd::encoding::CompressionLevel::Fastest)
+        }
+    };
+    let size = COMPRESSED_PAYLOAD_HEADER_SIZE + compressed.len();
+    if size >= payload.len() {
+        return None;
+    }
+    let mut framed = Vec::with_capacity(size);
+    for field in [
+        (size - size_of::<u32>()) as u32,
+        COMPRESSED_PAYLOAD_MAGIC,
+        compression as u32,
+        uncompressed_size,
+    ] {
+        framed.extend_from_slice(&field.to_le_bytes());
+    }
+    framed.extend_from_slice(&compressed);
+    Some(framed)
+}
+
+/// Decompress the compressed `payload` into the serialized payload it was
+/// made from, failing if that is larger than `max_size` bytes
+#[cfg(feature = "payload_compression")]
+pub fn decompress(payload: &[u8], max_size: usize) -> Result<Vec<u8>, PayloadCompressionError> {
+    let header = |index| field(payload, index).ok_or(PayloadCompressionError::Truncated);
+    let end = size_of::<u32>() + header(SIZE)? as usize;
+    let data = payload
+        .get(COMPRESSED_PAYLOAD_HEADER_SIZE..end)
+        .ok_or(PayloadCompressionError::Truncated)?;
+    let algorithm = header(ALGORITHM)?;
+    let size = header(UNCOMPRESSED_SIZE)? as usize;
+    if size > max_size {
+        return Err(PayloadCompressionError::TooLarge { size, max_size });
+    }
+    let uncompressed = match PayloadCompression::from_u64(algorithm as u64) {
+        Some(PayloadCompression::Lz4) => {
+            lz4_flex::block::decompress(data, size).map_err(|_| PayloadCompressionError::Corrupt)?
+        }
+        Some(PayloadCompression::Zstd) => {
+            // the decoder fills the vector's capacity, and no more
+            let mut uncompressed = Vec::with_capacity(size);
+            ruzstd::decoding::FrameDecoder::new()
+                .decode_all_to_vec(data, &mut uncompressed)
+                .map_err(|_| PayloadCompressionError::Corrupt)?;
+            uncompressed
+        }
+        _ => return Err(PayloadCompressionError::UnsupportedAlgorithm(algorithm)),
+    };
+    if uncompressed.len() != size {
+        return Err(PayloadCompressionError::Corrupt);
+    }
+    Ok(uncompressed)
+}
+
+#[cfg(all(test, feature = "payload_compression"))]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    fn payload(len: usize) -> Vec<u8> {
+        // a size-prefixed flatbuffer starts with its size, then the offset
+        // of its root table
+        let mut payload: Vec<u8> = (0..len).map(|i| (i / 64) as u8).collect();
+        payload[..4].copy_from_slice(&((len - 4) as u32).to_le_bytes());
+        payload[4..8].copy_from_slice(&8u32.to_le_bytes());
+        payload
+    }
+
+    #[test]
+    fn payloads_round_trip() {
+        for compression in [PayloadCompression::Lz4, PayloadCompression::Zstd] {
+            let payload = payload(0x4000);
+            assert!(!is_compressed(&payload));
+            let compressed = compress(compression, &payload).unwrap();
+            assert!(is_compressed(&compressed));
+            assert!(compressed.len() < payload.len() / 4);
+            assert_eq!(
+                compressed.len() - 4,
+                u32::from_le_bytes(compressed[..4].try_into().unwrap()) as usize
+            );
+            assert_eq!(payload, decompress(&compressed, payload.len()).unwrap());
+        }
+        assert_eq!(None, compress(PayloadCompression::None, &payload(0x4000)));
+    }
+
+    #[test]
+    fn incompressible_payloads_are_not_compressed() {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let random: Vec<u8> = (0..0x1000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        assert_eq!(None, compress(PayloadCompression::Lz4, &random));
+        assert_eq!(None, compress(PayloadCompression::Zstd, &random));
+    }
+
+    #[test]
+    fn decompression_is_bounded() {
+        let payload = payload(0x4000);
+        for compression in [PayloadCompression::Lz4, PayloadCompression::Zstd] {
+            let compressed = compress(compression, &payload).unwrap();
+            assert_eq!(
+                PayloadCompressionError::TooLarge {
+                    size: 0x4000,
+                    max_size: 0x3fff
+                },
+                decompress(&compressed, 0x3fff).unwrap_err()
+            );
+            assert_eq!(
+                PayloadCompressionError::Truncated,
+                decompress(&compressed[..compressed.len() - 1], 0x4000).unwrap_err()
+            );
+
+            // a header that understates the uncompressed size
+            let mut understated = compressed.clone();
+            understated[12..16].copy_from_slice(&0x100u32.to_le_bytes());
+            assert_eq!(
+                PayloadCompressionError::Corrupt,
+                decompress(&understated, 0x4000).unwrap_err()
+            );
+        }
+
+        let mut unsupported = compress(PayloadCompression::Lz4, &payload).unwrap();
+        unsupported[8..12].copy_from_slice(&7u32.to_le_bytes());
+        assert_eq!(
+            PayloadCompressionError::UnsupportedAlgorithm(7),
+            decompress(&unsupported, 0x4000).unwrap_err()
+        );
+        assert_eq!(
+            PayloadCompressionError::Truncated,
+            decompress(&[0; 8], 0x4000).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn negotiation() {
+        let mut data = PayloadCompressionData {
+            requested: PayloadCompression::Zstd as u64,
+            threshold: 0x1000,
+            accepted: 0,
+        };
+        assert_eq!(PayloadCompression::None, data.negotiated());
+        data.accepted = PayloadCompression::Zstd as u64;
+        assert_eq!(PayloadCompression::Zstd, data.negotiated());
+        data.accepted = PayloadCompression::Lz4 as u64;
+        assert_eq!(PayloadCompression::None, data.negotiated());
+
+        let limits = PayloadLimits::default();
+        assert_eq!(
+            DEFAULT_MAX_UNCOMPRESSED_SIZE,
+            max_uncompressed_size(&limits)
+        );
+    }
+}
@@ -0,0 +1,271 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! A region of guest memory the host writes named secrets into before the
+//! guest is initialised, so that they are never passed as guest function
+//! call parameters or logged.
+//!
+//! The region is a sequence of entries, which ends at the end of the region
+//! or at an entry whose name length is 0. Each entry is:
+//!
+//! - the little-endian `u32` length of the name
+//! - the little-endian `u32` length of the value
+//! - a little-endian `u32` of flags, see `WIPE_AFTER_READ` and `TAKEN`
+//! - the name as UTF-8, followed by the value
+//!
+//! A secret the guest has taken and that is wiped after it is read keeps
+//! its entry, so that the entries after it can still be found, but its
+//! value is zeroed and it is marked as taken.
+//!
+//! The host keeps the secrets in a buffer of its own and only copies them
+//! into the region for as long as the guest runs, so that they are never
+//! part of a snapshot of guest memory. When the guest stops, the host
+//! wipes its copy of the secrets the guest took from the region with
+//! `wipe_taken`, and zeroes the region.
+
+use alloc::vec::Vec;
+
+/// The size of the lengths and flags at the start of every entry
+pub const SECRET_HEADER_SIZE: usize = 3 * size_of::<u32>();
+
+/// The secret's value is zeroed the first time the guest takes it
+const WIPE_AFTER_READ: u32 = 1;
+/// The guest took the secret and its value was zeroed
+const TAKEN: u32 = 1 << 1;
+
+const NAME_LENGTH: usize = 0;
+const VALUE_LENGTH: usize = size_of::<u32>();
+const FLAGS: usize = 2 * size_of::<u32>();
+
+/// The secrets in a secrets region, over the bytes of the region
+pub struct Secrets<'a> {
+    buffer: &'a mut [u8],
+}
+
+/// Where a secret's entry is in the region
+struct Entry {
+    offset: usize,
+    name_length: usize,
+    value_length: usize,
+}
+
+impl Entry {
+    fn name_offset(&self) -> usize {
+        self.offset + SECRET_HEADER_SIZE
+    }
+
+    fn value_offset(&self) -> usize {
+        self.name_offset() + self.name_length
+    }
+
+    fn end(&self) -> usize {
+        self.value_offset() + self.value_length
+    }
+}
+
+impl<'a> Secrets<'a> {
+    /// Use `buffer` as a secrets region. A zeroed buffer has no secrets.
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self { buffer }
+    }
+
+    fn field(&self, offset: usize) -> u32 {
+        let mut bytes = [0; size_of::<u32>()];
+        bytes.copy_from_slice(&self.buffer[offset..offset + size_of::<u32>()]);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn set_field(&mut self, offset: usize, value: u32) {
+        self.buffer[offset..offset + size_of::<u32>()].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /// The entry at `offset`, if there is one that fits in the region
+    fn entry_at(&self, offset: usize) -> Option<Entry> {
+        if offset + SECRET_HEADER_SIZE > self.buffer.len() {
+            return None;
+        }
+        let entry = Entry {
+            offset,
+            name_length: self.field(offset + NAME_LENGTH) as usize,
+            value_length: self.field(offset + VALUE_LENGTH) as usize,
+        };
+        if entry.name_length == 0 || entry.end() > self.buffer.len() {
+            return None;
+        }
+        Some(entry)
+    }
+
+    /// The entry of the secret called `name`, and the offset after the
+    /// last entry
+    fn find(&self, name: &str) -> (Option<Entry>, usize) {
+        let mut offset = 0;
+        while let Some(entry) = self.entry_at(offset) {
+            offset = entry.end();
+            if &self.buffer[entry.name_offset()..entry.value_offset()] == name.as_bytes() {
+                return (Some(entry), offset);
+            }
+        }
+        (None, offset)
+    }
+
+    /// Whether there is a secret called `name`, including one that was
+    /// taken and wiped
+    pub fn contains(&self, name: &str) -> bool {
+        self.find(name).0.is_some()
+    }
+
+    /// Add the secret `value` called `name`. If `wipe_after_read` is set,
+    /// `take` returns it once, after which it is zeroed. Returns `false`
+    /// if `name` is empty or already in the region, or if the secret
+    /// doesn't fit in the region.
+    pub fn insert(&mut self, name: &str, value: &[u8], wipe_after_read: bool) -> bool {
+        let (existing, offset) = self.find(name);
+        if name.is_empty() || existing.is_some() {
+            return false;
+        }
+        let entry = Entry {
+            offset,
+            name_length: name.len(),
+            value_length: value.len(),
+        };
+        if entry.end() > self.buffer.len()
+            || u32::try_from(name.len()).is_err()
+            || u32::try_from(value.len()).is_err()
+        {
+            return false;
+        }
+        self.set_field(offset + NAME_LENGTH, name.len() as u32);
+        self.set_field(offset + VALUE_LENGTH, value.len() as u32);
+        let flags = if wipe_after_read { WIPE_AFTER_READ } else { 0 };
+        self.set_field(offset + FLAGS, flags);
+        self.buffer[entry.name_offset()..entry.value_offset()].copy_from_slice(name.as_bytes());
+        self.buffer[entry.value_offset()..entry.end()].copy_from_slice(value);
+        true
+    }
+
+    /// A copy of the value of the secret called `name`, or `None` if there
+    /// is no such secret or it was already taken and wiped
+    pub fn take(&mut self, name: &str) -> Option<Vec<u8>> {
+        let entry = self.find(name).0?;
+        let flags = self.field(entry.offset + FLAGS);
+        if flags & TAKEN != 0 {
+            return None;
+        }
+        let value = &mut self.buffer[entry.value_offset()..entry.end()];
+        let secret = value.to_vec();
+        if flags & WIPE_AFTER_READ != 0 {
+            value.fill(0);
+            self.set_field(entry.offset + FLAGS, flags | TAKEN);
+        }
+        Some(secret)
+    }
+
+    /// Wipe the secrets that are wiped after they are read and that the
+    /// guest took from `region`, a copy of these secrets. The flags of
+    /// the other secrets in `region` are ignored, so that a guest can't
+    /// wipe a secret that is meant to be read more than once.
+    pub fn wipe_taken(&mut self, region: &Secrets<'_>) {
+        let mut offset = 0;
+        while let Some(entry) = self.entry_at(offset) {
+            offset = entry.end();
+            let flags = self.field(entry.offset + FLAGS);
+            if flags & WIPE_AFTER_READ == 0 || flags & TAKEN != 0 {
+                continue;
+            }
+            let name = &self.buffer[entry.name_offset()..entry.value_offset()];
+            let taken = match core::str::from_utf8(name) {
+                Ok(name) => region
+                    .find(name)
+                    .0
+                    .is_some_and(|taken| region.field(taken.offset + FLAGS) & TAKEN != 0),
+                Err(_) => false,
+            };
+            if taken {
+                self.buffer[entry.value_offset()..entry.end()].fill(0);
+                self.set_field(entry.offset + FLAGS, flags | TAKEN);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::{Secrets, FLAGS, SECRET_HEADER_SIZE, TAKEN};
+
+    #[test]
+    fn insert_and_take() {
+        let mut buffer = vec![0; 64];
+        let mut secrets = Secrets::new(&mut buffer);
+        assert_eq!(None, secrets.take("api-key"));
+        assert!(secrets.insert("api-key", b"hunter2", false));
+        assert!(secrets.insert("token", b"abc", false));
+        assert!(!secrets.insert("token", b"def", false));
+        assert!(!secrets.insert("", b"def", false));
+        assert!(secrets.contains("api-key"));
+        assert!(!secrets.contains("api"));
+        assert_eq!(Some(b"hunter2".to_vec()), secrets.take("api-key"));
+        assert_eq!(Some(b"hunter2".to_vec()), secrets.take("api-key"));
+        assert_eq!(Some(b"abc".to_vec()), secrets.take("token"));
+    }
+
+    #[test]
+    fn wipe_after_read() {
+        let mut buffer = vec![0; 64];
+        let mut secrets = Secrets::new(&mut buffer);
+        assert!(secrets.insert("api-key", b"hunter2", true));
+        assert!(secrets.insert("token", b"abc", false));
+        assert_eq!(Some(b"hunter2".to_vec()), secrets.take("api-key"));
+        assert_eq!(None, secrets.take("api-key"));
+        assert!(secrets.contains("api-key"));
+        // the entries after a wiped secret can still be taken
+        assert_eq!(Some(b"abc".to_vec()), secrets.take("token"));
+        assert!(!buffer.windows(7).any(|w| w == b"hunter2"));
+    }
+
+    #[test]
+    fn wipe_taken() {
+        let mut host = vec![0; 64];
+        let mut host_secrets = Secrets::new(&mut host);
+        assert!(host_secrets.insert("api-key", b"hunter2", true));
+        assert!(host_secrets.insert("token", b"abc", false));
+
+        let mut region = vec![0; 64];
+        region.copy_from_slice(&host);
+        let mut guest_secrets = Secrets::new(&mut region);
+        assert_eq!(Some(b"hunter2".to_vec()), guest_secrets.take("api-key"));
+        // a guest can't mark a secret that can be read again as taken
+        let token = Secrets::new(&mut host).find("token").0.unwrap();
+        guest_secrets.set_field(token.offset + FLAGS, TAKEN);
+
+        let mut host_secrets = Secrets::new(&mut host);
+        host_secrets.wipe_taken(&guest_secrets);
+        assert_eq!(None, host_secrets.take("api-key"));
+        assert_eq!(Some(b"abc".to_vec()), host_secrets.take("token"));
+        assert!(!host.windows(7).any(|w| w == b"hunter2"));
+    }
+
+    #[test]
+    fn secrets_must_fit() {
+        let mut buffer = vec![0; SECRET_HEADER_SIZE + 10];
+        let mut secrets = Secrets::new(&mut buffer);
+        assert!(!secrets.insert("name", b"1234567", false));
+        assert!(secrets.insert("name", b"123456", false));
+        assert!(!secrets.insert("other", b"", false));
+        assert_eq!(Some(b"123456".to_vec()), secrets.take("name"));
+    }
+}
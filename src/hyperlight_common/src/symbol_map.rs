@@ -0,0 +1,192 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! A compact table of the functions and statics of a guest binary, which
+//! the guest build tooling writes next to the binary so that the host can
+//! show guest addresses by name without the binary's debug information.
+//!
+//! The encoded map is `SYMBOL_MAP_MAGIC`, the little-endian `u32` number of
+//! symbols, then for every symbol, sorted by offset:
+//!
+//! - the little-endian `u64` offset of the symbol from the start of the
+//!   loaded guest image
+//! - the little-endian `u32` size of the symbol
+//! - the little-endian `u16` length of the name, followed by the name as
+//!   UTF-8
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use anyhow::{bail, Result};
+
+/// The first bytes of an encoded symbol map
+pub const SYMBOL_MAP_MAGIC: &[u8; 8] = b"HLSYMAP1";
+/// The extension of the symbol map file written next to a guest binary
+pub const SYMBOL_MAP_EXTENSION: &str = "hlsym";
+
+/// A function or static in a guest binary
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Symbol {
+    /// The offset of the symbol from the start of the loaded guest image
+    pub offset: u64,
+    /// The size of the symbol in bytes
+    pub size: u32,
+    /// The demangled name of the symbol
+    pub name: String,
+}
+
+/// The symbols of a guest binary, sorted by offset
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SymbolMap {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolMap {
+    /// A map of `symbols`. Symbols that are empty, or whose name is too
+    /// long to encode, are left out.
+    pub fn new(mut symbols: Vec<Symbol>) -> Self {
+        symbols.retain(|s| s.size > 0 && u16::try_from(s.name.len()).is_ok());
+        symbols.sort_by(|a, b| (a.offset, &a.name).cmp(&(b.offset, &b.name)));
+        symbols.dedup_by_key(|s| s.offset);
+        Self { symbols }
+    }
+
+    /// The symbols in the map, sorted by offset
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// The symbol `offset` is in, and how far into the symbol it is
+    pub fn lookup(&self, offset: u64) -> Option<(&Symbol, u64)> {
+        let i = self.symbols.partition_point(|s| s.offset <= offset);
+        let symbol = self.symbols.get(i.checked_sub(1)?)?;
+        let delta = offset - symbol.offset;
+        (delta < symbol.size as u64).then_some((symbol, delta))
+    }
+
+    /// `offset` as `name+0x12` if it is in a symbol
+    pub fn symbolize(&self, offset: u64) -> Option<String> {
+        self.lookup(offset).map(|(symbol, delta)| match delta {
+            0 => symbol.name.clone(),
+            delta => alloc::format!("{}+{:#x}", symbol.name, delta),
+        })
+    }
+
+    /// Encode the map
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::from(&SYMBOL_MAP_MAGIC[..]);
+        bytes.extend_from_slice(&(self.symbols.len() as u32).to_le_bytes());
+        for symbol in &self.symbols {
+            bytes.extend_from_slice(&symbol.offset.to_le_bytes());
+            bytes.extend_from_slice(&symbol.size.to_le_bytes());
+            bytes.extend_from_slice(&(symbol.name.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(symbol.name.as_bytes());
+        }
+        bytes
+    }
+
+    /// Decode a map encoded with `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader { bytes };
+        if reader.take(SYMBOL_MAP_MAGIC.len())? != SYMBOL_MAP_MAGIC {
+            bail!("Not a guest symbol map");
+        }
+        let count = u32::from_le_bytes(reader.array()?);
+        let mut symbols = Vec::new();
+        for _ in 0..count {
+            let offset = u64::from_le_bytes(reader.array()?);
+            let size = u32::from_le_bytes(reader.array()?);
+            let name_length = u16::from_le_bytes(reader.array()?);
+            let name = match core::str::from_utf8(reader.take(name_length as usize)?) {
+                Ok(name) => name.to_string(),
+                Err(_) => bail!("Guest symbol name is not UTF-8"),
+            };
+            symbols.push(Symbol { offset, size, name });
+        }
+        if !reader.bytes.is_empty() {
+            bail!("Guest symbol map has {} trailing bytes", reader.bytes.len());
+        }
+        Ok(Self::new(symbols))
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.bytes.len() {
+            bail!("Guest symbol map is truncated");
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    use super::{Symbol, SymbolMap};
+
+    fn symbol(offset: u64, size: u32, name: &str) -> Symbol {
+        Symbol {
+            offset,
+            size,
+            name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let map = SymbolMap::new(vec![
+            symbol(0x2000, 0x10, "simpleguest::echo"),
+            symbol(0x1000, 0x100, "entrypoint"),
+            symbol(0x3000, 0, "empty"),
+        ]);
+        assert_eq!(2, map.symbols().len());
+        assert_eq!(None, map.symbolize(0xfff));
+        assert_eq!(Some("entrypoint".to_string()), map.symbolize(0x1000));
+        assert_eq!(Some("entrypoint+0xff".to_string()), map.symbolize(0x10ff));
+        assert_eq!(None, map.symbolize(0x1100));
+        assert_eq!(
+            Some("simpleguest::echo+0x4".to_string()),
+            map.symbolize(0x2004)
+        );
+        assert_eq!(None, map.symbolize(0x3000));
+    }
+
+    #[test]
+    fn round_trip() {
+        let map = SymbolMap::new(vec![
+            symbol(0x1000, 0x100, "entrypoint"),
+            symbol(0x2000, 0x10, "simpleguest::echo"),
+        ]);
+        let bytes = map.to_bytes();
+        assert_eq!(map, SymbolMap::from_bytes(&bytes).unwrap());
+        assert!(SymbolMap::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(SymbolMap::from_bytes(b"not a symbol map").is_err());
+    }
+}
@@ -0,0 +1,182 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! How a guest running under a hypervisor signals the host, to call a host
+//! function, log a message or abort.
+//!
+//! Each signal is a port number, identifying the channel, and a byte of
+//! data. The host chooses the port of each of its channels with a
+//! `PortMap`, and the guest may signal any other port below
+//! `PortMap::PORT_LIMIT` for channels the embedder handles itself. With
+//! `HostCallTransport::PortIo` the guest writes the byte to the I/O port
+//! with an `out` instruction. With `HostCallTransport::Mmio` the guest
+//! writes the byte to the address `mmio_doorbell_address(port)`, in a page
+//! of guest physical memory that isn't backed by host memory, so that the
+//! write exits to the host. Port I/O only exists on x86, and on some
+//! hypervisors MMIO exits are cheaper.
+
+use crate::mem::PAGE_SIZE;
+
+/// The mechanism the guest uses to signal the host. The host chooses it,
+/// and tells the guest in the PEB.
+#[repr(u64)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum HostCallTransport {
+    /// The guest writes to I/O ports
+    #[default]
+    PortIo = 0,
+    /// The guest writes to the MMIO doorbell page
+    Mmio = 1,
+}
+
+/// The channels the host handles itself
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Channel {
+    /// Forward the log record in the output data buffer
+    Log,
+    /// Call the host function in the output data buffer
+    Call,
+    /// Abort the guest function call, with the byte as the error code
+    Abort,
+    /// Append the byte to the guest's debug output
+    Debug,
+}
+
+/// The port the guest signals each of the host's channels on. The host
+/// chooses them, and tells the guest in the PEB.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PortMap {
+    /// The port of `Channel::Log`
+    pub log: u16,
+    /// The port of `Channel::Call`
+    pub call: u16,
+    /// The port of `Channel::Abort`
+    pub abort: u16,
+    /// The port of `Channel::Debug`
+    pub debug: u16,
+}
+
+impl PortMap {
+    /// The ports used by guests built before the port map was
+    /// configurable
+    pub const DEFAULT: Self = Self {
+        log: 99,
+        call: 101,
+        abort: 102,
+        debug: 103,
+    };
+
+    /// Every port is below this limit, so that it can be signalled through
+    /// the MMIO doorbell page as well as with port I/O
+    pub const PORT_LIMIT: u16 = PAGE_SIZE as u16;
+
+    /// The channel the guest signals on `port`, or `None` if it isn't one
+    /// of the host's
+    pub fn channel(&self, port: u16) -> Option<Channel> {
+        match port {
+            _ if port == self.log => Some(Channel::Log),
+            _ if port == self.call => Some(Channel::Call),
+            _ if port == self.abort => Some(Channel::Abort),
+            _ if port == self.debug => Some(Channel::Debug),
+            _ => None,
+        }
+    }
+
+    /// The port the guest signals `channel` on
+    pub fn port(&self, channel: Channel) -> u16 {
+        match channel {
+            Channel::Log => self.log,
+            Channel::Call => self.call,
+            Channel::Abort => self.abort,
+            Channel::Debug => self.debug,
+        }
+    }
+
+    /// Whether every channel has its own port below `PORT_LIMIT`
+    pub fn is_valid(&self) -> bool {
+        let ports = [self.log, self.call, self.abort, self.debug];
+        ports
+            .iter()
+            .enumerate()
+            .all(|(i, port)| *port < Self::PORT_LIMIT && !ports[..i].contains(port))
+    }
+}
+
+impl Default for PortMap {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The guest physical and virtual address of the MMIO doorbell page: the
+/// last page before the start of the guest's memory, which is otherwise
+/// unmapped.
+pub const MMIO_DOORBELL_ADDRESS: u64 = 0x200000 - PAGE_SIZE;
+
+/// The address in the MMIO doorbell page the guest writes to, to signal
+/// the host with `port`
+pub fn mmio_doorbell_address(port: u16) -> u64 {
+    MMIO_DOORBELL_ADDRESS + u64::from(port)
+}
+
+/// The port the guest signalled by writing to the guest physical address
+/// `address`, if it is in the MMIO doorbell page
+pub fn mmio_doorbell_port(address: u64) -> Option<u16> {
+    address
+        .checked_sub(MMIO_DOORBELL_ADDRESS)
+        .filter(|offset| *offset < PAGE_SIZE)
+        .map(|offset| offset as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        mmio_doorbell_address, mmio_doorbell_port, Channel, PortMap, MMIO_DOORBELL_ADDRESS,
+    };
+    use crate::mem::PAGE_SIZE;
+
+    #[test]
+    fn doorbell_ports() {
+        for port in [0, 99, 101, 102] {
+            assert_eq!(Some(port), mmio_doorbell_port(mmio_doorbell_address(port)));
+        }
+        assert_eq!(None, mmio_doorbell_port(MMIO_DOORBELL_ADDRESS - 1));
+        assert_eq!(None, mmio_doorbell_port(MMIO_DOORBELL_ADDRESS + PAGE_SIZE));
+    }
+
+    #[test]
+    fn port_map() {
+        let map = PortMap::default();
+        assert!(map.is_valid());
+        for channel in [Channel::Log, Channel::Call, Channel::Abort, Channel::Debug] {
+            assert_eq!(Some(channel), map.channel(map.port(channel)));
+        }
+        assert_eq!(None, map.channel(100));
+
+        let moved = PortMap { call: 0x300, ..map };
+        assert!(moved.is_valid());
+        assert_eq!(Some(Channel::Call), moved.channel(0x300));
+        assert_eq!(None, moved.channel(101));
+
+        assert!(!PortMap { debug: 99, ..map }.is_valid());
+        assert!(!PortMap {
+            log: PortMap::PORT_LIMIT,
+            ..map
+        }
+        .is_valid());
+    }
+}
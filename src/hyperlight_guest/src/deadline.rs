@@ -0,0 +1,36 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use core::time::Duration;
+
+use hyperlight_common::flatbuffer_wrappers::function_types::ReturnType;
+
+use crate::error::Result;
+use crate::host_function_call::{call_host_function, get_host_return_value};
+
+/// How long the current guest function call has left before the host
+/// cancels it: until the deadline the host attached to the call with
+/// `MultiUseSandbox::call_guest_function_by_name_with_deadline`, or until
+/// the sandbox's maximum execution time, whichever comes first.
+///
+/// Guest functions that can return a partial result should check this
+/// periodically and return once it gets short, rather than be cancelled
+/// with nothing to show for their work.
+pub fn hl_remaining_time() -> Result<Duration> {
+    call_host_function("HostRemainingTime", None, ReturnType::ULong)?;
+    let micros = get_host_return_value::<u64>()?;
+    Ok(Duration::from_micros(micros))
+}
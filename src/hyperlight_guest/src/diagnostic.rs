@@ -0,0 +1,115 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Aborting the guest with a record of where and why, which the host
+//! reports as `HyperlightError::GuestTrapped`, rather than with a bare
+//! code and message like `abort_with_code_and_message`.
+
+use alloc::string::ToString;
+use core::fmt;
+
+use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+pub use hyperlight_common::guest_diagnostic::DiagnosticKind;
+use hyperlight_common::guest_diagnostic::GuestDiagnostic;
+
+use crate::host_function_call::{outb, OutBAction};
+use crate::P_PEB;
+
+#[doc(hidden)]
+pub fn _trap(
+    kind: DiagnosticKind,
+    code: u32,
+    location: (&'static str, u32, u32),
+    condition: &str,
+    message: fmt::Arguments,
+) -> ! {
+    let (file, line, column) = location;
+    let diagnostic = GuestDiagnostic {
+        kind,
+        code,
+        file: file.to_string(),
+        line,
+        column,
+        condition: condition.to_string(),
+        message: alloc::fmt::format(message),
+    };
+    unsafe {
+        let peb_ptr = P_PEB.unwrap();
+        let buffer = (*peb_ptr).guestPanicContextData.guestPanicContextDataBuffer as *mut u8;
+        let size = (*peb_ptr).guestPanicContextData.guestPanicContextDataSize as usize;
+        if let Some(bytes) = diagnostic.to_bytes(size) {
+            core::ptr::copy_nonoverlapping(bytes.as_ptr(), buffer, bytes.len());
+        }
+    }
+    outb(OutBAction::Abort.port(), ErrorCode::GuestError as u8);
+    unreachable!()
+}
+
+/// Abort the guest with `code`, which means whatever the guest and its
+/// host agree it means, and an optional message formatted like
+/// `format!`. The host reports the code, the message and the location of
+/// the macro in a `HyperlightError::GuestTrapped` error.
+#[macro_export]
+macro_rules! hl_trap {
+    ($code:expr $(,)?) => {
+        $crate::diagnostic::_trap(
+            $crate::diagnostic::DiagnosticKind::Trap,
+            $code,
+            (file!(), line!(), column!()),
+            "",
+            format_args!(""),
+        )
+    };
+    ($code:expr, $($arg:tt)+) => {
+        $crate::diagnostic::_trap(
+            $crate::diagnostic::DiagnosticKind::Trap,
+            $code,
+            (file!(), line!(), column!()),
+            "",
+            format_args!($($arg)+),
+        )
+    };
+}
+
+/// Abort the guest if `condition` is false, like `assert!`. The host
+/// reports the condition, the optional message and the location of the
+/// macro in a `HyperlightError::GuestTrapped` error, rather than the
+/// formatted panic message `assert!` aborts with.
+#[macro_export]
+macro_rules! hl_assert {
+    ($condition:expr $(,)?) => {
+        if !$condition {
+            $crate::diagnostic::_trap(
+                $crate::diagnostic::DiagnosticKind::Assertion,
+                0,
+                (file!(), line!(), column!()),
+                stringify!($condition),
+                format_args!(""),
+            )
+        }
+    };
+    ($condition:expr, $($arg:tt)+) => {
+        if !$condition {
+            $crate::diagnostic::_trap(
+                $crate::diagnostic::DiagnosticKind::Assertion,
+                0,
+                (file!(), line!(), column!()),
+                stringify!($condition),
+                format_args!($($arg)+),
+            )
+        }
+    };
+}
@@ -0,0 +1,213 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Random numbers from where the host's entropy policy says they come
+//! from: the processor's `RDRAND`, nowhere, or a generator seeded by the
+//! host.
+//!
+//! Unless the guest may use the processor's `RDRAND` and `RDSEED`, the
+//! host hides them from the guest's CPUID and makes executing them raise
+//! an invalid opcode exception, refusing to create the sandbox on hosts
+//! that can't. The guest emulates
+//! them from the generator when the host seeds it, and fails the guest
+//! function call otherwise, so code that executes them directly gets the
+//! same random numbers as code that calls `random_u64`.
+
+use alloc::string::ToString;
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+use hyperlight_common::mem::EntropyMode;
+
+use crate::error::{HyperlightGuestError, Result};
+use crate::exceptions::{ExceptionAction, ExceptionContext};
+use crate::{P_PEB, RANDOM_SEED};
+
+/// The CPUID leaf 1 ECX bit reporting `RDRAND` support
+const RDRAND_BIT: u32 = 1 << 30;
+/// How many times `RDRAND` is retried when the processor has no random
+/// number ready, as Intel recommends
+const RDRAND_RETRIES: usize = 10;
+
+// RFLAGS bits
+const CF: u64 = 1;
+const PF: u64 = 1 << 2;
+const AF: u64 = 1 << 4;
+const ZF: u64 = 1 << 6;
+const SF: u64 = 1 << 7;
+const OF: u64 = 1 << 11;
+
+/// The state of the generator, or 0 if it has not been seeded yet
+static STATE: AtomicU64 = AtomicU64::new(0);
+
+fn mode() -> EntropyMode {
+    match unsafe { P_PEB } {
+        Some(peb) => unsafe { (*peb).entropy_mode },
+        None => EntropyMode::Hardware,
+    }
+}
+
+fn no_entropy(message: &str) -> HyperlightGuestError {
+    HyperlightGuestError::new(ErrorCode::GuestError, message.to_string())
+}
+
+/// Return a random number, from where the host's entropy policy says.
+/// Fails if the policy gives the guest no entropy, or if it may use the
+/// processor's `RDRAND` but the processor doesn't have it.
+pub fn random_u64() -> Result<u64> {
+    match mode() {
+        EntropyMode::Hardware => rdrand(),
+        EntropyMode::Trap => Err(no_entropy(
+            "The sandbox's entropy policy gives the guest no entropy",
+        )),
+        EntropyMode::Deterministic => Ok(deterministic_u64()),
+    }
+}
+
+/// Fill `buf` with random bytes, see `random_u64`
+pub fn fill_bytes(buf: &mut [u8]) -> Result<()> {
+    for chunk in buf.chunks_mut(8) {
+        let bytes = random_u64()?.to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+    Ok(())
+}
+
+fn rdrand() -> Result<u64> {
+    let cpuid = unsafe { core::arch::x86_64::__cpuid(1) };
+    if cpuid.ecx & RDRAND_BIT == 0 {
+        return Err(no_entropy("The processor doesn't support RDRAND"));
+    }
+    for _ in 0..RDRAND_RETRIES {
+        let value: u64;
+        let ready: u8;
+        unsafe {
+            asm!(
+                "rdrand {value}",
+                "setc {ready}",
+                value = out(reg) value,
+                ready = out(reg_byte) ready,
+                options(nomem, nostack),
+            );
+        }
+        if ready != 0 {
+            return Ok(value);
+        }
+    }
+    Err(no_entropy("RDRAND has no random number ready"))
+}
+
+/// The next number from a SplitMix64 generator seeded with the seed the
+/// host initialised the guest with. The state of the generator is part of
+/// guest memory, so it is restored along with the rest of the guest's
+/// state between guest function calls.
+fn deterministic_u64() -> u64 {
+    let mut state = STATE.load(Ordering::Relaxed);
+    if state == 0 {
+        // differ from the sequence `hyperlight_guest_std::random` derives
+        // from the same seed
+        state = unsafe { RANDOM_SEED } ^ 0x6a09_e667_f3bc_c908;
+    }
+    state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    STATE.store(state, Ordering::Relaxed);
+
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Handle an invalid opcode exception raised by `RDRAND` or `RDSEED`,
+/// returning `None` if the instruction at `context.rip` is neither, or the
+/// guest may use the processor's
+pub(crate) fn emulate_instruction(context: &mut ExceptionContext) -> Option<ExceptionAction> {
+    let mode = mode();
+    if mode == EntropyMode::Hardware {
+        return None;
+    }
+    let (register, bits, length) = decode(context.rip)?;
+    if mode == EntropyMode::Trap {
+        return Some(ExceptionAction::Fail(no_entropy(
+            "The guest executed RDRAND or RDSEED, which the sandbox's entropy policy disables",
+        )));
+    }
+
+    let value = deterministic_u64();
+    let destination = register_mut(context, register);
+    *destination = match bits {
+        64 => value,
+        // 32 bit results are zero extended
+        32 => value & 0xffff_ffff,
+        _ => (*destination & !0xffff) | (value & 0xffff),
+    };
+    // CF reports that a random number was ready, the other flags are cleared
+    context.rflags = (context.rflags & !(OF | SF | ZF | AF | PF)) | CF;
+    context.rip += length;
+    Some(ExceptionAction::Resume)
+}
+
+/// Decode the `RDRAND` or `RDSEED` at `rip`, returning the destination
+/// register number, the operand size in bits and the instruction length
+fn decode(rip: u64) -> Option<(u8, u32, u64)> {
+    // SAFETY: the guest was executing the instruction, so it is mapped
+    let byte = |i: u64| unsafe { core::ptr::read_volatile((rip + i) as *const u8) };
+    let mut i = 0;
+    let mut bits = 32;
+    if byte(i) == 0x66 {
+        bits = 16;
+        i += 1;
+    }
+    let mut rex_b = 0;
+    if byte(i) & 0xf0 == 0x40 {
+        let rex = byte(i);
+        if rex & 0x8 != 0 {
+            bits = 64;
+        }
+        rex_b = (rex & 0x1) << 3;
+        i += 1;
+    }
+    if byte(i) != 0x0f || byte(i + 1) != 0xc7 {
+        return None;
+    }
+    // a register operand, and a reg field of 6 for RDRAND or 7 for RDSEED
+    let modrm = byte(i + 2);
+    if modrm >> 6 != 0b11 || !matches!((modrm >> 3) & 0x7, 6 | 7) {
+        return None;
+    }
+    Some((rex_b | (modrm & 0x7), bits, i + 3))
+}
+
+fn register_mut(context: &mut ExceptionContext, register: u8) -> &mut u64 {
+    match register {
+        0 => &mut context.rax,
+        1 => &mut context.rcx,
+        2 => &mut context.rdx,
+        3 => &mut context.rbx,
+        4 => &mut context.rsp,
+        5 => &mut context.rbp,
+        6 => &mut context.rsi,
+        7 => &mut context.rdi,
+        8 => &mut context.r8,
+        9 => &mut context.r9,
+        10 => &mut context.r10,
+        11 => &mut context.r11,
+        12 => &mut context.r12,
+        13 => &mut context.r13,
+        14 => &mut context.r14,
+        _ => &mut context.r15,
+    }
+}
@@ -28,9 +28,10 @@ use crate::guest_function_call::dispatch_function;
 use crate::guest_logger::init_logger;
 use crate::host_function_call::{outb, OutBAction};
 use crate::idtr::load_idt;
+use crate::payload_compression::accept_payload_compression;
 use crate::{
-    __security_cookie, HEAP_ALLOCATOR, MIN_STACK_ADDRESS, OS_PAGE_SIZE, OUTB_PTR,
-    OUTB_PTR_WITH_CONTEXT, P_PEB, RUNNING_MODE,
+    __security_cookie, shadow_stack, HEAP_ALLOCATOR, HOST_CALL_TRANSPORT, MIN_STACK_ADDRESS,
+    OS_PAGE_SIZE, OUTB_PTR, OUTB_PTR_WITH_CONTEXT, P_PEB, RANDOM_SEED, RUNNING_MODE,
 };
 
 #[inline(never)]
@@ -48,7 +49,7 @@ pub extern "C" fn abort() -> ! {
 }
 
 pub fn abort_with_code(code: i32) -> ! {
-    outb(OutBAction::Abort as u16, code as u8);
+    outb(OutBAction::Abort.port(), code as u8);
     unreachable!()
 }
 
@@ -63,7 +64,7 @@ pub unsafe fn abort_with_code_and_message(code: i32, message_ptr: *const c_char)
         (*peb_ptr).guestPanicContextData.guestPanicContextDataBuffer as *mut c_char,
         CStr::from_ptr(message_ptr).count_bytes() + 1, // +1 for null terminator
     );
-    outb(OutBAction::Abort as u16, code as u8);
+    outb(OutBAction::Abort.port(), code as u8);
     unreachable!()
 }
 
@@ -82,11 +83,20 @@ pub extern "win64" fn entrypoint(peb_address: u64, seed: u64, ops: u64, max_log_
         panic!("PEB address is null");
     }
 
+    // entrypoint never returns, so it can switch to the shadow stack
+    unsafe {
+        let peb_ptr = peb_address as *const HyperlightPEB;
+        if (*peb_ptr).runMode == RunMode::Hypervisor {
+            shadow_stack::enable((*peb_ptr).gueststackData.shadowStackToken);
+        }
+    }
+
     INIT.call_once(|| {
         unsafe {
             P_PEB = Some(peb_address as *mut HyperlightPEB);
             let peb_ptr = P_PEB.unwrap();
             __security_cookie = peb_address ^ seed;
+            RANDOM_SEED = seed;
 
             let srand_seed = ((peb_address << 8 ^ seed >> 4) >> 32) as u32;
 
@@ -102,6 +112,7 @@ pub extern "win64" fn entrypoint(peb_address: u64, seed: u64, ops: u64, max_log_
             match (*peb_ptr).runMode {
                 RunMode::Hypervisor => {
                     RUNNING_MODE = RunMode::Hypervisor;
+                    HOST_CALL_TRANSPORT = (*peb_ptr).host_call_transport;
                     // This static is to make it easier to implement the __chkstk function in assembly.
                     // It also means that should we change the layout of the struct in the future, we
                     // don't have to change the assembly code.
@@ -146,6 +157,8 @@ pub extern "win64" fn entrypoint(peb_address: u64, seed: u64, ops: u64, max_log_
 
             (*peb_ptr).guest_function_dispatch_ptr = dispatch_function as usize as u64;
 
+            accept_payload_compression();
+
             reset_error();
 
             hyperlight_main();
@@ -0,0 +1,49 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use core::ptr::addr_of;
+
+use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+
+use crate::entrypoint::abort_with_code;
+use crate::P_PEB;
+
+/// Stop the guest function call in progress if the host has incremented
+/// its epoch up to the call's deadline, set with
+/// `SandboxConfiguration::set_epoch_deadline`. The call then fails on the
+/// host with `HyperlightError::EpochDeadlineReached`.
+///
+/// The host stops a guest that reaches its deadline even if it never
+/// calls this, by interrupting it; calling it stops the guest at a point
+/// of its choosing, and without waiting for the interrupt. This only reads two values from guest memory, so it is cheap enough to
+/// call at the top of every loop iteration and at every function entry.
+/// It does nothing if the call has no epoch deadline.
+#[inline]
+pub fn check_epoch() {
+    let Some(peb_ptr) = (unsafe { P_PEB }) else {
+        return;
+    };
+    // The host updates the epoch while the guest runs, so it must be read
+    // from memory every time
+    let deadline = unsafe { addr_of!((*peb_ptr).epochData.epochDeadline).read_volatile() };
+    if deadline == 0 {
+        return;
+    }
+    let epoch = unsafe { addr_of!((*peb_ptr).epochData.epoch).read_volatile() };
+    if epoch >= deadline {
+        abort_with_code(ErrorCode::EpochInterrupted as i32);
+    }
+}
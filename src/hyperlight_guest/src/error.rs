@@ -15,9 +15,10 @@ limitations under the License.
 */
 
 use alloc::format;
-use alloc::string::String;
+use alloc::string::{String, ToString};
 
 use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+use hyperlight_common::flatbuffer_wrappers::payload_limits::PayloadLimitExceeded;
 use {anyhow, serde_json};
 
 pub type Result<T> = core::result::Result<T, HyperlightGuestError>;
@@ -51,3 +52,12 @@ impl From<serde_json::Error> for HyperlightGuestError {
         }
     }
 }
+
+impl From<PayloadLimitExceeded> for HyperlightGuestError {
+    fn from(error: PayloadLimitExceeded) -> Self {
+        Self {
+            kind: ErrorCode::PayloadTooLarge,
+            message: error.to_string(),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use spin::Mutex;
+
+use crate::error::HyperlightGuestError;
+
+/// The CPU exceptions the guest can register handlers for
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Exception {
+    DivideError = 0,
+    Debug = 1,
+    NonMaskableInterrupt = 2,
+    Breakpoint = 3,
+    Overflow = 4,
+    BoundRangeExceeded = 5,
+    InvalidOpcode = 6,
+    DeviceNotAvailable = 7,
+    DoubleFault = 8,
+    CoprocessorSegmentOverrun = 9,
+    InvalidTss = 10,
+    SegmentNotPresent = 11,
+    StackSegmentFault = 12,
+    GeneralProtection = 13,
+    PageFault = 14,
+    X87FloatingPoint = 16,
+    AlignmentCheck = 17,
+    MachineCheck = 18,
+    SimdFloatingPoint = 19,
+    Virtualization = 20,
+    Security = 30,
+}
+
+/// The state of the guest when an exception occurred, as saved on the
+/// stack by the exception entry code. Handlers can modify it, for example
+/// to advance `rip` past the faulting instruction, and the guest resumes
+/// from the modified state if the handler returns
+/// `ExceptionAction::Resume`.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ExceptionContext {
+    pub gs: u64,
+    pub fs: u64,
+    pub es: u64,
+    pub ds: u64,
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    /// The error code pushed by the CPU, or 0 for exceptions without one
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// What to do after an exception handler has run
+#[derive(Debug)]
+pub enum ExceptionAction {
+    /// Resume the guest from the (possibly modified) `ExceptionContext`
+    Resume,
+    /// Fail the guest function call that was running with the given error,
+    /// which the host receives as a `HyperlightError::GuestError`
+    Fail(HyperlightGuestError),
+}
+
+/// A handler for a CPU exception. It is called with the exception number
+/// and, for page faults, the address that caused the fault.
+///
+/// Handlers run in the exception context, so they must not take locks
+/// the faulting code may hold, and should not cause another exception.
+/// Returning `ExceptionAction::Fail` allocates, which is only safe if the
+/// exception did not occur inside the allocator.
+pub type ExceptionHandler = fn(u8, u64, &mut ExceptionContext) -> ExceptionAction;
+
+const MAX_EXCEPTIONS: usize = 32;
+
+/// The handler for each exception number, or 0 if there is none
+static EXCEPTION_HANDLERS: [AtomicUsize; MAX_EXCEPTIONS] =
+    [const { AtomicUsize::new(0) }; MAX_EXCEPTIONS];
+
+struct PageFaultHandler {
+    addresses: Range<u64>,
+    handler: ExceptionHandler,
+}
+
+static PAGE_FAULT_HANDLERS: Mutex<Vec<PageFaultHandler>> = Mutex::new(Vec::new());
+
+/// Call `handler` whenever `exception` occurs, instead of aborting the
+/// guest. Page faults in a range registered with
+/// `register_page_fault_handler` are handled by the handler for that
+/// range instead.
+pub fn register_exception_handler(exception: Exception, handler: ExceptionHandler) {
+    EXCEPTION_HANDLERS[exception as usize].store(handler as usize, Ordering::Release);
+}
+
+/// Stop handling `exception`, so that the guest is aborted when it occurs
+pub fn unregister_exception_handler(exception: Exception) {
+    EXCEPTION_HANDLERS[exception as usize].store(0, Ordering::Release);
+}
+
+/// Call `handler` whenever a page fault occurs at an address in
+/// `addresses`, instead of aborting the guest
+pub fn register_page_fault_handler(addresses: Range<u64>, handler: ExceptionHandler) {
+    PAGE_FAULT_HANDLERS
+        .lock()
+        .push(PageFaultHandler { addresses, handler });
+}
+
+/// Stop handling page faults in `addresses`
+pub fn unregister_page_fault_handler(addresses: Range<u64>) {
+    PAGE_FAULT_HANDLERS
+        .lock()
+        .retain(|h| h.addresses != addresses);
+}
+
+/// Find the handler for the given exception. Returns `None` rather than
+/// waiting if the page fault handlers are locked, because the exception
+/// may have interrupted the code holding the lock.
+pub(crate) fn find_handler(exception_number: u64, fault_address: u64) -> Option<ExceptionHandler> {
+    if exception_number == Exception::PageFault as u64 {
+        let handler = PAGE_FAULT_HANDLERS
+            .try_lock()?
+            .iter()
+            .find(|h| h.addresses.contains(&fault_address))
+            .map(|h| h.handler);
+        if handler.is_some() {
+            return handler;
+        }
+    }
+
+    let handler = EXCEPTION_HANDLERS
+        .get(exception_number as usize)?
+        .load(Ordering::Acquire);
+    match handler {
+        0 => None,
+        handler => Some(unsafe { core::mem::transmute::<usize, ExceptionHandler>(handler) }),
+    }
+}
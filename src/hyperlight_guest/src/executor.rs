@@ -0,0 +1,190 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use alloc::boxed::Box;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use alloc::{format, vec};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use hyperlight_common::flatbuffer_wrappers::function_types::{
+    ParameterValue, ReturnType, ReturnValue,
+};
+use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+
+use crate::error::{HyperlightGuestError, Result};
+use crate::host_function_call::{call_host_function, get_host_return_value};
+
+/// Records whether a task has been woken since it was last polled
+struct TaskWaker {
+    woken: AtomicBool,
+}
+
+impl TaskWaker {
+    fn new() -> Arc<Self> {
+        // a new task is polled at least once
+        Arc::new(Self {
+            woken: AtomicBool::new(true),
+        })
+    }
+
+    fn take_woken(&self) -> bool {
+        self.woken.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Relaxed);
+    }
+}
+
+struct Task {
+    future: Pin<Box<dyn Future<Output = ()>>>,
+    waker: Arc<TaskWaker>,
+}
+
+/// A single-threaded executor for running `async` guest code.
+///
+/// The guest has no interrupts or other threads, so a task can only be
+/// woken by another task, or by itself (see `yield_now`). `run` returns
+/// an error rather than waiting forever if no task can make progress.
+#[derive(Default)]
+pub struct Executor {
+    tasks: Vec<Task>,
+}
+
+impl Executor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `future` to the tasks run by `run`
+    pub fn spawn<F>(&mut self, future: F)
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        self.tasks.push(Task {
+            future: Box::pin(future),
+            waker: TaskWaker::new(),
+        });
+    }
+
+    /// Poll the spawned tasks in turn until they have all completed
+    pub fn run(&mut self) -> Result<()> {
+        while !self.tasks.is_empty() {
+            let mut polled = false;
+            let mut i = 0;
+            while i < self.tasks.len() {
+                let task = &mut self.tasks[i];
+                if !task.waker.take_woken() {
+                    i += 1;
+                    continue;
+                }
+                polled = true;
+                let waker = Waker::from(task.waker.clone());
+                let mut cx = Context::from_waker(&waker);
+                match task.future.as_mut().poll(&mut cx) {
+                    Poll::Ready(()) => {
+                        self.tasks.swap_remove(i);
+                    }
+                    Poll::Pending => i += 1,
+                }
+            }
+            if !polled {
+                return Err(stalled(self.tasks.len()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run `future` to completion on the current thread and return its output
+pub fn block_on<F: Future>(future: F) -> Result<F::Output> {
+    let mut future = core::pin::pin!(future);
+    let task_waker = TaskWaker::new();
+    let waker = Waker::from(task_waker.clone());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if !task_waker.take_woken() {
+            return Err(stalled(1));
+        }
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return Ok(output);
+        }
+    }
+}
+
+fn stalled(tasks: usize) -> HyperlightGuestError {
+    HyperlightGuestError::new(
+        ErrorCode::GuestError,
+        format!(
+            "{} pending task(s) can never be woken, the guest has no other source of events",
+            tasks
+        ),
+    )
+}
+
+/// A future that is pending the first time it is polled, letting other
+/// tasks run before the task awaiting it continues
+pub fn yield_now() -> impl Future<Output = ()> {
+    let mut yielded = false;
+    core::future::poll_fn(move |cx| {
+        if yielded {
+            Poll::Ready(())
+        } else {
+            yielded = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    })
+}
+
+/// The `async` version of `call_host_function` followed by
+/// `get_host_return_value`.
+///
+/// Host function calls suspend the whole guest until the host returns, so
+/// this yields to the other tasks before making the call, and then
+/// completes as soon as the host has returned.
+pub async fn call_host_function_async<T: TryFrom<ReturnValue>>(
+    function_name: &str,
+    parameters: Option<Vec<ParameterValue>>,
+    return_type: ReturnType,
+) -> Result<T> {
+    yield_now().await;
+    call_host_function(function_name, parameters, return_type)?;
+    get_host_return_value::<T>()
+}
+
+/// Send `message` to the host's `HostPrint` function, see
+/// `call_host_function_async`
+pub async fn print_async(message: &str) -> Result<i32> {
+    call_host_function_async(
+        "HostPrint",
+        Some(vec![ParameterValue::String(message.to_string())]),
+        ReturnType::Int,
+    )
+    .await
+}
@@ -15,7 +15,8 @@ limitations under the License.
 */
 
 use core::arch::asm;
-use core::ptr::addr_of;
+use core::mem::size_of;
+use core::ptr::{addr_of, addr_of_mut};
 
 /// Entry in the Global Descriptor Table (GDT)
 /// For reference, see page 3-10 Vol. 3A of Intel 64 and IA-32
@@ -50,18 +51,75 @@ impl GdtEntry {
             access,
         }
     }
+
+    /// Creates the upper half of a 64-bit system segment descriptor, such
+    /// as the TSS descriptor, which holds bits 63..32 of its base address.
+    const fn system_upper(base: u64) -> Self {
+        Self {
+            limit_low: ((base >> 32) & 0xffff) as u16,
+            base_low: ((base >> 48) & 0xffff) as u16,
+            base_middle: 0,
+            access: 0,
+            flags_limit: 0,
+            base_high: 0,
+        }
+    }
 }
 
+/// The 64-bit Task State Segment (TSS), which the guest only uses for its
+/// Interrupt Stack Table (IST).
+/// For reference, see page 8-12 Vol. 3A of Intel 64 and IA-32
+/// Architectures Software Developer's Manual, figure 8-11.
+#[repr(C, packed)]
+struct TaskStateSegment {
+    reserved0: u32,
+    rsp: [u64; 3],
+    reserved1: u64,
+    ist: [u64; 7],
+    reserved2: u64,
+    reserved3: u16,
+    iomap_base: u16,
+}
+
+static mut TSS: TaskStateSegment = TaskStateSegment {
+    reserved0: 0,
+    rsp: [0; 3],
+    reserved1: 0,
+    ist: [0; 7],
+    reserved2: 0,
+    reserved3: 0,
+    // no I/O permission bitmap
+    iomap_base: size_of::<TaskStateSegment>() as u16,
+};
+
+/// The Interrupt Stack Table entry the double fault handler runs on, so
+/// that it can report a double fault caused by the stack it was raised on
+/// rather than fault again, which would triple fault the guest
+pub(crate) const DOUBLE_FAULT_IST: u8 = 1;
+
+const DOUBLE_FAULT_STACK_SIZE: usize = 0x4000;
+
+#[repr(C, align(16))]
+struct DoubleFaultStack([u8; DOUBLE_FAULT_STACK_SIZE]);
+
+static mut DOUBLE_FAULT_STACK: DoubleFaultStack = DoubleFaultStack([0; DOUBLE_FAULT_STACK_SIZE]);
+
+/// The selector of the TSS descriptor, which takes up two entries
+const TSS_SELECTOR: u16 = 0x18;
+
 // Global Descriptor Table (GDT)
 // For reference, see page 2-3 Vol. 3A of Intel 64 and IA-32
 // Architectures Software Developer's Manual.
-static mut GDT: [GdtEntry; 3] = [
+static mut GDT: [GdtEntry; 5] = [
     // Null descriptor
     GdtEntry::new(0, 0, 0, 0),
     // Kernel Code Segment (0x08)
     GdtEntry::new(0, 0, 0x9A, 0xA),
     // Kernel Data Segment (0x10)
     GdtEntry::new(0, 0, 0x92, 0xC),
+    // TSS (0x18), filled in by `load_gdt` once its address is known
+    GdtEntry::new(0, 0, 0, 0),
+    GdtEntry::new(0, 0, 0, 0),
 ];
 
 /// GDTR (GDT pointer)
@@ -71,10 +129,26 @@ struct GdtPointer {
     base: u64,
 }
 
-/// Load the GDT
+/// Load the GDT, and the TSS holding the stack the double fault handler
+/// runs on
 pub unsafe fn load_gdt() {
+    let stack_top = addr_of!(DOUBLE_FAULT_STACK) as u64 + DOUBLE_FAULT_STACK_SIZE as u64;
+    let mut ist = [0; 7];
+    ist[DOUBLE_FAULT_IST as usize - 1] = stack_top;
+    let tss = addr_of_mut!(TSS);
+    (*tss).ist = ist;
+    let tss_base = tss as u64;
+    // 0x89 = present, 64-bit TSS (available)
+    GDT[3] = GdtEntry::new(
+        tss_base as u32,
+        (size_of::<TaskStateSegment>() - 1) as u32,
+        0x89,
+        0,
+    );
+    GDT[4] = GdtEntry::system_upper(tss_base);
+
     let gdt_ptr = GdtPointer {
-        size: (core::mem::size_of::<[GdtEntry; 3]>() - 1) as u16,
+        size: (size_of::<[GdtEntry; 5]>() - 1) as u16,
         base: addr_of!(GDT) as *const _ as u64,
     };
 
@@ -94,4 +168,10 @@ pub unsafe fn load_gdt() {
     in(reg) &gdt_ptr,
     options(nostack, preserves_flags)
     );
+
+    asm!(
+    "ltr {0:x}",
+    in(reg) TSS_SELECTOR,
+    options(nostack, preserves_flags)
+    );
 }
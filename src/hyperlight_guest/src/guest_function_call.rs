@@ -17,15 +17,21 @@ limitations under the License.
 use alloc::format;
 use alloc::vec::Vec;
 
-use hyperlight_common::flatbuffer_wrappers::function_call::{FunctionCall, FunctionCallType};
+use hyperlight_common::flatbuffer_wrappers::function_call::{
+    FunctionCall, FunctionCallType, GUEST_FUNCTION_DETAILS_FUNCTION_NAME,
+};
 use hyperlight_common::flatbuffer_wrappers::function_types::ParameterType;
 use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+use hyperlight_common::flatbuffer_wrappers::util::get_flatbuffer_result;
 
 use crate::entrypoint::halt;
 use crate::error::{HyperlightGuestError, Result};
 use crate::guest_error::{reset_error, set_error};
+use crate::guest_logger::update_max_level_from_host;
+use crate::payload_compression::compress_output;
+use crate::progress::reset_progress;
 use crate::shared_input_data::try_pop_shared_input_data_into;
-use crate::shared_output_data::push_shared_output_data;
+use crate::shared_output_data::{check_output_payload_size, push_shared_output_data};
 use crate::REGISTERED_GUEST_FUNCTIONS;
 
 type GuestFunc = fn(&FunctionCall) -> Result<Vec<u8>>;
@@ -42,6 +48,10 @@ pub(crate) fn call_guest_function(function_call: FunctionCall) -> Result<Vec<u8>
         ));
     }
 
+    if function_call.function_name == GUEST_FUNCTION_DETAILS_FUNCTION_NAME {
+        return get_guest_function_details();
+    }
+
     // Find the function definition for the function call.
     if let Some(registered_function_definition) =
         unsafe { REGISTERED_GUEST_FUNCTIONS.get(&function_call.function_name) }
@@ -76,20 +86,53 @@ pub(crate) fn call_guest_function(function_call: FunctionCall) -> Result<Vec<u8>
     }
 }
 
+/// Serialize the signatures of the registered guest functions, so the host
+/// can check the parameters of guest function calls before making them
+fn get_guest_function_details() -> Result<Vec<u8>> {
+    #[allow(static_mut_refs)]
+    let function_details = unsafe { REGISTERED_GUEST_FUNCTIONS.function_details() };
+    let function_details: Vec<u8> = (&function_details).try_into().map_err(|e| {
+        HyperlightGuestError::new(
+            ErrorCode::GuestError,
+            format!("Failed to serialize guest function details: {:?}", e),
+        )
+    })?;
+    Ok(get_flatbuffer_result(function_details.as_slice()))
+}
+
 // This function is marked as no_mangle/inline to prevent the compiler from inlining it , if its inlined the epilogue will not be called
 // and we will leak memory as the epilogue will not be called as halt() is not going to return.
 #[no_mangle]
 #[inline(never)]
 fn internal_dispatch_function() -> Result<()> {
     reset_error();
+    update_max_level_from_host();
+    reset_progress();
+    #[cfg(feature = "malloc_trace")]
+    crate::malloc_trace::reset_malloc_trace();
 
     #[cfg(debug_assertions)]
     log::trace!("internal_dispatch_function");
 
-    let function_call = try_pop_shared_input_data_into::<FunctionCall>()
-        .expect("Function call deserialization failed");
-
-    let result_vec = call_guest_function(function_call).inspect_err(|e| {
+    let result = try_pop_shared_input_data_into::<FunctionCall>()
+        .and_then(call_guest_function)
+        .and_then(|result_vec| {
+            let result_vec = compress_output(result_vec)?;
+            check_output_payload_size(&result_vec)?;
+            Ok(result_vec)
+        });
+
+    // The profile and trace are only for the host to inspect, so failing
+    // to send them doesn't fail the call
+    #[cfg(feature = "heap_profiler")]
+    let _ = crate::heap_profiler::send_heap_profile();
+    #[cfg(feature = "malloc_trace")]
+    let _ = crate::malloc_trace::send_malloc_trace(match &result {
+        Ok(result_vec) => result_vec.as_ptr(),
+        Err(e) => e.message.as_ptr(),
+    });
+
+    let result_vec = result.inspect_err(|e| {
         set_error(e.kind.clone(), e.message.as_str());
     })?;
 
@@ -100,6 +143,7 @@ fn internal_dispatch_function() -> Result<()> {
 // which if it were included in the internal_dispatch_function cause the epilogue to not be called because the halt() would not return
 // when running in the hypervisor.
 pub(crate) extern "win64" fn dispatch_function() {
+    unsafe { crate::shadow_stack::reset() };
     let _ = internal_dispatch_function();
     halt();
 }
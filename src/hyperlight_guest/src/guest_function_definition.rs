@@ -20,6 +20,7 @@ use alloc::vec::Vec;
 
 use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterType, ReturnType};
 use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+use hyperlight_common::flatbuffer_wrappers::host_function_definition::FunctionFlags;
 
 use crate::error::{HyperlightGuestError, Result};
 
@@ -34,6 +35,13 @@ pub struct GuestFunctionDefinition {
     pub return_type: ReturnType,
     /// The function pointer to the guest function
     pub function_pointer: usize,
+    /// Whether the function is pure, idempotent or read-only, which the
+    /// host uses to pick safe ways to call it
+    pub flags: FunctionFlags,
+    /// The largest size in bytes the function's serialized return value
+    /// can be, if it declares one, which the host checks against the size
+    /// of its output data buffer before calling the function
+    pub max_result_size: Option<u64>,
 }
 
 impl GuestFunctionDefinition {
@@ -49,9 +57,29 @@ impl GuestFunctionDefinition {
             parameter_types,
             return_type,
             function_pointer,
+            flags: FunctionFlags::NONE,
+            max_result_size: None,
         }
     }
 
+    /// Declare that the function is pure, idempotent or read-only, e.g.
+    /// `FunctionFlags::IDEMPOTENT | FunctionFlags::READ_ONLY`. The host
+    /// trusts the declaration, so only declare what the function
+    /// guarantees.
+    pub fn with_flags(mut self, flags: FunctionFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Declare the largest size in bytes the function's serialized return
+    /// value can be. Calls to the function fail on the host, before the
+    /// guest runs, if the sandbox's output data buffer is smaller, rather
+    /// than when the guest writes the result.
+    pub fn with_max_result_size(mut self, max_result_size: u64) -> Self {
+        self.max_result_size = Some(max_result_size);
+        self
+    }
+
     /// Verify that `self` has same signature as the provided `parameter_types`.
     pub fn verify_parameters(&self, parameter_types: &[ParameterType]) -> Result<()> {
         // Verify that the function does not have more than `MAX_PARAMETERS` parameters.
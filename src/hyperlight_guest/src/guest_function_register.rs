@@ -17,6 +17,12 @@ limitations under the License.
 use alloc::collections::BTreeMap;
 use alloc::string::String;
 
+use hyperlight_common::flatbuffer_wrappers::function_call::{
+    is_in_namespace, namespaced_function_name, versioned_function_name,
+};
+use hyperlight_common::flatbuffer_wrappers::host_function_definition::HostFunctionDefinition;
+use hyperlight_common::flatbuffer_wrappers::host_function_details::HostFunctionDetails;
+
 use super::guest_function_definition::GuestFunctionDefinition;
 use crate::REGISTERED_GUEST_FUNCTIONS;
 
@@ -51,6 +57,39 @@ impl GuestFunctionRegister {
     pub fn get(&self, function_name: &str) -> Option<&GuestFunctionDefinition> {
         self.guest_functions.get(function_name)
     }
+
+    /// The registered guest functions in `namespace`, including those in
+    /// namespaces nested in it.
+    pub fn functions_in_namespace<'a>(
+        &'a self,
+        namespace: &'a str,
+    ) -> impl Iterator<Item = &'a GuestFunctionDefinition> + 'a {
+        self.guest_functions
+            .values()
+            .filter(move |f| is_in_namespace(&f.function_name, namespace))
+    }
+
+    /// The signatures of the registered guest functions, in the same form
+    /// as the host describes its functions to the guest.
+    pub fn function_details(&self) -> HostFunctionDetails {
+        HostFunctionDetails::new(Some(
+            self.guest_functions
+                .values()
+                .map(|f| {
+                    let definition = HostFunctionDefinition::new(
+                        f.function_name.clone(),
+                        Some(f.parameter_types.clone()).filter(|p| !p.is_empty()),
+                        f.return_type,
+                    )
+                    .with_flags(f.flags);
+                    match f.max_result_size {
+                        Some(size) => definition.with_max_result_size(size),
+                        None => definition,
+                    }
+                })
+                .collect(),
+        ))
+    }
 }
 
 pub fn register_function(function_definition: GuestFunctionDefinition) {
@@ -62,3 +101,27 @@ pub fn register_function(function_definition: GuestFunctionDefinition) {
         gfd.register(function_definition);
     }
 }
+
+/// Register `function_definition` in `namespace`, so the host calls it as
+/// `namespace::function_name`, and can list or permit all the functions in
+/// the namespace at once.
+pub fn register_namespaced_function(
+    namespace: &str,
+    mut function_definition: GuestFunctionDefinition,
+) {
+    function_definition.function_name =
+        namespaced_function_name(namespace, &function_definition.function_name);
+    register_function(function_definition);
+}
+
+/// Register `function_definition` as `version` of its function, so the
+/// host calls it as `function_name@v{version}`. Several versions of a
+/// function can be registered at once, for hosts that speak different
+/// versions of the guest's protocol; a host calling `function_name`
+/// without a version gets the version it chose as the default, or else
+/// the newest one.
+pub fn register_versioned_function(version: u32, mut function_definition: GuestFunctionDefinition) {
+    function_definition.function_name =
+        versioned_function_name(&function_definition.function_name, version);
+    register_function(function_definition);
+}
@@ -19,6 +19,7 @@ use alloc::format;
 use log::{LevelFilter, Metadata, Record};
 
 use crate::logging::log_message;
+use crate::P_PEB;
 
 // this is private on purpose so that `log` can only be called though the `log!` macros.
 struct GuestLogger {}
@@ -30,6 +31,17 @@ pub(crate) fn init_logger(level: LevelFilter) {
     log::set_max_level(level);
 }
 
+/// Switch to the max log level the host asked for in the PEB, if any.
+///
+/// The host writes this before every guest function call, so this needs to
+/// run at the start of each dispatch.
+pub(crate) fn update_max_level_from_host() {
+    let requested = unsafe { (*P_PEB.unwrap()).guest_max_log_level };
+    if let Some(level) = LevelFilter::iter().nth(requested as usize) {
+        log::set_max_level(level);
+    }
+}
+
 impl log::Log for GuestLogger {
     // The various macros like `info!` and `error!` will call the global log::max_level()
     // before calling our `log`. This means that we should log every message we get, because
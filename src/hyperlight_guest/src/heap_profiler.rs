@@ -0,0 +1,232 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! A guest allocator that records, for every allocation site, how many
+//! allocations were made there and how many of them are still live, and
+//! sends the counts to the host at the end of every guest function call,
+//! for the host to read with `MultiUseSandbox::last_heap_profile`.
+//!
+//! An allocation site is the chain of up to `SITE_FRAMES` return addresses
+//! that led to the allocator, which is found by following the frame
+//! pointer, so guests must be built with `-C force-frame-pointers=yes` for
+//! the sites to be meaningful. The innermost of them are in `alloc` and the
+//! allocator itself, which the host walks past with the guest's symbol map
+//! to find the guest code that allocated. Allocations whose site can't be
+//! found, and allocations made once `MAX_SITES` sites have been seen, are
+//! counted under an empty site.
+//!
+//! Each allocation is made `HEADER_SIZE` (or its alignment, if larger)
+//! bytes bigger, to remember its site and size when it is freed, so the
+//! guest's heap must be a little larger than without the profiler.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::null_mut;
+
+use buddy_system_allocator::LockedHeap;
+use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterValue, ReturnType};
+use spin::Mutex;
+
+use crate::error::Result;
+use crate::host_function_call::{call_host_function, get_host_return_value};
+use crate::{MIN_STACK_ADDRESS, P_PEB};
+
+/// The most allocation sites recorded during a guest function call
+pub const MAX_SITES: usize = 64;
+/// The most return addresses recorded for an allocation site
+pub const SITE_FRAMES: usize = 16;
+/// The bytes in front of every allocation that hold the index of its site
+/// and its size
+const HEADER_SIZE: usize = 16;
+
+#[derive(Clone, Copy)]
+struct SiteStats {
+    frames: [u64; SITE_FRAMES],
+    allocations: u64,
+    allocated_bytes: u64,
+    live_allocations: u64,
+    live_bytes: u64,
+}
+
+impl SiteStats {
+    const EMPTY: Self = Self {
+        frames: [0; SITE_FRAMES],
+        allocations: 0,
+        allocated_bytes: 0,
+        live_allocations: 0,
+        live_bytes: 0,
+    };
+}
+
+/// The statistics of every allocation site. The first entry is the empty
+/// site, for the allocations that couldn't be given a site of their own.
+#[derive(Clone, Copy)]
+struct Sites {
+    stats: [SiteStats; MAX_SITES],
+    len: usize,
+}
+
+impl Sites {
+    /// The index of the entry of the site `frames`, added if it is new
+    fn index(&mut self, frames: &[u64; SITE_FRAMES]) -> usize {
+        match self.stats[..self.len]
+            .iter()
+            .position(|s| s.frames == *frames)
+        {
+            Some(index) => index,
+            None if self.len < MAX_SITES => {
+                self.stats[self.len].frames = *frames;
+                self.len += 1;
+                self.len - 1
+            }
+            None => 0,
+        }
+    }
+}
+
+// Guest memory is restored after every guest function call, so the
+// statistics start again from those of the guest's initialisation for
+// every call
+static SITES: Mutex<Sites> = Mutex::new(Sites {
+    stats: [SiteStats::EMPTY; MAX_SITES],
+    len: 1,
+});
+
+/// A `GlobalAlloc` that records allocation sites and sizes, and makes the
+/// allocations from the guest's heap
+pub(crate) struct ProfilingAllocator {
+    heap: &'static LockedHeap<32>,
+}
+
+impl ProfilingAllocator {
+    pub(crate) const fn new(heap: &'static LockedHeap<32>) -> Self {
+        Self { heap }
+    }
+}
+
+/// The layout of the allocation made from the heap for an allocation of
+/// `layout`, and the offset of the allocation within it
+fn with_header(layout: Layout) -> Option<(Layout, usize)> {
+    let offset = layout.align().max(HEADER_SIZE);
+    let size = layout.size().checked_add(offset)?;
+    Some((Layout::from_size_align(size, layout.align()).ok()?, offset))
+}
+
+/// The return addresses of the functions that led to the allocator,
+/// innermost first, followed by zeros once the frame pointer no longer
+/// points into the stack
+#[inline(always)]
+fn allocation_site() -> [u64; SITE_FRAMES] {
+    let mut frames = [0; SITE_FRAMES];
+    let mut rbp: u64;
+    unsafe {
+        core::arch::asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack, preserves_flags));
+    }
+    let (stack_min, stack_max) = unsafe {
+        match P_PEB {
+            Some(peb) => (MIN_STACK_ADDRESS, (*peb).gueststackData.userStackAddress),
+            None => return frames,
+        }
+    };
+    for frame in frames.iter_mut() {
+        if rbp % 8 != 0 || rbp < stack_min || rbp.saturating_add(16) > stack_max {
+            break;
+        }
+        let caller_rbp = unsafe { (rbp as *const u64).read() };
+        *frame = unsafe { ((rbp + 8) as *const u64).read() };
+        // the stack grows down, so every caller's frame is above
+        if caller_rbp <= rbp {
+            break;
+        }
+        rbp = caller_rbp;
+    }
+    frames
+}
+
+unsafe impl GlobalAlloc for ProfilingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let site = allocation_site();
+        let Some((heap_layout, offset)) = with_header(layout) else {
+            return null_mut();
+        };
+        let base = self.heap.alloc(heap_layout);
+        if base.is_null() {
+            return base;
+        }
+        let size = layout.size() as u64;
+        let mut sites = SITES.lock();
+        let index = sites.index(&site);
+        let stats = &mut sites.stats[index];
+        stats.allocations = stats.allocations.saturating_add(1);
+        stats.allocated_bytes = stats.allocated_bytes.saturating_add(size);
+        stats.live_allocations = stats.live_allocations.saturating_add(1);
+        stats.live_bytes = stats.live_bytes.saturating_add(size);
+        drop(sites);
+
+        let ptr = base.add(offset);
+        let header = ptr.sub(HEADER_SIZE) as *mut u64;
+        header.write_unaligned(index as u64);
+        header.add(1).write_unaligned(size);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // `alloc` succeeded with this layout, so this does too
+        let Some((heap_layout, offset)) = with_header(layout) else {
+            return;
+        };
+        let header = ptr.sub(HEADER_SIZE) as *const u64;
+        let index = header.read_unaligned() as usize;
+        let size = header.add(1).read_unaligned();
+        {
+            let mut sites = SITES.lock();
+            let stats = &mut sites.stats[index.min(MAX_SITES - 1)];
+            stats.live_allocations = stats.live_allocations.saturating_sub(1);
+            stats.live_bytes = stats.live_bytes.saturating_sub(size);
+        }
+        self.heap.dealloc(ptr.sub(offset), heap_layout);
+    }
+}
+
+/// Send the statistics of every allocation site to the host, as
+/// little-endian `u64`s: for every site its `SITE_FRAMES` return addresses,
+/// the number of allocations made there, the bytes they allocated, and the
+/// number and bytes of those still live
+pub(crate) fn send_heap_profile() -> Result<()> {
+    // Copy the statistics first, so that building the profile, which
+    // allocates, doesn't change them or take the lock while it is held
+    let sites = *SITES.lock();
+    let profile: Vec<u8> = sites.stats[..sites.len]
+        .iter()
+        .filter(|stats| stats.allocations > 0)
+        .flat_map(|stats| {
+            stats.frames.into_iter().chain([
+                stats.allocations,
+                stats.allocated_bytes,
+                stats.live_allocations,
+                stats.live_bytes,
+            ])
+        })
+        .flat_map(u64::to_le_bytes)
+        .collect();
+    call_host_function(
+        "HostRecordHeapProfile",
+        Some(vec![ParameterValue::VecBytes(profile)]),
+        ReturnType::Void,
+    )?;
+    get_host_return_value::<()>()
+}
@@ -0,0 +1,32 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use hyperlight_common::flatbuffer_wrappers::function_types::ReturnType;
+
+use crate::error::Result;
+use crate::host_function_call::{call_host_function, get_host_return_value};
+
+/// Tell the host that the guest is still making progress.
+///
+/// Guest functions that run for a long time should call this periodically.
+/// If the host has been configured with a heartbeat timeout, a guest
+/// function call that goes longer than the timeout without calling this is
+/// treated as hung and cancelled, even if it is still within its maximum
+/// execution time.
+pub fn heartbeat() -> Result<()> {
+    call_host_function("HostHeartbeat", None, ReturnType::Void)?;
+    get_host_return_value::<()>()
+}
@@ -19,6 +19,7 @@ use core::slice::from_raw_parts;
 
 use hyperlight_common::flatbuffer_wrappers::guest_error::{ErrorCode, GuestError};
 
+use crate::guest_error::reset_error;
 use crate::P_PEB;
 
 pub(crate) fn check_for_host_error() {
@@ -31,7 +32,11 @@ pub(crate) fn check_for_host_error() {
 
         if !guest_error_buffer.is_empty() {
             let guest_error = GuestError::try_from(guest_error_buffer).expect("Invalid GuestError");
-            if guest_error.code != ErrorCode::NoError {
+            // a host function that panicked, or was called with an invalid
+            // argument, is reported to the caller of the host function by
+            // `take_host_function_error` instead
+            if guest_error.code != ErrorCode::NoError && !is_host_function_error(&guest_error.code)
+            {
                 (*peb_ptr).outputdata.outputDataBuffer = usize::MAX as *mut c_void;
                 panic!(
                     "Guest Error: {:?} - {}",
@@ -41,3 +46,31 @@ pub(crate) fn check_for_host_error() {
         }
     }
 }
+
+/// Whether `code` is an error the host hands the guest in place of the
+/// return value of a host function
+fn is_host_function_error(code: &ErrorCode) -> bool {
+    matches!(
+        code,
+        ErrorCode::HostFunctionError | ErrorCode::HostFunctionArgumentInvalid
+    )
+}
+
+/// Take the error the host wrote in place of the return value of the last
+/// host function called, if that host function panicked or one of its
+/// arguments was invalid
+pub(crate) fn take_host_function_error() -> Option<GuestError> {
+    unsafe {
+        let peb_ptr = P_PEB.unwrap();
+        let guest_error_buffer_ptr = (*peb_ptr).guestErrorData.guestErrorBuffer as *mut u8;
+        let guest_error_buffer_size = (*peb_ptr).guestErrorData.guestErrorSize as usize;
+
+        let guest_error_buffer = from_raw_parts(guest_error_buffer_ptr, guest_error_buffer_size);
+        let guest_error = GuestError::try_from(guest_error_buffer).ok()?;
+        if !is_host_function_error(&guest_error.code) {
+            return None;
+        }
+        reset_error();
+        Some(guest_error)
+    }
+}
@@ -26,25 +26,56 @@ use hyperlight_common::flatbuffer_wrappers::function_types::{
 use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
 use hyperlight_common::flatbuffer_wrappers::util::get_flatbuffer_result;
 use hyperlight_common::mem::RunMode;
+use hyperlight_common::transport::{mmio_doorbell_address, Channel, HostCallTransport, PortMap};
 
 use crate::error::{HyperlightGuestError, Result};
-use crate::host_error::check_for_host_error;
+use crate::host_error::{check_for_host_error, take_host_function_error};
 use crate::host_functions::validate_host_function_call;
 use crate::shared_input_data::try_pop_shared_input_data_into;
-use crate::shared_output_data::push_shared_output_data;
-use crate::{OUTB_PTR, OUTB_PTR_WITH_CONTEXT, P_PEB, RUNNING_MODE};
+use crate::shared_output_data::{
+    check_output_payload_size, payload_limits, push_shared_output_data,
+};
+use crate::{HOST_CALL_TRANSPORT, OUTB_PTR, OUTB_PTR_WITH_CONTEXT, P_PEB, RUNNING_MODE};
 
+#[derive(Copy, Clone)]
 pub enum OutBAction {
-    Log = 99,
-    CallFunction = 101,
-    Abort = 102,
+    Log,
+    CallFunction,
+    Abort,
+    Debug,
+}
+
+impl OutBAction {
+    /// The port the host handles this action on, from the port map the
+    /// host wrote to the PEB
+    pub fn port(self) -> u16 {
+        let port_map = match unsafe { P_PEB } {
+            Some(peb_ptr) => unsafe { (*peb_ptr).port_map },
+            None => PortMap::DEFAULT,
+        };
+        port_map.port(match self {
+            OutBAction::Log => Channel::Log,
+            OutBAction::CallFunction => Channel::Call,
+            OutBAction::Abort => Channel::Abort,
+            OutBAction::Debug => Channel::Debug,
+        })
+    }
 }
 
 /// Get a return value from a host function call.
 /// This usually requires a host function to be called first using `call_host_function`.
+/// If the host function panicked, this returns an error with
+/// `ErrorCode::HostFunctionError` and the panic's message, and if one of
+/// its arguments broke the function's validation, an error with
+/// `ErrorCode::HostFunctionArgumentInvalid`.
 pub fn get_host_return_value<T: TryFrom<ReturnValue>>() -> Result<T> {
-    let return_value = try_pop_shared_input_data_into::<ReturnValue>()
-        .expect("Unable to deserialize a return value from host");
+    if let Some(host_error) = take_host_function_error() {
+        return Err(HyperlightGuestError::new(
+            host_error.code,
+            host_error.message,
+        ));
+    }
+    let return_value = try_pop_shared_input_data_into::<ReturnValue>()?;
     T::try_from(return_value).map_err(|_| {
         HyperlightGuestError::new(
             ErrorCode::GuestError,
@@ -72,14 +103,16 @@ pub fn call_host_function(
     );
 
     validate_host_function_call(&host_function_call)?;
+    payload_limits().check_parameters(host_function_call.parameters.as_deref())?;
 
     let host_function_call_buffer: Vec<u8> = host_function_call
         .try_into()
         .expect("Unable to serialize host function call");
 
+    check_output_payload_size(&host_function_call_buffer)?;
     push_shared_output_data(host_function_call_buffer)?;
 
-    outb(OutBAction::CallFunction as u16, 0);
+    outb(OutBAction::CallFunction.port(), 0);
 
     Ok(())
 }
@@ -87,9 +120,12 @@ pub fn call_host_function(
 pub fn outb(port: u16, value: u8) {
     unsafe {
         match RUNNING_MODE {
-            RunMode::Hypervisor => {
-                hloutb(port, value);
-            }
+            RunMode::Hypervisor => match HOST_CALL_TRANSPORT {
+                HostCallTransport::PortIo => hloutb(port, value),
+                HostCallTransport::Mmio => {
+                    core::ptr::write_volatile(mmio_doorbell_address(port) as *mut u8, value)
+                }
+            },
             RunMode::InProcessLinux | RunMode::InProcessWindows => {
                 if let Some(outb_func) = OUTB_PTR_WITH_CONTEXT {
                     if let Some(peb_ptr) = P_PEB {
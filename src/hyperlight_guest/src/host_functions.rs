@@ -15,16 +15,17 @@ limitations under the License.
 */
 
 use alloc::format;
-use alloc::string::ToString;
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use core::slice::from_raw_parts;
 
 use hyperlight_common::flatbuffer_wrappers::function_call::FunctionCall;
-use hyperlight_common::flatbuffer_wrappers::function_types::ParameterType;
+use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterType, ReturnType};
 use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
 use hyperlight_common::flatbuffer_wrappers::host_function_details::HostFunctionDetails;
 
 use crate::error::{HyperlightGuestError, Result};
+use crate::host_function_call::{call_host_function, get_host_return_value};
 use crate::P_PEB;
 
 pub(crate) fn validate_host_function_call(function_call: &FunctionCall) -> Result<()> {
@@ -110,3 +111,40 @@ pub fn get_host_function_details() -> HostFunctionDetails {
         .try_into()
         .expect("Failed to convert buffer to HostFunctionDetails")
 }
+
+/// The names of the optional capabilities the host declared with
+/// `UninitializedSandbox::declare_feature`, such as "network", sorted.
+///
+/// Guests can branch on them rather than probing for the host functions
+/// that make a capability up by calling them.
+pub fn features() -> Vec<String> {
+    get_host_function_details().features
+}
+
+/// Whether the host declared the optional capability `name`, see
+/// `features`.
+pub fn has_feature(name: &str) -> bool {
+    get_host_function_details().has_feature(name)
+}
+
+/// The names of every function registered by the host, as reported by the
+/// host's built-in `HostListFunctions` function.
+pub fn list_host_functions() -> Result<Vec<String>> {
+    call_host_function("HostListFunctions", None, ReturnType::String)?;
+    let names = get_host_return_value::<String>()?;
+    Ok(names
+        .split('\n')
+        .filter(|name| !name.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+/// Whether the host has registered a function called `name`.
+///
+/// Guests that use optional host services can check for them with this,
+/// and do without them, rather than failing part way through a call. Returns
+/// `false` if the host can't be asked, because it doesn't provide
+/// `HostListFunctions`.
+pub fn host_has_function(name: &str) -> bool {
+    list_host_functions().is_ok_and(|names| names.iter().any(|n| n == name))
+}
@@ -0,0 +1,67 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use alloc::vec::Vec;
+
+use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterValue, ReturnType};
+
+use crate::error::Result;
+use crate::host_function_call::{call_host_function, get_host_return_value};
+
+/// The data returned by a streaming host function, read one chunk at a
+/// time so that it never has to fit in the input data buffer, or in guest
+/// memory, all at once
+pub struct HostStream {
+    id: u64,
+    finished: bool,
+}
+
+/// Call the streaming host function `function_name`, registered with
+/// `UninitializedSandbox::register_streaming_host_function`, and return
+/// the stream of data it returns.
+pub fn call_streaming_host_function(
+    function_name: &str,
+    parameters: Option<Vec<ParameterValue>>,
+) -> Result<HostStream> {
+    call_host_function(function_name, parameters, ReturnType::ULong)?;
+    let id = get_host_return_value::<u64>()?;
+    Ok(HostStream {
+        id,
+        finished: false,
+    })
+}
+
+impl HostStream {
+    /// Read the next chunk of the stream, or `None` once every chunk has
+    /// been read
+    pub fn read_next_chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.finished {
+            return Ok(None);
+        }
+        call_host_function(
+            "HostReadNextChunk",
+            Some(Vec::from(&[ParameterValue::ULong(self.id)])),
+            ReturnType::VecBytes,
+        )?;
+        let chunk = get_host_return_value::<Vec<u8>>()?;
+        // the host never returns an empty chunk before the stream finishes
+        if chunk.is_empty() {
+            self.finished = true;
+            return Ok(None);
+        }
+        Ok(Some(chunk))
+    }
+}
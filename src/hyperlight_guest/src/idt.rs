@@ -14,10 +14,11 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use crate::gdt::DOUBLE_FAULT_IST;
 use crate::interrupt_entry::{
     _do_excp0, _do_excp1, _do_excp10, _do_excp11, _do_excp12, _do_excp13, _do_excp14, _do_excp15,
-    _do_excp16, _do_excp17, _do_excp18, _do_excp19, _do_excp2, _do_excp20, _do_excp3, _do_excp30,
-    _do_excp4, _do_excp5, _do_excp6, _do_excp7, _do_excp8, _do_excp9,
+    _do_excp16, _do_excp17, _do_excp18, _do_excp19, _do_excp2, _do_excp20, _do_excp21, _do_excp3,
+    _do_excp30, _do_excp4, _do_excp5, _do_excp6, _do_excp7, _do_excp8, _do_excp9,
 };
 
 // An entry in the Interrupt Descriptor Table (IDT)
@@ -44,11 +45,12 @@ pub(crate) struct IdtEntry {
 }
 
 impl IdtEntry {
-    fn new(handler: u64) -> Self {
+    fn new(handler: u64, interrupt_stack_table_offset: u8) -> Self {
         Self {
             offset_low: (handler & 0xFFFF) as u16,
-            selector: 0x08,                  // Kernel Code Segment
-            interrupt_stack_table_offset: 0, // No interrupt stack table used
+            selector: 0x08, // Kernel Code Segment
+            // 0 runs the handler on the interrupted stack
+            interrupt_stack_table_offset,
             type_attr: 0x8E,
             // 0x8E = 10001110b
             // 1 00 0 1110
@@ -78,7 +80,7 @@ pub(crate) fn init_idt() {
     set_idt_entry(5, _do_excp5); // Bound Range Exceeded
     set_idt_entry(6, _do_excp6); // Invalid Opcode
     set_idt_entry(7, _do_excp7); // Device Not Available
-    set_idt_entry(8, _do_excp8); // Double Fault
+    set_idt_entry_with_ist(8, _do_excp8, DOUBLE_FAULT_IST); // Double Fault
     set_idt_entry(9, _do_excp9); // Coprocessor Segment Overrun
     set_idt_entry(10, _do_excp10); // Invalid TSS
     set_idt_entry(11, _do_excp11); // Segment Not Present
@@ -91,12 +93,19 @@ pub(crate) fn init_idt() {
     set_idt_entry(18, _do_excp18); // Machine Check
     set_idt_entry(19, _do_excp19); // SIMD Floating-Point Exception
     set_idt_entry(20, _do_excp20); // Virtualization Exception
+    set_idt_entry(21, _do_excp21); // Control Protection Exception
     set_idt_entry(30, _do_excp30); // Security Exception
 }
 
 fn set_idt_entry(index: usize, handler: unsafe extern "sysv64" fn()) {
+    set_idt_entry_with_ist(index, handler, 0);
+}
+
+/// Set the handler of an exception that runs on the given Interrupt Stack
+/// Table entry's stack
+fn set_idt_entry_with_ist(index: usize, handler: unsafe extern "sysv64" fn(), ist: u8) {
     let handler_addr = handler as *const () as u64;
     unsafe {
-        IDT[index] = IdtEntry::new(handler_addr);
+        IDT[index] = IdtEntry::new(handler_addr, ist);
     }
 }
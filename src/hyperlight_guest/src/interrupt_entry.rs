@@ -44,6 +44,7 @@ extern "sysv64" {
     pub(crate) fn _do_excp18();
     pub(crate) fn _do_excp19();
     pub(crate) fn _do_excp20();
+    pub(crate) fn _do_excp21();
     pub(crate) fn _do_excp30();
 }
 
@@ -124,6 +125,8 @@ macro_rules! generate_exceptions {
             "    mov rdi, rsp\n",
             "    call {hl_exception_handler}\n",
             context_restore!(),
+            // Pop the error code, every exception has one on the stack
+            "    add rsp, 8\n",
             "    iretq\n", // iretq is used to return from exception in x86_64
             generate_excp!(0, pusherrcode),
             generate_excp!(1, pusherrcode),
@@ -146,6 +149,7 @@ macro_rules! generate_exceptions {
             generate_excp!(18, pusherrcode),
             generate_excp!(19, pusherrcode),
             generate_excp!(20, pusherrcode),
+            generate_excp!(21),
             generate_excp!(30),
         )
     };
@@ -14,6 +14,81 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use alloc::format;
+use core::ffi::c_char;
+use core::fmt::{Display, Formatter, Write};
+
+use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+
+use crate::entropy::emulate_instruction;
+use crate::entrypoint::{abort_with_code_and_message, halt};
+use crate::exceptions::{find_handler, ExceptionAction, ExceptionContext};
+use crate::guest_error::set_error;
+use crate::{MIN_STACK_ADDRESS, P_PEB};
+
+/// The most caller frames listed when an unhandled exception aborts the
+/// guest
+const MAX_CALLER_FRAMES: usize = 8;
+
+/// The control protection exception, raised when a return doesn't match
+/// the shadow stack
+const CONTROL_PROTECTION_EXCEPTION: u64 = 21;
+
+/// The invalid opcode exception, raised by `RDRAND` and `RDSEED` when the
+/// host hides them from the guest
+const INVALID_OPCODE_EXCEPTION: u64 = 6;
+
+/// The double fault exception, raised when an exception can't be
+/// delivered, e.g. because the stack it is pushed to isn't mapped
+const DOUBLE_FAULT_EXCEPTION: u64 = 8;
+
+/// The page fault exception
+const PAGE_FAULT_EXCEPTION: u64 = 14;
+
+/// The bit of a page fault's error code that is set when the page was
+/// present, so the fault was caused by the page's protection
+const PAGE_FAULT_PROTECTION_VIOLATION: u64 = 1;
+
+/// The return addresses of the callers of the function that raised an
+/// exception, found by following the frame pointers from its `rbp`. The
+/// walk stops at the first frame pointer that isn't on the user stack or
+/// doesn't point further up it than the previous one, so a guest built
+/// without frame pointers lists fewer frames, but never faults.
+struct CallerFrames(u64);
+
+impl Display for CallerFrames {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        let (bottom, top) = unsafe {
+            match P_PEB {
+                Some(peb) => (MIN_STACK_ADDRESS, (*peb).gueststackData.userStackAddress),
+                None => return Ok(()),
+            }
+        };
+        let mut rbp = self.0;
+        for _ in 0..MAX_CALLER_FRAMES {
+            if rbp < bottom || rbp % 8 != 0 || rbp.saturating_add(16) > top {
+                break;
+            }
+            // SAFETY: the frame is on the user stack, checked above
+            let (next, return_address) = unsafe {
+                (
+                    core::ptr::read_volatile(rbp as *const u64),
+                    core::ptr::read_volatile((rbp + 8) as *const u64),
+                )
+            };
+            if return_address == 0 {
+                break;
+            }
+            write!(f, " {:#x}", return_address)?;
+            if next <= rbp {
+                break;
+            }
+            rbp = next;
+        }
+        Ok(())
+    }
+}
+
 /// Exception handler
 #[no_mangle]
 pub extern "sysv64" fn hl_exception_handler(
@@ -21,10 +96,146 @@ pub extern "sysv64" fn hl_exception_handler(
     exception_number: u64,
     page_fault_address: u64,
 ) {
+    let context = unsafe { &mut *(stack_pointer as *mut ExceptionContext) };
+    let action = match exception_number {
+        INVALID_OPCODE_EXCEPTION => emulate_instruction(context),
+        _ => None,
+    }
+    .or_else(|| {
+        find_handler(exception_number, page_fault_address)
+            .map(|handler| handler(exception_number as u8, page_fault_address, context))
+    });
+    if let Some(action) = action {
+        match action {
+            ExceptionAction::Resume => return,
+            ExceptionAction::Fail(e) => {
+                // the host restores the guest's registers before the next
+                // call, so the guest never resumes from this halt
+                set_error(e.kind, &e.message);
+                halt();
+            }
+        }
+    }
+
+    if exception_number == CONTROL_PROTECTION_EXCEPTION {
+        control_flow_violation(context);
+    }
+    if exception_number == DOUBLE_FAULT_EXCEPTION {
+        double_fault(context);
+    }
+    if exception_number == PAGE_FAULT_EXCEPTION
+        && context.error_code & PAGE_FAULT_PROTECTION_VIOLATION != 0
+    {
+        page_protection_violation(context, page_fault_address);
+    }
     panic!(
         "EXCEPTION: {:#x}\n\
             Page Fault Address: {:#x}\n\
-            Stack Pointer: {:#x}",
-        exception_number, page_fault_address, stack_pointer
+            Stack Pointer: {:#x}\n\
+            Instruction Pointer: {:#x}\n\
+            Frames:{}",
+        exception_number,
+        page_fault_address,
+        stack_pointer,
+        context.rip,
+        CallerFrames(context.rbp)
+    );
+}
+
+/// A NUL terminated message written without allocating, for exceptions
+/// that may be raised because the heap or the stack ran out. What doesn't
+/// fit in the buffer is left out.
+struct FixedMessage<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> FixedMessage<N> {
+    fn new() -> Self {
+        Self {
+            bytes: [0; N],
+            len: 0,
+        }
+    }
+
+    fn as_ptr(&self) -> *const c_char {
+        self.bytes.as_ptr() as *const c_char
+    }
+}
+
+impl<const N: usize> Write for FixedMessage<N> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        // keep the last byte for the NUL terminator
+        let count = s.len().min(N - 1 - self.len);
+        self.bytes[self.len..self.len + count].copy_from_slice(&s.as_bytes()[..count]);
+        self.len += count;
+        Ok(())
+    }
+}
+
+/// Abort the guest with `ErrorCode::DoubleFault`. The instruction pointer
+/// of a double fault is undefined, so the stack pointer it was raised with
+/// is reported instead, which is usually what caused it. The handler runs
+/// on its own small stack, and the heap may be what ran out, so the
+/// message is written to a buffer on the stack.
+fn double_fault(context: &ExceptionContext) -> ! {
+    let mut message = FixedMessage::<256>::new();
+    let _ = write!(
+        message,
+        "Double fault\n\
+            Stack Pointer: {:#x}\n\
+            Frames:{}",
+        context.rsp,
+        CallerFrames(context.rbp)
+    );
+    unsafe { abort_with_code_and_message(ErrorCode::DoubleFault as i32, message.as_ptr()) }
+}
+
+/// Abort the guest with `ErrorCode::PageProtectionViolation`, for the host
+/// to report which region the guest wrote to or ran that doesn't allow it
+fn page_protection_violation(context: &ExceptionContext, page_fault_address: u64) -> ! {
+    let message = format!(
+        "Page protection violation\n\
+            Page Fault Address: {:#x}\n\
+            Error Code: {:#x}\n\
+            Instruction Pointer: {:#x}\n\
+            Frames:{}\0",
+        page_fault_address,
+        context.error_code,
+        context.rip,
+        CallerFrames(context.rbp)
+    );
+    unsafe {
+        abort_with_code_and_message(
+            ErrorCode::PageProtectionViolation as i32,
+            message.as_ptr() as *const _,
+        )
+    }
+}
+
+/// Abort the guest with `ErrorCode::ControlFlowViolation`, reporting where
+/// the violation happened the way other exceptions do
+fn control_flow_violation(context: &ExceptionContext) -> ! {
+    let kind = match context.error_code {
+        1 => "near return",
+        2 => "far return",
+        3 => "missing end branch",
+        4 => "restore shadow stack",
+        5 => "shadow stack token",
+        _ => "unknown",
+    };
+    let message = format!(
+        "Control protection exception: {}\n\
+            Instruction Pointer: {:#x}\n\
+            Frames:{}\0",
+        kind,
+        context.rip,
+        CallerFrames(context.rbp)
     );
+    unsafe {
+        abort_with_code_and_message(
+            ErrorCode::ControlFlowViolation as i32,
+            message.as_ptr() as *const _,
+        )
+    }
 }
@@ -0,0 +1,45 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use alloc::string::ToString;
+use core::ptr::addr_of;
+use core::slice::from_raw_parts_mut;
+
+use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+
+use crate::error::{HyperlightGuestError, Result};
+use crate::P_PEB;
+
+/// The region the host mapped readable, writable and executable with
+/// `SandboxConfiguration::set_jit_scratch_size`.
+///
+/// The guest's code is mapped read-only and its data non-executable, so
+/// this is the only memory a guest can write code to and run it from.
+/// Like the rest of guest memory it is restored after every guest
+/// function call.
+pub fn jit_scratch() -> Result<&'static mut [u8]> {
+    let peb_ptr = unsafe { P_PEB.unwrap() };
+    let jit_scratch = unsafe { addr_of!((*peb_ptr).jitScratchData) };
+    let size = unsafe { (*jit_scratch).jitScratchSize };
+    if size == 0 {
+        return Err(HyperlightGuestError::new(
+            ErrorCode::GuestError,
+            "The host did not configure a JIT scratch region".to_string(),
+        ));
+    }
+
+    Ok(unsafe { from_raw_parts_mut((*jit_scratch).jitScratch as *mut u8, size as usize) })
+}
@@ -24,12 +24,14 @@ use buddy_system_allocator::LockedHeap;
 use guest_function_register::GuestFunctionRegister;
 use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
 use hyperlight_common::mem::{HyperlightPEB, RunMode};
+use hyperlight_common::transport::HostCallTransport;
 
 use crate::host_function_call::{outb, OutBAction};
 extern crate alloc;
 
 // Modules
 pub mod entrypoint;
+pub mod executor;
 pub mod shared_input_data;
 pub mod shared_output_data;
 
@@ -41,15 +43,34 @@ pub mod guest_function_register;
 pub mod host_error;
 pub mod host_function_call;
 pub mod host_functions;
+pub mod host_stream;
 
+pub mod deadline;
+pub mod diagnostic;
+pub mod entropy;
+pub mod epoch;
 pub(crate) mod guest_logger;
+#[cfg(feature = "heap_profiler")]
+pub mod heap_profiler;
+pub mod heartbeat;
+pub mod jit_scratch;
+#[cfg(feature = "malloc_trace")]
+pub mod malloc_trace;
 pub mod memory;
+pub(crate) mod payload_compression;
 pub mod print;
+pub mod progress;
+pub mod read_only_data;
+pub mod result_buffer;
+pub mod secrets;
 pub(crate) mod security_check;
 pub mod setjmp;
+pub(crate) mod shadow_stack;
+pub mod sleep;
 
 pub mod chkstk;
 pub mod error;
+pub mod exceptions;
 pub mod gdt;
 pub mod idt;
 pub mod idtr;
@@ -57,6 +78,8 @@ pub mod interrupt_entry;
 pub mod interrupt_handlers;
 pub mod logging;
 
+pub use host_functions::{features, has_feature};
+
 // Unresolved symbols
 ///cbindgen:ignore
 #[no_mangle]
@@ -82,14 +105,32 @@ fn panic(info: &core::panic::PanicInfo) -> ! {
             (*peb_ptr).guestPanicContextData.guestPanicContextDataSize as usize,
         );
     }
-    outb(OutBAction::Abort as u16, ErrorCode::UnknownError as u8);
+    outb(OutBAction::Abort.port(), ErrorCode::UnknownError as u8);
     unsafe { unreachable_unchecked() }
 }
 
+// Both wrap the heap allocator, and the profiler finds allocation sites
+// from the frame of its caller, so they can't wrap each other
+#[cfg(all(feature = "heap_profiler", feature = "malloc_trace"))]
+compile_error!("the heap_profiler and malloc_trace features can't be enabled together");
+
 // Globals
-#[global_allocator]
+#[cfg_attr(
+    not(any(feature = "heap_profiler", feature = "malloc_trace")),
+    global_allocator
+)]
 pub(crate) static HEAP_ALLOCATOR: LockedHeap<32> = LockedHeap::<32>::empty();
 
+#[cfg(feature = "heap_profiler")]
+#[global_allocator]
+static PROFILING_ALLOCATOR: heap_profiler::ProfilingAllocator =
+    heap_profiler::ProfilingAllocator::new(&HEAP_ALLOCATOR);
+
+#[cfg(feature = "malloc_trace")]
+#[global_allocator]
+static TRACING_ALLOCATOR: malloc_trace::TracingAllocator =
+    malloc_trace::TracingAllocator::new(&HEAP_ALLOCATOR);
+
 ///cbindgen:ignore
 #[no_mangle]
 pub(crate) static mut __security_cookie: u64 = 0;
@@ -98,11 +139,16 @@ pub(crate) static mut P_PEB: Option<*mut HyperlightPEB> = None;
 pub static mut MIN_STACK_ADDRESS: u64 = 0;
 
 pub static mut OS_PAGE_SIZE: u32 = 0;
+/// The random seed the host passed to the guest when it was initialised
+pub static mut RANDOM_SEED: u64 = 0;
 pub(crate) static mut OUTB_PTR: Option<extern "win64" fn(u16, u8)> = None;
 pub(crate) static mut OUTB_PTR_WITH_CONTEXT: Option<
     extern "win64" fn(*mut core::ffi::c_void, u16, u8),
 > = None;
 pub static mut RUNNING_MODE: RunMode = RunMode::None;
+/// How the guest signals the host when `RUNNING_MODE` is
+/// `RunMode::Hypervisor`
+pub(crate) static mut HOST_CALL_TRANSPORT: HostCallTransport = HostCallTransport::PortIo;
 
 pub(crate) static mut REGISTERED_GUEST_FUNCTIONS: GuestFunctionRegister =
     GuestFunctionRegister::new();
@@ -16,21 +16,39 @@ limitations under the License.
 
 use alloc::string::ToString;
 use alloc::vec::Vec;
+use core::slice::from_raw_parts_mut;
 
 use hyperlight_common::flatbuffer_wrappers::guest_log_data::GuestLogData;
 use hyperlight_common::flatbuffer_wrappers::guest_log_level::LogLevel;
+use hyperlight_common::log_ring::LogRing;
 
 use crate::host_function_call::{outb, OutBAction};
 use crate::shared_output_data::push_shared_output_data;
+use crate::P_PEB;
 
-fn write_log_data(
+/// The log ring buffer the host configured, if any
+fn guest_log_ring() -> Option<LogRing<'static>> {
+    let peb_ptr = unsafe { P_PEB? };
+    let ring_data = unsafe { &(*peb_ptr).guestLogRingData };
+    if ring_data.guestLogRingSize == 0 {
+        return None;
+    }
+    LogRing::new(unsafe {
+        from_raw_parts_mut(
+            ring_data.guestLogRing as *mut u8,
+            ring_data.guestLogRingSize as usize,
+        )
+    })
+}
+
+fn serialize_log_data(
     log_level: LogLevel,
     message: &str,
     source: &str,
     caller: &str,
     source_file: &str,
     line: u32,
-) {
+) -> Vec<u8> {
     let guest_log_data = GuestLogData::new(
         message.to_string(),
         source.to_string(),
@@ -40,11 +58,9 @@ fn write_log_data(
         line,
     );
 
-    let bytes: Vec<u8> = guest_log_data
+    guest_log_data
         .try_into()
-        .expect("Failed to convert GuestLogData to bytes");
-
-    push_shared_output_data(bytes).expect("Unable to push log data to shared output data");
+        .expect("Failed to convert GuestLogData to bytes")
 }
 
 pub fn log_message(
@@ -55,6 +71,16 @@ pub fn log_message(
     source_file: &str,
     line: u32,
 ) {
-    write_log_data(log_level, message, source, caller, source_file, line);
-    outb(OutBAction::Log as u16, 0);
+    let bytes = serialize_log_data(log_level, message, source, caller, source_file, line);
+    match guest_log_ring() {
+        // the host forwards the record the next time the guest exits to
+        // it, or counts it as dropped if the ring is full
+        Some(mut ring) => {
+            ring.push(&bytes);
+        }
+        None => {
+            push_shared_output_data(bytes).expect("Unable to push log data to shared output data");
+            outb(OutBAction::Log.port(), 0);
+        }
+    }
 }
@@ -0,0 +1,169 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! A guest allocator that logs every allocation and free made during a
+//! guest function call, and sends the log to the host at the end of the
+//! call, for the host to find the allocations the call leaked with
+//! `MultiUseSandbox::last_malloc_trace`.
+//!
+//! The log holds up to `MAX_EVENTS` events. The events of a call that
+//! makes more are dropped, and the host is told how many, so that it
+//! doesn't report the allocations whose frees were dropped as leaks.
+//!
+//! The allocation holding the call's result, or its error message, is
+//! still live when the log is sent, since it is written to the output data
+//! buffer afterwards, so it is left out of the log.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::alloc::{GlobalAlloc, Layout};
+
+use buddy_system_allocator::LockedHeap;
+use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterValue, ReturnType};
+use spin::Mutex;
+
+use crate::error::Result;
+use crate::host_function_call::{call_host_function, get_host_return_value};
+
+/// The most allocations and frees logged during a guest function call
+pub const MAX_EVENTS: usize = 2048;
+/// The most events sent to the host in one host function call, so that
+/// they fit in the smallest output data buffer
+const EVENTS_PER_CHUNK: usize = 256;
+/// The bit set in the size of the events that are frees
+const FREE_BIT: u64 = 1 << 63;
+
+#[derive(Clone, Copy)]
+struct Event {
+    address: u64,
+    /// The size of the allocation, with `FREE_BIT` set for frees
+    size: u64,
+}
+
+struct Events {
+    events: [Event; MAX_EVENTS],
+    len: usize,
+    dropped: u64,
+}
+
+impl Events {
+    fn push(&mut self, address: u64, size: u64) {
+        if self.len < MAX_EVENTS {
+            self.events[self.len] = Event { address, size };
+            self.len += 1;
+        } else {
+            self.dropped += 1;
+        }
+    }
+}
+
+static EVENTS: Mutex<Events> = Mutex::new(Events {
+    events: [Event {
+        address: 0,
+        size: 0,
+    }; MAX_EVENTS],
+    len: 0,
+    dropped: 0,
+});
+
+/// A `GlobalAlloc` that logs every allocation and free, and makes the
+/// allocations from the guest's heap
+pub(crate) struct TracingAllocator {
+    heap: &'static LockedHeap<32>,
+}
+
+impl TracingAllocator {
+    pub(crate) const fn new(heap: &'static LockedHeap<32>) -> Self {
+        Self { heap }
+    }
+}
+
+unsafe impl GlobalAlloc for TracingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.heap.alloc(layout);
+        if !ptr.is_null() {
+            EVENTS.lock().push(ptr as u64, layout.size() as u64);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        EVENTS
+            .lock()
+            .push(ptr as u64, layout.size() as u64 | FREE_BIT);
+        self.heap.dealloc(ptr, layout);
+    }
+}
+
+/// Forget the events logged so far, at the start of a guest function call
+pub(crate) fn reset_malloc_trace() {
+    let mut events = EVENTS.lock();
+    events.len = 0;
+    events.dropped = 0;
+}
+
+/// Send the events logged during the guest function call to the host, 16
+/// bytes per event: the address, and the size with the top bit set for
+/// frees, as little-endian `u64`s. The events are sent in chunks with the
+/// index of the chunk and the number of events that were dropped. The
+/// allocation of the call's result or error message, at `result`, is left
+/// out.
+pub(crate) fn send_malloc_trace(result: *const u8) -> Result<()> {
+    // Copy the events first, so that sending them, which allocates,
+    // doesn't log more of them or take the lock while it is held. The
+    // copy is allocated before the lock is taken, and the event its
+    // allocation logs isn't copied.
+    let len = EVENTS.lock().len;
+    let mut logged: Vec<Event> = Vec::with_capacity(len);
+    let dropped = {
+        let events = EVENTS.lock();
+        logged.extend_from_slice(&events.events[..len]);
+        events.dropped
+    };
+    // The result is live, so the last event at its address is its
+    // allocation
+    if let Some(index) = logged
+        .iter()
+        .rposition(|event| event.address == result as u64)
+        .filter(|&i| logged[i].size & FREE_BIT == 0)
+    {
+        logged.remove(index);
+    }
+    // An empty first chunk is sent for a call that logged no events, so
+    // that the host replaces the previous call's trace
+    let mut chunks: Vec<&[Event]> = logged.chunks(EVENTS_PER_CHUNK).collect();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        let bytes: Vec<u8> = chunk
+            .iter()
+            .flat_map(|event| [event.address, event.size])
+            .flat_map(u64::to_le_bytes)
+            .collect();
+        call_host_function(
+            "HostRecordMallocTrace",
+            Some(vec![
+                ParameterValue::ULong(index as u64),
+                ParameterValue::ULong(dropped),
+                ParameterValue::VecBytes(bytes),
+            ]),
+            ReturnType::Void,
+        )?;
+        get_host_return_value::<()>()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,88 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Compression of the guest function calls the host sends and the results
+//! the guest returns, see `hyperlight_common::payload_compression`.
+//!
+//! Only guests built with the `payload_compression` feature accept the
+//! algorithm the host asks for. The host doesn't compress the calls it
+//! sends to other guests, so they fail the calls that are compressed
+//! anyway.
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+use hyperlight_common::payload_compression::is_compressed;
+#[cfg(feature = "payload_compression")]
+use hyperlight_common::payload_compression::{
+    compress, decompress, max_uncompressed_size, PayloadCompression,
+};
+
+use crate::error::{HyperlightGuestError, Result};
+#[cfg(feature = "payload_compression")]
+use crate::shared_output_data::payload_limits;
+#[cfg(feature = "payload_compression")]
+use crate::P_PEB;
+
+/// Accept the algorithm the host asked for, when the guest is initialised
+pub(crate) fn accept_payload_compression() {
+    #[cfg(feature = "payload_compression")]
+    unsafe {
+        let data = &mut (*P_PEB.unwrap()).payload_compression;
+        if PayloadCompression::from_u64(data.requested).is_some() {
+            data.accepted = data.requested;
+        }
+    }
+}
+
+/// Decompress the payload `buffer` popped off the input data buffer, if
+/// the host compressed it
+pub(crate) fn decompress_input(buffer: &[u8]) -> Result<Option<Vec<u8>>> {
+    if !is_compressed(buffer) {
+        return Ok(None);
+    }
+    #[cfg(feature = "payload_compression")]
+    {
+        decompress(buffer, max_uncompressed_size(&payload_limits()))
+            .map(Some)
+            .map_err(|e| HyperlightGuestError::new(ErrorCode::GuestError, e.to_string()))
+    }
+    #[cfg(not(feature = "payload_compression"))]
+    Err(HyperlightGuestError::new(
+        ErrorCode::GuestError,
+        "Got a compressed payload, but the guest was built without the payload_compression feature"
+            .to_string(),
+    ))
+}
+
+/// Compress the serialized result of a guest function call, if the guest
+/// accepted payload compression and the result is large enough. Fails if
+/// the uncompressed result is larger than can be decompressed.
+pub(crate) fn compress_output(data: Vec<u8>) -> Result<Vec<u8>> {
+    #[cfg(feature = "payload_compression")]
+    {
+        let settings = unsafe { (*P_PEB.unwrap()).payload_compression };
+        if data.len() as u64 >= settings.threshold {
+            if let Some(compressed) = compress(settings.negotiated(), &data) {
+                let limits = payload_limits();
+                limits.check_payload_size(data.len(), max_uncompressed_size(&limits))?;
+                return Ok(compressed);
+            }
+        }
+    }
+    Ok(data)
+}
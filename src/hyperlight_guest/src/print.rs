@@ -17,11 +17,13 @@ limitations under the License.
 use alloc::string::String;
 use alloc::vec::Vec;
 use core::ffi::{c_char, CStr};
+use core::fmt::{self, Write};
 use core::mem;
 
 use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterValue, ReturnType};
 
-use crate::host_function_call::call_host_function;
+use crate::error::Result;
+use crate::host_function_call::{call_host_function, get_host_return_value, outb, OutBAction};
 
 const BUFFER_SIZE: usize = 1000;
 
@@ -66,3 +68,99 @@ pub unsafe extern "C" fn _putchar(c: c_char) {
         MESSAGE_BUFFER.clear();
     }
 }
+
+/// Write `message` to the host's debug channel, which the host logs line
+/// by line at debug level. Unlike printing with `HostPrint`, this calls no
+/// host function, so it works before the guest is initialised and when
+/// its input and output buffers are corrupt, but every byte exits to the
+/// host.
+pub fn debug_print(message: &str) {
+    let port = OutBAction::Debug.port();
+    for byte in message.bytes() {
+        outb(port, byte);
+    }
+}
+
+/// A `core::fmt::Write` adapter that buffers what is written to it and
+/// sends it to the host's `HostPrint` function when the buffer fills up,
+/// when it is flushed, and when it is dropped.
+#[derive(Default)]
+pub struct HostPrintWriter {
+    buffer: String,
+}
+
+impl HostPrintWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send everything buffered so far to the host
+    pub fn flush(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let message = mem::take(&mut self.buffer);
+        call_host_function(
+            "HostPrint",
+            Some(Vec::from(&[ParameterValue::String(message)])),
+            ReturnType::Int,
+        )?;
+        get_host_return_value::<i32>()?;
+        Ok(())
+    }
+}
+
+impl Write for HostPrintWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.buffer.push_str(s);
+        if self.buffer.len() >= BUFFER_SIZE {
+            self.flush().map_err(|_| fmt::Error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for HostPrintWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let mut writer = HostPrintWriter::new();
+    writer
+        .write_fmt(args)
+        .and_then(|_| writer.flush().map_err(|_| fmt::Error))
+        .expect("Failed to call HostPrint");
+}
+
+/// Print to the host's standard output, like `print!`
+#[macro_export]
+macro_rules! hl_print {
+    ($($arg:tt)*) => {
+        $crate::print::_print(format_args!($($arg)*))
+    };
+}
+
+/// Print to the host's standard output followed by a newline, like
+/// `println!`
+#[macro_export]
+macro_rules! hl_println {
+    () => {
+        $crate::hl_print!("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::print::_print(format_args!("{}\n", format_args!($($arg)*)))
+    };
+}
+
+/// Print to the host followed by a newline, like `eprintln!`. The guest
+/// has a single output channel, so this prints to the host's standard
+/// output like `hl_println!`.
+#[macro_export]
+macro_rules! hl_eprintln {
+    ($($arg:tt)*) => {
+        $crate::hl_println!($($arg)*)
+    };
+}
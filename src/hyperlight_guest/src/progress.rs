@@ -0,0 +1,64 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterValue, ReturnType};
+
+use crate::error::Result;
+use crate::host_function_call::{call_host_function, get_host_return_value};
+
+/// The percentage the host asked to next be told about. Reset at the start
+/// of every guest function call, since guest memory isn't restored between
+/// calls made through a call context.
+static mut NEXT_REPORTED_PERCENT: u32 = 0;
+
+/// Start throttling progress reports again for a new guest function call
+pub(crate) fn reset_progress() {
+    unsafe {
+        NEXT_REPORTED_PERCENT = 0;
+    }
+}
+
+/// Report the progress of the current guest function call to the host,
+/// which passes it to the handlers subscribed with
+/// `MultiUseSandbox::subscribe_progress`.
+///
+/// `percent` is how much of its work the call has done, from 0 to 100.
+/// Reports are throttled: after each report the host tells the guest how
+/// much further it must get before reporting again, and reports before
+/// then return without exiting to the host, so this can be called often.
+/// Each report that reaches the host also counts as a heartbeat.
+pub fn hl_report_progress(percent: u8, message: &str) -> Result<()> {
+    let percent = u32::from(percent.min(100));
+    if percent < unsafe { NEXT_REPORTED_PERCENT } {
+        return Ok(());
+    }
+    call_host_function(
+        "HostReportProgress",
+        Some(Vec::from(&[
+            ParameterValue::UInt(percent),
+            ParameterValue::String(message.to_string()),
+        ])),
+        ReturnType::UInt,
+    )?;
+    let next = get_host_return_value::<u32>()?;
+    unsafe {
+        NEXT_REPORTED_PERCENT = next;
+    }
+    Ok(())
+}
@@ -0,0 +1,45 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use alloc::string::ToString;
+use core::ptr::{addr_of, read_volatile};
+use core::slice::from_raw_parts;
+
+use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+
+use crate::error::{HyperlightGuestError, Result};
+use crate::P_PEB;
+
+/// The data the host last published to the read-only data region with
+/// `MultiUseSandbox::publish_read_only_data`.
+///
+/// The guest can't write to the region. The host only publishes between
+/// guest function calls, so the data doesn't change during a call, but
+/// the slice must not be kept from one call to the next.
+pub fn read_only_data() -> Result<&'static [u8]> {
+    let peb_ptr = unsafe { P_PEB.unwrap() };
+    let read_only_data = unsafe { addr_of!((*peb_ptr).readOnlyData) };
+    if unsafe { (*read_only_data).readOnlyDataSize } == 0 {
+        return Err(HyperlightGuestError::new(
+            ErrorCode::GuestError,
+            "The host did not configure a read-only data region".to_string(),
+        ));
+    }
+
+    // the host changes the length between calls, so always read it again
+    let length = unsafe { read_volatile(addr_of!((*read_only_data).readOnlyDataLength)) };
+    Ok(unsafe { from_raw_parts((*read_only_data).readOnlyData as *const u8, length as usize) })
+}
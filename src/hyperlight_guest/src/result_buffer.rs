@@ -0,0 +1,90 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::slice::from_raw_parts_mut;
+
+use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+use hyperlight_common::flatbuffer_wrappers::util::get_flatbuffer_result;
+
+use crate::error::{HyperlightGuestError, Result};
+use crate::P_PEB;
+
+/// Call `f` with the result buffer the host configured for this sandbox,
+/// and return the length of the result `f` wrote into it, serialized as
+/// the return value of a guest function.
+///
+/// `f` must return the number of bytes it wrote to the start of the
+/// buffer. A guest function returning the value of this function is
+/// called from the host with
+/// `MultiUseSandbox::call_guest_function_with_result_buffer`, which reads
+/// the result in place rather than it being copied through the output
+/// data buffer.
+pub fn with_result_buffer<F>(f: F) -> Result<Vec<u8>>
+where
+    F: FnOnce(&mut [u8]) -> Result<usize>,
+{
+    let peb_ptr = unsafe { P_PEB.unwrap() };
+    let result_buffer_size = unsafe { (*peb_ptr).resultBufferData.resultBufferSize as usize };
+    if result_buffer_size == 0 {
+        return Err(HyperlightGuestError::new(
+            ErrorCode::GuestError,
+            "The host did not configure a result buffer".to_string(),
+        ));
+    }
+
+    let result_buffer = unsafe {
+        from_raw_parts_mut(
+            (*peb_ptr).resultBufferData.resultBuffer as *mut u8,
+            result_buffer_size,
+        )
+    };
+    let len = f(result_buffer)?;
+    if len > result_buffer_size {
+        return Err(HyperlightGuestError::new(
+            ErrorCode::GuestError,
+            format!(
+                "Result of {} bytes does not fit in the {} byte result buffer",
+                len, result_buffer_size
+            ),
+        ));
+    }
+
+    Ok(get_flatbuffer_result(len as u64))
+}
+
+/// Copy `data` into the result buffer, see `with_result_buffer`.
+pub fn write_result_buffer(data: &[u8]) -> Result<Vec<u8>> {
+    with_result_buffer(|result_buffer| {
+        let result_buffer_size = result_buffer.len();
+        result_buffer
+            .get_mut(..data.len())
+            .ok_or_else(|| {
+                HyperlightGuestError::new(
+                    ErrorCode::GuestError,
+                    format!(
+                        "Result of {} bytes does not fit in the {} byte result buffer",
+                        data.len(),
+                        result_buffer_size
+                    ),
+                )
+            })?
+            .copy_from_slice(data);
+        Ok(data.len())
+    })
+}
@@ -0,0 +1,59 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::slice::from_raw_parts_mut;
+
+use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+use hyperlight_common::secrets::Secrets;
+
+use crate::error::{HyperlightGuestError, Result};
+use crate::P_PEB;
+
+/// Take the secret called `name` the host added with
+/// `UninitializedSandbox::add_secret`.
+///
+/// The secrets are only in guest memory while the guest runs, in
+/// `hyperlight_main` or a guest function call, so a secret the guest needs
+/// later must be taken again rather than kept in guest memory. If the host
+/// added the secret to be wiped after it is read, it is zeroed after the
+/// first time it is taken, and taking it again fails, in this call and any
+/// later one.
+pub fn hl_take_secret(name: &str) -> Result<Vec<u8>> {
+    let peb_ptr = unsafe { P_PEB.unwrap() };
+    let secrets_data = unsafe { &(*peb_ptr).secretsData };
+    if secrets_data.secretsSize == 0 {
+        return Err(HyperlightGuestError::new(
+            ErrorCode::GuestError,
+            "The host did not configure a secrets region".to_string(),
+        ));
+    }
+
+    let mut secrets = Secrets::new(unsafe {
+        from_raw_parts_mut(
+            secrets_data.secrets as *mut u8,
+            secrets_data.secretsSize as usize,
+        )
+    });
+    secrets.take(name).ok_or_else(|| {
+        HyperlightGuestError::new(
+            ErrorCode::GuestError,
+            format!("There is no secret called {} left to take", name),
+        )
+    })
+}
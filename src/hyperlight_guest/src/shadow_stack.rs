@@ -0,0 +1,102 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! The CET shadow stack the guest runs with when the host configures one.
+//!
+//! Once the shadow stack is enabled, every call also pushes its return
+//! address to the shadow stack, and every return checks the address it
+//! returns to against it, raising a control protection exception if they
+//! differ. So neither function that switches to the shadow stack, or
+//! resets it, may return to its caller, and both are always inlined.
+
+use core::arch::asm;
+
+const MSR_IA32_S_CET: u32 = 0x6a2;
+const MSR_IA32_PL0_SSP: u32 = 0x6a4;
+/// The IA32_S_CET bit enabling supervisor shadow stacks
+const S_CET_SH_STK_EN: u32 = 1;
+const CR4_CET: u64 = 1 << 23;
+
+/// The shadow stack pointer when the shadow stack is empty, or 0 if the
+/// guest runs without a shadow stack
+static mut SHADOW_STACK_TOP: u64 = 0;
+
+/// Switch to the shadow stack whose supervisor shadow stack token is at
+/// `token`, unless it is 0 or the guest already switched to it. Must only
+/// be called from a function that never returns.
+#[inline(always)]
+pub(crate) unsafe fn enable(token: u64) {
+    if token == 0 || SHADOW_STACK_TOP != 0 {
+        return;
+    }
+    asm!(
+        "wrmsr",
+        in("ecx") MSR_IA32_PL0_SSP,
+        in("eax") token as u32,
+        in("edx") (token >> 32) as u32,
+        options(nostack, preserves_flags),
+    );
+    asm!(
+        "mov {cr4}, cr4",
+        "or {cr4}, {cet}",
+        "mov cr4, {cr4}",
+        cr4 = out(reg) _,
+        cet = in(reg) CR4_CET,
+        options(nostack),
+    );
+    asm!(
+        "wrmsr",
+        in("ecx") MSR_IA32_S_CET,
+        in("eax") S_CET_SH_STK_EN,
+        in("edx") 0u32,
+        options(nostack, preserves_flags),
+    );
+    // marks the token busy and points the shadow stack pointer at it
+    asm!("setssbsy", options(nostack));
+    SHADOW_STACK_TOP = token;
+}
+
+/// Empty the shadow stack. The host starts every guest function call at
+/// the dispatch function without returning from where the guest halted,
+/// so the return addresses of the previous call are still on the shadow
+/// stack. Must only be called from a function that never returns.
+#[inline(always)]
+pub(crate) unsafe fn reset() {
+    if SHADOW_STACK_TOP == 0 {
+        return;
+    }
+    // incssp pops at most 255 return addresses at a time
+    asm!(
+        "rdsspq {ssp}",
+        "2:",
+        "mov {count}, {top}",
+        "sub {count}, {ssp}",
+        "shr {count}, 3",
+        "jz 3f",
+        "cmp {count}, 255",
+        "jbe 4f",
+        "mov {count}, 255",
+        "4:",
+        "incsspq {count}",
+        "rdsspq {ssp}",
+        "jmp 2b",
+        "3:",
+        top = in(reg) SHADOW_STACK_TOP,
+        ssp = out(reg) _,
+        count = out(reg) _,
+        options(nostack),
+    );
+}
@@ -22,6 +22,7 @@ use core::slice::from_raw_parts_mut;
 use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
 
 use crate::error::{HyperlightGuestError, Result};
+use crate::payload_compression::decompress_input;
 use crate::P_PEB;
 
 // Pops the top element from the shared input data buffer and returns it as a T
@@ -67,7 +68,21 @@ where
             .expect("Invalid stack pointer in pop_shared_input_data_into"),
     );
 
-    let buffer = &idb[last_element_offset_rel..];
+    // the element has to be between the stack pointer at the start of the buffer
+    // and the offset to it that we just read
+    if last_element_offset_rel < 8 || last_element_offset_rel > stack_ptr_rel - 8 {
+        return Err(HyperlightGuestError::new(
+            ErrorCode::GuestError,
+            format!(
+                "Invalid element offset: {} in pop_shared_input_data_into",
+                last_element_offset_rel
+            ),
+        ));
+    }
+
+    let buffer = &idb[last_element_offset_rel..stack_ptr_rel - 8];
+    let decompressed = decompress_input(buffer)?;
+    let buffer = decompressed.as_deref().unwrap_or(buffer);
 
     // convert the buffer to T
     let type_t = match T::try_from(buffer) {
@@ -20,10 +20,25 @@ use alloc::vec::Vec;
 use core::slice::from_raw_parts_mut;
 
 use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+use hyperlight_common::flatbuffer_wrappers::payload_limits::PayloadLimits;
 
 use crate::error::{HyperlightGuestError, Result};
 use crate::P_PEB;
 
+/// The limits the host set on the size of the function calls and return
+/// values the guest sends to it
+pub(crate) fn payload_limits() -> PayloadLimits {
+    unsafe { (*P_PEB.unwrap()).payload_limits }
+}
+
+/// Check a serialized function call or return value against the maximum
+/// payload size before it is pushed to the shared output buffer
+pub(crate) fn check_output_payload_size(data: &[u8]) -> Result<()> {
+    let shared_buffer_size = unsafe { (*P_PEB.unwrap()).outputdata.outputDataSize as usize };
+    payload_limits().check_payload_size(data.len(), shared_buffer_size)?;
+    Ok(())
+}
+
 pub fn push_shared_output_data(data: Vec<u8>) -> Result<()> {
     let peb_ptr = unsafe { P_PEB.unwrap() };
     let shared_buffer_size = unsafe { (*peb_ptr).outputdata.outputDataSize as usize };
@@ -0,0 +1,43 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use alloc::vec;
+
+use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterValue, ReturnType};
+
+use crate::error::Result;
+use crate::host_function_call::{call_host_function, get_host_return_value};
+
+/// Suspend the guest for at least `ms` milliseconds.
+///
+/// Unlike a busy loop, the vCPU does not run while the guest is
+/// suspended, the host sleeps in the `HostSleep` host function and then
+/// resumes the guest. The time spent sleeping counts towards the maximum
+/// execution time of the guest function call.
+pub fn hl_sleep(ms: u64) -> Result<()> {
+    call_host_function(
+        "HostSleep",
+        Some(vec![ParameterValue::ULong(ms)]),
+        ReturnType::Void,
+    )?;
+    get_host_return_value::<()>()
+}
+
+/// Give up the rest of the guest's time slice, letting the host run other
+/// work before the guest is resumed.
+pub fn hl_yield() -> Result<()> {
+    hl_sleep(0)
+}
@@ -0,0 +1,155 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! `cargo hyperlight-guest`, which builds hyperlight guests with the
+//! settings they need, checks the binaries it builds and writes their
+//! symbol maps next to them.
+
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command, Stdio};
+
+use anyhow::{Context, Result};
+use hyperlight_guest_build::{
+    cargo_config_args, check_guest_binary, read_metadata, symbol_map, symbol_map_path, GUEST_TARGET,
+};
+
+const USAGE: &str = "\
+Usage:
+    cargo hyperlight-guest build [--out-dir <dir>] [cargo build arguments...]
+    cargo hyperlight-guest metadata <binary>";
+
+fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    // cargo passes the name of the subcommand as the first argument
+    if args.first().map(String::as_str) == Some("hyperlight-guest") {
+        args.remove(0);
+    }
+    let res = match args.first().map(String::as_str) {
+        Some("build") => build(&args[1..]),
+        Some("metadata") if args.len() == 2 => print_metadata(Path::new(&args[1])),
+        _ => {
+            eprintln!("{}", USAGE);
+            exit(2);
+        }
+    };
+    if let Err(e) = res {
+        eprintln!("error: {:#}", e);
+        exit(1);
+    }
+}
+
+/// Build the guest in the current directory, then check every guest binary
+/// built, write its symbol map next to it and, if `--out-dir` is given,
+/// copy both
+fn build(args: &[String]) -> Result<()> {
+    let mut out_dir = None;
+    let mut cargo_args = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--out-dir" => {
+                let dir = args.next().context("--out-dir needs a directory")?;
+                out_dir = Some(PathBuf::from(dir));
+            }
+            _ => cargo_args.push(arg.clone()),
+        }
+    }
+    let has_target = cargo_args
+        .iter()
+        .any(|arg| arg == "--target" || arg.starts_with("--target="));
+
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo"));
+    let mut command = Command::new(cargo);
+    command
+        .arg("build")
+        .args(cargo_config_args())
+        .arg("--message-format=json-render-diagnostics");
+    if !has_target {
+        command.args(["--target", GUEST_TARGET]);
+    }
+    let mut child = command
+        .args(&cargo_args)
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("could not run cargo")?;
+
+    let mut binaries = Vec::new();
+    let stdout = child
+        .stdout
+        .take()
+        .context("could not read cargo's output")?;
+    for line in BufReader::new(stdout).lines() {
+        let message: serde_json::Value = match serde_json::from_str(&line?) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+        if message["reason"] == "compiler-artifact" {
+            if let Some(executable) = message["executable"].as_str() {
+                binaries.push(PathBuf::from(executable));
+            }
+        }
+    }
+    let status = child.wait().context("could not wait for cargo")?;
+    if !status.success() {
+        exit(status.code().unwrap_or(1));
+    }
+
+    for binary in binaries {
+        print_metadata(&binary)?;
+        let symbols = write_symbol_map(&binary)?;
+        if let Some(out_dir) = &out_dir {
+            std::fs::create_dir_all(out_dir)
+                .with_context(|| format!("could not create {}", out_dir.display()))?;
+            for path in [binary, symbols] {
+                let name = path.file_name().context("guest binary has no file name")?;
+                std::fs::copy(&path, out_dir.join(name))
+                    .with_context(|| format!("could not copy {}", path.display()))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Write the symbol map of the guest binary at `binary` next to it,
+/// returning the path it was written to
+fn write_symbol_map(binary: &Path) -> Result<PathBuf> {
+    let bytes =
+        std::fs::read(binary).with_context(|| format!("could not read {}", binary.display()))?;
+    let map = symbol_map(&bytes)?;
+    let path = symbol_map_path(binary);
+    std::fs::write(&path, map.to_bytes())
+        .with_context(|| format!("could not write {}", path.display()))?;
+    println!("    {} symbols: {}", map.symbols().len(), path.display());
+    Ok(path)
+}
+
+/// Check the guest binary at `path` and print its metadata
+fn print_metadata(path: &Path) -> Result<()> {
+    let binary =
+        std::fs::read(path).with_context(|| format!("could not read {}", path.display()))?;
+    check_guest_binary(&binary).with_context(|| format!("{} is not a guest", path.display()))?;
+    let metadata = read_metadata(&binary)?;
+    println!("{}", path.display());
+    if metadata.entries().is_empty() {
+        println!("    no metadata, see `hyperlight_guest_build::GuestBuild`");
+    }
+    for (key, value) in metadata.entries() {
+        println!("    {}: {}", key, value);
+    }
+    Ok(())
+}
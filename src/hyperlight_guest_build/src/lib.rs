@@ -0,0 +1,417 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Build support for hyperlight guest binaries.
+//!
+//! `GuestBuild` is called from a guest's build script to pass the linker
+//! arguments the guest needs and to generate the metadata embedded in the
+//! guest binary. The `cargo hyperlight-guest` subcommand, built from this
+//! crate, builds guests for the right target with the right settings and
+//! checks the binaries it builds, and writes the symbol map of every
+//! guest binary next to it, see `symbol_map`.
+
+use std::env;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use goblin::elf::program_header::PT_LOAD;
+use goblin::elf::sym::{STT_FUNC, STT_OBJECT};
+use goblin::Object;
+pub use hyperlight_common::guest_metadata::METADATA_SECTION;
+use hyperlight_common::guest_metadata::{
+    function_timeout_key, GuestMetadata, ABI_VERSION, ABI_VERSION_KEY, HEAP_SIZE_KEY,
+    STACK_SIZE_KEY,
+};
+use hyperlight_common::symbol_map::{Symbol, SymbolMap, SYMBOL_MAP_EXTENSION};
+
+/// The target Linux and KVM/MSHV guests are built for
+pub const GUEST_TARGET: &str = "x86_64-unknown-none";
+/// The target Windows guests are built for
+pub const WINDOWS_GUEST_TARGET: &str = "x86_64-pc-windows-msvc";
+/// The symbol the host enters the guest at
+pub const ENTRYPOINT: &str = "entrypoint";
+/// The file `GuestBuild::run` generates in `OUT_DIR`
+pub const METADATA_FILE: &str = "hyperlight_guest_metadata.rs";
+
+/// The linker arguments Windows guests are linked with
+const WINDOWS_LINK_ARGS: &[&str] = &[
+    "/RELEASE",
+    "/DEBUG",
+    "/NOLOGO",
+    "/NXCOMPAT",
+    "/SAFESEH:NO",
+    "/ENTRY:entrypoint",
+    "/SUBSYSTEM:NATIVE",
+    "/ALIGN:4096",
+    "/FILEALIGN:4096",
+    "/NODEFAULTLIB",
+    "/HEAP:131072,131072",
+    "/DYNAMICBASE",
+    "/STACK:65536,65536",
+    "/MACHINE:X64",
+];
+
+/// The linker arguments a guest built for `target` needs, or `None` if
+/// guests can't be built for `target`
+pub fn link_args(target: &str) -> Option<Vec<String>> {
+    match target {
+        GUEST_TARGET => Some(vec!["-e".to_string(), ENTRYPOINT.to_string()]),
+        WINDOWS_GUEST_TARGET => Some(WINDOWS_LINK_ARGS.iter().map(|a| a.to_string()).collect()),
+        _ => None,
+    }
+}
+
+/// The `--config` arguments `cargo build` needs to build guests: the
+/// linker, code model and linker arguments for every guest target, aborting
+/// on panic, and keeping the guest's symbols in release builds.
+pub fn cargo_config_args() -> Vec<String> {
+    let configs = [
+        format!("target.{}.linker=\"rust-lld\"", GUEST_TARGET),
+        format!(
+            "target.{}.rustflags=[\"-C\", \"code-model=small\", \"-C\", \"link-args=-e {}\"]",
+            GUEST_TARGET, ENTRYPOINT
+        ),
+        format!("target.{}.linker=\"rust-lld\"", WINDOWS_GUEST_TARGET),
+        format!(
+            "target.{}.rustflags=[\"-C\", \"link-args={}\"]",
+            WINDOWS_GUEST_TARGET,
+            WINDOWS_LINK_ARGS.join(" ")
+        ),
+        "profile.dev.panic=\"abort\"".to_string(),
+        "profile.release.panic=\"abort\"".to_string(),
+        "profile.release.strip=\"none\"".to_string(),
+    ];
+    configs
+        .into_iter()
+        .flat_map(|config| ["--config".to_string(), config])
+        .collect()
+}
+
+/// Configures a guest's build from its build script
+#[derive(Clone, Debug)]
+pub struct GuestBuild {
+    metadata: Vec<(String, String)>,
+}
+
+impl Default for GuestBuild {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GuestBuild {
+    /// A build whose metadata is the name and version of the guest's
+    /// package, the version of this crate and the version of the
+    /// host-guest interface the guest is built against
+    pub fn new() -> Self {
+        let package = |var: &str| env::var(var).unwrap_or_default();
+        Self {
+            metadata: vec![
+                ("name".to_string(), package("CARGO_PKG_NAME")),
+                ("version".to_string(), package("CARGO_PKG_VERSION")),
+                (
+                    "hyperlight-guest-build".to_string(),
+                    env!("CARGO_PKG_VERSION").to_string(),
+                ),
+                (ABI_VERSION_KEY.to_string(), ABI_VERSION.to_string()),
+            ],
+        }
+    }
+
+    /// Add `key` with `value` to the guest's metadata, replacing any value
+    /// `key` already has
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let (key, value) = (key.into(), value.into());
+        self.metadata.retain(|(k, _)| *k != key);
+        self.metadata.push((key, value));
+        self
+    }
+
+    /// Declare the default timeout of calls to the guest function
+    /// `function_name`. The host stops calls that run for longer unless it
+    /// overrides the timeout, see
+    /// `MultiUseSandbox::set_function_timeout`.
+    pub fn function_timeout(self, function_name: &str, timeout: Duration) -> Self {
+        let millis = timeout.as_millis().to_string();
+        self.metadata(function_timeout_key(function_name), millis)
+    }
+
+    /// Declare the heap size in bytes the guest expects, which the host
+    /// uses unless its sandbox configuration sets one
+    pub fn heap_size(self, bytes: u64) -> Self {
+        self.metadata(HEAP_SIZE_KEY, bytes.to_string())
+    }
+
+    /// Declare the stack size in bytes the guest expects, which the host
+    /// uses unless its sandbox configuration sets one
+    pub fn stack_size(self, bytes: u64) -> Self {
+        self.metadata(STACK_SIZE_KEY, bytes.to_string())
+    }
+
+    /// Pass the linker arguments the guest needs to cargo, and write the
+    /// guest's metadata to `METADATA_FILE` in `OUT_DIR`. Panics if the
+    /// metadata can't be written, as build scripts do.
+    pub fn run(self) {
+        let target = env::var("TARGET").unwrap_or_default();
+        match link_args(&target) {
+            Some(args) => {
+                for arg in args {
+                    println!("cargo:rustc-link-arg={}", arg);
+                }
+            }
+            None => println!(
+                "cargo:warning=hyperlight guests are built for {} or {}, not {}",
+                GUEST_TARGET, WINDOWS_GUEST_TARGET, target
+            ),
+        }
+
+        let out_dir = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR should be set"));
+        let source = self
+            .metadata_source()
+            .unwrap_or_else(|e| panic!("could not generate guest metadata: {}", e));
+        std::fs::write(out_dir.join(METADATA_FILE), source)
+            .unwrap_or_else(|e| panic!("could not write guest metadata: {}", e));
+    }
+
+    /// The Rust source of a static holding the guest's metadata in
+    /// `METADATA_SECTION`
+    fn metadata_source(&self) -> Result<String> {
+        let bytes = GuestMetadata::new(self.metadata.clone()).to_bytes()?;
+        let mut source = String::new();
+        writeln!(source, "#[used]")?;
+        writeln!(source, "#[link_section = \"{}\"]", METADATA_SECTION)?;
+        writeln!(
+            source,
+            "static HYPERLIGHT_GUEST_METADATA: [u8; {}] = {:?};",
+            bytes.len(),
+            bytes
+        )?;
+        Ok(source)
+    }
+}
+
+/// The metadata embedded in the guest binary `binary`, which is empty if
+/// it has none
+pub fn read_metadata(binary: &[u8]) -> Result<GuestMetadata> {
+    let section = match Object::parse(binary)? {
+        Object::Elf(elf) => elf
+            .section_headers
+            .iter()
+            .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(METADATA_SECTION))
+            .map(|sh| (sh.sh_offset as usize, sh.sh_size as usize)),
+        Object::PE(pe) => pe
+            .sections
+            .iter()
+            .find(|s| s.name().ok() == Some(METADATA_SECTION))
+            .map(|s| {
+                (
+                    s.pointer_to_raw_data as usize,
+                    s.size_of_raw_data.min(s.virtual_size) as usize,
+                )
+            }),
+        _ => bail!("guest binaries must be ELF or PE files"),
+    };
+    match section {
+        Some((offset, size)) => {
+            let bytes = binary
+                .get(offset..offset + size)
+                .ok_or_else(|| anyhow!("the {} section is truncated", METADATA_SECTION))?;
+            GuestMetadata::from_bytes(bytes)
+        }
+        None => Ok(GuestMetadata::default()),
+    }
+}
+
+/// The symbol map of the functions and statics of the ELF guest binary
+/// `binary`, with demangled names, for the host to show guest addresses by
+/// name without the binary's debug information. PE guests have no symbol
+/// table in the binary, so their map is empty.
+pub fn symbol_map(binary: &[u8]) -> Result<SymbolMap> {
+    let elf = match Object::parse(binary)? {
+        Object::Elf(elf) => elf,
+        Object::PE(_) => return Ok(SymbolMap::default()),
+        _ => bail!("guest binaries must be ELF or PE files"),
+    };
+    // The host loads the first PT_LOAD segment at the start of the guest
+    // image
+    let base = elf
+        .program_headers
+        .iter()
+        .find(|phdr| phdr.p_type == PT_LOAD)
+        .map(|phdr| phdr.p_vaddr)
+        .ok_or_else(|| anyhow!("the guest has no PT_LOAD segment"))?;
+    let symbols = elf
+        .syms
+        .iter()
+        .filter(|sym| matches!(sym.st_type(), STT_FUNC | STT_OBJECT) && sym.st_value >= base)
+        .filter_map(|sym| {
+            let name = elf.strtab.get_at(sym.st_name)?;
+            Some(Symbol {
+                offset: sym.st_value - base,
+                size: u32::try_from(sym.st_size).ok()?,
+                name: format!("{:#}", rustc_demangle::demangle(name)),
+            })
+        })
+        .collect();
+    Ok(SymbolMap::new(symbols))
+}
+
+/// Where the symbol map of the guest binary at `binary` is written, which
+/// is also where the host looks for it
+pub fn symbol_map_path(binary: &Path) -> PathBuf {
+    let mut path = binary.as_os_str().to_owned();
+    path.push(".");
+    path.push(SYMBOL_MAP_EXTENSION);
+    PathBuf::from(path)
+}
+
+/// Check that `binary` can be loaded by the host as a guest: it must be an
+/// ELF or PE file whose entry point is `ENTRYPOINT`
+pub fn check_guest_binary(binary: &[u8]) -> Result<()> {
+    match Object::parse(binary)? {
+        Object::Elf(elf) => {
+            let entrypoint = elf
+                .syms
+                .iter()
+                .find(|sym| elf.strtab.get_at(sym.st_name) == Some(ENTRYPOINT))
+                .ok_or_else(|| anyhow!("the guest has no {} symbol", ENTRYPOINT))?;
+            if elf.entry != entrypoint.st_value {
+                bail!(
+                    "the guest's entry point is {:#x}, not {} at {:#x}",
+                    elf.entry,
+                    ENTRYPOINT,
+                    entrypoint.st_value
+                );
+            }
+            Ok(())
+        }
+        Object::PE(pe) => {
+            if pe.entry == 0 {
+                bail!("the guest has no entry point");
+            }
+            Ok(())
+        }
+        _ => bail!("guest binaries must be ELF or PE files"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use hyperlight_common::guest_metadata::GuestMetadata;
+
+    use super::{cargo_config_args, link_args, symbol_map, symbol_map_path, GuestBuild};
+
+    #[test]
+    fn metadata_round_trips() {
+        let build = GuestBuild::new()
+            .metadata("description", "a guest")
+            .metadata("description", "the guest")
+            .metadata("empty", "");
+        let bytes = GuestMetadata::new(build.metadata.clone())
+            .to_bytes()
+            .unwrap();
+        let mut padded = bytes.clone();
+        padded.extend_from_slice(&[0; 8]);
+        let decoded = GuestMetadata::from_bytes(&padded).unwrap();
+        assert_eq!(build.metadata, decoded.entries());
+        assert_eq!(Some("the guest"), decoded.get("description"));
+        assert!(GuestMetadata::from_bytes(&[0; 16])
+            .unwrap()
+            .entries()
+            .is_empty());
+
+        assert!(
+            GuestMetadata::new(vec![("".to_string(), "value".to_string())])
+                .to_bytes()
+                .is_err()
+        );
+        assert!(
+            GuestMetadata::new(vec![("key".to_string(), "a\0b".to_string())])
+                .to_bytes()
+                .is_err()
+        );
+        assert!(build
+            .metadata_source()
+            .unwrap()
+            .contains("#[link_section = \".hlmeta\"]"));
+    }
+
+    #[test]
+    fn limits_in_metadata() {
+        let build = GuestBuild::new()
+            .function_timeout("Spin", Duration::from_millis(1500))
+            .function_timeout("Spin", Duration::from_secs(2))
+            .heap_size(1 << 20)
+            .stack_size(1 << 16);
+        let bytes = GuestMetadata::new(build.metadata.clone())
+            .to_bytes()
+            .unwrap();
+        let metadata = GuestMetadata::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            vec![("Spin", Duration::from_secs(2))],
+            metadata.function_timeouts().collect::<Vec<_>>()
+        );
+        assert_eq!(Some(1 << 20), metadata.heap_size());
+        assert_eq!(Some(1 << 16), metadata.stack_size());
+        assert_eq!(
+            Some(hyperlight_common::guest_metadata::ABI_VERSION),
+            metadata.abi_version()
+        );
+        assert!(build
+            .metadata("memory.heap_size", "a lot")
+            .metadata_source()
+            .is_err());
+    }
+
+    #[test]
+    fn guest_targets() {
+        assert_eq!(
+            Some(vec!["-e".to_string(), "entrypoint".to_string()]),
+            link_args("x86_64-unknown-none")
+        );
+        assert!(link_args("x86_64-pc-windows-msvc")
+            .unwrap()
+            .contains(&"/ENTRY:entrypoint".to_string()));
+        assert_eq!(None, link_args("x86_64-unknown-linux-gnu"));
+
+        let args = cargo_config_args();
+        assert!(args.chunks(2).all(|pair| pair[0] == "--config"));
+        assert!(args.contains(&"profile.release.panic=\"abort\"".to_string()));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn symbols_of_elf_binary() {
+        // the test binary is an ELF binary with a symbol table, like guests
+        let binary = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let map = symbol_map(&binary).unwrap();
+        let this = map
+            .symbols()
+            .iter()
+            .find(|s| s.name == "hyperlight_guest_build::symbol_map")
+            .unwrap();
+        assert_eq!(Some(this.name.clone()), map.symbolize(this.offset));
+        assert_eq!(
+            PathBuf::from("guest.hlsym"),
+            symbol_map_path(Path::new("guest"))
+        );
+    }
+}
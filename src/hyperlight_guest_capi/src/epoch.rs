@@ -0,0 +1,19 @@
+use core::ffi::c_void;
+
+use hyperlight_guest::epoch::check_epoch;
+
+#[no_mangle]
+pub extern "C" fn hl_check_epoch() {
+    check_epoch();
+}
+
+/// Called at the entry of every function of a guest built with
+/// `-finstrument-functions`, so that the guest checks the epoch without
+/// adding `hl_check_epoch` calls to its code
+#[no_mangle]
+pub extern "C" fn __cyg_profile_func_enter(_this_fn: *mut c_void, _call_site: *mut c_void) {
+    check_epoch();
+}
+
+#[no_mangle]
+pub extern "C" fn __cyg_profile_func_exit(_this_fn: *mut c_void, _call_site: *mut c_void) {}
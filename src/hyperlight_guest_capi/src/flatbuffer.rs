@@ -38,6 +38,34 @@ pub extern "C" fn hl_flatbuffer_result_from_ULong(value: u64) -> Box<FfiVec> {
     Box::new(unsafe { FfiVec::from_vec(vec) })
 }
 
+#[no_mangle]
+pub extern "C" fn hl_flatbuffer_result_from_Short(value: i16) -> Box<FfiVec> {
+    let vec = get_flatbuffer_result(value);
+
+    Box::new(unsafe { FfiVec::from_vec(vec) })
+}
+
+#[no_mangle]
+pub extern "C" fn hl_flatbuffer_result_from_UShort(value: u16) -> Box<FfiVec> {
+    let vec = get_flatbuffer_result(value);
+
+    Box::new(unsafe { FfiVec::from_vec(vec) })
+}
+
+#[no_mangle]
+pub extern "C" fn hl_flatbuffer_result_from_Byte(value: i8) -> Box<FfiVec> {
+    let vec = get_flatbuffer_result(value);
+
+    Box::new(unsafe { FfiVec::from_vec(vec) })
+}
+
+#[no_mangle]
+pub extern "C" fn hl_flatbuffer_result_from_UByte(value: u8) -> Box<FfiVec> {
+    let vec = get_flatbuffer_result(value);
+
+    Box::new(unsafe { FfiVec::from_vec(vec) })
+}
+
 #[no_mangle]
 pub extern "C" fn hl_flatbuffer_result_from_Float(value: f32) -> Box<FfiVec> {
     let vec = get_flatbuffer_result(value);
@@ -99,4 +127,24 @@ pub extern "C" fn hl_get_host_return_value_as_ULong() -> u64 {
     get_host_return_value().expect("Unable to get host return value as ulong")
 }
 
+#[no_mangle]
+pub extern "C" fn hl_get_host_return_value_as_Short() -> i16 {
+    get_host_return_value().expect("Unable to get host return value as i16")
+}
+
+#[no_mangle]
+pub extern "C" fn hl_get_host_return_value_as_UShort() -> u16 {
+    get_host_return_value().expect("Unable to get host return value as u16")
+}
+
+#[no_mangle]
+pub extern "C" fn hl_get_host_return_value_as_Byte() -> i8 {
+    get_host_return_value().expect("Unable to get host return value as i8")
+}
+
+#[no_mangle]
+pub extern "C" fn hl_get_host_return_value_as_UByte() -> u8 {
+    get_host_return_value().expect("Unable to get host return value as u8")
+}
+
 // TODO add bool, float, double, string, vecbytes
@@ -4,6 +4,7 @@
 extern crate alloc;
 
 pub mod dispatch;
+pub mod epoch;
 pub mod error;
 pub mod flatbuffer;
 pub mod logging;
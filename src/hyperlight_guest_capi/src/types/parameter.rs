@@ -22,6 +22,10 @@ pub union FfiParameterValue {
     pub Bool: bool,
     pub String: *mut c_char,
     pub VecBytes: FfiVec,
+    pub Short: i16,
+    pub UShort: u16,
+    pub Byte: i8,
+    pub UByte: u8,
 }
 
 /// An owned FFI version Of `ParameterValue`
@@ -43,6 +47,10 @@ impl FfiParameter {
             ParameterValue::Float(v) => (ParameterType::Float, FfiParameterValue { Float: v }),
             ParameterValue::Double(v) => (ParameterType::Double, FfiParameterValue { Double: v }),
             ParameterValue::Bool(v) => (ParameterType::Bool, FfiParameterValue { Bool: v }),
+            ParameterValue::Short(v) => (ParameterType::Short, FfiParameterValue { Short: v }),
+            ParameterValue::UShort(v) => (ParameterType::UShort, FfiParameterValue { UShort: v }),
+            ParameterValue::Byte(v) => (ParameterType::Byte, FfiParameterValue { Byte: v }),
+            ParameterValue::UByte(v) => (ParameterType::UByte, FfiParameterValue { UByte: v }),
             ParameterValue::String(v) => {
                 let c_str = CString::new(v.as_str()).expect("Unable to make CString from String");
                 let leaked = c_str.into_raw();
@@ -55,6 +63,13 @@ impl FfiParameter {
                     FfiParameterValue { VecBytes: leaked },
                 )
             }
+            ParameterValue::VecBytesSegments(segments) => {
+                let leaked = unsafe { FfiVec::from_vec(segments.concat()) };
+                (
+                    ParameterType::VecBytes,
+                    FfiParameterValue { VecBytes: leaked },
+                )
+            }
         };
         Ok(FfiParameter { tag, value: union })
     }
@@ -71,6 +86,10 @@ impl FfiParameter {
             ParameterType::Float => ParameterValue::Float(unsafe { self.value.Float }),
             ParameterType::Double => ParameterValue::Double(unsafe { self.value.Double }),
             ParameterType::Bool => ParameterValue::Bool(unsafe { self.value.Bool }),
+            ParameterType::Short => ParameterValue::Short(unsafe { self.value.Short }),
+            ParameterType::UShort => ParameterValue::UShort(unsafe { self.value.UShort }),
+            ParameterType::Byte => ParameterValue::Byte(unsafe { self.value.Byte }),
+            ParameterType::UByte => ParameterValue::UByte(unsafe { self.value.UByte }),
             ParameterType::String => ParameterValue::String(
                 unsafe { CStr::from_ptr(self.value.String) }
                     .to_string_lossy()
@@ -0,0 +1,35 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+pub use core::fmt::Write;
+
+use hyperlight_guest::print::HostPrintWriter;
+
+/// A handle to the host's standard output. Text written to it with
+/// `write!` is buffered, and sent to the host's `HostPrint` function when
+/// the buffer fills up, when `flush` is called and when it is dropped.
+pub type Stdout = HostPrintWriter;
+
+/// Returns a handle to the host's standard output, like `std::io::stdout`
+pub fn stdout() -> Stdout {
+    HostPrintWriter::new()
+}
+
+/// Returns a handle to the host's standard output. The guest has a single
+/// output channel, so this is the same as `stdout`.
+pub fn stderr() -> Stdout {
+    HostPrintWriter::new()
+}
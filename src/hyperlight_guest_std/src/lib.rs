@@ -0,0 +1,73 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! A small subset of the standard library for hyperlight guests.
+//!
+//! Guests are `#![no_std]`, so library code written against `std` has to
+//! be rewritten before it can be used in a guest. This crate provides
+//! replacements for the parts of `std` that such code most commonly
+//! uses, with the same names and paths where possible, built on
+//! `alloc` and the host functions every sandbox provides (`HostPrint`
+//! and `HostSleep`). Porting code is then mostly a matter of replacing
+//! `std::` with `hyperlight_guest_std::`.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod collections;
+pub mod io;
+pub mod prelude;
+pub mod random;
+pub mod thread;
+pub mod time;
+
+pub use alloc::{borrow, boxed, fmt, format, rc, slice, str, string, sync, vec};
+pub use core::{
+    any, array, cell, char, cmp, convert, hash, iter, marker, mem, ops, option, ptr, result,
+};
+
+/// Print to the host's standard output, like `std::print!`
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => {
+        $crate::__private::hl_print!($($arg)*)
+    };
+}
+
+/// Print to the host's standard output followed by a newline, like
+/// `std::println!`
+#[macro_export]
+macro_rules! println {
+    ($($arg:tt)*) => {
+        $crate::__private::hl_println!($($arg)*)
+    };
+}
+
+/// Print to the host followed by a newline, like `std::eprintln!`. The
+/// guest has a single output channel, so this prints to the host's
+/// standard output.
+#[macro_export]
+macro_rules! eprintln {
+    ($($arg:tt)*) => {
+        $crate::__private::hl_eprintln!($($arg)*)
+    };
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use hyperlight_guest::{hl_eprintln, hl_print, hl_println};
+}
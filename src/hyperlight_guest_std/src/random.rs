@@ -0,0 +1,50 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use hyperlight_guest::RANDOM_SEED;
+
+/// The state of the generator, or 0 if it has not been seeded yet
+static STATE: AtomicU64 = AtomicU64::new(0);
+
+/// Return the next pseudo-random number from a SplitMix64 generator
+/// seeded with the random seed the host passed to the guest.
+///
+/// This is not suitable for cryptography. The state of the generator is
+/// part of guest memory, so it is restored along with the rest of the
+/// guest's state between guest function calls.
+pub fn random_u64() -> u64 {
+    let mut state = STATE.load(Ordering::Relaxed);
+    if state == 0 {
+        state = unsafe { RANDOM_SEED };
+    }
+    state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    STATE.store(state, Ordering::Relaxed);
+
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Fill `buf` with pseudo-random bytes, see `random_u64`
+pub fn fill_bytes(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        let bytes = random_u64().to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
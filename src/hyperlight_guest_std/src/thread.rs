@@ -0,0 +1,35 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use core::time::Duration;
+
+use hyperlight_guest::sleep::{hl_sleep, hl_yield};
+
+/// Suspend the guest for at least `dur`, like `std::thread::sleep`.
+///
+/// The vCPU does not run while the guest is suspended. The time spent
+/// sleeping counts towards the maximum execution time of the guest
+/// function call.
+pub fn sleep(dur: Duration) {
+    let ms = dur.as_nanos().div_ceil(1_000_000);
+    hl_sleep(u64::try_from(ms).unwrap_or(u64::MAX)).expect("Failed to call HostSleep");
+}
+
+/// Let the host run other work before the guest continues, like
+/// `std::thread::yield_now`
+pub fn yield_now() {
+    hl_yield().expect("Failed to call HostSleep");
+}
@@ -14,28 +14,28 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::sync::{Arc, Mutex};
-
-use criterion::{criterion_group, criterion_main, Criterion};
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
 use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterValue, ReturnType};
-use hyperlight_host::func::HostFunction2;
+use hyperlight_host::hyperlight_bench::{
+    benchmark_group_name, byte_array_parameter, config_for_parameter_size, new_sandbox,
+    new_sandbox_with_host_add, new_uninitialized_sandbox, BoundaryCopy, BOUNDARY_COPY_SIZES,
+    LARGE_PARAMETER_SIZES,
+};
 use hyperlight_host::sandbox::{MultiUseSandbox, UninitializedSandbox};
-use hyperlight_host::sandbox_state::sandbox::EvolvableSandbox;
-use hyperlight_host::sandbox_state::transition::Noop;
-use hyperlight_host::GuestBinary;
 use hyperlight_testing::simple_guest_as_string;
 
 fn create_uninit_sandbox() -> UninitializedSandbox {
     let path = simple_guest_as_string().unwrap();
-    UninitializedSandbox::new(GuestBinary::FilePath(path), None, None, None).unwrap()
+    new_uninitialized_sandbox(&path, None).unwrap()
 }
 
 fn create_multiuse_sandbox() -> MultiUseSandbox {
-    create_uninit_sandbox().evolve(Noop::default()).unwrap()
+    let path = simple_guest_as_string().unwrap();
+    new_sandbox(&path, None).unwrap()
 }
 
 fn guest_call_benchmark(c: &mut Criterion) {
-    let mut group = c.benchmark_group("guest_functions");
+    let mut group = c.benchmark_group(benchmark_group_name("guest_functions"));
 
     // Benchmarks a single guest function call.
     // The benchmark does **not** include the time to reset the sandbox memory after the call.
@@ -69,23 +69,19 @@ fn guest_call_benchmark(c: &mut Criterion) {
         });
     });
 
+    // Benchmarks the round trip of a guest function call that takes no parameters
+    // and does no work, i.e. the fixed cost of a call.
+    group.bench_function("empty_guest_call", |b| {
+        let mut call_ctx = create_multiuse_sandbox().new_call_context();
+
+        b.iter(|| call_ctx.call("GetStatic", ReturnType::Int, None).unwrap());
+    });
+
     // Benchmarks a guest function call calling into the host.
     // The benchmark does **not** include the time to reset the sandbox memory after the call.
     group.bench_function("guest_call_with_call_to_host_function", |b| {
-        let mut uninitialized_sandbox = create_uninit_sandbox();
-
-        // Define a host function that adds two integers and register it.
-        fn add(a: i32, b: i32) -> hyperlight_host::Result<i32> {
-            Ok(a + b)
-        }
-        let host_function = Arc::new(Mutex::new(add));
-        host_function
-            .register(&mut uninitialized_sandbox, "HostAdd")
-            .unwrap();
-
-        let multiuse_sandbox: MultiUseSandbox =
-            uninitialized_sandbox.evolve(Noop::default()).unwrap();
-        let mut call_ctx = multiuse_sandbox.new_call_context();
+        let path = simple_guest_as_string().unwrap();
+        let mut call_ctx = new_sandbox_with_host_add(&path).unwrap().new_call_context();
 
         b.iter(|| {
             call_ctx
@@ -101,8 +97,101 @@ fn guest_call_benchmark(c: &mut Criterion) {
     group.finish();
 }
 
+fn large_parameter_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group(benchmark_group_name("large_parameters"));
+
+    // Benchmarks passing a byte array to the guest and getting one of the same
+    // size back, for each size in `LARGE_PARAMETER_SIZES`.
+    // The benchmark does **not** include the time to reset the sandbox memory after the call.
+    for &size in LARGE_PARAMETER_SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("byte_array", size), &size, |b, &size| {
+            let path = simple_guest_as_string().unwrap();
+            let mut call_ctx = new_sandbox(&path, Some(config_for_parameter_size(size)))
+                .unwrap()
+                .new_call_context();
+
+            b.iter_batched(
+                || byte_array_parameter(size),
+                |params| {
+                    call_ctx
+                        .call("SetByteArrayToZero", ReturnType::VecBytes, Some(params))
+                        .unwrap()
+                },
+                BatchSize::LargeInput,
+            );
+        });
+    }
+
+    group.finish();
+}
+
+fn pipelined_call_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group(benchmark_group_name("pipelined_calls"));
+
+    // The number of calls made in each iteration, and the size of each call's
+    // byte array parameter, large enough for marshalling to take a
+    // noticeable share of each call.
+    const CALLS: usize = 8;
+    const SIZE: usize = 0x40000;
+
+    let calls: Vec<_> = (0..CALLS)
+        .map(|_| {
+            (
+                "SetByteArrayToZero".to_string(),
+                ReturnType::VecBytes,
+                Some(byte_array_parameter(SIZE)),
+            )
+        })
+        .collect();
+    group.throughput(Throughput::Bytes((CALLS * SIZE) as u64));
+
+    // Benchmarks making the calls with `call_pipelined`, with one input and
+    // output data buffer slot, i.e. one call after the other, as the baseline,
+    // and with a slot per call, so the parameters of the next calls are
+    // serialized while the guest runs the current one.
+    // The benchmark does **not** include the time to reset the sandbox memory after the calls.
+    for slots in [1, CALLS] {
+        group.bench_with_input(BenchmarkId::new("slots", slots), &slots, |b, &slots| {
+            let path = simple_guest_as_string().unwrap();
+            let mut cfg = config_for_parameter_size(SIZE);
+            cfg.set_io_buffer_slots(slots);
+            let mut call_ctx = new_sandbox(&path, Some(cfg)).unwrap().new_call_context();
+
+            b.iter(|| call_ctx.call_pipelined(&calls).unwrap());
+        });
+    }
+
+    group.finish();
+}
+
+fn boundary_copy_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group(benchmark_group_name("boundary_copy"));
+
+    // Benchmarks the copies `HostSharedMemory` makes of parameters and
+    // results against the byte at a time loop they replaced, on host
+    // memory standing in for the guest's.
+    for &size in BOUNDARY_COPY_SIZES {
+        group.throughput(Throughput::Bytes(size as u64));
+        let src = vec![0xAB; size];
+        let mut dst = vec![0; size];
+        for copy in [BoundaryCopy::Vectorized, BoundaryCopy::ByteLoop] {
+            group.bench_function(
+                BenchmarkId::new(format!("to_guest/{:?}", copy), size),
+                |b| b.iter(|| copy.copy_to_guest(&mut dst, &src)),
+            );
+            group.bench_function(
+                BenchmarkId::new(format!("from_guest/{:?}", copy), size),
+                |b| b.iter(|| copy.copy_from_guest(&mut dst, &src)),
+            );
+        }
+    }
+
+    group.finish();
+}
+
 fn sandbox_benchmark(c: &mut Criterion) {
-    let mut group = c.benchmark_group("sandboxes");
+    let mut group = c.benchmark_group(benchmark_group_name("sandboxes"));
 
     // Benchmarks the time to create a new uninitialized sandbox.
     // Does **not** include the time to drop the sandbox.
@@ -143,6 +232,6 @@ fn sandbox_benchmark(c: &mut Criterion) {
 criterion_group! {
     name = benches;
     config = Criterion::default();
-    targets = guest_call_benchmark, sandbox_benchmark
+    targets = guest_call_benchmark, large_parameter_benchmark, pipelined_call_benchmark, boundary_copy_benchmark, sandbox_benchmark
 }
 criterion_main!(benches);
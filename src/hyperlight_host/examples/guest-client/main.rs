@@ -0,0 +1,40 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Print a typed Rust client for the functions a guest registers.
+//!
+//! Usage: `cargo run --example guest-client -- [GUEST_BINARY] [CLIENT_NAME]`
+//!
+//! Defaults to the simple test guest and `SimpleGuestClient`.
+
+use hyperlight_host::func::client_gen::generate_guest_client_for_binary;
+use hyperlight_host::GuestBinary;
+
+fn main() -> hyperlight_host::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let guest_binary = match args.next() {
+        Some(path) => path,
+        None => hyperlight_testing::simple_guest_as_string().unwrap(),
+    };
+    let client_name = args
+        .next()
+        .unwrap_or_else(|| "SimpleGuestClient".to_string());
+
+    let client =
+        generate_guest_client_for_binary(&client_name, GuestBinary::FilePath(guest_binary))?;
+    print!("{client}");
+    Ok(())
+}
@@ -31,7 +31,9 @@ fn get_sandbox_cfg() -> Option<SandboxConfiguration> {
     #[cfg(gdb)]
     {
         let mut cfg = SandboxConfiguration::default();
-        let debug_info = DebugInfo { port: 8080 };
+        // set `with_wait_on_crash(true)` to only stop the guest if it
+        // crashes
+        let debug_info = DebugInfo::new(8080);
         cfg.set_guest_debug_info(debug_info);
 
         Some(cfg)
@@ -28,13 +28,18 @@ use std::num::TryFromIntError;
 use std::str::Utf8Error;
 use std::string::FromUtf8Error;
 use std::sync::{MutexGuard, PoisonError};
-use std::time::SystemTimeError;
+use std::thread::ThreadId;
+use std::time::{Duration, SystemTimeError};
 
 #[cfg(target_os = "windows")]
 use crossbeam_channel::{RecvError, SendError};
 use flatbuffers::InvalidFlatbuffer;
-use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterValue, ReturnValue};
+use hyperlight_common::flatbuffer_wrappers::function_types::{
+    ParameterType, ParameterValue, ReturnValue,
+};
 use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+use hyperlight_common::flatbuffer_wrappers::payload_limits::PayloadLimitExceeded;
+use hyperlight_common::guest_diagnostic::GuestDiagnostic;
 use serde::{Deserialize, Serialize};
 use serde_yaml;
 use thiserror::Error;
@@ -43,6 +48,9 @@ use thiserror::Error;
 use crate::hypervisor::wrappers::HandleWrapper;
 use crate::mem::memory_region::MemoryRegionFlags;
 use crate::mem::ptr::RawPtr;
+use crate::sandbox::cpu_fault::CpuFaultContext;
+use crate::sandbox::msr::MsrAccess;
+use crate::sandbox::SandboxState;
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub(crate) struct HyperlightHostError {
@@ -50,6 +58,25 @@ pub(crate) struct HyperlightHostError {
     pub(crate) source: String,
 }
 
+/// The kinds of error a guest function call can fail with, see
+/// `HyperlightError::category`
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ErrorCategory {
+    /// The call was cancelled because it ran for too long, stopped sending
+    /// heartbeats or calling the host, or executed too many instructions
+    Timeout,
+    /// The guest crashed: it aborted, overflowed its stack, or accessed
+    /// memory or registers it isn't allowed to
+    GuestCrash,
+    /// The hypervisor, or the thread running the guest, failed
+    Hypervisor,
+    /// The guest function returned an error
+    GuestError,
+    /// Any other error, such as invalid parameters or a call that isn't
+    /// permitted
+    Other,
+}
+
 /// The error type for Hyperlight operations
 #[derive(Error, Debug)]
 pub enum HyperlightError {
@@ -83,6 +110,17 @@ pub enum HyperlightError {
     #[cfg(all(feature = "seccomp", target_os = "linux"))]
     DisallowedSyscall,
 
+    /// The sandbox was configured with an `EntropyPolicy` other than
+    /// `EntropyPolicy::Hardware`, but the hypervisor or the processor
+    /// can't make the guest fault when it executes `RDRAND` or `RDSEED`
+    #[error("RDRAND and RDSEED can't be trapped by {0}, so the entropy policy can't be enforced")]
+    EntropyInstructionsNotTrapped(String),
+
+    /// The epoch was incremented past the deadline of the guest function
+    /// call, and the guest stopped at its next epoch check
+    #[error("Guest execution was interrupted after reaching its epoch deadline")]
+    EpochDeadlineReached(),
+
     /// A generic error with a message
     #[error("{0}")]
     Error(String),
@@ -119,6 +157,23 @@ pub enum HyperlightError {
     #[error("Cannot run from guest binary when guest binary is a buffer")]
     GuestBinaryShouldBeAFile(),
 
+    /// The guest tried to write to its own code at the given address,
+    /// which is write protected, see
+    /// `SandboxConfiguration::set_write_protect_code`
+    #[error("Guest tried to modify its code at address {0:#x}")]
+    GuestCodeModificationAttempt(u64),
+
+    /// A return in the guest didn't match its shadow stack, such as when a
+    /// return address on the stack was overwritten, see
+    /// `SandboxConfiguration::set_shadow_stack_size`
+    #[error("Guest control flow violation: {0}")]
+    GuestControlFlowViolation(String),
+
+    /// The guest took a double fault, which its double fault handler
+    /// reported along with the stack pointer and its stack trace
+    #[error("Guest double faulted: {0}")]
+    GuestDoubleFault(String),
+
     /// Guest call resulted in error in guest
     #[error("Guest error occurred {0:?}: {1}")]
     GuestError(ErrorCode, String),
@@ -127,10 +182,68 @@ pub enum HyperlightError {
     #[error("Guest execution hung on the execution of a host function call")]
     GuestExecutionHungOnHostFunctionCall(),
 
+    /// The guest went longer than the configured heartbeat timeout without
+    /// calling `HostHeartbeat`, so its execution was cancelled
+    #[error("Guest execution was cancelled after no heartbeat for {0:?}")]
+    GuestHeartbeatLapsed(Duration),
+
+    /// The guest ran for longer than the configured maximum time between
+    /// host function calls without calling one, so its execution was
+    /// cancelled
+    #[error("Guest execution was cancelled after running for {0:?} without calling the host")]
+    GuestHostCallIntervalExceeded(Duration),
+
+    /// The guest executed the maximum number of instructions it was allowed
+    #[error("Guest execution was stopped after reaching the limit of {0} instructions")]
+    GuestInstructionLimitExceeded(u64),
+
+    /// The guest accessed a model specific register, which the sandbox's
+    /// `MsrPolicy` denies
+    #[error("The guest {1} MSR {0:#x}, which the sandbox's MSR policy denies")]
+    GuestMsrAccessDenied(u32, MsrAccess),
+
     /// Guest call already in progress
     #[error("Guest call is already in progress")]
     GuestFunctionCallAlreadyInProgress(),
 
+    /// A guest function was called that the sandbox's `GuestFunctionPolicy`
+    /// does not permit
+    #[error("Guest function {0} is not permitted by the sandbox's guest function policy")]
+    GuestFunctionNotPermitted(String),
+
+    /// A version of a guest function was called, or set as its default
+    /// version, that the guest did not register. Holds the function name,
+    /// the version, and the versions the guest did register.
+    #[error("Guest function {0} has no version {1}, the guest registered versions {2:?}")]
+    GuestFunctionVersionNotFound(String, u32, Vec<u32>),
+
+    /// A guest function was called that declared it can return a result
+    /// larger than the sandbox's output data buffer, or than its maximum
+    /// payload size. Holds the function name, the result size it declared,
+    /// and the largest result the sandbox can return.
+    #[error("Guest function {0} can return a result of {1} bytes, but the largest result the sandbox can return is {2} bytes")]
+    GuestFunctionResultTooLarge(String, u64, usize),
+
+    /// A guest function call was denied by the sandbox's
+    /// `GuestCallInterceptor`. Holds the function name and the reason the
+    /// interceptor gave.
+    #[error("Guest function {0} call denied by the sandbox's guest call interceptor: {1}")]
+    GuestCallDenied(String, String),
+
+    /// A guest function call was rejected because the function had as many
+    /// calls running and waiting as its `ConcurrencyLimit` allows. Holds the
+    /// function name and an estimate of how long to wait before trying
+    /// the call again, e.g. for a load balancer's `Retry-After`.
+    #[error("Guest function {0} is overloaded: it has reached its concurrency limit and its queue is full, retry after {1:?}")]
+    Overloaded(String, Duration),
+
+    /// A guest function was called with parameters that don't match the
+    /// signature the guest registered it with. Holds the function name, the
+    /// parameter types it takes, the parameter types it was called with, and
+    /// the index of the first parameter that differs.
+    #[error("Guest function {0} takes parameters {1:?} but was called with {2:?}, which differ at parameter index {3}")]
+    GuestFunctionParameterTypeMismatch(String, Vec<ParameterType>, Vec<ParameterType>, usize),
+
     /// The given type is not supported by the guest interface.
     #[error("Unsupported type: {0}")]
     GuestInterfaceUnsupportedType(String),
@@ -139,10 +252,33 @@ pub enum HyperlightError {
     #[error("The guest offset {0} is invalid.")]
     GuestOffsetIsInvalid(usize),
 
+    /// The guest aborted with `hl_assert!` or `hl_trap!`, reporting where
+    /// and why
+    #[error("Guest trapped: {0}")]
+    GuestTrapped(GuestDiagnostic),
+
+    /// The guest's vCPU triple faulted and shut down, with the given
+    /// registers. The sandbox has to be recreated rather than reset, as the
+    /// guest may have replaced its descriptor tables.
+    #[error("Guest triple faulted at {0}")]
+    GuestTripleFault(CpuFaultContext),
+
     /// A Host function was called by the guest but it was not registered.
     #[error("HostFunction {0} was not found")]
     HostFunctionNotFound(String),
 
+    /// A Host function called by the guest panicked. The guest is handed
+    /// the panic as an error with `ErrorCode::HostFunctionError`.
+    #[error("HostFunction {0} panicked: {1}")]
+    HostFunctionPanicked(String, String),
+
+    /// The argument at the given index of a call to a Host function broke
+    /// the function's `ArgumentValidation`, so the function wasn't called.
+    /// The guest is handed the error with
+    /// `ErrorCode::HostFunctionArgumentInvalid`.
+    #[error("HostFunction {0} argument {1} is invalid: {2}")]
+    HostFunctionArgumentInvalid(String, usize, String),
+
     /// An attempt to communicate with or from the Hypervisor Handler thread failed
     /// (i.e., usually a failure call to `.send()` or `.recv()` on a message passing
     /// channel)
@@ -171,6 +307,15 @@ pub enum HyperlightError {
     #[error("The flatbuffer is invalid")]
     InvalidFlatBuffer(#[from] InvalidFlatbuffer),
 
+    /// A snapshot file is malformed, or can't be applied on top of the
+    /// snapshots read before it
+    #[error("Invalid snapshot file: {0}")]
+    InvalidSnapshotFile(String),
+
+    /// A trace file is malformed, or of a newer version than is supported
+    #[error("Invalid trace file: {0}")]
+    InvalidTraceFile(String),
+
     /// Conversion of str to Json failed
     #[error("Conversion of str data to json failed")]
     JsonConversionFailure(#[from] serde_json::Error),
@@ -229,6 +374,10 @@ pub enum HyperlightError {
     #[error("An error occurred handling an outb message {0:?}: {1}")]
     OutBHandlingError(String, String),
 
+    /// A function call payload, or a parameter or return value in it, exceeded the configured maximum size
+    #[error("{0} size {1} exceeds the maximum allowed size {2}")]
+    PayloadTooLarge(&'static str, usize, usize),
+
     /// Failed to get value from parameter value
     #[error("Failed To Convert Parameter Value {0:?} to {1:?}")]
     ParameterValueConversionFailure(ParameterValue, &'static str),
@@ -257,6 +406,22 @@ pub enum HyperlightError {
     #[error("Failed To Convert Return Value {0:?} to {1:?}")]
     ReturnValueConversionFailure(ReturnValue, &'static str),
 
+    /// A guest function was called on a sandbox that is not ready to run one
+    #[error("Sandbox is {0:?}, it must be reset or recreated before it can be used")]
+    SandboxNotReady(SandboxState),
+
+    /// A guest function was called on a sandbox from a thread other than
+    /// the one it was transferred to with
+    /// `MultiUseSandbox::transfer_to_thread`
+    #[error("Sandbox belongs to thread {0:?}, but was used on thread {1:?}")]
+    SandboxOwnedByAnotherThread(ThreadId, ThreadId),
+
+    /// The sandbox was configured with a shadow stack, see
+    /// `SandboxConfiguration::set_shadow_stack_size`, but the hypervisor
+    /// or the host doesn't support CET shadow stacks
+    #[error("CET shadow stacks are not supported by {0}")]
+    ShadowStackNotSupported(String),
+
     /// Stack overflow detected in guest
     #[error("Stack overflow detected")]
     StackOverflow(),
@@ -275,6 +440,12 @@ pub enum HyperlightError {
     #[error("SystemTimeError {0:?}")]
     SystemTimeError(#[from] SystemTimeError),
 
+    /// Replaying a trace, the guest didn't behave as it did when the trace
+    /// was recorded. Holds the index of the guest function call in the
+    /// trace's guest calls, and how the guest diverged.
+    #[error("Replaying guest function call {0} of the trace diverged from the trace: {1}")]
+    TraceDivergence(usize, String),
+
     /// Error occurred when translating guest address
     #[error("An error occurred when translating guest address: {0:?}")]
     #[cfg(gdb)]
@@ -325,6 +496,68 @@ pub enum HyperlightError {
     YamlConversionFailure(#[from] serde_yaml::Error),
 }
 
+impl HyperlightError {
+    /// Whether a guest function call that failed with this error may have
+    /// left the guest in an inconsistent state, either because the guest
+    /// crashed or because its execution was interrupted part way through.
+    pub(crate) fn poisons_sandbox(&self) -> bool {
+        matches!(
+            self,
+            HyperlightError::EpochDeadlineReached()
+                | HyperlightError::ExecutionAccessViolation(_)
+                | HyperlightError::ExecutionCanceledByHost()
+                | HyperlightError::GuestAborted(_, _)
+                | HyperlightError::GuestCodeModificationAttempt(_)
+                | HyperlightError::GuestControlFlowViolation(_)
+                | HyperlightError::GuestDoubleFault(_)
+                | HyperlightError::GuestExecutionHungOnHostFunctionCall()
+                | HyperlightError::GuestHeartbeatLapsed(_)
+                | HyperlightError::GuestHostCallIntervalExceeded(_)
+                | HyperlightError::GuestInstructionLimitExceeded(_)
+                | HyperlightError::GuestMsrAccessDenied(_, _)
+                | HyperlightError::GuestTrapped(_)
+                | HyperlightError::GuestTripleFault(_)
+                | HyperlightError::HypervisorHandlerCommunicationFailure()
+                | HyperlightError::HypervisorHandlerMessageReceiveTimedout()
+                | HyperlightError::MemoryAccessViolation(_, _, _)
+                | HyperlightError::StackOverflow()
+        )
+    }
+
+    /// The kind of error this is, e.g. to decide whether a guest function
+    /// call that failed with it is worth retrying
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            HyperlightError::EpochDeadlineReached()
+            | HyperlightError::ExecutionCanceledByHost()
+            | HyperlightError::GuestExecutionHungOnHostFunctionCall()
+            | HyperlightError::GuestHeartbeatLapsed(_)
+            | HyperlightError::GuestHostCallIntervalExceeded(_)
+            | HyperlightError::GuestInstructionLimitExceeded(_)
+            | HyperlightError::HypervisorHandlerMessageReceiveTimedout() => ErrorCategory::Timeout,
+            HyperlightError::ExecutionAccessViolation(_)
+            | HyperlightError::GuestAborted(_, _)
+            | HyperlightError::GuestCodeModificationAttempt(_)
+            | HyperlightError::GuestControlFlowViolation(_)
+            | HyperlightError::GuestDoubleFault(_)
+            | HyperlightError::GuestMsrAccessDenied(_, _)
+            | HyperlightError::GuestTrapped(_)
+            | HyperlightError::GuestTripleFault(_)
+            | HyperlightError::MemoryAccessViolation(_, _, _)
+            | HyperlightError::StackOverflow() => ErrorCategory::GuestCrash,
+            HyperlightError::HypervisorHandlerCommunicationFailure() => ErrorCategory::Hypervisor,
+            #[cfg(kvm)]
+            HyperlightError::KVMError(_) => ErrorCategory::Hypervisor,
+            #[cfg(mshv)]
+            HyperlightError::MSHVError(_) => ErrorCategory::Hypervisor,
+            #[cfg(target_os = "windows")]
+            HyperlightError::WindowsAPIError(_) => ErrorCategory::Hypervisor,
+            HyperlightError::GuestError(_, _) => ErrorCategory::GuestError,
+            _ => ErrorCategory::Other,
+        }
+    }
+}
+
 impl From<Infallible> for HyperlightError {
     fn from(_: Infallible) -> Self {
         "Impossible as this is an infallible error".into()
@@ -337,6 +570,12 @@ impl From<&str> for HyperlightError {
     }
 }
 
+impl From<PayloadLimitExceeded> for HyperlightError {
+    fn from(e: PayloadLimitExceeded) -> Self {
+        HyperlightError::PayloadTooLarge(e.what, e.size, e.max_size)
+    }
+}
+
 impl<T> From<PoisonError<MutexGuard<'_, T>>> for HyperlightError {
     // Implemented this way rather than passing the error as a source to LockAttemptFailed as that would require
     // Box<dyn Error + Send + Sync> which is not easy to implement for PoisonError<MutexGuard<'_, T>>
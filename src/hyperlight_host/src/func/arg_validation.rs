@@ -0,0 +1,270 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Checking the arguments the guest calls a host function with before
+//! the host function is called, rather than in every host function.
+//!
+//! The rules for each parameter of a host function are declared with an
+//! `ArgumentValidation`, set with
+//! `UninitializedSandbox::set_host_function_argument_validation`. If an
+//! argument breaks a rule the host function isn't called, and the guest is
+//! handed an error with `ErrorCode::HostFunctionArgumentInvalid` in place
+//! of its return value.
+
+use std::fmt;
+use std::sync::Arc;
+
+use hyperlight_common::flatbuffer_wrappers::function_types::ParameterValue;
+
+use crate::HyperlightError::HostFunctionArgumentInvalid;
+use crate::Result;
+
+type Check = Arc<dyn Fn(&ParameterValue) -> std::result::Result<(), String> + Send + Sync>;
+
+/// A rule an argument of a host function must follow, which can be
+/// combined with others with `and`
+#[derive(Clone)]
+pub struct ArgRule(Check);
+
+impl fmt::Debug for ArgRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArgRule").finish_non_exhaustive()
+    }
+}
+
+impl ArgRule {
+    /// A rule checked by `check`, which returns why the argument is
+    /// invalid if it is
+    pub fn custom(
+        check: impl Fn(&ParameterValue) -> std::result::Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(check))
+    }
+
+    /// The argument is a string or bytes at most `max` bytes long
+    pub fn max_len(max: usize) -> Self {
+        Self::custom(move |value| match byte_len(value)? {
+            len if len > max => Err(format!("{} bytes is longer than {}", len, max)),
+            _ => Ok(()),
+        })
+    }
+
+    /// The argument is a string or bytes at least `min` bytes long
+    pub fn min_len(min: usize) -> Self {
+        Self::custom(move |value| match byte_len(value)? {
+            len if len < min => Err(format!("{} bytes is shorter than {}", len, min)),
+            _ => Ok(()),
+        })
+    }
+
+    /// The argument is a string, or bytes that are valid UTF-8
+    pub fn utf8() -> Self {
+        Self::custom(|value| match value {
+            ParameterValue::String(_) => Ok(()),
+            ParameterValue::VecBytes(bytes) => std::str::from_utf8(bytes)
+                .map(|_| ())
+                .map_err(|e| format!("not UTF-8: {}", e)),
+            ParameterValue::VecBytesSegments(segments) => std::str::from_utf8(&segments.concat())
+                .map(|_| ())
+                .map_err(|e| format!("not UTF-8: {}", e)),
+            value => Err(format!("{} is not a string or bytes", kind(value))),
+        })
+    }
+
+    /// The argument is an integer from `min` to `max` inclusive
+    pub fn range(min: i128, max: i128) -> Self {
+        Self::custom(move |value| {
+            let n = integer(value)?;
+            if n < min || n > max {
+                return Err(format!("{} is not in the range {}..={}", n, min, max));
+            }
+            Ok(())
+        })
+    }
+
+    /// The argument follows both this rule and `other`
+    pub fn and(self, other: ArgRule) -> Self {
+        Self::custom(move |value| {
+            (self.0)(value)?;
+            (other.0)(value)
+        })
+    }
+
+    /// Why `value` breaks the rule, if it does
+    pub fn check(&self, value: &ParameterValue) -> std::result::Result<(), String> {
+        (self.0)(value)
+    }
+}
+
+/// The rules the arguments of a host function must follow, by the index
+/// of the parameter
+#[derive(Clone, Debug, Default)]
+pub struct ArgumentValidation {
+    rules: Vec<(usize, ArgRule)>,
+}
+
+impl ArgumentValidation {
+    /// Validation with no rules, which every call passes
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check the parameter at `index` with `rule`, as well as any rules
+    /// already declared for it
+    pub fn param(mut self, index: usize, rule: ArgRule) -> Self {
+        self.rules.push((index, rule));
+        self
+    }
+
+    /// The highest parameter index a rule is declared for
+    pub(crate) fn max_index(&self) -> Option<usize> {
+        self.rules.iter().map(|(index, _)| *index).max()
+    }
+
+    /// Check `args`, the arguments of a call to `function_name`, returning
+    /// `HostFunctionArgumentInvalid` for the first one that breaks a rule
+    pub(crate) fn check(&self, function_name: &str, args: &[ParameterValue]) -> Result<()> {
+        for (index, rule) in &self.rules {
+            let reason = match args.get(*index) {
+                Some(value) => rule.check(value),
+                None => Err("the argument is missing".to_string()),
+            };
+            if let Err(reason) = reason {
+                return Err(HostFunctionArgumentInvalid(
+                    function_name.to_string(),
+                    *index,
+                    reason,
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn byte_len(value: &ParameterValue) -> std::result::Result<usize, String> {
+    match value {
+        ParameterValue::String(s) => Ok(s.len()),
+        ParameterValue::VecBytes(bytes) => Ok(bytes.len()),
+        ParameterValue::VecBytesSegments(segments) => Ok(segments.iter().map(Vec::len).sum()),
+        value => Err(format!("{} is not a string or bytes", kind(value))),
+    }
+}
+
+fn integer(value: &ParameterValue) -> std::result::Result<i128, String> {
+    match value {
+        ParameterValue::Byte(n) => Ok(*n as i128),
+        ParameterValue::UByte(n) => Ok(*n as i128),
+        ParameterValue::Short(n) => Ok(*n as i128),
+        ParameterValue::UShort(n) => Ok(*n as i128),
+        ParameterValue::Int(n) => Ok(*n as i128),
+        ParameterValue::UInt(n) => Ok(*n as i128),
+        ParameterValue::Long(n) => Ok(*n as i128),
+        ParameterValue::ULong(n) => Ok(*n as i128),
+        value => Err(format!("{} is not an integer", kind(value))),
+    }
+}
+
+/// The name of the variant of `value`, without its contents, which may be
+/// large
+fn kind(value: &ParameterValue) -> &'static str {
+    match value {
+        ParameterValue::Int(_) => "an Int",
+        ParameterValue::UInt(_) => "a UInt",
+        ParameterValue::Long(_) => "a Long",
+        ParameterValue::ULong(_) => "a ULong",
+        ParameterValue::Float(_) => "a Float",
+        ParameterValue::Double(_) => "a Double",
+        ParameterValue::String(_) => "a String",
+        ParameterValue::Bool(_) => "a Bool",
+        ParameterValue::VecBytes(_) => "a VecBytes",
+        ParameterValue::VecBytesSegments(_) => "a VecBytesSegments",
+        ParameterValue::Short(_) => "a Short",
+        ParameterValue::UShort(_) => "a UShort",
+        ParameterValue::Byte(_) => "a Byte",
+        ParameterValue::UByte(_) => "a UByte",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::flatbuffer_wrappers::function_types::ParameterValue;
+
+    use super::{ArgRule, ArgumentValidation};
+    use crate::HyperlightError;
+
+    #[test]
+    fn rules() {
+        let name = ArgRule::utf8()
+            .and(ArgRule::min_len(1))
+            .and(ArgRule::max_len(5));
+        assert!(name
+            .check(&ParameterValue::String("alice".to_string()))
+            .is_ok());
+        assert!(name
+            .check(&ParameterValue::VecBytes(b"bob".to_vec()))
+            .is_ok());
+        assert!(name.check(&ParameterValue::String(String::new())).is_err());
+        assert!(name
+            .check(&ParameterValue::String("mallory".to_string()))
+            .is_err());
+        assert!(name
+            .check(&ParameterValue::VecBytes(vec![0xff, 0xfe]))
+            .is_err());
+        assert!(name.check(&ParameterValue::Int(1)).is_err());
+
+        let port = ArgRule::range(1, 65535);
+        assert!(port.check(&ParameterValue::Int(443)).is_ok());
+        assert!(port.check(&ParameterValue::ULong(65535)).is_ok());
+        assert!(port.check(&ParameterValue::Int(0)).is_err());
+        assert!(port.check(&ParameterValue::ULong(u64::MAX)).is_err());
+        assert!(port.check(&ParameterValue::Double(443.0)).is_err());
+
+        let even = ArgRule::custom(|value| match value {
+            ParameterValue::Int(n) if n % 2 == 0 => Ok(()),
+            _ => Err("odd".to_string()),
+        });
+        assert!(even.check(&ParameterValue::Int(2)).is_ok());
+        assert_eq!(Err("odd".to_string()), even.check(&ParameterValue::Int(3)));
+    }
+
+    #[test]
+    fn the_first_invalid_argument_is_reported() {
+        let validation = ArgumentValidation::new()
+            .param(0, ArgRule::max_len(3))
+            .param(1, ArgRule::range(0, 10));
+        assert_eq!(Some(1), validation.max_index());
+
+        let args = [
+            ParameterValue::String("abc".to_string()),
+            ParameterValue::Int(5),
+        ];
+        assert!(validation.check("Put", &args).is_ok());
+
+        let args = [
+            ParameterValue::String("abc".to_string()),
+            ParameterValue::Int(11),
+        ];
+        match validation.check("Put", &args) {
+            Err(HyperlightError::HostFunctionArgumentInvalid(name, index, reason)) => {
+                assert_eq!("Put", name);
+                assert_eq!(1, index);
+                assert_eq!("11 is not in the range 0..=10", reason);
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        assert!(validation.check("Put", &args[..1]).is_err());
+    }
+}
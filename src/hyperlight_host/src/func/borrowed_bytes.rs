@@ -0,0 +1,162 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::ops::{Deref, Range};
+
+use tracing::{instrument, Span};
+
+use crate::mem::shared_mem::SharedMemory;
+use crate::{MultiUseSandbox, Result};
+
+/// Where the bytes returned by a guest function are
+#[derive(Debug)]
+pub(crate) enum ReturnedBytes {
+    /// The range of offsets the bytes take up in the sandbox's memory,
+    /// in its output data buffer
+    OutputData(Range<usize>),
+    /// A copy of the bytes
+    Owned(Vec<u8>),
+}
+
+/// The bytes returned by a guest function called with
+/// `MultiUseSandbox::call_borrowed`, read in place in the sandbox's output
+/// data buffer rather than copied out of it.
+///
+/// The view borrows the sandbox mutably, so the bytes can't change while
+/// it is alive and no other calls can be made on the sandbox until it is
+/// dropped. The sandbox's state is then restored, as it is after any other
+/// guest function call, which also clears the output data buffer.
+#[derive(Debug)]
+pub struct BorrowedBytes<'a> {
+    sbox: &'a mut MultiUseSandbox,
+    bytes: ReturnedBytes,
+    released: bool,
+}
+
+impl<'a> BorrowedBytes<'a> {
+    #[instrument(skip_all, parent = Span::current())]
+    pub(crate) fn new(sbox: &'a mut MultiUseSandbox, bytes: ReturnedBytes) -> Self {
+        Self {
+            sbox,
+            bytes,
+            released: false,
+        }
+    }
+
+    /// Release the bytes, restoring the sandbox's state. Dropping the view
+    /// does the same, but can only log an error if restoring the sandbox's
+    /// state fails.
+    #[instrument(err(Debug), skip(self), parent = Span::current())]
+    pub fn release(mut self) -> Result<()> {
+        self.released = true;
+        self.sbox.restore_state()?;
+        self.sbox.trim_if_requested();
+        Ok(())
+    }
+}
+
+impl Deref for BorrowedBytes<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.bytes {
+            ReturnedBytes::OutputData(range) => {
+                let shared_mem = &self.sbox.mem_mgr.unwrap_mgr().shared_mem;
+                // SAFETY: the range was checked to be in the shared memory
+                // when the bytes were found in the output data buffer. The
+                // guest isn't running, and can't be run nor the shared
+                // memory be written to by the host, as long as this view
+                // borrows the sandbox mutably.
+                unsafe {
+                    std::slice::from_raw_parts(shared_mem.base_ptr().add(range.start), range.len())
+                }
+            }
+            ReturnedBytes::Owned(bytes) => bytes,
+        }
+    }
+}
+
+impl AsRef<[u8]> for BorrowedBytes<'_> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl Drop for BorrowedBytes<'_> {
+    fn drop(&mut self) {
+        if self.released {
+            return;
+        }
+        if let Err(e) = self.sbox.restore_state() {
+            log::error!(
+                "Failed to reset sandbox {} after releasing borrowed bytes: {:?}",
+                self.sbox.id(),
+                e
+            );
+            return;
+        }
+        self.sbox.trim_if_requested();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::flatbuffer_wrappers::function_types::{ReturnType, ReturnValue};
+    use hyperlight_testing::simple_guest_as_string;
+
+    use crate::func::GuestCallAction;
+    use crate::sandbox_state::sandbox::EvolvableSandbox;
+    use crate::sandbox_state::transition::Noop;
+    use crate::{GuestBinary, MultiUseSandbox, UninitializedSandbox};
+
+    #[test]
+    fn borrowed_bytes_are_read_in_place() {
+        let path = simple_guest_as_string().unwrap();
+        let mut sbox: MultiUseSandbox =
+            UninitializedSandbox::new(GuestBinary::FilePath(path), None, None, None)
+                .unwrap()
+                .evolve(Noop::default())
+                .unwrap();
+
+        let bytes = [1u8, 2, 3, 4];
+        let zeroed = sbox
+            .call_borrowed("SetByteArrayToZero", &[bytes.as_slice().into()])
+            .unwrap();
+        assert_eq!(&[0; 4], &*zeroed);
+        drop(zeroed);
+
+        let zeroed = sbox
+            .call_borrowed("SetByteArrayToZero", &[bytes.as_slice().into()])
+            .unwrap();
+        assert_eq!(4, zeroed.len());
+        zeroed.release().unwrap();
+
+        // a function that doesn't return a VecBytes fails, and leaves the
+        // sandbox ready for the next call
+        assert!(sbox.call_borrowed("Echo", &["hello".into()]).is_err());
+        let res = sbox
+            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+            .unwrap();
+        assert_eq!(ReturnValue::Int(0), res);
+
+        // with an interceptor the bytes are copied
+        sbox.set_guest_call_interceptor(|_: &str, args| GuestCallAction::Proceed(args));
+        let zeroed = sbox
+            .call_borrowed("SetByteArrayToZero", &[bytes.as_slice().into()])
+            .unwrap();
+        assert_eq!(&[0; 4], &*zeroed);
+    }
+}
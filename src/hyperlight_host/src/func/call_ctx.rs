@@ -19,8 +19,9 @@ use hyperlight_common::flatbuffer_wrappers::function_types::{
 };
 use tracing::{instrument, Span};
 
-use super::guest_dispatch::call_function_on_guest;
-use crate::{MultiUseSandbox, Result};
+use super::call_recording::{CallRecording, RecordedCall};
+use crate::mem::mgr::GuestMemoryDigest;
+use crate::{new_error, MultiUseSandbox, Result};
 /// A context for calling guest functions.
 ///
 /// Takes ownership of an existing `MultiUseSandbox`.
@@ -39,6 +40,7 @@ use crate::{MultiUseSandbox, Result};
 #[derive(Debug)]
 pub struct MultiUseGuestCallContext {
     sbox: MultiUseSandbox,
+    recording: Option<CallRecording>,
 }
 
 impl MultiUseGuestCallContext {
@@ -47,7 +49,10 @@ impl MultiUseGuestCallContext {
     ///     
     #[instrument(skip_all, parent = Span::current())]
     pub fn start(sbox: MultiUseSandbox) -> Self {
-        Self { sbox }
+        Self {
+            sbox,
+            recording: None,
+        }
     }
 
     /// Call the guest function called `func_name` with the given arguments
@@ -72,7 +77,230 @@ impl MultiUseGuestCallContext {
         // !Send (and !Sync), we also don't need to worry about
         // synchronization
 
-        call_function_on_guest(&mut self.sbox, func_name, func_ret_type, args)
+        let res =
+            self.sbox
+                .call_guest_function_no_reset(func_name, func_ret_type.clone(), args.clone());
+        self.record(func_name, func_ret_type, args, false, &res)?;
+        res
+    }
+
+    /// Call the guest function called `func_name` with the given arguments
+    /// `args`, and expect the return value have the same type as
+    /// `func_ret_type`, as a transaction.
+    ///
+    /// If the call succeeds, its changes to the guest state are kept, as with
+    /// `call`. If the call returns an error or the guest crashes, the guest
+    /// memory is rolled back to the state it was in before the call, so that
+    /// a failed call has no side effects on any later calls made through
+    /// this context. If the guest crashed, the sandbox is still poisoned
+    /// after the rollback, see `MultiUseSandbox::state`.
+    ///
+    /// This takes a snapshot of the guest memory before every call, so it
+    /// is slower than `call`, except for calls to functions the guest
+    /// declared read-only or pure, which leave nothing to roll back.
+    /// See `MultiUseSandbox::guest_function_flags`.
+    #[instrument(err(Debug),skip(self, args),parent = Span::current())]
+    pub fn call_guest_function_transactional(
+        &mut self,
+        func_name: &str,
+        func_ret_type: ReturnType,
+        args: Option<Vec<ParameterValue>>,
+    ) -> Result<ReturnValue> {
+        let res = self.transactional_call(func_name, func_ret_type.clone(), args.clone());
+        self.record(func_name, func_ret_type, args, true, &res)?;
+        res
+    }
+
+    /// Call each of the guest functions in `calls`, given by name, return
+    /// type and arguments, in order, as `call` would, returning their
+    /// return values. Stops at the first call that fails and returns its
+    /// error, without making the calls after it.
+    ///
+    /// If the sandbox was configured with more than one input and output
+    /// data buffer slot, see `SandboxConfiguration::set_io_buffer_slots`,
+    /// the parameters of up to that many of the next calls are serialized
+    /// into free slots on another thread while the guest runs the current
+    /// call, so the time spent marshalling overlaps the time spent in the
+    /// guest. Otherwise, and while recording or while the sandbox is
+    /// tracing, see `MultiUseSandbox::start_trace`, the calls are made one
+    /// at a time.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn call_pipelined(
+        &mut self,
+        calls: &[(String, ReturnType, Option<Vec<ParameterValue>>)],
+    ) -> Result<Vec<ReturnValue>> {
+        if self.recording.is_some() {
+            return calls
+                .iter()
+                .map(|(name, ret, args)| self.call(name, *ret, args.clone()))
+                .collect();
+        }
+        self.sbox.call_guest_functions_pipelined_no_reset(calls)
+    }
+
+    fn transactional_call(
+        &mut self,
+        func_name: &str,
+        func_ret_type: ReturnType,
+        args: Option<Vec<ParameterValue>>,
+    ) -> Result<ReturnValue> {
+        if self.sbox.guest_function_flags(func_name).is_read_only() {
+            return self
+                .sbox
+                .call_guest_function_no_reset(func_name, func_ret_type, args);
+        }
+        self.sbox.check_ready()?;
+        self.sbox.resume()?;
+        self.sbox.mem_mgr.unwrap_mgr_mut().push_state()?;
+
+        let res = self
+            .sbox
+            .call_guest_function_no_reset(func_name, func_ret_type, args);
+
+        let mem_mgr = self.sbox.mem_mgr.unwrap_mgr_mut();
+        let rollback = match res {
+            Ok(_) => Ok(()),
+            Err(_) => mem_mgr.restore_state_from_last_snapshot(),
+        };
+        // always drop the snapshot, so that the snapshot stack is left as
+        // it was even if the rollback failed
+        mem_mgr.pop_state()?;
+        rollback?;
+        res
+    }
+
+    /// Start recording the guest function calls made through this context,
+    /// so that the guest can later be rewound to the state it was in before
+    /// any of them with `rewind_to`, and the calls re-executed one at a
+    /// time with `step_forward`, e.g. to step through a failing call under
+    /// a debugger.
+    ///
+    /// A checkpoint of the guest memory is taken now and then after every
+    /// `snapshot_interval` calls. Each checkpoint is a full copy of the
+    /// guest memory, and rewinding re-executes the calls made since the
+    /// latest checkpoint before the call rewound to. At most
+    /// `call_recording::MAX_CHECKPOINTS` are kept, the interval growing as
+    /// more calls are recorded. Any earlier recording is discarded. See the
+    /// `call_recording` module for the limits of re-executing calls.
+    #[instrument(err(Debug), skip(self), parent = Span::current())]
+    pub fn start_recording(&mut self, snapshot_interval: usize) -> Result<()> {
+        self.sbox.check_ready()?;
+        let initial = self.sbox.checkpoint()?;
+        self.recording = Some(CallRecording::new(snapshot_interval, initial)?);
+        Ok(())
+    }
+
+    /// Stop recording the guest function calls made through this context,
+    /// and discard the recorded calls and checkpoints
+    #[instrument(skip(self), parent = Span::current())]
+    pub fn stop_recording(&mut self) {
+        self.recording = None;
+    }
+
+    /// The guest function calls recorded since `start_recording` was
+    /// called, or an empty slice if this context isn't recording
+    pub fn recorded_calls(&self) -> &[RecordedCall] {
+        self.recording
+            .as_ref()
+            .map_or(&[], |recording| recording.calls())
+    }
+
+    /// The number of recorded calls whose effects are in the guest memory,
+    /// i.e. the index of the recorded call `step_forward` executes next, or
+    /// `None` if this context isn't recording
+    pub fn position(&self) -> Option<usize> {
+        self.recording.as_ref().map(CallRecording::position)
+    }
+
+    /// Rewind the guest to the state it was in before the recorded call
+    /// `call_index` was made, or after all of them if `call_index` is the
+    /// number of recorded calls. The sandbox is ready for calls after this
+    /// even if a later call poisoned it.
+    ///
+    /// The latest checkpoint taken before the call is restored, and the
+    /// recorded calls between it and `call_index` are re-executed. If one of
+    /// them poisons the sandbox, its error is returned and the guest is left
+    /// after that call. Making a new call, other than with `step_forward`,
+    /// after rewinding discards the recorded calls after it.
+    #[instrument(err(Debug), skip(self), parent = Span::current())]
+    pub fn rewind_to(&mut self, call_index: usize) -> Result<()> {
+        let recording = self.recording.as_mut().ok_or_else(not_recording)?;
+        let checkpoint = recording.rewind_to_checkpoint(call_index)?;
+        self.sbox.restore_checkpoint(checkpoint)?;
+        while self
+            .position()
+            .is_some_and(|position| position < call_index)
+        {
+            if let Err(e) = self.step_forward() {
+                self.sbox.check_ready().map_err(|_| e)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Re-execute the recorded call at the current position, as if it was
+    /// made again with the same function, return type and arguments, and
+    /// return its result. The call's recorded result is updated with it.
+    #[instrument(err(Debug), skip(self), parent = Span::current())]
+    pub fn step_forward(&mut self) -> Result<ReturnValue> {
+        let recording = self.recording.as_ref().ok_or_else(not_recording)?;
+        let call = recording.next_call()?;
+        let res = if call.transactional {
+            self.transactional_call(&call.function_name, call.return_type, call.args)
+        } else {
+            self.sbox
+                .call_guest_function_no_reset(&call.function_name, call.return_type, call.args)
+        };
+        let recording = self.recording.as_mut().ok_or_else(not_recording)?;
+        recording.replayed(recorded_result(&res));
+        self.checkpoint_if_due()?;
+        res
+    }
+
+    /// Record a call made through this context, if it is recording
+    fn record(
+        &mut self,
+        function_name: &str,
+        return_type: ReturnType,
+        args: Option<Vec<ParameterValue>>,
+        transactional: bool,
+        res: &Result<ReturnValue>,
+    ) -> Result<()> {
+        let Some(recording) = self.recording.as_mut() else {
+            return Ok(());
+        };
+        recording.record(RecordedCall {
+            function_name: function_name.to_string(),
+            return_type,
+            args,
+            transactional,
+            result: recorded_result(res),
+        });
+        self.checkpoint_if_due()
+    }
+
+    /// Take a checkpoint of the guest memory if the recording is due one,
+    /// and the guest memory is in a state that can be called into again
+    fn checkpoint_if_due(&mut self) -> Result<()> {
+        let due = self
+            .recording
+            .as_ref()
+            .is_some_and(CallRecording::needs_checkpoint);
+        if !due || self.sbox.check_ready().is_err() {
+            return Ok(());
+        }
+        let checkpoint = self.sbox.checkpoint()?;
+        if let Some(recording) = self.recording.as_mut() {
+            recording.add_checkpoint(checkpoint);
+        }
+        Ok(())
+    }
+
+    /// Digests of the guest memory as the calls made through this context
+    /// have left it, see `MultiUseSandbox::guest_memory_digest`.
+    #[instrument(err(Debug), skip(self), parent = Span::current())]
+    pub fn guest_memory_digest(&mut self) -> Result<GuestMemoryDigest> {
+        self.sbox.guest_memory_digest()
     }
 
     /// Close out the context and get back the internally-stored
@@ -99,6 +327,14 @@ impl MultiUseGuestCallContext {
     }
 }
 
+fn not_recording() -> crate::HyperlightError {
+    new_error!("The call context isn't recording guest function calls")
+}
+
+fn recorded_result(res: &Result<ReturnValue>) -> std::result::Result<ReturnValue, String> {
+    res.as_ref().cloned().map_err(ToString::to_string)
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::mpsc::sync_channel;
@@ -109,15 +345,17 @@ mod tests {
     };
     use hyperlight_testing::simple_guest_as_string;
 
+    use crate::func::call_recording::MAX_CHECKPOINTS;
+    use crate::sandbox::SandboxConfiguration;
     use crate::sandbox_state::sandbox::EvolvableSandbox;
     use crate::sandbox_state::transition::Noop;
     use crate::{GuestBinary, HyperlightError, MultiUseSandbox, Result, UninitializedSandbox};
 
-    fn new_uninit() -> Result<UninitializedSandbox> {
+    fn new_uninit(cfg: Option<SandboxConfiguration>) -> Result<UninitializedSandbox> {
         let path = simple_guest_as_string().map_err(|e| {
             HyperlightError::Error(format!("failed to get simple guest path ({e:?})"))
         })?;
-        UninitializedSandbox::new(GuestBinary::FilePath(path), None, None, None)
+        UninitializedSandbox::new(GuestBinary::FilePath(path), cfg, None, None)
     }
 
     /// Test to create a `MultiUseSandbox`, then call several guest functions
@@ -144,7 +382,8 @@ mod tests {
         // create new receiver thread and on it, begin listening for
         // requests to execute batches of calls
         let recv_hdl = thread::spawn(move || {
-            let mut sbox: MultiUseSandbox = new_uninit().unwrap().evolve(Noop::default()).unwrap();
+            let mut sbox: MultiUseSandbox =
+                new_uninit(None).unwrap().evolve(Noop::default()).unwrap();
             while let Ok(calls) = recv.recv() {
                 let mut ctx = sbox.new_call_context();
                 for call in calls {
@@ -199,7 +438,7 @@ mod tests {
 
     impl TestSandbox {
         pub fn new() -> Self {
-            let sbox: MultiUseSandbox = new_uninit().unwrap().evolve(Noop::default()).unwrap();
+            let sbox: MultiUseSandbox = new_uninit(None).unwrap().evolve(Noop::default()).unwrap();
             Self { sandbox: sbox }
         }
         pub fn call_add_to_static_multiple_times(mut self, i: i32) -> Result<TestSandbox> {
@@ -251,6 +490,212 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn transactional_call_rolls_back_on_failure() {
+        let sbox: MultiUseSandbox = new_uninit(None).unwrap().evolve(Noop::default()).unwrap();
+        let mut ctx = sbox.new_call_context();
+
+        let res = ctx
+            .call_guest_function_transactional(
+                "AddToStatic",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(5)]),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::Int(5), res);
+
+        // AddToStaticAndFail adds 10 to the counter before failing
+        let res =
+            ctx.call_guest_function_transactional("AddToStaticAndFail", ReturnType::Int, None);
+        assert!(res.is_err());
+        let res = ctx.call("GetStatic", ReturnType::Int, None).unwrap();
+        assert_eq!(ReturnValue::Int(5), res);
+
+        // without a transaction the failed call's changes are kept
+        let res = ctx.call("AddToStaticAndFail", ReturnType::Int, None);
+        assert!(res.is_err());
+        let res = ctx.call("GetStatic", ReturnType::Int, None).unwrap();
+        assert_eq!(ReturnValue::Int(15), res);
+
+        // the context still resets the sandbox to its original state
+        let mut sbox = ctx.finish().unwrap();
+        let res = sbox
+            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+            .unwrap();
+        assert_eq!(ReturnValue::Int(0), res);
+    }
+
+    #[test]
+    fn rewind_and_step_forward() {
+        let sbox: MultiUseSandbox = new_uninit(None).unwrap().evolve(Noop::default()).unwrap();
+        let mut ctx = sbox.new_call_context();
+        assert!(ctx.rewind_to(0).is_err());
+        assert!(ctx.start_recording(0).is_err());
+        ctx.start_recording(2).unwrap();
+
+        for n in 1..=5 {
+            ctx.call(
+                "AddToStatic",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(n)]),
+            )
+            .unwrap();
+        }
+        assert_eq!(5, ctx.recorded_calls().len());
+        assert_eq!(Some(5), ctx.position());
+        assert_eq!(Ok(ReturnValue::Int(6)), ctx.recorded_calls()[2].result);
+        assert!(ctx.rewind_to(6).is_err());
+
+        // back to before the fourth call, which restores the checkpoint
+        // taken after two calls and re-executes the third
+        ctx.rewind_to(3).unwrap();
+        assert_eq!(Some(3), ctx.position());
+        let res = ctx.step_forward().unwrap();
+        assert_eq!(ReturnValue::Int(10), res);
+        ctx.rewind_to(0).unwrap();
+        let res = ctx.step_forward().unwrap();
+        assert_eq!(ReturnValue::Int(1), res);
+
+        // a new call after rewinding replaces the later calls
+        let res = ctx
+            .call(
+                "AddToStatic",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(100)]),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::Int(101), res);
+        assert_eq!(2, ctx.recorded_calls().len());
+        assert!(ctx.step_forward().is_err());
+
+        ctx.stop_recording();
+        assert!(ctx.recorded_calls().is_empty());
+        assert_eq!(None, ctx.position());
+        let mut sbox = ctx.finish().unwrap();
+        let res = sbox
+            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+            .unwrap();
+        assert_eq!(ReturnValue::Int(0), res);
+    }
+
+    #[test]
+    fn recording_keeps_a_bounded_number_of_checkpoints() {
+        let sbox: MultiUseSandbox = new_uninit(None).unwrap().evolve(Noop::default()).unwrap();
+        let mut ctx = sbox.new_call_context();
+        ctx.start_recording(1).unwrap();
+        let calls = 3 * MAX_CHECKPOINTS as i32;
+        for _ in 0..calls {
+            ctx.call(
+                "AddToStatic",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(1)]),
+            )
+            .unwrap();
+        }
+        let positions = ctx.recording.as_ref().unwrap().checkpoint_positions();
+        assert!(positions.len() <= MAX_CHECKPOINTS);
+        assert_eq!(Some(&0), positions.first());
+
+        // every call can still be rewound to
+        ctx.rewind_to(1).unwrap();
+        assert_eq!(ReturnValue::Int(2), ctx.step_forward().unwrap());
+        ctx.rewind_to(calls as usize - 1).unwrap();
+        assert_eq!(ReturnValue::Int(calls), ctx.step_forward().unwrap());
+    }
+
+    #[test]
+    fn call_pipelined() {
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_io_buffer_slots(3);
+        let sbox: MultiUseSandbox = new_uninit(Some(cfg))
+            .unwrap()
+            .evolve(Noop::default())
+            .unwrap();
+        let mut ctx = sbox.new_call_context();
+
+        let add = |n| {
+            (
+                "AddToStatic".to_string(),
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(n)]),
+            )
+        };
+        let mut calls: Vec<_> = (1..=5).map(add).collect();
+        // a call that makes a host function call, whose result is written
+        // to the slot the guest is using
+        calls.push((
+            "PrintOutput".to_string(),
+            ReturnType::Int,
+            Some(vec![ParameterValue::String("pipelined\n".to_string())]),
+        ));
+        calls.push((
+            "Echo".to_string(),
+            ReturnType::String,
+            Some(vec![ParameterValue::String("echo".to_string())]),
+        ));
+        let res = ctx.call_pipelined(&calls).unwrap();
+        assert_eq!(
+            vec![
+                ReturnValue::Int(1),
+                ReturnValue::Int(3),
+                ReturnValue::Int(6),
+                ReturnValue::Int(10),
+                ReturnValue::Int(15),
+                ReturnValue::Int(10),
+                ReturnValue::String("echo".to_string()),
+            ],
+            res
+        );
+
+        // the calls after a failing call aren't made
+        let calls = vec![
+            add(1),
+            (
+                "ThisFunctionDoesNotExist".to_string(),
+                ReturnType::Int,
+                None,
+            ),
+            add(100),
+        ];
+        assert!(ctx.call_pipelined(&calls).is_err());
+
+        // and calls made one at a time still work
+        let res = ctx.call("GetStatic", ReturnType::Int, None).unwrap();
+        assert_eq!(ReturnValue::Int(16), res);
+    }
+
+    #[test]
+    fn call_pipelined_while_tracing() {
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_io_buffer_slots(3);
+        let mut sbox: MultiUseSandbox = new_uninit(Some(cfg))
+            .unwrap()
+            .evolve(Noop::default())
+            .unwrap();
+        sbox.start_trace().unwrap();
+
+        // the calls are made one at a time while tracing, so the host
+        // function calls they make are traced with the right guest call
+        let print = |message: &str| {
+            (
+                "PrintOutput".to_string(),
+                ReturnType::Int,
+                Some(vec![ParameterValue::String(message.to_string())]),
+            )
+        };
+        let mut ctx = sbox.new_call_context();
+        let res = ctx
+            .call_pipelined(&[print("first\n"), print("second\n")])
+            .unwrap();
+        assert_eq!(vec![ReturnValue::Int(6), ReturnValue::Int(7)], res);
+        let (name, ret, args) = print("third\n");
+        assert_eq!(ReturnValue::Int(6), ctx.call(&name, ret, args).unwrap());
+        let mut sbox = ctx.finish().unwrap();
+
+        let trace = sbox.finish_trace().unwrap().unwrap();
+        sbox.replay_trace(&trace).unwrap();
+    }
+
     struct TestFuncCall {
         func_name: String,
         ret_type: ReturnType,
@@ -0,0 +1,196 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Recording the guest function calls made through a
+//! `MultiUseGuestCallContext`, so that the guest can be stepped back to the
+//! state it was in before any of them and the calls re-executed from there.
+//!
+//! While recording, a checkpoint of the guest memory is taken when
+//! recording starts and then every `snapshot_interval` calls. Rewinding to
+//! a call restores the latest checkpoint taken before it and re-executes
+//! the recorded calls in between, so the interval trades the memory used
+//! by the checkpoints, each a full copy of the guest memory, against the
+//! number of calls re-executed when rewinding.
+//!
+//! At most `MAX_CHECKPOINTS` checkpoints are kept. When a recording grows
+//! past that, the interval is doubled and every other checkpoint dropped,
+//! so the memory used stays bounded however many calls are recorded, at
+//! the cost of re-executing more calls when rewinding far back.
+//!
+//! Re-executing a call only reproduces what happened the first time if
+//! the host functions it calls return the same results, since only the
+//! guest function calls are recorded. To step through a failing call under
+//! a debugger, record the calls on a sandbox created with the `gdb` feature
+//! and a debug port, rewind to the failing call, attach the debugger and
+//! re-execute the call with `MultiUseGuestCallContext::step_forward`.
+
+use std::fmt::{Debug, Formatter};
+
+use hyperlight_common::flatbuffer_wrappers::function_types::{
+    ParameterValue, ReturnType, ReturnValue,
+};
+
+use crate::mem::mgr::MemoryCheckpoint;
+use crate::{new_error, Result};
+
+/// The most checkpoints of the guest memory a recording keeps
+pub const MAX_CHECKPOINTS: usize = 16;
+
+/// A guest function call made through a `MultiUseGuestCallContext` while
+/// it was recording
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedCall {
+    /// The name of the guest function called
+    pub function_name: String,
+    /// The return type the call expected
+    pub return_type: ReturnType,
+    /// The arguments the function was called with
+    pub args: Option<Vec<ParameterValue>>,
+    /// Whether the call was made with
+    /// `MultiUseGuestCallContext::call_guest_function_transactional`
+    pub transactional: bool,
+    /// The value the call returned, or the error it failed with, as it
+    /// was last executed
+    pub result: std::result::Result<ReturnValue, String>,
+}
+
+/// The calls recorded by a `MultiUseGuestCallContext`, and the checkpoints
+/// of the guest memory taken between them
+pub(crate) struct CallRecording {
+    snapshot_interval: usize,
+    calls: Vec<RecordedCall>,
+    /// The checkpoints taken, each with the number of recorded calls that
+    /// had been executed when it was taken, in ascending order
+    checkpoints: Vec<(usize, MemoryCheckpoint)>,
+    /// The number of recorded calls whose effects are in the guest memory
+    position: usize,
+}
+
+impl CallRecording {
+    /// Start a recording whose first checkpoint, `initial`, is the state
+    /// of the guest memory before any recorded call
+    pub(crate) fn new(snapshot_interval: usize, initial: MemoryCheckpoint) -> Result<Self> {
+        if snapshot_interval == 0 {
+            return Err(new_error!("The snapshot interval must be at least 1"));
+        }
+        Ok(Self {
+            snapshot_interval,
+            calls: Vec::new(),
+            checkpoints: vec![(0, initial)],
+            position: 0,
+        })
+    }
+
+    pub(crate) fn calls(&self) -> &[RecordedCall] {
+        &self.calls
+    }
+
+    pub(crate) fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The recorded call that would be executed next when stepping forward
+    pub(crate) fn next_call(&self) -> Result<RecordedCall> {
+        self.calls.get(self.position).cloned().ok_or_else(|| {
+            new_error!(
+                "There is no recorded call after call {} to step forward to",
+                self.position
+            )
+        })
+    }
+
+    /// Record a new call that was executed at the current position. The
+    /// calls and checkpoints after the position are dropped, as the guest
+    /// has taken a different path from there.
+    pub(crate) fn record(&mut self, call: RecordedCall) {
+        self.calls.truncate(self.position);
+        let position = self.position;
+        self.checkpoints.retain(|(at, _)| *at <= position);
+        self.calls.push(call);
+        self.position += 1;
+    }
+
+    /// Update the result of the recorded call at the current position,
+    /// which has just been executed again
+    pub(crate) fn replayed(&mut self, result: std::result::Result<ReturnValue, String>) {
+        if let Some(call) = self.calls.get_mut(self.position) {
+            call.result = result;
+        }
+        self.position += 1;
+    }
+
+    /// Whether a checkpoint is due at the current position and hasn't been
+    /// taken yet
+    pub(crate) fn needs_checkpoint(&self) -> bool {
+        self.position % self.snapshot_interval == 0
+            && self
+                .checkpoints
+                .last()
+                .map_or(true, |(at, _)| *at < self.position)
+    }
+
+    /// Add the checkpoint taken at the current position, thinning out the
+    /// checkpoints if there are more than `MAX_CHECKPOINTS`. The first
+    /// checkpoint is always kept, so every call can still be rewound to.
+    pub(crate) fn add_checkpoint(&mut self, checkpoint: MemoryCheckpoint) {
+        self.checkpoints.push((self.position, checkpoint));
+        while self.checkpoints.len() > MAX_CHECKPOINTS {
+            self.snapshot_interval = self.snapshot_interval.saturating_mul(2);
+            let interval = self.snapshot_interval;
+            self.checkpoints.retain(|(at, _)| at % interval == 0);
+        }
+    }
+
+    /// The number of recorded calls that had been executed when each
+    /// checkpoint kept was taken
+    pub(crate) fn checkpoint_positions(&self) -> Vec<usize> {
+        self.checkpoints.iter().map(|(at, _)| *at).collect()
+    }
+
+    /// The latest checkpoint taken at or before `call_index`. The position
+    /// is moved to that checkpoint, so the caller must restore it.
+    pub(crate) fn rewind_to_checkpoint(
+        &mut self,
+        call_index: usize,
+    ) -> Result<&mut MemoryCheckpoint> {
+        if call_index > self.calls.len() {
+            return Err(new_error!(
+                "Cannot rewind to call {}, only {} calls have been recorded",
+                call_index,
+                self.calls.len()
+            ));
+        }
+        let (at, checkpoint) = self
+            .checkpoints
+            .iter_mut()
+            .rev()
+            .find(|(at, _)| *at <= call_index)
+            .ok_or_else(|| new_error!("No checkpoint was taken before call {}", call_index))?;
+        self.position = *at;
+        Ok(checkpoint)
+    }
+}
+
+impl Debug for CallRecording {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallRecording")
+            .field("snapshot_interval", &self.snapshot_interval)
+            .field("calls", &self.calls)
+            .field("checkpoints", &self.checkpoint_positions())
+            .field("position", &self.position)
+            .finish()
+    }
+}
@@ -0,0 +1,630 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Tracing the guest function calls made on a sandbox, the host function
+//! calls the guest made during each of them and their results, so that the
+//! trace can be written to a file and replayed against a later version of
+//! the guest to check that it still behaves the same.
+//!
+//! A trace is started with `MultiUseSandbox::start_trace` and replayed
+//! with `MultiUseSandbox::replay_trace`. While a trace is replayed the host
+//! functions aren't called: each host function call the guest makes is
+//! checked against the next one in the trace and answered with the traced
+//! result, so the guest sees the same host it was traced with. The
+//! sandbox replaying a trace must still register the host functions the
+//! guest calls, as the guest checks its calls against their signatures.
+//!
+//! Only calls made with `call_guest_function_by_name` and the functions
+//! built on it, including calls made through a `MultiUseGuestCallContext`,
+//! are traced. Calls that return their result in a result buffer or are
+//! pipelined aren't, nor are calls a guest call interceptor answers or
+//! denies without entering the guest.
+//!
+//! The file format is described in `docs/trace-file-format.md`.
+
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use hyperlight_common::flatbuffer_wrappers::function_call::{FunctionCall, FunctionCallType};
+use hyperlight_common::flatbuffer_wrappers::function_types::{
+    ParameterValue, ReturnType, ReturnValue,
+};
+use tracing::{instrument, Span};
+
+use crate::HyperlightError::{HostFunctionPanicked, InvalidTraceFile};
+use crate::{new_error, HyperlightError, Result};
+
+/// The bytes every trace file starts with
+const MAGIC: [u8; 8] = *b"HLTRACE\0";
+/// The length of the uncompressed header at the start of a trace file
+const HEADER_LEN: usize = 18;
+/// The zstd compression level traces are written with
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// The version of the trace file format written by this crate. Trace files
+/// of this version or older can be read; newer ones are rejected.
+pub const TRACE_FORMAT_VERSION: u16 = 1;
+
+/// How a traced host function call ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostCallOutcome {
+    /// The host function returned this value to the guest
+    Returned(ReturnValue),
+    /// The host function panicked with this message, which the guest was
+    /// handed as an error
+    Panicked(String),
+    /// The host function failed with this error, which failed the guest
+    /// function call
+    Failed(String),
+}
+
+/// A host function call the guest made during a traced guest function call
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracedHostCall {
+    /// The name of the host function called
+    pub function_name: String,
+    /// The arguments the guest called the function with
+    pub args: Vec<ParameterValue>,
+    /// The return type the guest expected
+    pub return_type: ReturnType,
+    /// What the call returned to the guest
+    pub outcome: HostCallOutcome,
+}
+
+/// A guest function call made on a sandbox while it was tracing
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracedGuestCall {
+    /// The name of the guest function called
+    pub function_name: String,
+    /// The return type the call expected
+    pub return_type: ReturnType,
+    /// The arguments the function was called with
+    pub args: Option<Vec<ParameterValue>>,
+    /// The host function calls the guest made during the call, in order
+    pub host_calls: Vec<TracedHostCall>,
+    /// The value the call returned, or the error it failed with
+    pub result: std::result::Result<ReturnValue, String>,
+}
+
+/// Something that happened to a sandbox while it was tracing
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceEvent {
+    /// A guest function was called
+    GuestCall(TracedGuestCall),
+    /// The sandbox's state was restored to the state it was in before the
+    /// guest function calls made since the last restore, as it is after
+    /// every call made with `MultiUseSandbox::call_guest_function_by_name`
+    Reset,
+}
+
+/// The guest function calls made on a sandbox and the host function calls
+/// the guest made during them, recorded with `MultiUseSandbox::start_trace`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CallTrace {
+    /// What happened to the sandbox, in order
+    pub events: Vec<TraceEvent>,
+}
+
+impl CallTrace {
+    /// The guest function calls in the trace, in order
+    pub fn guest_calls(&self) -> impl Iterator<Item = &TracedGuestCall> {
+        self.events.iter().filter_map(|event| match event {
+            TraceEvent::GuestCall(call) => Some(call),
+            TraceEvent::Reset => None,
+        })
+    }
+
+    /// Write this trace to `out` as a trace file
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub fn write_to(&self, mut out: impl Write) -> Result<()> {
+        out.write_all(&MAGIC)?;
+        out.write_all(&TRACE_FORMAT_VERSION.to_le_bytes())?;
+        out.write_all(&(self.events.len() as u64).to_le_bytes())?;
+        let mut encoder = zstd::Encoder::new(out, COMPRESSION_LEVEL)?;
+        for event in &self.events {
+            match event {
+                TraceEvent::GuestCall(call) => {
+                    encoder.write_all(&[0])?;
+                    write_guest_call(&mut encoder, call)?;
+                }
+                TraceEvent::Reset => encoder.write_all(&[1])?,
+            }
+        }
+        encoder.finish()?.flush()?;
+        Ok(())
+    }
+
+    /// Read a trace file written by `write_to` from `input`
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub fn read_from(mut input: impl Read) -> Result<Self> {
+        let mut header = [0; HEADER_LEN];
+        input.read_exact(&mut header)?;
+        if header[..8] != MAGIC {
+            return Err(InvalidTraceFile("not a hyperlight trace file".to_string()));
+        }
+        let version = u16::from_le_bytes([header[8], header[9]]);
+        if version == 0 || version > TRACE_FORMAT_VERSION {
+            return Err(InvalidTraceFile(format!(
+                "format version {} is not supported, the newest supported version is {}",
+                version, TRACE_FORMAT_VERSION
+            )));
+        }
+        let mut event_count = [0; 8];
+        event_count.copy_from_slice(&header[10..18]);
+        let event_count = u64::from_le_bytes(event_count);
+
+        let mut decoder = zstd::Decoder::new(input)?;
+        let mut events = Vec::new();
+        for _ in 0..event_count {
+            let event = match read_u8(&mut decoder)? {
+                0 => TraceEvent::GuestCall(read_guest_call(&mut decoder)?),
+                1 => TraceEvent::Reset,
+                kind => return Err(InvalidTraceFile(format!("unknown event kind {}", kind))),
+            };
+            events.push(event);
+        }
+        Ok(Self { events })
+    }
+}
+
+fn write_blob(out: &mut impl Write, bytes: &[u8]) -> Result<()> {
+    out.write_all(&u32::try_from(bytes.len())?.to_le_bytes())?;
+    out.write_all(bytes)?;
+    Ok(())
+}
+
+fn write_function_call(
+    out: &mut impl Write,
+    function_name: &str,
+    args: Option<Vec<ParameterValue>>,
+    call_type: FunctionCallType,
+    return_type: ReturnType,
+) -> Result<()> {
+    let call = FunctionCall::new(function_name.to_string(), args, call_type, return_type);
+    let bytes: Vec<u8> = call.try_into()?;
+    write_blob(out, &bytes)
+}
+
+fn write_return_value(out: &mut impl Write, value: &ReturnValue) -> Result<()> {
+    let bytes: Vec<u8> = value.try_into()?;
+    write_blob(out, &bytes)
+}
+
+fn write_guest_call(out: &mut impl Write, call: &TracedGuestCall) -> Result<()> {
+    write_function_call(
+        out,
+        &call.function_name,
+        call.args.clone(),
+        FunctionCallType::Guest,
+        call.return_type,
+    )?;
+    out.write_all(&u32::try_from(call.host_calls.len())?.to_le_bytes())?;
+    for host_call in &call.host_calls {
+        write_function_call(
+            out,
+            &host_call.function_name,
+            Some(host_call.args.clone()),
+            FunctionCallType::Host,
+            host_call.return_type,
+        )?;
+        match &host_call.outcome {
+            HostCallOutcome::Returned(value) => {
+                out.write_all(&[0])?;
+                write_return_value(out, value)?;
+            }
+            HostCallOutcome::Panicked(message) => {
+                out.write_all(&[1])?;
+                write_blob(out, message.as_bytes())?;
+            }
+            HostCallOutcome::Failed(message) => {
+                out.write_all(&[2])?;
+                write_blob(out, message.as_bytes())?;
+            }
+        }
+    }
+    match &call.result {
+        Ok(value) => {
+            out.write_all(&[0])?;
+            write_return_value(out, value)
+        }
+        Err(message) => {
+            out.write_all(&[1])?;
+            write_blob(out, message.as_bytes())
+        }
+    }
+}
+
+fn read_u8(input: &mut impl Read) -> Result<u8> {
+    let mut byte = [0; 1];
+    input.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_u32(input: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    input.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_blob(input: &mut impl Read) -> Result<Vec<u8>> {
+    let len = read_u32(input)? as usize;
+    // don't trust the length with an allocation before the bytes are there
+    let mut bytes = Vec::new();
+    input.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(InvalidTraceFile("the file is truncated".to_string()));
+    }
+    Ok(bytes)
+}
+
+fn read_string(input: &mut impl Read) -> Result<String> {
+    String::from_utf8(read_blob(input)?)
+        .map_err(|_| InvalidTraceFile("an error message is not valid UTF-8".to_string()))
+}
+
+fn read_function_call(input: &mut impl Read, call_type: FunctionCallType) -> Result<FunctionCall> {
+    let call = FunctionCall::try_from(read_blob(input)?.as_slice())
+        .map_err(|e| InvalidTraceFile(format!("invalid function call: {}", e)))?;
+    if call.function_call_type() != call_type {
+        return Err(InvalidTraceFile(format!(
+            "expected a {:?} function call, found a {:?} one",
+            call_type,
+            call.function_call_type()
+        )));
+    }
+    Ok(call)
+}
+
+fn read_return_value(input: &mut impl Read) -> Result<ReturnValue> {
+    ReturnValue::try_from(read_blob(input)?.as_slice())
+        .map_err(|e| InvalidTraceFile(format!("invalid return value: {}", e)))
+}
+
+fn read_guest_call(input: &mut impl Read) -> Result<TracedGuestCall> {
+    let call = read_function_call(input, FunctionCallType::Guest)?;
+    let host_call_count = read_u32(input)?;
+    let mut host_calls = Vec::new();
+    for _ in 0..host_call_count {
+        let host_call = read_function_call(input, FunctionCallType::Host)?;
+        let outcome = match read_u8(input)? {
+            0 => HostCallOutcome::Returned(read_return_value(input)?),
+            1 => HostCallOutcome::Panicked(read_string(input)?),
+            2 => HostCallOutcome::Failed(read_string(input)?),
+            kind => {
+                return Err(InvalidTraceFile(format!(
+                    "unknown host call outcome {}",
+                    kind
+                )))
+            }
+        };
+        host_calls.push(TracedHostCall {
+            function_name: host_call.function_name,
+            args: host_call.parameters.unwrap_or_default(),
+            return_type: host_call.expected_return_type,
+            outcome,
+        });
+    }
+    let result = match read_u8(input)? {
+        0 => Ok(read_return_value(input)?),
+        1 => Err(read_string(input)?),
+        kind => return Err(InvalidTraceFile(format!("unknown call result {}", kind))),
+    };
+    Ok(TracedGuestCall {
+        function_name: call.function_name,
+        return_type: call.expected_return_type,
+        args: call.parameters,
+        host_calls,
+        result,
+    })
+}
+
+#[derive(Default)]
+enum TracerState {
+    #[default]
+    Idle,
+    Recording {
+        events: Vec<TraceEvent>,
+        /// The host function calls made during the current guest call
+        host_calls: Vec<TracedHostCall>,
+    },
+    Replaying {
+        /// The traced host function calls the guest hasn't made yet
+        host_calls: VecDeque<TracedHostCall>,
+        /// How the guest first diverged from the trace, if it has
+        divergence: Option<String>,
+    },
+}
+
+/// Records the trace of a sandbox, or answers the guest's host function
+/// calls from one while it is replayed. Shared between the sandbox and the
+/// host functions it calls, so that the sandboxes recreated from a sandbox
+/// carry on with its trace.
+#[derive(Clone, Default)]
+pub(crate) struct CallTracer(Arc<Mutex<TracerState>>);
+
+impl std::fmt::Debug for CallTracer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallTracer").finish_non_exhaustive()
+    }
+}
+
+impl CallTracer {
+    fn lock(&self) -> MutexGuard<'_, TracerState> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Start a new trace, discarding any trace being recorded
+    pub(crate) fn start(&self) {
+        *self.lock() = TracerState::Recording {
+            events: Vec::new(),
+            host_calls: Vec::new(),
+        };
+    }
+
+    /// Stop recording, returning the trace recorded since `start`, or
+    /// `None` if no trace was being recorded
+    pub(crate) fn finish(&self) -> Option<CallTrace> {
+        let mut state = self.lock();
+        match std::mem::take(&mut *state) {
+            TracerState::Recording { events, .. } => Some(CallTrace { events }),
+            other => {
+                *state = other;
+                None
+            }
+        }
+    }
+
+    pub(crate) fn is_recording(&self) -> bool {
+        matches!(*self.lock(), TracerState::Recording { .. })
+    }
+
+    /// Record a guest function call, with the host function calls the
+    /// guest made since the previous one
+    pub(crate) fn record_guest_call(
+        &self,
+        function_name: &str,
+        return_type: ReturnType,
+        args: Option<&[ParameterValue]>,
+        result: &Result<ReturnValue>,
+    ) {
+        if let TracerState::Recording { events, host_calls } = &mut *self.lock() {
+            events.push(TraceEvent::GuestCall(TracedGuestCall {
+                function_name: function_name.to_string(),
+                return_type,
+                args: args.map(<[ParameterValue]>::to_vec),
+                host_calls: std::mem::take(host_calls),
+                result: match result {
+                    Ok(value) => Ok(value.clone()),
+                    Err(e) => Err(e.to_string()),
+                },
+            }));
+        }
+    }
+
+    /// Record that the sandbox's state was restored
+    pub(crate) fn record_reset(&self) {
+        if let TracerState::Recording { events, .. } = &mut *self.lock() {
+            events.push(TraceEvent::Reset);
+        }
+    }
+
+    /// Record a host function call the guest made
+    pub(crate) fn record_host_call(
+        &self,
+        function_name: &str,
+        args: Vec<ParameterValue>,
+        return_type: ReturnType,
+        result: &Result<ReturnValue>,
+    ) {
+        if let TracerState::Recording { host_calls, .. } = &mut *self.lock() {
+            host_calls.push(TracedHostCall {
+                function_name: function_name.to_string(),
+                args,
+                return_type,
+                outcome: match result {
+                    Ok(value) => HostCallOutcome::Returned(value.clone()),
+                    Err(HostFunctionPanicked(_, message)) => {
+                        HostCallOutcome::Panicked(message.clone())
+                    }
+                    Err(e) => HostCallOutcome::Failed(e.to_string()),
+                },
+            });
+        }
+    }
+
+    /// Answer the guest's host function calls from `host_calls` until
+    /// `finish_replay` is called
+    pub(crate) fn start_replay(&self, host_calls: Vec<TracedHostCall>) {
+        *self.lock() = TracerState::Replaying {
+            host_calls: host_calls.into(),
+            divergence: None,
+        };
+    }
+
+    /// Stop answering the guest's host function calls, returning how the
+    /// guest diverged from the trace since `start_replay`, if it did
+    pub(crate) fn finish_replay(&self) -> Option<String> {
+        match std::mem::take(&mut *self.lock()) {
+            TracerState::Replaying {
+                divergence: Some(divergence),
+                ..
+            } => Some(divergence),
+            TracerState::Replaying { host_calls, .. } if !host_calls.is_empty() => Some(format!(
+                "the guest didn't make the {} traced host function calls starting with a call to {}",
+                host_calls.len(),
+                host_calls[0].function_name
+            )),
+            _ => None,
+        }
+    }
+
+    /// If a trace is being replayed, answer a host function call with the
+    /// next traced call's outcome, or fail it if the guest diverged from
+    /// the trace. Returns `None` if no trace is being replayed.
+    pub(crate) fn replay_host_call(
+        &self,
+        function_name: &str,
+        args: &[ParameterValue],
+        return_type: ReturnType,
+    ) -> Option<Result<ReturnValue>> {
+        let mut state = self.lock();
+        let TracerState::Replaying {
+            host_calls,
+            divergence,
+        } = &mut *state
+        else {
+            return None;
+        };
+        let mismatch = match host_calls.pop_front() {
+            None => format!(
+                "the guest called host function {} after the traced host function calls",
+                function_name
+            ),
+            Some(traced)
+                if traced.function_name != function_name
+                    || traced.args != args
+                    || traced.return_type != return_type =>
+            {
+                format!(
+                    "the guest called host function {} with {:?} returning {:?}, the trace has a call to {} with {:?} returning {:?}",
+                    function_name,
+                    args,
+                    return_type,
+                    traced.function_name,
+                    traced.args,
+                    traced.return_type
+                )
+            }
+            Some(traced) => {
+                return Some(match traced.outcome {
+                    HostCallOutcome::Returned(value) => Ok(value),
+                    HostCallOutcome::Panicked(message) => {
+                        Err(HostFunctionPanicked(traced.function_name, message))
+                    }
+                    HostCallOutcome::Failed(message) => Err(HyperlightError::Error(message)),
+                })
+            }
+        };
+        let error = new_error!("Replaying the trace diverged: {}", mismatch);
+        divergence.get_or_insert(mismatch);
+        Some(Err(error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::flatbuffer_wrappers::function_types::{
+        ParameterValue, ReturnType, ReturnValue,
+    };
+
+    use super::{CallTrace, CallTracer, HostCallOutcome, TraceEvent};
+    use crate::HyperlightError;
+
+    fn traced() -> CallTrace {
+        let tracer = CallTracer::default();
+        tracer.start();
+        tracer.record_host_call(
+            "HostPrint",
+            vec![ParameterValue::String("hello".to_string())],
+            ReturnType::Int,
+            &Ok(ReturnValue::Int(5)),
+        );
+        tracer.record_host_call(
+            "Panics",
+            vec![],
+            ReturnType::Void,
+            &Err(HyperlightError::HostFunctionPanicked(
+                "Panics".to_string(),
+                "oops".to_string(),
+            )),
+        );
+        tracer.record_guest_call(
+            "PrintOutput",
+            ReturnType::Int,
+            Some(&[ParameterValue::String("hello".to_string())]),
+            &Ok(ReturnValue::Int(5)),
+        );
+        tracer.record_reset();
+        tracer.record_guest_call(
+            "Fails",
+            ReturnType::VecBytes,
+            None,
+            &Err(HyperlightError::Error("failed".to_string())),
+        );
+        tracer.finish().unwrap()
+    }
+
+    #[test]
+    fn record_write_and_read() {
+        let trace = traced();
+        assert_eq!(3, trace.events.len());
+        assert_eq!(TraceEvent::Reset, trace.events[1]);
+        let calls: Vec<_> = trace.guest_calls().collect();
+        assert_eq!(2, calls[0].host_calls.len());
+        assert_eq!(
+            HostCallOutcome::Panicked("oops".to_string()),
+            calls[0].host_calls[1].outcome
+        );
+        assert!(calls[1].host_calls.is_empty());
+        assert!(calls[1].result.is_err());
+
+        let mut file = Vec::new();
+        trace.write_to(&mut file).unwrap();
+        assert_eq!(trace, CallTrace::read_from(file.as_slice()).unwrap());
+
+        // a newer version, or something else entirely, is rejected
+        let mut newer = file.clone();
+        newer[8..10].copy_from_slice(&u16::MAX.to_le_bytes());
+        let res = CallTrace::read_from(newer.as_slice());
+        assert!(matches!(res, Err(HyperlightError::InvalidTraceFile(_))));
+        let res = CallTrace::read_from(&[0; 18][..]);
+        assert!(matches!(res, Err(HyperlightError::InvalidTraceFile(_))));
+    }
+
+    #[test]
+    fn replay_host_calls() {
+        let trace = traced();
+        let host_calls = trace.guest_calls().next().unwrap().host_calls.clone();
+        let tracer = CallTracer::default();
+        assert!(tracer
+            .replay_host_call("HostPrint", &[], ReturnType::Int)
+            .is_none());
+
+        tracer.start_replay(host_calls.clone());
+        let hello = [ParameterValue::String("hello".to_string())];
+        let res = tracer.replay_host_call("HostPrint", &hello, ReturnType::Int);
+        assert_eq!(ReturnValue::Int(5), res.unwrap().unwrap());
+        let res = tracer.replay_host_call("Panics", &[], ReturnType::Void);
+        assert!(matches!(
+            res,
+            Some(Err(HyperlightError::HostFunctionPanicked(_, _)))
+        ));
+        assert_eq!(None, tracer.finish_replay());
+
+        // different arguments, or fewer calls, are divergences
+        tracer.start_replay(host_calls.clone());
+        let res = tracer.replay_host_call("HostPrint", &[], ReturnType::Int);
+        assert!(matches!(res, Some(Err(_))));
+        assert!(tracer.finish_replay().is_some());
+        tracer.start_replay(host_calls);
+        tracer
+            .replay_host_call("HostPrint", &hello, ReturnType::Int)
+            .unwrap()
+            .unwrap();
+        assert!(tracer.finish_replay().is_some());
+    }
+}
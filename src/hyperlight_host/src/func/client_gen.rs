@@ -0,0 +1,338 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Generation of typed Rust clients for guests, so that host code calls
+//! guest functions through methods checked at compile time rather than by
+//! name.
+//!
+//! A build script can generate a client for a guest binary and include it
+//! in the host crate:
+//!
+//! ```no_run
+//! use hyperlight_host::func::client_gen::generate_guest_client_for_binary;
+//! use hyperlight_host::GuestBinary;
+//!
+//! let out_dir = std::env::var("OUT_DIR").unwrap();
+//! let client = generate_guest_client_for_binary(
+//!     "SimpleGuestClient",
+//!     GuestBinary::FilePath("simpleguest".to_string()),
+//! )
+//! .unwrap();
+//! std::fs::write(format!("{out_dir}/simple_guest_client.rs"), client).unwrap();
+//! ```
+//!
+//! and then, in the host crate,
+//! `include!(concat!(env!("OUT_DIR"), "/simple_guest_client.rs"));`.
+//! Creating the sandbox needs a hypervisor on the machine running the
+//! build script. The `guest-client` example does the same from the command
+//! line, so the client can be generated once and checked in instead.
+//!
+//! Only functions the guest registered with the guest library are known to
+//! the host, so only those get a method. Alternatively, the client can be
+//! generated with `generate_guest_client_from_interface` from the same
+//! interface definition the guest generates its registration code from, see
+//! `hyperlight_common::interface`, so that neither side can drift from it.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterType, ReturnType};
+use hyperlight_common::interface::{
+    check_type_name, method_name, parameter_rust_type, return_rust_type, InterfaceDefinition,
+};
+use tracing::{instrument, Span};
+
+use super::guest_signatures::GuestFunctionSignature;
+use crate::sandbox_state::sandbox::EvolvableSandbox;
+use crate::sandbox_state::transition::Noop;
+use crate::{new_error, GuestBinary, MultiUseSandbox, Result, UninitializedSandbox};
+
+/// Create a sandbox for `guest_binary`, and generate the source of a typed
+/// client named `client_name` for the functions the guest registers
+#[instrument(err(Debug), skip(guest_binary), parent = Span::current())]
+pub fn generate_guest_client_for_binary(
+    client_name: &str,
+    guest_binary: GuestBinary,
+) -> Result<String> {
+    let sandbox: MultiUseSandbox =
+        UninitializedSandbox::new(guest_binary, None, None, None)?.evolve(Noop::default())?;
+    generate_guest_client(client_name, &sandbox.guest_function_signatures())
+}
+
+/// Generate the source of a typed client named `client_name` with a
+/// method for each of `signatures`, as returned by
+/// `MultiUseSandbox::guest_function_signatures`.
+///
+/// The client borrows a `MultiUseSandbox` and calls each guest function
+/// with `call_guest_function_by_name`. Method names are the snake case
+/// function names, with namespaces joined by underscores, so `math::AddInts`
+/// becomes `math_add_ints`. Fails if `client_name` is not a valid type name,
+/// or if two functions would get the same method name.
+#[instrument(err(Debug), skip(signatures), parent = Span::current())]
+pub fn generate_guest_client(
+    client_name: &str,
+    signatures: &[GuestFunctionSignature],
+) -> Result<String> {
+    let methods: Vec<Method> = signatures
+        .iter()
+        .map(|s| Method {
+            function_name: &s.function_name,
+            parameters: s
+                .parameter_types
+                .iter()
+                .enumerate()
+                .map(|(i, t)| (format!("arg{i}"), t.clone()))
+                .collect(),
+            return_type: &s.return_type,
+        })
+        .collect();
+    generate(client_name, &methods)
+}
+
+/// Generate the source of a typed client named `client_name` with a
+/// method for each function in `interface`, named as in
+/// `generate_guest_client`, and with the parameter names from the
+/// interface definition.
+///
+/// Unlike `generate_guest_client`, this doesn't need a guest binary, so the
+/// client can be generated before the guest is built.
+#[instrument(err(Debug), skip(interface), parent = Span::current())]
+pub fn generate_guest_client_from_interface(
+    client_name: &str,
+    interface: &InterfaceDefinition,
+) -> Result<String> {
+    let methods: Vec<Method> = interface
+        .functions
+        .iter()
+        .map(|f| Method {
+            function_name: &f.name,
+            parameters: f.parameters.clone(),
+            return_type: &f.return_type,
+        })
+        .collect();
+    generate(client_name, &methods)
+}
+
+/// A method of a generated client
+struct Method<'a> {
+    function_name: &'a str,
+    parameters: Vec<(String, ParameterType)>,
+    return_type: &'a ReturnType,
+}
+
+fn generate(client_name: &str, methods: &[Method]) -> Result<String> {
+    check_type_name(client_name)
+        .map_err(|_| new_error!("{:?} is not a valid client type name", client_name))?;
+
+    let mut method_names = HashSet::new();
+    for method in methods {
+        let method_name = method_name(method.function_name);
+        if !method_names.insert(method_name.clone()) {
+            return Err(new_error!(
+                "More than one guest function would be called {} in {}",
+                method_name,
+                client_name
+            ));
+        }
+    }
+
+    let mut client = String::new();
+    write_client(&mut client, client_name, methods)
+        .map_err(|e| new_error!("Failed to generate {}: {}", client_name, e))?;
+    Ok(client)
+}
+
+fn write_client(out: &mut String, client_name: &str, methods: &[Method]) -> std::fmt::Result {
+    writeln!(
+        out,
+        "// Generated by hyperlight_host::func::client_gen. Do not edit."
+    )?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "/// A typed client for the functions of a hyperlight guest"
+    )?;
+    writeln!(out, "pub struct {client_name}<'a> {{")?;
+    writeln!(
+        out,
+        "    sandbox: &'a mut hyperlight_host::MultiUseSandbox,"
+    )?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+    writeln!(out, "impl<'a> {client_name}<'a> {{")?;
+    writeln!(
+        out,
+        "    /// Create a client that calls guest functions on `sandbox`"
+    )?;
+    writeln!(
+        out,
+        "    pub fn new(sandbox: &'a mut hyperlight_host::MultiUseSandbox) -> Self {{"
+    )?;
+    writeln!(out, "        Self {{ sandbox }}")?;
+    writeln!(out, "    }}")?;
+    for method in methods {
+        write_method(out, method)?;
+    }
+    writeln!(out, "}}")
+}
+
+fn write_method(out: &mut String, method: &Method) -> std::fmt::Result {
+    let method_name = method_name(method.function_name);
+    let params: Vec<String> = method
+        .parameters
+        .iter()
+        .map(|(name, t)| format!(", {name}: {}", parameter_rust_type(t)))
+        .collect();
+    let args: Vec<String> = method
+        .parameters
+        .iter()
+        .map(|(name, t)| format!("hyperlight_host::func::ParameterValue::{:?}({name})", t))
+        .collect();
+    let args = if args.is_empty() {
+        "None".to_string()
+    } else {
+        format!("Some(vec![{}])", args.join(", "))
+    };
+    let return_type = return_rust_type(method.return_type);
+
+    writeln!(out)?;
+    writeln!(
+        out,
+        "    /// Call the guest function `{}`",
+        method.function_name
+    )?;
+    writeln!(
+        out,
+        "    pub fn {method_name}(&mut self{}) -> hyperlight_host::Result<{return_type}> {{",
+        params.concat()
+    )?;
+    writeln!(
+        out,
+        "        let result = self.sandbox.call_guest_function_by_name("
+    )?;
+    writeln!(out, "            {:?},", method.function_name)?;
+    writeln!(
+        out,
+        "            hyperlight_host::func::ReturnType::{:?},",
+        method.return_type
+    )?;
+    writeln!(out, "            {args},")?;
+    writeln!(out, "        )?;")?;
+    writeln!(out, "        Ok(<{return_type}>::try_from(result)?)")?;
+    writeln!(out, "    }}")
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterType, ReturnType};
+    use hyperlight_common::flatbuffer_wrappers::host_function_definition::FunctionFlags;
+    use hyperlight_common::interface::InterfaceDefinition;
+    use hyperlight_testing::simple_guest_as_string;
+
+    use super::{
+        generate_guest_client, generate_guest_client_for_binary,
+        generate_guest_client_from_interface,
+    };
+    use crate::func::GuestFunctionSignature;
+    use crate::GuestBinary;
+
+    fn signature(
+        function_name: &str,
+        parameter_types: Vec<ParameterType>,
+        return_type: ReturnType,
+    ) -> GuestFunctionSignature {
+        GuestFunctionSignature {
+            function_name: function_name.to_string(),
+            parameter_types,
+            return_type,
+            flags: FunctionFlags::NONE,
+            max_result_size: None,
+        }
+    }
+
+    #[test]
+    fn generate_methods() {
+        let client = generate_guest_client(
+            "TestClient",
+            &[
+                signature("Echo", vec![ParameterType::String], ReturnType::String),
+                signature(
+                    "math::Add",
+                    vec![ParameterType::Int, ParameterType::Long],
+                    ReturnType::Long,
+                ),
+                signature("Reset", vec![], ReturnType::Void),
+            ],
+        )
+        .unwrap();
+        assert!(client.contains("pub struct TestClient<'a> {"));
+        assert!(client
+            .contains("pub fn echo(&mut self, arg0: String) -> hyperlight_host::Result<String> {"));
+        assert!(client.contains(
+            "pub fn math_add(&mut self, arg0: i32, arg1: i64) -> hyperlight_host::Result<i64> {"
+        ));
+        assert!(client.contains("\"math::Add\","));
+        assert!(client.contains(
+            "Some(vec![hyperlight_host::func::ParameterValue::Int(arg0), hyperlight_host::func::ParameterValue::Long(arg1)]),"
+        ));
+        assert!(client.contains("pub fn reset(&mut self) -> hyperlight_host::Result<()> {"));
+        assert!(client.contains("            None,\n"));
+    }
+
+    #[test]
+    fn reject_invalid_names() {
+        assert!(generate_guest_client("Not A Type", &[]).is_err());
+        assert!(generate_guest_client("struct", &[]).is_err());
+        let clashing = [
+            signature("GetValue", vec![], ReturnType::Int),
+            signature("get_value", vec![], ReturnType::Int),
+        ];
+        assert!(generate_guest_client("Client", &clashing).is_err());
+    }
+
+    #[test]
+    fn generate_from_interface() {
+        let interface = InterfaceDefinition::parse(
+            "fn Echo(message: String) -> String;\nfn math::Add(a: i32, b: i64) -> i64;",
+        )
+        .unwrap();
+        let client = generate_guest_client_from_interface("TestClient", &interface).unwrap();
+        assert!(client.contains(
+            "pub fn echo(&mut self, message: String) -> hyperlight_host::Result<String> {"
+        ));
+        assert!(client.contains(
+            "pub fn math_add(&mut self, a: i32, b: i64) -> hyperlight_host::Result<i64> {"
+        ));
+        assert!(client.contains(
+            "Some(vec![hyperlight_host::func::ParameterValue::Int(a), hyperlight_host::func::ParameterValue::Long(b)]),"
+        ));
+    }
+
+    #[test]
+    fn generate_for_simple_guest() {
+        let client = generate_guest_client_for_binary(
+            "SimpleGuestClient",
+            GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+        )
+        .unwrap();
+        assert!(client.contains(
+            "pub fn echo_double(&mut self, arg0: f64) -> hyperlight_host::Result<f64> {"
+        ));
+        assert!(client.contains(
+            "pub fn strings_echo(&mut self, arg0: String) -> hyperlight_host::Result<String> {"
+        ));
+    }
+}
@@ -0,0 +1,375 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterValue, ReturnValue};
+
+use super::guest_call_interceptor::{GuestCallAction, GuestCallInterceptor};
+use super::guest_signatures::GuestFunctionSignature;
+use crate::Result;
+
+/// How the results of calls to a guest function are cached
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct CachePolicy {
+    /// How long a result is returned from the cache after it was cached,
+    /// or `None` to keep it until it is evicted
+    pub ttl: Option<Duration>,
+    /// The most results cached for the function. When a new result is
+    /// cached and the function already has this many, the oldest one is
+    /// evicted.
+    pub max_entries: usize,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self {
+            ttl: None,
+            max_entries: 1024,
+        }
+    }
+}
+
+struct CacheEntry {
+    args: Option<Vec<ParameterValue>>,
+    value: ReturnValue,
+    cached_at: Instant,
+    /// The order entries were cached in, to evict the oldest
+    sequence: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    policies: HashMap<String, CachePolicy>,
+    /// The cached results of each function, keyed by the hash of the
+    /// parameters they were called with
+    entries: HashMap<String, HashMap<u64, CacheEntry>>,
+    next_sequence: u64,
+    hits: u64,
+    misses: u64,
+}
+
+/// Caches the results of calls to pure guest functions on the host, so
+/// that repeated calls with the same parameters are answered without
+/// entering the guest.
+///
+/// Only calls to functions given a `CachePolicy` with `cache` are cached,
+/// and only if they succeed. Set the cache on a sandbox with
+/// `MultiUseSandbox::set_guest_call_interceptor`. Clones share the same
+/// cache, so a clone can be kept to look at or clear the cache after it
+/// is set on a sandbox. A result is cached under the parameters the call
+/// was made with, so clones can be set on several sandboxes at once.
+/// Values returned from the cache are checked against the return type of
+/// the call by the sandbox, like any value returned by an interceptor.
+#[derive(Clone, Default)]
+pub struct GuestCallCache {
+    state: Arc<Mutex<CacheState>>,
+}
+
+impl GuestCallCache {
+    /// Create a cache that doesn't cache any functions' results
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cache the results of calls to the guest function `name` according
+    /// to `policy`, replacing its previous policy
+    pub fn cache(&self, name: impl Into<String>, policy: CachePolicy) -> &Self {
+        self.lock().policies.insert(name.into(), policy);
+        self
+    }
+
+    /// Cache the results of calls to every function in `signatures` that
+    /// the guest declared pure according to `policy`, e.g. with the
+    /// signatures from `MultiUseSandbox::guest_function_signatures`
+    pub fn cache_pure_functions(
+        &self,
+        signatures: &[GuestFunctionSignature],
+        policy: CachePolicy,
+    ) -> &Self {
+        let mut state = self.lock();
+        for signature in signatures.iter().filter(|s| s.flags.is_pure()) {
+            state
+                .policies
+                .insert(signature.function_name.clone(), policy);
+        }
+        drop(state);
+        self
+    }
+
+    /// Discard all cached results
+    pub fn clear(&self) {
+        self.lock().entries.clear();
+    }
+
+    /// The number of results currently cached, including expired results
+    /// not yet evicted
+    pub fn len(&self) -> usize {
+        self.lock().entries.values().map(HashMap::len).sum()
+    }
+
+    /// Whether no results are cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The number of calls to cached functions answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.lock().hits
+    }
+
+    /// The number of calls to cached functions that had to enter the guest
+    pub fn misses(&self) -> u64 {
+        self.lock().misses
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Hash parameters, hashing floating point values by their bits since
+/// `f32` and `f64` don't implement `Hash`
+fn hash_parameters(args: Option<&[ParameterValue]>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for arg in args.unwrap_or_default() {
+        std::mem::discriminant(arg).hash(&mut hasher);
+        match arg {
+            ParameterValue::Int(v) => v.hash(&mut hasher),
+            ParameterValue::UInt(v) => v.hash(&mut hasher),
+            ParameterValue::Long(v) => v.hash(&mut hasher),
+            ParameterValue::ULong(v) => v.hash(&mut hasher),
+            ParameterValue::Float(v) => v.to_bits().hash(&mut hasher),
+            ParameterValue::Double(v) => v.to_bits().hash(&mut hasher),
+            ParameterValue::String(v) => v.hash(&mut hasher),
+            ParameterValue::Bool(v) => v.hash(&mut hasher),
+            ParameterValue::VecBytes(v) => v.hash(&mut hasher),
+            ParameterValue::VecBytesSegments(v) => v.hash(&mut hasher),
+            ParameterValue::Short(v) => v.hash(&mut hasher),
+            ParameterValue::UShort(v) => v.hash(&mut hasher),
+            ParameterValue::Byte(v) => v.hash(&mut hasher),
+            ParameterValue::UByte(v) => v.hash(&mut hasher),
+        }
+    }
+    hasher.finish()
+}
+
+impl GuestCallInterceptor for GuestCallCache {
+    fn before_call(&mut self, name: &str, args: Option<Vec<ParameterValue>>) -> GuestCallAction {
+        let mut state = self.lock();
+        let Some(policy) = state.policies.get(name).copied() else {
+            return GuestCallAction::Proceed(args);
+        };
+        let hash = hash_parameters(args.as_deref());
+        let cached = state.entries.get_mut(name).and_then(|entries| {
+            let entry = entries.get(&hash)?;
+            if policy
+                .ttl
+                .is_some_and(|ttl| entry.cached_at.elapsed() >= ttl)
+            {
+                entries.remove(&hash);
+                return None;
+            }
+            // guard against parameters with the same hash
+            (entry.args == args).then(|| entry.value.clone())
+        });
+        match cached {
+            Some(value) => {
+                state.hits += 1;
+                GuestCallAction::Return(value)
+            }
+            None => {
+                state.misses += 1;
+                GuestCallAction::Proceed(args)
+            }
+        }
+    }
+
+    fn after_call(
+        &mut self,
+        name: &str,
+        args: Option<&[ParameterValue]>,
+        result: &Result<ReturnValue>,
+    ) {
+        let Ok(value) = result else {
+            return;
+        };
+        let mut state = self.lock();
+        let Some(policy) = state.policies.get(name).copied() else {
+            return;
+        };
+        if policy.max_entries == 0 {
+            return;
+        }
+        let hash = hash_parameters(args);
+        let sequence = state.next_sequence;
+        state.next_sequence += 1;
+        let entries = state.entries.entry(name.to_string()).or_default();
+        if entries.len() >= policy.max_entries && !entries.contains_key(&hash) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.sequence)
+                .map(|(hash, _)| *hash);
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            hash,
+            CacheEntry {
+                args: args.map(<[ParameterValue]>::to_vec),
+                value: value.clone(),
+                cached_at: Instant::now(),
+                sequence,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hyperlight_common::flatbuffer_wrappers::function_types::ParameterType;
+
+    use super::{CachePolicy, GuestCallCache};
+    use crate::func::{
+        FunctionFlags, GuestCallAction, GuestCallInterceptor, GuestFunctionSignature,
+        ParameterValue, ReturnType, ReturnValue,
+    };
+
+    fn call(cache: &mut GuestCallCache, name: &str, arg: i32) -> Option<ReturnValue> {
+        let args = Some(vec![ParameterValue::Int(arg)]);
+        match cache.before_call(name, args.clone()) {
+            GuestCallAction::Return(value) => Some(value),
+            GuestCallAction::Proceed(proceeded) => {
+                assert_eq!(args, proceeded);
+                cache.after_call(name, args.as_deref(), &Ok(ReturnValue::Int(arg * 2)));
+                None
+            }
+            GuestCallAction::Deny(reason) => panic!("call denied: {}", reason),
+        }
+    }
+
+    #[test]
+    fn caches_results_of_cached_functions() {
+        let mut cache = GuestCallCache::new();
+        cache.cache("Double", CachePolicy::default());
+
+        assert_eq!(None, call(&mut cache, "Double", 2));
+        assert_eq!(Some(ReturnValue::Int(4)), call(&mut cache, "Double", 2));
+        assert_eq!(None, call(&mut cache, "Double", 3));
+        assert_eq!(None, call(&mut cache, "NotCached", 2));
+        assert_eq!(None, call(&mut cache, "NotCached", 2));
+        assert_eq!((1, 2), (cache.hits(), cache.misses()));
+        assert_eq!(2, cache.len());
+
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(None, call(&mut cache, "Double", 2));
+    }
+
+    #[test]
+    fn caches_results_of_pure_functions() {
+        let signature = |name: &str, flags| GuestFunctionSignature {
+            function_name: name.to_string(),
+            parameter_types: vec![ParameterType::Int],
+            return_type: ReturnType::Int,
+            flags,
+            max_result_size: None,
+        };
+        let mut cache = GuestCallCache::new();
+        cache.cache_pure_functions(
+            &[
+                signature("Double", FunctionFlags::PURE),
+                signature("Increment", FunctionFlags::IDEMPOTENT),
+                signature("Add", FunctionFlags::NONE),
+            ],
+            CachePolicy::default(),
+        );
+
+        assert_eq!(None, call(&mut cache, "Double", 2));
+        assert_eq!(Some(ReturnValue::Int(4)), call(&mut cache, "Double", 2));
+        for name in ["Increment", "Add"] {
+            assert_eq!(None, call(&mut cache, name, 2));
+            assert_eq!(None, call(&mut cache, name, 2));
+        }
+        assert_eq!((1, 1), (cache.hits(), cache.misses()));
+    }
+
+    #[test]
+    fn failed_calls_are_not_cached() {
+        let mut cache = GuestCallCache::new();
+        cache.cache("Fails", CachePolicy::default());
+        let args = Some(vec![ParameterValue::Int(1)]);
+        cache.before_call("Fails", args.clone());
+        cache.after_call(
+            "Fails",
+            args.as_deref(),
+            &Err(crate::new_error!("guest failed")),
+        );
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn interleaved_calls_are_cached_under_their_own_parameters() {
+        let mut cache = GuestCallCache::new();
+        cache.cache("Double", CachePolicy::default());
+        let mut other = cache.clone();
+
+        let first = Some(vec![ParameterValue::Int(1)]);
+        let second = Some(vec![ParameterValue::Int(2)]);
+        cache.before_call("Double", first.clone());
+        other.before_call("Double", second.clone());
+        other.after_call("Double", second.as_deref(), &Ok(ReturnValue::Int(4)));
+        cache.after_call("Double", first.as_deref(), &Ok(ReturnValue::Int(2)));
+
+        assert_eq!(Some(ReturnValue::Int(2)), call(&mut cache, "Double", 1));
+        assert_eq!(Some(ReturnValue::Int(4)), call(&mut other, "Double", 2));
+    }
+
+    #[test]
+    fn evicts_oldest_and_expired_entries() {
+        let mut cache = GuestCallCache::new();
+        cache.cache(
+            "Double",
+            CachePolicy {
+                ttl: None,
+                max_entries: 2,
+            },
+        );
+        for arg in 0..3 {
+            call(&mut cache, "Double", arg);
+        }
+        assert_eq!(2, cache.len());
+        assert_eq!(None, call(&mut cache, "Double", 0));
+        assert_eq!(Some(ReturnValue::Int(4)), call(&mut cache, "Double", 2));
+
+        cache.cache(
+            "Expires",
+            CachePolicy {
+                ttl: Some(Duration::ZERO),
+                max_entries: 2,
+            },
+        );
+        assert_eq!(None, call(&mut cache, "Expires", 1));
+        assert_eq!(None, call(&mut cache, "Expires", 1));
+    }
+}
@@ -0,0 +1,105 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterValue, ReturnValue};
+
+use crate::Result;
+
+/// What to do with a guest function call, as decided by a
+/// `GuestCallInterceptor`
+#[derive(Clone, Debug, PartialEq)]
+pub enum GuestCallAction {
+    /// Call the guest function with these parameters, which may differ
+    /// from those the call was made with
+    Proceed(Option<Vec<ParameterValue>>),
+    /// Don't enter the guest, and return this value as the result of the
+    /// call, for example because it was cached from an earlier call
+    Return(ReturnValue),
+    /// Don't enter the guest, and fail the call with
+    /// `HyperlightError::GuestCallDenied` with this reason
+    Deny(String),
+}
+
+/// Sees every guest function call made on a `MultiUseSandbox`, before it
+/// enters the guest, and can rewrite its parameters, answer it without
+/// entering the guest, or deny it.
+///
+/// Calls are intercepted after the sandbox's `GuestFunctionPolicy` permits
+/// them, and before their parameters are checked against the function's
+/// signature, so rewritten parameters are checked too. Calls answered with
+/// `GuestCallAction::Return` are checked with the parameters they were
+/// made with, and fail as they would have had they entered the guest, or
+/// with `HyperlightError::UnexpectedReturnValueType` if the value isn't of
+/// the type the call expects.
+pub trait GuestCallInterceptor: Send {
+    /// Decide what to do with a call to the guest function `name` with
+    /// the parameters `args`
+    fn before_call(&mut self, name: &str, args: Option<Vec<ParameterValue>>) -> GuestCallAction;
+
+    /// Called with the result of each call that `before_call` let proceed,
+    /// and the parameters `before_call` returned for it, for example to
+    /// cache it. Does nothing by default.
+    fn after_call(
+        &mut self,
+        _name: &str,
+        _args: Option<&[ParameterValue]>,
+        _result: &Result<ReturnValue>,
+    ) {
+    }
+}
+
+impl<F> GuestCallInterceptor for F
+where
+    F: FnMut(&str, Option<Vec<ParameterValue>>) -> GuestCallAction + Send,
+{
+    fn before_call(&mut self, name: &str, args: Option<Vec<ParameterValue>>) -> GuestCallAction {
+        self(name, args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GuestCallAction, GuestCallInterceptor};
+    use crate::func::{ParameterValue, ReturnValue};
+
+    #[test]
+    fn closures_are_interceptors() {
+        let mut calls = 0;
+        let mut interceptor = |name: &str, args: Option<Vec<ParameterValue>>| {
+            calls += 1;
+            match name {
+                "Cached" => GuestCallAction::Return(ReturnValue::Int(42)),
+                "Secret" => GuestCallAction::Deny("not today".to_string()),
+                _ => GuestCallAction::Proceed(args),
+            }
+        };
+        assert_eq!(
+            GuestCallAction::Return(ReturnValue::Int(42)),
+            interceptor.before_call("Cached", None)
+        );
+        assert_eq!(
+            GuestCallAction::Deny("not today".to_string()),
+            interceptor.before_call("Secret", None)
+        );
+        let args = Some(vec![ParameterValue::Int(1)]);
+        assert_eq!(
+            GuestCallAction::Proceed(args.clone()),
+            interceptor.before_call("Echo", args)
+        );
+        interceptor.after_call("Echo", None, &Ok(ReturnValue::Void));
+        assert_eq!(3, calls);
+    }
+}
@@ -14,18 +14,61 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use hyperlight_common::flatbuffer_wrappers::function_call::{FunctionCall, FunctionCallType};
+use std::ops::Range;
+
+use hyperlight_common::flatbuffer_wrappers::function_call::{
+    serialize_function_call, FunctionCallType,
+};
 use hyperlight_common::flatbuffer_wrappers::function_types::{
-    ParameterValue, ReturnType, ReturnValue,
+    ParameterRef, ReturnType, ReturnValue,
 };
+use hyperlight_common::flatbuffer_wrappers::payload_limits::PayloadLimits;
 use tracing::{instrument, Span};
 
 use super::guest_err::check_for_guest_error;
 use crate::hypervisor::hypervisor_handler::HypervisorHandlerAction;
+use crate::mem::mgr::{IoSlotWriter, SandboxMemoryManager};
+use crate::mem::shared_mem::HostSharedMemory;
 use crate::sandbox::WrapperGetter;
 use crate::HyperlightError::GuestExecutionHungOnHostFunctionCall;
 use crate::{HyperlightError, Result};
 
+/// What a guest function call returns, read from the output data buffer
+pub(crate) trait GuestCallOutput: Sized {
+    /// Read the output of the guest function call that just returned
+    fn read(mem_mgr: &mut SandboxMemoryManager<HostSharedMemory>) -> Result<Self>;
+
+    /// The number of bytes of data in the output
+    #[cfg(feature = "boundary_spans")]
+    fn size(&self) -> usize;
+}
+
+impl GuestCallOutput for ReturnValue {
+    fn read(mem_mgr: &mut SandboxMemoryManager<HostSharedMemory>) -> Result<Self> {
+        mem_mgr.get_guest_function_call_result()
+    }
+
+    #[cfg(feature = "boundary_spans")]
+    fn size(&self) -> usize {
+        crate::sandbox::spans::result_size(self)
+    }
+}
+
+/// A `VecBytes` return value left in the output data buffer, as the range
+/// of offsets its bytes take up in the shared memory
+pub(crate) struct OutputDataBytes(pub(crate) Range<usize>);
+
+impl GuestCallOutput for OutputDataBytes {
+    fn read(mem_mgr: &mut SandboxMemoryManager<HostSharedMemory>) -> Result<Self> {
+        mem_mgr.get_guest_function_call_result_bytes().map(Self)
+    }
+
+    #[cfg(feature = "boundary_spans")]
+    fn size(&self) -> usize {
+        self.0.len()
+    }
+}
+
 /// Call a guest function by name, using the given `wrapper_getter`.
 #[instrument(
     err(Debug),
@@ -33,23 +76,19 @@ use crate::{HyperlightError, Result};
     parent = Span::current(),
     level = "Trace"
 )]
-pub(crate) fn call_function_on_guest<WrapperGetterT: WrapperGetter>(
+pub(crate) fn call_function_on_guest<WrapperGetterT: WrapperGetter, T: GuestCallOutput>(
     wrapper_getter: &mut WrapperGetterT,
     function_name: &str,
     return_type: ReturnType,
-    args: Option<Vec<ParameterValue>>,
-) -> Result<ReturnValue> {
-    let mut timedout = false;
-
-    let fc = FunctionCall::new(
-        function_name.to_string(),
-        args,
-        FunctionCallType::Guest,
-        return_type,
-    );
-
-    let buffer: Vec<u8> = fc
-        .try_into()
+    args: &[ParameterRef<'_>],
+) -> Result<T> {
+    wrapper_getter
+        .get_mgr_wrapper()
+        .unwrap_mgr()
+        .payload_limits()
+        .check_parameter_refs(args)?;
+
+    let buffer = serialize_function_call(function_name, args, FunctionCallType::Guest, return_type)
         .map_err(|_| HyperlightError::Error("Failed to serialize FunctionCall".to_string()))?;
 
     {
@@ -57,13 +96,58 @@ pub(crate) fn call_function_on_guest<WrapperGetterT: WrapperGetter>(
         mem_mgr.as_mut().write_guest_function_call(&buffer)?;
     }
 
+    run_function_on_guest(wrapper_getter, function_name)
+}
+
+/// Serialize a call to the guest function `function_name` and write it to
+/// the input data buffer `slot` with `writer`, so that it can be run with
+/// `run_function_on_guest` once the guest is pointed at the slot.
+#[instrument(err(Debug), skip(writer, limits, args), parent = Span::current(), level = "Trace")]
+pub(crate) fn write_function_call_to_slot(
+    writer: &mut IoSlotWriter,
+    limits: PayloadLimits,
+    slot: usize,
+    function_name: &str,
+    return_type: ReturnType,
+    args: &[ParameterRef<'_>],
+) -> Result<()> {
+    limits.check_parameter_refs(args)?;
+    let buffer = serialize_function_call(function_name, args, FunctionCallType::Guest, return_type)
+        .map_err(|_| HyperlightError::Error("Failed to serialize FunctionCall".to_string()))?;
+    writer.write(slot, &buffer)
+}
+
+/// Run the guest function call `function_name` that has already been
+/// written to the input data buffer the guest is pointed at, using the
+/// given `wrapper_getter`.
+#[instrument(err(Debug), skip(wrapper_getter), parent = Span::current(), level = "Trace")]
+pub(crate) fn run_function_on_guest<WrapperGetterT: WrapperGetter, T: GuestCallOutput>(
+    wrapper_getter: &mut WrapperGetterT,
+    function_name: &str,
+) -> Result<T> {
+    let mut timedout = false;
+
+    // the secrets are only in guest memory while the guest runs, so that
+    // a secret wiped after it is read doesn't come back with the snapshot
+    // the memory is restored from
+    wrapper_getter
+        .get_mgr_wrapper_mut()
+        .as_mut()
+        .inject_secrets()?;
     let mut hv_handler = wrapper_getter.get_hv_handler().clone();
-    match hv_handler.execute_hypervisor_handler_action(
+    let dispatched = hv_handler.execute_hypervisor_handler_action(
         HypervisorHandlerAction::DispatchCallFromHost(function_name.to_string()),
-    ) {
+    );
+    wrapper_getter
+        .get_mgr_wrapper_mut()
+        .as_mut()
+        .withdraw_secrets()?;
+    match dispatched {
         Ok(()) => {}
         Err(e) => match e {
-            HyperlightError::HypervisorHandlerMessageReceiveTimedout() => {
+            HyperlightError::HypervisorHandlerMessageReceiveTimedout()
+            | HyperlightError::GuestHeartbeatLapsed(_)
+            | HyperlightError::GuestHostCallIntervalExceeded(_) => {
                 timedout = true;
                 match hv_handler.terminate_hypervisor_handler_execution_and_reinitialise(
                     wrapper_getter.get_mgr_wrapper_mut().unwrap_mgr_mut(),
@@ -72,7 +156,16 @@ pub(crate) fn call_function_on_guest<WrapperGetterT: WrapperGetter>(
                         {}
                     // ^^^ do nothing, we just want to actually get the Flatbuffer return value
                     // from shared memory in this case
-                    e => return Err(e),
+                    HyperlightError::ExecutionCanceledByHost()
+                        if matches!(
+                            e,
+                            HyperlightError::GuestHeartbeatLapsed(_)
+                                | HyperlightError::GuestHostCallIntervalExceeded(_)
+                        ) =>
+                    {
+                        return Err(e)
+                    }
+                    cancel_err => return Err(cancel_err),
                 }
             }
             e => return Err(e),
@@ -83,24 +176,21 @@ pub(crate) fn call_function_on_guest<WrapperGetterT: WrapperGetter>(
     mem_mgr.check_stack_guard()?; // <- wrapper around mem_mgr `check_for_stack_guard`
     check_for_guest_error(mem_mgr)?;
 
-    mem_mgr
-        .as_mut()
-        .get_guest_function_call_result()
-        .map_err(|e| {
-            if timedout {
-                // if we timed-out, but still got here
-                // that means we had actually gotten stuck
-                // on the execution of a host function, and;
-                // hence, couldn't cancel guest execution.
-                // This particular check is needed now, because
-                // unlike w/ the previous scoped thread usage,
-                // we can't check if the thread completed or not.
-                log::error!("Guest execution hung on host function call");
-                GuestExecutionHungOnHostFunctionCall()
-            } else {
-                e
-            }
-        })
+    T::read(mem_mgr.as_mut()).map_err(|e| {
+        if timedout {
+            // if we timed-out, but still got here
+            // that means we had actually gotten stuck
+            // on the execution of a host function, and;
+            // hence, couldn't cancel guest execution.
+            // This particular check is needed now, because
+            // unlike w/ the previous scoped thread usage,
+            // we can't check if the thread completed or not.
+            log::error!("Guest execution hung on host function call");
+            GuestExecutionHungOnHostFunctionCall()
+        } else {
+            e
+        }
+    })
 }
 
 #[cfg(test)]
@@ -108,6 +198,8 @@ mod tests {
     use std::sync::{Arc, Mutex};
     use std::thread;
 
+    use hyperlight_common::flatbuffer_wrappers::function_types::ParameterValue;
+    use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
     use hyperlight_testing::{callback_guest_as_string, simple_guest_as_string};
 
     use super::*;
@@ -481,6 +573,70 @@ mod tests {
         }
     }
 
+    // The same as above, with an interrupt policy that recreates the sandbox
+    // instead of leaving it unusable.
+    #[test]
+    fn test_recreate_sandbox_calling_host_spinning_cpu() {
+        use crate::sandbox::{
+            InterruptEscalation, InterruptPolicy, SandboxConfiguration, SandboxState,
+        };
+
+        if !is_hypervisor_present() {
+            println!("Skipping test_call_guest_function_by_name because no hypervisor is present");
+            return;
+        }
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_interrupt_policy(InterruptPolicy {
+            escalation: InterruptEscalation::Recreate,
+            ..Default::default()
+        });
+        let mut usbox = UninitializedSandbox::new(
+            GuestBinary::FilePath(callback_guest_as_string().expect("Guest Binary Missing")),
+            Some(cfg),
+            None,
+            None,
+        )
+        .unwrap();
+
+        fn spin() -> Result<()> {
+            thread::sleep(std::time::Duration::from_secs(5));
+            Ok(())
+        }
+
+        let host_spin_func = Arc::new(Mutex::new(spin));
+
+        #[cfg(any(target_os = "windows", not(feature = "seccomp")))]
+        host_spin_func.register(&mut usbox, "Spin").unwrap();
+
+        #[cfg(all(target_os = "linux", feature = "seccomp"))]
+        host_spin_func
+            .register_with_extra_allowed_syscalls(
+                &mut usbox,
+                "Spin",
+                vec![libc::SYS_clock_nanosleep],
+            )
+            .unwrap();
+
+        let mut sandbox: MultiUseSandbox = usbox.evolve(Noop::default()).unwrap();
+        let result = sandbox.call_guest_function_by_name("CallHostSpin", ReturnType::Void, None);
+        assert!(matches!(
+            result,
+            Err(HyperlightError::GuestExecutionHungOnHostFunctionCall())
+        ));
+
+        // the sandbox was recreated while the host function was still
+        // running on the killed thread
+        assert_eq!(SandboxState::Ready, sandbox.state());
+        let result = sandbox
+            .call_guest_function_by_name(
+                "PrintOutput",
+                ReturnType::Int,
+                Some(vec![ParameterValue::String("recreated\n".to_string())]),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::Int(10), result);
+    }
+
     #[test]
     #[cfg(not(inprocess))]
     fn test_trigger_exception_on_guest() {
@@ -513,4 +669,51 @@ mod tests {
             ),
         }
     }
+
+    #[test]
+    #[cfg(not(inprocess))]
+    fn test_handled_exception_on_guest() {
+        let usbox = UninitializedSandbox::new(
+            GuestBinary::FilePath(simple_guest_as_string().expect("Guest Binary Missing")),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+
+        let mut multi_use_sandbox: MultiUseSandbox = usbox.evolve(Noop::default()).unwrap();
+
+        // the handler skips the faulting instruction
+        let res = multi_use_sandbox
+            .call_guest_function_by_name(
+                "TriggerHandledException",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Bool(true)]),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::Int(1), res);
+
+        // the handler turns the exception into a guest error
+        let res = multi_use_sandbox.call_guest_function_by_name(
+            "TriggerHandledException",
+            ReturnType::Int,
+            Some(vec![ParameterValue::Bool(false)]),
+        );
+        match res.unwrap_err() {
+            HyperlightError::GuestError(ErrorCode::GuestError, msg) => {
+                assert!(msg.starts_with("Invalid opcode at"));
+            }
+            e => panic!("Expected HyperlightError::GuestError but got {:?}", e),
+        }
+
+        // the sandbox is still usable
+        let res = multi_use_sandbox
+            .call_guest_function_by_name(
+                "TriggerHandledException",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Bool(true)]),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::Int(1), res);
+    }
 }
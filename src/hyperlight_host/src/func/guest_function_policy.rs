@@ -0,0 +1,160 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use hyperlight_common::flatbuffer_wrappers::function_call::{
+    is_in_namespace, split_function_version, FUNCTION_NAMESPACE_SEPARATOR,
+};
+
+/// A pattern matching guest function names
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GuestFunctionPattern {
+    /// Every guest function, written `*`
+    All,
+    /// Every guest function in the namespace, or in a namespace nested in
+    /// it, written `math::*`
+    Namespace(String),
+    /// The guest function with exactly this name, such as `math::add`, and
+    /// every version of it, such as `math::add@v2`
+    Function(String),
+}
+
+impl GuestFunctionPattern {
+    /// Whether `function_name` matches this pattern
+    pub fn matches(&self, function_name: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Namespace(namespace) => is_in_namespace(function_name, namespace),
+            Self::Function(name) => {
+                name == function_name
+                    || split_function_version(function_name).is_some_and(|(f, _)| f == name)
+            }
+        }
+    }
+}
+
+impl From<&str> for GuestFunctionPattern {
+    /// Parse `*`, `namespace::*` or a function name
+    fn from(pattern: &str) -> Self {
+        if pattern == "*" {
+            return Self::All;
+        }
+        match pattern
+            .strip_suffix('*')
+            .and_then(|p| p.strip_suffix(FUNCTION_NAMESPACE_SEPARATOR))
+        {
+            Some(namespace) => Self::Namespace(namespace.to_string()),
+            None => Self::Function(pattern.to_string()),
+        }
+    }
+}
+
+/// Which guest functions may be called on a sandbox.
+///
+/// A function may be called if it matches an allowed pattern and no denied
+/// pattern, so a whole namespace can be allowed with `math::*` while one of
+/// its functions is denied with `math::div`. The default policy allows
+/// every guest function.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GuestFunctionPolicy {
+    allowed: Vec<GuestFunctionPattern>,
+    denied: Vec<GuestFunctionPattern>,
+}
+
+impl Default for GuestFunctionPolicy {
+    fn default() -> Self {
+        Self {
+            allowed: vec![GuestFunctionPattern::All],
+            denied: Vec::new(),
+        }
+    }
+}
+
+impl GuestFunctionPolicy {
+    /// Create a policy that allows no guest functions, to allow only those
+    /// matching the patterns later passed to `allow`
+    pub fn deny_all() -> Self {
+        Self {
+            allowed: Vec::new(),
+            denied: Vec::new(),
+        }
+    }
+
+    /// Allow calls to the guest functions matching `pattern`, unless they
+    /// are denied
+    pub fn allow(&mut self, pattern: impl Into<GuestFunctionPattern>) {
+        self.allowed.push(pattern.into());
+    }
+
+    /// Deny calls to the guest functions matching `pattern`, even if they
+    /// are allowed
+    pub fn deny(&mut self, pattern: impl Into<GuestFunctionPattern>) {
+        self.denied.push(pattern.into());
+    }
+
+    /// Whether this policy permits calls to `function_name`
+    pub fn permits(&self, function_name: &str) -> bool {
+        self.allowed.iter().any(|p| p.matches(function_name))
+            && !self.denied.iter().any(|p| p.matches(function_name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GuestFunctionPattern, GuestFunctionPolicy};
+
+    #[test]
+    fn parse_patterns() {
+        assert_eq!(GuestFunctionPattern::All, "*".into());
+        assert_eq!(
+            GuestFunctionPattern::Namespace("math::trig".to_string()),
+            "math::trig::*".into()
+        );
+        assert_eq!(
+            GuestFunctionPattern::Function("math::add".to_string()),
+            "math::add".into()
+        );
+
+        let pattern = GuestFunctionPattern::from("math::*");
+        assert!(pattern.matches("math::add"));
+        assert!(pattern.matches("math::trig::sin"));
+        assert!(!pattern.matches("io::read"));
+        assert!(!pattern.matches("math"));
+    }
+
+    #[test]
+    fn deny_takes_precedence() {
+        assert!(GuestFunctionPolicy::default().permits("anything"));
+
+        let mut policy = GuestFunctionPolicy::deny_all();
+        assert!(!policy.permits("math::add"));
+        policy.allow("math::*");
+        policy.deny("math::div");
+        assert!(policy.permits("math::add"));
+        assert!(!policy.permits("math::div"));
+        assert!(!policy.permits("io::read"));
+    }
+
+    #[test]
+    fn functions_match_their_versions() {
+        let mut policy = GuestFunctionPolicy::deny_all();
+        policy.allow("Compute");
+        policy.deny("Compute@v1");
+        assert!(policy.permits("Compute"));
+        assert!(policy.permits("Compute@v2"));
+        assert!(!policy.permits("Compute@v1"));
+        assert!(!policy.permits("ComputeMore@v2"));
+    }
+}
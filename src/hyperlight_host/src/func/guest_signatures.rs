@@ -0,0 +1,417 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use hyperlight_common::flatbuffer_wrappers::function_call::{
+    split_function_version, versioned_function_name,
+};
+use hyperlight_common::flatbuffer_wrappers::function_types::{
+    ParameterRef, ParameterType, ParameterValue, ReturnType,
+};
+use hyperlight_common::flatbuffer_wrappers::host_function_definition::FunctionFlags;
+use hyperlight_common::flatbuffer_wrappers::host_function_details::HostFunctionDetails;
+use hyperlight_common::flatbuffer_wrappers::payload_limits::PayloadLimits;
+use tracing::{instrument, Span};
+
+use crate::{log_then_return, HyperlightError, Result};
+
+/// The signature of a function the guest registered with the guest library
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GuestFunctionSignature {
+    /// The function name
+    pub function_name: String,
+    /// The types of the function's parameters
+    pub parameter_types: Vec<ParameterType>,
+    /// The type of the function's return value
+    pub return_type: ReturnType,
+    /// Whether the guest declared the function pure, idempotent or
+    /// read-only
+    pub flags: FunctionFlags,
+    /// The largest size in bytes the guest declared the function's
+    /// serialized return value can be, if it declared one
+    pub max_result_size: Option<u64>,
+}
+
+/// The signatures of the functions the guest registered, as reported by
+/// the guest library when the sandbox was created.
+///
+/// Functions the guest did not register, such as those handled by its
+/// `guest_dispatch_function` or registered through the C API, are not
+/// known to the host, and calls to them are only checked by the guest.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct GuestFunctionSignatures(HashMap<String, GuestFunctionSignature>);
+
+impl GuestFunctionSignatures {
+    /// Read the signatures from the `HostFunctionDetails` flatbuffer
+    /// returned by the guest
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn from_flatbuffer(buffer: &[u8]) -> Result<Self> {
+        let details = HostFunctionDetails::try_from(buffer)?;
+        Ok(Self(
+            details
+                .host_functions
+                .into_iter()
+                .flatten()
+                .map(|f| {
+                    let signature = GuestFunctionSignature {
+                        function_name: f.function_name.clone(),
+                        parameter_types: f.parameter_types.unwrap_or_default(),
+                        return_type: f.return_type,
+                        flags: f.flags,
+                        max_result_size: f.max_result_size,
+                    };
+                    (f.function_name, signature)
+                })
+                .collect(),
+        ))
+    }
+
+    /// The signatures of the functions the guest registered
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &GuestFunctionSignature> {
+        self.0.values()
+    }
+
+    /// The flags the guest declared for `function_name`, which are none if
+    /// the guest didn't register it
+    pub(crate) fn flags(&self, function_name: &str) -> FunctionFlags {
+        self.0
+            .get(function_name)
+            .map_or(FunctionFlags::NONE, |s| s.flags)
+    }
+
+    /// The largest result the guest declared `function_name` can return,
+    /// if it registered the function and declared one
+    pub(crate) fn max_result_size(&self, function_name: &str) -> Option<u64> {
+        self.0.get(function_name).and_then(|s| s.max_result_size)
+    }
+
+    /// Check that the result `function_name` declared it can return fits
+    /// in an output data buffer of `output_size` bytes and within `limits`,
+    /// so that a call that could overflow the buffer fails before the
+    /// guest runs
+    #[instrument(err(Debug), skip(self, limits), parent = Span::current(), level = "Trace")]
+    pub(crate) fn check_result_size(
+        &self,
+        function_name: &str,
+        limits: PayloadLimits,
+        output_size: usize,
+    ) -> Result<()> {
+        let Some(max_result_size) = self.max_result_size(function_name) else {
+            return Ok(());
+        };
+        let size = usize::try_from(max_result_size).unwrap_or(usize::MAX);
+        if let Err(e) = limits.check_payload_size(size, output_size) {
+            log_then_return!(HyperlightError::GuestFunctionResultTooLarge(
+                function_name.to_string(),
+                max_result_size,
+                e.max_size
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether the guest registered `function_name`
+    pub(crate) fn contains(&self, function_name: &str) -> bool {
+        self.0.contains_key(function_name)
+    }
+
+    /// The versions of `function_name` the guest registered, oldest first
+    pub(crate) fn versions(&self, function_name: &str) -> Vec<u32> {
+        let mut versions: Vec<u32> = self
+            .0
+            .keys()
+            .filter_map(|name| split_function_version(name))
+            .filter(|(name, _)| *name == function_name)
+            .map(|(_, version)| version)
+            .collect();
+        versions.sort_unstable();
+        versions
+    }
+
+    /// The name of the guest function a call to `function_name` is made
+    /// to. A name with a version, such as `Compute@v2`, is called as it is,
+    /// unless the guest registered other versions of the function but not
+    /// that one. A name without a version calls `default_version` of the
+    /// function if there is one, or else the function registered with
+    /// that name, or else the newest version of it the guest registered.
+    pub(crate) fn resolve_version<'a>(
+        &self,
+        function_name: &'a str,
+        default_version: Option<u32>,
+    ) -> Result<Cow<'a, str>> {
+        if let Some((name, version)) = split_function_version(function_name) {
+            let versions = self.versions(name);
+            if !versions.is_empty() && !versions.contains(&version) {
+                log_then_return!(HyperlightError::GuestFunctionVersionNotFound(
+                    name.to_string(),
+                    version,
+                    versions
+                ));
+            }
+            return Ok(Cow::Borrowed(function_name));
+        }
+        if let Some(version) = default_version {
+            return Ok(Cow::Owned(versioned_function_name(function_name, version)));
+        }
+        if self.contains(function_name) {
+            return Ok(Cow::Borrowed(function_name));
+        }
+        Ok(match self.versions(function_name).last() {
+            Some(newest) => Cow::Owned(versioned_function_name(function_name, *newest)),
+            None => Cow::Borrowed(function_name),
+        })
+    }
+
+    /// Check that `args` match the parameter types `function_name` was
+    /// registered with, if the guest registered it
+    #[instrument(err(Debug), skip(self, args), parent = Span::current(), level = "Trace")]
+    pub(crate) fn check(&self, function_name: &str, args: &[ParameterRef<'_>]) -> Result<()> {
+        let Some(expected) = self.0.get(function_name).map(|s| &s.parameter_types) else {
+            return Ok(());
+        };
+        let actual: Vec<ParameterType> = args.iter().map(|a| a.into()).collect();
+        if let Some(index) =
+            (0..expected.len().max(actual.len())).find(|&i| expected.get(i) != actual.get(i))
+        {
+            log_then_return!(HyperlightError::GuestFunctionParameterTypeMismatch(
+                function_name.to_string(),
+                expected.clone(),
+                actual,
+                index
+            ));
+        }
+        Ok(())
+    }
+
+    /// Widen the arguments in `args` to the parameter types `function_name`
+    /// was registered with, where that can't lose information. Arguments to
+    /// functions the guest did not register are returned unchanged.
+    #[instrument(skip(self, args), parent = Span::current(), level = "Trace")]
+    pub(crate) fn coerce(
+        &self,
+        function_name: &str,
+        args: Vec<ParameterValue>,
+    ) -> Vec<ParameterValue> {
+        let Some(expected) = self.0.get(function_name).map(|s| &s.parameter_types) else {
+            return args;
+        };
+        args.into_iter()
+            .enumerate()
+            .map(|(i, arg)| match (arg, expected.get(i)) {
+                (ParameterValue::Int(v), Some(ParameterType::Long)) => {
+                    ParameterValue::Long(v.into())
+                }
+                (ParameterValue::UInt(v), Some(ParameterType::ULong)) => {
+                    ParameterValue::ULong(v.into())
+                }
+                (ParameterValue::Float(v), Some(ParameterType::Double)) => {
+                    ParameterValue::Double(v.into())
+                }
+                (arg, _) => arg,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::flatbuffer_wrappers::function_types::{
+        ParameterRef, ParameterType, ParameterValue, ReturnType,
+    };
+    use hyperlight_common::flatbuffer_wrappers::host_function_definition::{
+        FunctionFlags, HostFunctionDefinition,
+    };
+    use hyperlight_common::flatbuffer_wrappers::host_function_details::HostFunctionDetails;
+    use hyperlight_common::flatbuffer_wrappers::payload_limits::PayloadLimits;
+
+    use super::GuestFunctionSignatures;
+    use crate::HyperlightError;
+
+    fn signatures() -> GuestFunctionSignatures {
+        let details = HostFunctionDetails::new(Some(vec![
+            HostFunctionDefinition::new(
+                "Add".to_string(),
+                Some(vec![ParameterType::Int, ParameterType::Long]),
+                ReturnType::Long,
+            ),
+            HostFunctionDefinition::new("NoArgs".to_string(), None, ReturnType::Void)
+                .with_flags(FunctionFlags::IDEMPOTENT | FunctionFlags::READ_ONLY),
+        ]));
+        let buffer: Vec<u8> = (&details).try_into().unwrap();
+        GuestFunctionSignatures::from_flatbuffer(&buffer).unwrap()
+    }
+
+    #[test]
+    fn matching_and_unknown_calls_pass() {
+        let signatures = signatures();
+        signatures
+            .check("Add", &[ParameterRef::Int(1), ParameterRef::Long(2)])
+            .unwrap();
+        signatures.check("NoArgs", &[]).unwrap();
+        signatures
+            .check("NotRegistered", &[ParameterRef::Bool(true)])
+            .unwrap();
+    }
+
+    #[test]
+    fn flags() {
+        let signatures = signatures();
+        assert_eq!(FunctionFlags::NONE, signatures.flags("Add"));
+        let flags = signatures.flags("NoArgs");
+        assert!(flags.is_idempotent() && flags.is_read_only() && !flags.is_pure());
+        assert_eq!(FunctionFlags::NONE, signatures.flags("NotRegistered"));
+        assert!(FunctionFlags::PURE.is_idempotent() && FunctionFlags::PURE.is_read_only());
+        // unknown bits are dropped
+        assert_eq!(FunctionFlags::PURE, FunctionFlags::from_bits(0x81));
+    }
+
+    #[test]
+    fn mismatch_names_function_types_and_index() {
+        let signatures = signatures();
+        let err = signatures
+            .check("Add", &[ParameterRef::Int(1), ParameterRef::Int(2)])
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            HyperlightError::GuestFunctionParameterTypeMismatch(ref name, ref expected, ref actual, 1)
+                if name == "Add"
+                    && expected == &[ParameterType::Int, ParameterType::Long]
+                    && actual == &[ParameterType::Int, ParameterType::Int]
+        ));
+
+        // a missing parameter is reported at the first index not passed
+        let err = signatures
+            .check("Add", &[ParameterRef::Int(1)])
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            HyperlightError::GuestFunctionParameterTypeMismatch(_, _, _, 1)
+        ));
+        let err = signatures
+            .check("NoArgs", &[ParameterRef::Int(1)])
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            HyperlightError::GuestFunctionParameterTypeMismatch(_, _, _, 0)
+        ));
+    }
+
+    #[test]
+    fn result_sizes() {
+        let details = HostFunctionDetails::new(Some(vec![
+            HostFunctionDefinition::new("Small".to_string(), None, ReturnType::VecBytes)
+                .with_max_result_size(0x100),
+            HostFunctionDefinition::new("Large".to_string(), None, ReturnType::VecBytes)
+                .with_max_result_size(0x10000),
+            HostFunctionDefinition::new("Undeclared".to_string(), None, ReturnType::VecBytes),
+        ]));
+        let buffer: Vec<u8> = (&details).try_into().unwrap();
+        let signatures = GuestFunctionSignatures::from_flatbuffer(&buffer).unwrap();
+        assert_eq!(Some(0x100), signatures.max_result_size("Small"));
+        assert_eq!(None, signatures.max_result_size("Undeclared"));
+
+        let limits = PayloadLimits::default();
+        let check = |name| signatures.check_result_size(name, limits, 0x4000);
+        check("Small").unwrap();
+        check("Undeclared").unwrap();
+        check("NotRegistered").unwrap();
+        assert!(matches!(
+            check("Large").unwrap_err(),
+            HyperlightError::GuestFunctionResultTooLarge(ref name, 0x10000, 0x4000)
+                if name == "Large"
+        ));
+
+        // the maximum payload size limits results as well as the buffer
+        let limits = PayloadLimits {
+            max_payload_size: 0x80,
+            max_parameter_size: 0,
+        };
+        assert!(matches!(
+            signatures.check_result_size("Small", limits, 0x4000),
+            Err(HyperlightError::GuestFunctionResultTooLarge(_, 0x100, 0x80))
+        ));
+    }
+
+    #[test]
+    fn versions() {
+        let details = HostFunctionDetails::new(Some(vec![
+            HostFunctionDefinition::new("Compute@v1".to_string(), None, ReturnType::Int),
+            HostFunctionDefinition::new("Compute@v10".to_string(), None, ReturnType::Int),
+            HostFunctionDefinition::new("Compute@v2".to_string(), None, ReturnType::Int),
+            HostFunctionDefinition::new("Plain".to_string(), None, ReturnType::Int),
+            HostFunctionDefinition::new("Plain@v1".to_string(), None, ReturnType::Int),
+        ]));
+        let buffer: Vec<u8> = (&details).try_into().unwrap();
+        let signatures = GuestFunctionSignatures::from_flatbuffer(&buffer).unwrap();
+        assert_eq!(vec![1, 2, 10], signatures.versions("Compute"));
+        assert_eq!(Vec::<u32>::new(), signatures.versions("NotRegistered"));
+
+        let resolve = |name, default| signatures.resolve_version(name, default).unwrap();
+        // the newest version, unless there's a default
+        assert_eq!("Compute@v10", resolve("Compute", None));
+        assert_eq!("Compute@v2", resolve("Compute", Some(2)));
+        assert_eq!("Compute@v1", resolve("Compute@v1", Some(2)));
+        // a function registered without a version is preferred
+        assert_eq!("Plain", resolve("Plain", None));
+        assert_eq!("Plain@v1", resolve("Plain", Some(1)));
+        // functions the guest didn't register are left to the guest
+        assert_eq!("NotRegistered", resolve("NotRegistered", None));
+        assert_eq!("NotRegistered@v3", resolve("NotRegistered@v3", None));
+
+        let err = signatures.resolve_version("Compute@v3", None).unwrap_err();
+        assert!(matches!(
+            err,
+            HyperlightError::GuestFunctionVersionNotFound(ref name, 3, ref versions)
+                if name == "Compute" && versions == &[1, 2, 10]
+        ));
+    }
+
+    #[test]
+    fn coerce_widens_only_to_registered_types() {
+        let details = HostFunctionDetails::new(Some(vec![HostFunctionDefinition::new(
+            "Wide".to_string(),
+            Some(vec![
+                ParameterType::Long,
+                ParameterType::ULong,
+                ParameterType::Double,
+                ParameterType::Int,
+            ]),
+            ReturnType::Void,
+        )]));
+        let buffer: Vec<u8> = (&details).try_into().unwrap();
+        let signatures = GuestFunctionSignatures::from_flatbuffer(&buffer).unwrap();
+
+        let args = vec![
+            ParameterValue::Int(-1),
+            ParameterValue::UInt(u32::MAX),
+            ParameterValue::Float(1.5),
+            ParameterValue::Long(1),
+        ];
+        assert_eq!(
+            vec![
+                ParameterValue::Long(-1),
+                ParameterValue::ULong(u32::MAX.into()),
+                ParameterValue::Double(1.5),
+                // narrowing is never done, so this still fails the check
+                ParameterValue::Long(1),
+            ],
+            signatures.coerce("Wide", args.clone())
+        );
+        assert_eq!(args, signatures.coerce("NotRegistered", args.clone()));
+    }
+}
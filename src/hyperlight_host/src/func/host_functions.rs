@@ -225,6 +225,12 @@ macro_rules! host_function {
                 });
 
                 let parameter_types = Some(vec![$($P::get_hyperlight_type()),*]);
+                let sensitive: Vec<bool> = vec![$($P::is_sensitive()),*];
+                let sensitive_parameters = sensitive
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, sensitive)| sensitive.then_some(index))
+                    .collect();
 
                 if let Some(_eas) = extra_allowed_syscalls {
                     if cfg!(all(feature = "seccomp", target_os = "linux")) {
@@ -266,6 +272,11 @@ macro_rules! host_function {
                             HyperlightFunction::new(func),
                         )?;
                 }
+                sandbox
+                    .host_funcs
+                    .try_lock()
+                    .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+                    .set_sensitive_parameters(name, sensitive_parameters);
 
                 Ok(())
             }
@@ -0,0 +1,222 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Handles to host objects that the guest holds on to between host
+//! function calls, rather than a copy of the object.
+//!
+//! A host function returns a `Handle` to an object it put in the
+//! sandbox's `HostHandles`, and the guest passes the handle, a `ULong`,
+//! back to the host functions that use the object. The object stays on
+//! the host, so it is never copied into guest memory.
+//!
+//! The guest only remembers the handles it was given for as long as its
+//! memory does, so the objects behind the handles created during a guest
+//! function call are dropped when the sandbox's state is restored after
+//! it. Objects created while the guest was initialised, or during the
+//! guest function calls a sandbox is evolved with, are part of the state
+//! the sandbox is restored to, and are kept until the sandbox is dropped
+//! or they are removed.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use tracing::{instrument, Span};
+
+use crate::{new_error, Result};
+
+/// A reference to an object in a sandbox's `HostHandles`, passed to and
+/// from the guest as a `ULong`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Handle(u64);
+
+impl Handle {
+    /// The value the guest is given for the handle
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for Handle {
+    fn from(id: u64) -> Self {
+        Handle(id)
+    }
+}
+
+struct HandleTable {
+    /// Handles are never reused, so a handle the guest kept from before
+    /// its memory was restored doesn't refer to a newer object
+    next_id: u64,
+    /// The handles below this were created before the sandbox's state was
+    /// last captured, and survive it being restored
+    kept_below: u64,
+    objects: HashMap<u64, Box<dyn Any + Send>>,
+}
+
+impl Default for HandleTable {
+    fn default() -> Self {
+        // 0 is left unused, so that guests can use it as a null handle
+        Self {
+            next_id: 1,
+            kept_below: 1,
+            objects: HashMap::new(),
+        }
+    }
+}
+
+/// The host objects the guest of a sandbox holds handles to, shared by
+/// the sandbox and the host functions registered with it
+#[derive(Clone, Default)]
+pub struct HostHandles(Arc<Mutex<HandleTable>>);
+
+impl fmt::Debug for HostHandles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostHandles").finish_non_exhaustive()
+    }
+}
+
+impl HostHandles {
+    fn table(&self) -> Result<MutexGuard<'_, HandleTable>> {
+        self.0
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))
+    }
+
+    /// Keep `object` on the host, returning the handle to give the guest
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub fn insert<T: Any + Send>(&self, object: T) -> Result<Handle> {
+        let mut table = self.table()?;
+        let id = table.next_id;
+        table.next_id += 1;
+        table.objects.insert(id, Box::new(object));
+        Ok(Handle(id))
+    }
+
+    /// Call `f` with the object `handle` refers to, which fails if there
+    /// is no such object or it isn't a `T`, e.g. because the guest passed
+    /// a handle it made up or one from before its state was restored
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub fn with<T: Any + Send, R>(&self, handle: Handle, f: impl FnOnce(&mut T) -> R) -> Result<R> {
+        let mut table = self.table()?;
+        let object = table
+            .objects
+            .get_mut(&handle.0)
+            .ok_or_else(|| new_error!("No host object with handle {}", handle.0))?
+            .downcast_mut::<T>()
+            .ok_or_else(|| {
+                new_error!(
+                    "The host object with handle {} is not a {}",
+                    handle.0,
+                    std::any::type_name::<T>()
+                )
+            })?;
+        Ok(f(object))
+    }
+
+    /// Remove the object `handle` refers to, returning it. The guest can't
+    /// use the handle afterwards.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub fn remove<T: Any + Send>(&self, handle: Handle) -> Result<T> {
+        let mut table = self.table()?;
+        match table.objects.remove(&handle.0) {
+            Some(object) => match object.downcast::<T>() {
+                Ok(object) => Ok(*object),
+                Err(object) => {
+                    table.objects.insert(handle.0, object);
+                    Err(new_error!(
+                        "The host object with handle {} is not a {}",
+                        handle.0,
+                        std::any::type_name::<T>()
+                    ))
+                }
+            },
+            None => Err(new_error!("No host object with handle {}", handle.0)),
+        }
+    }
+
+    /// The number of objects the guest holds handles to
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub fn len(&self) -> Result<usize> {
+        Ok(self.table()?.objects.len())
+    }
+
+    /// Whether the guest holds no handles
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Keep the objects that exist now when the sandbox's state is
+    /// restored, because they are part of the state it is restored to
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn keep_all(&self) -> Result<()> {
+        let mut table = self.table()?;
+        table.kept_below = table.next_id;
+        Ok(())
+    }
+
+    /// Drop the objects created since the sandbox's state was last
+    /// captured, whose handles the guest forgets when its state is
+    /// restored
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn release_unkept(&self) -> Result<()> {
+        let mut table = self.table()?;
+        let kept_below = table.kept_below;
+        table.objects.retain(|id, _| *id < kept_below);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Handle, HostHandles};
+
+    #[test]
+    fn objects_are_found_by_handle_and_type() {
+        let handles = HostHandles::default();
+        let text = handles.insert("hello".to_string()).unwrap();
+        let number = handles.insert(42u64).unwrap();
+        assert_ne!(text, number);
+        assert_ne!(0, text.id());
+
+        assert_eq!(5, handles.with(text, |s: &mut String| s.len()).unwrap());
+        handles.with(number, |n: &mut u64| *n += 1).unwrap();
+        // the wrong type or an unknown handle are errors
+        assert!(handles.with(number, |s: &mut String| s.len()).is_err());
+        assert!(handles.with(Handle::from(1000), |n: &mut u64| *n).is_err());
+
+        assert!(handles.remove::<String>(number).is_err());
+        assert_eq!(43, handles.remove::<u64>(number).unwrap());
+        assert!(handles.remove::<u64>(number).is_err());
+        assert_eq!(1, handles.len().unwrap());
+    }
+
+    #[test]
+    fn only_kept_objects_survive_a_restore() {
+        let handles = HostHandles::default();
+        let kept = handles.insert(1u8).unwrap();
+        handles.keep_all().unwrap();
+        let released = handles.insert(2u8).unwrap();
+        handles.release_unkept().unwrap();
+        assert_eq!(1, handles.with(kept, |n: &mut u8| *n).unwrap());
+        assert!(handles.with(released, |n: &mut u8| *n).is_err());
+
+        // handles aren't reused after being released
+        let next = handles.insert(3u8).unwrap();
+        assert_ne!(released, next);
+    }
+}
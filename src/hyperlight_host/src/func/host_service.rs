@@ -0,0 +1,253 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Registering all of the methods of a host service as host functions in
+//! a namespace with a single call.
+//!
+//! A host service is a type whose methods the guest calls as host
+//! functions, such as a file system service with `read` and `write`
+//! methods. Rather than wrapping each method in a closure and registering
+//! it on its own, declare the service's trait inside the `host_service!`
+//! macro, which implements `HostService` for `dyn Trait + Send`, and
+//! register any implementation of the trait with
+//! `UninitializedSandbox::register_host_service`:
+//!
+//! ```no_run
+//! use std::sync::{Arc, Mutex};
+//!
+//! use hyperlight_host::{host_service, GuestBinary, Result, UninitializedSandbox};
+//!
+//! host_service! {
+//!     pub trait FsService {
+//!         fn read(&mut self, path: String) -> Result<Vec<u8>>;
+//!         fn write(&mut self, path: String, data: Vec<u8>) -> Result<i32>;
+//!     }
+//! }
+//!
+//! struct MemoryFs(std::collections::HashMap<String, Vec<u8>>);
+//!
+//! impl FsService for MemoryFs {
+//!     fn read(&mut self, path: String) -> Result<Vec<u8>> {
+//!         Ok(self.0.get(&path).cloned().unwrap_or_default())
+//!     }
+//!     fn write(&mut self, path: String, data: Vec<u8>) -> Result<i32> {
+//!         let len = data.len() as i32;
+//!         self.0.insert(path, data);
+//!         Ok(len)
+//!     }
+//! }
+//!
+//! # fn main() -> Result<()> {
+//! let mut sandbox = UninitializedSandbox::new(
+//!     GuestBinary::FilePath("guest".to_string()),
+//!     None,
+//!     None,
+//!     None,
+//! )?;
+//! // the guest calls `fs::read` and `fs::write`
+//! let fs: Arc<Mutex<dyn FsService + Send>> = Arc::new(Mutex::new(MemoryFs(Default::default())));
+//! sandbox.register_host_service("fs", fs)?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Each method of the trait is registered as a host function named after
+//! the method in the namespace, e.g. `fs::read`. The methods must take
+//! `&self` or `&mut self`, their parameter types must be supported by
+//! `SupportedParameterType`, and they must return a `hyperlight_host::Result`
+//! of a type supported by `SupportedReturnType`. The methods are called
+//! with the service locked, so only one of them runs at a time.
+//!
+//! A type whose methods aren't declared by a trait can list them instead,
+//! with their parameter and return types:
+//!
+//! ```no_run
+//! use hyperlight_host::{host_service, Result};
+//!
+//! struct Counter(i32);
+//!
+//! impl Counter {
+//!     fn add(&mut self, n: i32) -> Result<i32> {
+//!         self.0 += n;
+//!         Ok(self.0)
+//!     }
+//! }
+//!
+//! host_service! {
+//!     impl HostService for Counter {
+//!         fn add(n: i32) -> i32;
+//!     }
+//! }
+//! ```
+
+use std::sync::{Arc, Mutex};
+
+use crate::{Result, UninitializedSandbox};
+
+/// A type whose methods can be registered as host functions in a
+/// namespace with `UninitializedSandbox::register_host_service`. Implement
+/// it with the `host_service!` macro, which implements it for `dyn Trait +
+/// Send` when given a trait.
+pub trait HostService: Send + 'static {
+    /// Register each of the service's methods as a host function named
+    /// after the method in `namespace`
+    fn register_methods(
+        service: Arc<Mutex<Self>>,
+        sandbox: &mut UninitializedSandbox,
+        namespace: &str,
+    ) -> Result<()>;
+}
+
+/// Declare a trait and implement `HostService` for `dyn Trait + Send`, so
+/// that the methods of any implementation of the trait can be registered
+/// as host functions, or implement `HostService` for a type by listing the
+/// methods to register, with their parameters and return types. Listed
+/// methods must take `&self` or `&mut self` and the listed parameters, and
+/// return a `hyperlight_host::Result` of the listed return type, which is
+/// `()` if it is left out. See the `host_service` module for examples.
+#[macro_export]
+macro_rules! host_service {
+    (
+        $(#[$attr:meta])*
+        $vis:vis trait $service:ident {
+            $(
+                $(#[$method_attr:meta])*
+                fn $method:ident $params:tt -> $ret:ty;
+            )*
+        }
+    ) => {
+        $(#[$attr])*
+        $vis trait $service {
+            $(
+                $(#[$method_attr])*
+                fn $method $params -> $ret;
+            )*
+        }
+
+        impl $crate::func::host_service::HostService for dyn $service + Send {
+            fn register_methods(
+                service: ::std::sync::Arc<::std::sync::Mutex<Self>>,
+                sandbox: &mut $crate::UninitializedSandbox,
+                namespace: &str,
+            ) -> $crate::Result<()> {
+                $(
+                    $crate::host_service!(@method service, sandbox, namespace, $method, $ret, $params)?;
+                )*
+                Ok(())
+            }
+        }
+    };
+    (
+        impl HostService for $service:ty {
+            $(fn $method:ident($($arg:ident: $arg_ty:ty),* $(,)?) $(-> $ret:ty)?;)*
+        }
+    ) => {
+        impl $crate::func::host_service::HostService for $service {
+            fn register_methods(
+                service: ::std::sync::Arc<::std::sync::Mutex<Self>>,
+                sandbox: &mut $crate::UninitializedSandbox,
+                namespace: &str,
+            ) -> $crate::Result<()> {
+                $({
+                    let service = service.clone();
+                    let method = ::std::sync::Arc::new(::std::sync::Mutex::new(
+                        move |$($arg: $arg_ty),*| -> $crate::Result<$crate::host_service!(@ret $($ret)?)> {
+                            service
+                                .lock()
+                                .map_err(|e| $crate::new_error!(
+                                    "Error locking host service at {}:{}: {}",
+                                    file!(),
+                                    line!(),
+                                    e
+                                ))?
+                                .$method($($arg),*)
+                        },
+                    ));
+                    let name = $crate::func::host_service::method_name(namespace, stringify!($method));
+                    $crate::host_service!(@register method, sandbox, &name, $($arg)*)?;
+                })*
+                Ok(())
+            }
+        }
+    };
+    (@method $service:ident, $sandbox:ident, $namespace:ident, $method:ident, $ret:ty, (&mut self $(, $arg:ident: $arg_ty:ty)* $(,)?)) => {
+        $crate::host_service!(@trait_method $service, $sandbox, $namespace, $method, $ret, $($arg: $arg_ty),*)
+    };
+    (@method $service:ident, $sandbox:ident, $namespace:ident, $method:ident, $ret:ty, (&self $(, $arg:ident: $arg_ty:ty)* $(,)?)) => {
+        $crate::host_service!(@trait_method $service, $sandbox, $namespace, $method, $ret, $($arg: $arg_ty),*)
+    };
+    (@trait_method $service:ident, $sandbox:ident, $namespace:ident, $method:ident, $ret:ty, $($arg:ident: $arg_ty:ty),*) => {{
+        let service = $service.clone();
+        let method = ::std::sync::Arc::new(::std::sync::Mutex::new(
+            move |$($arg: $arg_ty),*| -> $ret {
+                service
+                    .lock()
+                    .map_err(|e| $crate::new_error!(
+                        "Error locking host service at {}:{}: {}",
+                        file!(),
+                        line!(),
+                        e
+                    ))?
+                    .$method($($arg),*)
+            },
+        ));
+        let name = $crate::func::host_service::method_name($namespace, stringify!($method));
+        $crate::host_service!(@register method, $sandbox, &name, $($arg)*)
+    }};
+    (@ret) => { () };
+    (@ret $ret:ty) => { $ret };
+    (@register $f:ident, $sandbox:ident, $name:expr,) => {
+        $crate::func::HostFunction0::register(&$f, $sandbox, $name)
+    };
+    (@register $f:ident, $sandbox:ident, $name:expr, $a1:ident) => {
+        $crate::func::HostFunction1::register(&$f, $sandbox, $name)
+    };
+    (@register $f:ident, $sandbox:ident, $name:expr, $a1:ident $a2:ident) => {
+        $crate::func::HostFunction2::register(&$f, $sandbox, $name)
+    };
+    (@register $f:ident, $sandbox:ident, $name:expr, $a1:ident $a2:ident $a3:ident) => {
+        $crate::func::HostFunction3::register(&$f, $sandbox, $name)
+    };
+    (@register $f:ident, $sandbox:ident, $name:expr, $a1:ident $a2:ident $a3:ident $a4:ident) => {
+        $crate::func::HostFunction4::register(&$f, $sandbox, $name)
+    };
+    (@register $f:ident, $sandbox:ident, $name:expr, $a1:ident $a2:ident $a3:ident $a4:ident $a5:ident) => {
+        $crate::func::HostFunction5::register(&$f, $sandbox, $name)
+    };
+    (@register $f:ident, $sandbox:ident, $name:expr, $a1:ident $a2:ident $a3:ident $a4:ident $a5:ident $a6:ident) => {
+        $crate::func::HostFunction6::register(&$f, $sandbox, $name)
+    };
+    (@register $f:ident, $sandbox:ident, $name:expr, $a1:ident $a2:ident $a3:ident $a4:ident $a5:ident $a6:ident $a7:ident) => {
+        $crate::func::HostFunction7::register(&$f, $sandbox, $name)
+    };
+    (@register $f:ident, $sandbox:ident, $name:expr, $a1:ident $a2:ident $a3:ident $a4:ident $a5:ident $a6:ident $a7:ident $a8:ident) => {
+        $crate::func::HostFunction8::register(&$f, $sandbox, $name)
+    };
+    (@register $f:ident, $sandbox:ident, $name:expr, $a1:ident $a2:ident $a3:ident $a4:ident $a5:ident $a6:ident $a7:ident $a8:ident $a9:ident) => {
+        $crate::func::HostFunction9::register(&$f, $sandbox, $name)
+    };
+    (@register $f:ident, $sandbox:ident, $name:expr, $a1:ident $a2:ident $a3:ident $a4:ident $a5:ident $a6:ident $a7:ident $a8:ident $a9:ident $a10:ident) => {
+        $crate::func::HostFunction10::register(&$f, $sandbox, $name)
+    };
+}
+
+/// The name a host service's `method` is registered with in `namespace`
+#[doc(hidden)]
+pub fn method_name(namespace: &str, method: &str) -> String {
+    hyperlight_common::flatbuffer_wrappers::function_call::namespaced_function_name(
+        namespace, method,
+    )
+}
@@ -0,0 +1,168 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tracing::{instrument, Span};
+
+use crate::sandbox::SandboxConfiguration;
+use crate::{new_error, Result};
+
+/// The chunks of data a streaming host function returns to the guest, in
+/// order. The guest reads them one at a time, so the data never has to fit
+/// in the input data buffer, or in memory, all at once.
+pub type ChunkStream = Box<dyn Iterator<Item = Result<Vec<u8>>> + Send>;
+
+/// The name of the host function the guest calls to read the next chunk of
+/// a stream returned by a streaming host function
+pub(crate) const READ_NEXT_CHUNK_FUNCTION_NAME: &str = "HostReadNextChunk";
+
+/// Room left in the input data buffer, beside a chunk, for the flatbuffer
+/// the chunk is returned to the guest in
+const CHUNK_OVERHEAD: usize = 1024;
+
+struct OpenStream {
+    chunks: ChunkStream,
+    /// The rest of a chunk that was too large to return to the guest at once
+    remainder: Vec<u8>,
+}
+
+#[derive(Default)]
+struct StreamTable {
+    next_id: u64,
+    streams: HashMap<u64, OpenStream>,
+}
+
+/// The streams returned to the guest by streaming host functions that the
+/// guest hasn't finished reading, shared by the host functions of a
+/// sandbox
+#[derive(Clone, Default)]
+pub(crate) struct HostChunkStreams(Arc<Mutex<StreamTable>>);
+
+impl HostChunkStreams {
+    /// Keep `chunks` for the guest to read, returning the id the guest
+    /// reads it by
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn open(&self, chunks: ChunkStream) -> Result<u64> {
+        let mut table = self
+            .0
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
+        let id = table.next_id;
+        table.next_id += 1;
+        table.streams.insert(
+            id,
+            OpenStream {
+                chunks,
+                remainder: Vec::new(),
+            },
+        );
+        Ok(id)
+    }
+
+    /// Return the next chunk of at most `max_len` bytes of the stream
+    /// `id`, or an empty chunk, and close the stream, once it is finished.
+    /// Empty chunks from the stream are skipped, so that an empty chunk
+    /// always means the stream is finished.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn read_next_chunk(&self, id: u64, max_len: usize) -> Result<Vec<u8>> {
+        let mut table = self
+            .0
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
+        let stream = table
+            .streams
+            .get_mut(&id)
+            .ok_or_else(|| new_error!("No host function stream with id {}", id))?;
+        let mut chunk = std::mem::take(&mut stream.remainder);
+        while chunk.is_empty() {
+            match stream.chunks.next() {
+                Some(next) => chunk = next?,
+                None => break,
+            }
+        }
+        if chunk.is_empty() {
+            table.streams.remove(&id);
+        } else if chunk.len() > max_len {
+            stream.remainder = chunk.split_off(max_len);
+        }
+        Ok(chunk)
+    }
+
+    /// Close every open stream, such as those the guest didn't finish
+    /// reading before its memory was restored
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn close_all(&self) -> Result<()> {
+        self.0
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+            .streams
+            .clear();
+        Ok(())
+    }
+}
+
+/// The largest chunk that can be returned to the guest by a sandbox
+/// configured with `cfg`
+pub(crate) fn max_chunk_size(cfg: &SandboxConfiguration) -> usize {
+    let mut max = cfg.get_input_data_size();
+    if cfg.get_max_call_payload_size() != 0 {
+        max = max.min(cfg.get_max_call_payload_size());
+    }
+    max = max.saturating_sub(CHUNK_OVERHEAD);
+    if cfg.get_max_parameter_size() != 0 {
+        max = max.min(cfg.get_max_parameter_size());
+    }
+    max.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HostChunkStreams;
+    use crate::new_error;
+
+    #[test]
+    fn chunks_are_split_and_streams_closed() {
+        let streams = HostChunkStreams::default();
+        let chunks: Vec<crate::Result<Vec<u8>>> = vec![Ok(vec![1; 10]), Ok(vec![]), Ok(vec![2; 3])];
+        let id = streams.open(Box::new(chunks.into_iter())).unwrap();
+
+        let read: Vec<_> = std::iter::from_fn(|| {
+            Some(streams.read_next_chunk(id, 4).unwrap()).filter(|c| !c.is_empty())
+        })
+        .collect();
+        assert_eq!(vec![vec![1; 4], vec![1; 4], vec![1; 2], vec![2; 3]], read);
+        // the stream was closed when it finished
+        assert!(streams.read_next_chunk(id, 4).is_err());
+    }
+
+    #[test]
+    fn errors_are_returned_and_streams_can_be_closed() {
+        let streams = HostChunkStreams::default();
+        let chunks: Vec<crate::Result<Vec<u8>>> = vec![Ok(vec![1]), Err(new_error!("read failed"))];
+        let id = streams.open(Box::new(chunks.into_iter())).unwrap();
+        assert_eq!(vec![1], streams.read_next_chunk(id, 4).unwrap());
+        assert!(streams.read_next_chunk(id, 4).is_err());
+
+        let other = streams
+            .open(Box::new(std::iter::once(Ok(vec![3]))))
+            .unwrap();
+        assert_ne!(id, other);
+        streams.close_all().unwrap();
+        assert!(streams.read_next_chunk(other, 4).is_err());
+    }
+}
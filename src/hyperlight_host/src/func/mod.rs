@@ -15,14 +15,39 @@ limitations under the License.
 */
 
 use crate::{new_error, Result};
+/// Rules for the arguments of host functions, checked before the host
+/// functions are called
+pub mod arg_validation;
+/// Views of bytes returned by a guest function, borrowed from the
+/// sandbox's output data buffer
+pub mod borrowed_bytes;
 /// Context structures used to allow the user to call one or more guest
 /// functions on the same Hyperlight sandbox instance, all from within the
 /// same state and mutual exclusion context.
 pub mod call_ctx;
+/// Recording the guest function calls made through a call context, to
+/// rewind the guest to an earlier call and re-execute the calls from there
+pub mod call_recording;
+/// Tracing the guest and host function calls made on a sandbox into a
+/// portable trace file, and replaying a trace against a guest
+pub mod call_trace;
+/// Generation of typed Rust clients for the functions a guest registers
+pub mod client_gen;
+/// Caching the results of calls to pure guest functions on the host
+pub mod guest_call_cache;
+/// Interceptors that see, and can rewrite, answer or deny, every guest
+/// function call made on a sandbox
+pub mod guest_call_interceptor;
 /// Functionality to dispatch a call from the host to the guest
 pub(crate) mod guest_dispatch;
 /// Functionality to check for errors after a guest call
 pub(crate) mod guest_err;
+/// Policies restricting which guest functions, or namespaces of guest
+/// functions, can be called on a sandbox
+pub mod guest_function_policy;
+/// Functionality to check guest function calls against the signatures the
+/// guest registered its functions with
+pub(crate) mod guest_signatures;
 /// Definitions and functionality to enable guest-to-host function calling,
 /// also called "host functions"
 ///
@@ -35,21 +60,74 @@ pub(crate) mod guest_err;
 /// - Dynamically dispatching a call from the guest to the appropriate
 ///   host function
 pub mod host_functions;
+/// Handles to host objects that the guest passes back to host functions
+/// instead of a copy of the object
+pub mod host_handles;
+/// Registering all of the methods of a host service as host functions in
+/// a namespace with a single call
+pub mod host_service;
+/// Host functions that return their data to the guest in chunks
+pub mod host_stream;
 /// Definitions and functionality for supported parameter types
 pub(crate) mod param_type;
+/// Redacting the parameters of guest function calls that must not be
+/// logged or traced
+pub mod redaction;
 /// Definitions and functionality for supported return types
 pub mod ret_type;
+/// Series of guest function calls that keep the guest's state between them
+pub mod session;
 
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, TryLockError};
 
+/// Re-export for `ArgRule` type
+pub use arg_validation::ArgRule;
+/// Re-export for `ArgumentValidation` type
+pub use arg_validation::ArgumentValidation;
+/// Re-export for `BorrowedBytes` type
+pub use borrowed_bytes::BorrowedBytes;
+/// Re-export for `CachePolicy` type
+pub use guest_call_cache::CachePolicy;
+/// Re-export for `GuestCallCache` type
+pub use guest_call_cache::GuestCallCache;
+/// Re-export for `GuestCallAction` enum
+pub use guest_call_interceptor::GuestCallAction;
+/// Re-export for `GuestCallInterceptor` trait
+pub use guest_call_interceptor::GuestCallInterceptor;
+/// Re-export for `GuestFunctionPattern` enum
+pub use guest_function_policy::GuestFunctionPattern;
+/// Re-export for `GuestFunctionPolicy` type
+pub use guest_function_policy::GuestFunctionPolicy;
+/// Re-export for `GuestFunctionSignature` type
+pub use guest_signatures::GuestFunctionSignature;
+/// Re-export for `Handle` type
+pub use host_handles::Handle;
+/// Re-export for `HostHandles` type
+pub use host_handles::HostHandles;
+/// Re-export for `HostService` trait
+pub use host_service::HostService;
+/// Re-export for `ChunkStream` type
+pub use host_stream::ChunkStream;
+/// Re-export for `ParameterRef` enum
+pub use hyperlight_common::flatbuffer_wrappers::function_types::ParameterRef;
 /// Re-export for `ParameterValue` enum
 pub use hyperlight_common::flatbuffer_wrappers::function_types::ParameterValue;
 /// Re-export for `ReturnType` enum
 pub use hyperlight_common::flatbuffer_wrappers::function_types::ReturnType;
 /// Re-export for `ReturnType` enum
 pub use hyperlight_common::flatbuffer_wrappers::function_types::ReturnValue;
+/// Re-export for `FunctionFlags` type
+pub use hyperlight_common::flatbuffer_wrappers::host_function_definition::FunctionFlags;
 pub use param_type::SupportedParameterType;
+/// Re-export for `RedactedArgs` type
+pub use redaction::RedactedArgs;
+/// Re-export for `RedactionPolicy` type
+pub use redaction::RedactionPolicy;
+/// Re-export for `Sensitive` type
+pub use redaction::Sensitive;
 pub use ret_type::SupportedReturnType;
+/// Re-export for `Session` type
+pub use session::Session;
 use tracing::{instrument, Span};
 
 type HLFunc = Arc<Mutex<Box<dyn FnMut(Vec<ParameterValue>) -> Result<ReturnValue> + Send>>>;
@@ -69,10 +147,20 @@ impl HyperlightFunction {
 
     #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
     pub(crate) fn call(&self, args: Vec<ParameterValue>) -> Result<ReturnValue> {
-        let mut f = self
-            .0
-            .try_lock()
-            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
+        let mut f = match self.0.try_lock() {
+            Ok(f) => f,
+            // the lock is poisoned if an earlier call panicked, which is
+            // handed to the guest as an error, so the function stays usable
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(e) => {
+                return Err(new_error!(
+                    "Error locking at {}:{}: {}",
+                    file!(),
+                    line!(),
+                    e
+                ))
+            }
+        };
         f(args)
     }
 }
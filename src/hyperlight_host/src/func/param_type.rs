@@ -17,6 +17,8 @@ limitations under the License.
 use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterType, ParameterValue};
 use tracing::{instrument, Span};
 
+use super::host_handles::Handle;
+use super::redaction::{Sensitive, REDACTED};
 use crate::HyperlightError::ParameterValueConversionFailure;
 use crate::{log_then_return, Result};
 
@@ -34,6 +36,10 @@ pub trait SupportedParameterType<T> {
     fn get_hyperlight_value(&self) -> ParameterValue;
     /// Get the actual inner value of this `SupportedParameterType`
     fn get_inner(a: ParameterValue) -> Result<T>;
+    /// Whether the parameter must never be shown, see `Sensitive`
+    fn is_sensitive() -> bool {
+        false
+    }
 }
 
 // We can then implement these traits for each type that Hyperlight supports as a parameter or return type
@@ -147,6 +153,94 @@ impl SupportedParameterType<u64> for u64 {
     }
 }
 
+impl SupportedParameterType<i16> for i16 {
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_type() -> ParameterType {
+        ParameterType::Short
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_value(&self) -> ParameterValue {
+        ParameterValue::Short(*self)
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    fn get_inner(a: ParameterValue) -> Result<i16> {
+        match a {
+            ParameterValue::Short(v) => Ok(v),
+            other => {
+                log_then_return!(ParameterValueConversionFailure(other.clone(), "i16"));
+            }
+        }
+    }
+}
+
+impl SupportedParameterType<u16> for u16 {
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_type() -> ParameterType {
+        ParameterType::UShort
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_value(&self) -> ParameterValue {
+        ParameterValue::UShort(*self)
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    fn get_inner(a: ParameterValue) -> Result<u16> {
+        match a {
+            ParameterValue::UShort(v) => Ok(v),
+            other => {
+                log_then_return!(ParameterValueConversionFailure(other.clone(), "u16"));
+            }
+        }
+    }
+}
+
+impl SupportedParameterType<i8> for i8 {
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_type() -> ParameterType {
+        ParameterType::Byte
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_value(&self) -> ParameterValue {
+        ParameterValue::Byte(*self)
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    fn get_inner(a: ParameterValue) -> Result<i8> {
+        match a {
+            ParameterValue::Byte(v) => Ok(v),
+            other => {
+                log_then_return!(ParameterValueConversionFailure(other.clone(), "i8"));
+            }
+        }
+    }
+}
+
+impl SupportedParameterType<u8> for u8 {
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_type() -> ParameterType {
+        ParameterType::UByte
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_value(&self) -> ParameterValue {
+        ParameterValue::UByte(*self)
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    fn get_inner(a: ParameterValue) -> Result<u8> {
+        match a {
+            ParameterValue::UByte(v) => Ok(v),
+            other => {
+                log_then_return!(ParameterValueConversionFailure(other.clone(), "u8"));
+            }
+        }
+    }
+}
+
 impl SupportedParameterType<bool> for bool {
     #[instrument(skip_all, parent = Span::current(), level= "Trace")]
     fn get_hyperlight_type() -> ParameterType {
@@ -190,3 +284,55 @@ impl SupportedParameterType<Vec<u8>> for Vec<u8> {
         }
     }
 }
+
+impl SupportedParameterType<Handle> for Handle {
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_type() -> ParameterType {
+        ParameterType::ULong
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_value(&self) -> ParameterValue {
+        ParameterValue::ULong(self.id())
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    fn get_inner(a: ParameterValue) -> Result<Handle> {
+        match a {
+            ParameterValue::ULong(ul) => Ok(Handle::from(ul)),
+            other => {
+                log_then_return!(ParameterValueConversionFailure(other.clone(), "Handle"));
+            }
+        }
+    }
+}
+
+impl<T: SupportedParameterType<T>> SupportedParameterType<Sensitive<T>> for Sensitive<T> {
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_type() -> ParameterType {
+        T::get_hyperlight_type()
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_value(&self) -> ParameterValue {
+        self.0.get_hyperlight_value()
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    fn get_inner(a: ParameterValue) -> Result<Sensitive<T>> {
+        // check the type first, so that the value isn't in the error
+        let expected = T::get_hyperlight_type();
+        if ParameterType::from(&a) != expected {
+            log_then_return!(
+                "Failed to convert a {} parameter value to {:?}",
+                REDACTED,
+                expected
+            );
+        }
+        T::get_inner(a).map(Sensitive)
+    }
+
+    fn is_sensitive() -> bool {
+        true
+    }
+}
@@ -0,0 +1,221 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::{Deref, DerefMut};
+
+use hyperlight_common::flatbuffer_wrappers::function_types::ParameterValue;
+
+use super::guest_function_policy::GuestFunctionPattern;
+
+/// What a redacted parameter is shown as
+pub const REDACTED: &str = "<redacted>";
+
+/// A parameter of a host function that must never be shown, such as a
+/// password the guest passes to the host. A host function declared with a
+/// `Sensitive<String>` parameter is called with the `String` the guest
+/// passed, but the value is left out of the `host_call` spans created with
+/// the `boundary_spans` feature, and of the errors for arguments that fail
+/// the function's `ArgumentValidation` or don't have the parameter's type.
+///
+/// It is shown as `REDACTED` when it is formatted with `Debug`.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Hash)]
+pub struct Sensitive<T>(pub T);
+
+impl<T> Sensitive<T> {
+    /// The value this wraps
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Sensitive<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Sensitive<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> Debug for Sensitive<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+/// Which parameters of which guest functions must never be shown, such as
+/// passwords or personal data, so that guest function calls can be logged
+/// and traced. The rules also apply to the host functions the guest calls
+/// by the name they are registered with, as well as their `Sensitive`
+/// parameters.
+///
+/// A parameter is redacted if it matches any of the policy's rules. The
+/// default policy redacts nothing.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RedactionPolicy {
+    /// The functions whose parameters are redacted, and the index of the
+    /// parameter, or `None` for all of them
+    rules: Vec<(GuestFunctionPattern, Option<usize>)>,
+}
+
+impl RedactionPolicy {
+    /// Redact the parameter at `index` of the guest functions matching
+    /// `pattern`
+    pub fn redact(&mut self, pattern: impl Into<GuestFunctionPattern>, index: usize) {
+        self.rules.push((pattern.into(), Some(index)));
+    }
+
+    /// Redact every parameter of the guest functions matching `pattern`
+    pub fn redact_all(&mut self, pattern: impl Into<GuestFunctionPattern>) {
+        self.rules.push((pattern.into(), None));
+    }
+
+    /// Whether the parameter at `index` of `function_name` is redacted
+    pub fn is_redacted(&self, function_name: &str, index: usize) -> bool {
+        self.rules.iter().any(|(pattern, i)| {
+            pattern.matches(function_name) && (i.is_none() || *i == Some(index))
+        })
+    }
+
+    /// `args`, the parameters of a call to `function_name`, formatted with
+    /// the redacted ones replaced by `REDACTED`
+    pub fn apply<'a, T: Debug>(
+        &'a self,
+        function_name: &'a str,
+        args: &'a [T],
+    ) -> RedactedArgs<'a, T> {
+        RedactedArgs {
+            policy: self,
+            function_name,
+            args,
+            sensitive: &[],
+        }
+    }
+
+    /// As `apply`, also redacting the parameters at the indexes in
+    /// `sensitive`
+    pub(crate) fn apply_with_sensitive<'a, T: Debug>(
+        &'a self,
+        function_name: &'a str,
+        args: &'a [T],
+        sensitive: &'a [usize],
+    ) -> RedactedArgs<'a, T> {
+        RedactedArgs {
+            sensitive,
+            ..self.apply(function_name, args)
+        }
+    }
+}
+
+/// The parameters of a guest function call, which are formatted with the
+/// parameters a `RedactionPolicy` redacts replaced by `REDACTED`. The
+/// parameters are `ParameterValue`s, or `ParameterRef`s for calls made
+/// with borrowed parameters.
+pub struct RedactedArgs<'a, T = ParameterValue> {
+    policy: &'a RedactionPolicy,
+    function_name: &'a str,
+    args: &'a [T],
+    /// The indexes of the `Sensitive` parameters of a host function
+    sensitive: &'a [usize],
+}
+
+impl<T: Debug> Debug for RedactedArgs<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut list = f.debug_list();
+        for (i, arg) in self.args.iter().enumerate() {
+            if self.sensitive.contains(&i) || self.policy.is_redacted(self.function_name, i) {
+                list.entry(&format_args!("{}", REDACTED));
+            } else {
+                list.entry(arg);
+            }
+        }
+        list.finish()
+    }
+}
+
+impl<T: Debug> Display for RedactedArgs<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::flatbuffer_wrappers::function_types::ParameterValue;
+
+    use super::{RedactionPolicy, Sensitive};
+    use crate::func::SupportedParameterType;
+
+    #[test]
+    fn redacts_matching_parameters() {
+        let args = [
+            ParameterValue::String("alice".to_string()),
+            ParameterValue::String("hunter2".to_string()),
+        ];
+        let mut policy = RedactionPolicy::default();
+        assert_eq!(
+            r#"[String("alice"), String("hunter2")]"#,
+            format!("{}", policy.apply("auth::login", &args))
+        );
+
+        policy.redact("auth::login", 1);
+        policy.redact_all("secrets::*");
+        assert!(policy.is_redacted("auth::login", 1));
+        assert!(!policy.is_redacted("auth::login", 0));
+        assert!(!policy.is_redacted("auth::logout", 1));
+        assert!(policy.is_redacted("secrets::put", 0));
+        assert_eq!(
+            r#"[String("alice"), <redacted>]"#,
+            format!("{}", policy.apply("auth::login", &args))
+        );
+        assert_eq!(
+            "[<redacted>, <redacted>]",
+            format!("{:?}", policy.apply("secrets::put", &args))
+        );
+        assert_eq!(
+            "[<redacted>, <redacted>]",
+            format!(
+                "{:?}",
+                policy.apply_with_sensitive("auth::login", &args, &[0])
+            )
+        );
+    }
+
+    #[test]
+    fn sensitive_parameters() {
+        let password = Sensitive("hunter2".to_string());
+        assert_eq!("<redacted>", format!("{:?}", password));
+        assert_eq!(7, password.len());
+        assert!(<Sensitive<String>>::is_sensitive());
+        assert!(!<String>::is_sensitive());
+
+        let value = password.get_hyperlight_value();
+        assert_eq!(ParameterValue::String("hunter2".to_string()), value);
+        let password = <Sensitive<String>>::get_inner(value).unwrap();
+        assert_eq!("hunter2", password.into_inner());
+
+        // the value isn't in the error for an argument of the wrong type
+        let err =
+            <Sensitive<i32>>::get_inner(ParameterValue::String("hunter2".to_string())).unwrap_err();
+        assert!(!format!("{:?}", err).contains("hunter2"));
+    }
+}
@@ -17,6 +17,7 @@ limitations under the License.
 use hyperlight_common::flatbuffer_wrappers::function_types::{ReturnType, ReturnValue};
 use tracing::{instrument, Span};
 
+use super::host_handles::Handle;
 use crate::HyperlightError::ReturnValueConversionFailure;
 use crate::{log_then_return, Result};
 
@@ -164,6 +165,94 @@ impl SupportedReturnType<u64> for u64 {
     }
 }
 
+impl SupportedReturnType<i16> for i16 {
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_type() -> ReturnType {
+        ReturnType::Short
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_value(&self) -> ReturnValue {
+        ReturnValue::Short(*self)
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    fn get_inner(a: ReturnValue) -> Result<i16> {
+        match a {
+            ReturnValue::Short(v) => Ok(v),
+            other => {
+                log_then_return!(ReturnValueConversionFailure(other.clone(), "i16"));
+            }
+        }
+    }
+}
+
+impl SupportedReturnType<u16> for u16 {
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_type() -> ReturnType {
+        ReturnType::UShort
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_value(&self) -> ReturnValue {
+        ReturnValue::UShort(*self)
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    fn get_inner(a: ReturnValue) -> Result<u16> {
+        match a {
+            ReturnValue::UShort(v) => Ok(v),
+            other => {
+                log_then_return!(ReturnValueConversionFailure(other.clone(), "u16"));
+            }
+        }
+    }
+}
+
+impl SupportedReturnType<i8> for i8 {
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_type() -> ReturnType {
+        ReturnType::Byte
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_value(&self) -> ReturnValue {
+        ReturnValue::Byte(*self)
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    fn get_inner(a: ReturnValue) -> Result<i8> {
+        match a {
+            ReturnValue::Byte(v) => Ok(v),
+            other => {
+                log_then_return!(ReturnValueConversionFailure(other.clone(), "i8"));
+            }
+        }
+    }
+}
+
+impl SupportedReturnType<u8> for u8 {
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_type() -> ReturnType {
+        ReturnType::UByte
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_value(&self) -> ReturnValue {
+        ReturnValue::UByte(*self)
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    fn get_inner(a: ReturnValue) -> Result<u8> {
+        match a {
+            ReturnValue::UByte(v) => Ok(v),
+            other => {
+                log_then_return!(ReturnValueConversionFailure(other.clone(), "u8"));
+            }
+        }
+    }
+}
+
 impl SupportedReturnType<bool> for bool {
     #[instrument(skip_all, parent = Span::current(), level= "Trace")]
     fn get_hyperlight_type() -> ReturnType {
@@ -207,3 +296,25 @@ impl SupportedReturnType<Vec<u8>> for Vec<u8> {
         }
     }
 }
+
+impl SupportedReturnType<Handle> for Handle {
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_type() -> ReturnType {
+        ReturnType::ULong
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_hyperlight_value(&self) -> ReturnValue {
+        ReturnValue::ULong(self.id())
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    fn get_inner(a: ReturnValue) -> Result<Handle> {
+        match a {
+            ReturnValue::ULong(ul) => Ok(Handle::from(ul)),
+            other => {
+                log_then_return!(ReturnValueConversionFailure(other.clone(), "Handle"));
+            }
+        }
+    }
+}
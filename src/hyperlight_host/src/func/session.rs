@@ -0,0 +1,135 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use hyperlight_common::flatbuffer_wrappers::function_types::{
+    ParameterValue, ReturnType, ReturnValue,
+};
+use tracing::{instrument, Span};
+
+use crate::{MultiUseSandbox, Result};
+
+/// A series of guest function calls made back to back on a
+/// `MultiUseSandbox`, for stateful conversations with a guest.
+///
+/// The session borrows the sandbox mutably, so no other calls can be made
+/// on the sandbox until the session ends. Calls made through the session
+/// keep the guest state the calls before them left, and the sandbox is
+/// reset to the state it was in before the session once the session is
+/// ended with `end`, or dropped.
+///
+/// Unlike `MultiUseGuestCallContext`, the session doesn't take ownership
+/// of the sandbox, so the sandbox can't be lost by dropping the session.
+/// Calls made through the session are checked by the sandbox's guest
+/// function policy and intercepted by its guest call interceptor, but are
+/// not retried by its retry policy, as retrying would lose the session's
+/// state.
+#[derive(Debug)]
+pub struct Session<'a> {
+    sbox: &'a mut MultiUseSandbox,
+    ended: bool,
+}
+
+impl<'a> Session<'a> {
+    #[instrument(skip_all, parent = Span::current())]
+    pub(crate) fn start(sbox: &'a mut MultiUseSandbox) -> Self {
+        Self { sbox, ended: false }
+    }
+
+    /// Call the guest function called `func_name` with the given arguments
+    /// `args`, and expect the return value have the same type as
+    /// `func_ret_type`, keeping the guest state the previous calls of the
+    /// session left.
+    #[instrument(err(Debug), skip(self, args), parent = Span::current())]
+    pub fn call(
+        &mut self,
+        func_name: &str,
+        func_ret_type: ReturnType,
+        args: Option<Vec<ParameterValue>>,
+    ) -> Result<ReturnValue> {
+        self.sbox
+            .call_guest_function_no_reset(func_name, func_ret_type, args)
+    }
+
+    /// End the session, resetting the sandbox to the state it was in before
+    /// the session. Dropping the session does the same, but can only log
+    /// an error if resetting the sandbox fails.
+    #[instrument(err(Debug), skip(self), parent = Span::current())]
+    pub fn end(mut self) -> Result<()> {
+        self.ended = true;
+        self.sbox.restore_state()
+    }
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        if self.ended {
+            return;
+        }
+        if let Err(e) = self.sbox.restore_state() {
+            log::error!(
+                "Failed to reset sandbox {} at the end of a session: {:?}",
+                self.sbox.id(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::flatbuffer_wrappers::function_types::{
+        ParameterValue, ReturnType, ReturnValue,
+    };
+    use hyperlight_testing::simple_guest_as_string;
+
+    use crate::sandbox_state::sandbox::EvolvableSandbox;
+    use crate::sandbox_state::transition::Noop;
+    use crate::{GuestBinary, MultiUseSandbox, UninitializedSandbox};
+
+    #[test]
+    fn session_keeps_state_until_it_ends() {
+        let path = simple_guest_as_string().unwrap();
+        let mut sbox: MultiUseSandbox =
+            UninitializedSandbox::new(GuestBinary::FilePath(path), None, None, None)
+                .unwrap()
+                .evolve(Noop::default())
+                .unwrap();
+        let get_static = |sbox: &mut MultiUseSandbox| {
+            sbox.call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+                .unwrap()
+        };
+
+        for end_explicitly in [true, false] {
+            let mut session = sbox.session();
+            for expected in [5, 10] {
+                let res = session
+                    .call(
+                        "AddToStatic",
+                        ReturnType::Int,
+                        Some(vec![ParameterValue::Int(5)]),
+                    )
+                    .unwrap();
+                assert_eq!(ReturnValue::Int(expected), res);
+            }
+            if end_explicitly {
+                session.end().unwrap();
+            } else {
+                drop(session);
+            }
+            assert_eq!(ReturnValue::Int(0), get_static(&mut sbox));
+        }
+    }
+}
@@ -0,0 +1,228 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::sync::{Arc, Mutex};
+
+use hyperlight_common::flatbuffer_wrappers::function_types::ParameterValue;
+use tracing::{instrument, Span};
+
+use crate::func::HostFunction2;
+use crate::mem::boundary_copy;
+use crate::sandbox::config::SandboxConfiguration;
+use crate::sandbox::hypervisor::get_available_hypervisor;
+use crate::sandbox_state::sandbox::EvolvableSandbox;
+use crate::sandbox_state::transition::Noop;
+use crate::{GuestBinary, MultiUseSandbox, Result, UninitializedSandbox};
+
+/// The name of the host function registered by
+/// `new_sandbox_with_host_add`. It adds its two `i32` parameters.
+pub const HOST_ADD_FUNCTION_NAME: &str = "HostAdd";
+
+/// The sizes in bytes of the `VecBytes` parameters used to measure
+/// large-parameter throughput. The multi-megabyte sizes are copied into
+/// the guest with non-temporal stores.
+pub const LARGE_PARAMETER_SIZES: &[usize] = &[0x400, 0x4000, 0x10000, 0x40000, 0x100000, 0x400000];
+
+/// The sizes in bytes of the copies measured by the `boundary_copy`
+/// benchmarks
+pub const BOUNDARY_COPY_SIZES: &[usize] = &[0x100000, 0x400000];
+
+/// The smallest guest heap used by `config_for_parameter_size`
+const BENCH_MIN_HEAP_SIZE: u64 = 0x20000;
+
+/// Returns the name of the hypervisor backend that sandboxes created in
+/// this process will run on, e.g. "kvm", "mshv" or "whp", or "none" if no
+/// hypervisor is available.
+///
+/// Benchmark IDs should include this so that results from different
+/// backends are never compared against each other.
+pub fn backend_name() -> String {
+    match get_available_hypervisor() {
+        Some(hypervisor) => format!("{:?}", hypervisor).to_lowercase(),
+        None => "none".to_string(),
+    }
+}
+
+/// Returns `name` qualified with the current backend, suitable for use as a
+/// benchmark group name.
+pub fn benchmark_group_name(name: &str) -> String {
+    format!("{}/{}", backend_name(), name)
+}
+
+/// Returns a `SandboxConfiguration` whose input and output buffers can
+/// hold a `VecBytes` parameter (or return value) of `parameter_size` bytes,
+/// with a guest heap big enough for the guest to decode, copy and return it.
+pub fn config_for_parameter_size(parameter_size: usize) -> SandboxConfiguration {
+    // leave room for the rest of the flatbuffer and the stack pointers
+    let buffer_size = parameter_size + 0x1000;
+    let mut cfg = SandboxConfiguration::default();
+    cfg.set_input_data_size(buffer_size.max(SandboxConfiguration::DEFAULT_INPUT_SIZE));
+    cfg.set_output_data_size(buffer_size.max(SandboxConfiguration::DEFAULT_OUTPUT_SIZE));
+    // the guest holds several copies of the parameter while handling the call
+    cfg.set_heap_size((buffer_size as u64 * 8).max(BENCH_MIN_HEAP_SIZE));
+    cfg
+}
+
+/// Returns the parameters for a call that takes a single `VecBytes` of
+/// `size` bytes, such as `SetByteArrayToZero` in `simpleguest`.
+pub fn byte_array_parameter(size: usize) -> Vec<ParameterValue> {
+    vec![ParameterValue::VecBytes(vec![0xAB; size])]
+}
+
+/// How parameters and results are copied across the boundary between the
+/// host and the guest, to compare the copies `HostSharedMemory` makes with
+/// the byte at a time loop they replaced
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum BoundaryCopy {
+    /// A vector at a time, with non-temporal stores into the guest for
+    /// large copies
+    Vectorized,
+    /// A volatile access per byte
+    ByteLoop,
+}
+
+impl BoundaryCopy {
+    /// Copy `src` into `dst` as if `dst` was guest memory
+    ///
+    /// # Panics
+    ///
+    /// If `dst` and `src` have different lengths
+    pub fn copy_to_guest(self, dst: &mut [u8], src: &[u8]) {
+        assert_eq!(dst.len(), src.len());
+        let base = dst.as_mut_ptr();
+        match self {
+            // Safety: `dst` is valid for writes of `src.len()` bytes
+            BoundaryCopy::Vectorized => unsafe { boundary_copy::copy_to_guest(base, src) },
+            BoundaryCopy::ByteLoop => {
+                for (i, b) in src.iter().enumerate() {
+                    unsafe { base.wrapping_add(i).write_volatile(*b) };
+                }
+            }
+        }
+    }
+
+    /// Copy `src` into `dst` as if `src` was guest memory
+    ///
+    /// # Panics
+    ///
+    /// If `dst` and `src` have different lengths
+    pub fn copy_from_guest(self, dst: &mut [u8], src: &[u8]) {
+        assert_eq!(dst.len(), src.len());
+        let base = src.as_ptr();
+        match self {
+            // Safety: `src` is valid for reads of `dst.len()` bytes
+            BoundaryCopy::Vectorized => unsafe { boundary_copy::copy_from_guest(dst, base) },
+            BoundaryCopy::ByteLoop => {
+                for (i, b) in dst.iter_mut().enumerate() {
+                    *b = unsafe { base.wrapping_add(i).read_volatile() };
+                }
+            }
+        }
+    }
+}
+
+/// Creates a new `UninitializedSandbox` for the guest binary at
+/// `guest_path`.
+#[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+pub fn new_uninitialized_sandbox(
+    guest_path: &str,
+    cfg: Option<SandboxConfiguration>,
+) -> Result<UninitializedSandbox> {
+    UninitializedSandbox::new(
+        GuestBinary::FilePath(guest_path.to_string()),
+        cfg,
+        None,
+        None,
+    )
+}
+
+/// Creates a new `MultiUseSandbox` for the guest binary at `guest_path`.
+#[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+pub fn new_sandbox(guest_path: &str, cfg: Option<SandboxConfiguration>) -> Result<MultiUseSandbox> {
+    new_uninitialized_sandbox(guest_path, cfg)?.evolve(Noop::default())
+}
+
+/// Creates a new `MultiUseSandbox` for the guest binary at `guest_path`
+/// with a host function named `HOST_ADD_FUNCTION_NAME` registered, for
+/// measuring the latency of calls from the guest to the host.
+#[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+pub fn new_sandbox_with_host_add(guest_path: &str) -> Result<MultiUseSandbox> {
+    let mut uninitialized_sandbox = new_uninitialized_sandbox(guest_path, None)?;
+
+    fn add(a: i32, b: i32) -> Result<i32> {
+        Ok(a + b)
+    }
+    let host_function = Arc::new(Mutex::new(add));
+    host_function.register(&mut uninitialized_sandbox, HOST_ADD_FUNCTION_NAME)?;
+
+    uninitialized_sandbox.evolve(Noop::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::flatbuffer_wrappers::function_types::{ReturnType, ReturnValue};
+    use hyperlight_testing::simple_guest_as_string;
+
+    use super::*;
+
+    #[test]
+    fn backend_name_matches_hypervisor_presence() {
+        assert_eq!(crate::is_hypervisor_present(), backend_name() != "none");
+        assert!(benchmark_group_name("sandboxes").ends_with("/sandboxes"));
+    }
+
+    #[test]
+    fn largest_parameter_fits_in_config() {
+        let size = *LARGE_PARAMETER_SIZES.iter().max().unwrap();
+        let path = simple_guest_as_string().unwrap();
+        let mut sandbox = new_sandbox(&path, Some(config_for_parameter_size(size))).unwrap();
+        let res = sandbox
+            .call_guest_function_by_name(
+                "SetByteArrayToZero",
+                ReturnType::VecBytes,
+                Some(byte_array_parameter(size)),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::VecBytes(vec![0; size]), res);
+    }
+
+    #[test]
+    fn boundary_copies_agree() {
+        let src: Vec<u8> = (0..0x1003).map(|i| i as u8).collect();
+        for copy in [BoundaryCopy::Vectorized, BoundaryCopy::ByteLoop] {
+            let mut guest = vec![0; src.len()];
+            copy.copy_to_guest(&mut guest, &src);
+            assert_eq!(src, guest);
+            let mut host = vec![0; src.len()];
+            copy.copy_from_guest(&mut host, &guest);
+            assert_eq!(src, host);
+        }
+    }
+
+    #[test]
+    fn host_add_is_registered() {
+        let path = simple_guest_as_string().unwrap();
+        let mut sandbox = new_sandbox_with_host_add(&path).unwrap();
+        let res = sandbox
+            .call_guest_function_by_name(
+                "Add",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(1), ParameterValue::Int(41)]),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::Int(42), res);
+    }
+}
@@ -0,0 +1,301 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Out-of-tree hypervisor backends.
+//!
+//! A crate that implements `Hypervisor` for a VMM Hyperlight doesn't ship
+//! a driver for makes a sandbox use it by setting a factory with
+//! `UninitializedSandbox::set_hypervisor_backend`. The factory is called
+//! on the thread that runs the vCPU, in place of creating a KVM, MSHV or
+//! WHP driver, with the guest memory the backend has to map and the
+//! registers its vCPU starts with. Memory added to the guest after that is
+//! mapped and unmapped with `map_region` and `unmap_region`.
+//!
+//! The backend's `initialise` and `dispatch_call_from_host` run the vCPU
+//! with `VirtualCPU::run`, passing on the `VcpuContext` they are handed,
+//! which calls back into `run`, `handle_io` and the other methods of the
+//! trait. The trait's signatures are the same whatever features
+//! `hyperlight_host` is built with, and the methods a backend doesn't need
+//! have default bodies.
+//!
+//! Guest function calls are cancelled with the `VcpuInterrupt` the
+//! backend returns from `interrupt_handle`. A backend that returns `None`
+//! is interrupted on Linux by sending the thread running the vCPU a
+//! real-time signal, so `run` must then return `HyperlightExit::Cancelled`
+//! when the VMM's run call is interrupted by a signal.
+
+use std::sync::Arc;
+
+use super::Hypervisor;
+use crate::mem::memory_region::MemoryRegion;
+use crate::{log_then_return, Result};
+
+/// The guest memory and initial registers of a sandbox's vCPU, handed to
+/// the factory set with `UninitializedSandbox::set_hypervisor_backend`
+#[derive(Debug, Clone)]
+pub struct HypervisorPartition {
+    /// The regions of host memory to map into the guest, with the guest's
+    /// permissions to each of them
+    pub regions: Vec<MemoryRegion>,
+    /// The guest physical address of the PML4 table, to load into CR3
+    pub pml4_addr: u64,
+    /// The guest address of the guest's entrypoint, to load into RIP
+    pub entrypoint_addr: u64,
+    /// The guest address of the top of the guest's stack, to load into RSP
+    pub rsp_addr: u64,
+}
+
+type BackendFactory = Arc<dyn Fn(HypervisorPartition) -> Result<Box<dyn Hypervisor>> + Send + Sync>;
+
+/// The factory of the `Hypervisor` a sandbox's guest runs on, if the
+/// embedder replaced the built-in drivers, shared by every sandbox created
+/// from the same source
+#[derive(Clone, Default)]
+pub(crate) struct HypervisorBackend(Option<BackendFactory>);
+
+impl std::fmt::Debug for HypervisorBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HypervisorBackend")
+            .field("set", &self.0.is_some())
+            .finish()
+    }
+}
+
+impl HypervisorBackend {
+    pub(crate) fn new(
+        factory: impl Fn(HypervisorPartition) -> Result<Box<dyn Hypervisor>> + Send + Sync + 'static,
+    ) -> Self {
+        Self(Some(Arc::new(factory)))
+    }
+
+    /// Whether the sandbox runs on the embedder's backend rather than on
+    /// a built-in driver
+    pub(crate) fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// Create the hypervisor for `partition` with the factory
+    pub(crate) fn create(&self, partition: HypervisorPartition) -> Result<Box<dyn Hypervisor>> {
+        match &self.0 {
+            Some(factory) => factory(partition),
+            None => log_then_return!("No hypervisor backend was set"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    #[cfg(kvm)]
+    use hyperlight_common::flatbuffer_wrappers::function_types::{
+        ParameterValue, ReturnType, ReturnValue,
+    };
+    #[cfg(kvm)]
+    use hyperlight_common::mem::PAGE_SIZE_USIZE;
+    #[cfg(kvm)]
+    use hyperlight_testing::simple_guest_as_string;
+    #[cfg(kvm)]
+    use log::LevelFilter;
+
+    use super::{HypervisorBackend, HypervisorPartition};
+    #[cfg(kvm)]
+    use crate::hypervisor::handlers::OutBHandlerWrapper;
+    #[cfg(kvm)]
+    use crate::hypervisor::kvm::{self, KVMDriver};
+    #[cfg(kvm)]
+    use crate::hypervisor::{HyperlightExit, Hypervisor, VcpuContext};
+    #[cfg(kvm)]
+    use crate::mem::memory_region::{MemoryRegion, MemoryRegionFlags, MemoryRegionType};
+    #[cfg(kvm)]
+    use crate::mem::ptr::RawPtr;
+    #[cfg(kvm)]
+    use crate::mem::shared_mem::{ExclusiveSharedMemory, SharedMemory};
+    use crate::new_error;
+    #[cfg(kvm)]
+    use crate::sandbox_state::sandbox::EvolvableSandbox;
+    #[cfg(kvm)]
+    use crate::sandbox_state::transition::Noop;
+    #[cfg(kvm)]
+    use crate::{GuestBinary, MultiUseSandbox, Result, UninitializedSandbox};
+
+    fn partition() -> HypervisorPartition {
+        HypervisorPartition {
+            regions: Vec::new(),
+            pml4_addr: 0,
+            entrypoint_addr: 0x1000,
+            rsp_addr: 0x2000,
+        }
+    }
+
+    #[test]
+    fn unset_backend_uses_the_built_in_drivers() {
+        let backend = HypervisorBackend::default();
+        assert!(!backend.is_set());
+        assert!(backend.create(partition()).is_err());
+    }
+
+    #[test]
+    fn set_backend_is_handed_the_partition() {
+        let entrypoint = Arc::new(AtomicU64::new(0));
+        let backend = {
+            let entrypoint = entrypoint.clone();
+            HypervisorBackend::new(move |partition| {
+                entrypoint.store(partition.entrypoint_addr, Ordering::SeqCst);
+                Err(new_error!("no vCPU"))
+            })
+        };
+        let cloned = backend.clone();
+        assert!(cloned.is_set());
+        assert!(cloned.create(partition()).is_err());
+        assert_eq!(0x1000, entrypoint.load(Ordering::SeqCst));
+    }
+
+    /// An out-of-tree backend made of the in-tree KVM driver, counting the
+    /// guest function calls dispatched to it
+    #[cfg(kvm)]
+    #[derive(Debug)]
+    struct CountingBackend {
+        kvm: KVMDriver,
+        calls: Arc<AtomicU64>,
+    }
+
+    #[cfg(kvm)]
+    impl Hypervisor for CountingBackend {
+        fn initialise(
+            &mut self,
+            peb_addr: RawPtr,
+            seed: u64,
+            page_size: u32,
+            guest_max_log_level: Option<LevelFilter>,
+            ctx: VcpuContext,
+        ) -> Result<()> {
+            self.kvm
+                .initialise(peb_addr, seed, page_size, guest_max_log_level, ctx)
+        }
+
+        fn dispatch_call_from_host(
+            &mut self,
+            dispatch_func_addr: RawPtr,
+            ctx: VcpuContext,
+        ) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.kvm.dispatch_call_from_host(dispatch_func_addr, ctx)
+        }
+
+        fn handle_io(
+            &mut self,
+            port: u16,
+            data: Vec<u8>,
+            rip: u64,
+            instruction_length: u64,
+            outb_handle_fn: OutBHandlerWrapper,
+        ) -> Result<()> {
+            self.kvm
+                .handle_io(port, data, rip, instruction_length, outb_handle_fn)
+        }
+
+        fn run(&mut self) -> Result<HyperlightExit> {
+            self.kvm.run()
+        }
+
+        fn as_mut_hypervisor(&mut self) -> &mut dyn Hypervisor {
+            self
+        }
+
+        fn get_memory_regions(&self) -> &[MemoryRegion] {
+            self.kvm.get_memory_regions()
+        }
+    }
+
+    /// Map a page past the end of the partition's memory into the guest,
+    /// and unmap it again
+    #[cfg(kvm)]
+    fn map_scratch_page(kvm: &mut KVMDriver, partition: &HypervisorPartition) -> Result<()> {
+        let scratch = ExclusiveSharedMemory::new(PAGE_SIZE_USIZE)?;
+        let guest_start = partition
+            .regions
+            .iter()
+            .map(|region| region.guest_region.end)
+            .max()
+            .unwrap_or_default();
+        let region = MemoryRegion {
+            guest_region: guest_start..guest_start + PAGE_SIZE_USIZE,
+            host_region: scratch.base_addr()..scratch.base_addr() + PAGE_SIZE_USIZE,
+            flags: MemoryRegionFlags::READ | MemoryRegionFlags::WRITE,
+            region_type: MemoryRegionType::Heap,
+        };
+
+        // Safety: the region is unmapped before `scratch` is dropped
+        unsafe { kvm.map_region(&region) }?;
+        if unsafe { kvm.map_region(&region) }.is_ok() {
+            return Err(new_error!("an overlapping region was mapped"));
+        }
+        kvm.unmap_region(&region)?;
+        if kvm.unmap_region(&region).is_ok() {
+            return Err(new_error!("an unmapped region was unmapped again"));
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(kvm)]
+    fn backend_wrapping_kvm_runs_guest_calls() -> Result<()> {
+        if !kvm::is_hypervisor_present() {
+            return Ok(());
+        }
+
+        let calls = Arc::new(AtomicU64::new(0));
+        let mut sandbox = UninitializedSandbox::new(
+            GuestBinary::FilePath(simple_guest_as_string().map_err(|e| new_error!("{}", e))?),
+            None,
+            None,
+            None,
+        )?;
+        {
+            let calls = calls.clone();
+            sandbox.set_hypervisor_backend(move |partition| {
+                let mut kvm = KVMDriver::new(
+                    partition.regions.clone(),
+                    partition.pml4_addr,
+                    partition.entrypoint_addr,
+                    partition.rsp_addr,
+                    #[cfg(gdb)]
+                    None,
+                    #[cfg(gdb)]
+                    false,
+                )?;
+                map_scratch_page(&mut kvm, &partition)?;
+                Ok(Box::new(CountingBackend {
+                    kvm,
+                    calls: calls.clone(),
+                }))
+            });
+        }
+        let mut sandbox: MultiUseSandbox = sandbox.evolve(Noop::default())?;
+
+        let calls_before = calls.load(Ordering::SeqCst);
+        let res = sandbox.call_guest_function_by_name(
+            "Echo",
+            ReturnType::String,
+            Some(vec![ParameterValue::String("hello".to_string())]),
+        )?;
+        assert_eq!(ReturnValue::String("hello".to_string()), res);
+        assert_eq!(calls_before + 1, calls.load(Ordering::SeqCst));
+        Ok(())
+    }
+}
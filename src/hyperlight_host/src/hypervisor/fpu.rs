@@ -17,3 +17,88 @@ limitations under the License.
 pub(crate) const FP_CONTROL_WORD_DEFAULT: u16 = 0x37f; // mask all fp-exception, set rounding to nearest, set precision to 64-bit
 pub(crate) const FP_TAG_WORD_DEFAULT: u8 = 0xff; // each 8 of x87 fpu registers is empty
 pub(crate) const MXCSR_DEFAULT: u32 = 0x1f80; // mask simd fp-exceptions, clear exception flags, set rounding to nearest, disable flush-to-zero mode, disable denormals-are-zero mode
+
+const XCR0_X87: u64 = 1;
+const XCR0_SSE: u64 = 1 << 1;
+const XCR0_AVX: u64 = 1 << 2;
+const XCR0_OPMASK: u64 = 1 << 5;
+const XCR0_ZMM_HI256: u64 = 1 << 6;
+const XCR0_HI16_ZMM: u64 = 1 << 7;
+/// The AVX-512 state components, which can only be enabled together
+const XCR0_AVX512: u64 = XCR0_OPMASK | XCR0_ZMM_HI256 | XCR0_HI16_ZMM;
+
+/// The value of XCR0 for guests with extended CPU state enabled: the x87,
+/// SSE, AVX and AVX-512 state components the host has enabled for itself.
+/// Other components, such as AMX, are never enabled for guests.
+pub(crate) fn guest_xcr0() -> crate::Result<u64> {
+    if !std::arch::is_x86_feature_detected!("xsave") {
+        crate::log_then_return!("The host does not support XSAVE");
+    }
+    // SAFETY: XGETBV is available, as the host supports XSAVE and has
+    // enabled it
+    let host_xcr0 = unsafe { std::arch::x86_64::_xgetbv(0) };
+    let mut xcr0 = host_xcr0 & (XCR0_X87 | XCR0_SSE | XCR0_AVX);
+    if xcr0 & XCR0_AVX != 0 && host_xcr0 & XCR0_AVX512 == XCR0_AVX512 {
+        xcr0 |= XCR0_AVX512;
+    }
+    Ok(xcr0)
+}
+
+/// The offset of MXCSR in the legacy region of an XSAVE area, followed by
+/// MXCSR_MASK
+const XSAVE_MXCSR_OFFSET: usize = 24;
+/// The offset of the XSTATE_BV field of an XSAVE area's header, followed by
+/// XCOMP_BV
+const XSAVE_XSTATE_BV_OFFSET: usize = 512;
+/// The length of an XSAVE area's legacy region and header
+const XSAVE_HEADER_END: usize = 576;
+
+/// Reset the XSAVE area `xsave`, as read from a vCPU in either the standard
+/// or the compacted format, to the state each guest function call starts
+/// in: the x87 and SSE state as the drivers' `set_fpu` calls reset it, and
+/// every other state component, including the upper bits of the AVX and
+/// AVX-512 vector registers and the AVX-512 opmask registers, in its
+/// initial, zeroed, state.
+pub(crate) fn reset_xsave_area(xsave: &mut [u8]) -> crate::Result<()> {
+    if xsave.len() < XSAVE_HEADER_END {
+        crate::log_then_return!(
+            "The XSAVE area is {} bytes, too short to hold its header",
+            xsave.len()
+        );
+    }
+    // MXCSR_MASK describes the CPU rather than its state, so it is kept
+    xsave[..XSAVE_MXCSR_OFFSET].fill(0);
+    xsave[XSAVE_MXCSR_OFFSET + 8..XSAVE_XSTATE_BV_OFFSET].fill(0);
+    xsave[..2].copy_from_slice(&FP_CONTROL_WORD_DEFAULT.to_le_bytes());
+    xsave[XSAVE_MXCSR_OFFSET..XSAVE_MXCSR_OFFSET + 4].copy_from_slice(&MXCSR_DEFAULT.to_le_bytes());
+    // the components whose bits are clear in XSTATE_BV are loaded in their
+    // initial state whatever the rest of the area holds. XCOMP_BV is kept,
+    // so the area stays in the format it was read in.
+    xsave[XSAVE_XSTATE_BV_OFFSET..XSAVE_XSTATE_BV_OFFSET + 8]
+        .copy_from_slice(&(XCR0_X87 | XCR0_SSE).to_le_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reset_xsave_area, FP_CONTROL_WORD_DEFAULT, MXCSR_DEFAULT};
+
+    #[test]
+    fn reset_xsave() {
+        let mut xsave = [0xaa; 4096];
+        reset_xsave_area(&mut xsave).unwrap();
+        assert_eq!(FP_CONTROL_WORD_DEFAULT.to_le_bytes(), xsave[..2]);
+        assert_eq!(MXCSR_DEFAULT.to_le_bytes(), xsave[24..28]);
+        // MXCSR_MASK
+        assert_eq!([0xaa; 4], xsave[28..32]);
+        // the x87 and XMM registers
+        assert!(xsave[32..416].iter().all(|b| *b == 0));
+        // only the x87 and SSE components are loaded from the area, the
+        // AVX and AVX-512 components are reset
+        assert_eq!(3_u64.to_le_bytes(), xsave[512..520]);
+        // XCOMP_BV
+        assert_eq!([0xaa; 8], xsave[520..528]);
+
+        assert!(reset_xsave_area(&mut [0; 512]).is_err());
+    }
+}
@@ -57,6 +57,10 @@ impl run_blocking::BlockingEventLoop for GdbBlockingEventLoop {
                             tid: (),
                             signal: Signal(SIGRTMIN() as u8),
                         },
+                        VcpuStopReason::Crash => BaseStopReason::SignalWithThread {
+                            tid: (),
+                            signal: Signal::SIGSEGV,
+                        },
                         VcpuStopReason::Unknown => {
                             log::warn!("Unknown stop reason received");
 
@@ -132,6 +132,8 @@ pub enum VcpuStopReason {
     HwBp,
     SwBp,
     Interrupt,
+    /// The guest crashed and the sandbox waits for a debugger to inspect it
+    Crash,
     Unknown,
 }
 
@@ -414,7 +416,8 @@ pub(crate) fn create_gdb_thread(
 
             let mut target = HyperlightSandboxTarget::new(hyp_conn, thread_id);
 
-            // Waits for vCPU to stop at entrypoint breakpoint
+            // Waits for vCPU to stop at entrypoint breakpoint, or to crash
+            // when waiting on crash
             let res = target.recv()?;
             if let DebugResponse::VcpuStopped(_) = res {
                 event_loop_thread(debugger, &mut target);
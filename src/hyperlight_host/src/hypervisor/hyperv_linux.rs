@@ -29,17 +29,21 @@ use std::fmt::{Debug, Formatter};
 use log::{error, LevelFilter};
 #[cfg(mshv2)]
 use mshv_bindings::hv_message;
-#[cfg(gdb)]
 use mshv_bindings::{
-    hv_intercept_parameters, hv_intercept_type_HV_INTERCEPT_TYPE_EXCEPTION,
-    hv_message_type_HVMSG_X64_EXCEPTION_INTERCEPT, mshv_install_intercept,
-    HV_INTERCEPT_ACCESS_MASK_EXECUTE,
+    hv_intercept_parameters, hv_intercept_type_HV_INTERCEPT_TYPE_X64_MSR, hv_message_type,
+    hv_message_type_HVMSG_GPA_INTERCEPT, hv_message_type_HVMSG_UNMAPPED_GPA,
+    hv_message_type_HVMSG_UNRECOVERABLE_EXCEPTION, hv_message_type_HVMSG_X64_HALT,
+    hv_message_type_HVMSG_X64_IO_PORT_INTERCEPT, hv_message_type_HVMSG_X64_MSR_INTERCEPT,
+    hv_register_assoc, hv_register_name_HV_X64_REGISTER_RAX, hv_register_name_HV_X64_REGISTER_RDX,
+    hv_register_name_HV_X64_REGISTER_RIP, hv_register_name_HV_X64_REGISTER_XFEM, hv_register_value,
+    mshv_install_intercept, mshv_user_mem_region, FloatingPointUnit, SegmentRegister,
+    SpecialRegisters, StandardRegisters, HV_INTERCEPT_ACCESS_MASK_READ,
+    HV_INTERCEPT_ACCESS_MASK_WRITE, HV_INTERCEPT_ACCESS_WRITE,
 };
+#[cfg(gdb)]
 use mshv_bindings::{
-    hv_message_type, hv_message_type_HVMSG_GPA_INTERCEPT, hv_message_type_HVMSG_UNMAPPED_GPA,
-    hv_message_type_HVMSG_X64_HALT, hv_message_type_HVMSG_X64_IO_PORT_INTERCEPT, hv_register_assoc,
-    hv_register_name_HV_X64_REGISTER_RIP, hv_register_value, mshv_user_mem_region,
-    FloatingPointUnit, SegmentRegister, SpecialRegisters, StandardRegisters,
+    hv_intercept_type_HV_INTERCEPT_TYPE_EXCEPTION, hv_message_type_HVMSG_X64_EXCEPTION_INTERCEPT,
+    HV_INTERCEPT_ACCESS_MASK_EXECUTE,
 };
 #[cfg(mshv3)]
 use mshv_bindings::{
@@ -49,20 +53,21 @@ use mshv_bindings::{
 use mshv_ioctls::{Mshv, VcpuFd, VmFd};
 use tracing::{instrument, Span};
 
-use super::fpu::{FP_CONTROL_WORD_DEFAULT, FP_TAG_WORD_DEFAULT, MXCSR_DEFAULT};
+use super::fpu::{
+    guest_xcr0, reset_xsave_area, FP_CONTROL_WORD_DEFAULT, FP_TAG_WORD_DEFAULT, MXCSR_DEFAULT,
+};
 #[cfg(gdb)]
 use super::gdb::{DebugCommChannel, DebugMsg, DebugResponse, GuestDebug, MshvDebug};
-#[cfg(gdb)]
-use super::handlers::DbgMemAccessHandlerWrapper;
-use super::handlers::{MemAccessHandlerWrapper, OutBHandlerWrapper};
+use super::handlers::OutBHandlerWrapper;
 use super::{
-    Hypervisor, VirtualCPU, CR0_AM, CR0_ET, CR0_MP, CR0_NE, CR0_PE, CR0_PG, CR0_WP, CR4_OSFXSR,
-    CR4_OSXMMEXCPT, CR4_PAE, EFER_LMA, EFER_LME, EFER_NX, EFER_SCE,
+    Hypervisor, VcpuContext, VirtualCPU, CR0_AM, CR0_ET, CR0_MP, CR0_NE, CR0_PE, CR0_PG, CR0_WP,
+    CR4_OSFXSR, CR4_OSXMMEXCPT, CR4_OSXSAVE, CR4_PAE, EFER_LMA, EFER_LME, EFER_NX, EFER_SCE,
 };
-use crate::hypervisor::hypervisor_handler::HypervisorHandler;
 use crate::hypervisor::HyperlightExit;
 use crate::mem::memory_region::{MemoryRegion, MemoryRegionFlags};
 use crate::mem::ptr::{GuestPtr, RawPtr};
+use crate::sandbox::cpu_fault::CpuFaultContext;
+use crate::sandbox::msr::{MsrAccess, MsrAction, MsrPolicy};
 #[cfg(gdb)]
 use crate::HyperlightError;
 use crate::{log_then_return, new_error, Result};
@@ -294,11 +299,17 @@ pub(super) struct HypervLinuxDriver {
     entrypoint: u64,
     mem_regions: Vec<MemoryRegion>,
     orig_rsp: GuestPtr,
+    /// Whether the guest can use the XSAVE state components beyond x87 and
+    /// SSE, which must then be reset before each call
+    extended_cpu_state: bool,
+    msr_policy: MsrPolicy,
 
     #[cfg(gdb)]
     debug: Option<MshvDebug>,
     #[cfg(gdb)]
     gdb_conn: Option<DebugCommChannel<DebugResponse, DebugMsg>>,
+    #[cfg(gdb)]
+    wait_on_crash: bool,
 }
 
 impl HypervLinuxDriver {
@@ -317,6 +328,7 @@ impl HypervLinuxDriver {
         rsp_ptr: GuestPtr,
         pml4_ptr: GuestPtr,
         #[cfg(gdb)] gdb_conn: Option<DebugCommChannel<DebugResponse, DebugMsg>>,
+        #[cfg(gdb)] wait_on_crash: bool,
     ) -> Result<Self> {
         let mshv = Mshv::new()?;
         let pr = Default::default();
@@ -343,7 +355,10 @@ impl HypervLinuxDriver {
         #[cfg(gdb)]
         let (debug, gdb_conn) = if let Some(gdb_conn) = gdb_conn {
             let mut debug = MshvDebug::new();
-            debug.add_hw_breakpoint(&vcpu_fd, entrypoint_ptr.absolute()?)?;
+            // When waiting on a crash, the guest only stops if it crashes
+            if !wait_on_crash {
+                debug.add_hw_breakpoint(&vcpu_fd, entrypoint_ptr.absolute()?)?;
+            }
 
             // The bellow intercepts make the vCPU exit with the Exception Intercept exit code
             // Check Table 6-1. Exceptions and Interrupts at Page 6-13 Vol. 1
@@ -391,11 +406,15 @@ impl HypervLinuxDriver {
             mem_regions,
             entrypoint: entrypoint_ptr.absolute()?,
             orig_rsp: rsp_ptr,
+            extended_cpu_state: false,
+            msr_policy: MsrPolicy::default(),
 
             #[cfg(gdb)]
             debug,
             #[cfg(gdb)]
             gdb_conn,
+            #[cfg(gdb)]
+            wait_on_crash,
         })
     }
 
@@ -460,11 +479,8 @@ impl Hypervisor for HypervLinuxDriver {
         peb_addr: RawPtr,
         seed: u64,
         page_size: u32,
-        outb_hdl: OutBHandlerWrapper,
-        mem_access_hdl: MemAccessHandlerWrapper,
-        hv_handler: Option<HypervisorHandler>,
         max_guest_log_level: Option<LevelFilter>,
-        #[cfg(gdb)] dbg_mem_access_fn: DbgMemAccessHandlerWrapper,
+        ctx: VcpuContext,
     ) -> Result<()> {
         let max_guest_log_level: u64 = match max_guest_log_level {
             Some(level) => level as u64,
@@ -486,14 +502,7 @@ impl Hypervisor for HypervLinuxDriver {
         };
         self.vcpu_fd.set_regs(&regs)?;
 
-        VirtualCPU::run(
-            self.as_mut_hypervisor(),
-            hv_handler,
-            outb_hdl,
-            mem_access_hdl,
-            #[cfg(gdb)]
-            dbg_mem_access_fn,
-        )?;
+        VirtualCPU::run(self.as_mut_hypervisor(), ctx)?;
 
         Ok(())
     }
@@ -502,10 +511,7 @@ impl Hypervisor for HypervLinuxDriver {
     fn dispatch_call_from_host(
         &mut self,
         dispatch_func_addr: RawPtr,
-        outb_handle_fn: OutBHandlerWrapper,
-        mem_access_fn: MemAccessHandlerWrapper,
-        hv_handler: Option<HypervisorHandler>,
-        #[cfg(gdb)] dbg_mem_access_fn: DbgMemAccessHandlerWrapper,
+        ctx: VcpuContext,
     ) -> Result<()> {
         // Reset general purpose registers, then set RIP and RSP
         let regs = StandardRegisters {
@@ -524,16 +530,15 @@ impl Hypervisor for HypervLinuxDriver {
             ..Default::default() // zero out the rest
         };
         self.vcpu_fd.set_fpu(&fpu)?;
+        if self.extended_cpu_state {
+            // reset the vector registers `set_fpu` doesn't reach
+            let mut xsave = self.vcpu_fd.get_xsave()?;
+            reset_xsave_area(&mut xsave.buffer)?;
+            self.vcpu_fd.set_xsave(&xsave)?;
+        }
 
         // run
-        VirtualCPU::run(
-            self.as_mut_hypervisor(),
-            hv_handler,
-            outb_handle_fn,
-            mem_access_fn,
-            #[cfg(gdb)]
-            dbg_mem_access_fn,
-        )?;
+        VirtualCPU::run(self.as_mut_hypervisor(), ctx)?;
 
         Ok(())
     }
@@ -571,6 +576,9 @@ impl Hypervisor for HypervLinuxDriver {
             hv_message_type_HVMSG_X64_IO_PORT_INTERCEPT;
         const UNMAPPED_GPA_MESSAGE: hv_message_type = hv_message_type_HVMSG_UNMAPPED_GPA;
         const INVALID_GPA_ACCESS_MESSAGE: hv_message_type = hv_message_type_HVMSG_GPA_INTERCEPT;
+        const UNRECOVERABLE_EXCEPTION_MESSAGE: hv_message_type =
+            hv_message_type_HVMSG_UNRECOVERABLE_EXCEPTION;
+        const MSR_INTERCEPT_MESSAGE: hv_message_type = hv_message_type_HVMSG_X64_MSR_INTERCEPT;
         #[cfg(gdb)]
         const EXCEPTION_INTERCEPT: hv_message_type = hv_message_type_HVMSG_X64_EXCEPTION_INTERCEPT;
 
@@ -630,6 +638,55 @@ impl Hypervisor for HypervLinuxDriver {
                         None => HyperlightExit::Mmio(gpa),
                     }
                 }
+                // MSR accesses are only intercepted when the policy passes
+                // none of them through, see `set_msr_policy`
+                MSR_INTERCEPT_MESSAGE => {
+                    let msr_message = m.to_msr_info()?;
+                    let msr = msr_message.msr_number;
+                    let access = if msr_message.header.intercept_access_type
+                        == HV_INTERCEPT_ACCESS_WRITE as u8
+                    {
+                        MsrAccess::Write((msr_message.rdx << 32) | (msr_message.rax & 0xffff_ffff))
+                    } else {
+                        MsrAccess::Read
+                    };
+                    match self.msr_policy.action(msr) {
+                        MsrAction::EmulateZero => {
+                            let rip = msr_message.header.rip
+                                + msr_message.header.instruction_length() as u64;
+                            let mut regs = vec![hv_register_assoc {
+                                name: hv_register_name_HV_X64_REGISTER_RIP,
+                                value: hv_register_value { reg64: rip },
+                                ..Default::default()
+                            }];
+                            if access == MsrAccess::Read {
+                                for name in [
+                                    hv_register_name_HV_X64_REGISTER_RAX,
+                                    hv_register_name_HV_X64_REGISTER_RDX,
+                                ] {
+                                    regs.push(hv_register_assoc {
+                                        name,
+                                        value: hv_register_value { reg64: 0 },
+                                        ..Default::default()
+                                    });
+                                }
+                            }
+                            self.vcpu_fd.set_reg(&regs)?;
+                            HyperlightExit::Retry()
+                        }
+                        _ => HyperlightExit::MsrAccessDenied(msr, access),
+                    }
+                }
+                // The guest couldn't deliver a double fault, so the vCPU shut down
+                UNRECOVERABLE_EXCEPTION_MESSAGE => {
+                    let regs = self.vcpu_fd.get_regs()?;
+                    let sregs = self.vcpu_fd.get_sregs()?;
+                    HyperlightExit::TripleFault(CpuFaultContext {
+                        rip: regs.rip,
+                        rsp: regs.rsp,
+                        cr2: sregs.cr2,
+                    })
+                }
                 // The only case an intercept exit is expected is when debugging is enabled
                 // and the intercepts are installed
                 #[cfg(gdb)]
@@ -657,12 +714,54 @@ impl Hypervisor for HypervLinuxDriver {
         Ok(result)
     }
 
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn enable_extended_cpu_state(&mut self) -> Result<()> {
+        let mut sregs = self.vcpu_fd.get_sregs()?;
+        sregs.cr4 |= CR4_OSXSAVE;
+        self.vcpu_fd.set_sregs(&sregs)?;
+
+        // XFEM is the hypervisor's name for XCR0
+        self.vcpu_fd.set_reg(&[hv_register_assoc {
+            name: hv_register_name_HV_X64_REGISTER_XFEM,
+            value: hv_register_value {
+                reg64: guest_xcr0()?,
+            },
+            ..Default::default()
+        }])?;
+        self.extended_cpu_state = true;
+        Ok(())
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn set_msr_policy(&mut self, policy: &MsrPolicy) -> Result<()> {
+        // The hypervisor intercepts either every MSR access or none, and an
+        // intercepted access can't be handed back to it
+        if policy.passes_any_through() {
+            log_then_return!("MSR policies that pass some MSRs through are not supported by mshv");
+        }
+        self.vm_fd
+            .install_intercept(mshv_install_intercept {
+                access_type_mask: HV_INTERCEPT_ACCESS_MASK_READ | HV_INTERCEPT_ACCESS_MASK_WRITE,
+                intercept_type: hv_intercept_type_HV_INTERCEPT_TYPE_X64_MSR,
+                intercept_parameter: hv_intercept_parameters { as_uint64: 0 },
+            })
+            .map_err(|e| new_error!("Cannot install the MSR intercept: {}", e))?;
+        self.msr_policy = *policy;
+        Ok(())
+    }
+
+    fn enable_mmio_doorbell(&mut self) -> Result<()> {
+        // Unmapped GPA exits don't report the value the guest wrote, only
+        // the bytes of the instruction that wrote it, which would have to
+        // be emulated
+        log_then_return!("The MMIO doorbell is not supported by mshv");
+    }
+
     #[instrument(skip_all, parent = Span::current(), level = "Trace")]
     fn as_mut_hypervisor(&mut self) -> &mut dyn Hypervisor {
         self as &mut dyn Hypervisor
     }
 
-    #[cfg(crashdump)]
     fn get_memory_regions(&self) -> &[MemoryRegion] {
         &self.mem_regions
     }
@@ -711,6 +810,34 @@ impl Hypervisor for HypervLinuxDriver {
 
         Ok(())
     }
+
+    #[cfg(gdb)]
+    fn wait_for_debugger_on_crash(
+        &mut self,
+        dbg_mem_access_fn: std::sync::Arc<
+            std::sync::Mutex<dyn super::handlers::DbgMemAccessHandlerCaller>,
+        >,
+    ) -> Result<()> {
+        if self.wait_on_crash {
+            self.wait_for_debugger(dbg_mem_access_fn)?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(gdb)]
+    fn wait_for_debugger(
+        &mut self,
+        dbg_mem_access_fn: std::sync::Arc<
+            std::sync::Mutex<dyn super::handlers::DbgMemAccessHandlerCaller>,
+        >,
+    ) -> Result<()> {
+        if self.gdb_conn.is_some() {
+            self.handle_debug(dbg_mem_access_fn, super::gdb::VcpuStopReason::Crash)?;
+        }
+
+        Ok(())
+    }
 }
 
 impl Drop for HypervLinuxDriver {
@@ -784,6 +911,8 @@ mod tests {
             pml4_ptr,
             #[cfg(gdb)]
             None,
+            #[cfg(gdb)]
+            false,
         )
         .unwrap();
     }
@@ -18,34 +18,36 @@ use core::ffi::c_void;
 use std::fmt;
 use std::fmt::{Debug, Formatter};
 use std::string::String;
+use std::sync::Arc;
 
 use hyperlight_common::mem::PAGE_SIZE_USIZE;
 use log::LevelFilter;
 use tracing::{instrument, Span};
 use windows::Win32::System::Hypervisor::{
-    WHvX64RegisterCr0, WHvX64RegisterCr3, WHvX64RegisterCr4, WHvX64RegisterCs, WHvX64RegisterEfer,
-    WHV_MEMORY_ACCESS_TYPE, WHV_PARTITION_HANDLE, WHV_REGISTER_VALUE, WHV_RUN_VP_EXIT_CONTEXT,
-    WHV_RUN_VP_EXIT_REASON, WHV_X64_SEGMENT_REGISTER, WHV_X64_SEGMENT_REGISTER_0,
+    WHvCancelRunVirtualProcessor, WHvX64RegisterCr0, WHvX64RegisterCr3, WHvX64RegisterCr4,
+    WHvX64RegisterCs, WHvX64RegisterEfer, WHvX64RegisterXCr0, WHV_MEMORY_ACCESS_TYPE,
+    WHV_PARTITION_HANDLE, WHV_REGISTER_VALUE, WHV_RUN_VP_EXIT_CONTEXT, WHV_RUN_VP_EXIT_REASON,
+    WHV_X64_SEGMENT_REGISTER, WHV_X64_SEGMENT_REGISTER_0,
 };
 
-use super::fpu::{FP_TAG_WORD_DEFAULT, MXCSR_DEFAULT};
-#[cfg(gdb)]
-use super::handlers::DbgMemAccessHandlerWrapper;
-use super::handlers::{MemAccessHandlerWrapper, OutBHandlerWrapper};
+use super::fpu::{guest_xcr0, reset_xsave_area, FP_TAG_WORD_DEFAULT, MXCSR_DEFAULT};
+use super::handlers::OutBHandlerWrapper;
 use super::surrogate_process::SurrogateProcess;
 use super::surrogate_process_manager::*;
 use super::windows_hypervisor_platform::{VMPartition, VMProcessor};
 use super::wrappers::{HandleWrapper, WHvFPURegisters};
 use super::{
-    HyperlightExit, Hypervisor, VirtualCPU, CR0_AM, CR0_ET, CR0_MP, CR0_NE, CR0_PE, CR0_PG, CR0_WP,
-    CR4_OSFXSR, CR4_OSXMMEXCPT, CR4_PAE, EFER_LMA, EFER_LME, EFER_NX, EFER_SCE,
+    HyperlightExit, Hypervisor, VcpuContext, VcpuInterrupt, VirtualCPU, CR0_AM, CR0_ET, CR0_MP,
+    CR0_NE, CR0_PE, CR0_PG, CR0_WP, CR4_OSFXSR, CR4_OSXMMEXCPT, CR4_OSXSAVE, CR4_PAE, EFER_LMA,
+    EFER_LME, EFER_NX, EFER_SCE,
 };
 use crate::hypervisor::fpu::FP_CONTROL_WORD_DEFAULT;
-use crate::hypervisor::hypervisor_handler::HypervisorHandler;
 use crate::hypervisor::wrappers::WHvGeneralRegisters;
 use crate::mem::memory_region::{MemoryRegion, MemoryRegionFlags};
 use crate::mem::ptr::{GuestPtr, RawPtr};
-use crate::{debug, new_error, Result};
+use crate::sandbox::cpu_fault::CpuFaultContext;
+use crate::sandbox::msr::MsrPolicy;
+use crate::{debug, log_then_return, new_error, Result};
 
 /// A Hypervisor driver for HyperV-on-Windows.
 pub(crate) struct HypervWindowsDriver {
@@ -56,6 +58,9 @@ pub(crate) struct HypervWindowsDriver {
     entrypoint: u64,
     orig_rsp: GuestPtr,
     mem_regions: Vec<MemoryRegion>,
+    /// Whether the guest can use the XSAVE state components beyond x87 and
+    /// SSE, which must then be reset before each call
+    extended_cpu_state: bool,
 }
 /* This does not automatically impl Send/Sync because the host
  * address of the shared memory region is a raw pointer, which are
@@ -102,6 +107,7 @@ impl HypervWindowsDriver {
             entrypoint,
             orig_rsp: GuestPtr::try_from(RawPtr::from(rsp))?,
             mem_regions,
+            extended_cpu_state: false,
         })
     }
 
@@ -305,11 +311,8 @@ impl Hypervisor for HypervWindowsDriver {
         peb_address: RawPtr,
         seed: u64,
         page_size: u32,
-        outb_hdl: OutBHandlerWrapper,
-        mem_access_hdl: MemAccessHandlerWrapper,
-        hv_handler: Option<HypervisorHandler>,
         max_guest_log_level: Option<LevelFilter>,
-        #[cfg(gdb)] dbg_mem_access_hdl: DbgMemAccessHandlerWrapper,
+        ctx: VcpuContext,
     ) -> Result<()> {
         let max_guest_log_level: u64 = match max_guest_log_level {
             Some(level) => level as u64,
@@ -331,14 +334,7 @@ impl Hypervisor for HypervWindowsDriver {
         };
         self.processor.set_general_purpose_registers(&regs)?;
 
-        VirtualCPU::run(
-            self.as_mut_hypervisor(),
-            hv_handler,
-            outb_hdl,
-            mem_access_hdl,
-            #[cfg(gdb)]
-            dbg_mem_access_hdl,
-        )?;
+        VirtualCPU::run(self.as_mut_hypervisor(), ctx)?;
 
         Ok(())
     }
@@ -347,10 +343,7 @@ impl Hypervisor for HypervWindowsDriver {
     fn dispatch_call_from_host(
         &mut self,
         dispatch_func_addr: RawPtr,
-        outb_hdl: OutBHandlerWrapper,
-        mem_access_hdl: MemAccessHandlerWrapper,
-        hv_handler: Option<HypervisorHandler>,
-        #[cfg(gdb)] dbg_mem_access_hdl: DbgMemAccessHandlerWrapper,
+        ctx: VcpuContext,
     ) -> Result<()> {
         // Reset general purpose registers, then set RIP and RSP
         let regs = WHvGeneralRegisters {
@@ -368,15 +361,14 @@ impl Hypervisor for HypervWindowsDriver {
             mxcsr: MXCSR_DEFAULT,
             ..Default::default() // zero out the rest
         })?;
+        if self.extended_cpu_state {
+            // reset the vector registers `set_fpu` doesn't reach
+            let mut xsave = self.processor.get_xsave()?;
+            reset_xsave_area(&mut xsave)?;
+            self.processor.set_xsave(&xsave)?;
+        }
 
-        VirtualCPU::run(
-            self.as_mut_hypervisor(),
-            hv_handler,
-            outb_hdl,
-            mem_access_hdl,
-            #[cfg(gdb)]
-            dbg_mem_access_hdl,
-        )?;
+        VirtualCPU::run(self.as_mut_hypervisor(), ctx)?;
 
         Ok(())
     }
@@ -455,6 +447,17 @@ impl Hypervisor for HypervWindowsDriver {
                     None => HyperlightExit::Mmio(gpa),
                 }
             }
+            // WHvRunVpExitReasonUnrecoverableException
+            // The guest couldn't deliver a double fault, so the vCPU shut down
+            WHV_RUN_VP_EXIT_REASON(4i32) => {
+                let regs = self.processor.get_regs()?;
+                let sregs = self.processor.get_sregs()?;
+                HyperlightExit::TripleFault(CpuFaultContext {
+                    rip: regs.rip,
+                    rsp: regs.rsp,
+                    cr2: unsafe { sregs.cr2.Reg64 },
+                })
+            }
             //  WHvRunVpExitReasonCanceled
             //  Execution was cancelled by the host.
             //  This will happen when guest code runs for too long
@@ -477,8 +480,37 @@ impl Hypervisor for HypervWindowsDriver {
         Ok(result)
     }
 
-    fn get_partition_handle(&self) -> WHV_PARTITION_HANDLE {
-        self.processor.get_partition_hdl()
+    fn interrupt_handle(&self) -> Option<Arc<dyn VcpuInterrupt>> {
+        Some(Arc::new(WhpInterrupt(self.processor.get_partition_hdl())))
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn enable_extended_cpu_state(&mut self) -> Result<()> {
+        let xcr0 = guest_xcr0()?;
+        self.processor.set_registers(&[
+            (
+                WHvX64RegisterCr4,
+                WHV_REGISTER_VALUE {
+                    Reg64: CR4_PAE | CR4_OSFXSR | CR4_OSXMMEXCPT | CR4_OSXSAVE,
+                },
+            ),
+            (WHvX64RegisterXCr0, WHV_REGISTER_VALUE { Reg64: xcr0 }),
+        ])?;
+        self.extended_cpu_state = true;
+        Ok(())
+    }
+
+    fn set_msr_policy(&mut self, _policy: &MsrPolicy) -> Result<()> {
+        // WHP only exits on accesses to the MSRs it doesn't handle itself
+        // and to a few fixed ones, so it can't deny or emulate the others
+        log_then_return!("MSR policies are not supported by WHP, which handles most MSRs itself");
+    }
+
+    fn enable_mmio_doorbell(&mut self) -> Result<()> {
+        // Memory access exits don't report the value the guest wrote, only
+        // the bytes of the instruction that wrote it, which would have to
+        // be emulated
+        log_then_return!("The MMIO doorbell is not supported by WHP");
     }
 
     #[instrument(skip_all, parent = Span::current(), level = "Trace")]
@@ -486,12 +518,31 @@ impl Hypervisor for HypervWindowsDriver {
         self as &mut dyn Hypervisor
     }
 
-    #[cfg(crashdump)]
     fn get_memory_regions(&self) -> &[MemoryRegion] {
         &self.mem_regions
     }
 }
 
+/// Cancels the run of the partition's vCPU with
+/// `WHvCancelRunVirtualProcessor`
+#[derive(Debug)]
+struct WhpInterrupt(WHV_PARTITION_HANDLE);
+
+// Safety: a partition handle can be used from any thread, and
+// `WHvCancelRunVirtualProcessor` is meant to be called from a thread other
+// than the one running the vCPU
+unsafe impl Send for WhpInterrupt {}
+unsafe impl Sync for WhpInterrupt {}
+
+impl VcpuInterrupt for WhpInterrupt {
+    fn interrupt(&self) -> Result<()> {
+        unsafe {
+            WHvCancelRunVirtualProcessor(self.0, 0, 0)
+                .map_err(|e| new_error!("Failed to cancel guest execution {:?}", e))
+        }
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use std::sync::{Arc, Mutex};
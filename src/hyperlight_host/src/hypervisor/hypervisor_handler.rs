@@ -22,29 +22,31 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 use std::thread::{sleep, JoinHandle};
 use std::time::Duration;
+#[cfg(not(gdb))]
+use std::time::Instant;
 
 #[cfg(target_os = "linux")]
 use crossbeam::atomic::AtomicCell;
 use crossbeam_channel::{Receiver, Sender};
+use hyperlight_common::transport::HostCallTransport;
 #[cfg(target_os = "linux")]
 use libc::{pthread_kill, pthread_self, ESRCH};
 use log::{error, info, LevelFilter};
 use tracing::{instrument, Span};
 #[cfg(target_os = "linux")]
 use vmm_sys_util::signal::SIGRTMIN;
-#[cfg(target_os = "windows")]
-use windows::Win32::System::Hypervisor::{WHvCancelRunVirtualProcessor, WHV_PARTITION_HANDLE};
 
 #[cfg(gdb)]
 use super::gdb::create_gdb_thread;
 #[cfg(feature = "function_call_metrics")]
 use crate::histogram_vec_observe;
+use crate::hypervisor::backend::{HypervisorBackend, HypervisorPartition};
 #[cfg(gdb)]
 use crate::hypervisor::handlers::DbgMemAccessHandlerWrapper;
 use crate::hypervisor::handlers::{MemAccessHandlerWrapper, OutBHandlerWrapper};
 #[cfg(target_os = "windows")]
 use crate::hypervisor::wrappers::HandleWrapper;
-use crate::hypervisor::Hypervisor;
+use crate::hypervisor::{Hypervisor, VcpuContext, VcpuInterrupt};
 use crate::mem::layout::SandboxMemoryLayout;
 use crate::mem::mgr::SandboxMemoryManager;
 use crate::mem::ptr::{GuestPtr, RawPtr};
@@ -52,9 +54,19 @@ use crate::mem::ptr_offset::Offset;
 use crate::mem::shared_mem::{GuestSharedMemory, HostSharedMemory, SharedMemory};
 #[cfg(gdb)]
 use crate::sandbox::config::DebugInfo;
+use crate::sandbox::cpu_fault::CpuFaultAction;
+use crate::sandbox::cpu_time::CpuTimeCounter;
+use crate::sandbox::cpuid::CpuidConfiguration;
+use crate::sandbox::deadline::CallDeadline;
+use crate::sandbox::epoch::EpochHandle;
+use crate::sandbox::guest_time::GuestTime;
+use crate::sandbox::heartbeat::{Heartbeat, HostCallTracker};
 use crate::sandbox::hypervisor::{get_available_hypervisor, HypervisorType};
+use crate::sandbox::interrupt::{InterruptAttempts, InterruptFailureCallback, InterruptPolicy};
 #[cfg(feature = "function_call_metrics")]
 use crate::sandbox::metrics::SandboxMetric::GuestFunctionCallDurationMicroseconds;
+use crate::sandbox::msr::MsrPolicy;
+use crate::sandbox::pause::PauseHandle;
 #[cfg(target_os = "linux")]
 use crate::signal_handlers::setup_signal_handlers;
 use crate::HyperlightError::{
@@ -68,8 +80,10 @@ type HypervisorHandlerRx = Receiver<HypervisorHandlerAction>;
 type HandlerMsgTx = Sender<HandlerMsg>;
 type HandlerMsgRx = Receiver<HandlerMsg>;
 
+/// The handle to the thread that runs a sandbox's vCPU, which backends
+/// pass on to `VirtualCPU::run`
 #[derive(Clone)]
-pub(crate) struct HypervisorHandler {
+pub struct HypervisorHandler {
     communication_channels: HvHandlerCommChannels,
     configuration: HvHandlerConfig,
     execution_variables: HvHandlerExecVars,
@@ -86,6 +100,43 @@ impl HypervisorHandler {
     pub(crate) fn set_run_cancelled(&self, run_cancelled: bool) {
         self.execution_variables.run_cancelled.store(run_cancelled);
     }
+
+    /// Whether the hypervisor handler thread is running an action
+    pub(crate) fn is_running(&self) -> bool {
+        self.execution_variables.running.load(Ordering::SeqCst)
+    }
+
+    /// The handle the vCPU is paused with
+    pub(crate) fn pause_handle(&self) -> &PauseHandle {
+        &self.configuration.pause
+    }
+
+    /// Called by the thread running the vCPU before it enters the guest:
+    /// park while the sandbox is paused, then give the time spent parked
+    /// back to the guest function call in progress
+    pub(crate) fn wait_while_paused(&self) {
+        let parked = self.configuration.pause.wait_while_paused();
+        if !parked.is_zero() {
+            self.configuration.call_deadline.extend(parked);
+        }
+    }
+
+    /// The epoch that guest function calls are stopped at when they reach
+    /// their deadline
+    pub(crate) fn epoch_handle(&self) -> &EpochHandle {
+        &self.configuration.epoch
+    }
+
+    /// The CPU time the guest, and the host functions it called, have
+    /// used so far
+    pub(crate) fn cpu_time(&self) -> Duration {
+        self.configuration.cpu_time.get()
+    }
+
+    /// What happens when the guest double or triple faults
+    pub(crate) fn cpu_fault_action(&self) -> CpuFaultAction {
+        self.configuration.cpu_fault_action
+    }
 }
 
 // Note: `join_handle` and `running` have to be `Arc` because we need
@@ -100,8 +151,8 @@ struct HvHandlerExecVars {
     timeout: Arc<Mutex<Duration>>,
     #[cfg(target_os = "linux")]
     thread_id: Arc<Mutex<Option<libc::pthread_t>>>,
-    #[cfg(target_os = "windows")]
-    partition_handle: Arc<Mutex<Option<WHV_PARTITION_HANDLE>>>,
+    /// What the vCPU is interrupted with, if not a signal
+    interrupt_handle: Arc<Mutex<Option<Arc<dyn VcpuInterrupt>>>>,
     running: Arc<AtomicBool>,
     #[cfg(target_os = "linux")]
     run_cancelled: Arc<crossbeam::atomic::AtomicCell<bool>>,
@@ -137,22 +188,24 @@ impl HvHandlerExecVars {
         .ok_or_else(|| new_error!("thread_id not set"))
     }
 
-    #[cfg(target_os = "windows")]
-    fn set_partition_handle(&mut self, partition_handle: WHV_PARTITION_HANDLE) -> Result<()> {
+    fn set_interrupt_handle(
+        &mut self,
+        interrupt_handle: Option<Arc<dyn VcpuInterrupt>>,
+    ) -> Result<()> {
         *self
-            .partition_handle
+            .interrupt_handle
             .try_lock()
-            .map_err(|_| new_error!("Failed to set_partition_handle"))? = Some(partition_handle);
+            .map_err(|_| new_error!("Failed to set_interrupt_handle"))? = interrupt_handle;
 
         Ok(())
     }
 
-    #[cfg(target_os = "windows")]
-    fn get_partition_handle(&self) -> Result<Option<WHV_PARTITION_HANDLE>> {
-        Ok(*self
-            .partition_handle
+    fn get_interrupt_handle(&self) -> Result<Option<Arc<dyn VcpuInterrupt>>> {
+        Ok(self
+            .interrupt_handle
             .try_lock()
-            .map_err(|_| new_error!("Failed to get_partition_handle"))?)
+            .map_err(|_| new_error!("Failed to get_interrupt_handle"))?
+            .clone())
     }
 
     fn set_timeout(&mut self, timeout: Duration) -> Result<()> {
@@ -192,10 +245,43 @@ pub(crate) struct HvHandlerConfig {
     pub(crate) mem_access_handler: MemAccessHandlerWrapper,
     pub(crate) max_wait_for_cancellation: Duration,
     pub(crate) max_guest_log_level: Option<LevelFilter>,
+    pub(crate) max_guest_instructions: u64,
+    pub(crate) extended_cpu_state: bool,
+    pub(crate) shadow_stack: bool,
+    pub(crate) cpuid: CpuidConfiguration,
+    pub(crate) trap_entropy_instructions: bool,
+    pub(crate) guest_time: GuestTime,
+    pub(crate) msr_policy: MsrPolicy,
+    pub(crate) interrupt_policy: InterruptPolicy,
+    pub(crate) cpu_fault_action: CpuFaultAction,
+    pub(crate) interrupt_failure: InterruptFailureCallback,
+    pub(crate) call_deadline: CallDeadline,
+    pub(crate) host_call_transport: HostCallTransport,
+    pub(crate) heartbeat: Heartbeat,
+    pub(crate) heartbeat_timeout: Option<Duration>,
+    pub(crate) host_calls: HostCallTracker,
+    pub(crate) max_time_between_host_calls: Option<Duration>,
+    pub(crate) pause: PauseHandle,
+    pub(crate) epoch: EpochHandle,
+    pub(crate) cpu_time: CpuTimeCounter,
+    pub(crate) hypervisor_backend: HypervisorBackend,
     #[cfg(gdb)]
     pub(crate) dbg_mem_access_handler: DbgMemAccessHandlerWrapper,
 }
 
+impl HvHandlerConfig {
+    /// The handlers the vCPU run by `hv_handler` handles its exits with
+    fn vcpu_context(&self, hv_handler: &HypervisorHandler) -> VcpuContext {
+        VcpuContext {
+            outb_handle_fn: self.outb_handler.clone(),
+            mem_access_fn: self.mem_access_handler.clone(),
+            hv_handler: Some(hv_handler.clone()),
+            #[cfg(gdb)]
+            dbg_mem_access_fn: self.dbg_mem_access_handler.clone(),
+        }
+    }
+}
+
 impl HypervisorHandler {
     /// Creates a new Hypervisor Handler with a given configuration. This call must precede a call
     /// to `start_hypervisor_handler`.
@@ -215,8 +301,7 @@ impl HypervisorHandler {
             shm: Arc::new(Mutex::new(None)),
             #[cfg(target_os = "linux")]
             thread_id: Arc::new(Mutex::new(None)),
-            #[cfg(target_os = "windows")]
-            partition_handle: Arc::new(Mutex::new(None)),
+            interrupt_handle: Arc::new(Mutex::new(None)),
             running: Arc::new(AtomicBool::new(false)),
             #[cfg(target_os = "linux")]
             run_cancelled: Arc::new(AtomicCell::new(false)),
@@ -244,8 +329,6 @@ impl HypervisorHandler {
         #[cfg(gdb)] debug_info: Option<DebugInfo>,
     ) -> Result<()> {
         let configuration = self.configuration.clone();
-        #[cfg(target_os = "windows")]
-        let in_process = sandbox_memory_manager.is_in_process();
 
         *self
             .execution_variables
@@ -306,18 +389,47 @@ impl HypervisorHandler {
                                     hv = Some(set_up_hypervisor_partition(
                                         execution_variables.shm.try_lock().map_err(|e| new_error!("Failed to lock shm: {}", e))?.deref_mut().as_mut().ok_or_else(|| new_error!("shm not set"))?,
                                         configuration.outb_handler.clone(),
+                                        &configuration.hypervisor_backend,
                                         #[cfg(gdb)]
                                         &debug_info,
                                     )?);
                                 }
                                 let hv = hv.as_mut().ok_or_else(|| new_error!("Hypervisor not set"))?;
 
-                                #[cfg(target_os = "windows")]
-                                if !in_process {
-                                    execution_variables
-                                        .set_partition_handle(hv.get_partition_handle())?;
+                                if configuration.max_guest_instructions > 0 {
+                                    hv.set_max_guest_instructions(configuration.max_guest_instructions)?;
+                                }
+
+                                if configuration.cpuid != CpuidConfiguration::default() {
+                                    hv.set_cpuid(&configuration.cpuid)?;
+                                }
+
+                                if configuration.trap_entropy_instructions {
+                                    hv.trap_entropy_instructions()?;
+                                }
+
+                                if configuration.extended_cpu_state {
+                                    hv.enable_extended_cpu_state()?;
+                                }
+
+                                if configuration.shadow_stack {
+                                    hv.enable_shadow_stack()?;
+                                }
+
+                                if configuration.guest_time != GuestTime::Host {
+                                    hv.set_guest_time(&configuration.guest_time)?;
                                 }
 
+                                if !configuration.msr_policy.is_passthrough() {
+                                    hv.set_msr_policy(&configuration.msr_policy)?;
+                                }
+
+                                if configuration.host_call_transport == HostCallTransport::Mmio {
+                                    hv.enable_mmio_doorbell()?;
+                                }
+
+                                execution_variables.set_interrupt_handle(hv.interrupt_handle())?;
+
                                 #[cfg(target_os = "linux")]
                                 {
                                     // We cannot use the Killable trait, so we get the `pthread_t` via a libc
@@ -354,17 +466,15 @@ impl HypervisorHandler {
                                     .lock
                                     .try_read();
 
-                                let res = hv.initialise(
-                                    configuration.peb_addr.clone(),
-                                    configuration.seed,
-                                    configuration.page_size,
-                                    configuration.outb_handler.clone(),
-                                    configuration.mem_access_handler.clone(),
-                                    Some(hv_handler_clone.clone()),
-                                    configuration.max_guest_log_level,
-                                    #[cfg(gdb)]
-                                    configuration.dbg_mem_access_handler.clone(),
-                                );
+                                let res = configuration.cpu_time.measure(|| {
+                                    hv.initialise(
+                                        configuration.peb_addr.clone(),
+                                        configuration.seed,
+                                        configuration.page_size,
+                                        configuration.max_guest_log_level,
+                                        configuration.vcpu_context(&hv_handler_clone),
+                                    )
+                                });
                                 drop(mem_lock_guard);
                                 drop(evar_lock_guard);
 
@@ -437,17 +547,13 @@ impl HypervisorHandler {
                                     .lock
                                     .try_read();
 
-                                let res = {
+                                let res = configuration.cpu_time.measure(|| {
                                     #[cfg(feature = "function_call_metrics")]
                                     {
                                         let start = std::time::Instant::now();
                                         let result = hv.dispatch_call_from_host(
                                             dispatch_function_addr,
-                                            configuration.outb_handler.clone(),
-                                            configuration.mem_access_handler.clone(),
-                                            Some(hv_handler_clone.clone()),
-                                            #[cfg(gdb)]
-                                            configuration.dbg_mem_access_handler.clone(),
+                                            configuration.vcpu_context(&hv_handler_clone),
                                         );
                                         histogram_vec_observe!(
                                             &GuestFunctionCallDurationMicroseconds,
@@ -460,13 +566,9 @@ impl HypervisorHandler {
                                     #[cfg(not(feature = "function_call_metrics"))]
                                     hv.dispatch_call_from_host(
                                         dispatch_function_addr,
-                                        configuration.outb_handler.clone(),
-                                        configuration.mem_access_handler.clone(),
-                                        Some(hv_handler_clone.clone()),
-                                        #[cfg(gdb)]
-                                        configuration.dbg_mem_access_handler.clone(),
+                                        configuration.vcpu_context(&hv_handler_clone),
                                     )
-                                };
+                                });
                                 drop(mem_lock_guard);
                                 drop(evar_lock_guard);
 
@@ -586,9 +688,13 @@ impl HypervisorHandler {
             HypervisorHandlerAction::Initialise => self
                 .execution_variables
                 .set_timeout(self.configuration.max_init_time)?,
-            HypervisorHandlerAction::DispatchCallFromHost(_) => self
-                .execution_variables
-                .set_timeout(self.configuration.max_exec_time)?,
+            HypervisorHandlerAction::DispatchCallFromHost(_) => {
+                let timeout = self
+                    .configuration
+                    .call_deadline
+                    .start_call(self.configuration.max_exec_time);
+                self.execution_variables.set_timeout(timeout)?
+            }
             HypervisorHandlerAction::TerminateHandlerThread => self
                 .execution_variables
                 .set_timeout(self.configuration.max_init_time)?,
@@ -597,6 +703,7 @@ impl HypervisorHandler {
             // `TerminateHandlerThread`.
         }
 
+        self.configuration.pause.start_call();
         self.set_running(true);
         self.communication_channels
             .to_handler_tx
@@ -605,6 +712,18 @@ impl HypervisorHandler {
 
         log::debug!("Waiting for Hypervisor Handler Response");
 
+        #[cfg(not(gdb))]
+        if let HypervisorHandlerAction::DispatchCallFromHost(_) = &hypervisor_handler_action {
+            let heartbeat_timeout = self.configuration.heartbeat_timeout;
+            let max_time_between_host_calls = self.configuration.max_time_between_host_calls;
+            if heartbeat_timeout.is_some() || max_time_between_host_calls.is_some() {
+                return self.try_receive_handler_msg_with_watchdogs(
+                    heartbeat_timeout,
+                    max_time_between_host_calls,
+                );
+            }
+        }
+
         self.try_receive_handler_msg()
     }
 
@@ -622,46 +741,144 @@ impl HypervisorHandler {
         // In this case, we will wait indefinitely for a message from the handler thread.
         // Note: This applies to all the running sandboxes, not just the one being debugged.
         #[cfg(gdb)]
-        let response = self.communication_channels.from_handler_rx.recv();
+        return match self.communication_channels.from_handler_rx.recv() {
+            Ok(msg) => msg.into_result(),
+            Err(_) => self.handler_msg_receive_timed_out(),
+        };
         #[cfg(not(gdb))]
-        let response = self
-            .communication_channels
-            .from_handler_rx
-            .recv_timeout(self.execution_variables.get_timeout()?);
-
-        match response {
-            Ok(msg) => match msg {
-                HandlerMsg::Error(e) => Err(e),
-                HandlerMsg::FinishedHypervisorHandlerAction => Ok(()),
-            },
-            Err(_) => {
-                // If we have timed out it may be that the handler thread returned an error before it sent a message, so rather than just timeout here
-                // we will try and get the join handle for the thread and if it has finished check to see if it returned an error
-                // if it did then we will return that error, otherwise we will return the timeout error
-                // we need to take ownership of the handle to join it
-                match self
-                    .execution_variables
-                    .join_handle
-                    .try_lock()
-                    .map_err(|_| HyperlightError::HypervisorHandlerMessageReceiveTimedout())?
-                    .take_if(|handle| handle.is_finished())
-                {
-                    Some(handle) => {
-                        // If the thread has finished, we try to join it and return the error if it has one
-                        let res = handle.join();
-                        if res.as_ref().is_ok_and(|inner_res| inner_res.is_err()) {
-                            #[allow(clippy::unwrap_used)]
-                            // We know that the thread has finished and that the inner result is an error, so we can safely unwrap the result and the contained err
-                            return Err(res.unwrap().unwrap_err());
-                        }
-                        Err(HyperlightError::HypervisorHandlerMessageReceiveTimedout())
+        {
+            // the time the vCPU spends paused doesn't count towards the
+            // timeout
+            let deadline = Instant::now() + self.execution_variables.get_timeout()?;
+            let paused_before = self.configuration.pause.paused_time();
+            let paused_since = || {
+                self.configuration
+                    .pause
+                    .paused_time()
+                    .saturating_sub(paused_before)
+            };
+            loop {
+                let paused = paused_since();
+                let response = self
+                    .communication_channels
+                    .from_handler_rx
+                    .recv_timeout((deadline + paused).saturating_duration_since(Instant::now()));
+                match response {
+                    Ok(msg) => return msg.into_result(),
+                    Err(crossbeam_channel::RecvTimeoutError::Timeout)
+                        if paused_since() > paused =>
+                    {
+                        continue
+                    }
+                    Err(_) => return self.handler_msg_receive_timed_out(),
+                }
+            }
+        }
+    }
+
+    /// Like `try_receive_handler_msg`, but also give up with
+    /// `GuestHeartbeatLapsed` if more than `heartbeat_timeout` passes
+    /// without the guest calling `HostHeartbeat`, and with
+    /// `GuestHostCallIntervalExceeded` if the guest runs for more than
+    /// `max_time_between_host_calls` without calling a host function.
+    ///
+    /// Only heartbeats sent and host function calls made after this
+    /// function is called count, so each guest function call gets the full
+    /// time to send its first heartbeat and make its first host call.
+    #[cfg(not(gdb))]
+    fn try_receive_handler_msg_with_watchdogs(
+        &self,
+        heartbeat_timeout: Option<Duration>,
+        max_time_between_host_calls: Option<Duration>,
+    ) -> Result<()> {
+        let call_start = Instant::now();
+        let deadline = call_start + self.execution_variables.get_timeout()?;
+        // the time the vCPU spends paused doesn't count towards any of the
+        // watchdogs
+        let paused = || self.configuration.pause.paused_time();
+        let last_heartbeat = || {
+            self.configuration
+                .heartbeat
+                .last()
+                .map_or(call_start, |beat| beat.max(call_start))
+        };
+        let running_since = || self.configuration.host_calls.running_since(call_start);
+
+        loop {
+            let mut wait_until = deadline + paused();
+            if let Some(heartbeat_timeout) = heartbeat_timeout {
+                wait_until = wait_until.min(last_heartbeat() + heartbeat_timeout + paused());
+            }
+            if let Some(max_time) = max_time_between_host_calls {
+                // while the guest is in a host function call, check again
+                // once it could have run for `max_time` after it returns
+                let since = running_since().unwrap_or_else(Instant::now);
+                wait_until = wait_until.min(since + max_time + paused());
+            }
+            let response = self
+                .communication_channels
+                .from_handler_rx
+                .recv_timeout(wait_until.saturating_duration_since(Instant::now()));
+
+            match response {
+                Ok(msg) => return msg.into_result(),
+                Err(_) if Instant::now() >= deadline + paused() => {
+                    return self.handler_msg_receive_timed_out()
+                }
+                Err(_) => {
+                    if let Some(heartbeat_timeout) =
+                        heartbeat_timeout.filter(|t| last_heartbeat().elapsed() >= *t + paused())
+                    {
+                        log::error!(
+                            "No heartbeat from the guest for {:?}, cancelling execution",
+                            heartbeat_timeout
+                        );
+                        return Err(HyperlightError::GuestHeartbeatLapsed(heartbeat_timeout));
                     }
-                    None => Err(HyperlightError::HypervisorHandlerMessageReceiveTimedout()),
+                    if let Some(max_time) = max_time_between_host_calls.filter(|t| {
+                        running_since().is_some_and(|since| since.elapsed() >= *t + paused())
+                    }) {
+                        log::error!(
+                            "The guest ran for {:?} without calling the host, cancelling execution",
+                            max_time
+                        );
+                        return Err(HyperlightError::GuestHostCallIntervalExceeded(max_time));
+                    }
+                    // the guest sent a heartbeat or called the host while we
+                    // were waiting
                 }
             }
         }
     }
 
+    /// Called once receiving a `HandlerMsg` from the Hypervisor Handler
+    /// Thread has timed out
+    fn handler_msg_receive_timed_out(&self) -> Result<()> {
+        // If we have timed out it may be that the handler thread returned an error before it sent a message, so rather than just timeout here
+        // we will try and get the join handle for the thread and if it has finished check to see if it returned an error
+        // if it did then we will return that error, otherwise we will return the timeout error
+        // we need to take ownership of the handle to join it
+        match self
+            .execution_variables
+            .join_handle
+            .try_lock()
+            .map_err(|_| HyperlightError::HypervisorHandlerMessageReceiveTimedout())?
+            .take_if(|handle| handle.is_finished())
+        {
+            Some(handle) => {
+                // If the thread has finished, we try to join it and return the error if it has one
+                let res = handle.join();
+                if res.as_ref().is_ok_and(|inner_res| inner_res.is_err()) {
+                    #[allow(clippy::unwrap_used)]
+                    // We know that the thread has finished and that the inner result is an error, so we can safely unwrap the result and the contained err
+                    return Err(res.unwrap().unwrap_err());
+                }
+                Err(HyperlightError::HypervisorHandlerMessageReceiveTimedout())
+            }
+            None => Err(HyperlightError::HypervisorHandlerMessageReceiveTimedout()),
+        }
+    }
+
     /// Terminate the execution of the hypervisor handler
     ///
     /// This function is intended to be called after a guest function called has
@@ -684,25 +901,40 @@ impl HypervisorHandler {
         &mut self,
         sandbox_memory_manager: &mut SandboxMemoryManager<HostSharedMemory>,
     ) -> Result<HyperlightError> {
+        let mut attempts = InterruptAttempts::new(self.configuration.interrupt_policy);
+        // a paused vCPU has to run again to be stopped, and interrupting it
+        // from now on stops it rather than pausing it
+        self.configuration.pause.cancel();
         {
             if !self.execution_variables.running.load(Ordering::SeqCst) {
                 info!("Execution finished while trying to cancel it");
                 return Ok(HypervisorHandlerExecutionCancelAttemptOnFinishedExecution());
             } else {
-                self.terminate_execution()?;
+                self.terminate_execution(&mut attempts)?;
             }
         }
 
         {
-            sleep(self.configuration.max_wait_for_cancellation);
+            let max_wait_for_cancellation = self.configuration.max_wait_for_cancellation;
+            sleep(max_wait_for_cancellation);
+            attempts.record_wait(max_wait_for_cancellation);
             // check if still running
-            if self.execution_variables.running.load(Ordering::SeqCst) {
+            while self.execution_variables.running.load(Ordering::SeqCst) {
                 // If we still fail to acquire the hv_lock, this means that
                 // we had actually timed-out on a host function call as the
                 // `WHvCancelRunVirtualProcessor` didn't unlock.
+                if attempts.give_up(
+                    max_wait_for_cancellation,
+                    &self.configuration.interrupt_failure,
+                ) {
+                    log::info!("Tried to cancel guest execution on host function call");
+                    return Err(GuestExecutionHungOnHostFunctionCall());
+                }
 
-                log::info!("Tried to cancel guest execution on host function call");
-                return Err(GuestExecutionHungOnHostFunctionCall());
+                self.interrupt_vcpu()?;
+                let interval = attempts.record_attempt();
+                sleep(interval);
+                attempts.record_wait(interval);
             }
         }
 
@@ -750,7 +982,7 @@ impl HypervisorHandler {
     }
 
     #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
-    pub(crate) fn terminate_execution(&self) -> Result<()> {
+    fn terminate_execution(&self, attempts: &mut InterruptAttempts) -> Result<()> {
         error!(
             "Execution timed out after {} milliseconds , cancelling execution",
             self.execution_variables.get_timeout()?.as_millis()
@@ -758,39 +990,25 @@ impl HypervisorHandler {
 
         #[cfg(target_os = "linux")]
         {
-            let thread_id = self.execution_variables.get_thread_id()?;
-            if thread_id == u64::MAX {
-                log_then_return!("Failed to get thread id to signal thread");
-            }
-            let mut count: u128 = 0;
             // We need to send the signal multiple times in case the thread was between checking if it
             // should be cancelled and entering the run loop
 
-            // We cannot do this forever (if the thread is calling a host function that never
-            // returns we will sit here forever), so use the timeout_wait_to_cancel to limit the number
-            // of iterations
-
-            let number_of_iterations =
-                self.configuration.max_wait_for_cancellation.as_micros() / 500;
+            // If the thread is calling a host function that never returns we could sit here
+            // forever, so once max_wait_for_cancellation has passed the interrupt policy decides
+            // whether to carry on
 
             while !self.execution_variables.run_cancelled.load() {
-                count += 1;
-
-                if count > number_of_iterations {
+                if attempts.give_up(
+                    self.configuration.max_wait_for_cancellation,
+                    &self.configuration.interrupt_failure,
+                ) {
                     break;
                 }
 
-                info!(
-                    "Sending signal to thread {} iteration: {}",
-                    thread_id, count
-                );
-
-                let ret = unsafe { pthread_kill(thread_id, SIGRTMIN()) };
-                // We may get ESRCH if we try to signal a thread that has already exited
-                if ret < 0 && ret != ESRCH {
-                    log_then_return!("error {} calling pthread_kill", ret);
-                }
-                std::thread::sleep(Duration::from_micros(500));
+                self.interrupt_vcpu()?;
+                let interval = attempts.record_attempt();
+                std::thread::sleep(interval);
+                attempts.record_wait(interval);
             }
             if !self.execution_variables.run_cancelled.load() {
                 log_then_return!(GuestExecutionHungOnHostFunctionCall());
@@ -798,20 +1016,37 @@ impl HypervisorHandler {
         }
         #[cfg(target_os = "windows")]
         {
-            if self.execution_variables.get_partition_handle()?.is_some() {
-                // partition handle only set when running in-hypervisor (not in-process)
-                unsafe {
-                    WHvCancelRunVirtualProcessor(
-                        #[allow(clippy::unwrap_used)]
-                        self.execution_variables.get_partition_handle()?.unwrap(), // safe unwrap as we checked is some
-                        0,
-                        0,
-                    )
-                    .map_err(|e| new_error!("Failed to cancel guest execution {:?}", e))?;
-                }
+            self.interrupt_vcpu()?;
+            attempts.record_attempt();
+        }
+
+        Ok(())
+    }
+
+    /// Interrupt the vCPU once: with its hypervisor's interrupt handle if
+    /// it has one, such as WHP's, and otherwise on Linux by signalling the
+    /// thread running it
+    pub(crate) fn interrupt_vcpu(&self) -> Result<()> {
+        if let Some(interrupt_handle) = self.execution_variables.get_interrupt_handle()? {
+            return interrupt_handle.interrupt();
+        }
+        #[cfg(target_os = "linux")]
+        {
+            let thread_id = self.execution_variables.get_thread_id()?;
+            if thread_id == u64::MAX {
+                log_then_return!("Failed to get thread id to signal thread");
+            }
+
+            info!("Sending signal to thread {}", thread_id);
+
+            let ret = unsafe { pthread_kill(thread_id, SIGRTMIN()) };
+            // We may get ESRCH if we try to signal a thread that has already exited
+            if ret < 0 && ret != ESRCH {
+                log_then_return!("error {} calling pthread_kill", ret);
             }
-            // if running in-process on windows, we currently have no way of cancelling the execution
         }
+        // on windows a hypervisor without an interrupt handle is the
+        // in-process one, whose execution we have no way of cancelling
 
         Ok(())
     }
@@ -850,10 +1085,20 @@ pub enum HandlerMsg {
     Error(HyperlightError),
 }
 
+impl HandlerMsg {
+    fn into_result(self) -> Result<()> {
+        match self {
+            HandlerMsg::Error(e) => Err(e),
+            HandlerMsg::FinishedHypervisorHandlerAction => Ok(()),
+        }
+    }
+}
+
 fn set_up_hypervisor_partition(
     mgr: &mut SandboxMemoryManager<GuestSharedMemory>,
     #[allow(unused_variables)] // parameter only used for in-process mode
     outb_handler: OutBHandlerWrapper,
+    hypervisor_backend: &HypervisorBackend,
     #[cfg(gdb)] debug_info: &Option<DebugInfo>,
 ) -> Result<Box<dyn Hypervisor>> {
     let mem_size = u64::try_from(mgr.shared_mem.mem_size())?;
@@ -914,10 +1159,19 @@ fn set_up_hypervisor_partition(
             }
         }
     } else {
+        if hypervisor_backend.is_set() {
+            return hypervisor_backend.create(HypervisorPartition {
+                regions,
+                pml4_addr: pml4_ptr.absolute()?,
+                entrypoint_addr: entrypoint_ptr.absolute()?,
+                rsp_addr: rsp_ptr.absolute()?,
+            });
+        }
+
         // Create gdb thread if gdb is enabled and the configuration is provided
         // This is only done when the hypervisor is not in-process
         #[cfg(gdb)]
-        let gdb_conn = if let Some(DebugInfo { port }) = debug_info {
+        let gdb_conn = if let Some(DebugInfo { port, .. }) = debug_info {
             let gdb_conn = create_gdb_thread(*port, unsafe { pthread_self() });
 
             // in case the gdb thread creation fails, we still want to continue
@@ -933,6 +1187,8 @@ fn set_up_hypervisor_partition(
         } else {
             None
         };
+        #[cfg(gdb)]
+        let wait_on_crash = debug_info.is_some_and(|info| info.wait_on_crash);
 
         match *get_available_hypervisor() {
             #[cfg(mshv)]
@@ -944,6 +1200,8 @@ fn set_up_hypervisor_partition(
                     pml4_ptr,
                     #[cfg(gdb)]
                     gdb_conn,
+                    #[cfg(gdb)]
+                    wait_on_crash,
                 )?;
                 Ok(Box::new(hv))
             }
@@ -957,6 +1215,8 @@ fn set_up_hypervisor_partition(
                     rsp_ptr.absolute()?,
                     #[cfg(gdb)]
                     gdb_conn,
+                    #[cfg(gdb)]
+                    wait_on_crash,
                 )?;
                 Ok(Box::new(hv))
             }
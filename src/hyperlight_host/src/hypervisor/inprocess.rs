@@ -19,11 +19,7 @@ use std::os::raw::c_void;
 
 use log::LevelFilter;
 
-#[cfg(gdb)]
-use super::handlers::DbgMemAccessHandlerWrapper;
-use super::{HyperlightExit, Hypervisor};
-#[cfg(crashdump)]
-use crate::mem::memory_region::MemoryRegion;
+use super::{HyperlightExit, Hypervisor, VcpuContext};
 use crate::sandbox::leaked_outb::LeakedOutBWrapper;
 use crate::Result;
 
@@ -74,11 +70,8 @@ impl<'a> Hypervisor for InprocessDriver<'a> {
         _peb_addr: crate::mem::ptr::RawPtr,
         seed: u64,
         page_size: u32,
-        _outb_handle_fn: super::handlers::OutBHandlerWrapper,
-        _mem_access_fn: super::handlers::MemAccessHandlerWrapper,
-        _hv_handler: Option<super::hypervisor_handler::HypervisorHandler>,
         _guest_max_log_level: Option<LevelFilter>,
-        #[cfg(gdb)] _dbg_mem_access_fn: DbgMemAccessHandlerWrapper,
+        _ctx: VcpuContext,
     ) -> crate::Result<()> {
         let entrypoint_fn: extern "win64" fn(u64, u64, u64, u64) =
             unsafe { std::mem::transmute(self.args.entrypoint_raw as *const c_void) };
@@ -96,10 +89,7 @@ impl<'a> Hypervisor for InprocessDriver<'a> {
     fn dispatch_call_from_host(
         &mut self,
         dispatch_func_addr: crate::mem::ptr::RawPtr,
-        _outb_handle_fn: super::handlers::OutBHandlerWrapper,
-        _mem_access_fn: super::handlers::MemAccessHandlerWrapper,
-        _hv_handler: Option<super::hypervisor_handler::HypervisorHandler>,
-        #[cfg(gdb)] _dbg_mem_access_fn: DbgMemAccessHandlerWrapper,
+        _ctx: VcpuContext,
     ) -> crate::Result<()> {
         let ptr: u64 = dispatch_func_addr.into();
         let dispatch_func: extern "win64" fn() =
@@ -127,14 +117,4 @@ impl<'a> Hypervisor for InprocessDriver<'a> {
     fn as_mut_hypervisor(&mut self) -> &mut dyn Hypervisor {
         self
     }
-
-    #[cfg(target_os = "windows")]
-    fn get_partition_handle(&self) -> windows::Win32::System::Hypervisor::WHV_PARTITION_HANDLE {
-        unimplemented!("get_partition_handle should not be needed since we are in in-process mode")
-    }
-
-    #[cfg(crashdump)]
-    fn get_memory_regions(&self) -> &[MemoryRegion] {
-        unimplemented!("get_memory_regions is not supported since we are in in-process mode")
-    }
 }
@@ -19,29 +19,55 @@ use std::fmt::Debug;
 #[cfg(gdb)]
 use std::sync::{Arc, Mutex};
 
-use kvm_bindings::{kvm_fpu, kvm_regs, kvm_userspace_memory_region, KVM_MEM_READONLY};
-use kvm_ioctls::Cap::UserMemory;
+use hyperlight_common::transport::mmio_doorbell_port;
+use kvm_bindings::{
+    kvm_enable_cap, kvm_fpu, kvm_guest_debug, kvm_msr_entry, kvm_msr_filter, kvm_regs,
+    kvm_userspace_memory_region, kvm_xcrs, CpuId, Msrs, KVMIO, KVM_CAP_X86_USER_SPACE_MSR,
+    KVM_GUESTDBG_ENABLE, KVM_GUESTDBG_SINGLESTEP, KVM_MAX_CPUID_ENTRIES, KVM_MEM_READONLY,
+    KVM_MSR_EXIT_REASON_FILTER, KVM_MSR_FILTER_DEFAULT_ALLOW, KVM_MSR_FILTER_DEFAULT_DENY,
+    KVM_MSR_FILTER_READ, KVM_MSR_FILTER_WRITE,
+};
+use kvm_ioctls::Cap::{TscControl, UserMemory};
 use kvm_ioctls::{Kvm, VcpuExit, VcpuFd, VmFd};
 use log::LevelFilter;
 use tracing::{instrument, Span};
+use vmm_sys_util::ioctl::ioctl_with_ref;
+use vmm_sys_util::ioctl_iow_nr;
 
-use super::fpu::{FP_CONTROL_WORD_DEFAULT, FP_TAG_WORD_DEFAULT, MXCSR_DEFAULT};
+use super::fpu::{
+    guest_xcr0, reset_xsave_area, FP_CONTROL_WORD_DEFAULT, FP_TAG_WORD_DEFAULT, MXCSR_DEFAULT,
+};
 #[cfg(gdb)]
 use super::gdb::{DebugCommChannel, DebugMsg, DebugResponse, GuestDebug, KvmDebug, VcpuStopReason};
-#[cfg(gdb)]
-use super::handlers::DbgMemAccessHandlerWrapper;
-use super::handlers::{MemAccessHandlerWrapper, OutBHandlerWrapper};
+use super::handlers::OutBHandlerWrapper;
 use super::{
-    HyperlightExit, Hypervisor, VirtualCPU, CR0_AM, CR0_ET, CR0_MP, CR0_NE, CR0_PE, CR0_PG, CR0_WP,
-    CR4_OSFXSR, CR4_OSXMMEXCPT, CR4_PAE, EFER_LMA, EFER_LME, EFER_NX, EFER_SCE,
+    HyperlightExit, Hypervisor, VcpuContext, VirtualCPU, CR0_AM, CR0_ET, CR0_MP, CR0_NE, CR0_PE,
+    CR0_PG, CR0_WP, CR4_OSFXSR, CR4_OSXMMEXCPT, CR4_OSXSAVE, CR4_PAE, EFER_LMA, EFER_LME, EFER_NX,
+    EFER_SCE,
 };
-use crate::hypervisor::hypervisor_handler::HypervisorHandler;
 use crate::mem::memory_region::{MemoryRegion, MemoryRegionFlags};
 use crate::mem::ptr::{GuestPtr, RawPtr};
+use crate::sandbox::cpu_fault::CpuFaultContext;
+use crate::sandbox::cpuid::CpuidConfiguration;
+use crate::sandbox::guest_time::GuestTime;
+use crate::sandbox::msr::{MsrAccess, MsrAction, MsrPolicy};
 #[cfg(gdb)]
 use crate::HyperlightError;
 use crate::{log_then_return, new_error, Result};
 
+// kvm-ioctls has no wrapper for setting the MSR filter
+ioctl_iow_nr!(KVM_X86_SET_MSR_FILTER, KVMIO, 0xc6, kvm_msr_filter);
+
+/// The CPUID.(EAX=7,ECX=0):ECX bit reporting CET shadow stack support
+const CPUID_7_ECX_CET_SS: u32 = 1 << 7;
+
+/// The vendor string of Intel processors, in the EBX, EDX, ECX order of
+/// CPUID leaf 0
+const INTEL_VENDOR: &[u8; 12] = b"GenuineIntel";
+
+/// The IA32_TIME_STAMP_COUNTER MSR
+const MSR_IA32_TSC: u32 = 0x10;
+
 /// Return `true` if the KVM API is available, version 12, and has UserMemory capability, or `false` otherwise
 #[instrument(skip_all, parent = Span::current(), level = "Trace")]
 pub(crate) fn is_hypervisor_present() -> bool {
@@ -275,17 +301,41 @@ mod debug {
 
 /// A Hypervisor driver for KVM on Linux
 pub(super) struct KVMDriver {
-    _kvm: Kvm,
-    _vm_fd: VmFd,
+    kvm: Kvm,
+    vm_fd: VmFd,
     vcpu_fd: VcpuFd,
     entrypoint: u64,
     orig_rsp: GuestPtr,
     mem_regions: Vec<MemoryRegion>,
+    /// The KVM memory slot each of `mem_regions` is mapped in
+    mem_slots: Vec<u32>,
+    /// The maximum number of instructions the guest may execute per call,
+    /// or 0 if there is no limit
+    max_guest_instructions: u64,
+    /// The number of instructions the guest has executed in the current call
+    executed_guest_instructions: u64,
+    /// The changes made to the CPUID KVM supports before giving it to the
+    /// vCPU
+    cpuid: CpuidConfiguration,
+    /// How the MSR accesses KVM's MSR filter exits on are handled
+    msr_policy: MsrPolicy,
+    /// Whether the guest signals the host through the MMIO doorbell page
+    mmio_doorbell: bool,
+    /// How the guest's time stamp counter advances
+    guest_time: GuestTime,
+    /// The time stamp counter the guest is resumed with, unless its time
+    /// is the host's
+    guest_tsc: u64,
+    /// Whether the guest can use the XSAVE state components beyond x87 and
+    /// SSE, which must then be reset before each call
+    extended_cpu_state: bool,
 
     #[cfg(gdb)]
     debug: Option<KvmDebug>,
     #[cfg(gdb)]
     gdb_conn: Option<DebugCommChannel<DebugResponse, DebugMsg>>,
+    #[cfg(gdb)]
+    wait_on_crash: bool,
 }
 
 impl KVMDriver {
@@ -299,28 +349,19 @@ impl KVMDriver {
         entrypoint: u64,
         rsp: u64,
         #[cfg(gdb)] gdb_conn: Option<DebugCommChannel<DebugResponse, DebugMsg>>,
+        #[cfg(gdb)] wait_on_crash: bool,
     ) -> Result<Self> {
         let kvm = Kvm::new()?;
 
         let vm_fd = kvm.create_vm_with_type(0)?;
 
-        let perm_flags =
-            MemoryRegionFlags::READ | MemoryRegionFlags::WRITE | MemoryRegionFlags::EXECUTE;
-
-        mem_regions.iter().enumerate().try_for_each(|(i, region)| {
-            let perm_flags = perm_flags.intersection(region.flags);
-            let kvm_region = kvm_userspace_memory_region {
-                slot: i as u32,
-                guest_phys_addr: region.guest_region.start as u64,
-                memory_size: (region.guest_region.end - region.guest_region.start) as u64,
-                userspace_addr: region.host_region.start as u64,
-                flags: match perm_flags {
-                    MemoryRegionFlags::READ => KVM_MEM_READONLY,
-                    _ => 0, // normal, RWX
-                },
-            };
-            unsafe { vm_fd.set_user_memory_region(kvm_region) }
-        })?;
+        let mem_slots: Vec<u32> = (0..mem_regions.len() as u32).collect();
+        mem_regions
+            .iter()
+            .zip(&mem_slots)
+            .try_for_each(|(region, slot)| unsafe {
+                vm_fd.set_user_memory_region(Self::user_memory_region(*slot, region))
+            })?;
 
         let mut vcpu_fd = vm_fd.create_vcpu(0)?;
         Self::setup_initial_sregs(&mut vcpu_fd, pml4_addr)?;
@@ -328,8 +369,11 @@ impl KVMDriver {
         #[cfg(gdb)]
         let (debug, gdb_conn) = if let Some(gdb_conn) = gdb_conn {
             let mut debug = KvmDebug::new();
-            // Add breakpoint to the entry point address
-            debug.add_hw_breakpoint(&vcpu_fd, entrypoint)?;
+            // Add breakpoint to the entry point address, unless the guest
+            // should only stop if it crashes
+            if !wait_on_crash {
+                debug.add_hw_breakpoint(&vcpu_fd, entrypoint)?;
+            }
 
             (Some(debug), Some(gdb_conn))
         } else {
@@ -339,22 +383,69 @@ impl KVMDriver {
         let rsp_gp = GuestPtr::try_from(RawPtr::from(rsp))?;
 
         let ret = Self {
-            _kvm: kvm,
-            _vm_fd: vm_fd,
+            kvm,
+            vm_fd,
             vcpu_fd,
             entrypoint,
             orig_rsp: rsp_gp,
             mem_regions,
+            mem_slots,
+            max_guest_instructions: 0,
+            executed_guest_instructions: 0,
+            cpuid: CpuidConfiguration::default(),
+            msr_policy: MsrPolicy::default(),
+            mmio_doorbell: false,
+            guest_time: GuestTime::default(),
+            guest_tsc: 0,
+            extended_cpu_state: false,
 
             #[cfg(gdb)]
             debug,
             #[cfg(gdb)]
             gdb_conn,
+            #[cfg(gdb)]
+            wait_on_crash,
         };
 
         Ok(ret)
     }
 
+    /// The KVM memory region `region` is mapped into the guest with, in
+    /// `slot`
+    fn user_memory_region(slot: u32, region: &MemoryRegion) -> kvm_userspace_memory_region {
+        let perm_flags = region.flags.intersection(
+            MemoryRegionFlags::READ | MemoryRegionFlags::WRITE | MemoryRegionFlags::EXECUTE,
+        );
+        kvm_userspace_memory_region {
+            slot,
+            guest_phys_addr: region.guest_region.start as u64,
+            memory_size: (region.guest_region.end - region.guest_region.start) as u64,
+            userspace_addr: region.host_region.start as u64,
+            flags: if perm_flags.contains(MemoryRegionFlags::WRITE) {
+                0 // normal, RWX
+            } else {
+                KVM_MEM_READONLY
+            },
+        }
+    }
+
+    /// Reset the vector registers `set_fpu` doesn't reach, see
+    /// `reset_xsave_area`
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn reset_xsave(&mut self) -> Result<()> {
+        let mut xsave = self.vcpu_fd.get_xsave()?;
+        let mut area: Vec<u8> = xsave.region.iter().flat_map(|w| w.to_le_bytes()).collect();
+        reset_xsave_area(&mut area)?;
+        for (word, bytes) in xsave.region.iter_mut().zip(area.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        // SAFETY: KVM reads no more than the 4096 bytes of `kvm_xsave`, as
+        // the only state components enabled for guests, see `guest_xcr0`,
+        // fit in them
+        unsafe { self.vcpu_fd.set_xsave(&xsave)? };
+        Ok(())
+    }
+
     #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
     fn setup_initial_sregs(vcpu_fd: &mut VcpuFd, pml4_addr: u64) -> Result<()> {
         // setup paging and IA-32e (64-bit) mode
@@ -367,6 +458,44 @@ impl KVMDriver {
         vcpu_fd.set_sregs(&sregs)?;
         Ok(())
     }
+
+    /// Give the vCPU the CPUID KVM supports, with the sandbox's CPUID
+    /// configuration applied, and return it
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn set_supported_cpuid(&mut self) -> Result<CpuId> {
+        let mut cpuid = self.kvm.get_supported_cpuid(KVM_MAX_CPUID_ENTRIES)?;
+        for entry in cpuid.as_mut_slice() {
+            let mut registers = [entry.eax, entry.ebx, entry.ecx, entry.edx];
+            self.cpuid
+                .apply(entry.function, entry.index, &mut registers);
+            [entry.eax, entry.ebx, entry.ecx, entry.edx] = registers;
+        }
+        self.vcpu_fd.set_cpuid2(&cpuid)?;
+        Ok(cpuid)
+    }
+
+    /// Read the vCPU's time stamp counter
+    fn get_guest_tsc(&self) -> Result<u64> {
+        let mut msrs = Msrs::from_entries(&[kvm_msr_entry {
+            index: MSR_IA32_TSC,
+            ..Default::default()
+        }])
+        .map_err(|e| new_error!("Could not create the MSR list: {:?}", e))?;
+        self.vcpu_fd.get_msrs(&mut msrs)?;
+        Ok(msrs.as_slice()[0].data)
+    }
+
+    /// Set the vCPU's time stamp counter to `tsc`
+    fn set_guest_tsc(&self, tsc: u64) -> Result<()> {
+        let msrs = Msrs::from_entries(&[kvm_msr_entry {
+            index: MSR_IA32_TSC,
+            data: tsc,
+            ..Default::default()
+        }])
+        .map_err(|e| new_error!("Could not create the MSR list: {:?}", e))?;
+        self.vcpu_fd.set_msrs(&msrs)?;
+        Ok(())
+    }
 }
 
 impl Debug for KVMDriver {
@@ -404,11 +533,8 @@ impl Hypervisor for KVMDriver {
         peb_addr: RawPtr,
         seed: u64,
         page_size: u32,
-        outb_hdl: OutBHandlerWrapper,
-        mem_access_hdl: MemAccessHandlerWrapper,
-        hv_handler: Option<HypervisorHandler>,
         max_guest_log_level: Option<LevelFilter>,
-        #[cfg(gdb)] dbg_mem_access_fn: DbgMemAccessHandlerWrapper,
+        ctx: VcpuContext,
     ) -> Result<()> {
         let max_guest_log_level: u64 = match max_guest_log_level {
             Some(level) => level as u64,
@@ -428,15 +554,9 @@ impl Hypervisor for KVMDriver {
             ..Default::default()
         };
         self.vcpu_fd.set_regs(&regs)?;
+        self.executed_guest_instructions = 0;
 
-        VirtualCPU::run(
-            self.as_mut_hypervisor(),
-            hv_handler,
-            outb_hdl,
-            mem_access_hdl,
-            #[cfg(gdb)]
-            dbg_mem_access_fn,
-        )?;
+        VirtualCPU::run(self.as_mut_hypervisor(), ctx)?;
 
         Ok(())
     }
@@ -445,10 +565,7 @@ impl Hypervisor for KVMDriver {
     fn dispatch_call_from_host(
         &mut self,
         dispatch_func_addr: RawPtr,
-        outb_handle_fn: OutBHandlerWrapper,
-        mem_access_fn: MemAccessHandlerWrapper,
-        hv_handler: Option<HypervisorHandler>,
-        #[cfg(gdb)] dbg_mem_access_fn: DbgMemAccessHandlerWrapper,
+        ctx: VcpuContext,
     ) -> Result<()> {
         // Reset general purpose registers, then set RIP and RSP
         let regs = kvm_regs {
@@ -466,16 +583,13 @@ impl Hypervisor for KVMDriver {
             ..Default::default() // zero out the rest
         };
         self.vcpu_fd.set_fpu(&fpu)?;
+        if self.extended_cpu_state {
+            self.reset_xsave()?;
+        }
+        self.executed_guest_instructions = 0;
 
         // run
-        VirtualCPU::run(
-            self.as_mut_hypervisor(),
-            hv_handler,
-            outb_handle_fn,
-            mem_access_fn,
-            #[cfg(gdb)]
-            dbg_mem_access_fn,
-        )?;
+        VirtualCPU::run(self.as_mut_hypervisor(), ctx)?;
 
         Ok(())
     }
@@ -509,6 +623,11 @@ impl Hypervisor for KVMDriver {
 
     #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
     fn run(&mut self) -> Result<HyperlightExit> {
+        // Setting the TSC when the vCPU is resumed leaves out the time it
+        // spent stopped
+        if self.guest_time != GuestTime::Host {
+            self.set_guest_tsc(self.guest_tsc)?;
+        }
         let exit_reason = self.vcpu_fd.run();
         let result = match exit_reason {
             Ok(VcpuExit::Hlt) => {
@@ -533,17 +652,64 @@ impl Hypervisor for KVMDriver {
                     None => HyperlightExit::Mmio(addr),
                 }
             }
-            Ok(VcpuExit::MmioWrite(addr, _)) => {
-                crate::debug!("KVM MMIO Write -Details: Address: {} \n {:#?}", addr, &self);
-
-                match self.get_memory_access_violation(
-                    addr as usize,
-                    &self.mem_regions,
-                    MemoryRegionFlags::WRITE,
-                ) {
-                    Some(access_violation_exit) => access_violation_exit,
-                    None => HyperlightExit::Mmio(addr),
+            Ok(VcpuExit::MmioWrite(addr, data)) => match mmio_doorbell_port(addr) {
+                Some(port) if self.mmio_doorbell => {
+                    crate::debug!(
+                        "KVM MMIO Doorbell Details : \nPort : {}\nData : {:?}",
+                        port,
+                        data
+                    );
+                    HyperlightExit::IoOut(port, data.to_vec(), 0, 0)
+                }
+                _ => {
+                    crate::debug!("KVM MMIO Write -Details: Address: {} \n {:#?}", addr, &self);
+
+                    match self.get_memory_access_violation(
+                        addr as usize,
+                        &self.mem_regions,
+                        MemoryRegionFlags::WRITE,
+                    ) {
+                        Some(access_violation_exit) => access_violation_exit,
+                        None => HyperlightExit::Mmio(addr),
+                    }
                 }
+            },
+            // The guest is single-stepped when its instructions are counted, so
+            // each debug exit means the guest has executed one more instruction
+            Ok(VcpuExit::Debug(_)) if self.max_guest_instructions > 0 => {
+                self.executed_guest_instructions += 1;
+                if self.executed_guest_instructions >= self.max_guest_instructions {
+                    HyperlightExit::InstructionLimitExceeded(self.max_guest_instructions)
+                } else {
+                    HyperlightExit::Retry()
+                }
+            }
+            // Only the MSRs the policy doesn't pass through are filtered, so
+            // these exits are for MSRs that are either emulated or denied
+            Ok(VcpuExit::X86Rdmsr(exit)) => match self.msr_policy.action(exit.index) {
+                MsrAction::EmulateZero => {
+                    *exit.data = 0;
+                    *exit.error = 0;
+                    HyperlightExit::Retry()
+                }
+                _ => HyperlightExit::MsrAccessDenied(exit.index, MsrAccess::Read),
+            },
+            Ok(VcpuExit::X86Wrmsr(exit)) => match self.msr_policy.action(exit.index) {
+                MsrAction::EmulateZero => {
+                    *exit.error = 0;
+                    HyperlightExit::Retry()
+                }
+                _ => HyperlightExit::MsrAccessDenied(exit.index, MsrAccess::Write(exit.data)),
+            },
+            // The guest couldn't deliver a double fault, so the vCPU shut down
+            Ok(VcpuExit::Shutdown) => {
+                let regs = self.vcpu_fd.get_regs()?;
+                let sregs = self.vcpu_fd.get_sregs()?;
+                HyperlightExit::TripleFault(CpuFaultContext {
+                    rip: regs.rip,
+                    rsp: regs.rsp,
+                    cr2: sregs.cr2,
+                })
             }
             #[cfg(gdb)]
             // KVM provides architecture specific information about the vCPU state when exiting
@@ -573,19 +739,227 @@ impl Hypervisor for KVMDriver {
                 HyperlightExit::Unknown(format!("Unexpected KVM Exit {:?}", other))
             }
         };
+        if matches!(self.guest_time, GuestTime::Virtual { .. }) {
+            self.guest_tsc = self.get_guest_tsc()?;
+        }
         Ok(result)
     }
 
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn set_max_guest_instructions(&mut self, max_instructions: u64) -> Result<()> {
+        #[cfg(gdb)]
+        if self.debug.is_some() {
+            log_then_return!("Limiting guest execution by instruction count is not supported while debugging the guest");
+        }
+
+        let dbg_cfg = kvm_guest_debug {
+            control: KVM_GUESTDBG_ENABLE | KVM_GUESTDBG_SINGLESTEP,
+            ..Default::default()
+        };
+        self.vcpu_fd
+            .set_guest_debug(&dbg_cfg)
+            .map_err(|e| new_error!("Could not enable single-stepping: {:?}", e))?;
+        self.max_guest_instructions = max_instructions;
+
+        Ok(())
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn set_cpuid(&mut self, cpuid: &CpuidConfiguration) -> Result<()> {
+        self.cpuid = *cpuid;
+        self.set_supported_cpuid()?;
+        Ok(())
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn set_guest_time(&mut self, guest_time: &GuestTime) -> Result<()> {
+        if let GuestTime::Virtual {
+            tsc_khz: Some(tsc_khz),
+        } = guest_time
+        {
+            if !self.kvm.check_extension(TscControl) {
+                log_then_return!("KVM cannot scale the guest's time stamp counter");
+            }
+            self.vcpu_fd
+                .set_tsc_khz(*tsc_khz)
+                .map_err(|e| new_error!("Could not set the guest's TSC frequency: {:?}", e))?;
+        }
+        self.guest_time = *guest_time;
+        self.guest_tsc = guest_time.initial_tsc();
+        Ok(())
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn set_msr_policy(&mut self, policy: &MsrPolicy) -> Result<()> {
+        // MSRs denied by the filter exit to the host instead of raising #GP
+        // in the guest, so that `run` can apply the policy's action
+        let cap = kvm_enable_cap {
+            cap: KVM_CAP_X86_USER_SPACE_MSR,
+            args: [KVM_MSR_EXIT_REASON_FILTER as u64, 0, 0, 0],
+            ..Default::default()
+        };
+        self.vm_fd
+            .enable_cap(&cap)
+            .map_err(|e| new_error!("Could not enable MSR exits: {:?}", e))?;
+
+        // The filter allows the MSRs that are passed through, with one
+        // single MSR range for each MSR whose action differs from the
+        // default in that respect
+        let default_passthrough = policy.default_action() == MsrAction::Passthrough;
+        let mut filter = kvm_msr_filter {
+            flags: if default_passthrough {
+                KVM_MSR_FILTER_DEFAULT_ALLOW
+            } else {
+                KVM_MSR_FILTER_DEFAULT_DENY
+            },
+            ..Default::default()
+        };
+        // one bitmap byte per range, which must outlive the ioctl
+        let mut bitmaps = [0u8; MsrPolicy::MAX_OVERRIDES];
+        let ranges = policy
+            .overrides()
+            .filter(|(_, action)| (*action == MsrAction::Passthrough) != default_passthrough);
+        for (i, (msr, _)) in ranges.enumerate() {
+            bitmaps[i] = u8::from(!default_passthrough);
+            let range = &mut filter.ranges[i];
+            range.flags = KVM_MSR_FILTER_READ | KVM_MSR_FILTER_WRITE;
+            range.nmsrs = 1;
+            range.base = msr;
+            range.bitmap = &mut bitmaps[i];
+        }
+        // SAFETY: the filter and the bitmaps it points to are valid for
+        // the duration of the ioctl, and KVM copies them
+        let ret = unsafe { ioctl_with_ref(&self.vm_fd, KVM_X86_SET_MSR_FILTER(), &filter) };
+        if ret < 0 {
+            log_then_return!(
+                "Could not set the MSR filter: {:?}",
+                std::io::Error::last_os_error()
+            );
+        }
+
+        self.msr_policy = *policy;
+        Ok(())
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn enable_mmio_doorbell(&mut self) -> Result<()> {
+        // No memory slot backs the doorbell page, so KVM exits on writes to it
+        self.mmio_doorbell = true;
+        Ok(())
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn trap_entropy_instructions(&mut self) -> Result<()> {
+        // On Intel processors KVM makes RDRAND and RDSEED exit when the
+        // guest's CPUID doesn't report them and raises an invalid opcode
+        // exception in the guest, AMD processors can't intercept them
+        let vendor = unsafe { std::arch::x86_64::__cpuid(0) };
+        let vendor = [vendor.ebx, vendor.edx, vendor.ecx].map(u32::to_le_bytes);
+        if vendor.concat() != INTEL_VENDOR {
+            log_then_return!(HyperlightError::EntropyInstructionsNotTrapped(
+                "KVM on a processor that isn't an Intel processor".to_string()
+            ));
+        }
+        Ok(())
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn enable_shadow_stack(&mut self) -> Result<()> {
+        // The guest can only set CR4.CET and the CET MSRs if its CPUID
+        // reports shadow stacks, which KVM only does if the host supports
+        // them and KVM can virtualize them
+        let cpuid = self.set_supported_cpuid()?;
+        let shadow_stack_supported = cpuid
+            .as_slice()
+            .iter()
+            .find(|entry| entry.function == 7 && entry.index == 0)
+            .is_some_and(|entry| entry.ecx & CPUID_7_ECX_CET_SS != 0);
+        if !shadow_stack_supported {
+            log_then_return!(HyperlightError::ShadowStackNotSupported(
+                "KVM on this host".to_string()
+            ));
+        }
+        Ok(())
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn enable_extended_cpu_state(&mut self) -> Result<()> {
+        // KVM only accepts XCR0 values that the vCPU's CPUID reports as
+        // supported, so give the vCPU the CPUID KVM supports first. State
+        // components hidden by the CPUID configuration aren't reported.
+        let cpuid = self.set_supported_cpuid()?;
+        let supported_xcr0 = cpuid
+            .as_slice()
+            .iter()
+            .find(|entry| entry.function == 0xd && entry.index == 0)
+            .map(|entry| (u64::from(entry.edx) << 32) | u64::from(entry.eax))
+            .ok_or_else(|| new_error!("KVM does not support XSAVE"))?;
+
+        let mut sregs = self.vcpu_fd.get_sregs()?;
+        sregs.cr4 |= CR4_OSXSAVE;
+        self.vcpu_fd.set_sregs(&sregs)?;
+
+        let mut xcrs = kvm_xcrs {
+            nr_xcrs: 1,
+            ..Default::default()
+        };
+        xcrs.xcrs[0].xcr = 0;
+        xcrs.xcrs[0].value = guest_xcr0()? & supported_xcr0;
+        self.vcpu_fd.set_xcrs(&xcrs)?;
+        self.extended_cpu_state = true;
+
+        Ok(())
+    }
+
     #[instrument(skip_all, parent = Span::current(), level = "Trace")]
     fn as_mut_hypervisor(&mut self) -> &mut dyn Hypervisor {
         self as &mut dyn Hypervisor
     }
 
-    #[cfg(crashdump)]
     fn get_memory_regions(&self) -> &[MemoryRegion] {
         &self.mem_regions
     }
 
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    unsafe fn map_region(&mut self, region: &MemoryRegion) -> Result<()> {
+        if self.mem_regions.iter().any(|mapped| {
+            mapped.guest_region.start < region.guest_region.end
+                && region.guest_region.start < mapped.guest_region.end
+        }) {
+            log_then_return!(
+                "Memory region {:?} overlaps a region mapped into the guest",
+                region.guest_region
+            );
+        }
+        let slot = (0..u32::MAX)
+            .find(|slot| !self.mem_slots.contains(slot))
+            .ok_or_else(|| new_error!("No KVM memory slot is free"))?;
+        self.vm_fd
+            .set_user_memory_region(Self::user_memory_region(slot, region))?;
+        self.mem_regions.push(region.clone());
+        self.mem_slots.push(slot);
+        Ok(())
+    }
+
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn unmap_region(&mut self, region: &MemoryRegion) -> Result<()> {
+        let Some(index) = self.mem_regions.iter().position(|mapped| mapped == region) else {
+            log_then_return!(
+                "Memory region {:?} is not mapped into the guest",
+                region.guest_region
+            );
+        };
+        // KVM deletes a slot when its memory is set to zero bytes
+        let deleted = kvm_userspace_memory_region {
+            slot: self.mem_slots[index],
+            ..Default::default()
+        };
+        unsafe { self.vm_fd.set_user_memory_region(deleted) }?;
+        self.mem_regions.remove(index);
+        self.mem_slots.remove(index);
+        Ok(())
+    }
+
     #[cfg(gdb)]
     fn handle_debug(
         &mut self,
@@ -627,6 +1001,30 @@ impl Hypervisor for KVMDriver {
 
         Ok(())
     }
+
+    #[cfg(gdb)]
+    fn wait_for_debugger_on_crash(
+        &mut self,
+        dbg_mem_access_fn: Arc<Mutex<dyn super::handlers::DbgMemAccessHandlerCaller>>,
+    ) -> Result<()> {
+        if self.wait_on_crash {
+            self.wait_for_debugger(dbg_mem_access_fn)?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(gdb)]
+    fn wait_for_debugger(
+        &mut self,
+        dbg_mem_access_fn: Arc<Mutex<dyn super::handlers::DbgMemAccessHandlerCaller>>,
+    ) -> Result<()> {
+        if self.gdb_conn.is_some() {
+            self.handle_debug(dbg_mem_access_fn, VcpuStopReason::Crash)?;
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -22,6 +22,8 @@ use crate::hypervisor::metrics::HypervisorMetric::NumberOfCancelledGuestExecutio
 use crate::mem::memory_region::{MemoryRegion, MemoryRegionFlags};
 use crate::{int_counter_inc, log_then_return, new_error, HyperlightError, Result};
 
+/// Out-of-tree hypervisor backends
+pub mod backend;
 /// Util for handling x87 fpu state
 #[cfg(any(kvm, mshv, target_os = "windows"))]
 pub mod fpu;
@@ -65,22 +67,27 @@ pub(crate) mod crashdump;
 
 use std::fmt::Debug;
 use std::str::FromStr;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+#[cfg(gdb)]
+use std::sync::Mutex;
 
 #[cfg(gdb)]
 use gdb::VcpuStopReason;
 
 #[cfg(gdb)]
 use self::handlers::{DbgMemAccessHandlerCaller, DbgMemAccessHandlerWrapper};
-use self::handlers::{
-    MemAccessHandlerCaller, MemAccessHandlerWrapper, OutBHandlerCaller, OutBHandlerWrapper,
-};
-use crate::hypervisor::hypervisor_handler::HypervisorHandler;
+use self::handlers::{MemAccessHandlerWrapper, OutBHandlerWrapper};
+pub use crate::hypervisor::hypervisor_handler::HypervisorHandler;
 use crate::mem::ptr::RawPtr;
+use crate::sandbox::cpu_fault::{CpuFaultAction, CpuFaultContext};
+use crate::sandbox::cpuid::CpuidConfiguration;
+use crate::sandbox::guest_time::GuestTime;
+use crate::sandbox::msr::{MsrAccess, MsrPolicy};
 
 pub(crate) const CR4_PAE: u64 = 1 << 5;
 pub(crate) const CR4_OSFXSR: u64 = 1 << 9;
 pub(crate) const CR4_OSXMMEXCPT: u64 = 1 << 10;
+pub(crate) const CR4_OSXSAVE: u64 = 1 << 18;
 pub(crate) const CR0_PE: u64 = 1;
 pub(crate) const CR0_MP: u64 = 1 << 1;
 pub(crate) const CR0_ET: u64 = 1 << 4;
@@ -113,27 +120,60 @@ pub enum HyperlightExit {
     Unknown(String),
     /// The operation should be retried, for example this can happen on Linux where a call to run the CPU can return EAGAIN
     Retry(),
+    /// The vCPU has executed the maximum number of instructions it was given
+    InstructionLimitExceeded(u64),
+    /// The vCPU accessed the given MSR, which the sandbox's MSR policy denies
+    MsrAccessDenied(u32, MsrAccess),
+    /// The vCPU triple faulted and shut down, with the given registers
+    TripleFault(CpuFaultContext),
+}
+
+/// The handlers the vCPU's exits are handled with, handed to `initialise`
+/// and `dispatch_call_from_host` to pass on to `VirtualCPU::run`. What it
+/// holds depends on the features `hyperlight_host` is built with, which
+/// is why backends only pass it on.
+#[derive(Clone)]
+pub struct VcpuContext {
+    pub(crate) outb_handle_fn: OutBHandlerWrapper,
+    pub(crate) mem_access_fn: MemAccessHandlerWrapper,
+    /// `None` when coming from the C API, which sets the hypervisor up
+    /// without a `HypervisorHandler`
+    pub(crate) hv_handler: Option<HypervisorHandler>,
+    #[cfg(gdb)]
+    pub(crate) dbg_mem_access_fn: DbgMemAccessHandlerWrapper,
+}
+
+impl VcpuContext {
+    /// The handler `handle_io` is called with
+    pub fn outb_handler(&self) -> &OutBHandlerWrapper {
+        &self.outb_handle_fn
+    }
+}
+
+/// Interrupts a vCPU from another thread, see `Hypervisor::interrupt_handle`
+pub trait VcpuInterrupt: Debug + Send + Sync {
+    /// Make the vCPU's `run` return `HyperlightExit::Cancelled` if it is
+    /// running the guest, or as soon as it next does
+    fn interrupt(&self) -> Result<()>;
 }
 
 /// A common set of hypervisor functionality
 ///
-/// Note: a lot of these structures take in an `Option<HypervisorHandler>`.
-/// This is because, if we are coming from the C API, we don't have a HypervisorHandler and have
-/// to account for the fact the Hypervisor was set up beforehand.
-pub(crate) trait Hypervisor: Debug + Sync + Send {
+/// Implemented by the built-in drivers, and by out-of-tree backends set
+/// with `UninitializedSandbox::set_hypervisor_backend`, see the `backend`
+/// module. The methods that only some builds use have default bodies, so
+/// that a backend builds whatever features `hyperlight_host` is built
+/// with.
+pub trait Hypervisor: Debug + Sync + Send {
     /// Initialise the internally stored vCPU with the given PEB address and
     /// random number seed, then run it until a HLT instruction.
-    #[allow(clippy::too_many_arguments)]
     fn initialise(
         &mut self,
         peb_addr: RawPtr,
         seed: u64,
         page_size: u32,
-        outb_handle_fn: OutBHandlerWrapper,
-        mem_access_fn: MemAccessHandlerWrapper,
-        hv_handler: Option<HypervisorHandler>,
         guest_max_log_level: Option<LevelFilter>,
-        #[cfg(gdb)] dbg_mem_access_fn: DbgMemAccessHandlerWrapper,
+        ctx: VcpuContext,
     ) -> Result<()>;
 
     /// Dispatch a call from the host to the guest using the given pointer
@@ -146,10 +186,7 @@ pub(crate) trait Hypervisor: Debug + Sync + Send {
     fn dispatch_call_from_host(
         &mut self,
         dispatch_func_addr: RawPtr,
-        outb_handle_fn: OutBHandlerWrapper,
-        mem_access_fn: MemAccessHandlerWrapper,
-        hv_handler: Option<HypervisorHandler>,
-        #[cfg(gdb)] dbg_mem_access_fn: DbgMemAccessHandlerWrapper,
+        ctx: VcpuContext,
     ) -> Result<()>;
 
     /// Handle an IO exit from the internally stored vCPU.
@@ -229,15 +266,103 @@ pub(crate) trait Hypervisor: Debug + Sync + Send {
         LevelFilter::from_str(level).unwrap_or(LevelFilter::Error) as u32
     }
 
+    /// Limit each subsequent call to `initialise` or
+    /// `dispatch_call_from_host` to executing at most `max_instructions`
+    /// guest instructions, after which `run` returns
+    /// `HyperlightExit::InstructionLimitExceeded`
+    fn set_max_guest_instructions(&mut self, _max_instructions: u64) -> Result<()> {
+        log_then_return!(
+            "Limiting guest execution by instruction count is not supported by this hypervisor"
+        );
+    }
+
+    /// Apply `cpuid` to the CPUID leaves exposed to the guest. Must be
+    /// called before `enable_extended_cpu_state` and `initialise`.
+    fn set_cpuid(&mut self, _cpuid: &CpuidConfiguration) -> Result<()> {
+        log_then_return!("Changing the guest's CPUID is not supported by this hypervisor");
+    }
+
+    /// Enable the XSAVE feature set, and the SSE, AVX and AVX-512 state
+    /// components the host supports, so that the guest can use vector
+    /// instructions beyond SSE2. Must be called before `initialise`.
+    fn enable_extended_cpu_state(&mut self) -> Result<()> {
+        log_then_return!("Extended CPU state is not supported by this hypervisor");
+    }
+
+    /// Expose CET shadow stacks to the guest, so that it can enable them
+    /// for itself when it starts. Must be called after `set_cpuid` and
+    /// before `initialise`.
+    fn enable_shadow_stack(&mut self) -> Result<()> {
+        log_then_return!(HyperlightError::ShadowStackNotSupported(
+            "this hypervisor".to_string()
+        ));
+    }
+
+    /// Make the guest fault when it executes `RDRAND` or `RDSEED`, which
+    /// `set_cpuid` hid from it, so that the guest library handles them as
+    /// the sandbox's `EntropyPolicy` says. Must be called after `set_cpuid`
+    /// and before `initialise`.
+    fn trap_entropy_instructions(&mut self) -> Result<()> {
+        log_then_return!(HyperlightError::EntropyInstructionsNotTrapped(
+            "this hypervisor".to_string()
+        ));
+    }
+
+    /// Make the guest's time stamp counter advance as `guest_time` says.
+    /// Must be called before `initialise`.
+    fn set_guest_time(&mut self, _guest_time: &GuestTime) -> Result<()> {
+        log_then_return!(
+            "Guest time stamp counters other than the host's are not supported by this hypervisor"
+        );
+    }
+
+    /// Handle guest accesses to model specific registers as `policy`
+    /// says, with accesses it denies making `run` return
+    /// `HyperlightExit::MsrAccessDenied`. Must be called before
+    /// `initialise`.
+    fn set_msr_policy(&mut self, _policy: &MsrPolicy) -> Result<()> {
+        log_then_return!("MSR policies are not supported by this hypervisor");
+    }
+
+    /// Report guest writes to the MMIO doorbell page from `run` as
+    /// `HyperlightExit::IoOut`, as if the guest had written to the port.
+    /// Must be called before `initialise`.
+    fn enable_mmio_doorbell(&mut self) -> Result<()> {
+        log_then_return!("The MMIO doorbell is not supported by this hypervisor");
+    }
+
     /// get a mutable trait object from self
     fn as_mut_hypervisor(&mut self) -> &mut dyn Hypervisor;
 
-    /// Get the partition handle for WHP
-    #[cfg(target_os = "windows")]
-    fn get_partition_handle(&self) -> windows::Win32::System::Hypervisor::WHV_PARTITION_HANDLE;
+    /// The handle guest function calls are cancelled with, or `None` to
+    /// interrupt the vCPU by sending the thread running it a real-time
+    /// signal, which only works on Linux
+    fn interrupt_handle(&self) -> Option<Arc<dyn VcpuInterrupt>> {
+        None
+    }
 
-    #[cfg(crashdump)]
-    fn get_memory_regions(&self) -> &[MemoryRegion];
+    /// Get the regions of memory mapped into the guest, which crash dumps
+    /// are made of
+    fn get_memory_regions(&self) -> &[MemoryRegion] {
+        &[]
+    }
+
+    /// Map `region` into the guest alongside the regions the hypervisor
+    /// was created with, for memory added to the sandbox after its
+    /// partition was set up
+    ///
+    /// # Safety
+    ///
+    /// The host memory of `region` must stay mapped until the region is
+    /// unmapped with `unmap_region` or the hypervisor is dropped
+    unsafe fn map_region(&mut self, _region: &MemoryRegion) -> Result<()> {
+        log_then_return!("Mapping memory into a created guest is not supported by this hypervisor");
+    }
+
+    /// Unmap `region`, which was mapped into the guest with `map_region`
+    fn unmap_region(&mut self, _region: &MemoryRegion) -> Result<()> {
+        log_then_return!("Unmapping memory from the guest is not supported by this hypervisor");
+    }
 
     #[cfg(gdb)]
     /// handles the cases when the vCPU stops due to a Debug event
@@ -248,6 +373,28 @@ pub(crate) trait Hypervisor: Debug + Sync + Send {
     ) -> Result<()> {
         unimplemented!()
     }
+
+    #[cfg(gdb)]
+    /// Called when the guest has crashed, before the error is returned.
+    /// If the sandbox was configured to wait for a debugger on crash, this
+    /// stops the vCPU in its crashed state until the gdb client continues
+    /// or detaches.
+    fn wait_for_debugger_on_crash(
+        &mut self,
+        _dbg_mem_access_fn: Arc<Mutex<dyn DbgMemAccessHandlerCaller>>,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(gdb)]
+    /// Stop the vCPU in its current state until the gdb client continues
+    /// or detaches, if one is debugging the sandbox
+    fn wait_for_debugger(
+        &mut self,
+        _dbg_mem_access_fn: Arc<Mutex<dyn DbgMemAccessHandlerCaller>>,
+    ) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// A virtual CPU that can be run until an exit occurs
@@ -256,14 +403,23 @@ pub struct VirtualCPU {}
 impl VirtualCPU {
     /// Run the given hypervisor until a halt instruction is reached
     #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
-    pub fn run(
-        hv: &mut dyn Hypervisor,
-        hv_handler: Option<HypervisorHandler>,
-        outb_handle_fn: Arc<Mutex<dyn OutBHandlerCaller>>,
-        mem_access_fn: Arc<Mutex<dyn MemAccessHandlerCaller>>,
-        #[cfg(gdb)] dbg_mem_access_fn: Arc<Mutex<dyn DbgMemAccessHandlerCaller>>,
-    ) -> Result<()> {
+    pub fn run(hv: &mut dyn Hypervisor, ctx: VcpuContext) -> Result<()> {
+        let VcpuContext {
+            outb_handle_fn,
+            mem_access_fn,
+            hv_handler,
+            #[cfg(gdb)]
+            dbg_mem_access_fn,
+        } = ctx;
         loop {
+            if let Some(hvh) = &hv_handler {
+                hvh.wait_while_paused();
+                // every guest is stopped at its epoch deadline, whether or
+                // not it checks the epoch itself
+                if hvh.epoch_handle().deadline_reached() {
+                    log_then_return!(HyperlightError::EpochDeadlineReached());
+                }
+            }
             match hv.run() {
                 #[cfg(gdb)]
                 Ok(HyperlightExit::Debug(stop_reason)) => {
@@ -276,11 +432,31 @@ impl VirtualCPU {
                     break;
                 }
                 Ok(HyperlightExit::IoOut(port, data, rip, instruction_length)) => {
-                    hv.handle_io(port, data, rip, instruction_length, outb_handle_fn.clone())?
+                    let res =
+                        hv.handle_io(port, data, rip, instruction_length, outb_handle_fn.clone());
+                    if let Err(e @ HyperlightError::GuestDoubleFault(_)) = &res {
+                        log::error!("{}", e);
+                        Self::on_cpu_fault(
+                            hv,
+                            &hv_handler,
+                            #[cfg(gdb)]
+                            dbg_mem_access_fn.clone(),
+                        )?;
+                    }
+                    #[cfg(gdb)]
+                    if matches!(
+                        res,
+                        Err(HyperlightError::GuestAborted(..) | HyperlightError::GuestTrapped(_))
+                    ) {
+                        hv.wait_for_debugger_on_crash(dbg_mem_access_fn.clone())?;
+                    }
+                    res?;
                 }
                 Ok(HyperlightExit::Mmio(addr)) => {
                     #[cfg(crashdump)]
                     crashdump::crashdump_to_tempfile(hv)?;
+                    #[cfg(gdb)]
+                    hv.wait_for_debugger_on_crash(dbg_mem_access_fn.clone())?;
 
                     mem_access_fn
                         .clone()
@@ -293,10 +469,15 @@ impl VirtualCPU {
                 Ok(HyperlightExit::AccessViolation(addr, tried, region_permission)) => {
                     #[cfg(crashdump)]
                     crashdump::crashdump_to_tempfile(hv)?;
+                    #[cfg(gdb)]
+                    hv.wait_for_debugger_on_crash(dbg_mem_access_fn.clone())?;
 
                     if region_permission.intersects(MemoryRegionFlags::STACK_GUARD) {
                         return Err(HyperlightError::StackOverflow());
                     }
+                    if region_permission.intersects(MemoryRegionFlags::WRITE_PROTECTED_CODE) {
+                        log_then_return!(HyperlightError::GuestCodeModificationAttempt(addr));
+                    }
                     log_then_return!(HyperlightError::MemoryAccessViolation(
                         addr,
                         tried,
@@ -304,6 +485,14 @@ impl VirtualCPU {
                     ));
                 }
                 Ok(HyperlightExit::Cancelled()) => {
+                    // The vCPU is also interrupted to pause it, in which case
+                    // it parks before it enters the guest again
+                    if hv_handler
+                        .as_ref()
+                        .is_some_and(|hvh| !hvh.pause_handle().is_cancelling())
+                    {
+                        continue;
+                    }
                     // Shutdown is returned when the host has cancelled execution
                     // After termination, the main thread will re-initialize the VM
                     if let Some(hvh) = hv_handler {
@@ -323,6 +512,21 @@ impl VirtualCPU {
                     log_then_return!("Unexpected VM Exit {:?}", reason);
                 }
                 Ok(HyperlightExit::Retry()) => continue,
+                Ok(HyperlightExit::InstructionLimitExceeded(limit)) => {
+                    log_then_return!(HyperlightError::GuestInstructionLimitExceeded(limit));
+                }
+                Ok(HyperlightExit::MsrAccessDenied(msr, access)) => {
+                    log_then_return!(HyperlightError::GuestMsrAccessDenied(msr, access));
+                }
+                Ok(HyperlightExit::TripleFault(context)) => {
+                    Self::on_cpu_fault(
+                        hv,
+                        &hv_handler,
+                        #[cfg(gdb)]
+                        dbg_mem_access_fn.clone(),
+                    )?;
+                    log_then_return!(HyperlightError::GuestTripleFault(context));
+                }
                 Err(e) => {
                     #[cfg(crashdump)]
                     crashdump::crashdump_to_tempfile(hv)?;
@@ -334,6 +538,37 @@ impl VirtualCPU {
 
         Ok(())
     }
+
+    /// Do what the sandbox is configured to do when the guest double or
+    /// triple faults, before the fault is returned as an error
+    fn on_cpu_fault(
+        hv: &mut dyn Hypervisor,
+        hv_handler: &Option<HypervisorHandler>,
+        #[cfg(gdb)] dbg_mem_access_fn: Arc<Mutex<dyn DbgMemAccessHandlerCaller>>,
+    ) -> Result<()> {
+        // the vCPU's registers show what the guest was doing when it
+        // faulted
+        log::debug!("{:?}", hv);
+        let action = hv_handler
+            .as_ref()
+            .map(|hvh| hvh.cpu_fault_action())
+            .unwrap_or_default();
+        match action {
+            CpuFaultAction::Teardown => {
+                #[cfg(gdb)]
+                hv.wait_for_debugger_on_crash(dbg_mem_access_fn)?;
+            }
+            #[cfg(crashdump)]
+            CpuFaultAction::CoreDump => {
+                crashdump::crashdump_to_tempfile(hv)?;
+                #[cfg(gdb)]
+                hv.wait_for_debugger_on_crash(dbg_mem_access_fn)?;
+            }
+            #[cfg(gdb)]
+            CpuFaultAction::WaitForDebugger => hv.wait_for_debugger(dbg_mem_access_fn)?,
+        }
+        Ok(())
+    }
 }
 
 #[cfg(all(test, any(target_os = "windows", kvm)))]
@@ -342,8 +577,10 @@ pub(crate) mod tests {
     use std::sync::{Arc, Mutex};
     use std::time::Duration;
 
+    use hyperlight_common::transport::HostCallTransport;
     use hyperlight_testing::dummy_guest_as_string;
 
+    use super::backend::HypervisorBackend;
     #[cfg(gdb)]
     use super::handlers::DbgMemAccessHandlerWrapper;
     use super::handlers::{MemAccessHandlerWrapper, OutBHandlerWrapper};
@@ -351,6 +588,16 @@ pub(crate) mod tests {
         HvHandlerConfig, HypervisorHandler, HypervisorHandlerAction,
     };
     use crate::mem::ptr::RawPtr;
+    use crate::sandbox::cpu_fault::CpuFaultAction;
+    use crate::sandbox::cpu_time::CpuTimeCounter;
+    use crate::sandbox::cpuid::CpuidConfiguration;
+    use crate::sandbox::deadline::CallDeadline;
+    use crate::sandbox::epoch::EpochHandle;
+    use crate::sandbox::guest_time::GuestTime;
+    use crate::sandbox::heartbeat::{Heartbeat, HostCallTracker};
+    use crate::sandbox::interrupt::{InterruptFailureCallback, InterruptPolicy};
+    use crate::sandbox::msr::MsrPolicy;
+    use crate::sandbox::pause::PauseHandle;
     use crate::sandbox::uninitialized::GuestBinary;
     use crate::sandbox::{SandboxConfiguration, UninitializedSandbox};
     use crate::{new_error, Result};
@@ -392,6 +639,26 @@ pub(crate) mod tests {
                 SandboxConfiguration::DEFAULT_MAX_WAIT_FOR_CANCELLATION as u64,
             ),
             max_guest_log_level: None,
+            max_guest_instructions: 0,
+            extended_cpu_state: false,
+            shadow_stack: false,
+            cpuid: CpuidConfiguration::default(),
+            trap_entropy_instructions: false,
+            guest_time: GuestTime::default(),
+            msr_policy: MsrPolicy::default(),
+            interrupt_policy: InterruptPolicy::default(),
+            cpu_fault_action: CpuFaultAction::default(),
+            interrupt_failure: InterruptFailureCallback::default(),
+            call_deadline: CallDeadline::default(),
+            host_call_transport: HostCallTransport::default(),
+            heartbeat: Heartbeat::default(),
+            heartbeat_timeout: None,
+            host_calls: HostCallTracker::default(),
+            max_time_between_host_calls: None,
+            pause: PauseHandle::default(),
+            epoch: EpochHandle::default(),
+            cpu_time: CpuTimeCounter::default(),
+            hypervisor_backend: HypervisorBackend::default(),
         };
 
         let mut hv_handler = HypervisorHandler::new(hv_handler_config);
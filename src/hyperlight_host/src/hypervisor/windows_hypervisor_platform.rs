@@ -113,6 +113,7 @@ impl VMPartition {
                     MemoryRegionFlags::WRITE => Ok(WHvMapGpaRangeFlagWrite),
                     MemoryRegionFlags::EXECUTE => Ok(WHvMapGpaRangeFlagExecute),
                     MemoryRegionFlags::STACK_GUARD => Ok(WHvMapGpaRangeFlagNone),
+                    MemoryRegionFlags::WRITE_PROTECTED_CODE => Ok(WHvMapGpaRangeFlagNone),
                     _ => Err(new_error!("Invalid Memory Region Flag")),
                 })
                 .collect::<Result<Vec<WHV_MAP_GPA_RANGE_FLAGS>>>()?
@@ -505,6 +506,46 @@ impl VMProcessor {
         Ok(())
     }
 
+    /// Get the vCPU's XSAVE area, in the compacted format
+    pub(super) fn get_xsave(&self) -> Result<Vec<u8>> {
+        let mut size = 0;
+        // a call without a buffer fails, but tells the size of the area
+        let _ = unsafe {
+            WHvGetVirtualProcessorXsaveState(
+                self.get_partition_hdl(),
+                0,
+                std::ptr::null_mut(),
+                0,
+                &mut size,
+            )
+        };
+        let mut xsave = vec![0; size as usize];
+        unsafe {
+            WHvGetVirtualProcessorXsaveState(
+                self.get_partition_hdl(),
+                0,
+                xsave.as_mut_ptr() as *mut c_void,
+                size,
+                &mut size,
+            )?;
+        }
+        xsave.truncate(size as usize);
+        Ok(xsave)
+    }
+
+    /// Set the vCPU's XSAVE area, as returned by `get_xsave`
+    pub(super) fn set_xsave(&mut self, xsave: &[u8]) -> Result<()> {
+        unsafe {
+            WHvSetVirtualProcessorXsaveState(
+                self.get_partition_hdl(),
+                0,
+                xsave.as_ptr() as *const c_void,
+                xsave.len() as u32,
+            )?;
+        }
+        Ok(())
+    }
+
     #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
     pub(super) fn run(&mut self) -> Result<WHV_RUN_VP_EXIT_CONTEXT> {
         let partition_handle = self.get_partition_hdl();
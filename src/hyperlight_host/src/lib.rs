@@ -35,6 +35,9 @@ pub(crate) mod built_info {
 pub mod error;
 /// Wrappers for host and guest functions.
 pub mod func;
+/// Helpers shared by the benchmarks in `benches/` and by applications
+/// that want to measure Hyperlight on their own hardware
+pub mod hyperlight_bench;
 /// Wrappers for hypervisor implementations
 pub mod hypervisor;
 /// Functionality to establish and manage an individual sandbox's
@@ -59,8 +62,8 @@ pub mod hypervisor;
 ///
 /// 0x200000    PML4
 /// 0x201000    PDPT
-/// 0x202000    PD
-/// 0x203000    The guest PE code (When the code has been loaded using LoadLibrary to debug the guest this will not be
+/// 0x202000    PDs, followed by the PTs
+/// After PTs   The guest PE code (When the code has been loaded using LoadLibrary to debug the guest this will not be
 /// present and code length will be zero;
 ///
 /// The pointer passed to the Entrypoint in the Guest application is the 0x200000 + size of page table + size of code,
@@ -87,6 +90,8 @@ pub(crate) mod testing;
 
 /// The re-export for the `HyperlightError` type
 pub use error::HyperlightError;
+/// The re-export for the metrics_snapshot function
+pub use metrics::metrics_snapshot;
 /// The re-export for the set_registry function
 pub use metrics::set_metrics_registry;
 /// The re-export for the `is_hypervisor_present` type
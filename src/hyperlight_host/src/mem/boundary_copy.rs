@@ -0,0 +1,237 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Copies between host memory and the memory of a guest that may be
+//! running, which `HostSharedMemory` makes function call parameters and
+//! results with.
+//!
+//! The guest can change its memory while the host copies it, so the
+//! guest's side of each copy is made with volatile accesses, which the
+//! compiler can't leave out, merge, or assume the values of. They are made
+//! a vector at a time rather than a byte at a time: 16 byte SSE2 loads and
+//! stores on x86_64, and 8 byte words elsewhere, aligned on the guest's
+//! side. Only the unaligned bytes at either end are copied one at a time,
+//! so copies that start and end on page boundaries have none.
+//!
+//! Copies into the guest of at least `NON_TEMPORAL_THRESHOLD` bytes are
+//! made with non-temporal stores, which bypass the host's caches, so that
+//! a multi-megabyte payload that only the guest reads doesn't evict the
+//! host's working set.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{
+    __m128i, _mm_loadu_si128, _mm_set1_epi8, _mm_sfence, _mm_storeu_si128, _mm_stream_si128,
+};
+
+#[cfg(target_arch = "x86_64")]
+type Vector = __m128i;
+#[cfg(not(target_arch = "x86_64"))]
+type Vector = u64;
+
+const VECTOR_SIZE: usize = size_of::<Vector>();
+
+/// The smallest copy into the guest made with non-temporal stores
+pub(crate) const NON_TEMPORAL_THRESHOLD: usize = 0x100000;
+
+#[cfg(target_arch = "x86_64")]
+mod vector {
+    use super::*;
+
+    pub(super) unsafe fn load_host(src: *const u8) -> Vector {
+        _mm_loadu_si128(src as *const Vector)
+    }
+
+    pub(super) unsafe fn store_host(dst: *mut u8, value: Vector) {
+        _mm_storeu_si128(dst as *mut Vector, value)
+    }
+
+    pub(super) unsafe fn store_guest(dst: *mut Vector, value: Vector, non_temporal: bool) {
+        if non_temporal {
+            _mm_stream_si128(dst, value)
+        } else {
+            dst.write_volatile(value)
+        }
+    }
+
+    pub(super) unsafe fn splat(value: u8) -> Vector {
+        _mm_set1_epi8(value as i8)
+    }
+
+    /// Order the non-temporal stores before the stores that follow them,
+    /// such as the one that tells the guest the data is there
+    pub(super) unsafe fn fence() {
+        _mm_sfence()
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+mod vector {
+    use super::*;
+
+    pub(super) unsafe fn load_host(src: *const u8) -> Vector {
+        (src as *const Vector).read_unaligned()
+    }
+
+    pub(super) unsafe fn store_host(dst: *mut u8, value: Vector) {
+        (dst as *mut Vector).write_unaligned(value)
+    }
+
+    pub(super) unsafe fn store_guest(dst: *mut Vector, value: Vector, _non_temporal: bool) {
+        dst.write_volatile(value)
+    }
+
+    pub(super) unsafe fn splat(value: u8) -> Vector {
+        Vector::from_ne_bytes([value; VECTOR_SIZE])
+    }
+
+    pub(super) unsafe fn fence() {}
+}
+
+/// How many of the `len` bytes from `address` come before the first
+/// vector-aligned address
+fn head_len(address: usize, len: usize) -> usize {
+    (address.wrapping_neg() % VECTOR_SIZE).min(len)
+}
+
+/// Copy `src` into the guest memory at `dst`
+///
+/// # Safety
+///
+/// `dst` must be valid for writes of `src.len()` bytes, which must not
+/// overlap `src`
+pub(crate) unsafe fn copy_to_guest(dst: *mut u8, src: &[u8]) {
+    copy_to_guest_with(dst, src, src.len() >= NON_TEMPORAL_THRESHOLD)
+}
+
+unsafe fn copy_to_guest_with(dst: *mut u8, src: &[u8], non_temporal: bool) {
+    let head = head_len(dst as usize, src.len());
+    let body_end = head + (src.len() - head) / VECTOR_SIZE * VECTOR_SIZE;
+    for (i, b) in src[..head].iter().enumerate() {
+        dst.add(i).write_volatile(*b);
+    }
+    for offset in (head..body_end).step_by(VECTOR_SIZE) {
+        let value = vector::load_host(src.as_ptr().add(offset));
+        vector::store_guest(dst.add(offset) as *mut Vector, value, non_temporal);
+    }
+    if non_temporal {
+        vector::fence();
+    }
+    for (i, b) in src.iter().enumerate().skip(body_end) {
+        dst.add(i).write_volatile(*b);
+    }
+}
+
+/// Copy the guest memory at `src` into `dst`
+///
+/// # Safety
+///
+/// `src` must be valid for reads of `dst.len()` bytes, which must not
+/// overlap `dst`
+pub(crate) unsafe fn copy_from_guest(dst: &mut [u8], src: *const u8) {
+    let head = head_len(src as usize, dst.len());
+    let body_end = head + (dst.len() - head) / VECTOR_SIZE * VECTOR_SIZE;
+    for (i, b) in dst[..head].iter_mut().enumerate() {
+        *b = src.add(i).read_volatile();
+    }
+    for offset in (head..body_end).step_by(VECTOR_SIZE) {
+        let value = (src.add(offset) as *const Vector).read_volatile();
+        vector::store_host(dst.as_mut_ptr().add(offset), value);
+    }
+    for (i, b) in dst.iter_mut().enumerate().skip(body_end) {
+        *b = src.add(i).read_volatile();
+    }
+}
+
+/// Set the `len` bytes of guest memory at `dst` to `value`
+///
+/// # Safety
+///
+/// `dst` must be valid for writes of `len` bytes
+pub(crate) unsafe fn fill_guest(dst: *mut u8, value: u8, len: usize) {
+    let non_temporal = len >= NON_TEMPORAL_THRESHOLD;
+    let head = head_len(dst as usize, len);
+    let body_end = head + (len - head) / VECTOR_SIZE * VECTOR_SIZE;
+    for i in 0..head {
+        dst.add(i).write_volatile(value);
+    }
+    let splat = vector::splat(value);
+    for offset in (head..body_end).step_by(VECTOR_SIZE) {
+        vector::store_guest(dst.add(offset) as *mut Vector, splat, non_temporal);
+    }
+    if non_temporal {
+        vector::fence();
+    }
+    for i in body_end..len {
+        dst.add(i).write_volatile(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{copy_from_guest, copy_to_guest_with, fill_guest, VECTOR_SIZE};
+
+    /// Every combination of misalignment at either end, up to a few vectors
+    fn ranges() -> impl Iterator<Item = (usize, usize)> {
+        (0..VECTOR_SIZE).flat_map(|start| (0..VECTOR_SIZE * 4).map(move |len| (start, len)))
+    }
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 + 1) as u8).collect()
+    }
+
+    #[test]
+    fn copies_to_the_guest() {
+        for non_temporal in [false, true] {
+            for (start, len) in ranges() {
+                let src = pattern(len);
+                let mut guest = vec![0u8; VECTOR_SIZE * 6];
+                unsafe { copy_to_guest_with(guest.as_mut_ptr().add(start), &src, non_temporal) };
+                assert_eq!(src, guest[start..start + len], "{} {}", start, len);
+                assert!(guest[..start].iter().all(|b| *b == 0));
+                assert!(guest[start + len..].iter().all(|b| *b == 0));
+            }
+        }
+    }
+
+    #[test]
+    fn copies_from_the_guest() {
+        let guest = pattern(VECTOR_SIZE * 6);
+        for (start, len) in ranges() {
+            // the host's side is misaligned as well
+            let mut dst = vec![0u8; len + 1];
+            unsafe { copy_from_guest(&mut dst[1..], guest.as_ptr().add(start)) };
+            assert_eq!(guest[start..start + len], dst[1..], "{} {}", start, len);
+        }
+    }
+
+    #[test]
+    fn fills_the_guest() {
+        for (start, len) in ranges() {
+            let mut guest = vec![0u8; VECTOR_SIZE * 6];
+            unsafe { fill_guest(guest.as_mut_ptr().add(start), 0xAB, len) };
+            assert!(guest[start..start + len].iter().all(|b| *b == 0xAB));
+            assert!(guest[..start].iter().all(|b| *b == 0));
+            assert!(guest[start + len..].iter().all(|b| *b == 0));
+        }
+
+        // large enough to use non-temporal stores
+        let len = super::NON_TEMPORAL_THRESHOLD + 3;
+        let mut guest = vec![0u8; len + 1];
+        unsafe { fill_guest(guest.as_mut_ptr().add(1), 0xCD, len) };
+        assert_eq!(0, guest[0]);
+        assert!(guest[1..].iter().all(|b| *b == 0xCD));
+    }
+}
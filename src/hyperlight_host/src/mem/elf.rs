@@ -14,20 +14,28 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+#[cfg(target_arch = "aarch64")]
+use goblin::elf::header::EM_AARCH64;
+#[cfg(target_arch = "x86_64")]
+use goblin::elf::header::EM_X86_64;
 #[cfg(target_arch = "aarch64")]
 use goblin::elf::reloc::{R_AARCH64_NONE, R_AARCH64_RELATIVE};
 #[cfg(target_arch = "x86_64")]
 use goblin::elf::reloc::{R_X86_64_NONE, R_X86_64_RELATIVE};
 use goblin::elf::{Elf, ProgramHeaders, Reloc};
-use goblin::elf64::program_header::PT_LOAD;
+use goblin::elf64::program_header::{PF_W, PF_X, PT_LOAD};
+use hyperlight_common::guest_metadata::{GuestMetadata, METADATA_SECTION};
 
 use crate::{log_then_return, new_error, Result};
 
+#[derive(Clone)]
 pub(crate) struct ElfInfo {
     payload: Vec<u8>,
+    machine: u16,
     phdrs: ProgramHeaders,
     entry: u64,
     relocs: Vec<Reloc>,
+    metadata: GuestMetadata,
 }
 
 impl ElfInfo {
@@ -41,13 +49,33 @@ impl ElfInfo {
         {
             log_then_return!("ELF must have at least one PT_LOAD header");
         }
+        let metadata = match elf
+            .section_headers
+            .iter()
+            .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(METADATA_SECTION))
+        {
+            Some(sh) => {
+                let start = sh.sh_offset as usize;
+                let section = bytes
+                    .get(start..start.saturating_add(sh.sh_size as usize))
+                    .ok_or_else(|| new_error!("the {} section is truncated", METADATA_SECTION))?;
+                GuestMetadata::from_bytes(section)?
+            }
+            None => GuestMetadata::default(),
+        };
         Ok(ElfInfo {
             payload: bytes.to_vec(),
+            machine: elf.header.e_machine,
             phdrs: elf.program_headers,
             entry: elf.entry,
             relocs,
+            metadata,
         })
     }
+    /// The metadata embedded in the binary, which is empty if it has none
+    pub(crate) fn metadata(&self) -> &GuestMetadata {
+        &self.metadata
+    }
     pub(crate) fn entrypoint_va(&self) -> u64 {
         self.entry
     }
@@ -70,6 +98,119 @@ impl ElfInfo {
             .unwrap();
         (max_phdr.p_vaddr + max_phdr.p_memsz - self.get_base_va()) as usize
     }
+    /// The file contents of each `PT_LOAD` segment, in order
+    pub(crate) fn loaded_segments(&self) -> Vec<&[u8]> {
+        self.phdrs
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD)
+            .filter_map(|phdr| {
+                let start = phdr.p_offset as usize;
+                self.payload
+                    .get(start..start.checked_add(phdr.p_filesz as usize)?)
+            })
+            .collect()
+    }
+    /// The start and end offsets, from the start of the loaded binary, of
+    /// the whole pages of the first executable and non-writable `PT_LOAD`
+    /// segment, or `None` if it doesn't span a whole page
+    pub(crate) fn code_only_pages(&self, page_size: usize) -> Option<(usize, usize)> {
+        let base_va = self.get_base_va();
+        let phdr = self.phdrs.iter().find(|phdr| {
+            phdr.p_type == PT_LOAD && phdr.p_flags & PF_X != 0 && phdr.p_flags & PF_W == 0
+        })?;
+        let start = usize::try_from(phdr.p_vaddr - base_va).ok()?;
+        let end = start.checked_add(usize::try_from(phdr.p_memsz).ok()?)?;
+        let start = start.checked_next_multiple_of(page_size)?;
+        let end = end - end % page_size;
+        (start < end).then_some((start, end))
+    }
+    /// The start and end offsets, from the start of the loaded binary, of
+    /// the pages that hold any of its executable `PT_LOAD` segments, or
+    /// `None` if it has none
+    pub(crate) fn executable_pages(&self, page_size: usize) -> Option<(usize, usize)> {
+        let base_va = self.get_base_va();
+        let (start, end) = self
+            .phdrs
+            .iter()
+            .filter(|phdr| phdr.p_type == PT_LOAD && phdr.p_flags & PF_X != 0)
+            .map(|phdr| {
+                let start = phdr.p_vaddr - base_va;
+                (start, start + phdr.p_memsz)
+            })
+            .reduce(|(start, end), (s, e)| (start.min(s), end.max(e)))?;
+        let start = usize::try_from(start).ok()?;
+        let end = usize::try_from(end).ok()?;
+        Some((
+            start - start % page_size,
+            end.checked_next_multiple_of(page_size)?,
+        ))
+    }
+    /// Check that the binary can be loaded: that it is for this
+    /// architecture, that its `PT_LOAD` segments are within the file,
+    /// that its entry point is in an executable segment and that its
+    /// relocations are supported and within the loaded binary
+    pub(crate) fn check(&self) -> Result<()> {
+        #[cfg(target_arch = "x86_64")]
+        let expected_machine = EM_X86_64;
+        #[cfg(target_arch = "aarch64")]
+        let expected_machine = EM_AARCH64;
+        if self.machine != expected_machine {
+            log_then_return!("ELF machine {} is not {}", self.machine, expected_machine);
+        }
+        let base_va = self.get_base_va();
+        for phdr in self.phdrs.iter().filter(|phdr| phdr.p_type == PT_LOAD) {
+            if phdr.p_vaddr < base_va {
+                log_then_return!(
+                    "PT_LOAD segment at {:#x} is before the first one at {:#x}",
+                    phdr.p_vaddr,
+                    base_va
+                );
+            }
+            if phdr.p_filesz > phdr.p_memsz {
+                log_then_return!(
+                    "PT_LOAD segment at {:#x} has more file contents than memory",
+                    phdr.p_vaddr
+                );
+            }
+            if phdr
+                .p_offset
+                .checked_add(phdr.p_filesz)
+                .map_or(true, |end| end > self.payload.len() as u64)
+            {
+                log_then_return!(
+                    "PT_LOAD segment at {:#x} is past the end of the file",
+                    phdr.p_vaddr
+                );
+            }
+        }
+        if !self.phdrs.iter().any(|phdr| {
+            phdr.p_type == PT_LOAD
+                && phdr.p_flags & PF_X != 0
+                && (phdr.p_vaddr..phdr.p_vaddr + phdr.p_memsz).contains(&self.entry)
+        }) {
+            log_then_return!(
+                "the entry point {:#x} is not in an executable PT_LOAD segment",
+                self.entry
+            );
+        }
+        let va_size = self.get_va_size() as u64;
+        for r in self.relocs.iter() {
+            #[cfg(target_arch = "aarch64")]
+            let supported = matches!(r.r_type, R_AARCH64_RELATIVE | R_AARCH64_NONE);
+            #[cfg(target_arch = "x86_64")]
+            let supported = matches!(r.r_type, R_X86_64_RELATIVE | R_X86_64_NONE);
+            if !supported {
+                log_then_return!("unsupported relocation {}", r.r_type);
+            }
+            if r.r_offset.checked_add(8).map_or(true, |end| end > va_size) {
+                log_then_return!(
+                    "relocation at {:#x} is outside the loaded binary",
+                    r.r_offset
+                );
+            }
+        }
+        Ok(())
+    }
     pub(crate) fn load_at(&self, load_addr: usize, target: &mut [u8]) -> Result<()> {
         let base_va = self.get_base_va();
         for phdr in self.phdrs.iter().filter(|phdr| phdr.p_type == PT_LOAD) {
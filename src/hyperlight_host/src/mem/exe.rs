@@ -18,7 +18,10 @@ use std::fs::File;
 use std::io::Read;
 use std::vec::Vec;
 
+use hyperlight_common::guest_metadata::GuestMetadata;
+
 use super::elf::ElfInfo;
+use super::exe_cache;
 use super::pe::headers::PEHeaders;
 use super::pe::pe_info::PEInfo;
 use super::ptr_offset::Offset;
@@ -28,6 +31,7 @@ use crate::Result;
 // files _really_ doesn't matter, and probably isn't really worth the
 // cost of an indirection.
 #[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
 pub enum ExeInfo {
     PE(PEInfo),
     Elf(ElfInfo),
@@ -36,7 +40,8 @@ pub enum ExeInfo {
 // There isn't a commonly-used standard convention for heap and stack
 // limits to be included in ELF files as they are in
 // PEs. Consequently, we use these static defaults as the default
-// limits, unless overwritten when setting up the sandbox.
+// limits, unless the guest declares its own in its metadata or they are
+// overwritten when setting up the sandbox.
 const DEFAULT_ELF_STACK_RESERVE: u64 = 65536;
 const DEFAULT_ELF_HEAP_RESERVE: u64 = 131072;
 
@@ -47,18 +52,44 @@ impl ExeInfo {
         file.read_to_end(&mut contents)?;
         Self::from_buf(&contents)
     }
+    /// Parse the guest binary in `buf`, reusing the result of an earlier
+    /// parse of a binary with the same contents if there is one
     pub fn from_buf(buf: &[u8]) -> Result<Self> {
+        exe_cache::get_or_parse(buf, Self::parse)
+    }
+    pub(super) fn parse(buf: &[u8]) -> Result<Self> {
         PEInfo::new(buf)
             .map(ExeInfo::PE)
             .or_else(|_| ElfInfo::new(buf).map(ExeInfo::Elf))
     }
+    /// The contents of each section of the binary that is loaded into
+    /// guest memory, before relocation
+    pub(super) fn loaded_sections(&self) -> Vec<&[u8]> {
+        match self {
+            ExeInfo::PE(pe) => vec![pe.payload.as_slice()],
+            ExeInfo::Elf(elf) => elf.loaded_segments(),
+        }
+    }
+    /// The metadata the guest build embedded in the binary
+    pub fn metadata(&self) -> &GuestMetadata {
+        match self {
+            ExeInfo::PE(pe) => pe.metadata(),
+            ExeInfo::Elf(elf) => elf.metadata(),
+        }
+    }
     pub fn stack_reserve(&self) -> u64 {
+        if let Some(size) = self.metadata().stack_size() {
+            return size;
+        }
         match self {
             ExeInfo::PE(pe) => pe.stack_reserve(),
             ExeInfo::Elf(_) => DEFAULT_ELF_STACK_RESERVE,
         }
     }
     pub fn heap_reserve(&self) -> u64 {
+        if let Some(size) = self.metadata().heap_size() {
+            return size;
+        }
         match self {
             ExeInfo::PE(pe) => pe.heap_reserve(),
             ExeInfo::Elf(_) => DEFAULT_ELF_HEAP_RESERVE,
@@ -76,6 +107,34 @@ impl ExeInfo {
             ExeInfo::Elf(elf) => elf.get_va_size(),
         }
     }
+    /// The start and end offsets, from the start of the loaded binary, of
+    /// the pages that hold only code and can be write protected. PE
+    /// binaries are loaded as they are laid out in the file, so none of
+    /// their pages are.
+    pub(super) fn code_only_pages(&self, page_size: usize) -> Option<(usize, usize)> {
+        match self {
+            ExeInfo::PE(_) => None,
+            ExeInfo::Elf(elf) => elf.code_only_pages(page_size),
+        }
+    }
+    /// The start and end offsets, from the start of the loaded binary, of
+    /// the pages that hold its code, which are the only ones the guest can
+    /// run. PE binaries are loaded as they are laid out in the file, so all
+    /// of their pages are.
+    pub(super) fn executable_pages(&self, page_size: usize) -> Option<(usize, usize)> {
+        match self {
+            ExeInfo::PE(_) => None,
+            ExeInfo::Elf(elf) => elf.executable_pages(page_size),
+        }
+    }
+    /// Check that the binary can be loaded and relocated, which parsing
+    /// it doesn't
+    pub(crate) fn check(&self) -> Result<()> {
+        match self {
+            ExeInfo::PE(pe) => pe.check_relocations(),
+            ExeInfo::Elf(elf) => elf.check(),
+        }
+    }
     // todo: this doesn't morally need to be &mut self, since we're
     // copying into target, but the PE loader chooses to apply
     // relocations in its owned representation of the PE contents,
@@ -0,0 +1,149 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use tracing::{instrument, Span};
+
+use super::exe::ExeInfo;
+use crate::Result;
+
+/// The maximum number of parsed guest binaries kept in the cache. When
+/// the cache is full the least recently used binary is evicted.
+const MAX_CACHED_GUEST_BINARIES: usize = 16;
+
+/// A guest binary that has already been parsed and validated
+struct CachedExe {
+    /// The SHA-256 hash of the contents of the guest binary
+    file_hash: String,
+    /// The parsed layout and relocation plan of the guest binary. This is
+    /// never loaded itself, only cloned for each new sandbox.
+    exe_info: ExeInfo,
+    /// The SHA-256 hash of each section of the guest binary that is
+    /// loaded into guest memory, in load order
+    section_hashes: Vec<String>,
+}
+
+/// Parsed guest binaries, least recently used first
+static EXE_CACHE: Mutex<VecDeque<CachedExe>> = Mutex::new(VecDeque::new());
+
+/// Return the `ExeInfo` for the guest binary in `buf`, calling `parse`
+/// only if a binary with the same contents has not been parsed before.
+///
+/// Creating many sandboxes from the same guest binary then only costs a
+/// hash of the binary and a copy of the parsed image, rather than parsing
+/// and validating the binary every time.
+#[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+pub(super) fn get_or_parse<F>(buf: &[u8], parse: F) -> Result<ExeInfo>
+where
+    F: FnOnce(&[u8]) -> Result<ExeInfo>,
+{
+    let file_hash = sha256::digest(buf);
+
+    {
+        let mut cache = lock_cache();
+        if let Some(index) = cache.iter().position(|c| c.file_hash == file_hash) {
+            #[allow(clippy::unwrap_used)] // index was just found
+            let cached = cache.remove(index).unwrap();
+            crate::debug!(
+                "Reusing parsed guest binary {} with section hashes {:?}",
+                cached.file_hash,
+                cached.section_hashes
+            );
+            let exe_info = cached.exe_info.clone();
+            cache.push_back(cached);
+            return Ok(exe_info);
+        }
+    }
+
+    // parse outside of the lock so that other binaries can be looked up
+    // in the meantime
+    let exe_info = parse(buf)?;
+    let section_hashes: Vec<String> = exe_info
+        .loaded_sections()
+        .into_iter()
+        .map(sha256::digest)
+        .collect();
+    crate::debug!("Parsed guest binary {}", file_hash);
+
+    let mut cache = lock_cache();
+    // another thread may have parsed the same binary in the meantime
+    if !cache.iter().any(|c| c.file_hash == file_hash) {
+        if cache.len() == MAX_CACHED_GUEST_BINARIES {
+            cache.pop_front();
+        }
+        cache.push_back(CachedExe {
+            file_hash,
+            exe_info: exe_info.clone(),
+            section_hashes,
+        });
+    }
+
+    Ok(exe_info)
+}
+
+fn lock_cache() -> std::sync::MutexGuard<'static, VecDeque<CachedExe>> {
+    // A poisoned lock only means another thread panicked while holding it,
+    // every entry in the cache is still complete
+    EXE_CACHE
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use hyperlight_testing::rust_guest_as_pathbuf;
+
+    use super::{get_or_parse, lock_cache};
+    use crate::mem::exe::ExeInfo;
+    use crate::testing::bytes_for_path;
+
+    #[test]
+    fn parses_each_binary_once() {
+        let mut bytes = bytes_for_path(rust_guest_as_pathbuf("simpleguest")).unwrap();
+        // trailing data is ignored by the ELF parser, but makes the contents
+        // (and so the cache key) unique to this test
+        bytes.extend_from_slice(uuid::Uuid::new_v4().as_bytes());
+
+        let parses = Cell::new(0);
+        let parse = |buf: &[u8]| {
+            parses.set(parses.get() + 1);
+            ExeInfo::parse(buf)
+        };
+
+        let first = get_or_parse(&bytes, parse).unwrap();
+        let second = get_or_parse(&bytes, parse).unwrap();
+        assert_eq!(1, parses.get());
+        assert_eq!(first.loaded_size(), second.loaded_size());
+        assert_eq!(first.entrypoint(), second.entrypoint());
+
+        let file_hash = sha256::digest(bytes.as_slice());
+        let cache = lock_cache();
+        let cached = cache.iter().find(|c| c.file_hash == file_hash).unwrap();
+        assert!(!cached.section_hashes.is_empty());
+    }
+
+    #[test]
+    fn parse_errors_are_not_cached() {
+        let bytes = uuid::Uuid::new_v4().as_bytes().to_vec();
+        assert!(get_or_parse(&bytes, ExeInfo::parse).is_err());
+        let file_hash = sha256::digest(bytes.as_slice());
+        assert!(!lock_cache().iter().any(|c| c.file_hash == file_hash));
+    }
+}
@@ -0,0 +1,273 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use tracing::{instrument, Span};
+
+use super::shared_mem::SharedMemory;
+use super::shared_mem_snapshot::SharedMemorySnapshot;
+use super::snapshot_encryption::{DecryptingReader, EncryptingWriter, SnapshotKey};
+use crate::sandbox::config::MemoryPopulation;
+use crate::{new_error, Result};
+
+/// Authenticated along with every chunk of an encrypted hibernation file,
+/// so it can't be passed off as any other kind of encrypted file
+const HIBERNATION_AAD: &[u8] = b"hyperlight hibernated memory";
+
+/// The guest memory and memory snapshots of a hibernated sandbox,
+/// compressed into a file that is deleted when this is dropped.
+///
+/// The file holds a single zstd stream of the guest memory, unless it is
+/// identical to the last snapshot, followed by every snapshot, oldest
+/// first. It is only ever read back by the process that wrote it, so the
+/// sizes needed to read it are kept here rather than in the file. If it was
+/// written with a key, the stream is encrypted with it, see
+/// `snapshot_encryption`.
+#[derive(Debug)]
+pub(crate) struct HibernatedMemory {
+    path: PathBuf,
+    mem_size: usize,
+    snapshot_count: usize,
+    encrypted: bool,
+    /// Whether the guest memory was identical to the last snapshot, as it
+    /// is after every `call_guest_function_by_name`, and so was not written
+    memory_is_last_snapshot: bool,
+    memory_population: MemoryPopulation,
+}
+
+impl HibernatedMemory {
+    /// Compress the memory in `shared_mem` and in `snapshots` into a new
+    /// file in `dir`, encrypted with `key` if there is one
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(super) fn write<S: SharedMemory>(
+        shared_mem: &mut S,
+        snapshots: &[SharedMemorySnapshot],
+        dir: &Path,
+        key: Option<&SnapshotKey>,
+    ) -> Result<Self> {
+        let path = dir.join(format!(
+            "hyperlight-sandbox-{:016x}.hibernated",
+            rand::random::<u64>()
+        ));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        // from here on, dropping `hibernated` deletes the file, so it isn't
+        // left behind if writing it fails
+        let mut hibernated = Self {
+            path,
+            mem_size: shared_mem.mem_size(),
+            snapshot_count: snapshots.len(),
+            encrypted: key.is_some(),
+            memory_is_last_snapshot: false,
+            memory_population: shared_mem.memory_population(),
+        };
+
+        let out = BufWriter::new(file);
+        match key {
+            None => {
+                let encoder = zstd::Encoder::new(out, 0)?;
+                hibernated
+                    .write_memory(shared_mem, snapshots, encoder)?
+                    .flush()?
+            }
+            Some(key) => {
+                let out = EncryptingWriter::new(key, HIBERNATION_AAD, out)?;
+                let encoder = zstd::Encoder::new(out, 0)?;
+                hibernated
+                    .write_memory(shared_mem, snapshots, encoder)?
+                    .finish()?
+                    .flush()?
+            }
+        }
+        Ok(hibernated)
+    }
+
+    /// Write the guest memory, unless it is identical to the last
+    /// snapshot, and `snapshots` to `encoder`, and return its output
+    fn write_memory<S: SharedMemory, W: Write>(
+        &mut self,
+        shared_mem: &mut S,
+        snapshots: &[SharedMemorySnapshot],
+        mut encoder: zstd::Encoder<'static, W>,
+    ) -> Result<W> {
+        self.memory_is_last_snapshot = shared_mem.with_exclusivity(|e| -> Result<bool> {
+            let memory = e.as_slice();
+            let memory_is_last_snapshot = snapshots.last().is_some_and(|s| s.as_slice() == memory);
+            if !memory_is_last_snapshot {
+                encoder.write_all(memory)?;
+            }
+            Ok(memory_is_last_snapshot)
+        })??;
+        for snapshot in snapshots {
+            encoder.write_all(snapshot.as_slice())?;
+        }
+        Ok(encoder.finish()?)
+    }
+
+    /// Whether the file was encrypted, and so needs a key to be read
+    pub(crate) fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    /// Decompress the guest memory back into `shared_mem`, decrypting it
+    /// with `key` if it was written with one, and return the snapshots,
+    /// oldest first
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(super) fn read<S: SharedMemory>(
+        &self,
+        shared_mem: &mut S,
+        key: Option<&SnapshotKey>,
+    ) -> Result<Vec<SharedMemorySnapshot>> {
+        if shared_mem.mem_size() != self.mem_size {
+            return Err(new_error!(
+                "Hibernated memory is {} bytes, but the shared memory is {} bytes",
+                self.mem_size,
+                shared_mem.mem_size()
+            ));
+        }
+
+        let file = File::open(&self.path)?;
+        match (key, self.encrypted) {
+            (None, false) => self.read_memory(shared_mem, zstd::Decoder::new(file)?),
+            (Some(key), true) => {
+                let input = DecryptingReader::new(key, HIBERNATION_AAD, file)?;
+                self.read_memory(shared_mem, zstd::Decoder::new(input)?)
+            }
+            (None, true) => Err(new_error!(
+                "Hibernated memory is encrypted, but no key was given to decrypt it"
+            )),
+            (Some(_), false) => Err(new_error!(
+                "Hibernated memory is not encrypted, but a key was given to decrypt it"
+            )),
+        }
+    }
+
+    /// Read the guest memory and snapshots written by `write_memory` from
+    /// `decoder`
+    fn read_memory<S: SharedMemory>(
+        &self,
+        shared_mem: &mut S,
+        mut decoder: impl Read,
+    ) -> Result<Vec<SharedMemorySnapshot>> {
+        if !self.memory_is_last_snapshot {
+            shared_mem.with_exclusivity(|e| decoder.read_exact(e.as_mut_slice()))??;
+        }
+        let snapshots = (0..self.snapshot_count)
+            .map(|_| {
+                let mut snapshot = vec![0; self.mem_size];
+                decoder.read_exact(&mut snapshot)?;
+                Ok(SharedMemorySnapshot::from_vec(snapshot))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        shared_mem.with_exclusivity(|e| -> Result<()> {
+            if let Some(last) = snapshots.last().filter(|_| self.memory_is_last_snapshot) {
+                e.copy_from_slice(last.as_slice(), 0)?;
+            }
+            if self.memory_population == MemoryPopulation::Prefault {
+                e.prefault()?;
+            }
+            Ok(())
+        })??;
+        Ok(snapshots)
+    }
+}
+
+impl Drop for HibernatedMemory {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::error!(
+                "Failed to delete hibernated sandbox memory {}: {:?}",
+                self.path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::mem::PAGE_SIZE_USIZE;
+
+    use super::HibernatedMemory;
+    use crate::mem::shared_mem::{ExclusiveSharedMemory, SharedMemory};
+    use crate::mem::shared_mem_snapshot::SharedMemorySnapshot;
+    use crate::mem::snapshot_encryption::SnapshotKey;
+
+    #[test]
+    fn write_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut eshm = ExclusiveSharedMemory::new(PAGE_SIZE_USIZE * 4).unwrap();
+        eshm.copy_from_slice(b"snapshot", 0).unwrap();
+        let snapshots = vec![SharedMemorySnapshot::new(&mut eshm).unwrap()];
+        eshm.copy_from_slice(b"memory", PAGE_SIZE_USIZE).unwrap();
+        let memory = eshm.copy_all_to_vec().unwrap();
+
+        let hibernated = HibernatedMemory::write(&mut eshm, &snapshots, dir.path(), None).unwrap();
+        assert_eq!(1, std::fs::read_dir(dir.path()).unwrap().count());
+        eshm.as_mut_slice().fill(0);
+
+        let restored = hibernated.read(&mut eshm, None).unwrap();
+        assert_eq!(memory, eshm.copy_all_to_vec().unwrap());
+        assert_eq!(1, restored.len());
+        assert_eq!(snapshots[0].as_slice(), restored[0].as_slice());
+
+        drop(hibernated);
+        assert_eq!(0, std::fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    #[test]
+    fn memory_identical_to_last_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut eshm = ExclusiveSharedMemory::new(PAGE_SIZE_USIZE * 4).unwrap();
+        eshm.copy_from_slice(b"snapshot", 0).unwrap();
+        let snapshots = vec![SharedMemorySnapshot::new(&mut eshm).unwrap()];
+
+        let hibernated = HibernatedMemory::write(&mut eshm, &snapshots, dir.path(), None).unwrap();
+        assert!(hibernated.memory_is_last_snapshot);
+        eshm.as_mut_slice().fill(0);
+
+        hibernated.read(&mut eshm, None).unwrap();
+        assert_eq!(b"snapshot", &eshm.as_slice()[..8]);
+    }
+
+    #[test]
+    fn encrypted() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut eshm = ExclusiveSharedMemory::new(PAGE_SIZE_USIZE * 4).unwrap();
+        eshm.copy_from_slice(b"tenant secret", 0).unwrap();
+        let memory = eshm.copy_all_to_vec().unwrap();
+        let key = SnapshotKey::generate();
+
+        let hibernated = HibernatedMemory::write(&mut eshm, &[], dir.path(), Some(&key)).unwrap();
+        assert!(hibernated.is_encrypted());
+        let file = std::fs::read(&hibernated.path).unwrap();
+        assert!(!file.windows(13).any(|w| w == b"tenant secret"));
+        eshm.as_mut_slice().fill(0);
+
+        assert!(hibernated.read(&mut eshm, None).is_err());
+        assert!(hibernated
+            .read(&mut eshm, Some(&SnapshotKey::generate()))
+            .is_err());
+        hibernated.read(&mut eshm, Some(&key)).unwrap();
+        assert_eq!(memory, eshm.copy_all_to_vec().unwrap());
+    }
+}
@@ -16,22 +16,29 @@ limitations under the License.
 use std::fmt::Debug;
 use std::mem::{offset_of, size_of};
 
-use hyperlight_common::mem::{GuestStackData, HyperlightPEB, RunMode, PAGE_SIZE_USIZE};
+use hyperlight_common::flatbuffer_wrappers::payload_limits::PayloadLimits;
+use hyperlight_common::mem::{EpochData, GuestStackData, HyperlightPEB, RunMode, PAGE_SIZE_USIZE};
+use hyperlight_common::payload_compression::PayloadCompressionData;
+use hyperlight_common::transport::PortMap;
 use paste::paste;
 use rand::{rng, RngCore};
 use tracing::{instrument, Span};
 
 use super::memory_region::MemoryRegionType::{
-    BootStack, Code, GuardPage, GuestErrorData, Heap, HostExceptionData, HostFunctionDefinitions,
-    InputData, KernelStack, OutputData, PageTables, PanicContext, Peb, Stack,
+    BootStack, Code, GuardPage, GuestErrorData, GuestLogRing, Heap, HostExceptionData,
+    HostFunctionDefinitions, InputData, JitScratch, KernelStack, OutputData, PageTables,
+    PanicContext, Peb, ReadOnlyData, ResultBuffer, Secrets, ShadowStack, Stack,
 };
 use super::memory_region::{MemoryRegion, MemoryRegionFlags, MemoryRegionVecBuilder};
 use super::mgr::AMOUNT_OF_MEMORY_PER_PT;
-use super::shared_mem::{ExclusiveSharedMemory, GuestSharedMemory, SharedMemory};
+use super::shared_mem::{ExclusiveSharedMemory, SharedMemory};
 use crate::error::HyperlightError::{GuestOffsetIsInvalid, MemoryRequestTooBig};
+use crate::sandbox::memory_layout::LayoutRegion;
 use crate::sandbox::SandboxConfiguration;
 use crate::{log_then_return, new_error, Result};
 
+// +-------------------------------------------+
+// |             Shadow Stack                  |
 // +-------------------------------------------+
 // |             Boot Stack (4KiB)             |
 // +-------------------------------------------+
@@ -44,10 +51,20 @@ use crate::{log_then_return, new_error, Result};
 // |             Guest (User) Stack            |
 // +-------------------------------------------+
 // |             Guard Page (4KiB)             |
+// +-------------------------------------------+ <- the regions from here to the PEB
+// |             Guest Heap                    |    are in the configured order, by
+// +-------------------------------------------+    default the one shown
+// |         Guest Panic Context               |
 // +-------------------------------------------+
-// |             Guest Heap                    |
+// |              JIT Scratch                  |
 // +-------------------------------------------+
-// |         Guest Panic Context               |
+// |                Secrets                    |
+// +-------------------------------------------+
+// |             Guest Log Ring                |
+// +-------------------------------------------+
+// |             Read-Only Data                |
+// +-------------------------------------------+
+// |             Result Buffer                 |
 // +-------------------------------------------+
 // |             Output Data                   |
 // +-------------------------------------------+
@@ -63,9 +80,9 @@ use crate::{log_then_return, new_error, Result};
 // +-------------------------------------------+
 // |               Guest Code                  |
 // +-------------------------------------------+
-// |                    PT                     |
-// +-------------------------------------------+ 0x203_000
-// |                    PD                     |
+// |                   PTs                     |
+// +-------------------------------------------+ 0x202_000 + PDs * 0x1_000
+// |                   PDs                     |
 // +-------------------------------------------+ 0x202_000
 // |                   PDPT                    |
 // +-------------------------------------------+ 0x201_000
@@ -94,6 +111,26 @@ use crate::{log_then_return, new_error, Result};
 /// - `OutputData` - this is a buffer that is used for output data from host program.
 ///   the length of this field is `OutputDataSize` from `SandboxConfiguration`
 ///
+/// - `ResultBuffer` - this is a buffer guest functions can write large results
+///   into for the host to read in place. the length of this field is
+///   `ResultBufferSize` from `SandboxConfiguration`, it is not mapped if that is 0
+///
+/// - `ReadOnlyData` - this is a buffer the host publishes data into for the guest to
+///   read, which the guest can't write to. the length of this field is
+///   `ReadOnlyDataSize` from `SandboxConfiguration`, it is not mapped if that is 0
+///
+/// - `GuestLogRing` - this is a ring buffer the guest writes its log records into for
+///   the host to drain. the length of this field is `GuestLogRingSize` from
+///   `SandboxConfiguration`, it is not mapped if that is 0
+///
+/// - `Secrets` - this is a buffer the host writes secrets into before the guest is
+///   initialised, for the guest to take. the length of this field is `SecretsSize`
+///   from `SandboxConfiguration`, it is not mapped if that is 0
+///
+/// - `JitScratch` - this is a buffer the guest can write code into and run it. the
+///   length of this field is `JitScratchSize` from `SandboxConfiguration`, it is not
+///   mapped if that is 0
+///
 /// - `GuestHeap` - this is a buffer that is used for heap data in the guest. the length
 ///   of this field is returned by the `heap_size()` method of this struct
 ///
@@ -111,6 +148,9 @@ use crate::{log_then_return, new_error, Result};
 /// Kernel Stack Guard Page is to Guard against boot stack overflow so we dont corrupt the kernel stack
 /// Kernel Stack - this is the stack that is used for kernel mode operations we switch to this early in the initialization function
 /// Guest Stack Guard Page is to Guard against kernel stack overflow so we dont corrupt the user stack
+/// Shadow Stack - this is the CET shadow stack the guest enables if `ShadowStackSize` from
+/// `SandboxConfiguration` is not 0, it is not mapped otherwise. It overflows into the boot
+/// stack, which isn't a shadow stack page, so an overflow faults rather than corrupting it
 
 #[derive(Copy, Clone)]
 pub(crate) struct SandboxMemoryLayout {
@@ -135,6 +175,18 @@ pub(crate) struct SandboxMemoryLayout {
     peb_guest_panic_context_offset: usize,
     peb_heap_data_offset: usize,
     peb_guest_stack_data_offset: usize,
+    peb_guest_max_log_level_offset: usize,
+    peb_payload_limits_offset: usize,
+    peb_result_buffer_offset: usize,
+    peb_host_call_transport_offset: usize,
+    peb_read_only_data_offset: usize,
+    peb_guest_log_ring_offset: usize,
+    peb_secrets_offset: usize,
+    peb_jit_scratch_offset: usize,
+    peb_epoch_offset: usize,
+    peb_entropy_mode_offset: usize,
+    peb_port_map_offset: usize,
+    peb_payload_compression_offset: usize,
 
     // The following are the actual values
     // that are written to the PEB struct
@@ -143,6 +195,11 @@ pub(crate) struct SandboxMemoryLayout {
     pub(super) guest_error_buffer_offset: usize,
     pub(super) input_data_buffer_offset: usize,
     pub(super) output_data_buffer_offset: usize,
+    pub(super) result_buffer_offset: usize,
+    pub(super) read_only_data_offset: usize,
+    pub(super) guest_log_ring_offset: usize,
+    pub(super) secrets_offset: usize,
+    jit_scratch_offset: usize,
     guest_panic_context_buffer_offset: usize,
     guest_heap_buffer_offset: usize,
     guard_page_offset: usize,
@@ -153,12 +210,22 @@ pub(crate) struct SandboxMemoryLayout {
     #[allow(dead_code)]
     pub(super) kernel_stack_size_rounded: usize,
     boot_stack_buffer_offset: usize,
+    shadow_stack_buffer_offset: usize,
+    shadow_stack_size: usize,
 
     // other
     pub(crate) peb_address: usize,
     code_size: usize,
+    // The page aligned start and end offsets, from the start of the code,
+    // of the pages that hold only code and are mapped read-only
+    write_protected_code: (usize, usize),
+    executable_code: (usize, usize),
     // The total size of the page tables
     total_page_table_size: usize,
+    // The number of page directories, each of which maps 1GB
+    page_directory_count: usize,
+    // The number of page tables, each of which maps 2MB
+    page_table_count: usize,
     // The offset in the sandbox memory where the code starts
     guest_code_offset: usize,
 }
@@ -219,6 +286,54 @@ impl Debug for SandboxMemoryLayout {
                 "Guest Stack Offset",
                 &format_args!("{:#x}", self.peb_guest_stack_data_offset),
             )
+            .field(
+                "Guest Max Log Level Offset",
+                &format_args!("{:#x}", self.peb_guest_max_log_level_offset),
+            )
+            .field(
+                "Payload Limits Offset",
+                &format_args!("{:#x}", self.peb_payload_limits_offset),
+            )
+            .field(
+                "Result Buffer Data Offset",
+                &format_args!("{:#x}", self.peb_result_buffer_offset),
+            )
+            .field(
+                "Host Call Transport Offset",
+                &format_args!("{:#x}", self.peb_host_call_transport_offset),
+            )
+            .field(
+                "Read-Only Data Offset",
+                &format_args!("{:#x}", self.peb_read_only_data_offset),
+            )
+            .field(
+                "Guest Log Ring Data Offset",
+                &format_args!("{:#x}", self.peb_guest_log_ring_offset),
+            )
+            .field(
+                "Secrets Data Offset",
+                &format_args!("{:#x}", self.peb_secrets_offset),
+            )
+            .field(
+                "JIT Scratch Data Offset",
+                &format_args!("{:#x}", self.peb_jit_scratch_offset),
+            )
+            .field(
+                "Epoch Data Offset",
+                &format_args!("{:#x}", self.peb_epoch_offset),
+            )
+            .field(
+                "Entropy Mode Offset",
+                &format_args!("{:#x}", self.peb_entropy_mode_offset),
+            )
+            .field(
+                "Port Map Offset",
+                &format_args!("{:#x}", self.peb_port_map_offset),
+            )
+            .field(
+                "Payload Compression Offset",
+                &format_args!("{:#x}", self.peb_payload_compression_offset),
+            )
             .field(
                 "Host Function Definitions Buffer Offset",
                 &format_args!("{:#x}", self.host_function_definitions_buffer_offset),
@@ -239,6 +354,26 @@ impl Debug for SandboxMemoryLayout {
                 "Output Data Buffer Offset",
                 &format_args!("{:#x}", self.output_data_buffer_offset),
             )
+            .field(
+                "Result Buffer Offset",
+                &format_args!("{:#x}", self.result_buffer_offset),
+            )
+            .field(
+                "Read-Only Data Buffer Offset",
+                &format_args!("{:#x}", self.read_only_data_offset),
+            )
+            .field(
+                "Guest Log Ring Offset",
+                &format_args!("{:#x}", self.guest_log_ring_offset),
+            )
+            .field(
+                "Secrets Offset",
+                &format_args!("{:#x}", self.secrets_offset),
+            )
+            .field(
+                "JIT Scratch Offset",
+                &format_args!("{:#x}", self.jit_scratch_offset),
+            )
             .field(
                 "Guest Panic Context Buffer Offset",
                 &format_args!("{:#x}", self.guest_panic_context_buffer_offset),
@@ -279,6 +414,14 @@ impl Debug for SandboxMemoryLayout {
                 "Boot Stack Buffer Offset",
                 &format_args!("{:#x}", self.boot_stack_buffer_offset),
             )
+            .field(
+                "Shadow Stack Buffer Offset",
+                &format_args!("{:#x}", self.shadow_stack_buffer_offset),
+            )
+            .field(
+                "Shadow Stack Size",
+                &format_args!("{:#x}", self.shadow_stack_size),
+            )
             .finish()
     }
 }
@@ -290,28 +433,25 @@ impl SandboxMemoryLayout {
     /// The offset into the sandbox's memory where the Page Directory Pointer
     /// Table starts.
     pub(super) const PDPT_OFFSET: usize = 0x1000;
-    /// The offset into the sandbox's memory where the Page Directory starts.
+    /// The offset into the sandbox's memory where the Page Directories
+    /// start. They are followed by the Page Tables.
     pub(super) const PD_OFFSET: usize = 0x2000;
-    /// The offset into the sandbox's memory where the Page Tables start.
-    pub(super) const PT_OFFSET: usize = 0x3000;
-    /// The address (not the offset) to the start of the page directory
+    /// The address (not the offset) to the start of the page directories
     pub(super) const PD_GUEST_ADDRESS: usize = Self::BASE_ADDRESS + Self::PD_OFFSET;
     /// The address (not the offset) into sandbox memory where the Page
     /// Directory Pointer Table starts
     pub(super) const PDPT_GUEST_ADDRESS: usize = Self::BASE_ADDRESS + Self::PDPT_OFFSET;
-    /// The address (not the offset) into sandbox memory where the Page
-    /// Tables start
-    pub(super) const PT_GUEST_ADDRESS: usize = Self::BASE_ADDRESS + Self::PT_OFFSET;
     /// The maximum amount of memory a single sandbox will be allowed.
-    /// The addressable virtual memory with current paging setup is virtual address 0x0 - 0x40000000 (excl.),
+    /// The PML4 has a single entry, so the addressable virtual memory is
+    /// virtual address 0x0 - 0x80_0000_0000 (excl.), 512 page directories of 1GB each.
     /// However, the memory up to Self::BASE_ADDRESS is not used.
-    const MAX_MEMORY_SIZE: usize = 0x40000000 - Self::BASE_ADDRESS;
+    pub(crate) const MAX_MEMORY_SIZE: usize = 512 * 0x40000000 - Self::BASE_ADDRESS;
 
     /// The base address of the sandbox's memory.
     pub(crate) const BASE_ADDRESS: usize = 0x0200000;
 
     // the offset into a sandbox's input/output buffer where the stack starts
-    const STACK_POINTER_SIZE_BYTES: u64 = 8;
+    pub(super) const STACK_POINTER_SIZE_BYTES: u64 = 8;
 
     /// Create a new `SandboxMemoryLayout` with the given
     /// `SandboxConfiguration`, code size and stack/heap size.
@@ -322,8 +462,35 @@ impl SandboxMemoryLayout {
         stack_size: usize,
         heap_size: usize,
     ) -> Result<Self> {
-        let total_page_table_size =
-            Self::get_total_page_table_size(cfg, code_size, stack_size, heap_size);
+        // The page tables have to map all of the memory, including
+        // themselves, so first lay out the memory without them
+        let without_page_tables =
+            Self::with_page_tables(cfg, code_size, stack_size, heap_size, 0, 0);
+        let (page_directory_count, page_table_count) = Self::get_page_table_counts(
+            without_page_tables.get_unaligned_memory_size()
+                - without_page_tables.total_page_table_size,
+        );
+        Ok(Self::with_page_tables(
+            cfg,
+            code_size,
+            stack_size,
+            heap_size,
+            page_directory_count,
+            page_table_count,
+        ))
+    }
+
+    /// Lay out the memory after a PML4, a PDPT, `page_directory_count`
+    /// page directories and `page_table_count` page tables
+    fn with_page_tables(
+        cfg: SandboxConfiguration,
+        code_size: usize,
+        stack_size: usize,
+        heap_size: usize,
+        page_directory_count: usize,
+        page_table_count: usize,
+    ) -> Self {
+        let total_page_table_size = (2 + page_directory_count + page_table_count) * PAGE_SIZE_USIZE;
         let guest_code_offset = total_page_table_size;
         // The following offsets are to the fields of the PEB struct itself!
         let peb_offset = total_page_table_size + round_up_to(code_size, PAGE_SIZE_USIZE);
@@ -343,43 +510,50 @@ impl SandboxMemoryLayout {
             peb_offset + offset_of!(HyperlightPEB, guestPanicContextData);
         let peb_heap_data_offset = peb_offset + offset_of!(HyperlightPEB, guestheapData);
         let peb_guest_stack_data_offset = peb_offset + offset_of!(HyperlightPEB, gueststackData);
+        let peb_guest_max_log_level_offset =
+            peb_offset + offset_of!(HyperlightPEB, guest_max_log_level);
+        let peb_payload_limits_offset = peb_offset + offset_of!(HyperlightPEB, payload_limits);
+        let peb_result_buffer_offset = peb_offset + offset_of!(HyperlightPEB, resultBufferData);
+        let peb_host_call_transport_offset =
+            peb_offset + offset_of!(HyperlightPEB, host_call_transport);
+        let peb_read_only_data_offset = peb_offset + offset_of!(HyperlightPEB, readOnlyData);
+        let peb_guest_log_ring_offset = peb_offset + offset_of!(HyperlightPEB, guestLogRingData);
+        let peb_secrets_offset = peb_offset + offset_of!(HyperlightPEB, secretsData);
+        let peb_jit_scratch_offset = peb_offset + offset_of!(HyperlightPEB, jitScratchData);
+        let peb_epoch_offset = peb_offset + offset_of!(HyperlightPEB, epochData);
+        let peb_entropy_mode_offset = peb_offset + offset_of!(HyperlightPEB, entropy_mode);
+        let peb_port_map_offset = peb_offset + offset_of!(HyperlightPEB, port_map);
+        let peb_payload_compression_offset =
+            peb_offset + offset_of!(HyperlightPEB, payload_compression);
 
         // The following offsets are the actual values that relate to memory layout,
         // which are written to PEB struct
         let peb_address = Self::BASE_ADDRESS + peb_offset;
-        // make sure host function definitions buffer starts at 4K boundary
-        let host_function_definitions_buffer_offset = round_up_to(
-            peb_guest_stack_data_offset + size_of::<GuestStackData>(),
-            PAGE_SIZE_USIZE,
-        );
-        // make sure host exception buffer starts at 4K boundary
-        let host_exception_buffer_offset = round_up_to(
-            host_function_definitions_buffer_offset + cfg.get_host_function_definition_size(),
-            PAGE_SIZE_USIZE,
-        );
-        let guest_error_buffer_offset = round_up_to(
-            host_exception_buffer_offset + cfg.get_host_exception_size(),
-            PAGE_SIZE_USIZE,
-        );
-        let input_data_buffer_offset = round_up_to(
-            guest_error_buffer_offset + cfg.get_guest_error_buffer_size(),
-            PAGE_SIZE_USIZE,
-        );
-        let output_data_buffer_offset = round_up_to(
-            input_data_buffer_offset + cfg.get_input_data_size(),
-            PAGE_SIZE_USIZE,
-        );
-        let guest_panic_context_buffer_offset = round_up_to(
-            output_data_buffer_offset + cfg.get_output_data_size(),
-            PAGE_SIZE_USIZE,
-        );
-        // make sure heap buffer starts at 4K boundary
-        let guest_heap_buffer_offset = round_up_to(
-            guest_panic_context_buffer_offset + cfg.get_guest_panic_context_buffer_size(),
-            PAGE_SIZE_USIZE,
-        );
-        // make sure guard page starts at 4K boundary
-        let guard_page_offset = round_up_to(guest_heap_buffer_offset + heap_size, PAGE_SIZE_USIZE);
+        // The regions between the PEB and the guard page are placed in the
+        // configured order, each starting at a 4K boundary
+        let mut offset = round_up_to(peb_offset + size_of::<HyperlightPEB>(), PAGE_SIZE_USIZE);
+        let mut region_offsets = [0; LayoutRegion::COUNT];
+        for region in cfg.get_region_order() {
+            region_offsets[region as usize] = offset;
+            offset = round_up_to(
+                offset + Self::get_region_size(cfg, heap_size, region),
+                PAGE_SIZE_USIZE,
+            );
+        }
+        let host_function_definitions_buffer_offset =
+            region_offsets[LayoutRegion::HostFunctionDefinitions as usize];
+        let host_exception_buffer_offset = region_offsets[LayoutRegion::HostExceptionData as usize];
+        let guest_error_buffer_offset = region_offsets[LayoutRegion::GuestErrorData as usize];
+        let input_data_buffer_offset = region_offsets[LayoutRegion::InputData as usize];
+        let output_data_buffer_offset = region_offsets[LayoutRegion::OutputData as usize];
+        let result_buffer_offset = region_offsets[LayoutRegion::ResultBuffer as usize];
+        let read_only_data_offset = region_offsets[LayoutRegion::ReadOnlyData as usize];
+        let guest_log_ring_offset = region_offsets[LayoutRegion::GuestLogRing as usize];
+        let secrets_offset = region_offsets[LayoutRegion::Secrets as usize];
+        let jit_scratch_offset = region_offsets[LayoutRegion::JitScratch as usize];
+        let guest_panic_context_buffer_offset = region_offsets[LayoutRegion::PanicContext as usize];
+        let guest_heap_buffer_offset = region_offsets[LayoutRegion::Heap as usize];
+        let guard_page_offset = offset;
         let guest_user_stack_buffer_offset = guard_page_offset + PAGE_SIZE_USIZE;
         // round up stack size to page size. This is needed for MemoryRegion
         let stack_size_rounded = round_up_to(stack_size, PAGE_SIZE_USIZE);
@@ -389,8 +563,10 @@ impl SandboxMemoryLayout {
         let kernel_stack_size_rounded = round_up_to(cfg.get_kernel_stack_size(), PAGE_SIZE_USIZE);
         let kernel_stack_guard_page_offset = kernel_stack_buffer_offset + kernel_stack_size_rounded;
         let boot_stack_buffer_offset = kernel_stack_guard_page_offset + PAGE_SIZE_USIZE;
+        let shadow_stack_buffer_offset = boot_stack_buffer_offset + PAGE_SIZE_USIZE;
+        let shadow_stack_size = round_up_to(cfg.get_shadow_stack_size(), PAGE_SIZE_USIZE);
 
-        Ok(Self {
+        Self {
             peb_offset,
             stack_size: stack_size_rounded,
             heap_size,
@@ -406,13 +582,32 @@ impl SandboxMemoryLayout {
             peb_guest_panic_context_offset,
             peb_heap_data_offset,
             peb_guest_stack_data_offset,
+            peb_guest_max_log_level_offset,
+            peb_payload_limits_offset,
+            peb_result_buffer_offset,
+            peb_read_only_data_offset,
+            peb_guest_log_ring_offset,
+            peb_secrets_offset,
+            peb_jit_scratch_offset,
+            peb_epoch_offset,
+            peb_entropy_mode_offset,
+            peb_port_map_offset,
+            peb_payload_compression_offset,
+            peb_host_call_transport_offset,
             guest_error_buffer_offset,
             sandbox_memory_config: cfg,
             code_size,
+            write_protected_code: (0, 0),
+            executable_code: (0, round_up_to(code_size, PAGE_SIZE_USIZE)),
             host_function_definitions_buffer_offset,
             host_exception_buffer_offset,
             input_data_buffer_offset,
             output_data_buffer_offset,
+            result_buffer_offset,
+            read_only_data_offset,
+            guest_log_ring_offset,
+            secrets_offset,
+            jit_scratch_offset,
             guest_heap_buffer_offset,
             guest_user_stack_buffer_offset,
             peb_address,
@@ -425,7 +620,47 @@ impl SandboxMemoryLayout {
             kernel_stack_guard_page_offset,
             kernel_stack_size_rounded,
             boot_stack_buffer_offset,
-        })
+            shadow_stack_buffer_offset,
+            shadow_stack_size,
+            page_directory_count,
+            page_table_count,
+        }
+    }
+
+    /// The offset of `region` in the sandbox's memory
+    fn get_region_offset(&self, region: LayoutRegion) -> usize {
+        match region {
+            LayoutRegion::HostFunctionDefinitions => self.host_function_definitions_buffer_offset,
+            LayoutRegion::HostExceptionData => self.host_exception_buffer_offset,
+            LayoutRegion::GuestErrorData => self.guest_error_buffer_offset,
+            LayoutRegion::InputData => self.input_data_buffer_offset,
+            LayoutRegion::OutputData => self.output_data_buffer_offset,
+            LayoutRegion::ResultBuffer => self.result_buffer_offset,
+            LayoutRegion::ReadOnlyData => self.read_only_data_offset,
+            LayoutRegion::GuestLogRing => self.guest_log_ring_offset,
+            LayoutRegion::Secrets => self.secrets_offset,
+            LayoutRegion::JitScratch => self.jit_scratch_offset,
+            LayoutRegion::PanicContext => self.guest_panic_context_buffer_offset,
+            LayoutRegion::Heap => self.guest_heap_buffer_offset,
+        }
+    }
+
+    /// The size of `region`, where the heap is `heap_size` bytes
+    fn get_region_size(cfg: SandboxConfiguration, heap_size: usize, region: LayoutRegion) -> usize {
+        match region {
+            LayoutRegion::HostFunctionDefinitions => cfg.get_host_function_definition_size(),
+            LayoutRegion::HostExceptionData => cfg.get_host_exception_size(),
+            LayoutRegion::GuestErrorData => cfg.get_guest_error_buffer_size(),
+            LayoutRegion::InputData => cfg.get_input_data_size() * cfg.get_io_buffer_slots(),
+            LayoutRegion::OutputData => cfg.get_output_data_size() * cfg.get_io_buffer_slots(),
+            LayoutRegion::ResultBuffer => cfg.get_result_buffer_size(),
+            LayoutRegion::ReadOnlyData => cfg.get_read_only_data_size(),
+            LayoutRegion::GuestLogRing => cfg.get_guest_log_ring_size(),
+            LayoutRegion::Secrets => cfg.get_secrets_size(),
+            LayoutRegion::JitScratch => cfg.get_jit_scratch_size(),
+            LayoutRegion::PanicContext => cfg.get_guest_panic_context_buffer_size(),
+            LayoutRegion::Heap => heap_size,
+        }
     }
 
     /// Gets the offset in guest memory to the RunMode field in the PEB struct.
@@ -453,6 +688,32 @@ impl SandboxMemoryLayout {
         self.peb_guest_error_offset + size_of::<u64>()
     }
 
+    /// Get the offset in guest memory to the max log level the host
+    /// wants the guest to use for the next guest function call
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(super) fn get_guest_max_log_level_offset(&self) -> usize {
+        self.peb_guest_max_log_level_offset
+    }
+
+    /// Get the offset in guest memory to the max payload size
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_max_payload_size_offset(&self) -> usize {
+        self.peb_payload_limits_offset + offset_of!(PayloadLimits, max_payload_size)
+    }
+
+    /// Get the offset in guest memory to the max parameter size
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_max_parameter_size_offset(&self) -> usize {
+        self.peb_payload_limits_offset + offset_of!(PayloadLimits, max_parameter_size)
+    }
+
+    /// Get the offset in guest memory to the payload compression the guest
+    /// accepted
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(super) fn get_accepted_payload_compression_offset(&self) -> usize {
+        self.peb_payload_compression_offset + offset_of!(PayloadCompressionData, accepted)
+    }
+
     /// Get the offset in guest memory to the output data size
     #[instrument(skip_all, parent = Span::current(), level= "Trace")]
     pub(super) fn get_output_data_size_offset(&self) -> usize {
@@ -512,7 +773,7 @@ impl SandboxMemoryLayout {
 
     /// Get the offset in guest memory to the output data pointer.
     #[instrument(skip_all, parent = Span::current(), level= "Trace")]
-    fn get_output_data_pointer_offset(&self) -> usize {
+    pub(super) fn get_output_data_pointer_offset(&self) -> usize {
         // This field is immediately after the output data size field,
         // which is a `u64`.
         self.get_output_data_size_offset() + size_of::<u64>()
@@ -527,6 +788,97 @@ impl SandboxMemoryLayout {
         self.output_data_buffer_offset
     }
 
+    /// Get the offset in guest memory to the result buffer size
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_result_buffer_size_offset(&self) -> usize {
+        // The size field is the first field in the `ResultBufferData` struct
+        self.peb_result_buffer_offset
+    }
+
+    /// Get the offset in guest memory to the result buffer pointer.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_result_buffer_pointer_offset(&self) -> usize {
+        // This field is immediately after the result buffer size field,
+        // which is a `u64`.
+        self.get_result_buffer_size_offset() + size_of::<u64>()
+    }
+
+    /// Get the offset in guest memory to the read-only data region size
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_read_only_data_size_offset(&self) -> usize {
+        // The size field is the first field in the `ReadOnlyData` struct
+        self.peb_read_only_data_offset
+    }
+
+    /// Get the offset in guest memory to the length of the data last
+    /// published to the read-only data region
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(super) fn get_read_only_data_length_offset(&self) -> usize {
+        // This field is immediately after the size field, which is a `u64`.
+        self.get_read_only_data_size_offset() + size_of::<u64>()
+    }
+
+    /// Get the offset in guest memory to the read-only data region pointer
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_read_only_data_pointer_offset(&self) -> usize {
+        // This field is immediately after the length field, which is a `u64`.
+        self.get_read_only_data_length_offset() + size_of::<u64>()
+    }
+
+    /// Get the offset in guest memory to the guest log ring size
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_guest_log_ring_size_offset(&self) -> usize {
+        // The size field is the first field in the `GuestLogRingData` struct
+        self.peb_guest_log_ring_offset
+    }
+
+    /// Get the offset in guest memory to the guest log ring pointer
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_guest_log_ring_pointer_offset(&self) -> usize {
+        // This field is immediately after the size field, which is a `u64`.
+        self.get_guest_log_ring_size_offset() + size_of::<u64>()
+    }
+
+    /// Get the offset in guest memory to the secrets region size
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_secrets_size_offset(&self) -> usize {
+        // The size field is the first field in the `SecretsData` struct
+        self.peb_secrets_offset
+    }
+
+    /// Get the offset in guest memory to the secrets region pointer
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_secrets_pointer_offset(&self) -> usize {
+        // This field is immediately after the size field, which is a `u64`.
+        self.get_secrets_size_offset() + size_of::<u64>()
+    }
+
+    /// Get the offset in guest memory to the JIT scratch region size
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_jit_scratch_size_offset(&self) -> usize {
+        // The size field is the first field in the `JitScratchData` struct
+        self.peb_jit_scratch_offset
+    }
+
+    /// Get the offset in guest memory to the JIT scratch region pointer
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_jit_scratch_pointer_offset(&self) -> usize {
+        // This field is immediately after the size field, which is a `u64`.
+        self.get_jit_scratch_size_offset() + size_of::<u64>()
+    }
+
+    /// Get the offset in guest memory to the epoch
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_epoch_offset(&self) -> usize {
+        self.peb_epoch_offset + offset_of!(EpochData, epoch)
+    }
+
+    /// Get the offset in guest memory to the epoch deadline
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_epoch_deadline_offset(&self) -> usize {
+        self.peb_epoch_offset + offset_of!(EpochData, epochDeadline)
+    }
+
     /// Get the offset in guest memory to the input data size.
     #[instrument(skip_all, parent = Span::current(), level= "Trace")]
     pub(super) fn get_input_data_size_offset(&self) -> usize {
@@ -536,12 +888,24 @@ impl SandboxMemoryLayout {
 
     /// Get the offset in guest memory to the input data pointer.
     #[instrument(skip_all, parent = Span::current(), level= "Trace")]
-    fn get_input_data_pointer_offset(&self) -> usize {
+    pub(super) fn get_input_data_pointer_offset(&self) -> usize {
         // The input data pointer is immediately after the input
         // data size field in the `InputData` struct which is a `u64`.
         self.get_input_data_size_offset() + size_of::<u64>()
     }
 
+    /// Get the offset in guest memory to the input data buffer of `slot`.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(super) fn get_input_data_slot_offset(&self, slot: usize) -> usize {
+        self.input_data_buffer_offset + slot * self.sandbox_memory_config.get_input_data_size()
+    }
+
+    /// Get the offset in guest memory to the output data buffer of `slot`.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(super) fn get_output_data_slot_offset(&self, slot: usize) -> usize {
+        self.output_data_buffer_offset + slot * self.sandbox_memory_config.get_output_data_size()
+    }
+
     /// Get the offset in guest memory to the code pointer
     #[instrument(skip_all, parent = Span::current(), level= "Trace")]
     pub(super) fn get_code_pointer_offset(&self) -> usize {
@@ -644,7 +1008,60 @@ impl SandboxMemoryLayout {
     /// layout.
     #[instrument(skip_all, parent = Span::current(), level= "Trace")]
     fn get_unaligned_memory_size(&self) -> usize {
-        self.get_boot_stack_buffer_offset() + PAGE_SIZE_USIZE
+        self.shadow_stack_buffer_offset + self.shadow_stack_size
+    }
+
+    /// Write protect the pages of the code between the offsets `start` and
+    /// `end` from the start of the code, which must be page aligned and
+    /// within the executable code
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(super) fn set_write_protected_code(&mut self, start: usize, end: usize) -> Result<()> {
+        self.check_code_pages("Write protected", start, end)?;
+        let (executable_start, executable_end) = self.executable_code;
+        if start < end && (start < executable_start || end > executable_end) {
+            return Err(new_error!(
+                "Write protected code {:#x}..{:#x} is outside the executable code {:#x}..{:#x}",
+                start,
+                end,
+                executable_start,
+                executable_end
+            ));
+        }
+        self.write_protected_code = (start, end);
+        Ok(())
+    }
+
+    /// Only let the guest run the pages of the code between the offsets
+    /// `start` and `end` from the start of the code, which must be page
+    /// aligned and within the code. The rest of the guest binary holds its
+    /// data, which the guest can't run. All of it is executable by default.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(super) fn set_executable_code(&mut self, start: usize, end: usize) -> Result<()> {
+        self.check_code_pages("Executable", start, end)?;
+        self.executable_code = (start, end);
+        Ok(())
+    }
+
+    /// Check that `start..end` are page aligned offsets within the code
+    fn check_code_pages(&self, what: &str, start: usize, end: usize) -> Result<()> {
+        if start % PAGE_SIZE_USIZE != 0 || end % PAGE_SIZE_USIZE != 0 || start > end {
+            return Err(new_error!(
+                "{} code {:#x}..{:#x} is not page aligned",
+                what,
+                start,
+                end
+            ));
+        }
+        if end > round_up_to(self.code_size, PAGE_SIZE_USIZE) {
+            return Err(new_error!(
+                "{} code {:#x}..{:#x} is outside the code, which is {:#x} bytes",
+                what,
+                start,
+                end,
+                self.code_size
+            ));
+        }
+        Ok(())
     }
 
     /// get the code offset
@@ -654,6 +1071,18 @@ impl SandboxMemoryLayout {
         self.guest_code_offset
     }
 
+    /// Get the size of the guest binary loaded at the code offset
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(super) fn get_code_size(&self) -> usize {
+        self.code_size
+    }
+
+    /// Get the offset in the sandbox memory where the guest heap starts
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(super) fn get_guest_heap_buffer_offset(&self) -> usize {
+        self.guest_heap_buffer_offset
+    }
+
     /// Get the guest address of the code section in the sandbox
     #[instrument(skip_all, parent = Span::current(), level= "Trace")]
     pub(crate) fn get_guest_code_address(&self) -> usize {
@@ -667,6 +1096,12 @@ impl SandboxMemoryLayout {
         self.user_stack_guard_page_offset
     }
 
+    /// The size of the kernel stack, rounded up to a whole number of pages
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(super) fn get_kernel_stack_size(&self) -> usize {
+        self.kernel_stack_size_rounded
+    }
+
     /// Get the offset in guest memory to the kernel stack buffer
     /// This is the offset in the sandbox memory where the kernel stack starts
     #[instrument(skip_all, parent = Span::current(), level= "Trace")]
@@ -688,63 +1123,69 @@ impl SandboxMemoryLayout {
         self.boot_stack_buffer_offset
     }
 
+    /// Get the size of the shadow stack, which is 0 if the guest runs
+    /// without one
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_shadow_stack_size(&self) -> usize {
+        self.shadow_stack_size
+    }
+
+    /// Get the offset of the shadow stack token address in guest memory,
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_shadow_stack_token_pointer_offset(&self) -> usize {
+        self.peb_guest_stack_data_offset + offset_of!(GuestStackData, shadowStackToken)
+    }
+
     #[cfg(test)]
     /// Get the page table size
     fn get_page_table_size(&self) -> usize {
         self.total_page_table_size
     }
 
-    // This function calculates the page table size for the sandbox
-    // We need enough memory to store the PML4, PDPT, PD and PTs
-    // The size of a single table is 4K, we can map up to 1GB total memory which requires 1 PML4, 1 PDPT, 1 PD and 512 PTs
-    // but we only need enough PTs to map the memory we are using. (In other words we only need 512 PTs to map the memory if the memory size is 1GB)
-    //
-    // Because we always start the physical address space at 0x200_000
-    // we can calculate the amount of memory needed for the PTs by calculating how much memory is needed for the sandbox configuration in total,
-    // then add 0x200_000 to that (as we start at 0x200_000),
-    // and then add 3 * 4K (for the PML4, PDPT and PD)  to that,
-    // then add 2MB to that (the maximum size of memory required for the PTs themselves is 2MB when we map 1GB of memory in 4K pages),
-    // then divide that by 0x200_000 (as we can map 2MB in each PT) and then round the result up by 1 .
-    // This will give us the total size of the PTs required for the sandbox to which we can add the size of the PML4, PDPT and PD.
-    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
-    fn get_total_page_table_size(
-        cfg: SandboxConfiguration,
-        code_size: usize,
-        stack_size: usize,
-        heap_size: usize,
-    ) -> usize {
-        // Get the configured memory size (assume each section is 4K aligned)
-
-        let mut total_mapped_memory_size: usize = round_up_to(code_size, PAGE_SIZE_USIZE);
-        total_mapped_memory_size += round_up_to(stack_size, PAGE_SIZE_USIZE);
-        total_mapped_memory_size += round_up_to(heap_size, PAGE_SIZE_USIZE);
-        total_mapped_memory_size += round_up_to(cfg.get_host_exception_size(), PAGE_SIZE_USIZE);
-        total_mapped_memory_size +=
-            round_up_to(cfg.get_host_function_definition_size(), PAGE_SIZE_USIZE);
-        total_mapped_memory_size += round_up_to(cfg.get_guest_error_buffer_size(), PAGE_SIZE_USIZE);
-        total_mapped_memory_size += round_up_to(cfg.get_input_data_size(), PAGE_SIZE_USIZE);
-        total_mapped_memory_size += round_up_to(cfg.get_output_data_size(), PAGE_SIZE_USIZE);
-        total_mapped_memory_size +=
-            round_up_to(cfg.get_guest_panic_context_buffer_size(), PAGE_SIZE_USIZE);
-        total_mapped_memory_size += round_up_to(size_of::<HyperlightPEB>(), PAGE_SIZE_USIZE);
-
-        // Add the base address of the sandbox
-        total_mapped_memory_size += Self::BASE_ADDRESS;
-
-        // Add the size of  the PML4, PDPT and PD
-        total_mapped_memory_size += 3 * PAGE_SIZE_USIZE;
-
-        // Add the maximum possible size of the PTs
-        total_mapped_memory_size += 512 * PAGE_SIZE_USIZE;
+    /// Get the number of page directories, which follow the PDPT
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(super) fn get_page_directory_count(&self) -> usize {
+        self.page_directory_count
+    }
 
-        // Get the number of pages needed for the PTs
+    /// Get the number of page tables, which follow the page directories
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(super) fn get_page_table_count(&self) -> usize {
+        self.page_table_count
+    }
 
-        let num_pages: usize = ((total_mapped_memory_size + AMOUNT_OF_MEMORY_PER_PT - 1)
-            / AMOUNT_OF_MEMORY_PER_PT)
-            + 1 // Round up
-            + 3; // PML4, PDPT, PD
+    /// Get the offset into the sandbox's memory where the Page Tables start
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(super) fn get_pt_offset(&self) -> usize {
+        Self::PD_OFFSET + self.page_directory_count * PAGE_SIZE_USIZE
+    }
 
-        num_pages * PAGE_SIZE_USIZE
+    // This function calculates the number of page directories and page
+    // tables needed to map the sandbox's memory, `memory_size` bytes of which
+    // are not page tables.
+    //
+    // Each page table maps 2MB of memory in 4K pages, and each page directory
+    // maps 1GB with 512 page tables. Because the physical address space starts
+    // at 0x200_000, one more page table maps the memory below that. The page
+    // tables map themselves too, so more page tables can need yet more of
+    // them, which is settled by growing the counts until they stop changing.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    fn get_page_table_counts(memory_size: usize) -> (usize, usize) {
+        let (mut page_directory_count, mut page_table_count) = (0, 0);
+        loop {
+            // The PML4 and PDPT are a page each
+            let page_table_size = (2 + page_directory_count + page_table_count) * PAGE_SIZE_USIZE;
+            let mapped_size = round_up_to(page_table_size + memory_size, PAGE_SIZE_USIZE);
+            let needed_page_tables = mapped_size.div_ceil(AMOUNT_OF_MEMORY_PER_PT) + 1;
+            let needed_page_directories = needed_page_tables.div_ceil(512);
+            if (needed_page_directories, needed_page_tables)
+                == (page_directory_count, page_table_count)
+            {
+                return (page_directory_count, page_table_count);
+            }
+            page_directory_count = needed_page_directories;
+            page_table_count = needed_page_tables;
+        }
     }
 
     /// Get the total size of guest memory in `self`'s memory
@@ -770,7 +1211,7 @@ impl SandboxMemoryLayout {
 
     /// Returns the memory regions associated with this memory layout,
     /// suitable for passing to a hypervisor for mapping into memory
-    pub fn get_memory_regions(&self, shared_mem: &GuestSharedMemory) -> Result<Vec<MemoryRegion>> {
+    pub fn get_memory_regions<S: SharedMemory>(&self, shared_mem: &S) -> Result<Vec<MemoryRegion>> {
         let mut builder = MemoryRegionVecBuilder::new(Self::BASE_ADDRESS, shared_mem.base_addr());
 
         // PML4, PDPT, PD
@@ -788,12 +1229,37 @@ impl SandboxMemoryLayout {
             ));
         }
 
-        // code
-        let peb_offset = builder.push_page_aligned(
-            self.code_size,
-            MemoryRegionFlags::READ | MemoryRegionFlags::WRITE | MemoryRegionFlags::EXECUTE,
-            Code,
-        );
+        // code, with the pages that hold only code write protected and
+        // the pages that hold only data not executable
+        let (executable_start, executable_end) = self.executable_code;
+        let (protected_start, protected_end) = match self.write_protected_code {
+            (start, end) if start < end => (start, end),
+            _ => (executable_start, executable_start),
+        };
+        let data_flags = MemoryRegionFlags::READ | MemoryRegionFlags::WRITE;
+        let code_flags = data_flags | MemoryRegionFlags::EXECUTE;
+        let mut peb_offset = code_offset;
+        for (start, end, flags) in [
+            (0, executable_start, data_flags),
+            (executable_start, protected_start, code_flags),
+            (
+                protected_start,
+                protected_end,
+                MemoryRegionFlags::READ
+                    | MemoryRegionFlags::EXECUTE
+                    | MemoryRegionFlags::WRITE_PROTECTED_CODE,
+            ),
+            (protected_end, executable_end, code_flags),
+            (
+                executable_end,
+                self.code_size.max(executable_end),
+                data_flags,
+            ),
+        ] {
+            if end > start {
+                peb_offset = builder.push_page_aligned(end - start, flags, Code);
+            }
+        }
 
         let expected_peb_offset = TryInto::<usize>::try_into(self.peb_offset)?;
 
@@ -806,145 +1272,63 @@ impl SandboxMemoryLayout {
         }
 
         // PEB
-        let host_functions_definitions_offset = builder.push_page_aligned(
+        let mut offset = builder.push_page_aligned(
             size_of::<HyperlightPEB>(),
             MemoryRegionFlags::READ | MemoryRegionFlags::WRITE,
             Peb,
         );
 
-        let expected_host_functions_definitions_offset =
-            TryInto::<usize>::try_into(self.host_function_definitions_buffer_offset)?;
-
-        if host_functions_definitions_offset != expected_host_functions_definitions_offset {
-            return Err(new_error!(
-                "Host Function Definitions offset does not match expected Host Function Definitions offset expected:  {}, actual:  {}",
-                expected_host_functions_definitions_offset,
-                host_functions_definitions_offset
-            ));
-        }
-
-        // host function definitions
-        let host_exception_offset = builder.push_page_aligned(
-            self.sandbox_memory_config
-                .get_host_function_definition_size(),
-            MemoryRegionFlags::READ,
-            HostFunctionDefinitions,
-        );
-
-        let expected_host_exception_offset =
-            TryInto::<usize>::try_into(self.host_exception_buffer_offset)?;
-
-        if host_exception_offset != expected_host_exception_offset {
-            return Err(new_error!(
-                "Host Exception offset does not match expected Host Exception offset expected:  {}, actual:  {}",
-                expected_host_exception_offset,
-                host_exception_offset
-            ));
-        }
-
-        // host exception
-        let guest_error_offset = builder.push_page_aligned(
-            self.sandbox_memory_config.get_host_exception_size(),
-            MemoryRegionFlags::READ | MemoryRegionFlags::WRITE,
-            HostExceptionData,
-        );
-
-        let expected_guest_error_offset =
-            TryInto::<usize>::try_into(self.guest_error_buffer_offset)?;
-
-        if guest_error_offset != expected_guest_error_offset {
-            return Err(new_error!(
-                "Guest Error offset does not match expected Guest Error offset expected:  {}, actual:  {}",
-                expected_guest_error_offset,
-                guest_error_offset
-            ));
-        }
-
-        // guest error
-        let input_data_offset = builder.push_page_aligned(
-            self.sandbox_memory_config.get_guest_error_buffer_size(),
-            MemoryRegionFlags::READ | MemoryRegionFlags::WRITE,
-            GuestErrorData,
-        );
-
-        let expected_input_data_offset = TryInto::<usize>::try_into(self.input_data_buffer_offset)?;
-
-        if input_data_offset != expected_input_data_offset {
-            return Err(new_error!(
-                "Input Data offset does not match expected Input Data offset expected:  {}, actual:  {}",
-                expected_input_data_offset,
-                input_data_offset
-            ));
-        }
-
-        // guest input data
-        let output_data_offset = builder.push_page_aligned(
-            self.sandbox_memory_config.get_input_data_size(),
-            MemoryRegionFlags::READ | MemoryRegionFlags::WRITE,
-            InputData,
-        );
-
-        let expected_output_data_offset =
-            TryInto::<usize>::try_into(self.output_data_buffer_offset)?;
-
-        if output_data_offset != expected_output_data_offset {
-            return Err(new_error!(
-                "Output Data offset does not match expected Output Data offset expected:  {}, actual:  {}",
-                expected_output_data_offset,
-                output_data_offset
-            ));
-        }
-
-        // guest output data
-        let guest_panic_context_offset = builder.push_page_aligned(
-            self.sandbox_memory_config.get_output_data_size(),
-            MemoryRegionFlags::READ | MemoryRegionFlags::WRITE,
-            OutputData,
-        );
-
-        let expected_guest_panic_context_offset =
-            TryInto::<usize>::try_into(self.guest_panic_context_buffer_offset)?;
-
-        if guest_panic_context_offset != expected_guest_panic_context_offset {
-            return Err(new_error!(
-                "Guest Panic Context offset does not match expected Guest Panic Context offset expected:  {}, actual:  {}",
-                expected_guest_panic_context_offset,
-                guest_panic_context_offset
-            ));
-        }
-
-        // guest panic context
-        let heap_offset = builder.push_page_aligned(
-            self.sandbox_memory_config
-                .get_guest_panic_context_buffer_size(),
-            MemoryRegionFlags::READ | MemoryRegionFlags::WRITE,
-            PanicContext,
-        );
-
-        let expected_heap_offset = TryInto::<usize>::try_into(self.guest_heap_buffer_offset)?;
-
-        if heap_offset != expected_heap_offset {
-            return Err(new_error!(
-                "Guest Heap offset does not match expected Guest Heap offset expected:  {}, actual:  {}",
-                expected_heap_offset,
-                heap_offset
-            ));
+        // the regions between the PEB and the guard page, in the configured order
+        for region in self.sandbox_memory_config.get_region_order() {
+            let expected_offset = self.get_region_offset(region);
+            if offset != expected_offset {
+                return Err(new_error!(
+                    "{:?} offset does not match expected {:?} offset expected:  {}, actual:  {}",
+                    region,
+                    region,
+                    expected_offset,
+                    offset
+                ));
+            }
+
+            let size = Self::get_region_size(self.sandbox_memory_config, self.heap_size, region);
+            let region_type = match region {
+                LayoutRegion::HostFunctionDefinitions => HostFunctionDefinitions,
+                LayoutRegion::HostExceptionData => HostExceptionData,
+                LayoutRegion::GuestErrorData => GuestErrorData,
+                LayoutRegion::InputData => InputData,
+                LayoutRegion::OutputData => OutputData,
+                LayoutRegion::ResultBuffer => ResultBuffer,
+                LayoutRegion::ReadOnlyData => ReadOnlyData,
+                LayoutRegion::GuestLogRing => GuestLogRing,
+                LayoutRegion::Secrets => Secrets,
+                LayoutRegion::JitScratch => JitScratch,
+                LayoutRegion::PanicContext => PanicContext,
+                LayoutRegion::Heap => Heap,
+            };
+            let flags = self
+                .sandbox_memory_config
+                .get_region_permissions(region)
+                .region_flags();
+
+            // the result buffer, the read-only data region, the guest log
+            // ring, the secrets region and the JIT scratch region are only
+            // mapped if they were configured
+            let optional = matches!(
+                region,
+                LayoutRegion::ResultBuffer
+                    | LayoutRegion::ReadOnlyData
+                    | LayoutRegion::GuestLogRing
+                    | LayoutRegion::Secrets
+                    | LayoutRegion::JitScratch
+            );
+            if optional && size == 0 {
+                continue;
+            }
+            offset = builder.push_page_aligned(size, flags, region_type);
         }
 
-        // heap
-        #[cfg(feature = "executable_heap")]
-        let guard_page_offset = builder.push_page_aligned(
-            self.heap_size,
-            MemoryRegionFlags::READ | MemoryRegionFlags::WRITE | MemoryRegionFlags::EXECUTE,
-            Heap,
-        );
-        #[cfg(not(feature = "executable_heap"))]
-        let guard_page_offset = builder.push_page_aligned(
-            self.heap_size,
-            MemoryRegionFlags::READ | MemoryRegionFlags::WRITE,
-            Heap,
-        );
-
+        let guard_page_offset = offset;
         let expected_guard_page_offset = TryInto::<usize>::try_into(self.guard_page_offset)?;
 
         if guard_page_offset != expected_guard_page_offset {
@@ -1042,12 +1426,36 @@ impl SandboxMemoryLayout {
             ));
         }
 
-        let final_offset = builder.push_page_aligned(
+        let shadow_stack_offset = builder.push_page_aligned(
             PAGE_SIZE_USIZE,
             MemoryRegionFlags::READ | MemoryRegionFlags::WRITE,
             BootStack,
         );
 
+        let expected_shadow_stack_offset =
+            TryInto::<usize>::try_into(self.shadow_stack_buffer_offset)?;
+
+        if shadow_stack_offset != expected_shadow_stack_offset {
+            return Err(new_error!(
+                "Shadow Stack offset does not match expected Shadow Stack offset expected:  {}, actual:  {}",
+                expected_shadow_stack_offset,
+                shadow_stack_offset
+            ));
+        }
+
+        // The processor writes return addresses to the shadow stack, so
+        // the hypervisor maps it writable; the guest's page tables are what
+        // keep the guest from writing to it
+        let final_offset = if self.shadow_stack_size > 0 {
+            builder.push_page_aligned(
+                self.shadow_stack_size,
+                MemoryRegionFlags::READ | MemoryRegionFlags::WRITE,
+                ShadowStack,
+            )
+        } else {
+            shadow_stack_offset
+        };
+
         let expected_final_offset = TryInto::<usize>::try_into(self.get_memory_size()?)?;
 
         if final_offset != expected_final_offset {
@@ -1169,6 +1577,66 @@ impl SandboxMemoryLayout {
         let addr = get_address!(output_data_buffer);
         shared_mem.write_u64(self.get_output_data_pointer_offset(), addr)?;
 
+        // Set up the result buffer
+        let result_buffer_size = self.sandbox_memory_config.get_result_buffer_size();
+        shared_mem.write_u64(
+            self.get_result_buffer_size_offset(),
+            result_buffer_size.try_into()?,
+        )?;
+        let addr = match result_buffer_size {
+            0 => 0,
+            _ => get_address!(result_buffer),
+        };
+        shared_mem.write_u64(self.get_result_buffer_pointer_offset(), addr)?;
+
+        // Set up the read-only data region, which is empty until the host
+        // publishes data into it
+        let read_only_data_size = self.sandbox_memory_config.get_read_only_data_size();
+        shared_mem.write_u64(
+            self.get_read_only_data_size_offset(),
+            read_only_data_size.try_into()?,
+        )?;
+        shared_mem.write_u64(self.get_read_only_data_length_offset(), 0)?;
+        let addr = match read_only_data_size {
+            0 => 0,
+            _ => get_address!(read_only_data),
+        };
+        shared_mem.write_u64(self.get_read_only_data_pointer_offset(), addr)?;
+
+        // Set up the guest log ring, which is empty as the memory is zeroed
+        let guest_log_ring_size = self.sandbox_memory_config.get_guest_log_ring_size();
+        shared_mem.write_u64(
+            self.get_guest_log_ring_size_offset(),
+            guest_log_ring_size.try_into()?,
+        )?;
+        let addr = match guest_log_ring_size {
+            0 => 0,
+            _ => get_address!(guest_log_ring),
+        };
+        shared_mem.write_u64(self.get_guest_log_ring_pointer_offset(), addr)?;
+
+        // Set up the secrets region, which has no secrets as the memory is
+        // zeroed until the host adds them
+        let secrets_size = self.sandbox_memory_config.get_secrets_size();
+        shared_mem.write_u64(self.get_secrets_size_offset(), secrets_size.try_into()?)?;
+        let addr = match secrets_size {
+            0 => 0,
+            _ => get_address!(secrets),
+        };
+        shared_mem.write_u64(self.get_secrets_pointer_offset(), addr)?;
+
+        // Set up the JIT scratch region
+        let jit_scratch_size = self.sandbox_memory_config.get_jit_scratch_size();
+        shared_mem.write_u64(
+            self.get_jit_scratch_size_offset(),
+            jit_scratch_size.try_into()?,
+        )?;
+        let addr = match jit_scratch_size {
+            0 => 0,
+            _ => get_address!(jit_scratch),
+        };
+        shared_mem.write_u64(self.get_jit_scratch_pointer_offset(), addr)?;
+
         // Set up the guest panic context buffer
         let addr = get_address!(guest_panic_context_buffer);
         shared_mem.write_u64(
@@ -1192,10 +1660,11 @@ impl SandboxMemoryLayout {
         // address at the bottom of the guest memory.
         // we then subtract the size of the stack, the size of the kernel stack,
         // the size of the boot stack, the size of the user stack guard page and the size of the kernel stack guard page
-        // which are all 4K
+        // which are all 4K, and the size of the shadow stack
 
         let bottom = guest_offset + size;
         let min_user_stack_address = bottom
+            - self.shadow_stack_size
             - self.stack_size
             - self.kernel_stack_size_rounded
             - PAGE_SIZE_USIZE
@@ -1235,20 +1704,98 @@ impl SandboxMemoryLayout {
 
         shared_mem.write_u64(self.get_boot_stack_pointer_offset(), start_of_boot_stack)?;
 
-        // End of setting up the PEB
+        // Set up the shadow stack token
 
-        // Initialize the stack pointers of input data and output data
-        // to point to the ninth (index 8) byte, which is the first free address
-        // of the each respective stack. The first 8 bytes are the stack pointer itself.
+        // The guest switches to the shadow stack with a supervisor shadow stack
+        // token at its top, which holds its own address. The guest can't run with
+        // a shadow stack in process, so it is told there is none.
+
+        let shadow_stack_token: u64 = if self.shadow_stack_size > 0 && !run_inprocess {
+            let token_offset = self.shadow_stack_buffer_offset + self.shadow_stack_size - 8;
+            let token_address =
+                get_address!(shadow_stack_buffer) + (self.shadow_stack_size - 8) as u64;
+            shared_mem.write_u64(token_offset, token_address)?;
+            token_address
+        } else {
+            0
+        };
+        shared_mem.write_u64(
+            self.get_shadow_stack_token_pointer_offset(),
+            shadow_stack_token,
+        )?;
+
+        // Keep the max log level passed to the guest entrypoint until the
+        // host asks for a different one
+        shared_mem.write_u64(self.get_guest_max_log_level_offset(), u64::MAX)?;
+
+        // Set up the payload limits the guest enforces on what it sends to the host
+        let payload_limits = self.sandbox_memory_config.get_payload_limits();
         shared_mem.write_u64(
-            self.input_data_buffer_offset,
-            Self::STACK_POINTER_SIZE_BYTES,
+            self.get_max_payload_size_offset(),
+            payload_limits.max_payload_size,
         )?;
         shared_mem.write_u64(
-            self.output_data_buffer_offset,
-            Self::STACK_POINTER_SIZE_BYTES,
+            self.get_max_parameter_size_offset(),
+            payload_limits.max_parameter_size,
+        )?;
+
+        // Tell the guest how to signal the host
+        shared_mem.write_u64(
+            self.peb_host_call_transport_offset,
+            self.sandbox_memory_config.get_host_call_transport() as u64,
+        )?;
+
+        // Tell the guest where its random numbers come from
+        shared_mem.write_u64(
+            self.peb_entropy_mode_offset,
+            self.sandbox_memory_config.get_entropy_policy().mode() as u64,
         )?;
 
+        // Tell the guest which port each of the host's channels is on
+        let port_map = self.sandbox_memory_config.get_port_map();
+        for (offset, port) in [
+            (offset_of!(PortMap, log), port_map.log),
+            (offset_of!(PortMap, call), port_map.call),
+            (offset_of!(PortMap, abort), port_map.abort),
+            (offset_of!(PortMap, debug), port_map.debug),
+        ] {
+            shared_mem.write_u16(self.peb_port_map_offset + offset, port)?;
+        }
+
+        // Ask the guest to compress large payloads, which it accepts when
+        // it is initialised
+        for (offset, value) in [
+            (
+                offset_of!(PayloadCompressionData, requested),
+                self.sandbox_memory_config.get_payload_compression() as u64,
+            ),
+            (
+                offset_of!(PayloadCompressionData, threshold),
+                self.sandbox_memory_config
+                    .get_payload_compression_threshold() as u64,
+            ),
+            (offset_of!(PayloadCompressionData, accepted), 0),
+        ] {
+            shared_mem.write_u64(self.peb_payload_compression_offset + offset, value)?;
+        }
+
+        // End of setting up the PEB
+
+        // Initialize the stack pointers of input data and output data
+        // to point to the ninth (index 8) byte, which is the first free address
+        // of the each respective stack. The first 8 bytes are the stack pointer itself.
+        // Each slot of the buffers is a stack of its own.
+        for slot in 0..self.sandbox_memory_config.get_io_buffer_slots() {
+            shared_mem.write_u64(
+                self.get_input_data_slot_offset(slot),
+                Self::STACK_POINTER_SIZE_BYTES,
+            )?;
+            shared_mem.write_u64(
+                self.get_output_data_slot_offset(slot),
+                Self::STACK_POINTER_SIZE_BYTES,
+            )?;
+        }
+
         Ok(())
     }
 }
@@ -1297,9 +1844,25 @@ mod tests {
 
         expected_size += round_up_to(cfg.get_guest_error_buffer_size(), PAGE_SIZE_USIZE);
 
-        expected_size += round_up_to(cfg.get_input_data_size(), PAGE_SIZE_USIZE);
+        expected_size += round_up_to(
+            cfg.get_input_data_size() * cfg.get_io_buffer_slots(),
+            PAGE_SIZE_USIZE,
+        );
 
-        expected_size += round_up_to(cfg.get_output_data_size(), PAGE_SIZE_USIZE);
+        expected_size += round_up_to(
+            cfg.get_output_data_size() * cfg.get_io_buffer_slots(),
+            PAGE_SIZE_USIZE,
+        );
+
+        expected_size += round_up_to(cfg.get_result_buffer_size(), PAGE_SIZE_USIZE);
+
+        expected_size += round_up_to(cfg.get_read_only_data_size(), PAGE_SIZE_USIZE);
+
+        expected_size += round_up_to(cfg.get_guest_log_ring_size(), PAGE_SIZE_USIZE);
+
+        expected_size += round_up_to(cfg.get_secrets_size(), PAGE_SIZE_USIZE);
+
+        expected_size += round_up_to(cfg.get_jit_scratch_size(), PAGE_SIZE_USIZE);
 
         expected_size += round_up_to(cfg.get_guest_panic_context_buffer_size(), PAGE_SIZE_USIZE);
 
@@ -1317,6 +1880,8 @@ mod tests {
 
         expected_size += PAGE_SIZE_USIZE; // boot stack
 
+        expected_size += round_up_to(cfg.get_shadow_stack_size(), PAGE_SIZE_USIZE);
+
         expected_size
     }
 
@@ -1329,4 +1894,271 @@ mod tests {
             get_expected_memory_size(&sbox_mem_layout)
         );
     }
+
+    #[test]
+    fn test_result_buffer_is_only_mapped_if_configured() {
+        let mut sbox_cfg = SandboxConfiguration::default();
+        let without = SandboxMemoryLayout::new(sbox_cfg, 4096, 2048, 4096).unwrap();
+        sbox_cfg.set_result_buffer_size(0x10001);
+        let with = SandboxMemoryLayout::new(sbox_cfg, 4096, 2048, 4096).unwrap();
+        assert_eq!(
+            with.get_memory_size().unwrap(),
+            get_expected_memory_size(&with)
+        );
+        assert_eq!(
+            with.result_buffer_offset + 0x11000,
+            with.guest_panic_context_buffer_offset
+        );
+        assert_eq!(
+            without.result_buffer_offset,
+            without.guest_panic_context_buffer_offset
+        );
+    }
+
+    #[test]
+    fn test_read_only_data_is_only_mapped_if_configured() {
+        let mut sbox_cfg = SandboxConfiguration::default();
+        let without = SandboxMemoryLayout::new(sbox_cfg, 4096, 2048, 4096).unwrap();
+        sbox_cfg.set_read_only_data_size(0x2800);
+        let with = SandboxMemoryLayout::new(sbox_cfg, 4096, 2048, 4096).unwrap();
+        assert_eq!(
+            with.get_memory_size().unwrap(),
+            get_expected_memory_size(&with)
+        );
+        assert_eq!(
+            with.read_only_data_offset + 0x3000,
+            with.guest_panic_context_buffer_offset
+        );
+        assert_eq!(
+            without.read_only_data_offset,
+            without.guest_panic_context_buffer_offset
+        );
+    }
+
+    #[test]
+    fn test_jit_scratch_is_only_mapped_if_configured() {
+        use crate::mem::shared_mem::ExclusiveSharedMemory;
+
+        let mut sbox_cfg = SandboxConfiguration::default();
+        let without = SandboxMemoryLayout::new(sbox_cfg, 4096, 2048, 4096).unwrap();
+        sbox_cfg.set_jit_scratch_size(0x1800);
+        let with = SandboxMemoryLayout::new(sbox_cfg, 4096, 2048, 4096).unwrap();
+        let size = with.get_memory_size().unwrap();
+        assert_eq!(size, get_expected_memory_size(&with));
+        assert_eq!(size, without.get_memory_size().unwrap() + 0x2000);
+
+        let shared_mem = ExclusiveSharedMemory::new(size).unwrap();
+        let regions = with.get_memory_regions(&shared_mem).unwrap();
+        let jit_scratch = regions
+            .iter()
+            .find(|r| r.region_type == JitScratch)
+            .unwrap();
+        assert_eq!(0x2000, jit_scratch.guest_region.len());
+        assert_eq!(
+            MemoryRegionFlags::READ | MemoryRegionFlags::WRITE | MemoryRegionFlags::EXECUTE,
+            jit_scratch.flags
+        );
+        assert!(without
+            .get_memory_regions(&shared_mem)
+            .unwrap()
+            .iter()
+            .all(|r| r.region_type != JitScratch));
+    }
+
+    #[test]
+    fn test_shadow_stack_is_only_mapped_if_configured() {
+        use crate::mem::shared_mem::ExclusiveSharedMemory;
+
+        let mut sbox_cfg = SandboxConfiguration::default();
+        let without = SandboxMemoryLayout::new(sbox_cfg, 4096, 2048, 4096).unwrap();
+        sbox_cfg.set_shadow_stack_size(0x1800);
+        let with = SandboxMemoryLayout::new(sbox_cfg, 4096, 2048, 4096).unwrap();
+        let size = with.get_memory_size().unwrap();
+        assert_eq!(size, get_expected_memory_size(&with));
+        assert_eq!(size, without.get_memory_size().unwrap() + 0x2000);
+
+        let mut shared_mem = ExclusiveSharedMemory::new(size).unwrap();
+        let regions = with.get_memory_regions(&shared_mem).unwrap();
+        let shadow_stack = regions.last().unwrap();
+        assert_eq!(ShadowStack, shadow_stack.region_type);
+        assert_eq!(0x2000, shadow_stack.guest_region.len());
+        assert!(without
+            .get_memory_regions(&shared_mem)
+            .unwrap()
+            .iter()
+            .all(|r| r.region_type != ShadowStack));
+
+        // the token at the top of the shadow stack holds its own address,
+        // which the guest finds in the PEB
+        let read_u64 = |shared_mem: &ExclusiveSharedMemory, offset: usize| {
+            u64::from_le_bytes(
+                shared_mem.as_slice()[offset..offset + 8]
+                    .try_into()
+                    .unwrap(),
+            )
+        };
+        let token_address = (SandboxMemoryLayout::BASE_ADDRESS + size - 8) as u64;
+        with.write(
+            &mut shared_mem,
+            SandboxMemoryLayout::BASE_ADDRESS,
+            size,
+            false,
+        )
+        .unwrap();
+        assert_eq!(token_address, read_u64(&shared_mem, size - 8));
+        assert_eq!(
+            token_address,
+            read_u64(&shared_mem, with.get_shadow_stack_token_pointer_offset())
+        );
+
+        // the guest can't use a shadow stack in process
+        with.write(
+            &mut shared_mem,
+            SandboxMemoryLayout::BASE_ADDRESS,
+            size,
+            true,
+        )
+        .unwrap();
+        assert_eq!(
+            0,
+            read_u64(&shared_mem, with.get_shadow_stack_token_pointer_offset())
+        );
+    }
+
+    #[test]
+    fn test_write_protected_code() {
+        use crate::mem::shared_mem::ExclusiveSharedMemory;
+
+        let sbox_cfg = SandboxConfiguration::default();
+        let mut layout = SandboxMemoryLayout::new(sbox_cfg, 0x4800, 2048, 4096).unwrap();
+        let shared_mem = ExclusiveSharedMemory::new(layout.get_memory_size().unwrap()).unwrap();
+        let code_regions = |layout: &SandboxMemoryLayout| {
+            layout
+                .get_memory_regions(&shared_mem)
+                .unwrap()
+                .into_iter()
+                .filter(|r| r.region_type == Code)
+                .map(|r| (r.guest_region.len(), r.flags))
+                .collect::<Vec<_>>()
+        };
+        let rwx = MemoryRegionFlags::READ | MemoryRegionFlags::WRITE | MemoryRegionFlags::EXECUTE;
+        assert_eq!(vec![(0x5000, rwx)], code_regions(&layout));
+
+        assert!(layout.set_write_protected_code(0x800, 0x2000).is_err());
+        assert!(layout.set_write_protected_code(0x1000, 0x6000).is_err());
+        layout.set_write_protected_code(0x1000, 0x3000).unwrap();
+        let protected = MemoryRegionFlags::READ
+            | MemoryRegionFlags::EXECUTE
+            | MemoryRegionFlags::WRITE_PROTECTED_CODE;
+        assert_eq!(
+            vec![(0x1000, rwx), (0x2000, protected), (0x2000, rwx)],
+            code_regions(&layout)
+        );
+
+        // the code can be protected up to its last, partial, page
+        layout.set_write_protected_code(0, 0x5000).unwrap();
+        assert_eq!(vec![(0x5000, protected)], code_regions(&layout));
+
+        // the pages outside the executable code hold data the guest can't
+        // run, and only executable code can be write protected
+        let rw = MemoryRegionFlags::READ | MemoryRegionFlags::WRITE;
+        layout.set_write_protected_code(0, 0).unwrap();
+        layout.set_executable_code(0x1000, 0x4000).unwrap();
+        assert_eq!(
+            vec![(0x1000, rw), (0x3000, rwx), (0x1000, rw)],
+            code_regions(&layout)
+        );
+        assert!(layout.set_write_protected_code(0, 0x2000).is_err());
+        layout.set_write_protected_code(0x2000, 0x3000).unwrap();
+        assert_eq!(
+            vec![
+                (0x1000, rw),
+                (0x1000, rwx),
+                (0x1000, protected),
+                (0x1000, rwx),
+                (0x1000, rw)
+            ],
+            code_regions(&layout)
+        );
+        assert!(layout.set_executable_code(0x1000, 0x6000).is_err());
+    }
+
+    #[test]
+    fn test_memory_larger_than_4gib() {
+        let sbox_cfg = SandboxConfiguration::default();
+        let heap_size = 16 * 1024 * 1024 * 1024;
+        let layout = SandboxMemoryLayout::new(sbox_cfg, 4096, 2048, heap_size).unwrap();
+        let mem_size = layout.get_memory_size().unwrap();
+        assert_eq!(mem_size, get_expected_memory_size(&layout));
+
+        // every 2MB of memory, and the 2MB below it, has a page table
+        let page_table_count = layout.get_page_table_count();
+        assert_eq!(
+            mem_size.div_ceil(AMOUNT_OF_MEMORY_PER_PT) + 1,
+            page_table_count
+        );
+        assert_eq!(
+            page_table_count.div_ceil(512),
+            layout.get_page_directory_count()
+        );
+        assert!(layout.get_page_directory_count() > 16);
+        assert_eq!(
+            SandboxMemoryLayout::PD_OFFSET + layout.get_page_directory_count() * PAGE_SIZE_USIZE,
+            layout.get_pt_offset()
+        );
+        assert_eq!(
+            layout.get_pt_offset() + page_table_count * PAGE_SIZE_USIZE,
+            layout.guest_code_offset
+        );
+
+        // the default layout still needs a single page directory
+        let layout = SandboxMemoryLayout::new(sbox_cfg, 4096, 2048, 4096).unwrap();
+        assert_eq!(1, layout.get_page_directory_count());
+    }
+
+    #[test]
+    fn test_memory_too_large() {
+        let sbox_cfg = SandboxConfiguration::default();
+        let layout = SandboxMemoryLayout::new(sbox_cfg, 4096, 2048, 512 * 0x40000000).unwrap();
+        assert!(layout.get_memory_size().is_err());
+    }
+
+    #[test]
+    fn test_region_order() {
+        let mut order = LayoutRegion::DEFAULT_ORDER;
+        order.reverse();
+        let mut sbox_cfg = SandboxConfiguration::default();
+        sbox_cfg.set_memory_layout(
+            crate::sandbox::memory_layout::MemoryLayoutBuilder::new()
+                .region_order(&order)
+                .build()
+                .unwrap(),
+        );
+        let layout = SandboxMemoryLayout::new(sbox_cfg, 4096, 2048, 0x10000).unwrap();
+        assert_eq!(
+            layout.get_memory_size().unwrap(),
+            get_expected_memory_size(&layout)
+        );
+
+        // the heap comes straight after the PEB, and each region is above
+        // the one before it in the order
+        assert_eq!(
+            round_up_to(
+                layout.peb_offset + size_of::<HyperlightPEB>(),
+                PAGE_SIZE_USIZE
+            ),
+            layout.guest_heap_buffer_offset
+        );
+        for pair in order.windows(2) {
+            assert!(layout.get_region_offset(pair[0]) <= layout.get_region_offset(pair[1]));
+        }
+        assert_eq!(
+            layout.host_function_definitions_buffer_offset
+                + round_up_to(
+                    sbox_cfg.get_host_function_definition_size(),
+                    PAGE_SIZE_USIZE
+                ),
+            layout.guard_page_offset
+        );
+    }
 }
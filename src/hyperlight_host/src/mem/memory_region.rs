@@ -57,6 +57,8 @@ bitflags! {
         const EXECUTE = 4;
         /// identifier that this is a stack guard page
         const STACK_GUARD = 8;
+        /// identifier that this is write protected guest code
+        const WRITE_PROTECTED_CODE = 16;
     }
 }
 
@@ -141,6 +143,16 @@ pub enum MemoryRegionType {
     InputData,
     /// The region contains the Output Data
     OutputData,
+    /// The region contains the Result Buffer
+    ResultBuffer,
+    /// The region contains the Read-Only Data
+    ReadOnlyData,
+    /// The region contains the Guest Log Ring
+    GuestLogRing,
+    /// The region contains the Secrets
+    Secrets,
+    /// The region contains the JIT Scratch region
+    JitScratch,
     /// The region contains the Panic Context
     PanicContext,
     /// The region contains the Heap
@@ -153,6 +165,65 @@ pub enum MemoryRegionType {
     KernelStack,
     /// The region contains the Boot Stack
     BootStack,
+    /// The region contains the Shadow Stack
+    ShadowStack,
+}
+
+impl MemoryRegionType {
+    /// A short name for the region, e.g. to label it in a memory dump
+    pub fn name(self) -> &'static str {
+        match self {
+            MemoryRegionType::PageTables => "page_tables",
+            MemoryRegionType::Code => "code",
+            MemoryRegionType::Peb => "peb",
+            MemoryRegionType::HostFunctionDefinitions => "host_function_definitions",
+            MemoryRegionType::HostExceptionData => "host_exception_data",
+            MemoryRegionType::GuestErrorData => "guest_error_data",
+            MemoryRegionType::InputData => "input_data",
+            MemoryRegionType::OutputData => "output_data",
+            MemoryRegionType::ResultBuffer => "result_buffer",
+            MemoryRegionType::ReadOnlyData => "read_only_data",
+            MemoryRegionType::GuestLogRing => "guest_log_ring",
+            MemoryRegionType::Secrets => "secrets",
+            MemoryRegionType::JitScratch => "jit_scratch",
+            MemoryRegionType::PanicContext => "panic_context",
+            MemoryRegionType::Heap => "heap",
+            MemoryRegionType::GuardPage => "guard_page",
+            MemoryRegionType::Stack => "stack",
+            MemoryRegionType::KernelStack => "kernel_stack",
+            MemoryRegionType::BootStack => "boot_stack",
+            MemoryRegionType::ShadowStack => "shadow_stack",
+        }
+    }
+}
+
+/// A region of a sandbox's guest memory, as returned by
+/// `MultiUseSandbox::memory_layout`, for tools such as debuggers and dump
+/// analyzers that need to interpret the guest memory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuestMemoryRegion {
+    /// The name of the region, see `MemoryRegionType::name`
+    pub name: &'static str,
+    /// What the region contains
+    pub region_type: MemoryRegionType,
+    /// The guest physical address the region starts at
+    pub guest_address: u64,
+    /// The size of the region in bytes
+    pub size: u64,
+    /// The guest's permissions to the region
+    pub permissions: MemoryRegionFlags,
+}
+
+impl From<&MemoryRegion> for GuestMemoryRegion {
+    fn from(region: &MemoryRegion) -> Self {
+        Self {
+            name: region.region_type.name(),
+            region_type: region.region_type,
+            guest_address: region.guest_region.start as u64,
+            size: region.guest_region.len() as u64,
+            permissions: region.flags,
+        }
+    }
 }
 
 /// represents a single memory region inside the guest. All memory within a region has
@@ -169,6 +240,29 @@ pub struct MemoryRegion {
     pub(crate) region_type: MemoryRegionType,
 }
 
+impl MemoryRegion {
+    /// The range of guest physical addresses the region is mapped at
+    pub fn guest_region(&self) -> Range<usize> {
+        self.guest_region.clone()
+    }
+
+    /// The range of host virtual addresses of the memory backing the
+    /// region
+    pub fn host_region(&self) -> Range<usize> {
+        self.host_region.clone()
+    }
+
+    /// The guest's permissions to the region
+    pub fn flags(&self) -> MemoryRegionFlags {
+        self.flags
+    }
+
+    /// What the region contains
+    pub fn region_type(&self) -> MemoryRegionType {
+        self.region_type
+    }
+}
+
 pub(crate) struct MemoryRegionVecBuilder {
     guest_base_phys_addr: usize,
     host_base_virt_addr: usize,
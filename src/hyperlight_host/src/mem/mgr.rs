@@ -16,33 +16,53 @@ limitations under the License.
 
 use core::mem::size_of;
 use std::cmp::Ordering;
+use std::ops::Range;
+use std::path::Path;
 use std::str::from_utf8;
 use std::sync::{Arc, Mutex};
 
 use hyperlight_common::flatbuffer_wrappers::function_call::{
     validate_guest_function_call_buffer, FunctionCall,
 };
-use hyperlight_common::flatbuffer_wrappers::function_types::ReturnValue;
+use hyperlight_common::flatbuffer_wrappers::function_types::{vec_bytes_return_value, ReturnValue};
 use hyperlight_common::flatbuffer_wrappers::guest_error::{ErrorCode, GuestError};
 use hyperlight_common::flatbuffer_wrappers::guest_log_data::GuestLogData;
 use hyperlight_common::flatbuffer_wrappers::host_function_details::HostFunctionDetails;
+use hyperlight_common::flatbuffer_wrappers::payload_limits::PayloadLimits;
+use hyperlight_common::log_ring::LogRing;
+use hyperlight_common::mem::PAGE_SIZE_USIZE;
+use hyperlight_common::payload_compression::{
+    self, max_uncompressed_size, PayloadCompression, PayloadCompressionData,
+    COMPRESSED_PAYLOAD_MAGIC,
+};
+use hyperlight_common::secrets::Secrets;
+use hyperlight_common::transport::{HostCallTransport, MMIO_DOORBELL_ADDRESS};
+use log::LevelFilter;
 use serde_json::from_str;
 use tracing::{instrument, Span};
 
 use super::exe::ExeInfo;
+use super::hibernation::HibernatedMemory;
 use super::layout::SandboxMemoryLayout;
 #[cfg(target_os = "windows")]
 use super::loaded_lib::LoadedLib;
-use super::memory_region::{MemoryRegion, MemoryRegionType};
+use super::memory_region::{MemoryRegion, MemoryRegionFlags, MemoryRegionType};
 use super::ptr::{GuestPtr, RawPtr};
 use super::ptr_offset::Offset;
 use super::shared_mem::{ExclusiveSharedMemory, GuestSharedMemory, HostSharedMemory, SharedMemory};
 use super::shared_mem_snapshot::SharedMemorySnapshot;
+use super::snapshot_encryption::SnapshotKey;
 use crate::error::HyperlightError::{
     ExceptionDataLengthIncorrect, ExceptionMessageTooBig, JsonConversionFailure, NoMemorySnapshot,
     UTF8SliceConversionFailure,
 };
 use crate::error::HyperlightHostError;
+use crate::sandbox::config::MemoryPopulation;
+use crate::sandbox::guest_log::GuestLogForwarder;
+#[cfg(kvm)]
+use crate::sandbox::hypervisor::{get_available_hypervisor, HypervisorType};
+use crate::sandbox::memory_layout::{LayoutRegion, RegionPermissions};
+use crate::sandbox::outb::forward_guest_log;
 use crate::sandbox::SandboxConfiguration;
 use crate::{log_then_return, new_error, HyperlightError, Result};
 
@@ -55,8 +75,35 @@ use crate::{log_then_return, new_error, HyperlightError, Result};
 const PAGE_PRESENT: u64 = 1; // Page is Present
 const PAGE_RW: u64 = 1 << 1; // Page is Read/Write (if not set page is read only so long as the WP bit in CR0 is set to 1 - which it is in Hyperlight)
 const PAGE_USER: u64 = 1 << 2; // User/Supervisor (if this bit is set then the page is accessible by user mode code)
+const PAGE_DIRTY: u64 = 1 << 6; // Dirty (a present page that is dirty but not Read/Write is a shadow stack page when CET is enabled)
 const PAGE_NX: u64 = 1 << 63; // Execute Disable (if this bit is set then data in the page cannot be executed)
 
+/// The page table entry flags for a page of a region the guest has
+/// `permissions` to
+fn region_page_flags(permissions: RegionPermissions) -> u64 {
+    let mut flags = PAGE_PRESENT;
+    if permissions.writable() {
+        flags |= PAGE_RW;
+    }
+    if !permissions.executable() {
+        flags |= PAGE_NX;
+    }
+    flags
+}
+
+/// The page table entry flags of a page of the guest binary mapped with
+/// `flags`
+fn code_page_flags(flags: MemoryRegionFlags) -> u64 {
+    let mut page_flags = PAGE_PRESENT;
+    if flags.contains(MemoryRegionFlags::WRITE) {
+        page_flags |= PAGE_RW;
+    }
+    if !flags.contains(MemoryRegionFlags::EXECUTE) {
+        page_flags |= PAGE_NX;
+    }
+    page_flags
+}
+
 // The amount of memory that can be mapped per page table
 pub(super) const AMOUNT_OF_MEMORY_PER_PT: usize = 0x200000;
 /// Read/write permissions flag for the 64-bit PDE
@@ -64,6 +111,99 @@ pub(super) const AMOUNT_OF_MEMORY_PER_PT: usize = 0x200000;
 /// The size of stack guard cookies
 pub(crate) const STACK_COOKIE_LEN: usize = 16;
 
+/// A copy of a sandbox's memory, taken with
+/// `SandboxMemoryManager::checkpoint`, that the memory can be restored to
+/// without changing the sandbox's stack of snapshots
+pub(crate) struct MemoryCheckpoint(SharedMemorySnapshot);
+
+/// SHA-256 digests of the parts of a sandbox's guest memory that guest
+/// function calls change, as hex strings, returned by
+/// `MultiUseSandbox::guest_memory_digest`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GuestMemoryDigest {
+    /// The digest of the guest heap
+    pub heap: String,
+    /// The digest of the guest's user stack. Unless stacks are scrubbed
+    /// after every call, see `SandboxConfiguration::set_scrub_stacks`, it
+    /// includes what the last call left on the stack, which differs between
+    /// a sandbox running in a virtual machine and one running in-process,
+    /// where the guest runs on the host thread's stack.
+    pub stack: String,
+    /// The digest of the static data of the guest binary that guest
+    /// function calls changed
+    pub data: String,
+}
+
+/// Writes guest function calls to the slots of the input data buffer,
+/// see `SandboxConfiguration::set_io_buffer_slots`. It can be used on
+/// another thread while the guest runs, as long as it only writes to slots
+/// the guest isn't using.
+#[derive(Clone)]
+pub(crate) struct IoSlotWriter {
+    shared_mem: HostSharedMemory,
+    layout: SandboxMemoryLayout,
+}
+
+impl IoSlotWriter {
+    /// Empty the input and output data buffers of `slot`, then write the
+    /// guest function call `buffer` to it
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn write(&mut self, slot: usize, buffer: &[u8]) -> Result<()> {
+        let stack_pointer = SandboxMemoryLayout::STACK_POINTER_SIZE_BYTES;
+        self.shared_mem
+            .write::<u64>(self.layout.get_input_data_slot_offset(slot), stack_pointer)?;
+        self.shared_mem
+            .write::<u64>(self.layout.get_output_data_slot_offset(slot), stack_pointer)?;
+        self.push(slot, buffer)
+    }
+
+    /// Push the guest function call `buffer` to the input data buffer of
+    /// `slot`, compressed if the guest accepted payload compression and it
+    /// is large enough
+    fn push(&mut self, slot: usize, buffer: &[u8]) -> Result<()> {
+        validate_guest_function_call_buffer(buffer).map_err(|e| {
+            new_error!(
+                "Guest function call buffer validation failed: {}",
+                e.to_string()
+            )
+        })?;
+        let cfg = &self.layout.sandbox_memory_config;
+        let input_data_size = cfg.get_input_data_size();
+        let limits = cfg.get_payload_limits();
+        let compressed = match buffer.len() >= cfg.get_payload_compression_threshold() {
+            true => payload_compression::compress(self.negotiated_payload_compression()?, buffer),
+            false => None,
+        };
+        let buffer = match &compressed {
+            Some(compressed) => {
+                limits.check_payload_size(buffer.len(), max_uncompressed_size(&limits))?;
+                compressed.as_slice()
+            }
+            None => buffer,
+        };
+        limits.check_payload_size(buffer.len(), input_data_size)?;
+        self.shared_mem.push_buffer(
+            self.layout.get_input_data_slot_offset(slot),
+            input_data_size,
+            buffer,
+        )
+    }
+
+    /// See `SandboxMemoryManager::negotiated_payload_compression`
+    fn negotiated_payload_compression(&self) -> Result<PayloadCompression> {
+        let cfg = &self.layout.sandbox_memory_config;
+        let accepted = self
+            .shared_mem
+            .read::<u64>(self.layout.get_accepted_payload_compression_offset())?;
+        Ok(PayloadCompressionData {
+            requested: cfg.get_payload_compression() as u64,
+            threshold: cfg.get_payload_compression_threshold() as u64,
+            accepted,
+        }
+        .negotiated())
+    }
+}
+
 /// A struct that is responsible for laying out and managing the memory
 /// for a given `Sandbox`.
 #[derive(Clone)]
@@ -81,6 +221,16 @@ pub(crate) struct SandboxMemoryManager<S> {
     /// A vector of memory snapshots that can be used to save and  restore the state of the memory
     /// This is used by the Rust Sandbox implementation (rather than the mem_snapshot field above which only exists to support current C API)
     snapshots: Arc<Mutex<Vec<SharedMemorySnapshot>>>,
+    /// The max log level the guest should switch to before the next guest
+    /// function call. `None` keeps the level the guest was initialised with.
+    guest_max_log_level: Option<LevelFilter>,
+    /// Identifies this sandbox in forwarded guest log records and rate
+    /// limits them
+    guest_log_forwarder: Arc<GuestLogForwarder>,
+    /// The host's copy of the secrets region, empty if no secret was
+    /// added. The secrets are only in guest memory while the guest runs,
+    /// so that they are never part of a snapshot.
+    secrets: Arc<Mutex<Vec<u8>>>,
     /// This field must be present, even though it's not read,
     /// so that its underlying resources are properly dropped at
     /// the right time.
@@ -109,6 +259,13 @@ where
             load_addr,
             entrypoint_offset,
             snapshots: Arc::new(Mutex::new(Vec::new())),
+            guest_max_log_level: None,
+            guest_log_forwarder: Arc::new(GuestLogForwarder::new(
+                layout
+                    .sandbox_memory_config
+                    .get_max_guest_log_records_per_second(),
+            )),
+            secrets: Arc::new(Mutex::new(Vec::new())),
             #[cfg(target_os = "windows")]
             _lib: lib,
         }
@@ -152,6 +309,28 @@ where
             + self.layout.stack_size as u64
             - 0x28;
 
+        let map_doorbell =
+            self.layout.sandbox_memory_config.get_host_call_transport() == HostCallTransport::Mmio;
+
+        // We only need to create enough PTEs to map the amount of memory we have
+        // We need one PT for every 2MB of memory that is mapped
+        // We can use the memory size to calculate the number of PTs we need
+        // We round up mem_size/2MB and then we need to add 1 as we start our memory mapping at 0x200000
+        let mem_size = usize::try_from(mem_size)?;
+        let num_pages: usize = mem_size.div_ceil(AMOUNT_OF_MEMORY_PER_PT) + 1;
+        if num_pages > self.layout.get_page_table_count() {
+            log_then_return!(
+                "Mapping {} bytes of memory needs {} page tables, but the layout only has {}",
+                mem_size,
+                num_pages,
+                self.layout.get_page_table_count()
+            );
+        }
+        let cfg = self.layout.sandbox_memory_config;
+        let pd_count = self.layout.get_page_directory_count();
+        let pt_offset = self.layout.get_pt_offset();
+        let pt_guest_address = SandboxMemoryLayout::BASE_ADDRESS + pt_offset;
+
         self.shared_mem.with_exclusivity(|shared_mem| {
             // Create PDL4 table with only 1 PML4E
             shared_mem.write_u64(
@@ -159,72 +338,109 @@ where
                 SandboxMemoryLayout::PDPT_GUEST_ADDRESS as u64 | PAGE_PRESENT | PAGE_RW,
             )?;
 
-            // Create PDPT with only 1 PDPTE
-            shared_mem.write_u64(
-                SandboxMemoryLayout::PDPT_OFFSET,
-                SandboxMemoryLayout::PD_GUEST_ADDRESS as u64 | PAGE_PRESENT | PAGE_RW,
-            )?;
-
-            for i in 0..512 {
-                let offset = SandboxMemoryLayout::PD_OFFSET + (i * 8);
-                let val_to_write: u64 = (SandboxMemoryLayout::PT_GUEST_ADDRESS as u64
-                    + (i * 4096) as u64)
+            // Create PDPT with a PDPTE for each PD, each of which maps 1GB
+            for d in 0..pd_count {
+                let offset = SandboxMemoryLayout::PDPT_OFFSET + (d * 8);
+                let val_to_write: u64 = (SandboxMemoryLayout::PD_GUEST_ADDRESS as u64
+                    + (d * 4096) as u64)
                     | PAGE_PRESENT
                     | PAGE_RW;
                 shared_mem.write_u64(offset, val_to_write)?;
             }
 
-            // We only need to create enough PTEs to map the amount of memory we have
-            // We need one PT for every 2MB of memory that is mapped
-            // We can use the memory size to calculate the number of PTs we need
-            // We round up mem_size/2MB and then we need to add 1 as we start our memory mapping at 0x200000
-
-            let mem_size = usize::try_from(mem_size)?;
-
-            let num_pages: usize =
-                ((mem_size + AMOUNT_OF_MEMORY_PER_PT - 1) / AMOUNT_OF_MEMORY_PER_PT) + 1;
+            // The PDs are contiguous, so the PDE for the i-th PT is the
+            // i-th entry from the start of the first PD. The PDEs after the
+            // last PT stay zeroed, and so not present.
+            for i in 0..num_pages {
+                let offset = SandboxMemoryLayout::PD_OFFSET + (i * 8);
+                let val_to_write: u64 =
+                    (pt_guest_address as u64 + (i * 4096) as u64) | PAGE_PRESENT | PAGE_RW;
+                shared_mem.write_u64(offset, val_to_write)?;
+            }
 
             // Create num_pages PT with 512 PTEs
             for p in 0..num_pages {
                 for i in 0..512 {
-                    let offset = SandboxMemoryLayout::PT_OFFSET + (p * 4096) + (i * 8);
+                    let offset = pt_offset + (p * 4096) + (i * 8);
                     // Each PTE maps a 4KB page
                     let val_to_write = if p == 0 {
-                        (p << 21) as u64 | (i << 12) as u64
+                        // The first 2MB is unmapped, apart from the MMIO
+                        // doorbell page if the guest uses it. That page isn't
+                        // backed by guest memory, so writes to it exit to the host.
+                        let addr = (p << 21) as u64 | (i << 12) as u64;
+                        if map_doorbell && addr == MMIO_DOORBELL_ADDRESS {
+                            addr | PAGE_PRESENT | PAGE_RW | PAGE_NX
+                        } else {
+                            addr
+                        }
                     } else {
                         let flags = match Self::get_page_flags(p, i, regions) {
-                            Ok(region_type) => match region_type {
-                                // TODO: We parse and load the exe according to its sections and then
-                                // have the correct flags set rather than just marking the entire binary as executable
-                                MemoryRegionType::Code => PAGE_PRESENT | PAGE_RW | PAGE_USER,
+                            Ok((region_type, region_flags)) => match region_type {
+                                // the guest binary is split into regions by
+                                // what its segments hold: only pages that hold
+                                // code are executable, and pages that hold only
+                                // code can't be written to
+                                MemoryRegionType::Code => code_page_flags(region_flags) | PAGE_USER,
                                 MemoryRegionType::Stack => {
                                     PAGE_PRESENT | PAGE_RW | PAGE_USER | PAGE_NX
                                 }
-                                #[cfg(feature = "executable_heap")]
-                                MemoryRegionType::Heap => PAGE_PRESENT | PAGE_RW | PAGE_USER,
-                                #[cfg(not(feature = "executable_heap"))]
-                                MemoryRegionType::Heap => {
-                                    PAGE_PRESENT | PAGE_RW | PAGE_USER | PAGE_NX
-                                }
                                 // The guard page is marked RW and User so that if it gets written to we can detect it in the host
                                 // If/When we implement an interrupt handler for page faults in the guest then we can remove this access and handle things properly there
                                 MemoryRegionType::GuardPage => {
                                     PAGE_PRESENT | PAGE_RW | PAGE_USER | PAGE_NX
                                 }
-                                MemoryRegionType::InputData => PAGE_PRESENT | PAGE_RW | PAGE_NX,
-                                MemoryRegionType::OutputData => PAGE_PRESENT | PAGE_RW | PAGE_NX,
                                 MemoryRegionType::Peb => PAGE_PRESENT | PAGE_RW | PAGE_NX,
-                                // Host Function Definitions are readonly in the guest
-                                MemoryRegionType::HostFunctionDefinitions => PAGE_PRESENT | PAGE_NX,
-                                MemoryRegionType::PanicContext => PAGE_PRESENT | PAGE_RW | PAGE_NX,
-                                MemoryRegionType::GuestErrorData => {
-                                    PAGE_PRESENT | PAGE_RW | PAGE_NX
-                                }
-                                // Host Exception Data are readonly in the guest
-                                MemoryRegionType::HostExceptionData => PAGE_PRESENT | PAGE_NX,
                                 MemoryRegionType::PageTables => PAGE_PRESENT | PAGE_RW | PAGE_NX,
                                 MemoryRegionType::KernelStack => PAGE_PRESENT | PAGE_RW | PAGE_NX,
                                 MemoryRegionType::BootStack => PAGE_PRESENT | PAGE_RW | PAGE_NX,
+                                // only shadow stack instructions, calls and
+                                // returns can write to a shadow stack page
+                                MemoryRegionType::ShadowStack => {
+                                    PAGE_PRESENT | PAGE_DIRTY | PAGE_NX
+                                }
+                                // the regions between the PEB and the stack
+                                // have the permissions they were configured
+                                // with, see `LayoutRegion::default_permissions`
+                                MemoryRegionType::Heap => {
+                                    region_page_flags(
+                                        cfg.get_region_permissions(LayoutRegion::Heap),
+                                    ) | PAGE_USER
+                                }
+                                MemoryRegionType::HostFunctionDefinitions => {
+                                    region_page_flags(cfg.get_region_permissions(
+                                        LayoutRegion::HostFunctionDefinitions,
+                                    ))
+                                }
+                                MemoryRegionType::HostExceptionData => region_page_flags(
+                                    cfg.get_region_permissions(LayoutRegion::HostExceptionData),
+                                ),
+                                MemoryRegionType::GuestErrorData => region_page_flags(
+                                    cfg.get_region_permissions(LayoutRegion::GuestErrorData),
+                                ),
+                                MemoryRegionType::InputData => region_page_flags(
+                                    cfg.get_region_permissions(LayoutRegion::InputData),
+                                ),
+                                MemoryRegionType::OutputData => region_page_flags(
+                                    cfg.get_region_permissions(LayoutRegion::OutputData),
+                                ),
+                                MemoryRegionType::ResultBuffer => region_page_flags(
+                                    cfg.get_region_permissions(LayoutRegion::ResultBuffer),
+                                ),
+                                MemoryRegionType::ReadOnlyData => region_page_flags(
+                                    cfg.get_region_permissions(LayoutRegion::ReadOnlyData),
+                                ),
+                                MemoryRegionType::GuestLogRing => region_page_flags(
+                                    cfg.get_region_permissions(LayoutRegion::GuestLogRing),
+                                ),
+                                MemoryRegionType::Secrets => region_page_flags(
+                                    cfg.get_region_permissions(LayoutRegion::Secrets),
+                                ),
+                                MemoryRegionType::JitScratch => region_page_flags(
+                                    cfg.get_region_permissions(LayoutRegion::JitScratch),
+                                ),
+                                MemoryRegionType::PanicContext => region_page_flags(
+                                    cfg.get_region_permissions(LayoutRegion::PanicContext),
+                                ),
                             },
                             // If there is an error then the address isn't mapped so mark it as not present
                             Err(_) => 0,
@@ -244,7 +460,7 @@ where
         p: usize,
         i: usize,
         regions: &mut [MemoryRegion],
-    ) -> Result<MemoryRegionType> {
+    ) -> Result<(MemoryRegionType, MemoryRegionFlags)> {
         let addr = (p << 21) + (i << 12);
 
         let idx = regions.binary_search_by(|region| {
@@ -258,7 +474,7 @@ where
         });
 
         match idx {
-            Ok(index) => Ok(regions[index].region_type),
+            Ok(index) => Ok((regions[index].region_type, regions[index].flags)),
             Err(_) => Err(new_error!("Could not find region for address: {}", addr)),
         }
     }
@@ -279,6 +495,10 @@ where
     /// this function will create a memory snapshot and push it onto the stack of snapshots
     /// It should be used when you want to save the state of the memory, for example, when evolving a sandbox to a new state
     pub(crate) fn push_state(&mut self) -> Result<()> {
+        // records left in the guest log ring would be forwarded again every
+        // time the snapshot is restored
+        self.drain_guest_log_ring()?;
+        self.withdraw_secrets()?;
         let snapshot = SharedMemorySnapshot::new(&mut self.shared_mem)?;
         self.snapshots
             .try_lock()
@@ -287,11 +507,54 @@ where
         Ok(())
     }
 
+    /// this function creates a memory snapshot that is not pushed onto the stack of snapshots
+    /// It should be used when the memory needs to be restored to a state other than the one the sandbox is reset
+    /// to, for example to rewind the calls made through a call context
+    pub(crate) fn checkpoint(&mut self) -> Result<MemoryCheckpoint> {
+        // records left in the guest log ring would be forwarded again every
+        // time the checkpoint is restored
+        self.drain_guest_log_ring()?;
+        self.withdraw_secrets()?;
+        Ok(MemoryCheckpoint(SharedMemorySnapshot::new(
+            &mut self.shared_mem,
+        )?))
+    }
+
+    /// this function restores the memory to the state it was in when `checkpoint` was created, without changing
+    /// the stack of snapshots
+    pub(crate) fn restore_checkpoint(&mut self, checkpoint: &mut MemoryCheckpoint) -> Result<()> {
+        // restoring the checkpoint would lose the records in the guest log ring
+        self.drain_guest_log_ring()?;
+        checkpoint.0.restore_from_snapshot(&mut self.shared_mem)
+    }
+
+    /// this function replaces the last snapshot on the stack with a snapshot of the current memory
+    /// It should be used when the guest has changed its state in a way that should be kept when the memory is next
+    /// restored, for example after the guest has trimmed its memory
+    pub(crate) fn replace_last_snapshot(&mut self) -> Result<()> {
+        // records left in the guest log ring would be forwarded again every
+        // time the snapshot is restored
+        self.drain_guest_log_ring()?;
+        self.withdraw_secrets()?;
+        let mut snapshots = self
+            .snapshots
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
+        match snapshots.last_mut() {
+            Some(snapshot) => snapshot.replace_snapshot(&mut self.shared_mem),
+            None => {
+                log_then_return!(NoMemorySnapshot);
+            }
+        }
+    }
+
     /// this function restores a memory snapshot from the last snapshot in the list but does not pop the snapshot
     /// off the stack
     /// It should be used when you want to restore the state of the memory to a previous state but still want to
     /// retain that state, for example after calling a function in the guest
     pub(crate) fn restore_state_from_last_snapshot(&mut self) -> Result<()> {
+        // restoring the snapshot would lose the records in the guest log ring
+        self.drain_guest_log_ring()?;
         let mut snapshots = self
             .snapshots
             .try_lock()
@@ -320,6 +583,21 @@ where
         self.restore_state_from_last_snapshot()
     }
 
+    /// this function pops the last snapshot off the stack without restoring any state
+    /// It should be used when a snapshot that was taken to be able to roll back a change is no longer needed,
+    /// for example after a transactional guest function call has either succeeded or been rolled back.
+    pub(crate) fn pop_state(&mut self) -> Result<()> {
+        let last = self
+            .snapshots
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+            .pop();
+        if last.is_none() {
+            log_then_return!(NoMemorySnapshot);
+        }
+        Ok(())
+    }
+
     /// Sets `addr` to the correct offset in the memory referenced by
     /// `shared_mem` to indicate the address of the outb pointer and context
     /// for calling outb function
@@ -333,6 +611,97 @@ where
             Ok(())
         })?
     }
+
+    /// Get the `GuestLogForwarder` used for guest log records from this sandbox
+    pub(crate) fn guest_log_forwarder(&self) -> &GuestLogForwarder {
+        &self.guest_log_forwarder
+    }
+
+    /// Forward the log records the guest wrote to its log ring buffer, and
+    /// count any it dropped because the ring was full. Does nothing if
+    /// there is no guest log ring.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn drain_guest_log_ring(&mut self) -> Result<()> {
+        let ring_size = self.layout.sandbox_memory_config.get_guest_log_ring_size();
+        if ring_size == 0 {
+            return Ok(());
+        }
+        let offset = self.layout.guest_log_ring_offset;
+        let (records, overflowed) = self.shared_mem.with_exclusivity(|e| {
+            let mut ring = LogRing::new(&mut e.as_mut_slice()[offset..offset + ring_size])
+                .ok_or_else(|| {
+                    new_error!(
+                        "The {} byte guest log ring is too small to hold any records",
+                        ring_size
+                    )
+                })?;
+            let mut records = Vec::new();
+            while let Some(record) = ring.pop() {
+                records.push(record);
+            }
+            Ok::<_, HyperlightError>((records, ring.take_dropped_records()))
+        })??;
+
+        for record in records {
+            let log_data = GuestLogData::try_from(record.as_slice())?;
+            forward_guest_log(&self.guest_log_forwarder, &log_data)?;
+        }
+        if overflowed > 0 {
+            self.guest_log_forwarder.record_overflow(overflowed);
+        }
+        Ok(())
+    }
+
+    /// Copy the secrets the host added into the secrets region, for the
+    /// guest to take while it runs. Does nothing if no secret was added.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn inject_secrets(&mut self) -> Result<()> {
+        let secrets = self
+            .secrets
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
+        if secrets.is_empty() {
+            return Ok(());
+        }
+        let offset = self.layout.secrets_offset;
+        self.shared_mem.with_exclusivity(|e| {
+            e.as_mut_slice()[offset..offset + secrets.len()].copy_from_slice(&secrets)
+        })
+    }
+
+    /// Wipe the host's copy of the secrets the guest took that are wiped
+    /// after they are read, and zero the secrets region, so that no secret
+    /// is left in guest memory once the guest stops. Does nothing if no
+    /// secret was added.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn withdraw_secrets(&mut self) -> Result<()> {
+        let mut secrets = self
+            .secrets
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
+        if secrets.is_empty() {
+            return Ok(());
+        }
+        let offset = self.layout.secrets_offset;
+        self.shared_mem.with_exclusivity(|e| {
+            let region = &mut e.as_mut_slice()[offset..offset + secrets.len()];
+            Secrets::new(&mut secrets).wipe_taken(&Secrets::new(region));
+            region.fill(0);
+        })
+    }
+}
+
+/// The size of the guest memory of a sandbox created for `exe_info` with
+/// `cfg`, without allocating it
+#[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+pub(crate) fn guest_memory_size(cfg: SandboxConfiguration, exe_info: &ExeInfo) -> Result<usize> {
+    SandboxMemoryLayout::new(
+        cfg,
+        exe_info.loaded_size(),
+        usize::try_from(cfg.get_stack_size(exe_info))?,
+        usize::try_from(cfg.get_heap_size(exe_info))?,
+    )?
+    .get_memory_size()
 }
 
 /// Common setup functionality for the
@@ -350,13 +719,24 @@ fn load_guest_binary_common<F>(
 where
     F: FnOnce(&ExclusiveSharedMemory, &SandboxMemoryLayout) -> Result<RawPtr>,
 {
-    let layout = SandboxMemoryLayout::new(
+    let mut layout = SandboxMemoryLayout::new(
         cfg,
         exe_info.loaded_size(),
         usize::try_from(cfg.get_stack_size(exe_info))?,
         usize::try_from(cfg.get_heap_size(exe_info))?,
     )?;
+    if let Some((start, end)) = exe_info.executable_pages(PAGE_SIZE_USIZE) {
+        layout.set_executable_code(start, end)?;
+    }
+    if cfg.get_write_protect_code() {
+        if let Some((start, end)) = exe_info.code_only_pages(PAGE_SIZE_USIZE) {
+            layout.set_write_protected_code(start, end)?;
+        }
+    }
     let mut shared_mem = ExclusiveSharedMemory::new(layout.get_memory_size()?)?;
+    if cfg.get_memory_population() == MemoryPopulation::Prefault {
+        shared_mem.prefault()?;
+    }
 
     let load_addr: RawPtr = load_addr_fn(&shared_mem, &layout)?;
 
@@ -509,6 +889,45 @@ impl SandboxMemoryManager<ExclusiveSharedMemory> {
         Ok(())
     }
 
+    /// Add the secret `value` called `name` to the host's copy of the
+    /// secrets region, for the guest to take with `hl_take_secret`. The
+    /// secrets are copied into guest memory for the guest to take while it
+    /// is initialised, and withdrawn before the memory is snapshotted.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn add_secret(
+        &mut self,
+        name: &str,
+        value: &[u8],
+        wipe_after_read: bool,
+    ) -> Result<()> {
+        let secrets_size = self.layout.sandbox_memory_config.get_secrets_size();
+        if secrets_size == 0 {
+            log_then_return!("The sandbox has no secrets region");
+        }
+        if name.is_empty() {
+            log_then_return!("Secrets must have a name");
+        }
+        let mut buffer = self
+            .secrets
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
+        buffer.resize(secrets_size, 0);
+        let mut secrets = Secrets::new(&mut buffer);
+        if secrets.contains(name) {
+            log_then_return!("There is already a secret called {}", name);
+        }
+        // the value is left out of the error so that it isn't logged
+        if !secrets.insert(name, value, wipe_after_read) {
+            log_then_return!(
+                "The secret called {} does not fit in the {} byte secrets region",
+                name,
+                secrets_size
+            );
+        }
+        let offset = self.layout.secrets_offset;
+        self.shared_mem.copy_from_slice(&buffer, offset)
+    }
+
     /// Set the stack guard to `cookie` using `layout` to calculate
     /// its location and `shared_mem` to write it.
     #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
@@ -533,6 +952,9 @@ impl SandboxMemoryManager<ExclusiveSharedMemory> {
                 load_addr: self.load_addr.clone(),
                 entrypoint_offset: self.entrypoint_offset,
                 snapshots: Arc::new(Mutex::new(Vec::new())),
+                guest_max_log_level: self.guest_max_log_level,
+                guest_log_forwarder: self.guest_log_forwarder.clone(),
+                secrets: self.secrets.clone(),
                 #[cfg(target_os = "windows")]
                 _lib: self._lib,
             },
@@ -543,6 +965,9 @@ impl SandboxMemoryManager<ExclusiveSharedMemory> {
                 load_addr: self.load_addr.clone(),
                 entrypoint_offset: self.entrypoint_offset,
                 snapshots: Arc::new(Mutex::new(Vec::new())),
+                guest_max_log_level: self.guest_max_log_level,
+                guest_log_forwarder: self.guest_log_forwarder.clone(),
+                secrets: Arc::new(Mutex::new(Vec::new())),
                 #[cfg(target_os = "windows")]
                 _lib: None,
             },
@@ -591,13 +1016,85 @@ impl SandboxMemoryManager<HostSharedMemory> {
         guest_ptr.absolute()
     }
 
+    /// Call `read_result` with the first `len` bytes of the result buffer,
+    /// without copying them out of guest memory.
+    #[instrument(err(Debug), skip(self, read_result), parent = Span::current(), level= "Trace")]
+    pub(crate) fn read_result_buffer<T, F: FnOnce(&[u8]) -> T>(
+        &mut self,
+        len: usize,
+        read_result: F,
+    ) -> Result<T> {
+        let result_buffer_size = self.layout.sandbox_memory_config.get_result_buffer_size();
+        if len > result_buffer_size {
+            log_then_return!(
+                "Guest wrote a {} byte result to the {} byte result buffer",
+                len,
+                result_buffer_size
+            );
+        }
+        let offset = self.layout.result_buffer_offset;
+        self.shared_mem
+            .with_exclusivity(|e| read_result(&e.as_slice()[offset..offset + len]))
+    }
+
+    /// Publish `data` to the read-only data region, replacing the data
+    /// published before. The data is also written to every memory snapshot,
+    /// so restoring the sandbox's state after a guest function call keeps
+    /// it.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn publish_read_only_data(&mut self, data: &[u8]) -> Result<()> {
+        let read_only_data_size = self.layout.sandbox_memory_config.get_read_only_data_size();
+        if read_only_data_size == 0 {
+            log_then_return!("The sandbox has no read-only data region");
+        }
+        if data.len() > read_only_data_size {
+            log_then_return!(
+                "Data of {} bytes does not fit in the {} byte read-only data region",
+                data.len(),
+                read_only_data_size
+            );
+        }
+        let offset = self.layout.read_only_data_offset;
+        let length_offset = self.layout.get_read_only_data_length_offset();
+        let length = (data.len() as u64).to_le_bytes();
+        self.shared_mem.with_exclusivity(|e| {
+            e.copy_from_slice(data, offset)?;
+            e.copy_from_slice(&length, length_offset)
+        })??;
+        for snapshot in self
+            .snapshots
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+            .iter_mut()
+        {
+            snapshot.write_at(offset, data)?;
+            snapshot.write_at(length_offset, &length)?;
+        }
+        Ok(())
+    }
+
+    /// Forward everything the guest has output that is still waiting for
+    /// the guest's next exit: the records in its log ring buffer and the
+    /// last, incomplete, line of its debug output. Called at the end of
+    /// each guest function call, so that the call's output is neither held
+    /// back until the next call nor mixed with it.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn flush_guest_output(&mut self) -> Result<()> {
+        self.guest_log_forwarder.flush_debug_output();
+        self.drain_guest_log_ring()
+    }
+
     /// Reads a host function call from memory
     #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
     pub(crate) fn get_host_function_call(&mut self) -> Result<FunctionCall> {
-        self.shared_mem.try_pop_buffer_into::<FunctionCall>(
-            self.layout.output_data_buffer_offset,
+        let (_, output_offset) = self.io_buffer_offsets()?;
+        let host_function_call = self.shared_mem.try_pop_buffer_into::<FunctionCall>(
+            output_offset,
             self.layout.sandbox_memory_config.get_output_data_size(),
-        )
+        )?;
+        self.payload_limits()
+            .check_parameters(host_function_call.parameters.as_deref())?;
+        Ok(host_function_call)
     }
 
     /// Writes a function call result to memory
@@ -608,44 +1105,405 @@ impl SandboxMemoryManager<HostSharedMemory> {
                 "write_response_from_host_method_call: failed to convert ReturnValue to Vec<u8>"
             )
         })?;
+        let limits = self.payload_limits();
+        limits.check_return_value(res)?;
+        limits.check_payload_size(
+            function_call_ret_val_buffer.len(),
+            self.layout.sandbox_memory_config.get_input_data_size(),
+        )?;
+        let (input_offset, _) = self.io_buffer_offsets()?;
         self.shared_mem.push_buffer(
-            self.layout.input_data_buffer_offset,
+            input_offset,
             self.layout.sandbox_memory_config.get_input_data_size(),
             function_call_ret_val_buffer.as_slice(),
         )
     }
 
+    /// Set the max log level the guest should use for subsequent guest
+    /// function calls.
+    ///
+    /// The level is written to the PEB along with every guest function call
+    /// since guest memory (including the guest's own max log level) is
+    /// restored from a snapshot after each call.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn set_guest_max_log_level(&mut self, log_level: LevelFilter) {
+        self.guest_max_log_level = Some(log_level);
+    }
+
+    /// Get the limits on the size of function calls and return values
+    /// passed between the host and the guest
+    pub(crate) fn payload_limits(&self) -> PayloadLimits {
+        self.layout.sandbox_memory_config.get_payload_limits()
+    }
+
+    /// Compress the guest memory and every memory snapshot into a new file
+    /// in `dir`, encrypted with `key` if there is one, then release the
+    /// snapshots and, where the hypervisor allows it, the pages backing the
+    /// guest memory. The memory must be restored with `resume` before the
+    /// guest runs again.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn hibernate(
+        &mut self,
+        dir: &Path,
+        key: Option<&SnapshotKey>,
+    ) -> Result<HibernatedMemory> {
+        let mut snapshots = self
+            .snapshots
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
+        let hibernated = HibernatedMemory::write(&mut self.shared_mem, &snapshots, dir, key)?;
+        *snapshots = Vec::new();
+        drop(snapshots);
+
+        #[cfg(target_os = "linux")]
+        if self.can_release_shared_mem() {
+            self.shared_mem.with_exclusivity(|e| e.release_pages())??;
+        }
+        Ok(hibernated)
+    }
+
+    /// Give the pages of the guest memory that only hold zeroes, such as
+    /// those the guest cleared when asked to trim its memory, back to the
+    /// host where the hypervisor allows it, returning how many pages were
+    /// released
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn release_zero_pages(&mut self) -> Result<usize> {
+        #[cfg(target_os = "linux")]
+        if self.can_release_shared_mem() {
+            return self
+                .shared_mem
+                .with_exclusivity(|e| e.release_zero_pages())?;
+        }
+        Ok(0)
+    }
+
+    /// Restore the guest memory and memory snapshots written by
+    /// `hibernate`, decrypting them with `key` if they were encrypted
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn resume(
+        &mut self,
+        hibernated: &HibernatedMemory,
+        key: Option<&SnapshotKey>,
+    ) -> Result<()> {
+        let snapshots = hibernated.read(&mut self.shared_mem, key)?;
+        *self
+            .snapshots
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))? =
+            snapshots;
+        Ok(())
+    }
+
+    /// Replace the guest memory with `memory`, taken from a sandbox created
+    /// from the same guest binary and configuration, and make it the state
+    /// the memory is restored to after each guest call. The stack guard
+    /// `cookie` of this sandbox is written back over the other sandbox's.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn restore_memory(
+        &mut self,
+        memory: &[u8],
+        cookie: &[u8; STACK_COOKIE_LEN],
+    ) -> Result<()> {
+        if memory.len() != self.shared_mem.mem_size() {
+            return Err(new_error!(
+                "Cannot restore {:#x} bytes of memory into a sandbox with {:#x} bytes of memory",
+                memory.len(),
+                self.shared_mem.mem_size()
+            ));
+        }
+        self.shared_mem.copy_from_slice(memory, 0)?;
+        self.shared_mem
+            .copy_from_slice(cookie, self.layout.get_top_of_user_stack_offset())?;
+
+        let mut snapshots = self
+            .snapshots
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
+        match snapshots.last_mut() {
+            Some(snapshot) => snapshot.replace_snapshot(&mut self.shared_mem),
+            None => {
+                log_then_return!(NoMemorySnapshot);
+            }
+        }
+    }
+
+    /// Zero the guest's user and kernel stacks, other than the stack guard
+    /// at the top of the user stack, if `SandboxConfiguration::set_scrub_stacks`
+    /// is enabled. Must only be called between guest function calls, when
+    /// nothing on the stacks is in use.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn scrub_stacks(&mut self) -> Result<()> {
+        if !self.layout.sandbox_memory_config.get_scrub_stacks() {
+            return Ok(());
+        }
+        self.shared_mem.fill(
+            0,
+            self.layout.get_top_of_user_stack_offset() + STACK_COOKIE_LEN,
+            self.layout.get_guest_stack_size() - STACK_COOKIE_LEN,
+        )?;
+        self.shared_mem.fill(
+            0,
+            self.layout.get_kernel_stack_buffer_offset(),
+            self.layout.get_kernel_stack_size(),
+        )
+    }
+
+    /// Digests of the guest heap, user stack and the static data of the
+    /// guest binary that do not depend on the address the guest memory is
+    /// loaded at: every 8-byte word that holds an address in the guest
+    /// memory is hashed as its offset from the start of the guest memory.
+    /// Sandboxes created from the same guest binary and configuration that
+    /// have made the same guest function calls have the same digests,
+    /// whether they run in a virtual machine or in-process.
+    ///
+    /// The stack cookie is left out of the stack digest, and only the words
+    /// of the guest binary that changed since the sandbox was initialised
+    /// are hashed into the data digest, as the guest's security cookie and
+    /// the statics its entrypoint sets up differ between sandboxes.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn guest_memory_digest(&self) -> Result<GuestMemoryDigest> {
+        let mut heap = vec![0u8; self.layout.heap_size];
+        self.shared_mem
+            .copy_to_slice(&mut heap, self.layout.get_guest_heap_buffer_offset())?;
+        self.rebase_guest_addresses(&mut heap);
+
+        let mut stack = vec![0u8; self.layout.get_guest_stack_size() - STACK_COOKIE_LEN];
+        self.shared_mem.copy_to_slice(
+            &mut stack,
+            self.layout.get_top_of_user_stack_offset() + STACK_COOKIE_LEN,
+        )?;
+        self.rebase_guest_addresses(&mut stack);
+
+        let code_offset = self.layout.get_guest_code_offset();
+        let mut image = vec![0u8; self.layout.get_code_size()];
+        self.shared_mem.copy_to_slice(&mut image, code_offset)?;
+        let snapshots = self
+            .snapshots
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
+        let initial = snapshots
+            .first()
+            .and_then(|s| s.as_slice().get(code_offset..code_offset + image.len()));
+        let mut data = Vec::new();
+        for (i, word) in image.chunks_mut(size_of::<u64>()).enumerate() {
+            let offset = i * size_of::<u64>();
+            if initial.is_some_and(|initial| initial[offset..offset + word.len()] == *word) {
+                continue;
+            }
+            self.rebase_guest_addresses(word);
+            data.extend_from_slice(&(offset as u64).to_le_bytes());
+            data.extend_from_slice(word);
+        }
+
+        Ok(GuestMemoryDigest {
+            heap: sha256::digest(heap.as_slice()),
+            stack: sha256::digest(stack.as_slice()),
+            data: sha256::digest(data.as_slice()),
+        })
+    }
+
+    /// Replace every 8-byte word of `bytes` that holds an address in the
+    /// guest memory with its offset from the start of the guest memory
+    fn rebase_guest_addresses(&self, bytes: &mut [u8]) {
+        let base = if self.inprocess {
+            self.shared_mem.base_addr() as u64
+        } else {
+            SandboxMemoryLayout::BASE_ADDRESS as u64
+        };
+        let end = base + self.shared_mem.mem_size() as u64;
+        for word in bytes.chunks_exact_mut(size_of::<u64>()) {
+            let mut value = [0u8; size_of::<u64>()];
+            value.copy_from_slice(word);
+            let value = u64::from_le_bytes(value);
+            if (base..end).contains(&value) {
+                word.copy_from_slice(&(value - base).to_le_bytes());
+            }
+        }
+    }
+
+    /// Whether the pages backing the guest memory can be released while the
+    /// sandbox is hibernated. KVM faults memory mapped into a virtual
+    /// machine back in when it is next touched, but mshv pins the pages it
+    /// maps for as long as the virtual machine exists.
+    #[cfg(target_os = "linux")]
+    fn can_release_shared_mem(&self) -> bool {
+        #[cfg(kvm)]
+        if *get_available_hypervisor() == Some(HypervisorType::Kvm) {
+            return true;
+        }
+        self.inprocess
+    }
+
     /// Writes a guest function call to memory
     #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
     pub(crate) fn write_guest_function_call(&mut self, buffer: &[u8]) -> Result<()> {
-        validate_guest_function_call_buffer(buffer).map_err(|e| {
-            new_error!(
-                "Guest function call buffer validation failed: {}",
-                e.to_string()
-            )
-        })?;
+        let slot = self.current_io_slot()?;
+        self.io_slot_writer().push(slot, buffer)?;
+        self.write_guest_max_log_level()
+    }
 
-        self.shared_mem.push_buffer(
-            self.layout.input_data_buffer_offset,
-            self.layout.sandbox_memory_config.get_input_data_size(),
-            buffer,
-        )
+    /// A writer of guest function calls to the slots of the input data
+    /// buffer
+    pub(crate) fn io_slot_writer(&self) -> IoSlotWriter {
+        IoSlotWriter {
+            shared_mem: self.shared_mem.clone(),
+            layout: self.layout,
+        }
+    }
+
+    /// Point the guest at the input and output data buffers of `slot`,
+    /// which an `IoSlotWriter` has written the next guest function call to
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn select_io_slot(&mut self, slot: usize) -> Result<()> {
+        let slots = self.layout.sandbox_memory_config.get_io_buffer_slots();
+        if slot >= slots {
+            log_then_return!(
+                "Cannot select input and output data slot {}, there are {} slots",
+                slot,
+                slots
+            );
+        }
+        let input = self.guest_address(self.layout.get_input_data_slot_offset(slot))?;
+        self.shared_mem
+            .write::<u64>(self.layout.get_input_data_pointer_offset(), input)?;
+        let output = self.guest_address(self.layout.get_output_data_slot_offset(slot))?;
+        self.shared_mem
+            .write::<u64>(self.layout.get_output_data_pointer_offset(), output)?;
+        self.write_guest_max_log_level()
+    }
+
+    /// The slot of the input and output data buffers the guest is pointed
+    /// at
+    fn current_io_slot(&self) -> Result<usize> {
+        let slots = self.layout.sandbox_memory_config.get_io_buffer_slots();
+        if slots == 1 {
+            return Ok(0);
+        }
+        let input = self
+            .shared_mem
+            .read::<u64>(self.layout.get_input_data_pointer_offset())?;
+        let first = self.guest_address(self.layout.get_input_data_slot_offset(0))?;
+        let size = self.layout.sandbox_memory_config.get_input_data_size() as u64;
+        input
+            .checked_sub(first)
+            .map(|offset| (offset / size) as usize)
+            .filter(|slot| *slot < slots)
+            .ok_or_else(|| {
+                new_error!(
+                    "The guest's input data pointer {:#x} is not in an input data slot",
+                    input
+                )
+            })
+    }
+
+    /// The offsets of the input and output data buffers the guest is
+    /// pointed at
+    fn io_buffer_offsets(&self) -> Result<(usize, usize)> {
+        let slot = self.current_io_slot()?;
+        Ok((
+            self.layout.get_input_data_slot_offset(slot),
+            self.layout.get_output_data_slot_offset(slot),
+        ))
+    }
+
+    /// The address the guest sees `offset` in the shared memory at
+    fn guest_address(&self, offset: usize) -> Result<u64> {
+        let address = if self.inprocess {
+            self.shared_mem.base_addr() + offset
+        } else {
+            SandboxMemoryLayout::BASE_ADDRESS + offset
+        };
+        Ok(u64::try_from(address)?)
+    }
+
+    /// Pass the max log level the guest should switch to on to it
+    fn write_guest_max_log_level(&mut self) -> Result<()> {
+        if let Some(log_level) = self.guest_max_log_level {
+            self.shared_mem.write::<u64>(
+                self.layout.get_guest_max_log_level_offset(),
+                log_level as u64,
+            )?;
+        }
+        Ok(())
     }
 
     /// Reads a function call result from memory
     #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
     pub(crate) fn get_guest_function_call_result(&mut self) -> Result<ReturnValue> {
-        self.shared_mem.try_pop_buffer_into::<ReturnValue>(
-            self.layout.output_data_buffer_offset,
+        let (_, output_offset) = self.io_buffer_offsets()?;
+        let output_data_size = self.layout.sandbox_memory_config.get_output_data_size();
+        let element = self
+            .shared_mem
+            .peek_buffer(output_offset, output_data_size)?;
+        let compressed = element.len() >= 2 * size_of::<u32>()
+            && self
+                .shared_mem
+                .read::<u32>(element.start + size_of::<u32>())?
+                == COMPRESSED_PAYLOAD_MAGIC;
+        let return_value = if compressed {
+            // a guest that never accepted an algorithm mustn't make the
+            // host decompress anything
+            if self.negotiated_payload_compression()? == PayloadCompression::None {
+                log_then_return!(
+                    "The guest compressed its function call result, but payload compression wasn't negotiated"
+                );
+            }
+            let payload = self
+                .shared_mem
+                .try_pop_buffer_into::<Vec<u8>>(output_offset, output_data_size)?;
+            let payload = payload_compression::decompress(
+                &payload,
+                max_uncompressed_size(&self.payload_limits()),
+            )
+            .map_err(|e| new_error!("Error decompressing guest function call result: {}", e))?;
+            ReturnValue::try_from(payload.as_slice())
+                .map_err(|_| new_error!("Failed to convert buffer to ReturnValue"))?
+        } else {
+            self.shared_mem
+                .try_pop_buffer_into::<ReturnValue>(output_offset, output_data_size)?
+        };
+        self.payload_limits().check_return_value(&return_value)?;
+        Ok(return_value)
+    }
+
+    /// The algorithm guest function calls and results are compressed
+    /// with, which is `PayloadCompression::None` until the guest accepts
+    /// the one the sandbox was configured with during its initialisation
+    pub(crate) fn negotiated_payload_compression(&self) -> Result<PayloadCompression> {
+        self.io_slot_writer().negotiated_payload_compression()
+    }
+
+    /// Find the `VecBytes` return value of a guest function call in the
+    /// output data buffer without popping it off, returning the range of
+    /// offsets its bytes take up in the shared memory. The bytes stay in
+    /// the output data buffer until the sandbox's state is restored.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_guest_function_call_result_bytes(&mut self) -> Result<Range<usize>> {
+        let (_, output_offset) = self.io_buffer_offsets()?;
+        let element = self.shared_mem.peek_buffer(
+            output_offset,
             self.layout.sandbox_memory_config.get_output_data_size(),
-        )
+        )?;
+        let bytes = self
+            .shared_mem
+            .with_exclusivity(|e| -> Result<Range<usize>> {
+                let buffer = &e.as_slice()[element.clone()];
+                let bytes = vec_bytes_return_value(buffer)
+                    .map_err(|e| new_error!("Error reading guest function call result: {}", e))?;
+                let start = element.start + (bytes.as_ptr() as usize - buffer.as_ptr() as usize);
+                Ok(start..start + bytes.len())
+            })??;
+        self.payload_limits()
+            .check_vec_bytes_return_value_size(bytes.len())?;
+        Ok(bytes)
     }
 
     /// Read guest log data from the `SharedMemory` contained within `self`
     #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
     pub(crate) fn read_guest_log_data(&mut self) -> Result<GuestLogData> {
+        let (_, output_offset) = self.io_buffer_offsets()?;
         self.shared_mem.try_pop_buffer_into::<GuestLogData>(
-            self.layout.output_data_buffer_offset,
+            output_offset,
             self.layout.sandbox_memory_config.get_output_data_size(),
         )
     }
@@ -737,6 +1595,50 @@ impl SandboxMemoryManager<HostSharedMemory> {
         })
     }
 
+    /// Write an error with `code`, `ErrorCode::HostFunctionError` or
+    /// `ErrorCode::HostFunctionArgumentInvalid`, and `message` to the guest
+    /// error buffer, where the guest reads it in place of the return value
+    /// of the host function it called. `message` is truncated if the error
+    /// doesn't fit in the buffer.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn write_host_function_error(
+        &mut self,
+        code: ErrorCode,
+        message: &str,
+    ) -> Result<()> {
+        let err_buffer_size_offset = self.layout.get_guest_error_buffer_size_offset();
+        let max_err_buffer_size =
+            usize::try_from(self.shared_mem.read::<u64>(err_buffer_size_offset)?)?;
+
+        let mut message = message.to_string();
+        let guest_error_buffer = loop {
+            let ge = GuestError::new(code, message.clone());
+            let buffer: Vec<u8> = (&ge).try_into().map_err(|_| {
+                new_error!("write_host_function_error: failed to convert GuestError to Vec<u8>")
+            })?;
+            if buffer.len() <= max_err_buffer_size {
+                break buffer;
+            }
+            if message.is_empty() {
+                log_then_return!(
+                    "The guest error buffer is too small to hold a host function error"
+                );
+            }
+            // leave some room for the flatbuffer's padding to change
+            let mut len = message
+                .len()
+                .saturating_sub(buffer.len() - max_err_buffer_size + 8);
+            while !message.is_char_boundary(len) {
+                len -= 1;
+            }
+            message.truncate(len);
+        };
+        self.shared_mem.copy_from_slice(
+            guest_error_buffer.as_slice(),
+            self.layout.guest_error_buffer_offset,
+        )
+    }
+
     /// This function writes an error to guest memory and is intended to be
     /// used when the host's outb handler code raises an error.
     #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
@@ -808,6 +1710,7 @@ impl SandboxMemoryManager<HostSharedMemory> {
 
 #[cfg(test)]
 mod tests {
+    use hyperlight_common::payload_compression::COMPRESSED_PAYLOAD_MAGIC;
     use hyperlight_testing::rust_guest_as_pathbuf;
     use serde_json::to_string;
     #[cfg(all(target_os = "windows", inprocess))]
@@ -919,6 +1822,165 @@ mod tests {
         assert_eq!(None, hmgr.get_host_error().unwrap());
     }
 
+    #[test]
+    fn scrub_stacks() {
+        for scrub in [false, true] {
+            let mut cfg = SandboxConfiguration::default();
+            cfg.set_scrub_stacks(scrub);
+            let layout = SandboxMemoryLayout::new(cfg, 0x10000, 0x10000, 0x10000).unwrap();
+            let mut eshm = ExclusiveSharedMemory::new(layout.get_memory_size().unwrap()).unwrap();
+            let mem_size = eshm.mem_size();
+            layout
+                .write(
+                    &mut eshm,
+                    SandboxMemoryLayout::BASE_ADDRESS,
+                    mem_size,
+                    false,
+                )
+                .unwrap();
+            let emgr = SandboxMemoryManager::new(
+                layout,
+                eshm,
+                false,
+                RawPtr::from(0),
+                Offset::from(0),
+                #[cfg(target_os = "windows")]
+                None,
+            );
+            let (mut hmgr, _) = emgr.build();
+
+            let stack_offset = layout.get_top_of_user_stack_offset();
+            let stack_size = layout.get_guest_stack_size();
+            let kernel_stack_offset = layout.get_kernel_stack_buffer_offset();
+            let kernel_stack_size = layout.get_kernel_stack_size();
+            hmgr.shared_mem
+                .fill(0xaa, stack_offset, stack_size)
+                .unwrap();
+            hmgr.shared_mem
+                .fill(0xaa, kernel_stack_offset, kernel_stack_size)
+                .unwrap();
+            hmgr.scrub_stacks().unwrap();
+
+            let mut stack = vec![0; stack_size];
+            hmgr.shared_mem
+                .copy_to_slice(&mut stack, stack_offset)
+                .unwrap();
+            let mut kernel_stack = vec![0; kernel_stack_size];
+            hmgr.shared_mem
+                .copy_to_slice(&mut kernel_stack, kernel_stack_offset)
+                .unwrap();
+            // the stack guard is always kept
+            assert!(stack[..super::STACK_COOKIE_LEN].iter().all(|&b| b == 0xaa));
+            let expected = if scrub { 0 } else { 0xaa };
+            assert!(stack[super::STACK_COOKIE_LEN..]
+                .iter()
+                .all(|&b| b == expected));
+            assert!(kernel_stack.iter().all(|&b| b == expected));
+        }
+    }
+
+    #[test]
+    fn guest_memory_digest() {
+        let cfg = SandboxConfiguration::default();
+        let layout = SandboxMemoryLayout::new(cfg, 0x10000, 0x10000, 0x10000).unwrap();
+        let mut eshm = ExclusiveSharedMemory::new(layout.get_memory_size().unwrap()).unwrap();
+        let mem_size = eshm.mem_size();
+        layout
+            .write(
+                &mut eshm,
+                SandboxMemoryLayout::BASE_ADDRESS,
+                mem_size,
+                false,
+            )
+            .unwrap();
+        let emgr = SandboxMemoryManager::new(
+            layout,
+            eshm,
+            false,
+            RawPtr::from(0),
+            Offset::from(0),
+            #[cfg(target_os = "windows")]
+            None,
+        );
+        let (mut hmgr, _) = emgr.build();
+        hmgr.push_state().unwrap();
+        let initial = hmgr.guest_memory_digest().unwrap();
+
+        // guest addresses are hashed as offsets into the guest memory
+        let heap_offset = layout.get_guest_heap_buffer_offset();
+        hmgr.shared_mem
+            .write::<u64>(
+                heap_offset,
+                (SandboxMemoryLayout::BASE_ADDRESS + 0x1000) as u64,
+            )
+            .unwrap();
+        let address = hmgr.guest_memory_digest().unwrap();
+        hmgr.shared_mem.write::<u64>(heap_offset, 0x1000).unwrap();
+        let offset = hmgr.guest_memory_digest().unwrap();
+        assert_ne!(initial.heap, address.heap);
+        assert_eq!(address, offset);
+
+        // the stack cookie is left out, the rest of the stack isn't
+        let stack_offset = layout.get_top_of_user_stack_offset();
+        hmgr.shared_mem.write::<u64>(stack_offset, 0xaa).unwrap();
+        assert_eq!(offset, hmgr.guest_memory_digest().unwrap());
+        hmgr.shared_mem
+            .write::<u64>(stack_offset + super::STACK_COOKIE_LEN, 0xaa)
+            .unwrap();
+        let stack = hmgr.guest_memory_digest().unwrap();
+        assert_ne!(offset.stack, stack.stack);
+        assert_eq!(offset.data, stack.data);
+
+        // only what changed in the guest binary since the snapshot counts
+        let code_offset = layout.get_guest_code_offset();
+        let code = hmgr.shared_mem.read::<u64>(code_offset).unwrap();
+        hmgr.shared_mem.write::<u64>(code_offset, !code).unwrap();
+        let data = hmgr.guest_memory_digest().unwrap();
+        assert_ne!(stack.data, data.data);
+        hmgr.shared_mem.write::<u64>(code_offset, code).unwrap();
+        assert_eq!(stack, hmgr.guest_memory_digest().unwrap());
+    }
+
+    /// A guest can't make the host decompress a result unless it accepted
+    /// payload compression when it was initialised
+    #[test]
+    fn compressed_result_needs_negotiated_compression() {
+        let cfg = SandboxConfiguration::default();
+        let layout = SandboxMemoryLayout::new(cfg, 0x10000, 0x10000, 0x10000).unwrap();
+        let mut eshm = ExclusiveSharedMemory::new(layout.get_memory_size().unwrap()).unwrap();
+        let mem_size = eshm.mem_size();
+        layout
+            .write(
+                &mut eshm,
+                SandboxMemoryLayout::BASE_ADDRESS,
+                mem_size,
+                false,
+            )
+            .unwrap();
+        let emgr = SandboxMemoryManager::new(
+            layout,
+            eshm,
+            false,
+            RawPtr::from(0),
+            Offset::from(0),
+            #[cfg(target_os = "windows")]
+            None,
+        );
+        let (mut hmgr, _) = emgr.build();
+        // a header claiming a 16 MiB LZ4 payload
+        let payload: Vec<u8> = [12, COMPRESSED_PAYLOAD_MAGIC, 1, 0x100_0000]
+            .iter()
+            .flat_map(|field: &u32| field.to_le_bytes())
+            .collect();
+        let output_offset = hmgr.layout.get_output_data_slot_offset(0);
+        hmgr.shared_mem
+            .push_buffer(output_offset, cfg.get_output_data_size(), &payload)
+            .unwrap();
+
+        let err = hmgr.get_guest_function_call_result().unwrap_err();
+        assert!(err.to_string().contains("wasn't negotiated"), "{}", err);
+    }
+
     /// write a host error to shared memory, then try to read it back out
     #[test]
     fn round_trip_host_error() {
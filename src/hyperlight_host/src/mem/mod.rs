@@ -14,6 +14,8 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+/// Vectorized copies between host memory and the memory of a running guest
+pub(crate) mod boundary_copy;
 /// Reusable structure to hold data and provide a `Drop` implementation
 #[cfg(inprocess)]
 pub(crate) mod custom_drop;
@@ -21,6 +23,10 @@ pub(crate) mod custom_drop;
 pub(crate) mod elf;
 /// A generic wrapper for executable files (PE, ELF, etc)
 pub(crate) mod exe;
+/// A cache of parsed guest binaries, keyed by the hash of their contents
+pub(crate) mod exe_cache;
+/// Guest memory written to disk while its sandbox is hibernated
+pub(crate) mod hibernation;
 /// Functionality to establish a sandbox's memory layout.
 pub mod layout;
 /// Safe wrapper around an HINSTANCE created by the windows
@@ -50,3 +56,9 @@ pub mod shared_mem_snapshot;
 /// Utilities for writing shared memory tests
 #[cfg(test)]
 pub(crate) mod shared_mem_tests;
+/// Authenticated encryption of snapshot and hibernation files with keys
+/// supplied by the embedder
+pub mod snapshot_encryption;
+/// A versioned, compressed file format for chains of full and incremental
+/// snapshots of a guest's memory
+pub mod snapshot_file;
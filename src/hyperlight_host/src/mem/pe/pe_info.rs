@@ -18,11 +18,12 @@ use std::io::{Cursor, Read, Write};
 use std::{iter, mem};
 
 use goblin::pe::optional_header::OptionalHeader;
-use goblin::pe::section_table::SectionTable;
 use goblin::pe::PE;
+use hyperlight_common::guest_metadata::{GuestMetadata, METADATA_SECTION};
+use hyperlight_common::mem::PAGE_SIZE_USIZE;
 use tracing::{instrument, Span};
 
-use crate::mem::pe::base_relocations;
+use crate::mem::pe::base_relocations::{self, BaseRelocation};
 use crate::{log_then_return, Result};
 
 const IMAGE_REL_BASED_DIR64: u8 = 10;
@@ -36,10 +37,14 @@ const CHARACTERISTICS_EXECUTABLE_IMAGE: u16 = 0x0002;
 /// Does not contain comprehensive information about a given
 /// PE file, but rather just enough to be able to do relocations,
 /// symbol resolution, and actually execute it within a `Sandbox`.
+#[derive(Clone)]
 pub(crate) struct PEInfo {
     pub(crate) payload: Vec<u8>,
     optional_header: OptionalHeader,
-    reloc_section: Option<SectionTable>,
+    /// The base relocations from the `.reloc` section, parsed once
+    /// up front since they don't depend on the load address
+    relocations: Vec<BaseRelocation>,
+    metadata: GuestMetadata,
 }
 
 impl PEInfo {
@@ -190,10 +195,30 @@ impl PEInfo {
             iter::repeat(0).take(data_section_additional_bytes as usize),
         );
 
+        let relocations = base_relocations::get_base_relocations(&pe_bytes, &reloc_section)?;
+
+        let metadata = match pe
+            .sections
+            .iter()
+            .find(|section| section.name().unwrap_or_default() == METADATA_SECTION)
+        {
+            Some(section) => {
+                let start = section.pointer_to_raw_data as usize;
+                let size = section.size_of_raw_data.min(section.virtual_size) as usize;
+                let bytes = match pe_bytes.get(start..start.saturating_add(size)) {
+                    Some(bytes) => bytes,
+                    None => log_then_return!("the {} section is truncated", METADATA_SECTION),
+                };
+                GuestMetadata::from_bytes(bytes)?
+            }
+            None => GuestMetadata::default(),
+        };
+
         Ok(Self {
             payload: pe_bytes,
             optional_header,
-            reloc_section,
+            relocations,
+            metadata,
         })
     }
 
@@ -210,6 +235,12 @@ impl PEInfo {
         self.optional_header.windows_fields.image_base
     }
 
+    /// The metadata embedded in the `METADATA_SECTION` section, which
+    /// is empty if there is none.
+    pub(crate) fn metadata(&self) -> &GuestMetadata {
+        &self.metadata
+    }
+
     /// Return the stack reserve field from the optional COFF header.
     #[instrument(skip_all, parent = Span::current(), level= "Trace")]
     pub(crate) fn stack_reserve(&self) -> u64 {
@@ -258,6 +289,18 @@ impl PEInfo {
         Ok(applied)
     }
 
+    /// Check that every relocation is of a supported type and within the
+    /// payload, by computing the patches to load the PE somewhere other
+    /// than its preferred load address.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn check_relocations(&self) -> Result<()> {
+        let address = self
+            .preferred_load_address()
+            .wrapping_add(PAGE_SIZE_USIZE as u64);
+        self.get_exe_relocation_patches(address as usize)?;
+        Ok(())
+    }
+
     /// Get a list of patches to make to the symbol table to
     /// complete the relocations in the relocation table.
     #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
@@ -277,11 +320,9 @@ impl PEInfo {
             return Ok(Vec::new());
         }
 
-        let relocations =
-            base_relocations::get_base_relocations(&self.payload, &self.reloc_section)?;
-        let mut patches = Vec::with_capacity(relocations.len());
+        let mut patches = Vec::with_capacity(self.relocations.len());
 
-        for reloc in relocations {
+        for reloc in &self.relocations {
             match reloc.typ {
                 // IMAGE_REL_BASED_DIR64:
                 // "The base relocation applies the difference to the
@@ -17,8 +17,10 @@ limitations under the License.
 use std::any::type_name;
 use std::ffi::c_void;
 use std::io::Error;
+use std::ops::Range;
 #[cfg(target_os = "linux")]
 use std::ptr::null_mut;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 
 use hyperlight_common::mem::PAGE_SIZE_USIZE;
@@ -37,6 +39,8 @@ use windows::Win32::System::Memory::{
     MEMORY_MAPPED_VIEW_ADDRESS, PAGE_EXECUTE_READWRITE, PAGE_NOACCESS, PAGE_PROTECTION_FLAGS,
 };
 
+use super::boundary_copy;
+use crate::sandbox::config::MemoryPopulation;
 #[cfg(target_os = "windows")]
 use crate::HyperlightError::MemoryAllocationFailed;
 #[cfg(target_os = "windows")]
@@ -96,6 +100,8 @@ macro_rules! generate_writer {
 pub struct HostMapping {
     ptr: *mut u8,
     size: usize,
+    /// Whether every usable page of the mapping has been populated
+    prefaulted: AtomicBool,
     #[cfg(target_os = "windows")]
     handle: HANDLE,
 }
@@ -386,6 +392,7 @@ impl ExclusiveSharedMemory {
             region: Arc::new(HostMapping {
                 ptr: addr as *mut u8,
                 size: total_size,
+                prefaulted: AtomicBool::new(false),
             }),
         })
     }
@@ -507,6 +514,7 @@ impl ExclusiveSharedMemory {
             region: Arc::new(HostMapping {
                 ptr: addr.Value as *mut u8,
                 size: total_size,
+                prefaulted: AtomicBool::new(false),
                 handle,
             }),
         })
@@ -551,6 +559,119 @@ impl ExclusiveSharedMemory {
         Ok(())
     }
 
+    /// Populate every usable page of this shared memory up front, so
+    /// that neither the host nor the guest takes a page fault the first
+    /// time it touches a page. The contents of the memory are not changed.
+    ///
+    /// By default shared memory is populated lazily, on first touch,
+    /// which makes creating a sandbox cheaper at the cost of a slower
+    /// first guest function call.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn prefault(&mut self) -> Result<()> {
+        if self.region.prefaulted.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        #[cfg(target_os = "linux")]
+        let populated = {
+            use libc::{madvise, MADV_POPULATE_WRITE};
+
+            // MADV_POPULATE_WRITE is only available since Linux 5.14,
+            // fall back to touching each page if it fails
+            unsafe {
+                madvise(
+                    self.base_ptr() as *mut c_void,
+                    self.mem_size(),
+                    MADV_POPULATE_WRITE,
+                ) == 0
+            }
+        };
+        #[cfg(target_os = "windows")]
+        let populated = false;
+
+        if !populated {
+            let base = self.base_ptr();
+            for offset in (0..self.mem_size()).step_by(PAGE_SIZE_USIZE) {
+                // Safety: offset is within the usable memory of this
+                // mapping, and we have exclusive access to it
+                unsafe {
+                    let page = base.add(offset);
+                    page.write_volatile(page.read_volatile());
+                }
+            }
+        }
+
+        self.region.prefaulted.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Give the pages backing this shared memory back to the host, without
+    /// unmapping the memory. The memory reads as zeroes afterwards, and its
+    /// pages are populated again on first touch.
+    #[cfg(target_os = "linux")]
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn release_pages(&mut self) -> Result<()> {
+        use libc::{madvise, MADV_REMOVE};
+
+        // The memory is a shared anonymous mapping, MADV_DONTNEED would
+        // only drop this process's references to its pages, not free them
+        let res = unsafe { madvise(self.base_ptr() as *mut c_void, self.mem_size(), MADV_REMOVE) };
+        if res != 0 {
+            return Err(new_error!(
+                "Failed to release shared memory pages: {:#?}",
+                Error::last_os_error().raw_os_error()
+            ));
+        }
+        self.region.prefaulted.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Give the pages of this shared memory that only hold zeroes back to
+    /// the host, returning how many were released. The memory reads the
+    /// same afterwards, and the released pages are populated again on
+    /// first touch.
+    #[cfg(target_os = "linux")]
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn release_zero_pages(&mut self) -> Result<usize> {
+        use libc::{madvise, MADV_REMOVE};
+
+        let mut released = 0;
+        let mut run_start = None;
+        let pages = self.mem_size() / PAGE_SIZE_USIZE;
+        for page in 0..=pages {
+            let zero = page < pages
+                && self.as_slice()[page * PAGE_SIZE_USIZE..(page + 1) * PAGE_SIZE_USIZE]
+                    .iter()
+                    .all(|b| *b == 0);
+            match (zero, run_start) {
+                (true, None) => run_start = Some(page),
+                (false, Some(start)) => {
+                    // See release_pages for why this isn't MADV_DONTNEED
+                    let res = unsafe {
+                        madvise(
+                            self.base_ptr().add(start * PAGE_SIZE_USIZE) as *mut c_void,
+                            (page - start) * PAGE_SIZE_USIZE,
+                            MADV_REMOVE,
+                        )
+                    };
+                    if res != 0 {
+                        return Err(new_error!(
+                            "Failed to release shared memory pages: {:#?}",
+                            Error::last_os_error().raw_os_error()
+                        ));
+                    }
+                    released += page - start;
+                    run_start = None;
+                }
+                _ => {}
+            }
+        }
+        if released > 0 {
+            self.region.prefaulted.store(false, Ordering::Relaxed);
+        }
+        Ok(released)
+    }
+
     /// Internal helper method to get the backing memory as a mutable slice.
     ///
     /// # Safety
@@ -725,6 +846,16 @@ pub trait SharedMemory {
         self.region().size
     }
 
+    /// Return whether the pages of this SharedMemory are populated on
+    /// first touch or have all been populated up front.
+    fn memory_population(&self) -> MemoryPopulation {
+        if self.region().prefaulted.load(Ordering::Relaxed) {
+            MemoryPopulation::Prefault
+        } else {
+            MemoryPopulation::Lazy
+        }
+    }
+
     /// Run some code with exclusive access to the SharedMemory
     /// underlying this.  If the SharedMemory is not an
     /// ExclusiveSharedMemory, any concurrent accesses to the relevant
@@ -790,6 +921,13 @@ unsafe impl AllValid for i64 {}
 unsafe impl AllValid for [u8; 16] {}
 
 impl HostSharedMemory {
+    /// A reference to the host mapping that doesn't keep it mapped, for
+    /// tests that check when it is unmapped
+    #[cfg(test)]
+    pub(crate) fn downgrade(&self) -> std::sync::Weak<HostMapping> {
+        Arc::downgrade(&self.region)
+    }
+
     /// Read a value of type T, whose representation is the same
     /// between the sandbox and the host, and which has no invalid bit
     /// patterns
@@ -824,6 +962,26 @@ impl HostSharedMemory {
         Ok(())
     }
 
+    /// Atomically write `value` at `offset`, which must be aligned for a
+    /// `u64`, so that a guest running at the same time never reads a
+    /// partly written value
+    pub(crate) fn store_u64_atomic(&self, offset: usize, value: u64) -> Result<()> {
+        bounds_check!(offset, std::mem::size_of::<u64>(), self.mem_size());
+        let ptr = self.base_ptr().wrapping_add(offset) as *mut u64;
+        if !ptr.is_aligned() {
+            log_then_return!("Offset {:#x} is not aligned for an atomic u64", offset);
+        }
+        let guard = self
+            .lock
+            .try_read()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
+        // Safety: the pointer is in bounds and aligned, and the memory
+        // stays mapped while the lock is held
+        unsafe { AtomicU64::from_ptr(ptr) }.store(value, Ordering::SeqCst);
+        drop(guard);
+        Ok(())
+    }
+
     /// Copy the contents of the slice into the sandbox at the
     /// specified offset
     pub fn copy_to_slice(&self, slice: &mut [u8], offset: usize) -> Result<()> {
@@ -833,12 +991,9 @@ impl HostSharedMemory {
             .lock
             .try_read()
             .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
-        // todo: replace with something a bit more optimized + correct
-        for (i, b) in slice.iter_mut().enumerate() {
-            unsafe {
-                *b = base.wrapping_add(i).read_volatile();
-            }
-        }
+        // Safety: the range is in bounds, and the memory stays mapped
+        // while the lock is held
+        unsafe { boundary_copy::copy_from_guest(slice, base) };
         drop(guard);
         Ok(())
     }
@@ -852,12 +1007,9 @@ impl HostSharedMemory {
             .lock
             .try_read()
             .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
-        // todo: replace with something a bit more optimized + correct
-        for (i, b) in slice.iter().enumerate() {
-            unsafe {
-                base.wrapping_add(i).write_volatile(*b);
-            }
-        }
+        // Safety: the range is in bounds, and the memory stays mapped
+        // while the lock is held
+        unsafe { boundary_copy::copy_to_guest(base, slice) };
         drop(guard);
         Ok(())
     }
@@ -871,10 +1023,9 @@ impl HostSharedMemory {
             .lock
             .try_read()
             .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
-        // todo: replace with something a bit more optimized + correct
-        for i in 0..len {
-            unsafe { base.wrapping_add(i).write_volatile(value) };
-        }
+        // Safety: the range is in bounds, and the memory stays mapped
+        // while the lock is held
+        unsafe { boundary_copy::fill_guest(base, value, len) };
         drop(guard);
         Ok(())
     }
@@ -940,6 +1091,39 @@ impl HostSharedMemory {
     where
         T: for<'b> TryFrom<&'b [u8]>,
     {
+        let element = self.peek_buffer(buffer_start_offset, buffer_size)?;
+        let stack_pointer_rel = self.read::<u64>(buffer_start_offset)? as usize;
+        let last_element_offset_rel = element.start - buffer_start_offset;
+
+        let mut result_buffer = vec![0; element.len()];
+
+        self.copy_to_slice(&mut result_buffer, element.start)?;
+        let to_return = T::try_from(result_buffer.as_slice()).map_err(|_e| {
+            new_error!(
+                "pop_buffer_into: failed to convert buffer to {}",
+                type_name::<T>()
+            )
+        })?;
+
+        // update the stack pointer to point to the element we just popped off since that is now free
+        self.write::<u64>(buffer_start_offset, last_element_offset_rel as u64)?;
+
+        // zero out the memory we just popped off
+        let num_bytes_to_zero = stack_pointer_rel - last_element_offset_rel;
+        self.fill(0, element.start, num_bytes_to_zero)?;
+
+        Ok(to_return)
+    }
+
+    /// Find the size-prefixed flatbuffer on top of the given buffer without
+    /// popping it off, returning the range of offsets it takes up.
+    /// NOTE! buffer_start_offset must point to the beginning of the buffer
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub fn peek_buffer(
+        &self,
+        buffer_start_offset: usize,
+        buffer_size: usize,
+    ) -> Result<Range<usize>> {
         // get the stackpointer
         let stack_pointer_rel = self.read::<u64>(buffer_start_offset)? as usize;
 
@@ -958,36 +1142,39 @@ impl HostSharedMemory {
         let last_element_offset_rel: usize =
             self.read::<u64>(last_element_offset_abs - 8)? as usize;
 
+        // the element must start after the stack pointer at the start of the buffer
+        // and end before the offset to it that we just read. The guest controls both
+        // values so they can't be trusted.
+        if last_element_offset_rel < 8 || last_element_offset_rel > stack_pointer_rel - 8 {
+            return Err(new_error!(
+                "Unable to pop data from buffer: Element offset is out of bounds. Element offset: {}, Stack pointer: {}",
+                last_element_offset_rel,
+                stack_pointer_rel
+            ));
+        }
+        let max_element_size = stack_pointer_rel - 8 - last_element_offset_rel;
+
         // make it absolute
         let last_element_offset_abs = last_element_offset_rel + buffer_start_offset;
 
         // Get the size of the flatbuffer buffer from memory
         let fb_buffer_size = {
-            let size_i32 = self.read::<u32>(last_element_offset_abs)? + 4;
+            let size_u64 = self.read::<u32>(last_element_offset_abs)? as u64 + 4;
             // ^^^ flatbuffer byte arrays are prefixed by 4 bytes
             // indicating its size, so, to get the actual size, we need
             // to add 4.
-            usize::try_from(size_i32)
+            usize::try_from(size_u64)
         }?;
 
-        let mut result_buffer = vec![0; fb_buffer_size];
-
-        self.copy_to_slice(&mut result_buffer, last_element_offset_abs)?;
-        let to_return = T::try_from(result_buffer.as_slice()).map_err(|_e| {
-            new_error!(
-                "pop_buffer_into: failed to convert buffer to {}",
-                type_name::<T>()
-            )
-        })?;
-
-        // update the stack pointer to point to the element we just popped off since that is now free
-        self.write::<u64>(buffer_start_offset, last_element_offset_rel as u64)?;
-
-        // zero out the memory we just popped off
-        let num_bytes_to_zero = stack_pointer_rel - last_element_offset_rel;
-        self.fill(0, last_element_offset_abs, num_bytes_to_zero)?;
+        if fb_buffer_size > max_element_size {
+            return Err(new_error!(
+                "Unable to pop data from buffer: Flatbuffer size {} exceeds the size of the element {}",
+                fb_buffer_size,
+                max_element_size
+            ));
+        }
 
-        Ok(to_return)
+        Ok(last_element_offset_abs..last_element_offset_abs + fb_buffer_size)
     }
 }
 
@@ -1142,6 +1329,70 @@ mod tests {
         assert!(gm.is_err());
     }
 
+    #[test]
+    fn prefault() {
+        use crate::sandbox::config::MemoryPopulation;
+
+        let mut eshm = ExclusiveSharedMemory::new(PAGE_SIZE_USIZE * 4).unwrap();
+        assert_eq!(MemoryPopulation::Lazy, eshm.memory_population());
+        eshm.copy_from_slice(b"abc", PAGE_SIZE_USIZE).unwrap();
+
+        eshm.prefault().unwrap();
+        assert_eq!(MemoryPopulation::Prefault, eshm.memory_population());
+        // prefaulting must not change the contents of the memory
+        assert_eq!(
+            b"abc",
+            &eshm.as_slice()[PAGE_SIZE_USIZE..PAGE_SIZE_USIZE + 3]
+        );
+        assert!(eshm.as_slice()[..PAGE_SIZE_USIZE].iter().all(|b| *b == 0));
+
+        // the mode is shared by every view of the same memory
+        let (hshm, _) = eshm.build();
+        assert_eq!(MemoryPopulation::Prefault, hshm.memory_population());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn release_pages() {
+        use crate::sandbox::config::MemoryPopulation;
+
+        let mut eshm = ExclusiveSharedMemory::new(PAGE_SIZE_USIZE * 4).unwrap();
+        eshm.copy_from_slice(b"abc", PAGE_SIZE_USIZE).unwrap();
+        eshm.prefault().unwrap();
+
+        eshm.release_pages().unwrap();
+        assert_eq!(MemoryPopulation::Lazy, eshm.memory_population());
+        assert!(eshm.as_slice().iter().all(|b| *b == 0));
+
+        // the memory is still mapped and usable
+        eshm.copy_from_slice(b"def", PAGE_SIZE_USIZE).unwrap();
+        assert_eq!(
+            b"def",
+            &eshm.as_slice()[PAGE_SIZE_USIZE..PAGE_SIZE_USIZE + 3]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn release_zero_pages() {
+        use crate::sandbox::config::MemoryPopulation;
+
+        let mut eshm = ExclusiveSharedMemory::new(PAGE_SIZE_USIZE * 4).unwrap();
+        eshm.copy_from_slice(b"abc", PAGE_SIZE_USIZE).unwrap();
+        eshm.prefault().unwrap();
+
+        // only the page holding data is kept
+        assert_eq!(3, eshm.release_zero_pages().unwrap());
+        assert_eq!(MemoryPopulation::Lazy, eshm.memory_population());
+        assert_eq!(
+            b"abc",
+            &eshm.as_slice()[PAGE_SIZE_USIZE..PAGE_SIZE_USIZE + 3]
+        );
+        assert!(eshm.as_slice()[PAGE_SIZE_USIZE + 3..]
+            .iter()
+            .all(|b| *b == 0));
+    }
+
     #[test]
     fn clone() {
         let eshm = ExclusiveSharedMemory::new(PAGE_SIZE_USIZE).unwrap();
@@ -1178,6 +1429,55 @@ mod tests {
         drop(hshm2);
     }
 
+    #[test]
+    fn pop_buffer_rejects_corrupted_elements() {
+        use hyperlight_common::flatbuffer_wrappers::function_types::ReturnValue;
+
+        const BUFFER_SIZE: usize = 1024;
+        let data = Vec::<u8>::try_from(&ReturnValue::Int(42)).unwrap();
+        let new_hshm = || {
+            let eshm = ExclusiveSharedMemory::new(4096).unwrap();
+            let (mut hshm, _) = eshm.build();
+            hshm.write::<u64>(0, 8).unwrap();
+            hshm.push_buffer(0, BUFFER_SIZE, &data).unwrap();
+            hshm
+        };
+        let back_pointer_offset = 8 + data.len();
+
+        let mut hshm = new_hshm();
+        let popped: ReturnValue = hshm.try_pop_buffer_into(0, BUFFER_SIZE).unwrap();
+        assert!(matches!(popped, ReturnValue::Int(42)));
+
+        // offset to the element points past the stack pointer
+        let mut hshm = new_hshm();
+        hshm.write::<u64>(back_pointer_offset, (BUFFER_SIZE * 2) as u64)
+            .unwrap();
+        assert!(hshm
+            .try_pop_buffer_into::<ReturnValue>(0, BUFFER_SIZE)
+            .is_err());
+
+        // offset to the element points into the stack pointer
+        let mut hshm = new_hshm();
+        hshm.write::<u64>(back_pointer_offset, 0).unwrap();
+        assert!(hshm
+            .try_pop_buffer_into::<ReturnValue>(0, BUFFER_SIZE)
+            .is_err());
+
+        // size prefix is larger than the element
+        let mut hshm = new_hshm();
+        hshm.write::<u32>(8, u32::MAX).unwrap();
+        assert!(hshm
+            .try_pop_buffer_into::<ReturnValue>(0, BUFFER_SIZE)
+            .is_err());
+
+        // size prefix is smaller than the flatbuffer
+        let mut hshm = new_hshm();
+        hshm.write::<u32>(8, 4).unwrap();
+        assert!(hshm
+            .try_pop_buffer_into::<ReturnValue>(0, BUFFER_SIZE)
+            .is_err());
+    }
+
     #[test]
     fn copy_all_to_vec() {
         let mut data = vec![b'a', b'b', b'c'];
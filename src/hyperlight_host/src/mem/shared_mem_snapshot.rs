@@ -14,10 +14,11 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use hyperlight_common::mem::PAGE_SIZE_USIZE;
 use tracing::{instrument, Span};
 
 use super::shared_mem::SharedMemory;
-use crate::Result;
+use crate::{new_error, Result};
 
 /// A wrapper around a `SharedMemory` reference and a snapshot
 /// of the memory therein
@@ -45,14 +46,74 @@ impl SharedMemorySnapshot {
         Ok(())
     }
 
+    /// Create a snapshot from memory contents that were copied out of a
+    /// `SharedMemory` earlier
+    pub(super) fn from_vec(snapshot: Vec<u8>) -> Self {
+        Self { snapshot }
+    }
+
+    /// Overwrite the memory contents stored in this snapshot at `offset`
+    /// with `data`, so that restoring the snapshot keeps `data`
+    pub(super) fn write_at(&mut self, offset: usize, data: &[u8]) -> Result<()> {
+        self.snapshot
+            .get_mut(offset..offset + data.len())
+            .ok_or_else(|| {
+                new_error!(
+                    "Writing {} bytes at offset {:#x} is outside the {} byte snapshot",
+                    data.len(),
+                    offset,
+                    self.snapshot.len()
+                )
+            })?
+            .copy_from_slice(data);
+        Ok(())
+    }
+
+    /// The memory contents stored in this snapshot
+    pub(super) fn as_slice(&self) -> &[u8] {
+        &self.snapshot
+    }
+
     /// Copy the memory from the internally-stored memory snapshot
-    /// into the internally-stored `SharedMemory`
+    /// into the internally-stored `SharedMemory`.
+    ///
+    /// Every page is compared with the snapshot and only the pages that
+    /// differ are written. This doesn't use the hypervisor's dirty page
+    /// log: the whole memory is still read on every restore, but pages
+    /// that weren't changed aren't copied or written back. Since every page
+    /// is compared, the memory is left exactly as it was when the snapshot
+    /// was taken, whatever wrote to it.
     #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
     pub(super) fn restore_from_snapshot<S: SharedMemory>(
         &mut self,
         shared_mem: &mut S,
     ) -> Result<()> {
-        shared_mem.with_exclusivity(|e| e.copy_from_slice(self.snapshot.as_slice(), 0))?
+        let restored = shared_mem.with_exclusivity(|e| {
+            let memory = e.as_mut_slice();
+            if memory.len() != self.snapshot.len() {
+                return Err(new_error!(
+                    "Cannot restore a {} byte snapshot into {} bytes of memory",
+                    self.snapshot.len(),
+                    memory.len()
+                ));
+            }
+            let mut restored: usize = 0;
+            for (page, snapshot_page) in memory
+                .chunks_mut(PAGE_SIZE_USIZE)
+                .zip(self.snapshot.chunks(PAGE_SIZE_USIZE))
+            {
+                if page != snapshot_page {
+                    page.copy_from_slice(snapshot_page);
+                    restored += 1;
+                }
+            }
+            Ok(restored)
+        })??;
+        log::trace!(
+            "Restored {} pages that differed from the snapshot",
+            restored
+        );
+        Ok(())
     }
 }
 
@@ -62,6 +123,23 @@ mod tests {
 
     use crate::mem::shared_mem::ExclusiveSharedMemory;
 
+    #[test]
+    fn restore_changed_pages() {
+        let data: Vec<u8> = (0..4 * PAGE_SIZE_USIZE).map(|i| i as u8).collect();
+        let mut gm = ExclusiveSharedMemory::new(data.len()).unwrap();
+        gm.copy_from_slice(data.as_slice(), 0).unwrap();
+        let mut snap = super::SharedMemorySnapshot::new(&mut gm).unwrap();
+
+        // write a byte in the middle of one page and across the boundary of
+        // two others
+        gm.copy_from_slice(&[0xff], PAGE_SIZE_USIZE + 7).unwrap();
+        gm.copy_from_slice(&[0xee; 2], 3 * PAGE_SIZE_USIZE - 1)
+            .unwrap();
+        assert_ne!(data, gm.copy_all_to_vec().unwrap());
+        snap.restore_from_snapshot(&mut gm).unwrap();
+        assert_eq!(data, gm.copy_all_to_vec().unwrap());
+    }
+
     #[test]
     fn restore_replace() {
         let mut data1 = vec![b'a', b'b', b'c'];
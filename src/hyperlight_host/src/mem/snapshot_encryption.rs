@@ -0,0 +1,328 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Authenticated encryption of snapshot files and hibernation files, so
+//! that guest memory never reaches the disk in plaintext.
+//!
+//! Keys are supplied by the embedder, typically one per tenant: snapshot
+//! files are encrypted with the key given to `SnapshotEncoder::set_key`,
+//! and hibernation files with the key returned by the provider set with
+//! `MultiUseSandbox::set_hibernation_key_provider`, which can fetch it from
+//! a key management service.
+//!
+//! The data is encrypted with XChaCha20-Poly1305 in chunks of `CHUNK_LEN`
+//! bytes, each authenticated on its own so that a file can be written and
+//! read without holding all of it in memory. The nonce of each chunk is a
+//! random prefix, written before the first chunk, followed by the index of
+//! the chunk and whether it is the last one, so chunks can't be reordered,
+//! dropped or appended without decryption failing.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+use crate::Result;
+
+/// The length of a `SnapshotKey`, in bytes
+pub const SNAPSHOT_KEY_LEN: usize = 32;
+
+/// The length of the random part of each chunk's nonce
+const NONCE_PREFIX_LEN: usize = 19;
+/// The length of the plaintext of every chunk but the last
+const CHUNK_LEN: usize = 64 * 1024;
+/// The length of the authentication tag after each chunk
+const TAG_LEN: usize = 16;
+
+/// A key snapshot and hibernation files are encrypted with. The key is
+/// overwritten with zeroes when it is dropped, and is never printed.
+#[derive(Clone)]
+pub struct SnapshotKey([u8; SNAPSHOT_KEY_LEN]);
+
+impl SnapshotKey {
+    /// Create a new `SnapshotKey` from its bytes
+    pub fn new(bytes: [u8; SNAPSHOT_KEY_LEN]) -> Self {
+        Self(bytes)
+    }
+
+    /// Create a new random `SnapshotKey`
+    pub fn generate() -> Self {
+        Self(rand::random())
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(Key::from_slice(&self.0))
+    }
+}
+
+impl fmt::Debug for SnapshotKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SnapshotKey(..)")
+    }
+}
+
+impl Drop for SnapshotKey {
+    fn drop(&mut self) {
+        for byte in self.0.iter_mut() {
+            // Safety: `byte` is a valid, aligned reference. The write is
+            // volatile so it isn't optimised away as a dead store.
+            unsafe { std::ptr::write_volatile(byte, 0) };
+        }
+    }
+}
+
+/// Returns the key the hibernation file of the sandbox with the given ID is
+/// encrypted with, see `MultiUseSandbox::set_hibernation_key_provider`
+pub type SnapshotKeyProvider = Arc<dyn Fn(u64) -> Result<SnapshotKey> + Send + Sync>;
+
+fn chunk_nonce(prefix: &[u8; NONCE_PREFIX_LEN], index: u32, last: bool) -> XNonce {
+    let mut nonce = [0; NONCE_PREFIX_LEN + 5];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..NONCE_PREFIX_LEN + 4].copy_from_slice(&index.to_be_bytes());
+    nonce[NONCE_PREFIX_LEN + 4] = last as u8;
+    XNonce::clone_from_slice(&nonce)
+}
+
+/// Encrypts everything written to it before writing it to the inner
+/// writer. The last chunk is only written by `finish`, which must be
+/// called once everything has been written.
+pub(crate) struct EncryptingWriter<W: Write> {
+    inner: W,
+    cipher: XChaCha20Poly1305,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    aad: Vec<u8>,
+    next_index: u32,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncryptingWriter<W> {
+    /// Write a random nonce prefix to `inner`, then encrypt what's written
+    /// after it with `key`, authenticating `aad` along with every chunk
+    pub(crate) fn new(key: &SnapshotKey, aad: &[u8], mut inner: W) -> Result<Self> {
+        let nonce_prefix: [u8; NONCE_PREFIX_LEN] = rand::random();
+        inner.write_all(&nonce_prefix)?;
+        Ok(Self {
+            inner,
+            cipher: key.cipher(),
+            nonce_prefix,
+            aad: aad.to_vec(),
+            next_index: 0,
+            buffer: Vec::with_capacity(CHUNK_LEN),
+        })
+    }
+
+    /// Encrypt and write the first `len` bytes of the buffer as a chunk
+    fn seal(&mut self, len: usize, last: bool) -> io::Result<()> {
+        let nonce = chunk_nonce(&self.nonce_prefix, self.next_index, last);
+        let payload = Payload {
+            msg: &self.buffer[..len],
+            aad: &self.aad,
+        };
+        let chunk = self
+            .cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| io::Error::other("failed to encrypt a chunk"))?;
+        self.inner.write_all(&chunk)?;
+        self.buffer.drain(..len);
+        self.next_index = self
+            .next_index
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("too many chunks to encrypt"))?;
+        Ok(())
+    }
+
+    /// Encrypt and write the last chunk, and return the inner writer
+    pub(crate) fn finish(mut self) -> Result<W> {
+        // every full chunk has already been written, so the last chunk is
+        // always shorter than `CHUNK_LEN`, which is how readers find it
+        self.seal(self.buffer.len(), true)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for EncryptingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while self.buffer.len() >= CHUNK_LEN {
+            self.seal(CHUNK_LEN, false)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts what's read from the inner reader, which was written by an
+/// `EncryptingWriter`. Reads fail with `io::ErrorKind::InvalidData` if a
+/// chunk fails to authenticate, because the key or associated data is
+/// wrong or the data was modified or truncated.
+pub(crate) struct DecryptingReader<R: Read> {
+    inner: R,
+    cipher: XChaCha20Poly1305,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+    aad: Vec<u8>,
+    next_index: u32,
+    /// The decrypted chunk being read, and how much of it has been read
+    plaintext: Vec<u8>,
+    position: usize,
+    /// Whether the last chunk has been decrypted
+    done: bool,
+}
+
+impl<R: Read> DecryptingReader<R> {
+    /// Read the nonce prefix from `inner`, then decrypt what follows it
+    /// with `key`, authenticating `aad` along with every chunk
+    pub(crate) fn new(key: &SnapshotKey, aad: &[u8], mut inner: R) -> Result<Self> {
+        let mut nonce_prefix = [0; NONCE_PREFIX_LEN];
+        inner.read_exact(&mut nonce_prefix)?;
+        Ok(Self {
+            inner,
+            cipher: key.cipher(),
+            nonce_prefix,
+            aad: aad.to_vec(),
+            next_index: 0,
+            plaintext: Vec::new(),
+            position: 0,
+            done: false,
+        })
+    }
+
+    /// Read and decrypt the next chunk
+    fn open(&mut self) -> io::Result<()> {
+        let mut chunk = vec![0; CHUNK_LEN + TAG_LEN];
+        let mut len = 0;
+        while len < chunk.len() {
+            match self.inner.read(&mut chunk[len..]) {
+                Ok(0) => break,
+                Ok(n) => len += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        if len < TAG_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the encrypted data is truncated",
+            ));
+        }
+        // only the last chunk is shorter than a full chunk
+        let last = len < chunk.len();
+        let nonce = chunk_nonce(&self.nonce_prefix, self.next_index, last);
+        let payload = Payload {
+            msg: &chunk[..len],
+            aad: &self.aad,
+        };
+        self.plaintext = self.cipher.decrypt(&nonce, payload).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the encrypted data failed to authenticate, the key is wrong or the data was modified",
+            )
+        })?;
+        self.position = 0;
+        self.next_index = self.next_index.wrapping_add(1);
+        self.done = last;
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for DecryptingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.open()?;
+        }
+        let len = buf.len().min(self.plaintext.len() - self.position);
+        buf[..len].copy_from_slice(&self.plaintext[self.position..self.position + len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+
+    use super::{DecryptingReader, EncryptingWriter, SnapshotKey, CHUNK_LEN};
+
+    fn encrypt(key: &SnapshotKey, aad: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut writer = EncryptingWriter::new(key, aad, Vec::new()).unwrap();
+        // write in uneven pieces, so chunks are split across writes
+        for piece in data.chunks(1000) {
+            writer.write_all(piece).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn decrypt(key: &SnapshotKey, aad: &[u8], encrypted: &[u8]) -> std::io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        DecryptingReader::new(key, aad, encrypted)
+            .unwrap()
+            .read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn round_trip() {
+        let key = SnapshotKey::generate();
+        for len in [0, 1, CHUNK_LEN - 1, CHUNK_LEN, CHUNK_LEN * 2 + 5] {
+            let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let encrypted = encrypt(&key, b"aad", &data);
+            if len > 0 {
+                assert_ne!(data.as_slice(), &encrypted[encrypted.len() - len..]);
+            }
+            assert_eq!(data, decrypt(&key, b"aad", &encrypted).unwrap());
+        }
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let key = SnapshotKey::generate();
+        let data = vec![7; CHUNK_LEN * 2];
+        let encrypted = encrypt(&key, b"aad", &data);
+        let is_invalid = |res: std::io::Result<Vec<u8>>| matches!(res, Err(e) if e.kind() == ErrorKind::InvalidData);
+
+        assert!(is_invalid(decrypt(
+            &SnapshotKey::generate(),
+            b"aad",
+            &encrypted
+        )));
+        assert!(is_invalid(decrypt(&key, b"other", &encrypted)));
+
+        let mut modified = encrypted.clone();
+        modified[100] ^= 1;
+        assert!(is_invalid(decrypt(&key, b"aad", &modified)));
+
+        // dropping the last chunk, or part of it, is detected
+        for len in [encrypted.len() - 1, encrypted.len() - 17] {
+            assert!(is_invalid(decrypt(&key, b"aad", &encrypted[..len])));
+        }
+        let mut appended = encrypted.clone();
+        appended.extend_from_slice(&[0; 20]);
+        assert!(is_invalid(decrypt(&key, b"aad", &appended)));
+    }
+
+    #[test]
+    fn keys_are_not_printed() {
+        let key = SnapshotKey::new([0xab; 32]);
+        assert_eq!("SnapshotKey(..)", format!("{:?}", key));
+    }
+}
@@ -0,0 +1,671 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::io::{Read, Write};
+
+use hyperlight_common::mem::PAGE_SIZE_USIZE;
+use tracing::{instrument, Span};
+
+use super::layout::SandboxMemoryLayout;
+use super::snapshot_encryption::{DecryptingReader, EncryptingWriter, SnapshotKey};
+use crate::HyperlightError::InvalidSnapshotFile;
+use crate::{new_error, Result};
+
+/// The bytes every snapshot file starts with
+const MAGIC: [u8; 8] = *b"HLSNAPSH";
+/// The length of the uncompressed header at the start of a snapshot file
+const HEADER_LEN: usize = 48;
+
+/// The newest version of the snapshot file format, written by this crate
+/// for encrypted snapshots. Unencrypted snapshots are written as version 1,
+/// which older readers can read. Snapshot files of this version or older
+/// can be read; newer ones are rejected.
+pub const SNAPSHOT_FORMAT_VERSION: u16 = 2;
+
+/// The flag set in the high byte of the `kind` field of encrypted snapshot
+/// files, from version 2
+const KIND_FLAG_ENCRYPTED: u16 = 0x100;
+
+/// The zstd compression level snapshots are written with by default
+pub const DEFAULT_SNAPSHOT_COMPRESSION_LEVEL: i32 = 3;
+
+/// Whether a snapshot file holds all of a guest's memory, or only the pages
+/// that changed since the previous snapshot in its chain
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SnapshotKind {
+    /// Every page of the memory that isn't all zeroes
+    Full,
+    /// The pages that changed since the previous snapshot in the chain
+    Incremental,
+}
+
+/// The uncompressed header at the start of every snapshot file, as
+/// described in `docs/snapshot-file-format.md`
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct SnapshotHeader {
+    /// The version of the format the file was written in
+    pub version: u16,
+    /// Whether the file holds a full or an incremental snapshot
+    pub kind: SnapshotKind,
+    /// Whether the pages in the file are encrypted, see
+    /// `SnapshotEncoder::set_key`
+    pub encrypted: bool,
+    /// The size of each page stored in the file
+    pub page_size: u32,
+    /// The size of the guest memory the snapshot was taken of
+    pub memory_size: u64,
+    /// Identifies the chain of snapshots the file belongs to
+    pub chain_id: u64,
+    /// The position of the snapshot in its chain, starting at 0 for the
+    /// full snapshot
+    pub sequence: u64,
+    /// The number of pages stored in the file
+    pub page_count: u64,
+}
+
+impl SnapshotHeader {
+    /// Read and validate the header at the start of `input`, leaving
+    /// `input` at the start of the compressed pages
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub fn read_from(mut input: impl Read) -> Result<Self> {
+        let mut bytes = [0; HEADER_LEN];
+        input.read_exact(&mut bytes).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => {
+                InvalidSnapshotFile("the file is too short to hold a snapshot header".to_string())
+            }
+            _ => e.into(),
+        })?;
+        if bytes[..8] != MAGIC {
+            return Err(InvalidSnapshotFile(
+                "not a hyperlight snapshot file".to_string(),
+            ));
+        }
+
+        let version = u16::from_le_bytes([bytes[8], bytes[9]]);
+        if version == 0 || version > SNAPSHOT_FORMAT_VERSION {
+            return Err(InvalidSnapshotFile(format!(
+                "format version {} is not supported, the newest supported version is {}",
+                version, SNAPSHOT_FORMAT_VERSION
+            )));
+        }
+        let mut kind = u16::from_le_bytes([bytes[10], bytes[11]]);
+        let encrypted = version >= 2 && kind & KIND_FLAG_ENCRYPTED != 0;
+        if encrypted {
+            kind &= !KIND_FLAG_ENCRYPTED;
+        }
+        let kind = match kind {
+            0 => SnapshotKind::Full,
+            1 => SnapshotKind::Incremental,
+            kind => {
+                return Err(InvalidSnapshotFile(format!(
+                    "unknown snapshot kind {}",
+                    kind
+                )))
+            }
+        };
+        let header = Self {
+            version,
+            kind,
+            encrypted,
+            page_size: u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+            memory_size: read_u64(&bytes[16..24]),
+            chain_id: read_u64(&bytes[24..32]),
+            sequence: read_u64(&bytes[32..40]),
+            page_count: read_u64(&bytes[40..48]),
+        };
+
+        // the encoder only writes pages of the size sandboxes map their
+        // memory in, and no sandbox has more memory than its page tables
+        // can map, so a header claiming otherwise can't make the reader
+        // allocate more memory than a sandbox could have
+        if header.page_size as usize != PAGE_SIZE_USIZE {
+            return Err(InvalidSnapshotFile(format!(
+                "page size {:#x} is not supported, expected {:#x}",
+                header.page_size, PAGE_SIZE_USIZE
+            )));
+        }
+        if header.memory_size > SandboxMemoryLayout::MAX_MEMORY_SIZE as u64 {
+            return Err(InvalidSnapshotFile(format!(
+                "memory size {:#x} is larger than the most memory a sandbox can have, {:#x}",
+                header.memory_size,
+                SandboxMemoryLayout::MAX_MEMORY_SIZE
+            )));
+        }
+        if header.memory_size % header.page_size as u64 != 0 {
+            return Err(InvalidSnapshotFile(format!(
+                "memory size {:#x} is not a multiple of page size {:#x}",
+                header.memory_size, header.page_size
+            )));
+        }
+        if header.page_count > header.memory_size / header.page_size as u64 {
+            return Err(InvalidSnapshotFile(format!(
+                "{} pages don't fit in memory size {:#x}",
+                header.page_count, header.memory_size
+            )));
+        }
+        Ok(header)
+    }
+
+    /// The bytes of this header, as written at the start of the file. The
+    /// pages of encrypted files are authenticated along with them.
+    fn to_bytes(self) -> [u8; HEADER_LEN] {
+        let mut kind: u16 = match self.kind {
+            SnapshotKind::Full => 0,
+            SnapshotKind::Incremental => 1,
+        };
+        if self.encrypted {
+            kind |= KIND_FLAG_ENCRYPTED;
+        }
+        let mut bytes = [0; HEADER_LEN];
+        bytes[..8].copy_from_slice(&MAGIC);
+        bytes[8..10].copy_from_slice(&self.version.to_le_bytes());
+        bytes[10..12].copy_from_slice(&kind.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.page_size.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.memory_size.to_le_bytes());
+        bytes[24..32].copy_from_slice(&self.chain_id.to_le_bytes());
+        bytes[32..40].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[40..48].copy_from_slice(&self.page_count.to_le_bytes());
+        bytes
+    }
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut le_bytes = [0; 8];
+    le_bytes.copy_from_slice(bytes);
+    u64::from_le_bytes(le_bytes)
+}
+
+/// Writes a guest's memory as a chain of snapshot files: a full snapshot,
+/// followed by incremental snapshots holding only the pages that changed
+/// since the previous snapshot in the chain, all compressed with zstd.
+///
+/// The encoder keeps a copy of the memory it last wrote, to compare the
+/// next snapshot against. Create a new encoder to start a new chain.
+#[derive(Debug)]
+pub struct SnapshotEncoder {
+    chain_id: u64,
+    next_sequence: u64,
+    previous: Option<Vec<u8>>,
+    compression_level: i32,
+    key: Option<SnapshotKey>,
+}
+
+impl Default for SnapshotEncoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_SNAPSHOT_COMPRESSION_LEVEL)
+    }
+}
+
+impl SnapshotEncoder {
+    /// Create a new `SnapshotEncoder`, that compresses snapshots with the
+    /// given zstd compression level
+    pub fn new(compression_level: i32) -> Self {
+        Self {
+            chain_id: rand::random(),
+            next_sequence: 0,
+            previous: None,
+            compression_level,
+            key: None,
+        }
+    }
+
+    /// Encrypt the pages of the snapshots written from now on with `key`,
+    /// or stop encrypting them if `key` is `None`. The header of an
+    /// encrypted snapshot isn't encrypted, but is authenticated along with
+    /// its pages, so a file that was modified, or is read with another
+    /// key, is rejected by `SnapshotDecoder::apply`.
+    pub fn set_key(&mut self, key: Option<SnapshotKey>) {
+        self.key = key;
+    }
+
+    /// Write a snapshot of `memory` to `out`, and return its header. The
+    /// first snapshot written by an encoder is a full snapshot, the
+    /// following ones are incremental.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub fn encode(&mut self, memory: &[u8], mut out: impl Write) -> Result<SnapshotHeader> {
+        if memory.len() % PAGE_SIZE_USIZE != 0 {
+            return Err(new_error!(
+                "Memory size {:#x} is not a multiple of the page size",
+                memory.len()
+            ));
+        }
+        let pages: Vec<usize> = match &self.previous {
+            None => memory
+                .chunks(PAGE_SIZE_USIZE)
+                .enumerate()
+                .filter(|(_, page)| page.iter().any(|b| *b != 0))
+                .map(|(i, _)| i)
+                .collect(),
+            Some(previous) if previous.len() != memory.len() => {
+                return Err(new_error!(
+                    "Memory size {:#x} differs from the previous snapshot's {:#x}",
+                    memory.len(),
+                    previous.len()
+                ))
+            }
+            Some(previous) => memory
+                .chunks(PAGE_SIZE_USIZE)
+                .zip(previous.chunks(PAGE_SIZE_USIZE))
+                .enumerate()
+                .filter(|(_, (page, previous_page))| page != previous_page)
+                .map(|(i, _)| i)
+                .collect(),
+        };
+
+        let header = SnapshotHeader {
+            // unencrypted snapshots are written in the oldest version that
+            // can hold them, so older readers can still read them
+            version: match self.key {
+                None => 1,
+                Some(_) => SNAPSHOT_FORMAT_VERSION,
+            },
+            kind: match self.previous {
+                None => SnapshotKind::Full,
+                Some(_) => SnapshotKind::Incremental,
+            },
+            encrypted: self.key.is_some(),
+            page_size: PAGE_SIZE_USIZE as u32,
+            memory_size: memory.len() as u64,
+            chain_id: self.chain_id,
+            sequence: self.next_sequence,
+            page_count: pages.len() as u64,
+        };
+        let header_bytes = header.to_bytes();
+        out.write_all(&header_bytes)?;
+        match &self.key {
+            None => self.write_pages(memory, &pages, out)?.flush()?,
+            Some(key) => {
+                let out = EncryptingWriter::new(key, &header_bytes, out)?;
+                self.write_pages(memory, &pages, out)?.finish()?.flush()?
+            }
+        }
+
+        match &mut self.previous {
+            Some(previous) => previous.copy_from_slice(memory),
+            None => self.previous = Some(memory.to_vec()),
+        }
+        self.next_sequence += 1;
+        Ok(header)
+    }
+
+    /// Compress the records of `pages` of `memory` to `out`, and return
+    /// `out`
+    fn write_pages<W: Write>(&self, memory: &[u8], pages: &[usize], out: W) -> Result<W> {
+        let mut encoder = zstd::Encoder::new(out, self.compression_level)?;
+        for page in pages {
+            let offset = page * PAGE_SIZE_USIZE;
+            encoder.write_all(&(*page as u64).to_le_bytes())?;
+            encoder.write_all(&memory[offset..offset + PAGE_SIZE_USIZE])?;
+        }
+        Ok(encoder.finish()?)
+    }
+}
+
+/// Reads a chain of snapshot files written by `SnapshotEncoder` back into
+/// a copy of the guest memory they were taken of.
+///
+/// Use `MultiUseSandbox::snapshot_decoder` to create a decoder for the
+/// snapshots to restore to a sandbox, which rejects snapshots of a
+/// different amount of memory before allocating any.
+#[derive(Debug, Default)]
+pub struct SnapshotDecoder {
+    chain_id: u64,
+    next_sequence: u64,
+    memory: Option<Vec<u8>>,
+    memory_size: Option<usize>,
+    key: Option<SnapshotKey>,
+}
+
+impl SnapshotDecoder {
+    /// Create a new `SnapshotDecoder`, which has not read any memory yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decrypt the snapshots applied from now on with `key`, which they
+    /// were written with by `SnapshotEncoder::set_key`. While a key is
+    /// set, unencrypted snapshots are rejected, so a snapshot can't be
+    /// swapped for an unencrypted one; without a key, encrypted snapshots
+    /// are rejected.
+    pub fn set_key(&mut self, key: Option<SnapshotKey>) {
+        self.key = key;
+    }
+
+    /// Reject snapshots applied from now on unless they were taken of
+    /// `memory_size` bytes of guest memory, or accept snapshots of any
+    /// size if `memory_size` is `None`
+    pub fn set_memory_size(&mut self, memory_size: Option<usize>) {
+        self.memory_size = memory_size;
+    }
+
+    /// Read the snapshot file in `input` and apply it to the memory read
+    /// so far, returning its header.
+    ///
+    /// A full snapshot replaces the memory read so far. An incremental
+    /// snapshot must be the next snapshot in the chain of the last
+    /// snapshot applied. If the snapshot can't be read, the memory read so
+    /// far is left unchanged.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level= "Trace")]
+    pub fn apply(&mut self, mut input: impl Read) -> Result<SnapshotHeader> {
+        let header = SnapshotHeader::read_from(&mut input)?;
+        let page_size = usize::try_from(header.page_size)?;
+        let memory_size = usize::try_from(header.memory_size)?;
+        if let Some(expected) = self.memory_size {
+            if memory_size != expected {
+                return Err(InvalidSnapshotFile(format!(
+                    "memory size {:#x} differs from the expected {:#x}",
+                    memory_size, expected
+                )));
+            }
+        }
+        if header.kind == SnapshotKind::Incremental {
+            match &self.memory {
+                None => {
+                    return Err(InvalidSnapshotFile(
+                        "an incremental snapshot must follow a full snapshot".to_string(),
+                    ))
+                }
+                Some(_) if header.chain_id != self.chain_id => {
+                    return Err(InvalidSnapshotFile(format!(
+                        "snapshot belongs to chain {:#x}, expected chain {:#x}",
+                        header.chain_id, self.chain_id
+                    )))
+                }
+                Some(_) if header.sequence != self.next_sequence => {
+                    return Err(InvalidSnapshotFile(format!(
+                        "snapshot {} can't be applied after snapshot {}",
+                        header.sequence,
+                        self.next_sequence.wrapping_sub(1)
+                    )))
+                }
+                Some(memory) if memory.len() != memory_size => {
+                    return Err(InvalidSnapshotFile(format!(
+                        "memory size {:#x} differs from the previous snapshot's {:#x}",
+                        memory_size,
+                        memory.len()
+                    )))
+                }
+                Some(_) => {}
+            }
+        }
+
+        // read every page before changing the memory, so a truncated or
+        // corrupted file doesn't leave it half updated
+        let record_len = page_size.checked_add(8).ok_or_else(|| {
+            InvalidSnapshotFile(format!("page size {:#x} is too large", page_size))
+        })?;
+        let records_len = usize::try_from(header.page_count)?
+            .checked_mul(record_len)
+            .ok_or_else(|| {
+                InvalidSnapshotFile(format!("{} pages are too many", header.page_count))
+            })?;
+        let records = match (&self.key, header.encrypted) {
+            (None, false) => read_records(zstd::Decoder::new(input)?, records_len)?,
+            (Some(key), true) => {
+                let input = DecryptingReader::new(key, &header.to_bytes(), input)?;
+                read_records(zstd::Decoder::new(input)?, records_len)?
+            }
+            (None, true) => {
+                return Err(InvalidSnapshotFile(
+                    "the snapshot is encrypted, but no key was set".to_string(),
+                ))
+            }
+            (Some(_), false) => {
+                return Err(InvalidSnapshotFile(
+                    "the snapshot is not encrypted, but a key was set".to_string(),
+                ))
+            }
+        };
+        for record in records.chunks(record_len) {
+            let page = usize::try_from(read_u64(&record[..8]))?;
+            if page >= memory_size / page_size {
+                return Err(InvalidSnapshotFile(format!(
+                    "page {} is outside of memory size {:#x}",
+                    page, memory_size
+                )));
+            }
+        }
+
+        let memory = match header.kind {
+            SnapshotKind::Full => self.memory.insert(vec![0; memory_size]),
+            #[allow(clippy::unwrap_used)] // checked above
+            SnapshotKind::Incremental => self.memory.as_mut().unwrap(),
+        };
+        for record in records.chunks(record_len) {
+            let offset = read_u64(&record[..8]) as usize * page_size;
+            memory[offset..offset + page_size].copy_from_slice(&record[8..]);
+        }
+        self.chain_id = header.chain_id;
+        self.next_sequence = header.sequence.wrapping_add(1);
+        Ok(header)
+    }
+
+    /// The memory read so far, or `None` if no snapshot has been applied
+    pub fn memory(&self) -> Option<&[u8]> {
+        self.memory.as_deref()
+    }
+}
+
+/// Read the `len` bytes of page records from `input`. The buffer grows as
+/// the records are read, rather than being allocated up front, so a file
+/// whose header claims more pages than it holds fails once its data runs
+/// out.
+fn read_records(input: impl Read, len: usize) -> Result<Vec<u8>> {
+    let mut records = Vec::new();
+    input
+        .take(len as u64)
+        .read_to_end(&mut records)
+        .map_err(|e| match e.kind() {
+            std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof => {
+                InvalidSnapshotFile(e.to_string())
+            }
+            _ => e.into(),
+        })?;
+    if records.len() != len {
+        return Err(InvalidSnapshotFile(format!(
+            "the file holds {:#x} bytes of pages, the header claims {:#x}",
+            records.len(),
+            len
+        )));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::mem::PAGE_SIZE_USIZE;
+
+    use super::{SnapshotDecoder, SnapshotEncoder, SnapshotHeader, SnapshotKind};
+    use crate::mem::snapshot_encryption::SnapshotKey;
+    use crate::HyperlightError;
+
+    #[test]
+    fn full_then_incremental() {
+        let mut memory = vec![0; PAGE_SIZE_USIZE * 8];
+        memory[0] = 1;
+        memory[PAGE_SIZE_USIZE * 3] = 2;
+        let mut encoder = SnapshotEncoder::default();
+        let mut decoder = SnapshotDecoder::new();
+
+        let mut full = Vec::new();
+        let header = encoder.encode(&memory, &mut full).unwrap();
+        assert_eq!(SnapshotKind::Full, header.kind);
+        // pages that are all zeroes are not stored
+        assert_eq!(2, header.page_count);
+        assert_eq!(header, decoder.apply(full.as_slice()).unwrap());
+        assert_eq!(Some(memory.as_slice()), decoder.memory());
+
+        memory[PAGE_SIZE_USIZE * 5 + 7] = 3;
+        let mut incremental = Vec::new();
+        let header = encoder.encode(&memory, &mut incremental).unwrap();
+        assert_eq!(SnapshotKind::Incremental, header.kind);
+        assert_eq!(1, header.sequence);
+        assert_eq!(1, header.page_count);
+        decoder.apply(incremental.as_slice()).unwrap();
+        assert_eq!(Some(memory.as_slice()), decoder.memory());
+
+        // applying the same incremental snapshot twice is rejected, and
+        // leaves the memory unchanged
+        let res = decoder.apply(incremental.as_slice());
+        assert!(matches!(res, Err(HyperlightError::InvalidSnapshotFile(_))));
+        assert_eq!(Some(memory.as_slice()), decoder.memory());
+    }
+
+    #[test]
+    fn incremental_from_another_chain() {
+        let memory = vec![1; PAGE_SIZE_USIZE];
+        let mut first = SnapshotEncoder::default();
+        let mut second = SnapshotEncoder::default();
+        let mut decoder = SnapshotDecoder::new();
+
+        let mut snapshot = Vec::new();
+        first.encode(&memory, &mut snapshot).unwrap();
+        decoder.apply(snapshot.as_slice()).unwrap();
+
+        second.encode(&memory, Vec::new()).unwrap();
+        let mut snapshot = Vec::new();
+        second.encode(&memory, &mut snapshot).unwrap();
+        let res = decoder.apply(snapshot.as_slice());
+        assert!(matches!(res, Err(HyperlightError::InvalidSnapshotFile(_))));
+    }
+
+    #[test]
+    fn encrypted() {
+        let mut memory = vec![0; PAGE_SIZE_USIZE * 4];
+        memory[..6].copy_from_slice(b"secret");
+        let key = SnapshotKey::generate();
+        let mut encoder = SnapshotEncoder::default();
+        encoder.set_key(Some(key.clone()));
+
+        let mut snapshot = Vec::new();
+        let header = encoder.encode(&memory, &mut snapshot).unwrap();
+        assert!(header.encrypted);
+        assert_eq!(2, header.version);
+        assert_eq!(
+            header,
+            SnapshotHeader::read_from(snapshot.as_slice()).unwrap()
+        );
+        assert!(!snapshot.windows(6).any(|w| w == b"secret"));
+
+        let is_invalid = |res: crate::Result<SnapshotHeader>| {
+            matches!(res, Err(HyperlightError::InvalidSnapshotFile(_)))
+        };
+        // without the key, or with another one
+        assert!(is_invalid(
+            SnapshotDecoder::new().apply(snapshot.as_slice())
+        ));
+        let mut decoder = SnapshotDecoder::new();
+        decoder.set_key(Some(SnapshotKey::generate()));
+        assert!(is_invalid(decoder.apply(snapshot.as_slice())));
+        assert_eq!(None, decoder.memory());
+
+        // the header is authenticated along with the pages
+        let mut modified = snapshot.clone();
+        modified[24] ^= 1;
+        let mut decoder = SnapshotDecoder::new();
+        decoder.set_key(Some(key.clone()));
+        assert!(is_invalid(decoder.apply(modified.as_slice())));
+
+        assert_eq!(header, decoder.apply(snapshot.as_slice()).unwrap());
+        assert_eq!(Some(memory.as_slice()), decoder.memory());
+
+        // while a key is set, unencrypted snapshots are rejected
+        let mut unencrypted = Vec::new();
+        let header = SnapshotEncoder::default()
+            .encode(&memory, &mut unencrypted)
+            .unwrap();
+        assert!(!header.encrypted);
+        assert_eq!(1, header.version);
+        assert!(is_invalid(decoder.apply(unencrypted.as_slice())));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut snapshot = Vec::new();
+        SnapshotEncoder::default()
+            .encode(&[0; PAGE_SIZE_USIZE], &mut snapshot)
+            .unwrap();
+        snapshot[8..10].copy_from_slice(&u16::MAX.to_le_bytes());
+        let res = SnapshotHeader::read_from(snapshot.as_slice());
+        assert!(matches!(res, Err(HyperlightError::InvalidSnapshotFile(_))));
+
+        let res = SnapshotHeader::read_from(&[0; 48][..]);
+        assert!(matches!(res, Err(HyperlightError::InvalidSnapshotFile(_))));
+    }
+
+    #[test]
+    fn truncated() {
+        let mut memory = vec![0; PAGE_SIZE_USIZE * 4];
+        memory
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, b)| *b = i as u8);
+        let mut snapshot = Vec::new();
+        SnapshotEncoder::default()
+            .encode(&memory, &mut snapshot)
+            .unwrap();
+
+        let mut decoder = SnapshotDecoder::new();
+        for len in [20, 48, 60, snapshot.len() - 1] {
+            let res = decoder.apply(&snapshot[..len]);
+            assert!(
+                matches!(res, Err(HyperlightError::InvalidSnapshotFile(_))),
+                "{:?}",
+                res
+            );
+            assert_eq!(None, decoder.memory());
+        }
+    }
+
+    #[test]
+    fn oversized_header() {
+        let mut snapshot = Vec::new();
+        let header = SnapshotEncoder::default()
+            .encode(&[1; PAGE_SIZE_USIZE], &mut snapshot)
+            .unwrap();
+        let with = |change: &dyn Fn(&mut SnapshotHeader)| {
+            let mut header = header;
+            change(&mut header);
+            let mut modified = snapshot.clone();
+            modified[..48].copy_from_slice(&header.to_bytes());
+            modified
+        };
+        let is_invalid = |snapshot: Vec<u8>| {
+            let res = SnapshotDecoder::new().apply(snapshot.as_slice());
+            matches!(res, Err(HyperlightError::InvalidSnapshotFile(_)))
+        };
+
+        // none of these allocate the memory or pages the header claims
+        assert!(is_invalid(with(&|h| h.page_size = u32::MAX)));
+        assert!(is_invalid(with(&|h| h.page_size = 1 << 31)));
+        assert!(is_invalid(with(&|h| {
+            h.memory_size = u64::MAX - (u64::MAX % PAGE_SIZE_USIZE as u64)
+        })));
+        assert!(is_invalid(with(&|h| {
+            h.memory_size = 1 << 38;
+            h.page_count = h.memory_size / PAGE_SIZE_USIZE as u64;
+        })));
+
+        // a decoder for a sandbox's memory rejects snapshots of any other
+        // size
+        let mut decoder = SnapshotDecoder::new();
+        decoder.set_memory_size(Some(PAGE_SIZE_USIZE * 2));
+        let res = decoder.apply(snapshot.as_slice());
+        assert!(matches!(res, Err(HyperlightError::InvalidSnapshotFile(_))));
+        decoder.set_memory_size(Some(PAGE_SIZE_USIZE));
+        decoder.apply(snapshot.as_slice()).unwrap();
+    }
+}
@@ -0,0 +1,229 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use once_cell::sync::Lazy;
+
+/// The upper bounds, in microseconds, of the buckets guest call latencies
+/// are counted in. Calls slower than the last bound are counted in an
+/// extra, unbounded, bucket.
+pub const GUEST_CALL_LATENCY_BUCKETS_MICROSECONDS: &[u64] = &[
+    50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000,
+    1_000_000,
+];
+
+/// The most guest functions metrics are recorded for by name. Calls to
+/// functions first called after this many functions have been recorded
+/// are counted together under `OTHER_GUEST_FUNCTIONS`, so that calls to
+/// many differently named functions can't grow the metrics without limit.
+pub const MAX_RECORDED_GUEST_FUNCTIONS: usize = 1024;
+
+/// The name the calls to guest functions that aren't recorded by name are
+/// counted under, see `MAX_RECORDED_GUEST_FUNCTIONS`
+pub const OTHER_GUEST_FUNCTIONS: &str = "<other>";
+
+/// The latency and errors of all the calls made to one guest function
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GuestFunctionMetrics {
+    /// The number of calls made to the function, including failed calls
+    pub call_count: u64,
+    /// The number of calls that returned an error
+    pub error_count: u64,
+    /// The sum of the latencies of all calls
+    pub total_latency: Duration,
+    /// The latency of the slowest call
+    pub max_latency: Duration,
+    /// The number of calls in each latency bucket. `latency_buckets[i]`
+    /// counts the calls that took at most
+    /// `GUEST_CALL_LATENCY_BUCKETS_MICROSECONDS[i]` microseconds, but more
+    /// than the previous bound. The last element counts the calls slower
+    /// than every bound.
+    pub latency_buckets: Vec<u64>,
+}
+
+impl Default for GuestFunctionMetrics {
+    fn default() -> Self {
+        Self {
+            call_count: 0,
+            error_count: 0,
+            total_latency: Duration::ZERO,
+            max_latency: Duration::ZERO,
+            latency_buckets: vec![0; GUEST_CALL_LATENCY_BUCKETS_MICROSECONDS.len() + 1],
+        }
+    }
+}
+
+impl GuestFunctionMetrics {
+    /// The mean latency of the calls made to the function, or `None` if
+    /// no calls were made
+    pub fn mean_latency(&self) -> Option<Duration> {
+        let count = u32::try_from(self.call_count).ok()?;
+        self.total_latency.checked_div(count)
+    }
+
+    /// An upper bound on the latency that `percentile` percent of the calls
+    /// completed within, from the bucket the percentile falls in. Returns
+    /// `None` if no calls were made, or if the percentile falls in the
+    /// unbounded bucket, in which case it is at most `max_latency`.
+    pub fn latency_percentile(&self, percentile: f64) -> Option<Duration> {
+        if self.call_count == 0 {
+            return None;
+        }
+        let target = ((percentile.clamp(0.0, 100.0) / 100.0) * self.call_count as f64).ceil();
+        let mut seen = 0;
+        for (count, bound) in self
+            .latency_buckets
+            .iter()
+            .zip(GUEST_CALL_LATENCY_BUCKETS_MICROSECONDS)
+        {
+            seen += count;
+            if seen as f64 >= target {
+                return Some(Duration::from_micros(*bound).min(self.max_latency));
+            }
+        }
+        None
+    }
+
+    fn record(&mut self, latency: Duration, failed: bool) {
+        self.call_count += 1;
+        if failed {
+            self.error_count += 1;
+        }
+        self.total_latency += latency;
+        self.max_latency = self.max_latency.max(latency);
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        let bucket = GUEST_CALL_LATENCY_BUCKETS_MICROSECONDS
+            .iter()
+            .position(|bound| micros <= *bound)
+            .unwrap_or(GUEST_CALL_LATENCY_BUCKETS_MICROSECONDS.len());
+        self.latency_buckets[bucket] += 1;
+    }
+}
+
+/// A point in time copy of the metrics hyperlight records for guest
+/// function calls, as returned by `metrics_snapshot`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// The metrics for each guest function called so far, by function
+    /// name, see `MAX_RECORDED_GUEST_FUNCTIONS`
+    pub guest_functions: HashMap<String, GuestFunctionMetrics>,
+}
+
+static GUEST_CALL_METRICS: Lazy<Mutex<HashMap<String, GuestFunctionMetrics>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Record a call to the guest function `function_name` that took
+/// `latency`, and whether it returned an error
+pub(crate) fn record_guest_call(function_name: &str, latency: Duration, failed: bool) {
+    // A poisoned lock only means another thread panicked while holding it,
+    // the metrics are still usable
+    let mut metrics = GUEST_CALL_METRICS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    record_in(&mut metrics, function_name, latency, failed);
+}
+
+fn record_in(
+    metrics: &mut HashMap<String, GuestFunctionMetrics>,
+    function_name: &str,
+    latency: Duration,
+    failed: bool,
+) {
+    if let Some(function_metrics) = metrics.get_mut(function_name) {
+        function_metrics.record(latency, failed);
+        return;
+    }
+    // the entry for the other functions doesn't count against the limit
+    let recorded = metrics.len() - usize::from(metrics.contains_key(OTHER_GUEST_FUNCTIONS));
+    let name = if recorded < MAX_RECORDED_GUEST_FUNCTIONS {
+        function_name
+    } else {
+        OTHER_GUEST_FUNCTIONS
+    };
+    metrics
+        .entry(name.to_string())
+        .or_default()
+        .record(latency, failed);
+}
+
+/// Return the latency histograms and error counts of every guest function
+/// called so far, across all the sandboxes in this process.
+///
+/// Unlike the prometheus metrics enabled by the `function_call_metrics`
+/// feature, these are always recorded and need no metrics registry.
+pub fn metrics_snapshot() -> MetricsSnapshot {
+    let metrics = GUEST_CALL_METRICS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    MetricsSnapshot {
+        guest_functions: metrics.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use super::{
+        metrics_snapshot, record_guest_call, record_in, GuestFunctionMetrics,
+        MAX_RECORDED_GUEST_FUNCTIONS, OTHER_GUEST_FUNCTIONS,
+    };
+
+    #[test]
+    fn records_latency_and_errors() {
+        let name = format!("test_function_{}", uuid::Uuid::new_v4());
+        record_guest_call(&name, Duration::from_micros(10), false);
+        record_guest_call(&name, Duration::from_micros(400), true);
+        record_guest_call(&name, Duration::from_secs(2), false);
+
+        let metrics = metrics_snapshot().guest_functions.remove(&name).unwrap();
+        assert_eq!(3, metrics.call_count);
+        assert_eq!(1, metrics.error_count);
+        assert_eq!(Duration::from_secs(2), metrics.max_latency);
+        assert_eq!(1, metrics.latency_buckets[0]);
+        assert_eq!(1, metrics.latency_buckets[3]);
+        assert_eq!(1, *metrics.latency_buckets.last().unwrap());
+        assert_eq!(
+            Some(Duration::from_micros(500)),
+            metrics.latency_percentile(50.0)
+        );
+        assert_eq!(None, metrics.latency_percentile(99.0));
+    }
+
+    #[test]
+    fn number_of_recorded_functions_is_capped() {
+        let mut metrics = HashMap::new();
+        for i in 0..MAX_RECORDED_GUEST_FUNCTIONS + 10 {
+            record_in(&mut metrics, &format!("f{}", i), Duration::ZERO, false);
+        }
+        // calls to functions that are already recorded still count for them
+        record_in(&mut metrics, "f0", Duration::ZERO, false);
+        assert_eq!(MAX_RECORDED_GUEST_FUNCTIONS + 1, metrics.len());
+        assert_eq!(10, metrics[OTHER_GUEST_FUNCTIONS].call_count);
+        assert_eq!(2, metrics["f0"].call_count);
+    }
+
+    #[test]
+    fn no_calls() {
+        let metrics = GuestFunctionMetrics::default();
+        assert_eq!(None, metrics.mean_latency());
+        assert_eq!(None, metrics.latency_percentile(50.0));
+    }
+}
@@ -43,6 +43,13 @@ pub use histogram_vec::HistogramVec;
 mod histogram;
 /// AHistogram for Hyperlight
 pub use histogram::Histogram;
+mod guest_calls;
+pub(crate) use guest_calls::record_guest_call;
+/// Latency histograms and error counts of guest function calls
+pub use guest_calls::{
+    metrics_snapshot, GuestFunctionMetrics, MetricsSnapshot,
+    GUEST_CALL_LATENCY_BUCKETS_MICROSECONDS, MAX_RECORDED_GUEST_FUNCTIONS, OTHER_GUEST_FUNCTIONS,
+};
 /// A trait that should be implemented by all enums that represent hyperlight metrics
 pub trait HyperlightMetricEnum<T>:
     IntoEnumIterator + VariantNames + From<T> + Into<&'static str>
@@ -0,0 +1,309 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::collections::{HashMap, VecDeque};
+
+/// Identifies the tenant a queued guest call belongs to
+pub type TenantId = u64;
+
+/// The priority of a queued guest call. Calls with a higher priority are
+/// always scheduled before calls with a lower priority, unless a lower
+/// priority call has waited long enough to be protected from starvation.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum CallPriority {
+    /// Scheduled only when there are no normal or high priority calls
+    Low = 0,
+    /// The priority of calls queued with `CallScheduler::push`
+    #[default]
+    Normal = 1,
+    /// Scheduled before any normal or low priority calls
+    High = 2,
+}
+
+const PRIORITY_LEVELS: usize = 3;
+
+/// The pass added to a tenant with weight 1 each time one of its calls is
+/// scheduled. Tenants with a higher weight advance by proportionally less,
+/// and so are scheduled proportionally more often.
+const STRIDE: u64 = 1 << 20;
+
+/// Schedules guest calls queued by several tenants against a shared set of
+/// sandboxes, so that a burst of calls from one tenant can't indefinitely
+/// delay the calls of the others.
+///
+/// - Calls are scheduled by `CallPriority` first.
+/// - Between tenants with calls of the same priority, calls are scheduled in
+///   proportion to the tenants' weights (stride scheduling). Tenants default
+///   to a weight of 1.
+/// - Calls of the same tenant and priority are scheduled in the order they
+///   were queued.
+/// - A tenant whose calls of a priority have been passed over for
+///   `max_wait_rounds` calls to `pop` has its next call of that priority
+///   scheduled regardless of its priority or weight. The wait is tracked
+///   per tenant, so a backlog of other tenants' calls doesn't make it
+///   wait longer, and of the tenants over the limit the one that has
+///   waited longest is scheduled first.
+///
+/// `CallScheduler` only decides the order calls run in, the caller owns
+/// the sandboxes and runs the calls. It is not synchronized, wrap it in a
+/// `Mutex` to share it between threads.
+#[derive(Debug)]
+pub struct CallScheduler<T> {
+    tenants: HashMap<TenantId, Tenant<T>>,
+    max_wait_rounds: u64,
+    /// The number of calls scheduled so far
+    round: u64,
+    /// The pass of the most recently scheduled tenant. Tenants that become
+    /// active again start from here, so they can't bank credit while idle.
+    virtual_time: u64,
+    len: usize,
+}
+
+#[derive(Debug)]
+struct Tenant<T> {
+    weight: u32,
+    pass: u64,
+    queues: [VecDeque<T>; PRIORITY_LEVELS],
+    /// For each priority, the round since which the tenant's calls of that
+    /// priority have been waiting: when the first of them was queued, or
+    /// when one of them was last scheduled
+    waiting_since: [u64; PRIORITY_LEVELS],
+}
+
+impl<T> Tenant<T> {
+    fn new(weight: u32) -> Self {
+        Self {
+            weight,
+            pass: 0,
+            queues: Default::default(),
+            waiting_since: [0; PRIORITY_LEVELS],
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.queues.iter().all(VecDeque::is_empty)
+    }
+}
+
+impl<T> CallScheduler<T> {
+    /// The default number of scheduling rounds a call can be passed over
+    /// for before it is scheduled regardless of priority
+    pub const DEFAULT_MAX_WAIT_ROUNDS: u64 = 1000;
+
+    /// Create a new, empty `CallScheduler`. A queued call is scheduled at
+    /// the latest after `max_wait_rounds` other calls have been scheduled
+    /// ahead of it. If `max_wait_rounds` is 0, calls are never scheduled
+    /// out of order to prevent starvation.
+    pub fn new(max_wait_rounds: u64) -> Self {
+        Self {
+            tenants: HashMap::new(),
+            max_wait_rounds,
+            round: 0,
+            virtual_time: 0,
+            len: 0,
+        }
+    }
+
+    /// Set the weight of `tenant`. A tenant with weight 2 is scheduled twice
+    /// as often as a tenant with weight 1 when both have calls of the same
+    /// priority queued. The minimum weight is 1.
+    pub fn set_tenant_weight(&mut self, tenant: TenantId, weight: u32) {
+        let weight = weight.max(1);
+        self.tenants
+            .entry(tenant)
+            .and_modify(|t| t.weight = weight)
+            .or_insert_with(|| Tenant::new(weight));
+    }
+
+    /// Queue a call with `CallPriority::Normal` for `tenant`
+    pub fn push(&mut self, tenant: TenantId, call: T) {
+        self.push_with_priority(tenant, CallPriority::Normal, call)
+    }
+
+    /// Queue a call with the given priority for `tenant`
+    pub fn push_with_priority(&mut self, tenant: TenantId, priority: CallPriority, call: T) {
+        let virtual_time = self.virtual_time;
+        let t = self.tenants.entry(tenant).or_insert_with(|| Tenant::new(1));
+        if t.is_idle() {
+            t.pass = t.pass.max(virtual_time);
+        }
+        let queue = &mut t.queues[priority as usize];
+        if queue.is_empty() {
+            t.waiting_since[priority as usize] = self.round;
+        }
+        queue.push_back(call);
+        self.len += 1;
+    }
+
+    /// Remove and return the next call to run, along with the tenant it
+    /// belongs to, or `None` if no calls are queued.
+    pub fn pop(&mut self) -> Option<(TenantId, T)> {
+        let (tenant, priority) = self.starved_call().or_else(|| self.fair_share_call())?;
+
+        let t = self.tenants.get_mut(&tenant)?;
+        let call = t.queues[priority].pop_front()?;
+        t.pass += STRIDE / u64::from(t.weight);
+        self.virtual_time = self.virtual_time.max(t.pass - STRIDE / u64::from(t.weight));
+        self.round += 1;
+        t.waiting_since[priority] = self.round;
+        self.len -= 1;
+        Some((tenant, call))
+    }
+
+    /// The total number of queued calls
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if no calls are queued
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of calls queued for `tenant`
+    pub fn queued_for(&self, tenant: TenantId) -> usize {
+        self.tenants
+            .get(&tenant)
+            .map_or(0, |t| t.queues.iter().map(VecDeque::len).sum())
+    }
+
+    /// The tenant and priority that have waited longest to be scheduled, if
+    /// they have waited for at least `max_wait_rounds`
+    fn starved_call(&self) -> Option<(TenantId, usize)> {
+        if self.max_wait_rounds == 0 {
+            return None;
+        }
+        self.tenants
+            .iter()
+            .flat_map(|(id, t)| {
+                (0..PRIORITY_LEVELS)
+                    .filter(|priority| !t.queues[*priority].is_empty())
+                    .map(move |priority| (t.waiting_since[priority], *id, priority))
+            })
+            .filter(|(waiting_since, _, _)| self.round - waiting_since >= self.max_wait_rounds)
+            .min()
+            .map(|(_, id, priority)| (id, priority))
+    }
+
+    /// The tenant with the lowest pass among those with calls of the
+    /// highest queued priority
+    fn fair_share_call(&self) -> Option<(TenantId, usize)> {
+        (0..PRIORITY_LEVELS).rev().find_map(|priority| {
+            self.tenants
+                .iter()
+                .filter(|(_, t)| !t.queues[priority].is_empty())
+                .min_by_key(|(id, t)| (t.pass, **id))
+                .map(|(id, _)| (*id, priority))
+        })
+    }
+}
+
+impl<T> Default for CallScheduler<T> {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MAX_WAIT_ROUNDS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CallPriority, CallScheduler};
+
+    #[test]
+    fn tenants_are_scheduled_in_proportion_to_weight() {
+        let mut scheduler = CallScheduler::new(0);
+        scheduler.set_tenant_weight(1, 3);
+        for i in 0..100 {
+            scheduler.push(1, i);
+            scheduler.push(2, i);
+        }
+        let first_40: Vec<_> = (0..40).map(|_| scheduler.pop().unwrap().0).collect();
+        assert_eq!(30, first_40.iter().filter(|t| **t == 1).count());
+        assert_eq!(10, first_40.iter().filter(|t| **t == 2).count());
+        assert_eq!(160, scheduler.len());
+    }
+
+    #[test]
+    fn burst_does_not_delay_other_tenants() {
+        let mut scheduler = CallScheduler::default();
+        for i in 0..1000 {
+            scheduler.push(1, i);
+        }
+        scheduler.pop().unwrap();
+        scheduler.push(2, 0);
+        // tenant 2 was idle, so it gets the next turn rather than waiting
+        // behind tenant 1's burst
+        assert_eq!(Some((2, 0)), scheduler.pop());
+        assert_eq!(Some((1, 1)), scheduler.pop());
+    }
+
+    #[test]
+    fn calls_of_a_tenant_keep_their_order() {
+        let mut scheduler = CallScheduler::default();
+        for i in 0..10 {
+            scheduler.push(7, i);
+        }
+        let calls: Vec<_> = std::iter::from_fn(|| scheduler.pop())
+            .map(|(_, c)| c)
+            .collect();
+        assert_eq!((0..10).collect::<Vec<_>>(), calls);
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn higher_priority_first() {
+        let mut scheduler = CallScheduler::default();
+        scheduler.push_with_priority(1, CallPriority::Low, "low");
+        scheduler.push(1, "normal");
+        scheduler.push_with_priority(2, CallPriority::High, "high");
+        assert_eq!(Some((2, "high")), scheduler.pop());
+        assert_eq!(Some((1, "normal")), scheduler.pop());
+        assert_eq!(Some((1, "low")), scheduler.pop());
+        assert_eq!(None, scheduler.pop());
+    }
+
+    #[test]
+    fn starved_calls_are_scheduled() {
+        let mut scheduler = CallScheduler::new(5);
+        scheduler.push_with_priority(1, CallPriority::Low, "low");
+        for _ in 0..10 {
+            scheduler.push_with_priority(2, CallPriority::High, "high");
+        }
+        let order: Vec<_> = (0..6).map(|_| scheduler.pop().unwrap().1).collect();
+        assert_eq!(vec!["high"; 5], order[..5]);
+        assert_eq!("low", order[5]);
+        assert_eq!(0, scheduler.queued_for(1));
+        assert_eq!(5, scheduler.queued_for(2));
+    }
+
+    #[test]
+    fn single_call_is_not_starved_by_another_tenants_burst() {
+        let mut scheduler = CallScheduler::new(5);
+        for _ in 0..1000 {
+            scheduler.push_with_priority(1, CallPriority::High, "burst");
+        }
+        for _ in 0..10 {
+            scheduler.pop().unwrap();
+        }
+        // tenant 1's oldest calls have waited far longer than tenant 2's
+        // call will, but tenant 1 is scheduled all the time
+        scheduler.push_with_priority(2, CallPriority::Low, "single");
+        let waited = std::iter::from_fn(|| scheduler.pop())
+            .position(|(tenant, _)| tenant == 2)
+            .unwrap();
+        assert_eq!(5, waited);
+        assert_eq!(1000 - 10 - 5, scheduler.queued_for(1));
+    }
+}
@@ -0,0 +1,470 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::{log_then_return, HyperlightError, Result};
+
+/// How many calls to a guest function can run at once, and how many more
+/// can wait for one of them to finish
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ConcurrencyLimit {
+    /// The most calls to the function that can run at once. The minimum
+    /// is 1.
+    pub max_concurrent: usize,
+    /// The most calls to the function that can wait for a running call to
+    /// finish. Calls beyond this are rejected.
+    pub max_queued: usize,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    running: usize,
+    queued: usize,
+}
+
+/// What happened to the calls to a function since its limit was set
+#[derive(Debug, Default)]
+struct History {
+    waited_calls: u64,
+    total_wait: Duration,
+    max_wait: Duration,
+    rejected_calls: u64,
+    finished_calls: u64,
+    total_run_time: Duration,
+}
+
+impl History {
+    /// How long a call rejected now should wait before it is tried again:
+    /// long enough for the calls ahead of it to finish, going by how long
+    /// calls have taken so far
+    fn retry_after(&self, limit: ConcurrencyLimit, queued: usize) -> Duration {
+        if self.finished_calls == 0 {
+            return FunctionConcurrencyLimits::DEFAULT_RETRY_AFTER;
+        }
+        let mean_run_time = mean(self.total_run_time, self.finished_calls);
+        let rounds = queued.checked_div(limit.max_concurrent).unwrap_or(queued) + 1;
+        mean_run_time.saturating_mul(u32::try_from(rounds).unwrap_or(u32::MAX))
+    }
+}
+
+/// The mean of `count` durations adding up to `total`, which `Duration`'s
+/// division by a `u32` can't compute once there are 2^32 of them
+fn mean(total: Duration, count: u64) -> Duration {
+    match total.as_nanos().checked_div(u128::from(count)) {
+        Some(nanos) => Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX)),
+        None => Duration::ZERO,
+    }
+}
+
+/// The load on a guest function with a `ConcurrencyLimit`, returned by
+/// `FunctionConcurrencyLimits::load`, e.g. to report to a load balancer.
+/// The counts of past calls cover the calls made since the function's
+/// limit was first set.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FunctionLoad {
+    /// The function's limit
+    pub limit: ConcurrencyLimit,
+    /// The number of calls holding a permit
+    pub running: usize,
+    /// The number of calls waiting for a permit, i.e. the queue depth
+    pub queued: usize,
+    /// The number of calls that had to wait for a permit
+    pub waited_calls: u64,
+    /// The total time calls waited for a permit
+    pub total_wait: Duration,
+    /// The longest time a call waited for a permit
+    pub max_wait: Duration,
+    /// The number of calls rejected with `HyperlightError::Overloaded`
+    pub rejected_calls: u64,
+}
+
+impl FunctionLoad {
+    /// How full the function's running calls and queue are, from 0 to 1.
+    /// At 1, further calls are rejected.
+    pub fn saturation(&self) -> f64 {
+        let capacity = self.limit.max_concurrent + self.limit.max_queued;
+        ((self.running + self.queued) as f64 / capacity as f64).min(1.0)
+    }
+
+    /// The mean time the calls that had to wait for a permit waited
+    pub fn mean_wait(&self) -> Duration {
+        mean(self.total_wait, self.waited_calls)
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    limits: HashMap<String, ConcurrencyLimit>,
+    usage: HashMap<String, Usage>,
+    history: HashMap<String, History>,
+}
+
+/// Limits how many calls to each guest function run at once when guest
+/// functions are called concurrently on a shared set of sandboxes, so
+/// that functions known to use a lot of memory can't be called in
+/// unbounded parallel.
+///
+/// Before calling a guest function, take a permit for it with `acquire`,
+/// and hold it until the call has finished. If the function already has
+/// `max_concurrent` calls running, `acquire` waits for one of them to
+/// finish, unless `max_queued` calls are already waiting, in which case it
+/// fails with `HyperlightError::Overloaded`, with a hint of when to try
+/// again. Calls to functions without a limit are never held back. The
+/// queue depth, wait times and saturation of each limited function are
+/// reported by `load`, so that callers can shed load before calls are
+/// rejected.
+///
+/// `FunctionConcurrencyLimits` only admits calls, the caller owns the
+/// sandboxes and runs the calls. It is synchronized, share it between
+/// threads in an `Arc`.
+#[derive(Debug, Default)]
+pub struct FunctionConcurrencyLimits {
+    state: Mutex<State>,
+    /// Signalled when a running call finishes
+    released: Condvar,
+}
+
+/// Permission to run a call to a guest function, given by
+/// `FunctionConcurrencyLimits::acquire`. The call is counted as running
+/// until the permit is dropped.
+#[derive(Debug)]
+#[must_use = "the call is only counted as running while the permit is held"]
+pub struct ConcurrencyPermit<'a> {
+    limits: &'a FunctionConcurrencyLimits,
+    /// The function the permit is for, or `None` if it has no limit
+    function: Option<String>,
+    acquired: Instant,
+}
+
+impl FunctionConcurrencyLimits {
+    /// The retry-after hint of calls rejected before any call to the
+    /// function has finished
+    pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_millis(100);
+
+    /// Create limits that don't limit any guest functions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit calls to the guest function `name`, replacing its previous
+    /// limit. Calls already running or waiting are not affected.
+    pub fn set_limit(&self, name: impl Into<String>, limit: ConcurrencyLimit) {
+        let limit = ConcurrencyLimit {
+            max_concurrent: limit.max_concurrent.max(1),
+            ..limit
+        };
+        self.lock().limits.insert(name.into(), limit);
+        // waiting calls may now fit under the new limit
+        self.released.notify_all();
+    }
+
+    /// Stop limiting calls to the guest function `name`
+    pub fn remove_limit(&self, name: &str) {
+        let mut state = self.lock();
+        state.limits.remove(name);
+        state.history.remove(name);
+        drop(state);
+        self.released.notify_all();
+    }
+
+    /// Take a permit to call the guest function `name`, waiting for a
+    /// running call to finish if it is at its concurrency limit, or fail
+    /// if too many calls are already waiting.
+    pub fn acquire(&self, name: &str) -> Result<ConcurrencyPermit<'_>> {
+        let mut state = self.lock();
+        let Some(limit) = state.limits.get(name).copied() else {
+            return Ok(ConcurrencyPermit {
+                limits: self,
+                function: None,
+                acquired: Instant::now(),
+            });
+        };
+        let usage = state.usage.entry(name.to_string()).or_default();
+        if usage.running >= limit.max_concurrent {
+            if usage.queued >= limit.max_queued {
+                let queued = usage.queued;
+                let history = state.history.entry(name.to_string()).or_default();
+                history.rejected_calls += 1;
+                let retry_after = history.retry_after(limit, queued);
+                log_then_return!(HyperlightError::Overloaded(name.to_string(), retry_after));
+            }
+            usage.queued += 1;
+            let waiting_since = Instant::now();
+            state = self.wait_for_turn(state, name);
+            let usage = state.usage.entry(name.to_string()).or_default();
+            usage.queued -= 1;
+            let wait = waiting_since.elapsed();
+            let history = state.history.entry(name.to_string()).or_default();
+            history.waited_calls += 1;
+            history.total_wait += wait;
+            history.max_wait = history.max_wait.max(wait);
+        }
+        state.usage.entry(name.to_string()).or_default().running += 1;
+        Ok(ConcurrencyPermit {
+            limits: self,
+            function: Some(name.to_string()),
+            acquired: Instant::now(),
+        })
+    }
+
+    /// The load on the guest function `name`, or `None` if it has no limit
+    pub fn load(&self, name: &str) -> Option<FunctionLoad> {
+        let state = self.lock();
+        let limit = *state.limits.get(name)?;
+        let (running, queued) = state
+            .usage
+            .get(name)
+            .map_or((0, 0), |u| (u.running, u.queued));
+        let history = state.history.get(name);
+        Some(FunctionLoad {
+            limit,
+            running,
+            queued,
+            waited_calls: history.map_or(0, |h| h.waited_calls),
+            total_wait: history.map_or(Duration::ZERO, |h| h.total_wait),
+            max_wait: history.map_or(Duration::ZERO, |h| h.max_wait),
+            rejected_calls: history.map_or(0, |h| h.rejected_calls),
+        })
+    }
+
+    /// The number of calls to the guest function `name` that hold a permit
+    pub fn running(&self, name: &str) -> usize {
+        self.lock().usage.get(name).map_or(0, |u| u.running)
+    }
+
+    /// The number of calls to the guest function `name` waiting for a
+    /// permit
+    pub fn queued(&self, name: &str) -> usize {
+        self.lock().usage.get(name).map_or(0, |u| u.queued)
+    }
+
+    /// Wait until `name` has fewer running calls than its limit, or no
+    /// longer has a limit
+    fn wait_for_turn<'a>(
+        &self,
+        mut state: MutexGuard<'a, State>,
+        name: &str,
+    ) -> MutexGuard<'a, State> {
+        loop {
+            let at_limit = match state.limits.get(name) {
+                Some(limit) => state
+                    .usage
+                    .get(name)
+                    .is_some_and(|u| u.running >= limit.max_concurrent),
+                None => false,
+            };
+            if !at_limit {
+                return state;
+            }
+            state = self.released.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for ConcurrencyPermit<'_> {
+    fn drop(&mut self) {
+        let Some(function) = self.function.take() else {
+            return;
+        };
+        let mut state = self.limits.lock();
+        if let Some(usage) = state.usage.get_mut(&function) {
+            usage.running -= 1;
+            if usage.running == 0 && usage.queued == 0 {
+                state.usage.remove(&function);
+            }
+        }
+        if state.limits.contains_key(&function) {
+            let history = state.history.entry(function).or_default();
+            history.finished_calls += 1;
+            history.total_run_time += self.acquired.elapsed();
+        }
+        drop(state);
+        self.limits.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{ConcurrencyLimit, FunctionConcurrencyLimits, FunctionLoad, History};
+    use crate::HyperlightError;
+
+    #[test]
+    fn means_of_many_calls_do_not_overflow() {
+        let limit = ConcurrencyLimit {
+            max_concurrent: 1,
+            max_queued: usize::MAX,
+        };
+        // 2^32 calls would wrap to 0 as a u32
+        let history = History {
+            finished_calls: 1 << 32,
+            total_run_time: Duration::from_secs(1 << 32),
+            ..Default::default()
+        };
+        assert_eq!(Duration::from_secs(3), history.retry_after(limit, 2));
+        // the wait is capped rather than overflowing
+        let history = History {
+            finished_calls: 1,
+            total_run_time: Duration::MAX,
+            ..Default::default()
+        };
+        assert_eq!(Duration::MAX, history.retry_after(limit, usize::MAX - 1));
+
+        let load = FunctionLoad {
+            limit,
+            running: 0,
+            queued: 0,
+            waited_calls: (1 << 32) + 1,
+            total_wait: Duration::from_secs((1 << 32) + 1),
+            max_wait: Duration::from_secs(1),
+            rejected_calls: 0,
+        };
+        assert_eq!(Duration::from_secs(1), load.mean_wait());
+    }
+
+    #[test]
+    fn unlimited_functions_are_not_held_back() {
+        let limits = FunctionConcurrencyLimits::new();
+        let permits: Vec<_> = (0..100).map(|_| limits.acquire("Echo").unwrap()).collect();
+        assert_eq!(0, limits.running("Echo"));
+        drop(permits);
+    }
+
+    #[test]
+    fn calls_beyond_the_queue_are_rejected() {
+        let limits = FunctionConcurrencyLimits::new();
+        limits.set_limit(
+            "Heavy",
+            ConcurrencyLimit {
+                max_concurrent: 2,
+                max_queued: 0,
+            },
+        );
+        let first = limits.acquire("Heavy").unwrap();
+        let _second = limits.acquire("Heavy").unwrap();
+        assert_eq!(2, limits.running("Heavy"));
+        let res = limits.acquire("Heavy");
+        assert!(matches!(
+            res,
+            Err(HyperlightError::Overloaded(ref name, retry_after))
+                if name == "Heavy" && retry_after == FunctionConcurrencyLimits::DEFAULT_RETRY_AFTER
+        ));
+        // other functions are not affected
+        let _light = limits.acquire("Light").unwrap();
+
+        drop(first);
+        assert_eq!(1, limits.running("Heavy"));
+        let _third = limits.acquire("Heavy").unwrap();
+    }
+
+    #[test]
+    fn queued_calls_wait_for_a_running_call() {
+        let limits = Arc::new(FunctionConcurrencyLimits::new());
+        limits.set_limit(
+            "Heavy",
+            ConcurrencyLimit {
+                max_concurrent: 1,
+                max_queued: 1,
+            },
+        );
+        let running = limits.acquire("Heavy").unwrap();
+
+        let waiter = {
+            let limits = limits.clone();
+            thread::spawn(move || {
+                let _permit = limits.acquire("Heavy").unwrap();
+                limits.running("Heavy")
+            })
+        };
+        while limits.queued("Heavy") == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(limits.acquire("Heavy").is_err());
+
+        drop(running);
+        assert_eq!(1, waiter.join().unwrap());
+        assert_eq!(0, limits.running("Heavy"));
+        assert_eq!(0, limits.queued("Heavy"));
+    }
+
+    #[test]
+    fn load_reports_queue_depth_waits_and_rejections() {
+        let limits = Arc::new(FunctionConcurrencyLimits::new());
+        assert_eq!(None, limits.load("Heavy"));
+        limits.set_limit(
+            "Heavy",
+            ConcurrencyLimit {
+                max_concurrent: 1,
+                max_queued: 1,
+            },
+        );
+        let load = limits.load("Heavy").unwrap();
+        assert_eq!(0.0, load.saturation());
+        assert_eq!(Duration::ZERO, load.mean_wait());
+
+        let running = limits.acquire("Heavy").unwrap();
+        let waiter = {
+            let limits = limits.clone();
+            thread::spawn(move || {
+                let _permit = limits.acquire("Heavy").unwrap();
+            })
+        };
+        while limits.queued("Heavy") == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        let load = limits.load("Heavy").unwrap();
+        assert_eq!((1, 1), (load.running, load.queued));
+        assert_eq!(1.0, load.saturation());
+        assert!(limits.acquire("Heavy").is_err());
+
+        thread::sleep(Duration::from_millis(20));
+        drop(running);
+        waiter.join().unwrap();
+
+        let load = limits.load("Heavy").unwrap();
+        assert_eq!((0, 0), (load.running, load.queued));
+        assert_eq!(1, load.waited_calls);
+        assert_eq!(1, load.rejected_calls);
+        assert!(load.max_wait >= Duration::from_millis(20));
+        assert_eq!(load.total_wait, load.mean_wait());
+
+        // once calls have finished, the hint follows how long they took
+        let _running = limits.acquire("Heavy").unwrap();
+        let _queued = {
+            let limits = limits.clone();
+            thread::spawn(move || limits.acquire("Heavy").map(|_| ()))
+        };
+        while limits.queued("Heavy") == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        match limits.acquire("Heavy") {
+            Err(HyperlightError::Overloaded(_, retry_after)) => {
+                assert!(retry_after >= Duration::from_millis(20));
+            }
+            res => panic!("expected the call to be rejected, got {res:?}"),
+        }
+    }
+}
@@ -17,16 +17,69 @@ limitations under the License.
 use std::cmp::{max, min};
 use std::time::Duration;
 
+use hyperlight_common::flatbuffer_wrappers::payload_limits::PayloadLimits;
+use hyperlight_common::payload_compression::PayloadCompression;
+use hyperlight_common::transport::{HostCallTransport, PortMap};
 use tracing::{instrument, Span};
 
+use super::cpu_fault::CpuFaultAction;
+use super::cpuid::CpuidConfiguration;
+use super::entropy::EntropyPolicy;
+use super::guest_time::GuestTime;
+use super::interrupt::InterruptPolicy;
+use super::memory_layout::{LayoutRegion, MemoryLayout, RegionPermissions};
+use super::msr::MsrPolicy;
 use crate::mem::exe::ExeInfo;
 
 /// Used for passing debug configuration to a sandbox
 #[cfg(gdb)]
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
 pub struct DebugInfo {
     /// Guest debug port
     pub port: u16,
+    /// When `false`, the guest stops at its entry point until a gdb client
+    /// attaches. When `true`, the guest runs freely and only stops if it
+    /// crashes (an MMIO access, a memory access violation or a guest
+    /// abort), keeping the crashed vCPU state around for a gdb client to
+    /// attach to and inspect. The call that crashed returns its error once
+    /// the gdb client continues or detaches.
+    pub wait_on_crash: bool,
+}
+
+#[cfg(gdb)]
+impl DebugInfo {
+    /// Debug the guest on `port`, stopping it at its entry point until a
+    /// gdb client attaches
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            wait_on_crash: false,
+        }
+    }
+
+    /// Let the guest run freely and only stop it if it crashes, see
+    /// `wait_on_crash`
+    pub fn with_wait_on_crash(self, wait_on_crash: bool) -> Self {
+        Self {
+            wait_on_crash,
+            ..self
+        }
+    }
+}
+
+/// How the memory shared with the guest is populated
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+#[repr(C)]
+pub enum MemoryPopulation {
+    /// Pages are populated on first touch. Creating a sandbox is faster,
+    /// but the first guest function calls take page faults on the memory
+    /// they use.
+    #[default]
+    Lazy,
+    /// Every page is populated when the sandbox is created, trading a
+    /// slower creation for predictable first-call latency.
+    Prefault,
 }
 
 /// The complete set of configuration needed to create a Sandbox
@@ -85,6 +138,11 @@ pub struct SandboxConfiguration {
     /// field should be represented as an `Option`, that type is not
     /// FFI-safe, so it cannot be.
     max_wait_for_cancellation: u8,
+    /// How a running guest is interrupted when a guest function call is
+    /// cancelled.
+    interrupt_policy: InterruptPolicy,
+    /// What happens when the guest double or triple faults.
+    cpu_fault_action: CpuFaultAction,
     // The max_initialization_time represents the maximum time the host should wait for a guest to initialize
     // If set to 0, the max_initialization_time will be set to the default value of 2000ms.
     // The minimum value is 1ms.
@@ -96,6 +154,97 @@ pub struct SandboxConfiguration {
     /// The size of the memory buffer that is made available for serializing
     /// guest panic context
     guest_panic_context_buffer_size: usize,
+    /// The maximum number of guest log records the host will forward per
+    /// second for this sandbox. Records above this rate are dropped and
+    /// counted. If set to 0, guest log records are not rate limited.
+    max_guest_log_records_per_second: u32,
+    /// The maximum size of a serialized function call or return value passed
+    /// between the host and the guest. If set to 0, the size is only limited
+    /// by the size of the input or output data buffer.
+    max_call_payload_size: usize,
+    /// The maximum size of any single String or VecBytes parameter or return
+    /// value passed between the host and the guest. If set to 0, there is no
+    /// limit.
+    max_parameter_size: usize,
+    /// Whether the memory shared with the guest is populated on first
+    /// touch or up front when the sandbox is created.
+    memory_population: MemoryPopulation,
+    /// The size of the memory buffer that guest functions can write large
+    /// results into for the host to read in place. If set to 0, there is
+    /// no result buffer.
+    result_buffer_size: usize,
+    /// The size of the region the host publishes data into for the guest
+    /// to read. If set to 0, there is no read-only data region.
+    read_only_data_size: usize,
+    /// The size of the ring buffer the guest writes log records into. If
+    /// set to 0, the guest calls the host for every log record.
+    guest_log_ring_size: usize,
+    /// The size of the region the host adds secrets into for the guest to
+    /// take. If set to 0, there is no secrets region.
+    secrets_size: usize,
+    /// The size of the region the guest can write code into and run it. If
+    /// set to 0, there is no JIT scratch region.
+    jit_scratch_size: usize,
+    /// The maximum number of instructions a guest function call (or the
+    /// guest initialisation) may execute before it is stopped. If set to 0,
+    /// there is no limit.
+    max_guest_instructions: u64,
+    /// The longest time in milliseconds a guest function call may go
+    /// without calling `HostHeartbeat` before it is cancelled. If set to 0,
+    /// heartbeats are not monitored.
+    heartbeat_timeout: u64,
+    /// The longest time in milliseconds a guest function call may run
+    /// without calling a host function before it is cancelled. If set to 0,
+    /// there is no limit.
+    max_time_between_host_calls: u64,
+    /// The number of epoch increments after the start of a guest function
+    /// call at which the guest stops at its next epoch check. If set to 0,
+    /// calls have no epoch deadline.
+    epoch_deadline: u64,
+    /// The smallest change in percent the guest reports progress for with
+    /// `hl_report_progress`, after its first report.
+    min_progress_step: u8,
+    /// Whether guest function arguments are widened to the parameter types
+    /// the guest function takes, where that can't lose information.
+    lenient_parameter_coercion: bool,
+    /// Whether the guest may use the XSAVE feature set, and the AVX and
+    /// AVX-512 state the host supports.
+    extended_cpu_state: bool,
+    /// Whether the guest's stacks are zeroed after every guest function
+    /// call.
+    scrub_stacks: bool,
+    /// The number of slots the input and output data buffers are split
+    /// into, each of the input and output data sizes, so the host can
+    /// write the next guest function call while the guest runs another.
+    io_buffer_slots: usize,
+    /// Whether the pages of the guest binary that hold only code are
+    /// mapped read-only by the hypervisor.
+    write_protect_code: bool,
+    /// The size of the guest's supervisor shadow stack, or 0 if the guest
+    /// runs without one.
+    shadow_stack_size: usize,
+    /// The changes made to the CPUID leaves exposed to the guest.
+    cpuid: CpuidConfiguration,
+    /// Where the guest's random numbers come from.
+    entropy_policy: EntropyPolicy,
+    /// How the guest's time stamp counter advances.
+    guest_time: GuestTime,
+    /// How guest reads and writes of model specific registers are handled.
+    msr_policy: MsrPolicy,
+    /// How the guest signals the host when running under a hypervisor.
+    host_call_transport: HostCallTransport,
+    /// The port the guest signals each of the host's channels on.
+    port_map: PortMap,
+    /// The algorithm guest function calls and results are compressed
+    /// with, if the guest accepts it.
+    payload_compression: PayloadCompression,
+    /// The smallest serialized guest function call or result that is
+    /// compressed.
+    payload_compression_threshold: usize,
+    /// The order of the guest memory regions between the PEB and the stack.
+    region_order: [LayoutRegion; LayoutRegion::COUNT],
+    /// The permissions the guest has to each region, indexed by region.
+    region_permissions: [RegionPermissions; LayoutRegion::COUNT],
 }
 
 impl SandboxConfiguration {
@@ -147,6 +296,52 @@ impl SandboxConfiguration {
     pub const MIN_KERNEL_STACK_SIZE: usize = 0x1000;
     /// The default value for kernel stack size
     pub const DEFAULT_KERNEL_STACK_SIZE: usize = Self::MIN_KERNEL_STACK_SIZE;
+    /// The default value for the maximum number of guest log records
+    /// forwarded per second (0 means no limit)
+    pub const DEFAULT_MAX_GUEST_LOG_RECORDS_PER_SECOND: u32 = 0;
+    /// The default value for the maximum size of a function call payload
+    /// (0 means the size is limited by the input and output data sizes)
+    pub const DEFAULT_MAX_CALL_PAYLOAD_SIZE: usize = 0;
+    /// The default value for the maximum size of a String or VecBytes
+    /// parameter or return value (0 means no limit)
+    pub const DEFAULT_MAX_PARAMETER_SIZE: usize = 0;
+    /// The default size of the smallest guest function call or result that
+    /// is compressed, when payload compression is on
+    pub const DEFAULT_PAYLOAD_COMPRESSION_THRESHOLD: usize = 0x1000;
+    /// The default size of the result buffer (0 means there is no result
+    /// buffer)
+    pub const DEFAULT_RESULT_BUFFER_SIZE: usize = 0;
+    /// The default size of the read-only data region (0 means there is no
+    /// read-only data region)
+    pub const DEFAULT_READ_ONLY_DATA_SIZE: usize = 0;
+    /// The default size of the guest log ring buffer (0 means the guest
+    /// calls the host for every log record)
+    pub const DEFAULT_GUEST_LOG_RING_SIZE: usize = 0;
+    /// The default size of the secrets region (0 means there is no
+    /// secrets region)
+    pub const DEFAULT_SECRETS_SIZE: usize = 0;
+    /// The default size of the JIT scratch region (0 means there is no JIT
+    /// scratch region)
+    pub const DEFAULT_JIT_SCRATCH_SIZE: usize = 0;
+    /// The default maximum number of instructions a guest function call may
+    /// execute (0 means no limit)
+    pub const DEFAULT_MAX_GUEST_INSTRUCTIONS: u64 = 0;
+    /// The default heartbeat timeout (in milliseconds, 0 means heartbeats
+    /// are not monitored)
+    pub const DEFAULT_HEARTBEAT_TIMEOUT: u64 = 0;
+    /// The default maximum time between host function calls (in
+    /// milliseconds, 0 means no limit)
+    pub const DEFAULT_MAX_TIME_BETWEEN_HOST_CALLS: u64 = 0;
+    /// The default epoch deadline (0 means guest function calls have no
+    /// epoch deadline)
+    pub const DEFAULT_EPOCH_DEADLINE: u64 = 0;
+    /// The default smallest change in percent the guest reports progress
+    /// for
+    pub const DEFAULT_MIN_PROGRESS_STEP: u8 = 1;
+    /// The default number of input and output data buffer slots
+    pub const DEFAULT_IO_BUFFER_SLOTS: usize = 1;
+    /// The maximum number of input and output data buffer slots
+    pub const MAX_IO_BUFFER_SLOTS: usize = 16;
 
     #[allow(clippy::too_many_arguments)]
     /// Create a new configuration for a sandbox with the given sizes.
@@ -232,6 +427,38 @@ impl SandboxConfiguration {
                 guest_panic_context_buffer_size,
                 Self::MIN_GUEST_PANIC_CONTEXT_BUFFER_SIZE,
             ),
+            max_guest_log_records_per_second: Self::DEFAULT_MAX_GUEST_LOG_RECORDS_PER_SECOND,
+            max_call_payload_size: Self::DEFAULT_MAX_CALL_PAYLOAD_SIZE,
+            max_parameter_size: Self::DEFAULT_MAX_PARAMETER_SIZE,
+            memory_population: MemoryPopulation::default(),
+            result_buffer_size: Self::DEFAULT_RESULT_BUFFER_SIZE,
+            read_only_data_size: Self::DEFAULT_READ_ONLY_DATA_SIZE,
+            guest_log_ring_size: Self::DEFAULT_GUEST_LOG_RING_SIZE,
+            secrets_size: Self::DEFAULT_SECRETS_SIZE,
+            jit_scratch_size: Self::DEFAULT_JIT_SCRATCH_SIZE,
+            max_guest_instructions: Self::DEFAULT_MAX_GUEST_INSTRUCTIONS,
+            heartbeat_timeout: Self::DEFAULT_HEARTBEAT_TIMEOUT,
+            max_time_between_host_calls: Self::DEFAULT_MAX_TIME_BETWEEN_HOST_CALLS,
+            epoch_deadline: Self::DEFAULT_EPOCH_DEADLINE,
+            interrupt_policy: InterruptPolicy::default(),
+            cpu_fault_action: CpuFaultAction::default(),
+            min_progress_step: Self::DEFAULT_MIN_PROGRESS_STEP,
+            lenient_parameter_coercion: false,
+            extended_cpu_state: false,
+            scrub_stacks: false,
+            io_buffer_slots: Self::DEFAULT_IO_BUFFER_SLOTS,
+            write_protect_code: true,
+            shadow_stack_size: 0,
+            cpuid: CpuidConfiguration::default(),
+            entropy_policy: EntropyPolicy::default(),
+            guest_time: GuestTime::default(),
+            msr_policy: MsrPolicy::default(),
+            host_call_transport: HostCallTransport::default(),
+            port_map: PortMap::default(),
+            payload_compression: PayloadCompression::None,
+            payload_compression_threshold: Self::DEFAULT_PAYLOAD_COMPRESSION_THRESHOLD,
+            region_order: LayoutRegion::DEFAULT_ORDER,
+            region_permissions: LayoutRegion::default_permissions_table(),
             #[cfg(gdb)]
             guest_debug_info,
         }
@@ -288,6 +515,26 @@ impl SandboxConfiguration {
         self.heap_size_override = heap_size;
     }
 
+    /// Apply the sizes set in `layout` to this configuration, and place
+    /// the guest memory regions in its order with its permissions
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_memory_layout(&mut self, layout: MemoryLayout) {
+        if let Some(heap_size) = layout.heap_size {
+            self.set_heap_size(heap_size);
+        }
+        if let Some(stack_size) = layout.stack_size {
+            self.set_stack_size(stack_size);
+        }
+        if let Some(input_data_size) = layout.input_data_size {
+            self.set_input_data_size(input_data_size);
+        }
+        if let Some(output_data_size) = layout.output_data_size {
+            self.set_output_data_size(output_data_size);
+        }
+        self.region_order = layout.region_order;
+        self.region_permissions = layout.region_permissions;
+    }
+
     /// Set the kernel stack size to use in the guest sandbox. If less than the minimum value of MIN_KERNEL_STACK_SIZE, the minimum value will be used.
     /// If its not a multiple of the page size, it will be increased to the a multiple of the page size when memory is allocated.
     #[instrument(skip_all, parent = Span::current(), level= "Trace")]
@@ -360,6 +607,380 @@ impl SandboxConfiguration {
         );
     }
 
+    /// Set the maximum number of guest log records the host will forward per second
+    /// for a sandbox. Any records logged above this rate are dropped and counted.
+    /// If set to 0, guest log records are not rate limited.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_max_guest_log_records_per_second(&mut self, max_records: u32) {
+        self.max_guest_log_records_per_second = max_records;
+    }
+
+    /// Set the maximum size of a serialized function call or return value passed between
+    /// the host and the guest. Larger payloads are rejected with a `PayloadTooLarge` error.
+    /// If set to 0, the size is only limited by the input or output data size.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_max_call_payload_size(&mut self, max_call_payload_size: usize) {
+        self.max_call_payload_size = max_call_payload_size;
+    }
+
+    /// Set the maximum size of any single String or VecBytes parameter or return value
+    /// passed between the host and the guest. Larger values are rejected with a
+    /// `PayloadTooLarge` error. If set to 0, there is no limit.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_max_parameter_size(&mut self, max_parameter_size: usize) {
+        self.max_parameter_size = max_parameter_size;
+    }
+
+    /// Set whether the memory shared with the guest is populated lazily, on
+    /// first touch (the default), or up front when the sandbox is created.
+    /// Use `MemoryPopulation::Prefault` for latency-sensitive deployments
+    /// that can afford a slower sandbox creation.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_memory_population(&mut self, memory_population: MemoryPopulation) {
+        self.memory_population = memory_population;
+    }
+
+    /// Set the size of the memory buffer that guest functions can write
+    /// large results into, so that the host can read them in place with
+    /// `MultiUseSandbox::call_guest_function_with_result_buffer` rather
+    /// than having them copied out of the output data buffer. If set to 0
+    /// (the default), there is no result buffer.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_result_buffer_size(&mut self, result_buffer_size: usize) {
+        self.result_buffer_size = result_buffer_size;
+    }
+
+    /// Set the size of the region the host publishes data into with
+    /// `MultiUseSandbox::publish_read_only_data`, such as configuration or
+    /// lookup tables used by many guest function calls. The guest can read
+    /// the region but not write to it. If set to 0 (the default), there is
+    /// no read-only data region.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_read_only_data_size(&mut self, read_only_data_size: usize) {
+        self.read_only_data_size = read_only_data_size;
+    }
+
+    /// Set the size of the ring buffer the guest writes its log records
+    /// into. Rather than stopping to call the host for every record, the
+    /// guest appends it to the ring, and the host forwards the records in
+    /// the ring whenever the guest exits to it, such as when it calls a
+    /// host function or a guest function call returns. Records that don't
+    /// fit in the ring are dropped, and counted by
+    /// `MultiUseSandbox::overflowed_guest_log_records`. If set to 0 (the
+    /// default), the guest calls the host for every log record.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_guest_log_ring_size(&mut self, guest_log_ring_size: usize) {
+        self.guest_log_ring_size = guest_log_ring_size;
+    }
+
+    /// Set the size of the region the host adds secrets, such as API keys,
+    /// into with `UninitializedSandbox::add_secret`, for the guest to take
+    /// with `hyperlight_guest::secrets::hl_take_secret`. Every secret takes
+    /// 12 bytes more than the length of its name and value. If set to 0
+    /// (the default), there is no secrets region.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_secrets_size(&mut self, secrets_size: usize) {
+        self.secrets_size = secrets_size;
+    }
+
+    /// Set the size of a region the guest can write code into and run it,
+    /// e.g. code it compiled at runtime, which it gets with
+    /// `hyperlight_guest::jit_scratch::jit_scratch`. The guest's own code
+    /// can't be written to and its data can't be run, so this is the only
+    /// memory a guest can do both in, unless the region permissions say
+    /// otherwise. If set to 0 (the default), there is no JIT scratch region.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_jit_scratch_size(&mut self, jit_scratch_size: usize) {
+        self.jit_scratch_size = jit_scratch_size;
+    }
+
+    /// Set the maximum number of instructions a guest function call (or the
+    /// guest initialisation) may execute. A call that exceeds the limit is
+    /// stopped and fails with `HyperlightError::GuestInstructionLimitExceeded`.
+    /// Unlike `set_max_execution_time`, the limit does not depend on the
+    /// speed or load of the host, so the same call with the same inputs is
+    /// always stopped at the same point.
+    ///
+    /// The guest is single-stepped to count its instructions, which makes
+    /// it orders of magnitude slower. This is meant for differential testing
+    /// and other environments that need reproducible limits, not for
+    /// general use. Only supported on KVM. If set to 0 (the default), there
+    /// is no limit.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_max_guest_instructions(&mut self, max_guest_instructions: u64) {
+        self.max_guest_instructions = max_guest_instructions;
+    }
+
+    /// Cancel guest function calls that go longer than `heartbeat_timeout`
+    /// without the guest calling the `HostHeartbeat` host function (e.g.
+    /// with `hyperlight_guest::heartbeat::heartbeat`), failing them with
+    /// `HyperlightError::GuestHeartbeatLapsed`.
+    ///
+    /// This lets a long `max_execution_time` be set for guests that do a
+    /// lot of work, while a guest that stops making progress is still
+    /// detected quickly. The time since the last heartbeat is only checked
+    /// while a guest function call is running, and only guests that send
+    /// heartbeats should be given a timeout. If set to 0 (the default),
+    /// heartbeats are not monitored.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_heartbeat_timeout(&mut self, heartbeat_timeout: Duration) {
+        self.heartbeat_timeout = u64::try_from(heartbeat_timeout.as_millis()).unwrap_or(u64::MAX);
+    }
+
+    /// Cancel guest function calls once the guest runs for longer than
+    /// `max_time` without calling a host function, failing them with
+    /// `HyperlightError::GuestHostCallIntervalExceeded`. The time is
+    /// measured from the start of the call or from when the last host
+    /// function the guest called returned, so time spent in host functions
+    /// doesn't count.
+    ///
+    /// Like `set_heartbeat_timeout`, this detects a guest stuck in a tight
+    /// loop long before its maximum execution time, but works for any guest
+    /// that calls the host regularly, rather than only for guests that send
+    /// heartbeats. If set to 0 (the default), there is no limit.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_max_time_between_host_calls(&mut self, max_time: Duration) {
+        self.max_time_between_host_calls = u64::try_from(max_time.as_millis()).unwrap_or(u64::MAX);
+    }
+
+    /// Stop guest function calls once the sandbox's `EpochHandle` has been
+    /// incremented `epoch_deadline` times since they started, failing them
+    /// with `HyperlightError::EpochDeadlineReached`.
+    ///
+    /// The host interrupts the guest when the deadline is reached, and
+    /// stops it before it runs again. A guest can also stop itself sooner
+    /// by calling `hyperlight_guest::epoch::check_epoch`, e.g. at the top of
+    /// its loops, or at every function entry for C guests built with
+    /// `-finstrument-functions`. If set to 0 (the default), calls have no
+    /// epoch deadline.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_epoch_deadline(&mut self, epoch_deadline: u64) {
+        self.epoch_deadline = epoch_deadline;
+    }
+
+    /// Set the smallest change in percent that a guest reporting its
+    /// progress with `hyperlight_guest::progress::hl_report_progress`
+    /// exits to the host for. After each report, the guest skips reports
+    /// until its progress has grown by at least `min_progress_step`
+    /// percent, so a guest reporting progress in a tight loop doesn't
+    /// exit to the host on every iteration. The minimum is 1, the default.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_min_progress_step(&mut self, min_progress_step: u8) {
+        self.min_progress_step = min_progress_step.clamp(1, 100);
+    }
+
+    /// Set whether guest function arguments are widened to the parameter
+    /// types the guest function takes: an `Int` to a `Long`, a `UInt` to a
+    /// `ULong`, and a `Float` to a `Double`. This saves callers whose
+    /// integer or float widths differ slightly from the guest's from
+    /// converting every argument by hand. No other conversions are made,
+    /// and arguments to functions the guest did not register with the
+    /// guest library are passed unchanged. Disabled by default.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_lenient_parameter_coercion(&mut self, lenient_parameter_coercion: bool) {
+        self.lenient_parameter_coercion = lenient_parameter_coercion;
+    }
+
+    /// Set whether the guest may use extended CPU state. When enabled, the
+    /// guest's CR4.OSXSAVE is set and XCR0 enables the SSE, AVX and AVX-512
+    /// state components the host has enabled for itself, so guests can run
+    /// vectorized code beyond baseline SSE2. The hypervisor saves and
+    /// restores this state across VM exits, including host function calls.
+    ///
+    /// As with the x87 and SSE state, the AVX and AVX-512 state, including
+    /// the upper bits of the vector registers and the opmask registers, is
+    /// reset before each guest function call. Sandboxes fail to
+    /// initialise if the host does not support XSAVE. Disabled by default.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_extended_cpu_state(&mut self, extended_cpu_state: bool) {
+        self.extended_cpu_state = extended_cpu_state;
+    }
+
+    /// Set whether the guest's stacks are zeroed after every guest
+    /// function call, so that what one call left on the stack can't be
+    /// read by the next call, for sandboxes that are reused for calls on
+    /// behalf of different tenants.
+    ///
+    /// Every guest function call starts at the top of the stack, so the
+    /// stacks hold nothing that is needed between calls. The guest's
+    /// memory is restored after calls made with
+    /// `MultiUseSandbox::call_guest_function_by_name` anyway, this matters
+    /// for sequences of calls made through a `MultiUseGuestCallContext`,
+    /// and costs a write of the whole stack per call. Disabled by default.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_scrub_stacks(&mut self, scrub_stacks: bool) {
+        self.scrub_stacks = scrub_stacks;
+    }
+
+    /// Set whether the pages of the guest binary that hold only code, its
+    /// executable and non-writable `PT_LOAD` segments, are mapped
+    /// read-only in the hypervisor's page tables. A guest that writes to
+    /// its own code then fails with
+    /// `HyperlightError::GuestCodeModificationAttempt` instead of
+    /// silently changing what it executes, whatever its own page tables
+    /// allow. Only ELF guests running in a hypervisor are protected. Disable
+    /// this for guests that patch their own code. Enabled by default.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_write_protect_code(&mut self, write_protect_code: bool) {
+        self.write_protect_code = write_protect_code;
+    }
+
+    /// Set the size of the shadow stack the guest runs with, rounded up to
+    /// a whole number of pages. The guest enables the CET shadow stack
+    /// when it starts, after which every return in the guest is checked
+    /// against the return address the call pushed to the shadow stack,
+    /// which the guest can't write to, and a mismatch, such as from an
+    /// overwritten return address, fails the call with
+    /// `HyperlightError::GuestControlFlowViolation`. Creating a sandbox
+    /// fails with `HyperlightError::ShadowStackNotSupported` if the
+    /// hypervisor doesn't expose CET shadow stacks to the guest, which only
+    /// KVM does, on hosts that support them. Guests that
+    /// `longjmp` or whose exception handlers resume at a different
+    /// instruction can't run with a shadow stack. If set to 0 (the
+    /// default), the guest runs without one.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_shadow_stack_size(&mut self, shadow_stack_size: usize) {
+        self.shadow_stack_size = shadow_stack_size;
+    }
+
+    /// Set the number of slots the input and output data buffers are
+    /// split into. Each slot holds the input and output data of one guest
+    /// function call and is as large as the configured input and output
+    /// data sizes, so the buffers take up `io_buffer_slots` times as much
+    /// memory.
+    ///
+    /// With more than one slot, `MultiUseGuestCallContext::call_pipelined`
+    /// serializes the parameters of the next calls into free slots while
+    /// the guest is running the current one, overlapping marshalling with
+    /// execution. Other calls use one slot at a time. The value is clamped
+    /// between 1, the default, and `MAX_IO_BUFFER_SLOTS`.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_io_buffer_slots(&mut self, io_buffer_slots: usize) {
+        self.io_buffer_slots = io_buffer_slots.clamp(1, Self::MAX_IO_BUFFER_SLOTS);
+    }
+
+    /// Set the changes made to the CPUID leaves exposed to the guest, to
+    /// hide features such as `RDRAND` or AVX-512 and to set the vendor
+    /// and brand strings, so that guests behave the same on every host.
+    /// Hidden AVX and AVX-512 state is also left out of XCR0 when
+    /// extended CPU state is enabled.
+    ///
+    /// Only KVM supports changing the CPUID, creating a sandbox with a
+    /// non-default CPUID configuration fails on other hypervisors. By
+    /// default the hypervisor's CPUID is exposed unchanged.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_cpuid(&mut self, cpuid: CpuidConfiguration) {
+        self.cpuid = cpuid;
+    }
+
+    /// Set where the guest's random numbers come from: the processor's
+    /// `RDRAND` and `RDSEED`, nowhere, or a generator seeded by the host,
+    /// see `EntropyPolicy`. The policy hides `RDRAND` and `RDSEED` from
+    /// the guest's CPUID unless it is `EntropyPolicy::Hardware`, and the
+    /// other policies are only supported by KVM on Intel processors, which
+    /// make the guest fault when it executes them. Defaults to
+    /// `EntropyPolicy::Hardware`.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_entropy_policy(&mut self, entropy_policy: EntropyPolicy) {
+        self.entropy_policy = entropy_policy;
+    }
+
+    /// Set how the guest's time stamp counter advances, see `GuestTime`.
+    /// Only KVM supports time stamp counters other than the host's,
+    /// creating a sandbox with one fails on other hypervisors. Defaults
+    /// to `GuestTime::Host`.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_guest_time(&mut self, guest_time: GuestTime) {
+        self.guest_time = guest_time;
+    }
+
+    /// Set how guest reads and writes of model specific registers are
+    /// handled. A guest access denied by the policy stops the guest, and
+    /// the guest function call fails with
+    /// `HyperlightError::GuestMsrAccessDenied`.
+    ///
+    /// KVM supports every policy. mshv intercepts either every MSR access
+    /// or none, so creating a sandbox with a policy that passes some MSRs
+    /// through, but not all of them, fails on it. WHP handles most MSRs
+    /// itself, so creating a sandbox with a policy that doesn't pass every
+    /// access through fails on it. By default every access is passed
+    /// through to the hypervisor.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_msr_policy(&mut self, msr_policy: MsrPolicy) {
+        self.msr_policy = msr_policy;
+    }
+
+    /// Set how a running guest is interrupted when a guest function call
+    /// is cancelled: how often it is interrupted again while it hasn't
+    /// stopped, and what happens if it still hasn't stopped after the
+    /// time set with `set_max_execution_cancel_wait_time`. By default the
+    /// guest is interrupted every 500 microseconds, and the call fails
+    /// with `HyperlightError::GuestExecutionHungOnHostFunctionCall` if
+    /// the guest doesn't stop in time.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_interrupt_policy(&mut self, interrupt_policy: InterruptPolicy) {
+        self.interrupt_policy = interrupt_policy;
+    }
+
+    /// Set what happens when the guest double or triple faults, before
+    /// the guest function call fails with
+    /// `HyperlightError::GuestDoubleFault` or
+    /// `HyperlightError::GuestTripleFault`: the guest is torn down
+    /// straight away, or, when built with the `crashdump` or `gdb`
+    /// features, a core dump is written or a debugger is waited for first.
+    /// Defaults to `CpuFaultAction::Teardown`.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_cpu_fault_action(&mut self, cpu_fault_action: CpuFaultAction) {
+        self.cpu_fault_action = cpu_fault_action;
+    }
+
+    /// Set how the guest signals the host to call host functions, log
+    /// and abort: by writing to I/O ports, or by writing to an MMIO
+    /// doorbell page. Guests built with `hyperlight_guest` support both.
+    ///
+    /// Only KVM reports the value written to the doorbell, so creating a
+    /// sandbox that uses it fails on mshv and WHP. Guests running in
+    /// process always call the host directly. Defaults to
+    /// `HostCallTransport::PortIo`.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_host_call_transport(&mut self, host_call_transport: HostCallTransport) {
+        self.host_call_transport = host_call_transport;
+    }
+
+    /// Set the port the guest signals each of the host's channels on, to
+    /// keep the ports an embedder uses for its own channels, registered
+    /// with `UninitializedSandbox::register_port_handler`, free. Guests
+    /// built with `hyperlight_guest` read the map from the PEB, older
+    /// guests only work with `PortMap::DEFAULT`.
+    ///
+    /// Creating a sandbox fails if two channels share a port, or a port
+    /// isn't below `PortMap::PORT_LIMIT`. Defaults to `PortMap::DEFAULT`.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_port_map(&mut self, port_map: PortMap) {
+        self.port_map = port_map;
+    }
+
+    /// Compress the guest function calls and results whose serialized
+    /// size is at least `threshold` bytes with `compression`, so that
+    /// calls and results that compress well can be larger than the input
+    /// and output data buffers. The guest must be built with the
+    /// `payload_compression` feature of `hyperlight_guest`, and accepts the
+    /// algorithm when it is initialised; payloads sent to other guests are
+    /// never compressed. Payloads that don't get smaller are sent
+    /// uncompressed, and host function calls are never compressed.
+    ///
+    /// The uncompressed size of a payload is limited by the maximum call
+    /// payload size, or `DEFAULT_MAX_UNCOMPRESSED_SIZE` of
+    /// `hyperlight_common::payload_compression` if it is 0. Guest
+    /// functions that return `VecBytes` can't leave their results in the
+    /// output data buffer to be read in place while compression is on.
+    /// Defaults to `PayloadCompression::None`.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub fn set_payload_compression(&mut self, compression: PayloadCompression, threshold: usize) {
+        self.payload_compression = compression;
+        self.payload_compression_threshold = threshold;
+    }
+
     /// Sets the configuration for the guest debug
     #[cfg(gdb)]
     #[instrument(skip_all, parent = Span::current(), level= "Trace")]
@@ -411,6 +1032,181 @@ impl SandboxConfiguration {
         self.max_initialization_time
     }
 
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_max_guest_log_records_per_second(&self) -> u32 {
+        self.max_guest_log_records_per_second
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_max_call_payload_size(&self) -> usize {
+        self.max_call_payload_size
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_max_parameter_size(&self) -> usize {
+        self.max_parameter_size
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_memory_population(&self) -> MemoryPopulation {
+        self.memory_population
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_result_buffer_size(&self) -> usize {
+        self.result_buffer_size
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_read_only_data_size(&self) -> usize {
+        self.read_only_data_size
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_guest_log_ring_size(&self) -> usize {
+        self.guest_log_ring_size
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_secrets_size(&self) -> usize {
+        self.secrets_size
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_jit_scratch_size(&self) -> usize {
+        self.jit_scratch_size
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_max_guest_instructions(&self) -> u64 {
+        self.max_guest_instructions
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_heartbeat_timeout(&self) -> Option<Duration> {
+        match self.heartbeat_timeout {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_max_time_between_host_calls(&self) -> Option<Duration> {
+        match self.max_time_between_host_calls {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        }
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_epoch_deadline(&self) -> u64 {
+        self.epoch_deadline
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_min_progress_step(&self) -> u8 {
+        self.min_progress_step
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_lenient_parameter_coercion(&self) -> bool {
+        self.lenient_parameter_coercion
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_extended_cpu_state(&self) -> bool {
+        self.extended_cpu_state
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_scrub_stacks(&self) -> bool {
+        self.scrub_stacks
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_write_protect_code(&self) -> bool {
+        self.write_protect_code
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_shadow_stack_size(&self) -> usize {
+        self.shadow_stack_size
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_io_buffer_slots(&self) -> usize {
+        self.io_buffer_slots
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_cpuid(&self) -> CpuidConfiguration {
+        self.cpuid
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_entropy_policy(&self) -> EntropyPolicy {
+        self.entropy_policy
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_guest_time(&self) -> GuestTime {
+        self.guest_time
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_msr_policy(&self) -> MsrPolicy {
+        self.msr_policy
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_interrupt_policy(&self) -> InterruptPolicy {
+        self.interrupt_policy
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_cpu_fault_action(&self) -> CpuFaultAction {
+        self.cpu_fault_action
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_host_call_transport(&self) -> HostCallTransport {
+        self.host_call_transport
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_port_map(&self) -> PortMap {
+        self.port_map
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_payload_compression(&self) -> PayloadCompression {
+        self.payload_compression
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_payload_compression_threshold(&self) -> usize {
+        self.payload_compression_threshold
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_region_order(&self) -> [LayoutRegion; LayoutRegion::COUNT] {
+        self.region_order
+    }
+
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_region_permissions(&self, region: LayoutRegion) -> RegionPermissions {
+        self.region_permissions[region as usize]
+    }
+
+    /// The payload limits enforced by both the host and the guest
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn get_payload_limits(&self) -> PayloadLimits {
+        PayloadLimits {
+            max_payload_size: self.max_call_payload_size as u64,
+            max_parameter_size: self.max_parameter_size as u64,
+        }
+    }
+
     #[cfg(gdb)]
     #[instrument(skip_all, parent = Span::current(), level= "Trace")]
     pub(crate) fn get_guest_debug_info(&self) -> Option<DebugInfo> {
@@ -475,9 +1271,304 @@ impl Default for SandboxConfiguration {
 mod tests {
     use std::time::Duration;
 
-    use super::SandboxConfiguration;
+    use hyperlight_common::payload_compression::PayloadCompression;
+    use hyperlight_common::transport::{HostCallTransport, PortMap};
+
+    use super::{MemoryPopulation, SandboxConfiguration};
+    use crate::sandbox::cpu_fault::CpuFaultAction;
+    use crate::sandbox::cpuid::{CpuFeatures, CpuidConfiguration};
+    use crate::sandbox::entropy::EntropyPolicy;
+    use crate::sandbox::guest_time::GuestTime;
+    use crate::sandbox::interrupt::{InterruptEscalation, InterruptPolicy};
+    use crate::sandbox::memory_layout::{LayoutRegion, MemoryLayoutBuilder, RegionPermissions};
+    use crate::sandbox::msr::{MsrAction, MsrPolicy};
     use crate::testing::{callback_guest_exe_info, simple_guest_exe_info};
 
+    #[test]
+    fn memory_population() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(MemoryPopulation::Lazy, cfg.get_memory_population());
+        cfg.set_memory_population(MemoryPopulation::Prefault);
+        assert_eq!(MemoryPopulation::Prefault, cfg.get_memory_population());
+    }
+
+    #[test]
+    fn result_buffer_size() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(0, cfg.get_result_buffer_size());
+        cfg.set_result_buffer_size(0x10000);
+        assert_eq!(0x10000, cfg.get_result_buffer_size());
+    }
+
+    #[test]
+    fn read_only_data_size() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(0, cfg.get_read_only_data_size());
+        cfg.set_read_only_data_size(0x4000);
+        assert_eq!(0x4000, cfg.get_read_only_data_size());
+    }
+
+    #[test]
+    fn guest_log_ring_size() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(0, cfg.get_guest_log_ring_size());
+        cfg.set_guest_log_ring_size(0x10000);
+        assert_eq!(0x10000, cfg.get_guest_log_ring_size());
+    }
+
+    #[test]
+    fn secrets_size() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(0, cfg.get_secrets_size());
+        cfg.set_secrets_size(0x1000);
+        assert_eq!(0x1000, cfg.get_secrets_size());
+    }
+
+    #[test]
+    fn jit_scratch_size() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(0, cfg.get_jit_scratch_size());
+        cfg.set_jit_scratch_size(0x2000);
+        assert_eq!(0x2000, cfg.get_jit_scratch_size());
+    }
+
+    #[test]
+    fn max_guest_instructions() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(0, cfg.get_max_guest_instructions());
+        cfg.set_max_guest_instructions(1_000_000);
+        assert_eq!(1_000_000, cfg.get_max_guest_instructions());
+    }
+
+    #[test]
+    fn heartbeat_timeout() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(None, cfg.get_heartbeat_timeout());
+        cfg.set_heartbeat_timeout(Duration::from_millis(250));
+        assert_eq!(
+            Some(Duration::from_millis(250)),
+            cfg.get_heartbeat_timeout()
+        );
+        cfg.set_heartbeat_timeout(Duration::ZERO);
+        assert_eq!(None, cfg.get_heartbeat_timeout());
+    }
+
+    #[test]
+    fn max_time_between_host_calls() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(None, cfg.get_max_time_between_host_calls());
+        cfg.set_max_time_between_host_calls(Duration::from_millis(50));
+        assert_eq!(
+            Some(Duration::from_millis(50)),
+            cfg.get_max_time_between_host_calls()
+        );
+        cfg.set_max_time_between_host_calls(Duration::ZERO);
+        assert_eq!(None, cfg.get_max_time_between_host_calls());
+    }
+
+    #[test]
+    fn epoch_deadline() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(0, cfg.get_epoch_deadline());
+        cfg.set_epoch_deadline(3);
+        assert_eq!(3, cfg.get_epoch_deadline());
+    }
+
+    #[test]
+    fn min_progress_step() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(1, cfg.get_min_progress_step());
+        cfg.set_min_progress_step(10);
+        assert_eq!(10, cfg.get_min_progress_step());
+        cfg.set_min_progress_step(0);
+        assert_eq!(1, cfg.get_min_progress_step());
+    }
+
+    #[test]
+    fn lenient_parameter_coercion() {
+        let mut cfg = SandboxConfiguration::default();
+        assert!(!cfg.get_lenient_parameter_coercion());
+        cfg.set_lenient_parameter_coercion(true);
+        assert!(cfg.get_lenient_parameter_coercion());
+    }
+
+    #[test]
+    fn extended_cpu_state() {
+        let mut cfg = SandboxConfiguration::default();
+        assert!(!cfg.get_extended_cpu_state());
+        cfg.set_extended_cpu_state(true);
+        assert!(cfg.get_extended_cpu_state());
+    }
+
+    #[test]
+    fn scrub_stacks() {
+        let mut cfg = SandboxConfiguration::default();
+        assert!(!cfg.get_scrub_stacks());
+        cfg.set_scrub_stacks(true);
+        assert!(cfg.get_scrub_stacks());
+    }
+
+    #[test]
+    fn write_protect_code() {
+        let mut cfg = SandboxConfiguration::default();
+        assert!(cfg.get_write_protect_code());
+        cfg.set_write_protect_code(false);
+        assert!(!cfg.get_write_protect_code());
+    }
+
+    #[test]
+    fn shadow_stack_size() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(0, cfg.get_shadow_stack_size());
+        cfg.set_shadow_stack_size(0x2000);
+        assert_eq!(0x2000, cfg.get_shadow_stack_size());
+    }
+
+    #[test]
+    fn io_buffer_slots() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(1, cfg.get_io_buffer_slots());
+        cfg.set_io_buffer_slots(4);
+        assert_eq!(4, cfg.get_io_buffer_slots());
+        cfg.set_io_buffer_slots(0);
+        assert_eq!(1, cfg.get_io_buffer_slots());
+        cfg.set_io_buffer_slots(SandboxConfiguration::MAX_IO_BUFFER_SLOTS + 1);
+        assert_eq!(
+            SandboxConfiguration::MAX_IO_BUFFER_SLOTS,
+            cfg.get_io_buffer_slots()
+        );
+    }
+
+    #[test]
+    fn cpuid() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(CpuidConfiguration::default(), cfg.get_cpuid());
+        let mut cpuid = CpuidConfiguration::default();
+        cpuid.hide_features(CpuFeatures::RDRAND);
+        cfg.set_cpuid(cpuid);
+        assert_eq!(cpuid, cfg.get_cpuid());
+    }
+
+    #[test]
+    fn entropy_policy() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(EntropyPolicy::Hardware, cfg.get_entropy_policy());
+        cfg.set_entropy_policy(EntropyPolicy::Deterministic(42));
+        assert_eq!(EntropyPolicy::Deterministic(42), cfg.get_entropy_policy());
+    }
+
+    #[test]
+    fn guest_time() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(GuestTime::Host, cfg.get_guest_time());
+        let guest_time = GuestTime::Virtual {
+            tsc_khz: Some(1_000_000),
+        };
+        cfg.set_guest_time(guest_time);
+        assert_eq!(guest_time, cfg.get_guest_time());
+    }
+
+    #[test]
+    fn msr_policy() {
+        let mut cfg = SandboxConfiguration::default();
+        assert!(cfg.get_msr_policy().is_passthrough());
+        let mut policy = MsrPolicy::new(MsrAction::Deny);
+        policy.set_action(0x10, MsrAction::Passthrough).unwrap();
+        cfg.set_msr_policy(policy);
+        assert_eq!(policy, cfg.get_msr_policy());
+    }
+
+    #[test]
+    fn interrupt_policy() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(InterruptPolicy::default(), cfg.get_interrupt_policy());
+        let policy = InterruptPolicy {
+            backoff_factor: 2,
+            escalation: InterruptEscalation::KeepInterrupting,
+            ..Default::default()
+        };
+        cfg.set_interrupt_policy(policy);
+        assert_eq!(policy, cfg.get_interrupt_policy());
+    }
+
+    #[test]
+    fn cpu_fault_action() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(CpuFaultAction::Teardown, cfg.get_cpu_fault_action());
+        #[cfg(crashdump)]
+        {
+            cfg.set_cpu_fault_action(CpuFaultAction::CoreDump);
+            assert_eq!(CpuFaultAction::CoreDump, cfg.get_cpu_fault_action());
+        }
+        #[cfg(gdb)]
+        {
+            cfg.set_cpu_fault_action(CpuFaultAction::WaitForDebugger);
+            assert_eq!(CpuFaultAction::WaitForDebugger, cfg.get_cpu_fault_action());
+        }
+        cfg.set_cpu_fault_action(CpuFaultAction::Teardown);
+        assert_eq!(CpuFaultAction::Teardown, cfg.get_cpu_fault_action());
+    }
+
+    #[test]
+    fn memory_layout() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(LayoutRegion::DEFAULT_ORDER, cfg.get_region_order());
+        let mut order = LayoutRegion::DEFAULT_ORDER;
+        order.swap(0, 7);
+        let layout = MemoryLayoutBuilder::new()
+            .heap_size(0x1_0000_0000)
+            .input_data_size(0x20000)
+            .region_order(&order)
+            .region_permissions(LayoutRegion::Heap, RegionPermissions::ReadWriteExecute)
+            .build()
+            .unwrap();
+        cfg.set_memory_layout(layout);
+        assert_eq!(0x1_0000_0000, cfg.heap_size_override);
+        assert_eq!(0x20000, cfg.get_input_data_size());
+        assert_eq!(
+            SandboxConfiguration::DEFAULT_OUTPUT_SIZE,
+            cfg.get_output_data_size()
+        );
+        assert_eq!(order, cfg.get_region_order());
+        assert_eq!(
+            RegionPermissions::ReadWriteExecute,
+            cfg.get_region_permissions(LayoutRegion::Heap)
+        );
+    }
+
+    #[test]
+    fn host_call_transport() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(HostCallTransport::PortIo, cfg.get_host_call_transport());
+        cfg.set_host_call_transport(HostCallTransport::Mmio);
+        assert_eq!(HostCallTransport::Mmio, cfg.get_host_call_transport());
+    }
+
+    #[test]
+    fn port_map() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(PortMap::DEFAULT, cfg.get_port_map());
+        let port_map = PortMap {
+            call: 0x300,
+            ..PortMap::DEFAULT
+        };
+        cfg.set_port_map(port_map);
+        assert_eq!(port_map, cfg.get_port_map());
+    }
+
+    #[test]
+    fn payload_compression() {
+        let mut cfg = SandboxConfiguration::default();
+        assert_eq!(PayloadCompression::None, cfg.get_payload_compression());
+        assert_eq!(
+            SandboxConfiguration::DEFAULT_PAYLOAD_COMPRESSION_THRESHOLD,
+            cfg.get_payload_compression_threshold()
+        );
+        cfg.set_payload_compression(PayloadCompression::Zstd, 0x800);
+        assert_eq!(PayloadCompression::Zstd, cfg.get_payload_compression());
+        assert_eq!(0x800, cfg.get_payload_compression_threshold());
+    }
+
     #[test]
     fn overrides() {
         const STACK_SIZE_OVERRIDE: u64 = 0x10000;
@@ -733,6 +1824,27 @@ mod tests {
                 prop_assert_eq!(time, cfg.get_max_initialization_time());
             }
 
+            #[test]
+            fn max_guest_log_records_per_second(max_records in 0..=u32::MAX) {
+                let mut cfg = SandboxConfiguration::default();
+                cfg.set_max_guest_log_records_per_second(max_records);
+                prop_assert_eq!(max_records, cfg.get_max_guest_log_records_per_second());
+            }
+
+            #[test]
+            fn max_call_payload_size(size in 0..=SandboxConfiguration::DEFAULT_INPUT_SIZE * 10) {
+                let mut cfg = SandboxConfiguration::default();
+                cfg.set_max_call_payload_size(size);
+                prop_assert_eq!(size, cfg.get_max_call_payload_size());
+            }
+
+            #[test]
+            fn max_parameter_size(size in 0..=SandboxConfiguration::DEFAULT_INPUT_SIZE * 10) {
+                let mut cfg = SandboxConfiguration::default();
+                cfg.set_max_parameter_size(size);
+                prop_assert_eq!(size, cfg.get_max_parameter_size());
+            }
+
             #[test]
             fn stack_size_override(size in 0x1000..=0x10000u64) {
                 let mut cfg = SandboxConfiguration::default();
@@ -749,9 +1861,9 @@ mod tests {
 
             #[test]
             #[cfg(gdb)]
-            fn guest_debug_info(port in 9000..=u16::MAX) {
+            fn guest_debug_info(port in 9000..=u16::MAX, wait_on_crash: bool) {
                 let mut cfg = SandboxConfiguration::default();
-                let debug_info = DebugInfo { port };
+                let debug_info = DebugInfo::new(port).with_wait_on_crash(wait_on_crash);
                 cfg.set_guest_debug_info(debug_info);
                 prop_assert_eq!(debug_info, *cfg.get_guest_debug_info().as_ref().unwrap());
             }
@@ -0,0 +1,73 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Double and triple faults of the guest's vCPU.
+//!
+//! A double fault is raised when the CPU can't deliver an exception, most
+//! often because the stack it pushes the exception to isn't mapped. The
+//! guest library runs its double fault handler on a stack of its own, so
+//! it can report the fault, which fails the guest function call with
+//! `HyperlightError::GuestDoubleFault`. If the double fault can't be
+//! delivered either, e.g. because the guest replaced its interrupt
+//! descriptor table, the vCPU triple faults and stops, and the call fails
+//! with `HyperlightError::GuestTripleFault` and the registers the vCPU
+//! stopped with.
+//!
+//! Either fault leaves the sandbox poisoned, and what else happens first
+//! is set with `SandboxConfiguration::set_cpu_fault_action`.
+
+use std::fmt::{Display, Formatter};
+
+/// What happens when the guest double or triple faults, before the guest
+/// function call fails
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub enum CpuFaultAction {
+    /// Tear the guest down: the call fails straight away, and the
+    /// sandbox has to be reset or, after a triple fault, recreated
+    #[default]
+    Teardown,
+    /// Write a core dump of the vCPU's registers and the guest memory to
+    /// a temporary file, whose path is logged, then tear the guest down
+    #[cfg(crashdump)]
+    CoreDump,
+    /// Stop the vCPU in its faulted state until the gdb client debugging
+    /// the sandbox continues or detaches, whether or not
+    /// `DebugInfo::wait_on_crash` is set, then tear the guest down
+    #[cfg(gdb)]
+    WaitForDebugger,
+}
+
+/// The registers the vCPU stopped with when it triple faulted
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CpuFaultContext {
+    /// The instruction pointer
+    pub rip: u64,
+    /// The stack pointer
+    pub rsp: u64,
+    /// The address of the last page fault
+    pub cr2: u64,
+}
+
+impl Display for CpuFaultContext {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "rip {:#x}, rsp {:#x}, cr2 {:#x}",
+            self.rip, self.rsp, self.cr2
+        )
+    }
+}
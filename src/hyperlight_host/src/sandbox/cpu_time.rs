@@ -0,0 +1,122 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Accounting for the CPU time a sandbox uses.
+//!
+//! The CPU time of the thread running the sandbox's vCPU is measured
+//! around the guest's initialisation and every guest function call, which
+//! includes the host functions the guest calls, since they run on the
+//! same thread. When the `seccomp` feature is enabled, host functions run
+//! on worker threads instead, and their CPU time is measured on the
+//! worker around each host function call. The time the vCPU thread spends
+//! waiting for a worker is not CPU time, so nothing is counted twice.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The CPU time used by the calling thread so far, or `None` if it can't
+/// be read
+pub(crate) fn thread_cpu_time() -> Option<Duration> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        // SAFETY: `ts` is a valid timespec for the duration of the call
+        let ret = unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut ts) };
+        if ret != 0 {
+            return None;
+        }
+        Some(Duration::new(
+            u64::try_from(ts.tv_sec).ok()?,
+            u32::try_from(ts.tv_nsec).ok()?,
+        ))
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use windows::Win32::Foundation::FILETIME;
+        use windows::Win32::System::Threading::{GetCurrentThread, GetThreadTimes};
+
+        let mut creation = FILETIME::default();
+        let mut exit = FILETIME::default();
+        let mut kernel = FILETIME::default();
+        let mut user = FILETIME::default();
+        // SAFETY: the pointers are to valid FILETIMEs for the duration of
+        // the call, and the pseudo handle of the current thread needs no
+        // closing
+        unsafe {
+            GetThreadTimes(
+                GetCurrentThread(),
+                &mut creation,
+                &mut exit,
+                &mut kernel,
+                &mut user,
+            )
+        }
+        .ok()?;
+        // FILETIMEs count 100 nanosecond intervals
+        let ticks = |t: FILETIME| (u64::from(t.dwHighDateTime) << 32) | u64::from(t.dwLowDateTime);
+        Some(Duration::from_nanos(
+            (ticks(kernel) + ticks(user)).saturating_mul(100),
+        ))
+    }
+}
+
+/// The CPU time a sandbox has used, shared between the threads that use it
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CpuTimeCounter(Arc<AtomicU64>);
+
+impl CpuTimeCounter {
+    /// Run `f` on the calling thread, adding the CPU time it uses
+    pub(crate) fn measure<T>(&self, f: impl FnOnce() -> T) -> T {
+        let start = thread_cpu_time();
+        let res = f();
+        if let Some(used) = start.and_then(|start| thread_cpu_time()?.checked_sub(start)) {
+            self.0.fetch_add(
+                u64::try_from(used.as_nanos()).unwrap_or(u64::MAX),
+                Ordering::Relaxed,
+            );
+        }
+        res
+    }
+
+    /// The CPU time used so far
+    pub(crate) fn get(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::CpuTimeCounter;
+
+    #[test]
+    fn counts_cpu_time_not_wall_time() {
+        let counter = CpuTimeCounter::default();
+        counter.measure(|| std::thread::sleep(Duration::from_millis(50)));
+        assert!(counter.get() < Duration::from_millis(25));
+
+        let start = Instant::now();
+        counter.measure(|| while start.elapsed() < Duration::from_millis(50) {});
+        assert!(counter.get() >= Duration::from_millis(25));
+        // the counter is shared by its clones
+        assert_eq!(counter.get(), counter.clone().get());
+    }
+}
@@ -0,0 +1,254 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use bitflags::bitflags;
+
+use crate::{new_error, Result};
+
+bitflags! {
+    /// CPU features that can be hidden from the guest's CPUID
+    #[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+    pub struct CpuFeatures: u64 {
+        /// The `RDRAND` instruction
+        const RDRAND = 1;
+        /// The `RDSEED` instruction
+        const RDSEED = 1 << 1;
+        /// AVX. Hiding it also hides AVX2 and AVX-512, which depend on it
+        const AVX = 1 << 2;
+        /// AVX2
+        const AVX2 = 1 << 3;
+        /// Every AVX-512 extension
+        const AVX512 = 1 << 4;
+        /// The AES-NI instructions
+        const AES = 1 << 5;
+        /// The SHA extensions
+        const SHA = 1 << 6;
+        /// The bit reporting that the guest is running under a hypervisor
+        const HYPERVISOR = 1 << 7;
+    }
+}
+
+// Leaf 1 ECX bits
+const AES_BIT: u32 = 1 << 25;
+const AVX_BIT: u32 = 1 << 28;
+const RDRAND_BIT: u32 = 1 << 30;
+const HYPERVISOR_BIT: u32 = 1 << 31;
+// Leaf 7, subleaf 0 EBX bits
+const AVX2_BIT: u32 = 1 << 5;
+const RDSEED_BIT: u32 = 1 << 18;
+const SHA_BIT: u32 = 1 << 29;
+// AVX512F, DQ, IFMA, PF, ER, CD, BW and VL
+const AVX512_EBX_BITS: u32 =
+    (1 << 16) | (1 << 17) | (1 << 21) | (1 << 26) | (1 << 27) | (1 << 28) | (1 << 30) | (1 << 31);
+// AVX512_VBMI, VBMI2, VNNI, BITALG and VPOPCNTDQ
+const AVX512_ECX_BITS: u32 = (1 << 1) | (1 << 6) | (1 << 11) | (1 << 12) | (1 << 14);
+// AVX512_4VNNIW, 4FMAPS, VP2INTERSECT and FP16
+const AVX512_EDX_BITS: u32 = (1 << 2) | (1 << 3) | (1 << 8) | (1 << 23);
+// Leaf 7, subleaf 1 EAX AVX512_BF16 bit
+const AVX512_BF16_BIT: u32 = 1 << 5;
+// The XSAVE state components of AVX and AVX-512, as reported in leaf 0xd
+const XCR0_AVX: u32 = 1 << 2;
+const XCR0_AVX512: u32 = (1 << 5) | (1 << 6) | (1 << 7);
+
+/// The first of the three leaves holding the processor brand string
+const BRAND_LEAF: u32 = 0x8000_0002;
+
+/// Changes to the CPUID leaves exposed to the guest, so that guests can't
+/// come to rely on features of a particular host, and see the same CPU
+/// wherever they run.
+///
+/// The default configuration exposes the hypervisor's CPUID unchanged.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CpuidConfiguration {
+    hidden_features: CpuFeatures,
+    vendor: Option<[u8; 12]>,
+    brand: Option<[u8; 48]>,
+}
+
+impl CpuidConfiguration {
+    /// Hide `features` from the guest, in addition to any already hidden
+    pub fn hide_features(&mut self, features: CpuFeatures) {
+        self.hidden_features |= features;
+    }
+
+    /// Set the vendor string in leaf 0, such as `GenuineIntel`, which must
+    /// be 12 ASCII characters
+    pub fn set_vendor(&mut self, vendor: &str) -> Result<()> {
+        if !vendor.is_ascii() {
+            return Err(new_error!("The CPU vendor {:?} is not ASCII", vendor));
+        }
+        self.vendor =
+            Some(vendor.as_bytes().try_into().map_err(|_| {
+                new_error!("The CPU vendor {:?} is not 12 characters long", vendor)
+            })?);
+        Ok(())
+    }
+
+    /// Set the processor brand string in leaves 0x80000002 to 0x80000004,
+    /// which must be at most 47 ASCII characters
+    pub fn set_brand(&mut self, brand: &str) -> Result<()> {
+        if !brand.is_ascii() || brand.len() > 47 {
+            return Err(new_error!(
+                "The CPU brand {:?} is not at most 47 ASCII characters",
+                brand
+            ));
+        }
+        // the string is NUL terminated
+        let mut bytes = [0; 48];
+        bytes[..brand.len()].copy_from_slice(brand.as_bytes());
+        self.brand = Some(bytes);
+        Ok(())
+    }
+
+    /// The features hidden from the guest, including those hidden because
+    /// they depend on a hidden feature
+    fn hidden(&self) -> CpuFeatures {
+        let mut hidden = self.hidden_features;
+        if hidden.contains(CpuFeatures::AVX) {
+            hidden |= CpuFeatures::AVX2 | CpuFeatures::AVX512;
+        }
+        hidden
+    }
+
+    /// The XCR0 state components that must not be enabled for the guest
+    pub(crate) fn hidden_xcr0(&self) -> u64 {
+        let hidden = self.hidden();
+        let mut xcr0 = 0;
+        if hidden.contains(CpuFeatures::AVX) {
+            xcr0 |= XCR0_AVX;
+        }
+        if hidden.contains(CpuFeatures::AVX512) {
+            xcr0 |= XCR0_AVX512;
+        }
+        xcr0.into()
+    }
+
+    /// Apply this configuration to the `eax`, `ebx`, `ecx` and `edx`
+    /// values of the CPUID leaf `function`, subleaf `index`
+    pub(crate) fn apply(&self, function: u32, index: u32, registers: &mut [u32; 4]) {
+        let hidden = self.hidden();
+        let mask =
+            |feature: CpuFeatures, bits: u32| if hidden.contains(feature) { bits } else { 0 };
+        let [eax, ebx, ecx, edx] = registers;
+        match (function, index) {
+            (0, _) => {
+                if let Some(vendor) = self.vendor {
+                    // the vendor string is stored in EBX, EDX, ECX order
+                    *ebx = u32::from_le_bytes([vendor[0], vendor[1], vendor[2], vendor[3]]);
+                    *edx = u32::from_le_bytes([vendor[4], vendor[5], vendor[6], vendor[7]]);
+                    *ecx = u32::from_le_bytes([vendor[8], vendor[9], vendor[10], vendor[11]]);
+                }
+            }
+            (1, _) => {
+                *ecx &= !(mask(CpuFeatures::AES, AES_BIT)
+                    | mask(CpuFeatures::AVX, AVX_BIT)
+                    | mask(CpuFeatures::RDRAND, RDRAND_BIT)
+                    | mask(CpuFeatures::HYPERVISOR, HYPERVISOR_BIT));
+            }
+            (7, 0) => {
+                *ebx &= !(mask(CpuFeatures::AVX2, AVX2_BIT)
+                    | mask(CpuFeatures::RDSEED, RDSEED_BIT)
+                    | mask(CpuFeatures::SHA, SHA_BIT)
+                    | mask(CpuFeatures::AVX512, AVX512_EBX_BITS));
+                *ecx &= !mask(CpuFeatures::AVX512, AVX512_ECX_BITS);
+                *edx &= !mask(CpuFeatures::AVX512, AVX512_EDX_BITS);
+            }
+            (7, 1) => {
+                *eax &= !mask(CpuFeatures::AVX512, AVX512_BF16_BIT);
+            }
+            (0xd, 0) => {
+                // the upper half of XCR0 has no AVX components
+                *eax &= !(self.hidden_xcr0() as u32);
+            }
+            (BRAND_LEAF..=0x8000_0004, _) => {
+                if let Some(brand) = self.brand {
+                    let leaf = &brand[(function - BRAND_LEAF) as usize * 16..][..16];
+                    for (register, bytes) in [eax, ebx, ecx, edx].into_iter().zip(leaf.chunks(4)) {
+                        *register = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CpuFeatures, CpuidConfiguration};
+
+    fn apply(cpuid: &CpuidConfiguration, function: u32, index: u32) -> [u32; 4] {
+        let mut registers = [u32::MAX; 4];
+        cpuid.apply(function, index, &mut registers);
+        registers
+    }
+
+    #[test]
+    fn default_changes_nothing() {
+        let cpuid = CpuidConfiguration::default();
+        for (function, index) in [(0, 0), (1, 0), (7, 0), (7, 1), (0xd, 0), (0x8000_0002, 0)] {
+            assert_eq!([u32::MAX; 4], apply(&cpuid, function, index));
+        }
+        assert_eq!(0, cpuid.hidden_xcr0());
+    }
+
+    #[test]
+    fn hide_features() {
+        let mut cpuid = CpuidConfiguration::default();
+        cpuid.hide_features(CpuFeatures::RDRAND | CpuFeatures::RDSEED);
+        assert_eq!(!(1 << 30), apply(&cpuid, 1, 0)[2]);
+        assert_eq!(!(1 << 18), apply(&cpuid, 7, 0)[1]);
+        assert_eq!(0, cpuid.hidden_xcr0());
+
+        cpuid.hide_features(CpuFeatures::AVX512);
+        let leaf7 = apply(&cpuid, 7, 0);
+        assert_eq!(0, leaf7[1] & (1 << 16));
+        assert_ne!(0, leaf7[1] & (1 << 5));
+        assert_eq!(0, apply(&cpuid, 7, 1)[0] & (1 << 5));
+        assert_eq!(0b1110_0000, cpuid.hidden_xcr0());
+
+        // AVX2 and AVX-512 depend on AVX
+        cpuid.hide_features(CpuFeatures::AVX);
+        assert_eq!(0, apply(&cpuid, 1, 0)[2] & (1 << 28));
+        assert_eq!(0, apply(&cpuid, 7, 0)[1] & (1 << 5));
+        assert_eq!(0b1110_0100, cpuid.hidden_xcr0());
+        assert_eq!(!0b1110_0100, apply(&cpuid, 0xd, 0)[0]);
+    }
+
+    #[test]
+    fn vendor_and_brand() {
+        let mut cpuid = CpuidConfiguration::default();
+        assert!(cpuid.set_vendor("TooShort").is_err());
+        assert!(cpuid.set_brand(&"x".repeat(48)).is_err());
+
+        cpuid.set_vendor("GenuineIntel").unwrap();
+        let leaf0 = apply(&cpuid, 0, 0);
+        let vendor: Vec<u8> = [leaf0[1], leaf0[3], leaf0[2]]
+            .iter()
+            .flat_map(|r| r.to_le_bytes())
+            .collect();
+        assert_eq!(b"GenuineIntel", vendor.as_slice());
+        assert_eq!(u32::MAX, leaf0[0]);
+
+        cpuid.set_brand("Hyperlight Virtual CPU").unwrap();
+        let brand: Vec<u8> = (0x8000_0002..=0x8000_0004)
+            .flat_map(|function| apply(&cpuid, function, 0))
+            .flat_map(|r| r.to_le_bytes())
+            .collect();
+        assert_eq!(b"Hyperlight Virtual CPU", &brand[..22]);
+        assert!(brand[22..].iter().all(|b| *b == 0));
+    }
+}
@@ -0,0 +1,398 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Fingerprinting guest crashes, so that identical failures can be grouped
+//! across sandboxes.
+//!
+//! A fingerprint is made of the kind of fault and the guest functions at
+//! the top of the stack when it happened. When an unhandled CPU exception
+//! aborts the guest, the guest library reports the exception vector, which
+//! is part of the kind of fault, and the instruction pointer and the
+//! return addresses found by following the frame pointers, which are
+//! symbolized with the guest binary's symbol map, leaving out the offset
+//! into each function so that the fingerprint survives unrelated changes
+//! to the function. Without a symbol map, the addresses are used relative
+//! to where the guest binary was loaded, which only groups crashes of the
+//! same build. Guest panics, failed `hl_assert!`s and `hl_trap!`s, which
+//! have no frames, use the source location they happened at instead.
+//! Double faults have no meaningful instruction pointer, so only their
+//! callers are used, and triple faults only have the instruction pointer
+//! the vCPU stopped at. Memory access violations are reported by the
+//! hypervisor without the instruction that caused them, so they are only
+//! fingerprinted by the kind of access.
+//!
+//! Guest crashes whose error carries a stack trace or a panic location are
+//! returned with their fingerprint on a last `Fingerprint:` line of the
+//! error's message.
+
+use std::fmt::{Display, Formatter};
+
+use hyperlight_common::guest_diagnostic::DiagnosticKind;
+
+use crate::error::ErrorCategory;
+use crate::HyperlightError;
+
+/// The most frames a fingerprint is made of
+const MAX_FRAMES: usize = 5;
+
+/// A stable identifier for a guest crash, equal for crashes with the same
+/// kind of fault in the same guest functions, whichever sandbox they
+/// happened in. See `MultiUseSandbox::crash_fingerprint`.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct CrashFingerprint {
+    fault: String,
+    frames: Vec<String>,
+}
+
+impl CrashFingerprint {
+    /// The kind of fault, e.g. `stack_overflow`, `guest_aborted:1` or
+    /// `guest_exception:0xe` for an unhandled page fault
+    pub fn fault(&self) -> &str {
+        &self.fault
+    }
+
+    /// The guest functions at the top of the stack when the guest crashed,
+    /// innermost first, or an empty slice if they aren't known
+    pub fn frames(&self) -> &[String] {
+        &self.frames
+    }
+
+    /// A 64 bit hash of the fault and the frames, which is the same in
+    /// every process and on every host, e.g. to use as a metric label
+    pub fn id(&self) -> u64 {
+        // FNV-1a, since the hashers in std aren't guaranteed to be stable
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for part in std::iter::once(&self.fault).chain(&self.frames) {
+            for byte in part.bytes().chain(std::iter::once(0)) {
+                hash ^= u64::from(byte);
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        }
+        hash
+    }
+
+    /// The fingerprint of the guest crash `err`, or `None` if `err` isn't a
+    /// guest crash. `symbolize` names the guest function an address is in,
+    /// and `load_addr` is where the guest binary was loaded.
+    pub(crate) fn from_error(
+        err: &HyperlightError,
+        load_addr: u64,
+        symbolize: impl Fn(u64) -> Option<String>,
+    ) -> Option<Self> {
+        if err.category() != ErrorCategory::GuestCrash {
+            return None;
+        }
+        let frame = |address: u64| match symbolize(address) {
+            Some(symbol) => match symbol.rsplit_once("+0x") {
+                Some((function, _)) => function.to_string(),
+                None => symbol,
+            },
+            None => format!("{:#x}", address.wrapping_sub(load_addr)),
+        };
+        let (fault, frames) = match err {
+            HyperlightError::StackOverflow() => ("stack_overflow".to_string(), vec![]),
+            HyperlightError::ExecutionAccessViolation(address) => (
+                "execution_access_violation".to_string(),
+                vec![frame(*address)],
+            ),
+            HyperlightError::GuestCodeModificationAttempt(address) => {
+                ("guest_code_modification".to_string(), vec![frame(*address)])
+            }
+            HyperlightError::MemoryAccessViolation(_, tried, _) => {
+                (format!("memory_access_violation:{}", tried), vec![])
+            }
+            HyperlightError::GuestMsrAccessDenied(msr, _) => {
+                (format!("msr_access_denied:{:#x}", msr), vec![])
+            }
+            HyperlightError::GuestControlFlowViolation(message) => (
+                "control_flow_violation".to_string(),
+                exception_addresses(message)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(frame)
+                    .collect(),
+            ),
+            HyperlightError::GuestAborted(code, message) => {
+                let fault = match field(message, "EXCEPTION:").and_then(parse_address) {
+                    Some(vector) => format!("guest_exception:{:#x}", vector),
+                    None => format!("guest_aborted:{}", code),
+                };
+                let frames = match exception_addresses(message) {
+                    Some(addresses) => addresses.into_iter().map(frame).collect(),
+                    None => panic_location(message).into_iter().collect(),
+                };
+                (fault, frames)
+            }
+            HyperlightError::GuestDoubleFault(message) => (
+                "double_fault".to_string(),
+                frame_addresses(message).into_iter().map(frame).collect(),
+            ),
+            HyperlightError::GuestTripleFault(context) => {
+                ("triple_fault".to_string(), vec![frame(context.rip)])
+            }
+            HyperlightError::GuestTrapped(diagnostic) => {
+                let fault = match diagnostic.kind {
+                    DiagnosticKind::Assertion => "guest_assertion".to_string(),
+                    DiagnosticKind::Trap => format!("guest_trap:{}", diagnostic.code),
+                };
+                let location = format!(
+                    "{}:{}:{}",
+                    diagnostic.file, diagnostic.line, diagnostic.column
+                );
+                (fault, vec![location])
+            }
+            _ => ("guest_crash".to_string(), vec![]),
+        };
+        Some(Self {
+            fault,
+            frames: frames.into_iter().take(MAX_FRAMES).collect(),
+        })
+    }
+
+    /// Add the fingerprint to the message of `err`, the guest crash it was
+    /// made from, if the message has the stack trace or the panic location
+    /// the fingerprint was made from
+    pub(crate) fn attach(&self, err: HyperlightError) -> HyperlightError {
+        if self.frames.is_empty() {
+            return err;
+        }
+        let with_fingerprint = |message: String| format!("{}\nFingerprint: {}", message, self);
+        match err {
+            HyperlightError::GuestAborted(code, message) => {
+                HyperlightError::GuestAborted(code, with_fingerprint(message))
+            }
+            HyperlightError::GuestControlFlowViolation(message) => {
+                HyperlightError::GuestControlFlowViolation(with_fingerprint(message))
+            }
+            HyperlightError::GuestDoubleFault(message) => {
+                HyperlightError::GuestDoubleFault(with_fingerprint(message))
+            }
+            err => err,
+        }
+    }
+}
+
+impl Display for CrashFingerprint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x} ({}", self.id(), self.fault)?;
+        if !self.frames.is_empty() {
+            write!(f, " in {}", self.frames.join(" < "))?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// The instruction pointer and caller return addresses the guest library
+/// reports when an unhandled exception or a control flow violation aborts
+/// the guest
+fn exception_addresses(message: &str) -> Option<Vec<u64>> {
+    let rip = parse_address(field(message, "Instruction Pointer:")?)?;
+    Some(
+        std::iter::once(rip)
+            .chain(frame_addresses(message))
+            .collect(),
+    )
+}
+
+/// The caller return addresses on the `Frames:` line the guest library
+/// reports, if there is one
+fn frame_addresses(message: &str) -> Vec<u64> {
+    field(message, "Frames:")
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter_map(parse_address)
+        .collect()
+}
+
+fn field<'a>(message: &'a str, name: &str) -> Option<&'a str> {
+    message
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(name))
+        .map(str::trim)
+}
+
+fn parse_address(address: &str) -> Option<u64> {
+    u64::from_str_radix(address.trim_start_matches("0x"), 16).ok()
+}
+
+/// The source location a guest panicked at, from the panic message's
+/// `panicked at <file>:<line>:<column>:` prefix
+fn panic_location(message: &str) -> Option<String> {
+    let location = message.lines().next()?.strip_prefix("panicked at ")?;
+    Some(location.trim_end_matches(':').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::guest_diagnostic::{DiagnosticKind, GuestDiagnostic};
+
+    use super::CrashFingerprint;
+    use crate::mem::memory_region::MemoryRegionFlags;
+    use crate::sandbox::cpu_fault::CpuFaultContext;
+    use crate::HyperlightError;
+
+    const LOAD_ADDR: u64 = 0x20_0000;
+
+    fn symbolize(address: u64) -> Option<String> {
+        match address - LOAD_ADDR {
+            0x100..=0x1ff => Some(format!("guest::crash+{:#x}", address - LOAD_ADDR - 0x100)),
+            0x200..=0x2ff => Some("guest::caller".to_string()),
+            _ => None,
+        }
+    }
+
+    fn fingerprint(err: HyperlightError) -> Option<CrashFingerprint> {
+        CrashFingerprint::from_error(&err, LOAD_ADDR, symbolize)
+    }
+
+    #[test]
+    fn exception_frames_are_symbolized() {
+        let message = |rip: u64| {
+            format!(
+                "EXCEPTION: 0x6\nPage Fault Address: 0x0\nStack Pointer: 0x1234\n\
+                 Instruction Pointer: {:#x}\nFrames: {:#x} {:#x}",
+                LOAD_ADDR + rip,
+                LOAD_ADDR + 0x210,
+                LOAD_ADDR + 0x400
+            )
+        };
+        let crash = fingerprint(HyperlightError::GuestAborted(1, message(0x104))).unwrap();
+        assert_eq!("guest_exception:0x6", crash.fault());
+        assert_eq!(["guest::crash", "guest::caller", "0x400"], crash.frames());
+
+        // a crash elsewhere in the same function has the same fingerprint
+        let same = fingerprint(HyperlightError::GuestAborted(1, message(0x1f0))).unwrap();
+        assert_eq!(crash, same);
+        assert_eq!(crash.id(), same.id());
+        assert!(crash
+            .to_string()
+            .starts_with(&format!("{:016x}", crash.id())));
+
+        let other = fingerprint(HyperlightError::GuestAborted(1, message(0x204))).unwrap();
+        assert_ne!(crash.id(), other.id());
+
+        // so does a different exception at the same place
+        let page_fault = message(0x104).replace("EXCEPTION: 0x6", "EXCEPTION: 0xe");
+        let other = fingerprint(HyperlightError::GuestAborted(1, page_fault)).unwrap();
+        assert_eq!("guest_exception:0xe", other.fault());
+        assert_eq!(crash.frames(), other.frames());
+        assert_ne!(crash.id(), other.id());
+
+        // the fingerprint is added to the error, which still has the same
+        // fingerprint
+        let err = crash.attach(HyperlightError::GuestAborted(1, message(0x104)));
+        let HyperlightError::GuestAborted(1, attached) = &err else {
+            panic!("unexpected error {:?}", err);
+        };
+        assert!(attached.ends_with(&format!("\nFingerprint: {}", crash)));
+        assert_eq!(crash, fingerprint(err).unwrap());
+    }
+
+    #[test]
+    fn panics_use_their_location() {
+        let crash = fingerprint(HyperlightError::GuestAborted(
+            0,
+            "panicked at src/main.rs:12:5:\nindex out of bounds: the len is 3 but the index is 7"
+                .to_string(),
+        ))
+        .unwrap();
+        assert_eq!(["src/main.rs:12:5"], crash.frames());
+        let same = fingerprint(HyperlightError::GuestAborted(
+            0,
+            "panicked at src/main.rs:12:5:\nindex out of bounds: the len is 3 but the index is 9"
+                .to_string(),
+        ))
+        .unwrap();
+        assert_eq!(crash, same);
+        assert_eq!("guest_aborted:0", crash.fault());
+
+        // aborts without a location are returned as they are
+        let err = HyperlightError::GuestAborted(25, "Oh no".to_string());
+        let crash = fingerprint(HyperlightError::GuestAborted(25, "Oh no".to_string())).unwrap();
+        assert!(crash.frames().is_empty());
+        assert!(matches!(
+            crash.attach(err),
+            HyperlightError::GuestAborted(25, message) if message == "Oh no"
+        ));
+    }
+
+    #[test]
+    fn traps_use_their_location() {
+        let trap = |code, message: &str| {
+            HyperlightError::GuestTrapped(GuestDiagnostic {
+                kind: DiagnosticKind::Trap,
+                code,
+                file: "src/main.rs".to_string(),
+                line: 20,
+                column: 9,
+                condition: String::new(),
+                message: message.to_string(),
+            })
+        };
+        let crash = fingerprint(trap(3, "bad input 1")).unwrap();
+        assert_eq!("guest_trap:3", crash.fault());
+        assert_eq!(["src/main.rs:20:9"], crash.frames());
+        assert_eq!(crash, fingerprint(trap(3, "bad input 2")).unwrap());
+        assert_ne!(crash, fingerprint(trap(4, "bad input 1")).unwrap());
+    }
+
+    #[test]
+    fn cpu_faults() {
+        let crash = fingerprint(HyperlightError::GuestDoubleFault(format!(
+            "Double fault\nStack Pointer: 0x0\nFrames: {:#x} {:#x}",
+            LOAD_ADDR + 0x120,
+            LOAD_ADDR + 0x230
+        )))
+        .unwrap();
+        assert_eq!("double_fault", crash.fault());
+        assert_eq!(["guest::crash", "guest::caller"], crash.frames());
+
+        let crash = fingerprint(HyperlightError::GuestTripleFault(CpuFaultContext {
+            rip: LOAD_ADDR + 0x180,
+            rsp: 0,
+            cr2: 0xffff_fff8,
+        }))
+        .unwrap();
+        assert_eq!("triple_fault", crash.fault());
+        assert_eq!(["guest::crash"], crash.frames());
+    }
+
+    #[test]
+    fn only_crashes_are_fingerprinted() {
+        let crash = fingerprint(HyperlightError::StackOverflow()).unwrap();
+        assert_eq!("stack_overflow", crash.fault());
+        assert!(crash.frames().is_empty());
+        let crash = fingerprint(HyperlightError::MemoryAccessViolation(
+            0x1234,
+            MemoryRegionFlags::WRITE,
+            MemoryRegionFlags::READ,
+        ))
+        .unwrap();
+        assert_eq!("memory_access_violation:WRITE", crash.fault());
+
+        let crash = fingerprint(HyperlightError::GuestControlFlowViolation(format!(
+            "near return\nInstruction Pointer: {:#x}\nFrames: {:#x}",
+            LOAD_ADDR + 0x1c0,
+            LOAD_ADDR + 0x220
+        )))
+        .unwrap();
+        assert_eq!("control_flow_violation", crash.fault());
+        assert_eq!(["guest::crash", "guest::caller"], crash.frames());
+
+        assert!(fingerprint(HyperlightError::ExecutionCanceledByHost()).is_none());
+        assert!(fingerprint(HyperlightError::Error("oops".to_string())).is_none());
+    }
+}
@@ -0,0 +1,235 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use crate::Result;
+
+/// Whether sandboxes for a key tracked by a `CrashLoopDetector` should be
+/// created or recreated
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CrashLoopState {
+    /// The key's most recent calls did not crash, or it never crashed
+    Healthy,
+    /// The key's guest crashed recently. Wait for the given time before
+    /// recreating its sandbox.
+    BackingOff(Duration),
+    /// The key's guest crashed too many times in a row. No sandbox should
+    /// be created for it until it is released with
+    /// `CrashLoopDetector::release`.
+    Quarantined,
+}
+
+/// Tracks guest crashes per key, such as a tenant or a guest binary, so
+/// that a guest that crashes every time it is called isn't endlessly
+/// recreated.
+///
+/// - After each consecutive crash, the key backs off for twice as long as
+///   after the previous one, starting at `initial_backoff` and up to
+///   `max_backoff`.
+/// - After `quarantine_after` consecutive crashes, the key is quarantined
+///   until `release` is called.
+/// - A call that completes without crashing resets the key to healthy.
+///
+/// Only errors that poison a sandbox, i.e. that crashed the guest or
+/// interrupted it, count as crashes. Errors returned by the guest function
+/// itself do not.
+///
+/// `CrashLoopDetector` only decides whether sandboxes should be created,
+/// the caller owns the sandboxes. It is not synchronized, wrap it in a
+/// `Mutex` to share it between threads.
+#[derive(Debug)]
+pub struct CrashLoopDetector<K> {
+    keys: HashMap<K, CrashHistory>,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    quarantine_after: u32,
+}
+
+#[derive(Debug)]
+struct CrashHistory {
+    consecutive_crashes: u32,
+    backoff_until: Instant,
+}
+
+impl<K: Eq + Hash> CrashLoopDetector<K> {
+    /// The default backoff after the first crash
+    pub const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+    /// The default longest backoff
+    pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(60);
+    /// The default number of consecutive crashes after which a key is
+    /// quarantined
+    pub const DEFAULT_QUARANTINE_AFTER: u32 = 10;
+
+    /// Create a new `CrashLoopDetector`. If `quarantine_after` is 0, keys
+    /// are never quarantined and only back off.
+    pub fn new(initial_backoff: Duration, max_backoff: Duration, quarantine_after: u32) -> Self {
+        Self {
+            keys: HashMap::new(),
+            initial_backoff,
+            max_backoff: max_backoff.max(initial_backoff),
+            quarantine_after,
+        }
+    }
+
+    /// Record that a guest call for `key` crashed the guest
+    pub fn record_crash(&mut self, key: K) {
+        self.record_crash_at(key, Instant::now())
+    }
+
+    /// Record that a guest call for `key` completed without crashing the
+    /// guest, resetting `key` to healthy unless it is quarantined
+    pub fn record_success(&mut self, key: &K) {
+        if self.state(key) != CrashLoopState::Quarantined {
+            self.keys.remove(key);
+        }
+    }
+
+    /// Record the result of a guest call for `key`, as a crash if it
+    /// failed with an error that poisons the sandbox and as a success
+    /// otherwise
+    pub fn record_result<T>(&mut self, key: K, result: &Result<T>) {
+        match result {
+            Err(e) if e.poisons_sandbox() => self.record_crash(key),
+            _ => self.record_success(&key),
+        }
+    }
+
+    /// Whether sandboxes for `key` should currently be created
+    pub fn state(&self, key: &K) -> CrashLoopState {
+        self.state_at(key, Instant::now())
+    }
+
+    /// The number of consecutive crashes recorded for `key`
+    pub fn consecutive_crashes(&self, key: &K) -> u32 {
+        self.keys.get(key).map_or(0, |h| h.consecutive_crashes)
+    }
+
+    /// The keys that are currently quarantined
+    pub fn quarantined(&self) -> impl Iterator<Item = &K> {
+        self.keys
+            .iter()
+            .filter(|(_, h)| self.is_quarantined(h))
+            .map(|(key, _)| key)
+    }
+
+    /// Forget the crashes recorded for `key`, releasing it from quarantine
+    /// or backoff
+    pub fn release(&mut self, key: &K) {
+        self.keys.remove(key);
+    }
+
+    fn record_crash_at(&mut self, key: K, now: Instant) {
+        let history = self.keys.entry(key).or_insert(CrashHistory {
+            consecutive_crashes: 0,
+            backoff_until: now,
+        });
+        history.consecutive_crashes = history.consecutive_crashes.saturating_add(1);
+        let doublings = (history.consecutive_crashes - 1).min(31);
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1u32 << doublings)
+            .min(self.max_backoff);
+        history.backoff_until = now + backoff;
+    }
+
+    fn state_at(&self, key: &K, now: Instant) -> CrashLoopState {
+        match self.keys.get(key) {
+            Some(h) if self.is_quarantined(h) => CrashLoopState::Quarantined,
+            Some(h) if h.backoff_until > now => CrashLoopState::BackingOff(h.backoff_until - now),
+            _ => CrashLoopState::Healthy,
+        }
+    }
+
+    fn is_quarantined(&self, history: &CrashHistory) -> bool {
+        self.quarantine_after > 0 && history.consecutive_crashes >= self.quarantine_after
+    }
+}
+
+impl<K: Eq + Hash> Default for CrashLoopDetector<K> {
+    fn default() -> Self {
+        Self::new(
+            Self::DEFAULT_INITIAL_BACKOFF,
+            Self::DEFAULT_MAX_BACKOFF,
+            Self::DEFAULT_QUARANTINE_AFTER,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{CrashLoopDetector, CrashLoopState};
+    use crate::HyperlightError;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let mut detector =
+            CrashLoopDetector::new(Duration::from_millis(100), Duration::from_millis(300), 0);
+        let now = Instant::now();
+        let backoffs: Vec<_> = (0..4)
+            .map(|_| {
+                detector.record_crash_at("tenant", now);
+                detector.state_at(&"tenant", now)
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                CrashLoopState::BackingOff(Duration::from_millis(100)),
+                CrashLoopState::BackingOff(Duration::from_millis(200)),
+                CrashLoopState::BackingOff(Duration::from_millis(300)),
+                CrashLoopState::BackingOff(Duration::from_millis(300)),
+            ],
+            backoffs
+        );
+        assert_eq!(
+            CrashLoopState::Healthy,
+            detector.state_at(&"tenant", now + Duration::from_millis(300))
+        );
+        assert_eq!(CrashLoopState::Healthy, detector.state(&"other"));
+    }
+
+    #[test]
+    fn quarantine_until_released() {
+        let mut detector = CrashLoopDetector::new(Duration::ZERO, Duration::ZERO, 3);
+        for _ in 0..3 {
+            detector.record_crash(1);
+        }
+        assert_eq!(CrashLoopState::Quarantined, detector.state(&1));
+        assert_eq!(vec![&1], detector.quarantined().collect::<Vec<_>>());
+
+        // a success doesn't lift the quarantine
+        detector.record_success(&1);
+        assert_eq!(CrashLoopState::Quarantined, detector.state(&1));
+
+        detector.release(&1);
+        assert_eq!(CrashLoopState::Healthy, detector.state(&1));
+        assert_eq!(0, detector.consecutive_crashes(&1));
+    }
+
+    #[test]
+    fn only_poisoning_errors_are_crashes() {
+        let mut detector = CrashLoopDetector::default();
+        detector.record_result::<()>(1, &Err(HyperlightError::ExecutionCanceledByHost()));
+        assert_eq!(1, detector.consecutive_crashes(&1));
+        detector.record_result::<()>(1, &Err(HyperlightError::Error("guest".to_string())));
+        assert_eq!(0, detector.consecutive_crashes(&1));
+        assert_eq!(CrashLoopState::Healthy, detector.state(&1));
+    }
+}
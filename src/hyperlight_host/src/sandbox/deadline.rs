@@ -0,0 +1,143 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+struct DeadlineState {
+    /// The deadline the host attached to the next guest function call
+    requested: Option<Instant>,
+    /// The timeout of the guest function the next calls are to
+    timeout: Option<Duration>,
+    /// When the guest function call in progress will be cancelled
+    current: Option<Instant>,
+}
+
+/// The deadline of a sandbox's guest function calls, shared between the
+/// sandbox, the `HostRemainingTime` host function, and the thread waiting
+/// for guest function calls to finish
+#[derive(Clone, Debug, Default)]
+pub(crate) struct CallDeadline(Arc<Mutex<DeadlineState>>);
+
+impl CallDeadline {
+    /// Cancel the next guest function calls, until this is called again,
+    /// at `deadline` if that is before their maximum execution time
+    pub(crate) fn request(&self, deadline: Option<Instant>) {
+        self.lock().requested = deadline;
+    }
+
+    /// Cancel the next guest function calls, until this is called again,
+    /// once they have run for `timeout` if that is before their deadline
+    pub(crate) fn set_timeout(&self, timeout: Option<Duration>) {
+        self.lock().timeout = timeout;
+    }
+
+    /// Record that a guest function call that may run for `max_exec_time`
+    /// is starting, and return how long it may run for
+    pub(crate) fn start_call(&self, max_exec_time: Duration) -> Duration {
+        let now = Instant::now();
+        let mut state = self.lock();
+        let max_exec_time = match state.timeout {
+            Some(timeout) => timeout.min(max_exec_time),
+            None => max_exec_time,
+        };
+        let deadline = match state.requested {
+            Some(requested) => requested.min(now + max_exec_time),
+            None => now + max_exec_time,
+        };
+        state.current = Some(deadline);
+        deadline.saturating_duration_since(now)
+    }
+
+    /// Move the deadline of the guest function call in progress back by
+    /// `by`, the time it spent paused
+    pub(crate) fn extend(&self, by: Duration) {
+        if let Some(current) = &mut self.lock().current {
+            *current += by;
+        }
+    }
+
+    /// How long the guest function call in progress has left before it is
+    /// cancelled, or `None` if no call has started
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        self.lock()
+            .current
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, DeadlineState> {
+        // The lock only guards `Instant`s and a `Duration`, which are always valid even
+        // if a thread panicked while holding it
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::CallDeadline;
+
+    #[test]
+    fn earliest_deadline_wins() {
+        let deadline = CallDeadline::default();
+        assert_eq!(None, deadline.remaining());
+
+        let max_exec_time = Duration::from_secs(10);
+        assert_eq!(max_exec_time, deadline.start_call(max_exec_time));
+        assert!(deadline.remaining().unwrap() <= max_exec_time);
+
+        deadline.request(Some(Instant::now() + Duration::from_secs(1)));
+        let timeout = deadline.start_call(max_exec_time);
+        assert!(timeout <= Duration::from_secs(1));
+        assert!(deadline.remaining().unwrap() <= timeout);
+
+        deadline.request(Some(Instant::now() + Duration::from_secs(60)));
+        assert_eq!(max_exec_time, deadline.start_call(max_exec_time));
+
+        deadline.request(Some(Instant::now() - Duration::from_millis(1)));
+        assert_eq!(Duration::ZERO, deadline.start_call(max_exec_time));
+        assert_eq!(Some(Duration::ZERO), deadline.remaining());
+
+        // time spent paused is given back to the call
+        deadline.extend(Duration::from_secs(5));
+        assert!(deadline.remaining().unwrap() > Duration::from_secs(4));
+    }
+
+    #[test]
+    fn function_timeout_shortens_calls() {
+        let deadline = CallDeadline::default();
+        let max_exec_time = Duration::from_secs(10);
+
+        deadline.set_timeout(Some(Duration::from_secs(2)));
+        assert_eq!(Duration::from_secs(2), deadline.start_call(max_exec_time));
+        // the maximum execution time still applies to longer timeouts
+        deadline.set_timeout(Some(Duration::from_secs(60)));
+        assert_eq!(max_exec_time, deadline.start_call(max_exec_time));
+
+        // and so does an earlier deadline
+        deadline.set_timeout(Some(Duration::from_secs(2)));
+        deadline.request(Some(Instant::now() + Duration::from_secs(1)));
+        assert!(deadline.start_call(max_exec_time) <= Duration::from_secs(1));
+
+        deadline.request(None);
+        deadline.set_timeout(None);
+        assert_eq!(max_exec_time, deadline.start_call(max_exec_time));
+    }
+}
@@ -0,0 +1,105 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use hyperlight_common::mem::EntropyMode;
+
+use super::cpuid::{CpuFeatures, CpuidConfiguration};
+
+/// Where the guest's random numbers come from, so that the same guest can
+/// run with real entropy in production and deterministically in tests.
+///
+/// Unless the policy is `EntropyPolicy::Hardware`, `RDRAND` and `RDSEED`
+/// are hidden from the guest's CPUID, which on Intel processors also makes
+/// the KVM guest fault when it executes them. The guest library handles
+/// that fault as the policy says, as it does for guests that ask it for
+/// random numbers with `hyperlight_guest::entropy::random_u64`. Other
+/// processors and hypervisors can't make the guest fault, so guests that
+/// execute `RDRAND` or `RDSEED` without checking the CPUID would still get
+/// the processor's random numbers, and creating a sandbox with a policy
+/// other than `EntropyPolicy::Hardware` fails on them with
+/// `HyperlightError::EntropyInstructionsNotTrapped`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum EntropyPolicy {
+    /// The guest uses the processor's `RDRAND` and `RDSEED`, if it has
+    /// them, and the host seeds the guest with a random seed
+    #[default]
+    Hardware,
+    /// The guest has no source of entropy, and executing `RDRAND` or
+    /// `RDSEED` fails the guest function call with a
+    /// `HyperlightError::GuestError`
+    Trap,
+    /// `RDRAND` and `RDSEED` are emulated from a generator seeded with the
+    /// given seed, which is also the seed the guest is initialised with,
+    /// so every sandbox created with the same seed gets the same random
+    /// numbers. The generator is not suitable for cryptography.
+    Deterministic(u64),
+}
+
+impl EntropyPolicy {
+    /// How the guest gets its random numbers under this policy
+    pub(crate) fn mode(&self) -> EntropyMode {
+        match self {
+            EntropyPolicy::Hardware => EntropyMode::Hardware,
+            EntropyPolicy::Trap => EntropyMode::Trap,
+            EntropyPolicy::Deterministic(_) => EntropyMode::Deterministic,
+        }
+    }
+
+    /// The seed the guest is initialised with, or `None` if it is random
+    pub(crate) fn seed(&self) -> Option<u64> {
+        match self {
+            EntropyPolicy::Deterministic(seed) => Some(*seed),
+            _ => None,
+        }
+    }
+
+    /// `cpuid` with the features this policy hides from the guest hidden
+    pub(crate) fn apply(&self, mut cpuid: CpuidConfiguration) -> CpuidConfiguration {
+        if *self != EntropyPolicy::Hardware {
+            cpuid.hide_features(CpuFeatures::RDRAND | CpuFeatures::RDSEED);
+        }
+        cpuid
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::mem::EntropyMode;
+
+    use super::EntropyPolicy;
+    use crate::sandbox::cpuid::{CpuFeatures, CpuidConfiguration};
+
+    #[test]
+    fn policies() {
+        let cpuid = CpuidConfiguration::default();
+        let policy = EntropyPolicy::default();
+        assert_eq!(EntropyMode::Hardware, policy.mode());
+        assert_eq!(None, policy.seed());
+        assert_eq!(cpuid, policy.apply(cpuid));
+
+        let mut hidden = CpuidConfiguration::default();
+        hidden.hide_features(CpuFeatures::RDRAND | CpuFeatures::RDSEED);
+        let policy = EntropyPolicy::Trap;
+        assert_eq!(EntropyMode::Trap, policy.mode());
+        assert_eq!(None, policy.seed());
+        assert_eq!(hidden, policy.apply(cpuid));
+
+        let policy = EntropyPolicy::Deterministic(42);
+        assert_eq!(EntropyMode::Deterministic, policy.mode());
+        assert_eq!(Some(42), policy.seed());
+        assert_eq!(hidden, policy.apply(cpuid));
+    }
+}
@@ -0,0 +1,176 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Mutex};
+
+use crate::hypervisor::hypervisor_handler::HypervisorHandler;
+use crate::mem::shared_mem::HostSharedMemory;
+use crate::Result;
+
+/// The guest memory the epoch and the deadline are mirrored to, and the
+/// hypervisor handler running the guest's vCPU
+struct AttachedMemory {
+    id: u64,
+    shared_mem: HostSharedMemory,
+    epoch_offset: usize,
+    deadline_offset: usize,
+    hv_handler: HypervisorHandler,
+}
+
+#[derive(Default)]
+struct EpochState {
+    epoch: u64,
+    /// The deadline of the guest function call in progress, or 0 if it
+    /// has none
+    deadline: u64,
+    next_id: u64,
+    attached: Option<AttachedMemory>,
+}
+
+impl EpochState {
+    fn deadline_reached(&self) -> bool {
+        self.deadline != 0 && self.epoch >= self.deadline
+    }
+}
+
+/// A counter the host increments, e.g. from a timer thread, to interrupt
+/// guest function calls that run past their epoch deadline, set with
+/// `SandboxConfiguration::set_epoch_deadline`.
+///
+/// Incrementing the epoch up to the deadline of the guest function call
+/// in progress interrupts the sandbox's vCPU, and the loop running the
+/// vCPU stops the guest before it enters it again. The call then fails
+/// with `HyperlightError::EpochDeadlineReached`. Unlike cancelling a call
+/// when its maximum execution time passes, this never stops the guest in
+/// the middle of a host function call, which finishes first.
+///
+/// The epoch is also mirrored into the guest's memory, where the guest can
+/// compare it to the deadline with `hyperlight_guest::epoch::check_epoch`
+/// to stop at a point of its choosing. An interrupt that arrives while the
+/// vCPU is between two runs of the guest is lost, in which case the guest
+/// is stopped at the next increment, or at its next exit to the host.
+///
+/// The handle is shared by every sandbox recreated from the same
+/// sandbox, and can be cloned and sent to other threads.
+#[derive(Clone, Default)]
+pub struct EpochHandle(Arc<Mutex<EpochState>>);
+
+impl EpochHandle {
+    /// Increment the epoch, returning its new value
+    pub fn increment(&self) -> u64 {
+        let mut state = self.lock();
+        state.epoch += 1;
+        if let Some(attached) = &state.attached {
+            // Failing to mirror the epoch only means the guest is not
+            // interrupted where it checks the epoch, e.g. while its memory
+            // is being restored
+            let _ = attached
+                .shared_mem
+                .store_u64_atomic(attached.epoch_offset, state.epoch);
+            if state.deadline_reached() && attached.hv_handler.is_running() {
+                // the loop running the vCPU checks the deadline before it
+                // enters the guest again
+                let _ = attached.hv_handler.interrupt_vcpu();
+            }
+        }
+        state.epoch
+    }
+
+    /// Whether the guest function call in progress has reached its
+    /// deadline, which the loop running the vCPU checks every time it is
+    /// about to enter the guest
+    pub(crate) fn deadline_reached(&self) -> bool {
+        self.lock().deadline_reached()
+    }
+
+    /// The current value of the epoch
+    pub fn current(&self) -> u64 {
+        self.lock().epoch
+    }
+
+    /// Mirror the epoch into the guest memory `shared_mem`, and interrupt
+    /// the vCPU run by `hv_handler` when a call reaches its deadline, from
+    /// now on, returning an id to detach it with
+    pub(crate) fn attach(
+        &self,
+        shared_mem: HostSharedMemory,
+        epoch_offset: usize,
+        deadline_offset: usize,
+        hv_handler: HypervisorHandler,
+    ) -> u64 {
+        let mut state = self.lock();
+        state.next_id += 1;
+        let id = state.next_id;
+        // the deadline of the last call of a sandbox this one was
+        // recreated from doesn't apply to it
+        state.deadline = 0;
+        state.attached = Some(AttachedMemory {
+            id,
+            shared_mem,
+            epoch_offset,
+            deadline_offset,
+            hv_handler,
+        });
+        id
+    }
+
+    /// Stop mirroring the epoch into the guest memory attached as `id`,
+    /// if it is still attached
+    pub(crate) fn detach(&self, id: u64) {
+        let mut state = self.lock();
+        if state.attached.as_ref().is_some_and(|a| a.id == id) {
+            state.attached = None;
+        }
+    }
+
+    /// Write the epoch, and the deadline `ticks` after it, to the attached
+    /// guest memory before a guest function call. A `ticks` of 0 disables
+    /// the deadline.
+    pub(crate) fn start_call(&self, ticks: u64) -> Result<()> {
+        let mut state = self.lock();
+        state.deadline = match ticks {
+            0 => 0,
+            ticks => state.epoch.saturating_add(ticks),
+        };
+        if let Some(attached) = &state.attached {
+            attached
+                .shared_mem
+                .store_u64_atomic(attached.epoch_offset, state.epoch)?;
+            attached
+                .shared_mem
+                .store_u64_atomic(attached.deadline_offset, state.deadline)?;
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, EpochState> {
+        // The epoch is only ever incremented, and the memory it is
+        // mirrored to rewritten before every call, so the state is valid
+        // even if a thread panicked while holding the lock
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Debug for EpochHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EpochHandle")
+            .field("epoch", &self.current())
+            .finish()
+    }
+}
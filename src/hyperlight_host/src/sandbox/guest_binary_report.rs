@@ -0,0 +1,199 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Checking a guest binary before creating a sandbox for it, for example
+//! when a guest is uploaded rather than when it is first run.
+
+use std::fmt;
+
+use hyperlight_common::guest_metadata::{GuestMetadata, ABI_VERSION};
+
+use super::uninitialized::GuestBinary;
+use crate::mem::exe::ExeInfo;
+use crate::mem::mgr::guest_memory_size;
+use crate::sandbox::SandboxConfiguration;
+
+/// The format of a guest binary
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GuestBinaryFormat {
+    /// An ELF executable
+    Elf,
+    /// A PE executable
+    Pe,
+}
+
+/// What `GuestBinary::validate` found out about a guest binary
+#[derive(Clone, Debug, Default)]
+pub struct GuestBinaryReport {
+    /// The format of the binary, or `None` if it couldn't be parsed
+    pub format: Option<GuestBinaryFormat>,
+    /// The metadata the guest build embedded in the binary, which is
+    /// empty if it has none
+    pub metadata: GuestMetadata,
+    /// The version of the host-guest interface the guest declares it was
+    /// built against
+    pub abi_version: Option<u32>,
+    /// The size of the binary once loaded into guest memory
+    pub loaded_size: usize,
+    /// The size of the guest's stack
+    pub stack_size: u64,
+    /// The size of the guest's heap
+    pub heap_size: u64,
+    /// The size of the guest memory of a sandbox created for the binary,
+    /// or `None` if it couldn't be worked out
+    pub estimated_memory_size: Option<usize>,
+    /// The problems that would stop a sandbox being created for the
+    /// binary
+    pub errors: Vec<String>,
+    /// The problems that wouldn't stop a sandbox being created for the
+    /// binary, but that suggest it wasn't built for this host
+    pub warnings: Vec<String>,
+}
+
+impl GuestBinaryReport {
+    /// Whether a sandbox can be created for the binary, that is whether
+    /// there are no errors
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    /// Check `guest_binary` as `UninitializedSandbox::new` would load it
+    /// with `cfg`, without creating a sandbox
+    pub(super) fn new(guest_binary: &GuestBinary, cfg: SandboxConfiguration) -> Self {
+        let mut report = GuestBinaryReport::default();
+        let exe_info = match guest_binary {
+            GuestBinary::FilePath(path) => ExeInfo::from_file(path),
+            GuestBinary::Buffer(buffer) => ExeInfo::from_buf(buffer),
+        };
+        let exe_info = match exe_info {
+            Ok(exe_info) => exe_info,
+            Err(e) => {
+                report
+                    .errors
+                    .push(format!("The binary couldn't be parsed: {}", e));
+                return report;
+            }
+        };
+
+        report.format = Some(match exe_info {
+            ExeInfo::PE(_) => GuestBinaryFormat::Pe,
+            ExeInfo::Elf(_) => GuestBinaryFormat::Elf,
+        });
+        if let Err(e) = exe_info.check() {
+            report
+                .errors
+                .push(format!("The binary can't be loaded: {}", e));
+        }
+
+        report.metadata = exe_info.metadata().clone();
+        report.abi_version = report.metadata.abi_version();
+        if report.metadata.entries().is_empty() {
+            report.warnings.push(
+                "The binary has no metadata, it wasn't built with hyperlight-guest-build"
+                    .to_string(),
+            );
+        }
+        match report.abi_version {
+            Some(version) if version > ABI_VERSION => report.errors.push(format!(
+                "The binary was built against ABI version {}, newer than the host's {}",
+                version, ABI_VERSION
+            )),
+            Some(version) if version < ABI_VERSION => report.warnings.push(format!(
+                "The binary was built against ABI version {}, older than the host's {}",
+                version, ABI_VERSION
+            )),
+            Some(_) => {}
+            None => report.warnings.push(
+                "The binary doesn't declare the ABI version it was built against".to_string(),
+            ),
+        }
+
+        report.loaded_size = exe_info.loaded_size();
+        report.stack_size = cfg.get_stack_size(&exe_info);
+        report.heap_size = cfg.get_heap_size(&exe_info);
+        match guest_memory_size(cfg, &exe_info) {
+            Ok(size) => report.estimated_memory_size = Some(size),
+            Err(e) => report
+                .errors
+                .push(format!("The guest memory can't be laid out: {}", e)),
+        }
+        report
+    }
+}
+
+impl fmt::Display for GuestBinaryReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.format {
+            Some(format) => writeln!(f, "format: {:?}", format)?,
+            None => writeln!(f, "format: unknown")?,
+        }
+        match self.abi_version {
+            Some(version) => writeln!(f, "ABI version: {}", version)?,
+            None => writeln!(f, "ABI version: none")?,
+        }
+        writeln!(f, "loaded size: {:#x}", self.loaded_size)?;
+        writeln!(f, "stack size: {:#x}", self.stack_size)?;
+        writeln!(f, "heap size: {:#x}", self.heap_size)?;
+        if let Some(size) = self.estimated_memory_size {
+            writeln!(f, "estimated memory size: {:#x}", size)?;
+        }
+        for error in &self.errors {
+            writeln!(f, "error: {}", error)?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "warning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::guest_metadata::ABI_VERSION;
+    use hyperlight_testing::simple_guest_as_string;
+
+    use super::GuestBinaryFormat;
+    use crate::sandbox::uninitialized::GuestBinary;
+    use crate::sandbox::SandboxConfiguration;
+
+    #[test]
+    fn validate_guest_binaries() {
+        let guest = GuestBinary::FilePath(simple_guest_as_string().unwrap());
+        let report = guest.validate(None);
+        assert!(report.is_valid(), "{}", report);
+        assert_eq!(Some(GuestBinaryFormat::Elf), report.format);
+        // simpleguest is built with hyperlight-guest-build, so it carries
+        // the metadata its build script generates
+        assert_eq!(Some(ABI_VERSION), report.abi_version);
+        assert_eq!(Some("simpleguest"), report.metadata.get("name"));
+        assert!(report.warnings.is_empty(), "{}", report);
+        assert!(report.loaded_size > 0);
+        assert!(report.estimated_memory_size.unwrap() > report.loaded_size);
+
+        // the configuration's sizes are used
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_heap_size(0x40000);
+        let report = guest.validate(Some(cfg));
+        assert_eq!(0x40000, report.heap_size);
+
+        let report = GuestBinary::Buffer(vec![0x7f, b'E', b'L', b'F', 1, 2, 3]).validate(None);
+        assert!(!report.is_valid());
+        assert_eq!(None, report.format);
+
+        let report = GuestBinary::FilePath("does/not/exist".to_string()).validate(None);
+        assert!(!report.is_valid());
+    }
+}
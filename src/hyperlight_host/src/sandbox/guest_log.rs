@@ -0,0 +1,229 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{instrument, Span};
+
+/// Source of the unique IDs attached to every forwarded guest log record
+static NEXT_SANDBOX_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The window over which `max_records_per_second` is enforced
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(1);
+
+/// The longest line of debug output buffered before it is logged
+const MAX_DEBUG_LINE: usize = 4096;
+
+/// Per-sandbox state used when forwarding guest log records to the host.
+///
+/// Holds the ID that identifies the sandbox in the forwarded records and
+/// enforces the configured rate limit, counting any records it drops.
+#[derive(Debug)]
+pub(crate) struct GuestLogForwarder {
+    sandbox_id: u64,
+    max_records_per_second: u32,
+    window: Mutex<RateLimitWindow>,
+    dropped_records: AtomicU64,
+    overflowed_records: AtomicU64,
+    /// The guest's debug output since the last complete line
+    debug_line: Mutex<Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct RateLimitWindow {
+    start: Instant,
+    forwarded: u32,
+    dropped: u64,
+}
+
+impl GuestLogForwarder {
+    /// Create a new `GuestLogForwarder` with a fresh sandbox ID. If
+    /// `max_records_per_second` is 0, records are never dropped.
+    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
+    pub(crate) fn new(max_records_per_second: u32) -> Self {
+        Self {
+            sandbox_id: NEXT_SANDBOX_ID.fetch_add(1, Ordering::Relaxed),
+            max_records_per_second,
+            window: Mutex::new(RateLimitWindow {
+                start: Instant::now(),
+                forwarded: 0,
+                dropped: 0,
+            }),
+            dropped_records: AtomicU64::new(0),
+            overflowed_records: AtomicU64::new(0),
+            debug_line: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The ID attached to every guest log record forwarded for this sandbox
+    pub(crate) fn sandbox_id(&self) -> u64 {
+        self.sandbox_id
+    }
+
+    /// The total number of guest log records dropped because the sandbox
+    /// exceeded its rate limit
+    pub(crate) fn dropped_records(&self) -> u64 {
+        self.dropped_records.load(Ordering::Relaxed)
+    }
+
+    /// The total number of guest log records the guest dropped because its
+    /// log ring buffer was full
+    pub(crate) fn overflowed_records(&self) -> u64 {
+        self.overflowed_records.load(Ordering::Relaxed)
+    }
+
+    /// Count `count` guest log records the guest dropped because its log
+    /// ring buffer was full
+    pub(crate) fn record_overflow(&self, count: u64) {
+        log::warn!(
+            "Dropped {} guest log records from sandbox {} because its log ring buffer was full",
+            count,
+            self.sandbox_id
+        );
+        self.overflowed_records.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Append a byte of the guest's debug output to the current line,
+    /// logging the line once it is complete
+    pub(crate) fn push_debug_output(&self, byte: u8) {
+        let mut line = self
+            .debug_line
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if byte != b'\n' {
+            line.push(byte);
+        }
+        if byte == b'\n' || line.len() >= MAX_DEBUG_LINE {
+            Self::log_debug_line(&mut line);
+        }
+    }
+
+    /// Log the guest's debug output since the last complete line, so that
+    /// it isn't held back until, or joined to the output of, the next
+    /// guest function call. Returns the number of bytes logged.
+    pub(crate) fn flush_debug_output(&self) -> usize {
+        let mut line = self
+            .debug_line
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let len = line.len();
+        if len > 0 {
+            Self::log_debug_line(&mut line);
+        }
+        len
+    }
+
+    fn log_debug_line(line: &mut Vec<u8>) {
+        log::debug!("Guest debug output: {}", String::from_utf8_lossy(line));
+        line.clear();
+    }
+
+    /// Returns `true` if the next guest log record should be forwarded, or
+    /// `false` if it should be dropped because the rate limit was reached.
+    pub(crate) fn try_forward(&self) -> bool {
+        if self.max_records_per_second == 0 {
+            return true;
+        }
+
+        // A poisoned lock only means another thread panicked mid-update,
+        // the counters are still usable
+        let mut window = self
+            .window
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let now = Instant::now();
+        if now.duration_since(window.start) >= RATE_LIMIT_WINDOW {
+            if window.dropped > 0 {
+                log::warn!(
+                    "Dropped {} guest log records from sandbox {} due to rate limiting",
+                    window.dropped,
+                    self.sandbox_id
+                );
+            }
+            window.start = now;
+            window.forwarded = 0;
+            window.dropped = 0;
+        }
+
+        if window.forwarded < self.max_records_per_second {
+            window.forwarded += 1;
+            true
+        } else {
+            window.dropped += 1;
+            self.dropped_records.fetch_add(1, Ordering::Relaxed);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GuestLogForwarder;
+
+    #[test]
+    fn unlimited_never_drops() {
+        let forwarder = GuestLogForwarder::new(0);
+        for _ in 0..1000 {
+            assert!(forwarder.try_forward());
+        }
+        assert_eq!(0, forwarder.dropped_records());
+    }
+
+    #[test]
+    fn drops_records_above_limit() {
+        let forwarder = GuestLogForwarder::new(10);
+        let forwarded = (0..25).filter(|_| forwarder.try_forward()).count();
+        assert_eq!(10, forwarded);
+        assert_eq!(15, forwarder.dropped_records());
+    }
+
+    #[test]
+    fn flushes_partial_debug_lines() {
+        let forwarder = GuestLogForwarder::new(0);
+        for byte in b"line\npartial" {
+            forwarder.push_debug_output(*byte);
+        }
+        // the complete line was logged as it was written
+        assert_eq!(b"partial".len(), forwarder.flush_debug_output());
+        assert_eq!(0, forwarder.flush_debug_output());
+
+        // as are overlong lines
+        for _ in 0..super::MAX_DEBUG_LINE + 1 {
+            forwarder.push_debug_output(b'x');
+        }
+        assert_eq!(1, forwarder.flush_debug_output());
+    }
+
+    #[test]
+    fn counts_overflowed_records() {
+        let forwarder = GuestLogForwarder::new(0);
+        assert_eq!(0, forwarder.overflowed_records());
+        forwarder.record_overflow(3);
+        forwarder.record_overflow(4);
+        assert_eq!(7, forwarder.overflowed_records());
+        assert_eq!(0, forwarder.dropped_records());
+    }
+
+    #[test]
+    fn sandbox_ids_are_unique() {
+        let first = GuestLogForwarder::new(0);
+        let second = GuestLogForwarder::new(0);
+        assert_ne!(first.sandbox_id(), second.sandbox_id());
+    }
+}
@@ -0,0 +1,66 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+/// How the guest's time stamp counter advances, so that guest logic that
+/// times itself with `RDTSC` behaves the same however long the host takes
+/// to run host functions or to call the guest again.
+///
+/// Outside of `GuestTime::Host`, the host sets the guest's TSC every time
+/// it resumes the vCPU, which costs a system call per VM exit. The TSC is
+/// part of the vCPU's state rather than of guest memory, so restoring a
+/// snapshot does not restore it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum GuestTime {
+    /// The guest's TSC is the host's, which keeps advancing while the
+    /// guest is stopped
+    #[default]
+    Host,
+    /// The guest's TSC starts at 0 when the sandbox is created, and only
+    /// advances while the vCPU runs. If `tsc_khz` is set, the TSC advances
+    /// at that frequency instead of the host's, which needs a processor
+    /// that can scale the TSC.
+    Virtual {
+        /// The frequency of the guest's TSC in kHz, or `None` for the
+        /// host's
+        tsc_khz: Option<u32>,
+    },
+    /// The guest's TSC is set back to the given value every time the vCPU
+    /// is resumed, so the guest reads the same time at the start of every
+    /// guest function call, host function return and other VM exit
+    Frozen(u64),
+}
+
+impl GuestTime {
+    /// The TSC the guest starts with
+    pub(crate) fn initial_tsc(&self) -> u64 {
+        match self {
+            GuestTime::Frozen(tsc) => *tsc,
+            _ => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GuestTime;
+
+    #[test]
+    fn initial_tsc() {
+        assert_eq!(0, GuestTime::Virtual { tsc_khz: None }.initial_tsc());
+        assert_eq!(0, GuestTime::Host.initial_tsc());
+        assert_eq!(1000, GuestTime::Frozen(1000).initial_tsc());
+    }
+}
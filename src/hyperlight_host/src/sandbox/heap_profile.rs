@@ -0,0 +1,254 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::sync::{Arc, Mutex};
+
+use tracing::{instrument, Span};
+
+use crate::{log_then_return, Result};
+
+/// The return addresses the guest records for each allocation site
+const SITE_FRAMES: usize = 16;
+/// The size of each site's statistics in the profile the guest sends
+const SITE_RECORD_SIZE: usize = (SITE_FRAMES + 4) * size_of::<u64>();
+
+/// The prefixes of the guest functions between the code that allocated and
+/// the allocator, which are skipped to find an allocation's site
+const ALLOCATOR_FRAMES: &[&str] = &[
+    "alloc::",
+    "core::",
+    "__rust_",
+    "__rg_",
+    "hyperlight_guest::heap_profiler::",
+];
+
+/// The allocations a guest made at one allocation site
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HeapProfileSite {
+    /// The return address in the guest code that allocated: the first of
+    /// `frames` outside `alloc`, `core` and the allocator if the guest
+    /// binary's symbol map was loaded, or else the first of `frames`. It is
+    /// 0 for allocations the guest couldn't find the site of.
+    pub site: u64,
+    /// The return addresses that led to the allocator, innermost first
+    pub frames: Vec<u64>,
+    /// How many allocations were made at the site
+    pub allocations: u64,
+    /// How many bytes were allocated at the site
+    pub allocated_bytes: u64,
+    /// How many of the allocations made at the site were still live at the
+    /// end of the guest function call
+    pub live_allocations: u64,
+    /// How many bytes of the allocations made at the site were still live
+    /// at the end of the guest function call
+    pub live_bytes: u64,
+    /// The guest function the site is in, and how far into it, if the
+    /// guest binary's symbol map was loaded
+    pub symbol: Option<String>,
+}
+
+/// Whether `symbol` is a function between the code that allocated and the
+/// allocator
+fn is_allocator_frame(symbol: &str) -> bool {
+    let symbol = symbol.trim_start_matches('<');
+    ALLOCATOR_FRAMES
+        .iter()
+        .any(|prefix| symbol.starts_with(prefix))
+}
+
+/// The allocations made by a guest built with the `heap_profiler` feature
+/// of `hyperlight_guest`, by allocation site, from its initialisation until
+/// the end of a guest function call. Sites are sorted by the bytes still
+/// live, then by the bytes allocated, largest first, so leaks come first.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HeapProfile {
+    /// The allocation sites the guest made allocations at
+    pub sites: Vec<HeapProfileSite>,
+}
+
+impl HeapProfile {
+    /// The bytes still live at the end of the guest function call
+    pub fn live_bytes(&self) -> u64 {
+        self.sites
+            .iter()
+            .fold(0, |bytes, site| bytes.saturating_add(site.live_bytes))
+    }
+
+    /// The bytes allocated since the guest was initialised
+    pub fn allocated_bytes(&self) -> u64 {
+        self.sites
+            .iter()
+            .fold(0, |bytes, site| bytes.saturating_add(site.allocated_bytes))
+    }
+
+    /// Set the site of every allocation site to the first of its frames
+    /// that `symbolize` finds outside the allocator, along with its symbol
+    pub(crate) fn resolve_sites(&mut self, symbolize: impl Fn(u64) -> Option<String>) {
+        for site in &mut self.sites {
+            let symbols: Vec<(u64, Option<String>)> = site
+                .frames
+                .iter()
+                .map(|&frame| (frame, symbolize(frame)))
+                .collect();
+            let resolved = symbols
+                .iter()
+                .find(|(_, symbol)| symbol.as_deref().is_some_and(|s| !is_allocator_frame(s)))
+                .or_else(|| symbols.first());
+            if let Some((frame, symbol)) = resolved {
+                site.site = *frame;
+                site.symbol = symbol.clone();
+            }
+        }
+    }
+
+    /// Parse the profile the guest sent with the `HostRecordHeapProfile`
+    /// host function
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn from_guest_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() % SITE_RECORD_SIZE != 0 {
+            log_then_return!(
+                "Heap profile of {} bytes is not made of {} byte site records",
+                bytes.len(),
+                SITE_RECORD_SIZE
+            );
+        }
+        let mut sites: Vec<HeapProfileSite> = bytes
+            .chunks_exact(SITE_RECORD_SIZE)
+            .map(|record| {
+                let field = |i: usize| {
+                    let mut le_bytes = [0u8; 8];
+                    le_bytes.copy_from_slice(&record[i * 8..(i + 1) * 8]);
+                    u64::from_le_bytes(le_bytes)
+                };
+                let frames: Vec<u64> = (0..SITE_FRAMES)
+                    .map(field)
+                    .take_while(|&frame| frame != 0)
+                    .collect();
+                HeapProfileSite {
+                    site: frames.first().copied().unwrap_or(0),
+                    frames,
+                    allocations: field(SITE_FRAMES),
+                    allocated_bytes: field(SITE_FRAMES + 1),
+                    live_allocations: field(SITE_FRAMES + 2),
+                    live_bytes: field(SITE_FRAMES + 3),
+                    symbol: None,
+                }
+            })
+            .collect();
+        sites.sort_by(|a, b| {
+            (b.live_bytes, b.allocated_bytes).cmp(&(a.live_bytes, a.allocated_bytes))
+        });
+        Ok(Self { sites })
+    }
+}
+
+/// The last heap profile a sandbox's guest sent, shared between the
+/// `HostRecordHeapProfile` host function and the sandbox
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LastHeapProfile(Arc<Mutex<Option<HeapProfile>>>);
+
+impl LastHeapProfile {
+    pub(crate) fn record(&self, profile: HeapProfile) {
+        *self.lock() = Some(profile);
+    }
+
+    pub(crate) fn get(&self) -> Option<HeapProfile> {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<HeapProfile>> {
+        // A profile is only ever replaced whole, so it is always valid
+        // even if a thread panicked while holding the lock
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HeapProfile, SITE_FRAMES, SITE_RECORD_SIZE};
+
+    fn record(frames: &[u64], counts: [u64; 4]) -> Vec<u8> {
+        let mut fields = [0; SITE_FRAMES].to_vec();
+        fields[..frames.len()].copy_from_slice(frames);
+        fields.extend(counts);
+        fields.iter().flat_map(|f| f.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn parse_guest_profile() {
+        let mut bytes = record(&[0x1000], [4, 400, 0, 0]);
+        bytes.extend(record(&[0x2000, 0x2100], [2, 64, 1, 32]));
+        bytes.extend(record(&[], [1, 8, 1, 8]));
+        let profile = HeapProfile::from_guest_bytes(&bytes).unwrap();
+
+        let sites: Vec<u64> = profile.sites.iter().map(|s| s.site).collect();
+        assert_eq!(vec![0x2000, 0, 0x1000], sites);
+        assert_eq!(vec![0x2000, 0x2100], profile.sites[0].frames);
+        assert_eq!(40, profile.live_bytes());
+        assert_eq!(472, profile.allocated_bytes());
+
+        assert_eq!(
+            HeapProfile::default(),
+            HeapProfile::from_guest_bytes(&[]).unwrap()
+        );
+        assert!(HeapProfile::from_guest_bytes(&bytes[..SITE_RECORD_SIZE - 1]).is_err());
+
+        let bytes = [
+            record(&[0x1000], [1, u64::MAX, 0, 0]),
+            record(&[0x2000], [1, 1, 0, 0]),
+        ];
+        let profile = HeapProfile::from_guest_bytes(&bytes.concat()).unwrap();
+        assert_eq!(u64::MAX, profile.allocated_bytes());
+    }
+
+    #[test]
+    fn sites_skip_allocator_frames() {
+        let bytes = [
+            record(&[0x10, 0x20, 0x30, 0x40], [1, 8, 1, 8]),
+            record(&[0x10, 0x50], [1, 8, 1, 8]),
+            record(&[0x60], [1, 8, 1, 8]),
+        ];
+        let mut profile = HeapProfile::from_guest_bytes(&bytes.concat()).unwrap();
+        profile.resolve_sites(|frame| {
+            let name = match frame {
+                0x10 => "__rg_alloc",
+                0x20 => "alloc::raw_vec::RawVec<T,A>::grow_one",
+                0x30 => "<alloc::string::String as core::clone::Clone>::clone",
+                0x40 => "guest::handle_request",
+                0x50 => "alloc::alloc::alloc",
+                _ => return None,
+            };
+            Some(format!("{}+0x4", name))
+        });
+        let sites: Vec<_> = profile
+            .sites
+            .iter()
+            .map(|s| (s.site, s.symbol.as_deref()))
+            .collect();
+        assert_eq!(
+            vec![
+                (0x40, Some("guest::handle_request+0x4")),
+                // a site entirely in the allocator, or without symbols, is
+                // its innermost frame
+                (0x10, Some("__rg_alloc+0x4")),
+                (0x60, None),
+            ],
+            sites
+        );
+    }
+}
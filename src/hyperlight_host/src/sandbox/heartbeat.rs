@@ -0,0 +1,102 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use tracing::{instrument, Span};
+
+use crate::Result;
+
+/// The time a guest last called the `HostHeartbeat` host function, shared
+/// between that function, the sandbox, and the thread waiting for guest
+/// function calls to finish
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Heartbeat(Arc<Mutex<Option<Instant>>>);
+
+impl Heartbeat {
+    /// Record that the guest sent a heartbeat now
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn beat(&self) -> Result<()> {
+        *self.lock() = Some(Instant::now());
+        Ok(())
+    }
+
+    /// The time of the last heartbeat, or `None` if the guest never sent one
+    pub(crate) fn last(&self) -> Option<Instant> {
+        *self.lock()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Instant>> {
+        // The lock only guards a single `Instant`, which is always valid
+        // even if a thread panicked while holding it
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[derive(Debug, Default)]
+struct HostCallState {
+    /// Whether the guest is waiting for a host function call to return
+    in_host_call: bool,
+    /// The time the guest last returned from a host function call
+    last_returned: Option<Instant>,
+}
+
+/// Whether the guest is in a host function call, and when it last returned
+/// from one, shared between the outb handler and the thread waiting for
+/// guest function calls to finish, so that guests that stop calling the
+/// host can be cancelled
+#[derive(Clone, Debug, Default)]
+pub(crate) struct HostCallTracker(Arc<Mutex<HostCallState>>);
+
+impl HostCallTracker {
+    /// Record that the guest called a host function
+    pub(crate) fn enter(&self) {
+        self.lock().in_host_call = true;
+    }
+
+    /// Record that the host function the guest called returned
+    pub(crate) fn exit(&self) {
+        let mut state = self.lock();
+        state.in_host_call = false;
+        state.last_returned = Some(Instant::now());
+    }
+
+    /// The time the guest has been running since without calling the host:
+    /// the time it last returned from a host function call, or `call_start`
+    /// if it hasn't since then. `None` while it is in a host function call.
+    pub(crate) fn running_since(&self, call_start: Instant) -> Option<Instant> {
+        let state = self.lock();
+        match state.in_host_call {
+            true => None,
+            false => Some(
+                state
+                    .last_returned
+                    .map_or(call_start, |t| t.max(call_start)),
+            ),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HostCallState> {
+        // The state is a flag and an `Instant`, which are always valid even
+        // if a thread panicked while holding the lock
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -14,26 +14,83 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use std::io::{IsTerminal, Write};
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterValue, ReturnValue};
+use hyperlight_common::flatbuffer_wrappers::function_types::{
+    ParameterValue, ReturnType, ReturnValue,
+};
 use hyperlight_common::flatbuffer_wrappers::host_function_definition::HostFunctionDefinition;
 use hyperlight_common::flatbuffer_wrappers::host_function_details::HostFunctionDetails;
-use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 use tracing::{instrument, Span};
 
+use super::cpu_time::CpuTimeCounter;
 use super::{ExtraAllowedSyscall, FunctionsMap};
+use crate::func::arg_validation::ArgumentValidation;
+use crate::func::call_trace::CallTracer;
+use crate::func::host_handles::HostHandles;
+use crate::func::host_stream::HostChunkStreams;
+use crate::func::redaction::{RedactedArgs, RedactionPolicy, REDACTED};
 use crate::func::HyperlightFunction;
 use crate::mem::mgr::SandboxMemoryManager;
 use crate::mem::shared_mem::ExclusiveSharedMemory;
-use crate::HyperlightError::HostFunctionNotFound;
-use crate::{new_error, Result};
+use crate::HyperlightError::{
+    HostFunctionArgumentInvalid, HostFunctionNotFound, HostFunctionPanicked,
+};
+use crate::{log_then_return, new_error, Result};
 
 #[derive(Default, Clone)]
 /// A Wrapper around details of functions exposed by the Host
+///
+/// Cloning it is cheap, so that a host function can be called on a clone
+/// without holding the lock on the wrapper the sandbox shares.
 pub struct HostFuncsWrapper {
-    functions_map: FunctionsMap,
-    function_details: HostFunctionDetails,
+    functions_map: Arc<FunctionsMap>,
+    function_details: Arc<HostFunctionDetails>,
+    /// The names of the registered host functions, sorted, shared with the
+    /// `HostListFunctions` host function
+    function_names: Arc<Mutex<Vec<String>>>,
+    /// The streams returned by streaming host functions, shared with the
+    /// `HostReadNextChunk` host function
+    chunk_streams: HostChunkStreams,
+    /// The host objects the guest holds handles to
+    host_handles: HostHandles,
+    /// Called when a host function panics
+    panic_callback: HostFunctionPanicCallback,
+    /// The rules the arguments of host functions are checked against
+    /// before the functions are called, by function name
+    argument_validation: Arc<HashMap<String, ArgumentValidation>>,
+    /// The indexes of the `Sensitive` parameters of host functions, by
+    /// function name
+    sensitive_parameters: Arc<HashMap<String, Vec<usize>>>,
+    /// The policy set with `MultiUseSandbox::set_redaction_policy`, if any
+    redaction_policy: Option<Arc<RedactionPolicy>>,
+    /// Records the host function calls the guest makes while the sandbox
+    /// is tracing, and answers them while it replays a trace
+    tracer: CallTracer,
+}
+
+type HostFunctionPanicHandler = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
+/// The callback set with
+/// `UninitializedSandbox::set_host_function_panic_callback`, if any
+#[derive(Clone, Default)]
+pub(crate) struct HostFunctionPanicCallback(Option<HostFunctionPanicHandler>);
+
+impl HostFunctionPanicCallback {
+    pub(crate) fn new(callback: impl Fn(&str, &str) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(callback)))
+    }
+
+    /// Call the callback, if there is one, with the name of the host
+    /// function that panicked and the panic's message
+    fn notify(&self, name: &str, message: &str) {
+        if let Some(callback) = &self.0 {
+            callback(name, message);
+        }
+    }
 }
 
 impl HostFuncsWrapper {
@@ -43,7 +100,7 @@ impl HostFuncsWrapper {
     }
     #[instrument(skip_all, parent = Span::current(), level = "Trace")]
     fn get_host_funcs_mut(&mut self) -> &mut FunctionsMap {
-        &mut self.functions_map
+        Arc::make_mut(&mut self.functions_map)
     }
     #[instrument(skip_all, parent = Span::current(), level = "Trace")]
     fn get_host_func_details(&self) -> &HostFunctionDetails {
@@ -51,7 +108,108 @@ impl HostFuncsWrapper {
     }
     #[instrument(skip_all, parent = Span::current(), level = "Trace")]
     fn get_host_func_details_mut(&mut self) -> &mut HostFunctionDetails {
-        &mut self.function_details
+        Arc::make_mut(&mut self.function_details)
+    }
+
+    /// The names of the registered host functions, sorted. The list is
+    /// updated as more host functions are registered.
+    #[instrument(skip_all, parent = Span::current(), level = "Trace")]
+    pub(super) fn function_names(&self) -> Arc<Mutex<Vec<String>>> {
+        self.function_names.clone()
+    }
+
+    /// The streams returned to the guest by streaming host functions
+    #[instrument(skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn chunk_streams(&self) -> HostChunkStreams {
+        self.chunk_streams.clone()
+    }
+
+    /// The host objects the guest holds handles to, shared by the host
+    /// functions that hand them out and use them
+    #[instrument(skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn host_handles(&self) -> HostHandles {
+        self.host_handles.clone()
+    }
+
+    /// The tracer of the sandboxes these host functions are registered
+    /// with
+    #[instrument(skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn tracer(&self) -> CallTracer {
+        self.tracer.clone()
+    }
+
+    /// Set the callback called when a host function panics
+    #[instrument(skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn set_panic_callback(&mut self, callback: HostFunctionPanicCallback) {
+        self.panic_callback = callback;
+    }
+
+    /// Check the arguments of calls to the host function `name` against
+    /// `validation` before calling it, replacing any validation set
+    /// before. Fails if there is no such host function or a rule is for a
+    /// parameter it doesn't have.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn set_argument_validation(
+        &mut self,
+        name: &str,
+        validation: ArgumentValidation,
+    ) -> Result<()> {
+        let hfd = self
+            .get_host_func_details()
+            .find_by_function_name(name)
+            .ok_or_else(|| HostFunctionNotFound(name.to_string()))?;
+        let param_count = hfd.parameter_types.map_or(0, |types| types.len());
+        if let Some(index) = validation.max_index() {
+            if index >= param_count {
+                log_then_return!(
+                    "Host function {} has {} parameters, but there is a rule for parameter {}",
+                    name,
+                    param_count,
+                    index
+                );
+            }
+        }
+        Arc::make_mut(&mut self.argument_validation).insert(name.to_string(), validation);
+        Ok(())
+    }
+
+    /// Never show the parameters of the host function `name` at
+    /// `indexes`, which are declared `Sensitive`
+    #[instrument(skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn set_sensitive_parameters(&mut self, name: &str, indexes: Vec<usize>) {
+        let sensitive_parameters = Arc::make_mut(&mut self.sensitive_parameters);
+        if indexes.is_empty() {
+            sensitive_parameters.remove(name);
+        } else {
+            sensitive_parameters.insert(name.to_string(), indexes);
+        }
+    }
+
+    /// The indexes of the `Sensitive` parameters of the host function
+    /// `name`
+    fn sensitive_indexes(&self, name: &str) -> &[usize] {
+        self.sensitive_parameters
+            .get(name)
+            .map_or(&[], |indexes| indexes.as_slice())
+    }
+
+    /// Redact the parameters of host function calls that `policy` matches,
+    /// as well as `Sensitive` ones, when they are shown
+    #[instrument(skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn set_redaction_policy(&mut self, policy: Option<RedactionPolicy>) {
+        self.redaction_policy = policy.map(Arc::new);
+    }
+
+    /// `args`, the arguments of a call to the host function `name`, with
+    /// their redacted parameters replaced, or `None` if there is no
+    /// redaction policy, in which case the arguments aren't shown
+    pub(crate) fn redacted_args<'a>(
+        &'a self,
+        name: &'a str,
+        args: &'a [ParameterValue],
+    ) -> Option<RedactedArgs<'a>> {
+        let policy = self.redaction_policy.as_deref()?;
+        Some(policy.apply_with_sensitive(name, args, self.sensitive_indexes(name)))
     }
 
     /// Register a host function with the sandbox.
@@ -79,6 +237,37 @@ impl HostFuncsWrapper {
         register_host_function_helper(self, mgr, hfd, func, Some(extra_allowed_syscalls))
     }
 
+    /// Declare the feature `name` to the guest, and write it with the
+    /// details of the host functions to the memory managed by `mgr`
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn declare_feature(
+        &mut self,
+        mgr: &mut SandboxMemoryManager<ExclusiveSharedMemory>,
+        name: &str,
+    ) -> Result<()> {
+        if name.is_empty() {
+            log_then_return!("A host feature's name can't be empty");
+        }
+        self.get_host_func_details_mut().insert_feature(name);
+        self.write_host_function_details(mgr)
+    }
+
+    /// Write the details of all the registered host functions to the
+    /// memory managed by `mgr`, where the guest looks them up.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn write_host_function_details(
+        &self,
+        mgr: &mut SandboxMemoryManager<ExclusiveSharedMemory>,
+    ) -> Result<()> {
+        let buffer: Vec<u8> = self.get_host_func_details().try_into().map_err(|e| {
+            new_error!(
+                "Error serializing host function details to flatbuffer: {}",
+                e
+            )
+        })?;
+        mgr.write_buffer_host_function_details(&buffer)
+    }
+
     /// Assuming a host function called `"HostPrint"` exists, and takes a
     /// single string parameter, call it with the given `msg` parameter.
     ///
@@ -90,6 +279,7 @@ impl HostFuncsWrapper {
             self.get_host_funcs(),
             "HostPrint",
             vec![ParameterValue::String(msg)],
+            None,
         )?;
         res.try_into()
             .map_err(|_| HostFunctionNotFound("HostPrint".to_string()))
@@ -100,14 +290,90 @@ impl HostFuncsWrapper {
     ///
     /// Return `Err` if no such function exists,
     /// its parameter list doesn't match `args`, or there was another error
-    /// getting, configuring or calling the function.
+    /// getting, configuring or calling the function. If an argument breaks
+    /// the function's `ArgumentValidation` the function isn't called and
+    /// `HostFunctionArgumentInvalid` is returned. If the function
+    /// panicked, the panic callback is called and `HostFunctionPanicked`
+    /// is returned.
     #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
     pub(super) fn call_host_function(
         &self,
         name: &str,
         args: Vec<ParameterValue>,
     ) -> Result<ReturnValue> {
-        call_host_func_impl(self.get_host_funcs(), name, args)
+        self.call_host_function_with_cpu_time(name, args, None)
+    }
+
+    /// As `call_host_function`, also adding the CPU time the function uses
+    /// on a seccomp worker thread to `cpu_time`. Host functions that run on
+    /// the calling thread are accounted for by its caller.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(super) fn call_host_function_with_cpu_time(
+        &self,
+        name: &str,
+        args: Vec<ParameterValue>,
+        cpu_time: Option<&CpuTimeCounter>,
+    ) -> Result<ReturnValue> {
+        self.check_arguments(name, &args)?;
+        self.call_checked_host_function(name, args, cpu_time)
+    }
+
+    /// Check `args` against the `ArgumentValidation` of the host function
+    /// `name`, if it has one
+    fn check_arguments(&self, name: &str, args: &[ParameterValue]) -> Result<()> {
+        match self.argument_validation.get(name) {
+            // why a sensitive argument is invalid could show its value
+            Some(validation) => validation.check(name, args).map_err(|e| match e {
+                HostFunctionArgumentInvalid(name, index, _)
+                    if self.sensitive_indexes(&name).contains(&index) =>
+                {
+                    HostFunctionArgumentInvalid(name, index, REDACTED.to_string())
+                }
+                e => e,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// As `call_host_function_with_cpu_time`, for arguments that have
+    /// already been checked
+    fn call_checked_host_function(
+        &self,
+        name: &str,
+        args: Vec<ParameterValue>,
+        cpu_time: Option<&CpuTimeCounter>,
+    ) -> Result<ReturnValue> {
+        let res = call_host_func_impl(self.get_host_funcs(), name, args, cpu_time);
+        if let Err(HostFunctionPanicked(name, message)) = &res {
+            log::error!("Host function {} panicked: {}", name, message);
+            self.panic_callback.notify(name, message);
+        }
+        res
+    }
+
+    /// As `call_host_function_with_cpu_time`, for a call the guest made
+    /// expecting `return_type`. The call is recorded if the sandbox is
+    /// tracing, and answered from the trace instead of calling the host
+    /// function if the sandbox is replaying one. Calls with invalid
+    /// arguments are neither recorded nor replayed.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(super) fn call_host_function_traced(
+        &self,
+        name: &str,
+        args: Vec<ParameterValue>,
+        return_type: ReturnType,
+        cpu_time: &CpuTimeCounter,
+    ) -> Result<ReturnValue> {
+        self.check_arguments(name, &args)?;
+        if let Some(res) = self.tracer.replay_host_call(name, &args, return_type) {
+            return res;
+        }
+        let traced_args = self.tracer.is_recording().then(|| args.clone());
+        let res = self.call_checked_host_function(name, args, Some(cpu_time));
+        if let Some(args) = traced_args {
+            self.tracer.record_host_call(name, args, return_type, &res);
+        }
+        res
     }
 }
 
@@ -142,15 +408,19 @@ fn register_host_function_helper(
     self_
         .get_host_func_details_mut()
         .sort_host_functions_by_name();
-    let buffer: Vec<u8> = self_.get_host_func_details().try_into().map_err(|e| {
-        new_error!(
-            "Error serializing host function details to flatbuffer: {}",
-            e
-        )
-    })?;
-    mgr.write_buffer_host_function_details(&buffer)?;
-
-    Ok(())
+    let function_names = self_
+        .get_host_func_details()
+        .host_functions
+        .iter()
+        .flatten()
+        .map(|f| f.function_name.clone())
+        .collect();
+    *self_
+        .function_names
+        .try_lock()
+        .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))? =
+        function_names;
+    self_.write_host_function_details(mgr)
 }
 
 #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
@@ -158,6 +428,7 @@ fn call_host_func_impl(
     host_funcs: &FunctionsMap,
     name: &str,
     args: Vec<ParameterValue>,
+    _cpu_time: Option<&CpuTimeCounter>,
 ) -> Result<ReturnValue> {
     // Inner function containing the common logic
     fn call_func(
@@ -171,16 +442,6 @@ fn call_host_func_impl(
 
         let func = func_with_syscalls.0.clone();
 
-        #[cfg(all(feature = "seccomp", target_os = "linux"))]
-        {
-            let syscalls = func_with_syscalls.1.clone();
-            let seccomp_filter =
-                crate::seccomp::guest::get_seccomp_filter_for_host_function_worker_thread(
-                    syscalls,
-                )?;
-            seccompiler::apply_filter(&seccomp_filter)?;
-        }
-
         #[cfg(feature = "function_call_metrics")]
         {
             let start = std::time::Instant::now();
@@ -199,58 +460,70 @@ fn call_host_func_impl(
 
     cfg_if::cfg_if! {
         if #[cfg(all(feature = "seccomp", target_os = "linux"))] {
-            // Clone variables for the thread
+            let syscalls = host_funcs
+                .get(name)
+                .ok_or_else(|| HostFunctionNotFound(name.to_string()))?
+                .1
+                .clone();
+
+            // Clone variables for the worker thread
             let host_funcs_cloned = host_funcs.clone();
             let name_cloned = name.to_string();
-            let args_cloned = args.clone();
-
-            // Create a new thread when seccomp is enabled on Linux
-            let join_handle = std::thread::Builder::new()
-                .name(format!("Host Function Worker Thread for: {:?}", name_cloned))
-                .spawn(move || {
-                    // We have a `catch_unwind` here because, if a disallowed syscall is issued,
-                    // we handle it by panicking. This is to avoid returning execution to the
-                    // offending host function—for two reasons: (1) if a host function is issuing
-                    // disallowed syscalls, it could be unsafe to return to, and (2) returning
-                    // execution after trapping the disallowed syscall can lead to UB (e.g., try
-                    // running a host function that attempts to sleep without `SYS_clock_nanosleep`,
-                    // you'll block the syscall but panic in the aftermath).
-                    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| call_func(&host_funcs_cloned, &name_cloned, args_cloned))) {
-                        Ok(val) => val,
-                        Err(err) => {
-                            if let Some(crate::HyperlightError::DisallowedSyscall) = err.downcast_ref::<crate::HyperlightError>() {
-                                return Err(crate::HyperlightError::DisallowedSyscall)
-                            }
-
-                            crate::log_then_return!("Host function {} panicked", name_cloned);
-                        }
+            let cpu_time = _cpu_time.cloned().unwrap_or_default();
+
+            // Run the function on a pooled worker thread with the seccomp filter applied.
+            // The worker catches panics because, if a disallowed syscall is issued,
+            // we handle it by panicking. This is to avoid returning execution to the
+            // offending host function—for two reasons: (1) if a host function is issuing
+            // disallowed syscalls, it could be unsafe to return to, and (2) returning
+            // execution after trapping the disallowed syscall can lead to UB (e.g., try
+            // running a host function that attempts to sleep without `SYS_clock_nanosleep`,
+            // you'll block the syscall but panic in the aftermath).
+            match crate::seccomp::worker_pool::run_on_worker(syscalls, move || {
+                cpu_time.measure(|| call_func(&host_funcs_cloned, &name_cloned, args))
+            })? {
+                Ok(val) => val,
+                Err(err) => {
+                    if let Some(crate::HyperlightError::DisallowedSyscall) = err.downcast_ref::<crate::HyperlightError>() {
+                        return Err(crate::HyperlightError::DisallowedSyscall)
                     }
-                })?;
 
-            join_handle.join().map_err(|_| new_error!("Error joining thread executing host function"))?
+                    Err(HostFunctionPanicked(name.to_string(), panic_message(err.as_ref())))
+                }
+            }
         } else {
-            // Directly call the function without creating a new thread
-            call_func(host_funcs, name, args)
+            // Directly call the function without creating a new thread,
+            // catching panics so that they don't unwind through the
+            // hypervisor handler
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                call_func(host_funcs, name, args)
+            }))
+            .unwrap_or_else(|err| {
+                Err(HostFunctionPanicked(name.to_string(), panic_message(err.as_ref())))
+            })
         }
     }
 }
 
-/// The default writer function is to write to stdout with green text.
+/// The message a panic was raised with, if it was raised with one
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// The function registered as `HostSleep`, which the guest calls to be
+/// suspended for the given number of milliseconds. Sleeping for 0
+/// milliseconds yields the thread running the guest.
 #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
-pub(super) fn default_writer_func(s: String) -> Result<i32> {
-    match std::io::stdout().is_terminal() {
-        false => {
-            print!("{}", s);
-            Ok(s.len() as i32)
-        }
-        true => {
-            let mut stdout = StandardStream::stdout(ColorChoice::Auto);
-            let mut color_spec = ColorSpec::new();
-            color_spec.set_fg(Some(Color::Green));
-            stdout.set_color(&color_spec)?;
-            stdout.write_all(s.as_bytes())?;
-            stdout.reset()?;
-            Ok(s.len() as i32)
-        }
+pub(super) fn sleep_func(ms: u64) -> Result<()> {
+    match ms {
+        0 => std::thread::yield_now(),
+        ms => std::thread::sleep(Duration::from_millis(ms)),
     }
+    Ok(())
 }
@@ -0,0 +1,492 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::collections::HashMap;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use tracing::{instrument, Span};
+
+use crate::sandbox::memory_pressure::SandboxUsage;
+use crate::sandbox::SandboxState;
+use crate::{MultiUseSandbox, Result};
+
+/// What `HousekeepingScheduler::run_if_due` did
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum HousekeepingOutcome {
+    /// The sandbox isn't due for housekeeping for the given time
+    NotDue(Duration),
+    /// The housekeeping function was called
+    Ran,
+    /// The sandbox was due, but it was running a guest function call,
+    /// wasn't ready to call guest functions or was hibernated, so it was
+    /// left alone until the next interval
+    Skipped,
+}
+
+/// Schedules calls to a guest maintenance function, such as one that
+/// expires cached entries, on long-lived sandboxes that are otherwise
+/// idle. The function is called with `MultiUseSandbox::run_housekeeping`,
+/// so what it changes is kept after later guest function calls.
+///
+/// - Each sandbox is due for housekeeping `interval` after it was first
+///   seen by `run_if_due`, and `interval` after each time it was due since.
+/// - A random delay of up to `jitter` is added to each interval, so that
+///   sandboxes created together don't all run their housekeeping at once.
+/// - A sandbox that is due but is busy running a guest function call,
+///   isn't ready to call guest functions, e.g. because it was poisoned, or
+///   is hibernated, is skipped rather than waited for, reset or resumed,
+///   and is due again an interval later.
+/// - A sandbox stops being scheduled when it is dropped.
+///
+/// `start` moves the scheduler to a thread of its own that runs the
+/// housekeeping of the sandboxes handed to it, which is how most embedders
+/// use it. An embedder that manages its own pool of sandboxes can instead
+/// call `run_if_due` with each idle sandbox, waiting until `next_due` in
+/// between.
+#[derive(Debug)]
+pub struct HousekeepingScheduler {
+    function_name: String,
+    interval: Duration,
+    jitter: Duration,
+    /// When each sandbox, by ID, is next due
+    due: HashMap<u64, Due>,
+}
+
+#[derive(Debug)]
+struct Due {
+    at: Instant,
+    /// Whether the sandbox is still alive, since its usage is dropped with
+    /// it
+    usage: Weak<SandboxUsage>,
+}
+
+impl HousekeepingScheduler {
+    /// Create a new `HousekeepingScheduler` that calls the guest function
+    /// `function_name`, which takes no parameters and returns nothing,
+    /// every `interval`
+    pub fn new(function_name: impl Into<String>, interval: Duration) -> Self {
+        Self {
+            function_name: function_name.into(),
+            interval,
+            jitter: Duration::ZERO,
+            due: HashMap::new(),
+        }
+    }
+
+    /// Add a random delay of up to `jitter` to each interval. Defaults to
+    /// no jitter.
+    pub fn set_jitter(&mut self, jitter: Duration) {
+        self.jitter = jitter;
+    }
+
+    /// The name of the guest function housekeeping calls
+    pub fn function_name(&self) -> &str {
+        &self.function_name
+    }
+
+    /// Call the housekeeping function on `sbox` if it is due, and schedule
+    /// its next housekeeping. If the call fails, its error is returned and
+    /// the sandbox is due again an interval later.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn run_if_due(&mut self, sbox: &mut MultiUseSandbox) -> Result<HousekeepingOutcome> {
+        let ready = sbox.state() == SandboxState::Ready && !sbox.is_hibernated();
+        let outcome = self.poll_at(sbox.id(), &sbox.usage, ready, Instant::now());
+        if outcome == HousekeepingOutcome::Ran {
+            sbox.run_housekeeping(&self.function_name)?;
+        }
+        Ok(outcome)
+    }
+
+    /// The earliest time a live sandbox the scheduler has seen is due, or
+    /// `None` if there are none
+    pub fn next_due(&self) -> Option<Instant> {
+        self.live().map(|(_, due)| due.at).min()
+    }
+
+    /// The IDs of the live sandboxes that are due, see
+    /// `MultiUseSandbox::id`
+    pub fn due_sandboxes(&self) -> Vec<u64> {
+        let now = Instant::now();
+        self.live()
+            .filter(|(_, due)| due.at <= now)
+            .map(|(sandbox_id, _)| *sandbox_id)
+            .collect()
+    }
+
+    /// Stop scheduling housekeeping for the sandbox with the given ID
+    /// before it is dropped
+    pub fn forget(&mut self, sandbox_id: u64) {
+        self.due.remove(&sandbox_id);
+    }
+
+    /// Run housekeeping on a thread of its own for the sandboxes added to
+    /// the returned `HousekeepingThread`, until it is dropped
+    pub fn start(self) -> Result<HousekeepingThread> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(ThreadState {
+                scheduler: self,
+                sandboxes: Vec::new(),
+                stopped: false,
+            }),
+            wake: Condvar::new(),
+        });
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("Hyperlight Housekeeping".to_string())
+                .spawn(move || shared.run())?
+        };
+        Ok(HousekeepingThread {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    fn live(&self) -> impl Iterator<Item = (&u64, &Due)> + '_ {
+        self.due
+            .iter()
+            .filter(|(_, due)| due.usage.strong_count() > 0)
+    }
+
+    /// Decide whether the sandbox `sandbox_id` runs its housekeeping at
+    /// `now`, scheduling the next one if it is due. The sandboxes that were
+    /// dropped since are forgotten.
+    fn poll_at(
+        &mut self,
+        sandbox_id: u64,
+        usage: &Arc<SandboxUsage>,
+        ready: bool,
+        now: Instant,
+    ) -> HousekeepingOutcome {
+        self.due.retain(|_, due| due.usage.strong_count() > 0);
+        let next = now + self.next_interval();
+        let due = self.due.entry(sandbox_id).or_insert_with(|| Due {
+            at: next,
+            usage: Arc::downgrade(usage),
+        });
+        if due.at > now {
+            return HousekeepingOutcome::NotDue(due.at - now);
+        }
+        due.at = next;
+        if ready && !usage.is_busy() {
+            HousekeepingOutcome::Ran
+        } else {
+            HousekeepingOutcome::Skipped
+        }
+    }
+
+    fn next_interval(&self) -> Duration {
+        self.interval + self.jitter.mul_f64(rand::random::<f64>())
+    }
+}
+
+/// The thread a `HousekeepingScheduler` runs housekeeping on, see
+/// `HousekeepingScheduler::start`. Dropping it stops the thread.
+#[derive(Debug)]
+pub struct HousekeepingThread {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl HousekeepingThread {
+    /// Schedule housekeeping for `sbox`, returning it to call guest
+    /// functions on with the lock held. While a guest function call is
+    /// running or the lock is held, the sandbox is busy and its
+    /// housekeeping is skipped. Housekeeping stops when every clone of the
+    /// returned sandbox is dropped.
+    pub fn add(&self, sbox: MultiUseSandbox) -> Arc<Mutex<MultiUseSandbox>> {
+        let (id, usage) = (sbox.id(), sbox.usage.clone());
+        let sbox = Arc::new(Mutex::new(sbox));
+        self.shared.lock().sandboxes.push(ManagedSandbox {
+            id,
+            usage,
+            sandbox: Arc::downgrade(&sbox),
+        });
+        self.shared.wake.notify_all();
+        sbox
+    }
+
+    /// The number of live sandboxes housekeeping is scheduled for
+    pub fn sandbox_count(&self) -> usize {
+        self.shared
+            .lock()
+            .sandboxes
+            .iter()
+            .filter(|managed| managed.sandbox.strong_count() > 0)
+            .count()
+    }
+}
+
+impl Drop for HousekeepingThread {
+    fn drop(&mut self) {
+        self.shared.lock().stopped = true;
+        self.shared.wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::error!("The housekeeping thread panicked");
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<ThreadState>,
+    /// Notified when a sandbox is added or the thread is stopped
+    wake: Condvar,
+}
+
+#[derive(Debug)]
+struct ThreadState {
+    scheduler: HousekeepingScheduler,
+    sandboxes: Vec<ManagedSandbox>,
+    stopped: bool,
+}
+
+#[derive(Debug)]
+struct ManagedSandbox {
+    id: u64,
+    /// Shared with the sandbox, to tell whether it is running a guest
+    /// function call without waiting for its lock
+    usage: Arc<SandboxUsage>,
+    sandbox: Weak<Mutex<MultiUseSandbox>>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, ThreadState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn run(&self) {
+        let mut state = self.lock();
+        while !state.stopped {
+            let ThreadState {
+                scheduler,
+                sandboxes,
+                ..
+            } = &mut *state;
+            let now = Instant::now();
+            sandboxes.retain(|managed| {
+                let Some(sbox) = managed.sandbox.upgrade() else {
+                    scheduler.forget(managed.id);
+                    return false;
+                };
+                // a sandbox whose lock is held is busy too, don't wait for it
+                let mut guard = match managed.usage.is_busy() {
+                    true => None,
+                    false => sbox.try_lock().ok(),
+                };
+                let ready = guard.as_ref().is_some_and(|sbox| {
+                    sbox.state() == SandboxState::Ready && !sbox.is_hibernated()
+                });
+                let outcome = scheduler.poll_at(managed.id, &managed.usage, ready, now);
+                if let (HousekeepingOutcome::Ran, Some(sbox)) = (outcome, guard.as_deref_mut()) {
+                    if let Err(e) = sbox.run_housekeeping(&scheduler.function_name) {
+                        log::warn!("Housekeeping sandbox {} failed: {:?}", managed.id, e);
+                    }
+                }
+                true
+            });
+            let wait = match state.scheduler.next_due() {
+                Some(due) => due.saturating_duration_since(Instant::now()),
+                None => state.scheduler.interval,
+            };
+            state = self
+                .wake
+                .wait_timeout(state, wait)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use hyperlight_common::flatbuffer_wrappers::function_types::{
+        ParameterValue, ReturnType, ReturnValue,
+    };
+    use hyperlight_testing::simple_guest_as_string;
+
+    use super::{HousekeepingOutcome, HousekeepingScheduler};
+    use crate::func::call_ctx::MultiUseGuestCallContext;
+    use crate::sandbox::memory_pressure::SandboxUsage;
+    use crate::sandbox::uninitialized::GuestBinary;
+    use crate::sandbox_state::sandbox::EvolvableSandbox;
+    use crate::sandbox_state::transition::{MultiUseContextCallback, Noop};
+    use crate::{MultiUseSandbox, UninitializedSandbox};
+
+    #[test]
+    fn sandboxes_are_due_every_interval() {
+        let interval = Duration::from_secs(10);
+        let mut scheduler = HousekeepingScheduler::new("Expire", interval);
+        let usage = Arc::new(SandboxUsage::default());
+        let start = Instant::now();
+        assert_eq!(
+            HousekeepingOutcome::NotDue(interval),
+            scheduler.poll_at(1, &usage, true, start)
+        );
+        assert_eq!(
+            HousekeepingOutcome::NotDue(Duration::from_secs(5)),
+            scheduler.poll_at(1, &usage, true, start + Duration::from_secs(5))
+        );
+        assert_eq!(Some(start + interval), scheduler.next_due());
+
+        let due = start + interval;
+        assert_eq!(
+            HousekeepingOutcome::Ran,
+            scheduler.poll_at(1, &usage, true, due)
+        );
+        assert_eq!(
+            HousekeepingOutcome::NotDue(interval),
+            scheduler.poll_at(1, &usage, true, due)
+        );
+
+        // a sandbox that isn't ready is skipped until the next interval
+        let due = due + interval;
+        assert_eq!(
+            HousekeepingOutcome::Skipped,
+            scheduler.poll_at(1, &usage, false, due)
+        );
+        assert_eq!(
+            HousekeepingOutcome::NotDue(interval),
+            scheduler.poll_at(1, &usage, true, due)
+        );
+
+        // and so is one that is running a guest function call
+        let due = due + interval;
+        usage.set_busy(true);
+        assert_eq!(
+            HousekeepingOutcome::Skipped,
+            scheduler.poll_at(1, &usage, true, due)
+        );
+        usage.set_busy(false);
+
+        scheduler.forget(1);
+        assert_eq!(None, scheduler.next_due());
+    }
+
+    #[test]
+    fn dropped_sandboxes_are_forgotten() {
+        let interval = Duration::from_secs(10);
+        let mut scheduler = HousekeepingScheduler::new("Expire", interval);
+        let dropped = Arc::new(SandboxUsage::default());
+        let kept = Arc::new(SandboxUsage::default());
+        let start = Instant::now();
+        scheduler.poll_at(1, &dropped, true, start);
+        scheduler.poll_at(2, &kept, true, start + interval);
+        assert_eq!(Some(start + interval), scheduler.next_due());
+
+        drop(dropped);
+        assert_eq!(Some(start + interval * 2), scheduler.next_due());
+        scheduler.poll_at(2, &kept, true, start + interval);
+        assert_eq!(1, scheduler.due.len());
+    }
+
+    #[test]
+    fn jitter_delays_each_interval() {
+        let interval = Duration::from_secs(10);
+        let jitter = Duration::from_secs(2);
+        let mut scheduler = HousekeepingScheduler::new("Expire", interval);
+        scheduler.set_jitter(jitter);
+        let start = Instant::now();
+        for sandbox_id in 0..20 {
+            let usage = Arc::new(SandboxUsage::default());
+            let HousekeepingOutcome::NotDue(wait) =
+                scheduler.poll_at(sandbox_id, &usage, true, start)
+            else {
+                panic!("a new sandbox is not due");
+            };
+            assert!(wait >= interval && wait <= interval + jitter);
+        }
+    }
+
+    /// A sandbox whose guest has filled its cache with 4096 entries
+    fn cached_sandbox() -> MultiUseSandbox {
+        let path = simple_guest_as_string().unwrap();
+        let sbox: MultiUseSandbox =
+            UninitializedSandbox::new(GuestBinary::FilePath(path), None, None, None)
+                .unwrap()
+                .evolve(Noop::default())
+                .unwrap();
+        let func = Box::new(|call_ctx: &mut MultiUseGuestCallContext| {
+            call_ctx.call(
+                "FillCache",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(4096)]),
+            )?;
+            Ok(())
+        });
+        sbox.evolve(MultiUseContextCallback::from(func)).unwrap()
+    }
+
+    fn cache_size(sbox: &mut MultiUseSandbox) -> ReturnValue {
+        sbox.call_guest_function_by_name("GetCacheSize", ReturnType::Int, None)
+            .unwrap()
+    }
+
+    #[test]
+    fn housekeeping_calls_the_guest() {
+        let mut sbox = cached_sandbox();
+        assert_eq!(ReturnValue::Int(4096), cache_size(&mut sbox));
+
+        // the guest expires its cache on each housekeeping
+        let mut scheduler = HousekeepingScheduler::new("TrimMemory", Duration::from_millis(10));
+
+        assert!(matches!(
+            scheduler.run_if_due(&mut sbox).unwrap(),
+            HousekeepingOutcome::NotDue(_)
+        ));
+        assert_eq!(Vec::<u64>::new(), scheduler.due_sandboxes());
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(vec![sbox.id()], scheduler.due_sandboxes());
+        assert_eq!(
+            HousekeepingOutcome::Ran,
+            scheduler.run_if_due(&mut sbox).unwrap()
+        );
+        // what housekeeping changed is kept after later calls
+        assert_eq!(ReturnValue::Int(0), cache_size(&mut sbox));
+        assert_eq!(ReturnValue::Int(0), cache_size(&mut sbox));
+    }
+
+    #[test]
+    fn housekeeping_thread_runs_on_idle_sandboxes() {
+        let scheduler = HousekeepingScheduler::new("TrimMemory", Duration::from_millis(50));
+        let housekeeping = scheduler.start().unwrap();
+        let sbox = housekeeping.add(cached_sandbox());
+        assert_eq!(1, housekeeping.sandbox_count());
+
+        // housekeeping is skipped while the sandbox is locked
+        {
+            let mut sbox = sbox.lock().unwrap();
+            std::thread::sleep(Duration::from_millis(150));
+            assert_eq!(ReturnValue::Int(4096), cache_size(&mut sbox));
+        }
+
+        let start = Instant::now();
+        while cache_size(&mut sbox.lock().unwrap()) != ReturnValue::Int(0) {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        drop(sbox);
+        assert_eq!(0, housekeeping.sandbox_count());
+    }
+}
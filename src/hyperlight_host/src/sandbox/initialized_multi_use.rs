@@ -14,22 +14,81 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
+use std::thread::ThreadId;
+use std::time::{Duration, Instant};
 
+use hyperlight_common::flatbuffer_wrappers::function_call::{
+    is_in_namespace, GUEST_FUNCTION_DETAILS_FUNCTION_NAME, TRIM_MEMORY_FUNCTION_NAME,
+};
 use hyperlight_common::flatbuffer_wrappers::function_types::{
-    ParameterValue, ReturnType, ReturnValue,
+    ParameterRef, ParameterValue, ReturnType, ReturnValue,
 };
+use hyperlight_common::flatbuffer_wrappers::host_function_definition::FunctionFlags;
+use hyperlight_common::payload_compression::{max_uncompressed_size, PayloadCompression};
+use log::LevelFilter;
 use tracing::{instrument, Span};
 
 use super::host_funcs::HostFuncsWrapper;
+use super::uninitialized::SandboxSource;
 use super::{MemMgrWrapper, WrapperGetter};
+use crate::func::borrowed_bytes::{BorrowedBytes, ReturnedBytes};
 use crate::func::call_ctx::MultiUseGuestCallContext;
-use crate::func::guest_dispatch::call_function_on_guest;
+use crate::func::call_trace::{CallTrace, CallTracer, TraceEvent};
+use crate::func::guest_call_interceptor::{GuestCallAction, GuestCallInterceptor};
+use crate::func::guest_dispatch::{
+    call_function_on_guest, run_function_on_guest, write_function_call_to_slot, GuestCallOutput,
+    OutputDataBytes,
+};
+use crate::func::guest_function_policy::GuestFunctionPolicy;
+use crate::func::guest_signatures::{GuestFunctionSignature, GuestFunctionSignatures};
+use crate::func::host_handles::HostHandles;
+use crate::func::redaction::RedactionPolicy;
+use crate::func::session::Session;
 use crate::hypervisor::hypervisor_handler::HypervisorHandler;
-use crate::mem::shared_mem::HostSharedMemory;
+use crate::mem::hibernation::HibernatedMemory;
+use crate::mem::memory_region::GuestMemoryRegion;
+use crate::mem::mgr::{GuestMemoryDigest, MemoryCheckpoint};
+use crate::mem::shared_mem::{HostSharedMemory, SharedMemory};
+use crate::mem::snapshot_encryption::{SnapshotKey, SnapshotKeyProvider};
+use crate::mem::snapshot_file::{SnapshotDecoder, SnapshotEncoder, SnapshotHeader};
+use crate::metrics::record_guest_call;
+use crate::sandbox::config::MemoryPopulation;
+use crate::sandbox::crash_fingerprint::CrashFingerprint;
+use crate::sandbox::epoch::EpochHandle;
+use crate::sandbox::heap_profile::HeapProfile;
+use crate::sandbox::interrupt::needs_recreating;
+use crate::sandbox::malloc_trace::{LastMallocTrace, MallocTrace};
+use crate::sandbox::memory_pressure::{MemoryRegistry, SandboxUsage};
+use crate::sandbox::metrics::SandboxMetric::GuestCrashCount;
+use crate::sandbox::pause::PauseHandle;
+use crate::sandbox::progress::ProgressReport;
+use crate::sandbox::reclaim::defer_teardown;
+use crate::sandbox::retry::{RetryPolicy, RetryRecovery};
 use crate::sandbox_state::sandbox::{DevolvableSandbox, EvolvableSandbox, Sandbox};
 use crate::sandbox_state::transition::{MultiUseContextCallback, Noop};
-use crate::Result;
+use crate::{
+    int_counter_vec_inc, log_then_return, new_error, HyperlightError, Result, UninitializedSandbox,
+};
+
+/// Whether a `MultiUseSandbox` can be used to call guest functions
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SandboxState {
+    /// The sandbox is ready to call guest functions
+    Ready,
+    /// A guest function call is in progress. A sandbox is only left in
+    /// this state if a call panicked part way through.
+    Busy,
+    /// A guest function call crashed the guest or had to be interrupted,
+    /// so the guest may be in an inconsistent state. Guest functions can't
+    /// be called until the sandbox is reset or recreated.
+    Poisoned,
+}
 
 /// A sandbox that supports being used Multiple times.
 /// The implication of being used multiple times is two-fold:
@@ -39,11 +98,45 @@ use crate::Result;
 ///
 /// 2. A MultiUseGuestCallContext can be created from the sandbox and used to make multiple guest function calls to the Sandbox.
 ///    in this case the state of the sandbox is not reset until the context is finished and the `MultiUseSandbox` is returned.
+///
+/// A sandbox can be moved between threads, see `sandbox::transfer`.
 pub struct MultiUseSandbox {
     // We need to keep a reference to the host functions, even if the compiler marks it as unused. The compiler cannot detect our dynamic usages of the host function in `HyperlightFunction::call`.
     pub(super) _host_funcs: Arc<Mutex<HostFuncsWrapper>>,
     pub(crate) mem_mgr: MemMgrWrapper<HostSharedMemory>,
     hv_handler: HypervisorHandler,
+    state: SandboxState,
+    source: SandboxSource,
+    hibernated: Option<HibernatedMemory>,
+    guest_signatures: GuestFunctionSignatures,
+    guest_function_policy: GuestFunctionPolicy,
+    guest_call_interceptor: Option<Box<dyn GuestCallInterceptor>>,
+    retry_policy: Option<RetryPolicy>,
+    redaction_policy: Option<RedactionPolicy>,
+    hibernation_key: Option<SnapshotKeyProvider>,
+    /// The version of each versioned guest function called when it is
+    /// called without one
+    default_versions: HashMap<String, u32>,
+    /// The algorithm the guest accepted to compress large guest function
+    /// calls and results with when it was initialised
+    payload_compression: PayloadCompression,
+    /// Identifies this sandbox's memory as the memory the epoch is
+    /// mirrored to
+    epoch_attachment: u64,
+    /// Identifies this sandbox's vCPU as the one the pause handle pauses
+    pause_attachment: u64,
+    /// The last malloc trace this sandbox's guest sent
+    malloc_trace: LastMallocTrace,
+    /// Identifies this sandbox's trace as the one the malloc trace
+    /// recorder records in
+    malloc_trace_attachment: u64,
+    /// Whether the sandbox is running a guest function call and when it
+    /// was last used, and whether it was asked to trim its memory before
+    /// its next one, see `memory_pressure`
+    pub(super) usage: Arc<SandboxUsage>,
+    /// The thread the sandbox was last transferred to, which is the only
+    /// one that can call guest functions on it
+    pub(super) owner_thread: Option<ThreadId>,
 }
 
 // We need to implement drop to join the
@@ -54,14 +147,32 @@ pub struct MultiUseSandbox {
 // sandboxes and caused the system to run out of
 // resources. Now, this is covered by the test:
 // `create_1000_sandboxes`.
+//
+// Joining the thread, and releasing the memory and the virtual machine
+// once it has stopped, is left to the reclamation thread (see
+// `sandbox::reclaim`) so that dropping a sandbox is cheap. The memory is
+// only unmapped when the last of the clones moved to that thread is dropped.
 impl Drop for MultiUseSandbox {
     fn drop(&mut self) {
-        match self.hv_handler.kill_hypervisor_handler_thread() {
-            Ok(_) => {}
-            Err(e) => {
-                log::error!("[POTENTIAL THREAD LEAK] Potentially failed to kill hypervisor handler thread when dropping MultiUseSandbox: {:?}", e);
+        let mut hv_handler = self.hv_handler.clone();
+        let mem_mgr = self.mem_mgr.clone();
+        let hibernated = self.hibernated.take();
+        self.source.epoch.detach(self.epoch_attachment);
+        self.source.pause.detach(self.pause_attachment);
+        self.source
+            .malloc_trace
+            .detach(self.malloc_trace_attachment);
+        MemoryRegistry::global().unregister(self.id());
+        defer_teardown(move || {
+            match hv_handler.kill_hypervisor_handler_thread() {
+                Ok(_) => {}
+                Err(e) => {
+                    log::error!("[POTENTIAL THREAD LEAK] Potentially failed to kill hypervisor handler thread when dropping MultiUseSandbox: {:?}", e);
+                }
             }
-        }
+            drop(hibernated);
+            drop(mem_mgr);
+        });
     }
 }
 
@@ -76,12 +187,91 @@ impl MultiUseSandbox {
         host_funcs: Arc<Mutex<HostFuncsWrapper>>,
         mgr: MemMgrWrapper<HostSharedMemory>,
         hv_handler: HypervisorHandler,
+        source: SandboxSource,
     ) -> MultiUseSandbox {
+        let epoch_attachment = {
+            let mgr = mgr.unwrap_mgr();
+            source.epoch.attach(
+                mgr.shared_mem.clone(),
+                mgr.layout.get_epoch_offset(),
+                mgr.layout.get_epoch_deadline_offset(),
+                hv_handler.clone(),
+            )
+        };
+        let pause_attachment = source.pause.attach(hv_handler.clone());
+        let malloc_trace = LastMallocTrace::default();
+        let malloc_trace_attachment = source.malloc_trace.attach(malloc_trace.clone());
+        let usage = Arc::new(SandboxUsage::default());
+        {
+            let mgr = mgr.unwrap_mgr();
+            MemoryRegistry::global().register(
+                mgr.guest_log_forwarder().sandbox_id(),
+                mgr.shared_mem.mem_size(),
+                usage.clone(),
+            );
+        }
         Self {
             _host_funcs: host_funcs,
             mem_mgr: mgr,
             hv_handler,
+            state: SandboxState::Ready,
+            source,
+            hibernated: None,
+            guest_signatures: GuestFunctionSignatures::default(),
+            guest_function_policy: GuestFunctionPolicy::default(),
+            guest_call_interceptor: None,
+            retry_policy: None,
+            redaction_policy: None,
+            hibernation_key: None,
+            default_versions: HashMap::new(),
+            payload_compression: PayloadCompression::None,
+            epoch_attachment,
+            pause_attachment,
+            malloc_trace,
+            malloc_trace_attachment,
+            usage,
+            owner_thread: None,
+        }
+    }
+
+    /// Find the algorithm the guest accepted to compress large guest
+    /// function calls and results with when it was initialised
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(super) fn load_payload_compression(&mut self) -> Result<()> {
+        self.payload_compression = self.mem_mgr.unwrap_mgr().negotiated_payload_compression()?;
+        Ok(())
+    }
+
+    /// Ask the guest for the signatures of the functions it registered, so
+    /// calls to them can be checked before entering the guest, then restore
+    /// the sandbox's state.
+    ///
+    /// Guests built against an older guest library can't report their
+    /// signatures, calls to them are then only checked by the guest.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(super) fn load_guest_signatures(&mut self) -> Result<()> {
+        let res: Result<ReturnValue> = call_function_on_guest(
+            self,
+            GUEST_FUNCTION_DETAILS_FUNCTION_NAME,
+            ReturnType::VecBytes,
+            &[],
+        );
+        self.restore_state()?;
+        match res {
+            Ok(ReturnValue::VecBytes(details)) => {
+                self.guest_signatures = GuestFunctionSignatures::from_flatbuffer(&details)?;
+            }
+            Ok(other) => log::debug!(
+                "Guest returned {:?} for its function signatures, not checking guest function calls",
+                other
+            ),
+            Err(e) if e.poisons_sandbox() => return Err(e),
+            Err(e) => log::debug!(
+                "Guest did not report its function signatures, not checking guest function calls: {:?}",
+                e
+            ),
         }
+        Ok(())
     }
 
     /// Create a new `MultiUseCallContext` suitable for making 0 or more
@@ -155,7 +345,19 @@ impl MultiUseSandbox {
         MultiUseGuestCallContext::start(self)
     }
 
+    /// Start a session of guest function calls made back to back on this
+    /// sandbox, keeping the guest state between them, until the session
+    /// ends and the sandbox is reset. See `Session`.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn session(&mut self) -> Session<'_> {
+        Session::start(self)
+    }
+
     /// Call a guest function by name, with the given return type and arguments.
+    ///
+    /// If a retry policy was set with `set_retry_policy`, calls that fail
+    /// with a retryable error are tried again, after the sandbox is reset
+    /// or recreated as the policy sets.
     #[instrument(err(Debug), skip(self, args), parent = Span::current())]
     pub fn call_guest_function_by_name(
         &mut self,
@@ -163,192 +365,3293 @@ impl MultiUseSandbox {
         func_ret_type: ReturnType,
         args: Option<Vec<ParameterValue>>,
     ) -> Result<ReturnValue> {
-        let res = call_function_on_guest(self, func_name, func_ret_type, args);
+        let args: Vec<ParameterRef<'_>> = args.iter().flatten().map(ParameterRef::from).collect();
+        self.call_guest_function_by_name_borrowed(func_name, func_ret_type, &args)
+    }
+
+    /// Call a guest function by name, as with `call_guest_function_by_name`,
+    /// with borrowed arguments, so that `&str` and `&[u8]` arguments are
+    /// serialized into the call without being copied into a `String` or
+    /// `Vec<u8>` first.
+    ///
+    /// If the sandbox has a guest call interceptor or lenient parameter
+    /// coercion, the arguments are copied into `ParameterValue`s for them.
+    #[instrument(err(Debug), skip(self, args), parent = Span::current())]
+    pub fn call_guest_function_by_name_borrowed(
+        &mut self,
+        func_name: &str,
+        func_ret_type: ReturnType,
+        args: &[ParameterRef<'_>],
+    ) -> Result<ReturnValue> {
+        self.check_ready()?;
+        let res = self.call_with_retries(func_name, |sbox| {
+            sbox.call_guest_function_refs_no_reset(func_name, func_ret_type, args)
+        });
         self.restore_state()?;
+        self.trim_if_requested();
         res
     }
 
-    /// Restore the Sandbox's state
-    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
-    pub(crate) fn restore_state(&mut self) -> Result<()> {
-        let mem_mgr = self.mem_mgr.unwrap_mgr_mut();
-        mem_mgr.restore_state_from_last_snapshot()
+    /// Call a guest function by name that returns a `VecBytes`, as with
+    /// `call_guest_function_by_name_borrowed`, and return a view of the
+    /// bytes it returned where the guest left them in the output data
+    /// buffer, rather than a copy of them.
+    ///
+    /// The view borrows the sandbox, so it is valid until the next call.
+    /// The sandbox's state is restored once the view is dropped or
+    /// released with `BorrowedBytes::release`. If the sandbox has a guest
+    /// call interceptor or lenient parameter coercion, the view holds a
+    /// copy of the bytes instead.
+    #[instrument(err(Debug), skip(self, args), parent = Span::current())]
+    pub fn call_borrowed(
+        &mut self,
+        func_name: &str,
+        args: &[ParameterRef<'_>],
+    ) -> Result<BorrowedBytes<'_>> {
+        self.check_ready()?;
+        let res = self.call_with_retries(func_name, |sbox| {
+            sbox.call_borrowed_no_reset(func_name, args)
+        });
+        match res {
+            Ok(bytes) => Ok(BorrowedBytes::new(self, bytes)),
+            Err(e) => {
+                self.restore_state()?;
+                self.trim_if_requested();
+                Err(e)
+            }
+        }
     }
-}
 
-impl WrapperGetter for MultiUseSandbox {
-    fn get_mgr_wrapper(&self) -> &MemMgrWrapper<HostSharedMemory> {
-        &self.mem_mgr
-    }
-    fn get_mgr_wrapper_mut(&mut self) -> &mut MemMgrWrapper<HostSharedMemory> {
-        &mut self.mem_mgr
-    }
-    fn get_hv_handler(&self) -> &HypervisorHandler {
-        &self.hv_handler
-    }
-    fn get_hv_handler_mut(&mut self) -> &mut HypervisorHandler {
-        &mut self.hv_handler
-    }
-}
+    /// Make a guest function call with `call`, trying it again as the
+    /// sandbox's retry policy sets, if it has one
+    fn call_with_retries<T>(
+        &mut self,
+        func_name: &str,
+        mut call: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        let Some(policy) = self.retry_policy else {
+            return call(self);
+        };
+        if policy.idempotent_only() && !self.guest_function_flags(func_name).is_idempotent() {
+            return call(self);
+        }
 
-impl Sandbox for MultiUseSandbox {
-    fn check_stack_guard(&self) -> Result<bool> {
-        self.mem_mgr.check_stack_guard()
+        let mut attempt = 1;
+        loop {
+            match call(self) {
+                Err(e) if policy.should_retry(&e, attempt) => {
+                    log::warn!(
+                        "Retrying guest function {} after attempt {} failed: {:?}",
+                        func_name,
+                        attempt,
+                        e
+                    );
+                    match policy.recovery() {
+                        RetryRecovery::Reset => self.reset()?,
+                        RetryRecovery::Recreate => self.recreate_in_place()?,
+                    }
+                    attempt += 1;
+                }
+                res => return res,
+            }
+        }
     }
-}
 
-impl std::fmt::Debug for MultiUseSandbox {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("MultiUseSandbox")
-            .field("stack_guard", &self.mem_mgr.get_stack_cookie())
-            .finish()
+    /// Call a guest function by name, as with `call_guest_function_by_name`,
+    /// but cancel the call at `deadline` if it hasn't returned by then and
+    /// that is before the sandbox's maximum execution time.
+    ///
+    /// The guest can read how long the call has left with
+    /// `hyperlight_guest::deadline::hl_remaining_time`, so that it can
+    /// return a partial result before it is cancelled. Calls without a
+    /// deadline have until their maximum execution time.
+    #[instrument(err(Debug), skip(self, args), parent = Span::current())]
+    pub fn call_guest_function_by_name_with_deadline(
+        &mut self,
+        func_name: &str,
+        func_ret_type: ReturnType,
+        args: Option<Vec<ParameterValue>>,
+        deadline: Instant,
+    ) -> Result<ReturnValue> {
+        self.source.deadline.request(Some(deadline));
+        let res = self.call_guest_function_by_name(func_name, func_ret_type, args);
+        self.source.deadline.request(None);
+        res
     }
-}
 
-impl DevolvableSandbox<MultiUseSandbox, MultiUseSandbox, Noop<MultiUseSandbox, MultiUseSandbox>>
-    for MultiUseSandbox
-{
-    /// Consume `self` and move it back to a `MultiUseSandbox` with previous state.
+    /// Call a guest function by name that writes its result into the
+    /// result buffer and returns only the length of the result, then call
+    /// `read_result` with the result.
     ///
-    /// The purpose of this function is to allow multiple states to be associated with a single MultiUseSandbox.
+    /// `read_result` reads the result in place in guest memory, so large
+    /// results are never copied through the output data buffer. The result
+    /// buffer must have been configured with
+    /// `SandboxConfiguration::set_result_buffer_size`, and the guest
+    /// function must write to it with
+    /// `hyperlight_guest::result_buffer::write_result_buffer` or
+    /// `with_result_buffer`.
     ///
-    /// An implementation such as HyperlightJs or HyperlightWasm can use this to call guest functions to load JS or WASM code and then evolve the sandbox causing state to be captured.
-    /// The new MultiUseSandbox can then be used to call guest functions to execute the loaded code.
-    /// The devolve can be used to return the MultiUseSandbox to the state before the code was loaded. Thus avoiding initialisation overhead
-    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
-    fn devolve(mut self, _tsn: Noop<MultiUseSandbox, MultiUseSandbox>) -> Result<MultiUseSandbox> {
-        self.mem_mgr
-            .unwrap_mgr_mut()
-            .pop_and_restore_state_from_snapshot()?;
-        Ok(self)
+    /// As with `call_guest_function_by_name`, the sandbox's state is
+    /// restored after the call, once `read_result` has returned.
+    #[instrument(err(Debug), skip(self, args, read_result), parent = Span::current())]
+    pub fn call_guest_function_with_result_buffer<T, F: FnOnce(&[u8]) -> T>(
+        &mut self,
+        func_name: &str,
+        args: Option<Vec<ParameterValue>>,
+        read_result: F,
+    ) -> Result<T> {
+        self.check_ready()?;
+        let res = self
+            .call_guest_function_no_reset(func_name, ReturnType::ULong, args)
+            .and_then(|len| {
+                let len = usize::try_from(u64::try_from(len)?)?;
+                self.mem_mgr
+                    .unwrap_mgr_mut()
+                    .read_result_buffer(len, read_result)
+            });
+        self.restore_state()?;
+        res
     }
-}
 
-impl<'a, F>
-    EvolvableSandbox<
-        MultiUseSandbox,
-        MultiUseSandbox,
-        MultiUseContextCallback<'a, MultiUseSandbox, F>,
-    > for MultiUseSandbox
-where
-    F: FnOnce(&mut MultiUseGuestCallContext) -> Result<()> + 'a,
-{
-    /// The purpose of this function is to allow multiple states to be associated with a single MultiUseSandbox.
-    ///
-    /// An implementation such as HyperlightJs or HyperlightWasm can use this to call guest functions to load JS or WASM code and then evolve the sandbox causing state to be captured.
-    /// The new MultiUseSandbox can then be used to call guest functions to execute the loaded code.
+    /// Publish `data` to the sandbox's read-only data region, replacing any
+    /// data published before, for guest functions to read with
+    /// `hyperlight_guest::read_only_data::read_only_data`.
     ///
-    /// The evolve function creates a new MultiUseCallContext which is then passed to a callback function  allowing the
-    /// callback function to call guest functions as part of the evolve process, once the callback function  is complete
-    /// the context is finished using a crate internal method that does not restore the prior state of the Sanbbox.
-    /// It then creates a mew  memory snapshot on the snapshot stack and returns the MultiUseSandbox
-    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
-    fn evolve(
-        self,
-        transition_func: MultiUseContextCallback<'a, MultiUseSandbox, F>,
-    ) -> Result<MultiUseSandbox> {
-        let mut ctx = self.new_call_context();
-        transition_func.call(&mut ctx)?;
-        let mut sbox = ctx.finish_no_reset();
-        sbox.mem_mgr.unwrap_mgr_mut().push_state()?;
-        Ok(sbox)
+    /// The region must have been configured with
+    /// `SandboxConfiguration::set_read_only_data_size`. The guest can read
+    /// but not write to it, so data such as configuration or lookup tables
+    /// can be shared by many guest function calls without being passed as
+    /// parameters. Publishing takes `&mut self`, so it never overlaps a
+    /// guest function call, and the data is visible to the next call.
+    /// Restoring the sandbox's state, after a call or with `reset`, keeps
+    /// the data.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn publish_read_only_data(&mut self, data: &[u8]) -> Result<()> {
+        self.resume()?;
+        self.mem_mgr.unwrap_mgr_mut().publish_read_only_data(data)
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use hyperlight_common::flatbuffer_wrappers::function_types::{
-        ParameterValue, ReturnType, ReturnValue,
-    };
-    use hyperlight_testing::simple_guest_as_string;
 
-    use crate::func::call_ctx::MultiUseGuestCallContext;
-    use crate::sandbox::SandboxConfiguration;
-    use crate::sandbox_state::sandbox::{DevolvableSandbox, EvolvableSandbox};
-    use crate::sandbox_state::transition::{MultiUseContextCallback, Noop};
-    use crate::{GuestBinary, MultiUseSandbox, UninitializedSandbox};
+    /// Call a guest function by name without restoring the sandbox's state
+    /// afterwards, keeping track of whether the call left the sandbox
+    /// poisoned.
+    #[instrument(err(Debug), skip(self, args), parent = Span::current(), level = "Trace")]
+    pub(crate) fn call_guest_function_no_reset(
+        &mut self,
+        func_name: &str,
+        func_ret_type: ReturnType,
+        args: Option<Vec<ParameterValue>>,
+    ) -> Result<ReturnValue> {
+        self.check_ready()?;
+        let func_name = &*self.resolve_function_name(func_name)?;
+        self.check_permitted(func_name)?;
+        // the parameters the interceptor let the call proceed with, handed
+        // back to it with the call's result
+        let mut proceeded = None;
+        let args = match self.guest_call_interceptor.as_mut() {
+            Some(interceptor) => match interceptor.before_call(func_name, args.clone()) {
+                GuestCallAction::Proceed(args) => {
+                    proceeded = Some(args.clone());
+                    args
+                }
+                GuestCallAction::Return(ret) => {
+                    // a call answered without entering the guest must still
+                    // be one the guest would have accepted, returning what
+                    // the caller expects
+                    let args = self.coerce_args(func_name, args);
+                    let refs: Vec<ParameterRef<'_>> =
+                        args.iter().flatten().map(ParameterRef::from).collect();
+                    self.check_args(func_name, &refs)?;
+                    if ReturnType::from(&ret) != func_ret_type {
+                        log_then_return!(HyperlightError::UnexpectedReturnValueType(
+                            ret,
+                            format!("{:?}", func_ret_type)
+                        ));
+                    }
+                    return Ok(ret);
+                }
+                GuestCallAction::Deny(reason) => {
+                    log_then_return!(HyperlightError::GuestCallDenied(
+                        func_name.to_string(),
+                        reason
+                    ));
+                }
+            },
+            None => args,
+        };
+        let args = self.coerce_args(func_name, args);
+        let refs: Vec<ParameterRef<'_>> = args.iter().flatten().map(ParameterRef::from).collect();
+        let res = self.dispatch_guest_call(func_name, func_ret_type, &refs);
+        self.call_tracer()?
+            .record_guest_call(func_name, func_ret_type, args.as_deref(), &res);
+        if let (Some(interceptor), Some(proceeded)) =
+            (self.guest_call_interceptor.as_mut(), proceeded)
+        {
+            interceptor.after_call(func_name, proceeded.as_deref(), &res);
+        }
+        res
+    }
 
-    // Tests to ensure that many (1000) function calls can be made in a call context with a small stack (1K) and heap(14K).
-    // This test effectively ensures that the stack is being properly reset after each call and we are not leaking memory in the Guest.
-    #[test]
-    fn test_with_small_stack_and_heap() {
-        let mut cfg = SandboxConfiguration::default();
-        cfg.set_heap_size(20 * 1024);
-        cfg.set_stack_size(16 * 1024);
+    /// Call a guest function by name with borrowed arguments without
+    /// restoring the sandbox's state afterwards, as
+    /// `call_guest_function_no_reset` does.
+    #[instrument(err(Debug), skip(self, args), parent = Span::current(), level = "Trace")]
+    pub(crate) fn call_guest_function_refs_no_reset(
+        &mut self,
+        func_name: &str,
+        func_ret_type: ReturnType,
+        args: &[ParameterRef<'_>],
+    ) -> Result<ReturnValue> {
+        let func_name = &*self.resolve_function_name(func_name)?;
+        // interceptors, coercion and tracing work on owned arguments
+        if self.guest_call_interceptor.is_some()
+            || self.source.cfg.get_lenient_parameter_coercion()
+            || self.call_tracer()?.is_recording()
+        {
+            let args = (!args.is_empty()).then(|| args.iter().map(|&a| a.into()).collect());
+            return self.call_guest_function_no_reset(func_name, func_ret_type, args);
+        }
+        self.check_ready()?;
+        self.check_permitted(func_name)?;
+        self.dispatch_guest_call(func_name, func_ret_type, args)
+    }
 
-        let sbox1: MultiUseSandbox = {
-            let path = simple_guest_as_string().unwrap();
-            let u_sbox =
-                UninitializedSandbox::new(GuestBinary::FilePath(path), Some(cfg), None, None)
-                    .unwrap();
-            u_sbox.evolve(Noop::default())
+    /// Call a guest function that returns a `VecBytes` without restoring
+    /// the sandbox's state afterwards, leaving the bytes in the output data
+    /// buffer where possible
+    #[instrument(err(Debug), skip(self, args), parent = Span::current(), level = "Trace")]
+    fn call_borrowed_no_reset(
+        &mut self,
+        func_name: &str,
+        args: &[ParameterRef<'_>],
+    ) -> Result<ReturnedBytes> {
+        let func_name = &*self.resolve_function_name(func_name)?;
+        // interceptors, coercion and tracing work on owned arguments and
+        // return values, and compressed results must be decompressed
+        if self.guest_call_interceptor.is_some()
+            || self.source.cfg.get_lenient_parameter_coercion()
+            || self.call_tracer()?.is_recording()
+            || self.payload_compression != PayloadCompression::None
+        {
+            let ret =
+                self.call_guest_function_refs_no_reset(func_name, ReturnType::VecBytes, args)?;
+            return Ok(ReturnedBytes::Owned(ret.try_into()?));
         }
-        .unwrap();
+        self.check_ready()?;
+        self.check_permitted(func_name)?;
+        let OutputDataBytes(range) =
+            self.dispatch_guest_call(func_name, ReturnType::VecBytes, args)?;
+        Ok(ReturnedBytes::OutputData(range))
+    }
 
-        let mut ctx = sbox1.new_call_context();
+    /// The name of the guest function a call to `func_name` is made to,
+    /// which is a version of it if `func_name` has no version but the guest
+    /// registered versions of it, see `set_default_guest_function_version`
+    fn resolve_function_name<'a>(&self, func_name: &'a str) -> Result<Cow<'a, str>> {
+        self.guest_signatures
+            .resolve_version(func_name, self.default_versions.get(func_name).copied())
+    }
 
-        for _ in 0..1000 {
-            ctx.call(
-                "Echo",
-                ReturnType::String,
-                Some(vec![ParameterValue::String("hello".to_string())]),
-            )
-            .unwrap();
+    /// Widen `args` to the parameter types of `func_name` if the sandbox
+    /// is configured to, see `SandboxConfiguration::set_lenient_parameter_coercion`
+    fn coerce_args(
+        &self,
+        func_name: &str,
+        args: Option<Vec<ParameterValue>>,
+    ) -> Option<Vec<ParameterValue>> {
+        match args {
+            Some(args) if self.source.cfg.get_lenient_parameter_coercion() => {
+                Some(self.guest_signatures.coerce(func_name, args))
+            }
+            args => args,
         }
+    }
 
-        let sbox2: MultiUseSandbox = {
-            let path = simple_guest_as_string().unwrap();
-            let u_sbox =
-                UninitializedSandbox::new(GuestBinary::FilePath(path), Some(cfg), None, None)
-                    .unwrap();
-            u_sbox.evolve(Noop::default())
+    /// Check `args` against the signature of `func_name` and the sandbox's
+    /// payload limits, as every call is checked before it enters the guest
+    fn check_args(&self, func_name: &str, args: &[ParameterRef<'_>]) -> Result<()> {
+        self.guest_signatures.check(func_name, args)?;
+        self.source
+            .cfg
+            .get_payload_limits()
+            .check_parameter_refs(args)?;
+        Ok(())
+    }
+
+    fn check_permitted(&self, func_name: &str) -> Result<()> {
+        if !self.guest_function_policy.permits(func_name) {
+            log_then_return!(HyperlightError::GuestFunctionNotPermitted(
+                func_name.to_string()
+            ));
         }
-        .unwrap();
+        Ok(())
+    }
 
-        let mut ctx = sbox2.new_call_context();
+    /// Call the guest functions `calls` one after another without
+    /// restoring the sandbox's state in between, stopping at the first
+    /// call that fails. When the sandbox has more than one input and
+    /// output data buffer slot, the parameters of the next calls are
+    /// serialized into free slots on another thread while the guest runs
+    /// the current call.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn call_guest_functions_pipelined_no_reset(
+        &mut self,
+        calls: &[(String, ReturnType, Option<Vec<ParameterValue>>)],
+    ) -> Result<Vec<ReturnValue>> {
+        let slots = self.source.cfg.get_io_buffer_slots();
+        // interceptors and coercion may change the arguments of a call
+        // before it is serialized, and the guest function calls have to be
+        // traced along with the host function calls they make
+        if slots == 1
+            || self.guest_call_interceptor.is_some()
+            || self.source.cfg.get_lenient_parameter_coercion()
+            || self.call_tracer()?.is_recording()
+        {
+            return calls
+                .iter()
+                .map(|(name, ret, args)| {
+                    self.call_guest_function_no_reset(name, *ret, args.clone())
+                })
+                .collect();
+        }
+        self.check_ready()?;
+        let names = calls
+            .iter()
+            .map(|(name, _, _)| self.resolve_function_name(name))
+            .collect::<Result<Vec<_>>>()?;
+        for name in &names {
+            self.check_permitted(name)?;
+        }
+        // the guest memory must not be written while it is hibernated
+        self.resume()?;
 
-        for i in 0..1000 {
-            ctx.call(
-                "PrintUsingPrintf",
-                ReturnType::Int,
-                Some(vec![ParameterValue::String(
-                    format!("Hello World {}\n", i).to_string(),
-                )]),
-            )
-            .unwrap();
+        let mgr = self.mem_mgr.unwrap_mgr();
+        let mut writer = mgr.io_slot_writer();
+        let limits = mgr.payload_limits();
+        let (free_tx, free_rx) = channel();
+        let (written_tx, written_rx) = channel();
+        for slot in 0..slots {
+            free_tx
+                .send(slot)
+                .map_err(|e| new_error!("Error sending free slot: {}", e))?;
         }
+        let names = names.as_slice();
+        let res = std::thread::scope(|scope| {
+            scope.spawn(move || {
+                for ((_, ret, args), name) in calls.iter().zip(names) {
+                    // wait for the guest to be done with a slot
+                    let Ok(slot) = free_rx.recv() else {
+                        break;
+                    };
+                    let refs: Vec<ParameterRef<'_>> =
+                        args.iter().flatten().map(ParameterRef::from).collect();
+                    let written =
+                        write_function_call_to_slot(&mut writer, limits, slot, name, *ret, &refs);
+                    if written_tx.send((slot, written)).is_err() {
+                        break;
+                    }
+                }
+            });
+            // returning drops the channels, which stops the thread above
+            self.run_pipelined_calls(calls, names, written_rx, free_tx)
+        });
+        // the calls made after these use the first slot again
+        self.mem_mgr.unwrap_mgr_mut().select_io_slot(0)?;
+        res
     }
 
-    /// Tests that evolving from MultiUseSandbox to MultiUseSandbox creates a new state
-    /// and devolving from MultiUseSandbox to MultiUseSandbox restores the previous state
-    #[test]
-    fn evolve_devolve_handles_state_correctly() {
-        let sbox1: MultiUseSandbox = {
-            let path = simple_guest_as_string().unwrap();
-            let u_sbox =
-                UninitializedSandbox::new(GuestBinary::FilePath(path), None, None, None).unwrap();
-            u_sbox.evolve(Noop::default())
+    /// Run each of `calls` once it has been written to a slot, received
+    /// from `written`, and hand the slot back to `free` when it is done
+    fn run_pipelined_calls(
+        &mut self,
+        calls: &[(String, ReturnType, Option<Vec<ParameterValue>>)],
+        names: &[Cow<'_, str>],
+        written: Receiver<(usize, Result<()>)>,
+        free: Sender<usize>,
+    ) -> Result<Vec<ReturnValue>> {
+        let mut results = Vec::with_capacity(calls.len());
+        for ((_, _, args), name) in calls.iter().zip(names) {
+            let (slot, res) = written
+                .recv()
+                .map_err(|e| new_error!("Error receiving written slot: {}", e))?;
+            res?;
+            let refs: Vec<ParameterRef<'_>> =
+                args.iter().flatten().map(ParameterRef::from).collect();
+            let ret = self.run_guest_call(name, &refs, |sbox| {
+                sbox.mem_mgr.unwrap_mgr_mut().select_io_slot(slot)?;
+                run_function_on_guest(sbox, name)
+            })?;
+            results.push(ret);
+            // the thread writing the calls may already have stopped
+            let _ = free.send(slot);
         }
-        .unwrap();
+        Ok(results)
+    }
 
-        let func = Box::new(|call_ctx: &mut MultiUseGuestCallContext| {
-            call_ctx.call(
-                "AddToStatic",
-                ReturnType::Int,
-                Some(vec![ParameterValue::Int(5)]),
-            )?;
-            Ok(())
+    /// Make a guest function call once any interceptor has seen it,
+    /// keeping track of whether it left the sandbox poisoned
+    #[instrument(err(Debug), skip(self, args), parent = Span::current(), level = "Trace")]
+    fn dispatch_guest_call<T: GuestCallOutput>(
+        &mut self,
+        func_name: &str,
+        func_ret_type: ReturnType,
+        args: &[ParameterRef<'_>],
+    ) -> Result<T> {
+        self.run_guest_call(func_name, args, |sbox| {
+            call_function_on_guest(sbox, func_name, func_ret_type, args)
+        })
+    }
+
+    /// Make the guest function call `func_name` with `call`, keeping track
+    /// of whether it left the sandbox poisoned
+    fn run_guest_call<T>(
+        &mut self,
+        func_name: &str,
+        args: &[ParameterRef<'_>],
+        call: impl FnOnce(&mut Self) -> Result<T>,
+    ) -> Result<T> {
+        self.guest_signatures.check(func_name, args)?;
+        let limits = self.source.cfg.get_payload_limits();
+        // a compressed result can be larger than the output data buffer
+        let max_result_size = match self.payload_compression {
+            PayloadCompression::None => self.source.cfg.get_output_data_size(),
+            _ => max_uncompressed_size(&limits),
+        };
+        self.guest_signatures
+            .check_result_size(func_name, limits, max_result_size)?;
+        self.resume()?;
+        self.source
+            .epoch
+            .start_call(self.source.cfg.get_epoch_deadline())?;
+        self.source
+            .deadline
+            .set_timeout(self.source.function_timeouts.get(func_name).copied());
+        self.state = SandboxState::Busy;
+        self.usage.set_busy(true);
+        let start = Instant::now();
+        #[cfg(feature = "boundary_spans")]
+        let span = crate::sandbox::spans::guest_call_span(self.id(), func_name, args);
+        #[cfg(feature = "boundary_spans")]
+        let entered = span.enter();
+        #[cfg(feature = "boundary_spans")]
+        if let Some(policy) = &self.redaction_policy {
+            crate::sandbox::spans::record_args(&span, &policy.apply(func_name, args));
+        }
+        let res = call(self);
+        // forward what the guest output since it last exited, keeping the
+        // call's error if it failed
+        let flushed = self.mem_mgr.unwrap_mgr_mut().flush_guest_output();
+        let res = res.and_then(|ret| flushed.map(|()| ret));
+        // don't leave what this call put on the stack for the next one
+        let scrubbed = self.mem_mgr.unwrap_mgr_mut().scrub_stacks();
+        let res = res.and_then(|ret| scrubbed.map(|()| ret));
+        #[cfg(feature = "boundary_spans")]
+        {
+            crate::sandbox::spans::record_result(&span, &res);
+            drop(entered);
+        }
+        record_guest_call(func_name, start.elapsed(), res.is_err());
+        self.usage.set_busy(false);
+        let res = res.map_err(|e| match self.crash_fingerprint(&e) {
+            Some(crash) => crash.attach(e),
+            None => e,
         });
-        let transition_func = MultiUseContextCallback::from(func);
-        let mut sbox2 = sbox1.evolve(transition_func).unwrap();
-        let res = sbox2
-            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
-            .unwrap();
-        assert_eq!(res, ReturnValue::Int(5));
-        let mut sbox3: MultiUseSandbox = sbox2.devolve(Noop::default()).unwrap();
-        let res = sbox3
-            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
-            .unwrap();
-        assert_eq!(res, ReturnValue::Int(0));
+        self.state = match &res {
+            Err(e) if e.poisons_sandbox() => {
+                let location = match e {
+                    HyperlightError::ExecutionAccessViolation(address)
+                    | HyperlightError::MemoryAccessViolation(address, _, _) => {
+                        self.symbolize(*address)
+                    }
+                    _ => None,
+                };
+                match location {
+                    Some(location) => log::warn!(
+                        "Sandbox {} poisoned by failed guest call: {:?} in {}",
+                        self.id(),
+                        e,
+                        location
+                    ),
+                    None => log::warn!(
+                        "Sandbox {} poisoned by failed guest call: {:?}",
+                        self.id(),
+                        e
+                    ),
+                }
+                if let Some(crash) = self.crash_fingerprint(e) {
+                    log::warn!("Sandbox {} guest crash fingerprint: {}", self.id(), crash);
+                    int_counter_vec_inc!(
+                        &GuestCrashCount,
+                        &[crash.fault(), format!("{:016x}", crash.id()).as_str()]
+                    );
+                }
+                SandboxState::Poisoned
+            }
+            _ => SandboxState::Ready,
+        };
+        if let Err(e) = &res {
+            if needs_recreating(&self.source.cfg.get_interrupt_policy(), e) {
+                // the thread running the guest is stuck in a host function,
+                // so it is killed with the old virtual machine when the old
+                // sandbox is dropped
+                log::warn!("Recreating sandbox {} with a hung guest", self.id());
+                if let Err(e) = self.recreate_in_place() {
+                    log::error!("Failed to recreate sandbox {}: {:?}", self.id(), e);
+                }
+            }
+        }
+        res
+    }
+
+    /// Whether this sandbox can currently be used to call guest functions.
+    ///
+    /// A sandbox becomes `SandboxState::Poisoned` when a guest function
+    /// call crashes the guest or times out. After that, every attempt to
+    /// call a guest function returns `HyperlightError::SandboxNotReady`
+    /// until `reset` or `recreate` is called.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn state(&self) -> SandboxState {
+        self.state
+    }
+
+    /// Restore the guest memory to the most recent snapshot, which is the
+    /// state the sandbox was in after it was created or last evolved, and
+    /// mark the sandbox as ready to call guest functions again.
+    ///
+    /// Every page of guest memory is compared with the snapshot and the
+    /// pages that differ are restored, so no state written by earlier
+    /// calls, such as a guest's static variables, is left behind, which
+    /// makes it suitable for isolating requests from different tenants.
+    /// The comparison still reads all of the guest memory, but only the
+    /// pages that differ are copied, so this is much cheaper than
+    /// `recreate`, though it reuses the same virtual machine. If calls keep
+    /// failing after a reset, use `recreate`.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn reset(&mut self) -> Result<()> {
+        self.restore_state()?;
+        self.state = SandboxState::Ready;
+        Ok(())
+    }
+
+    /// Consume this sandbox and create a new one from scratch, from the
+    /// same guest binary and configuration, and with the same host
+    /// functions registered.
+    ///
+    /// Any state captured by evolving this sandbox is lost, the returned
+    /// sandbox is in the state a newly created sandbox would be in, after
+    /// any warm-up calls added with `UninitializedSandbox::add_warm_up_call`.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn recreate(mut self) -> Result<MultiUseSandbox> {
+        let source = self.source.clone();
+        let host_funcs = self._host_funcs.clone();
+        let guest_function_policy = self.guest_function_policy.clone();
+        let guest_call_interceptor = self.guest_call_interceptor.take();
+        let retry_policy = self.retry_policy;
+        let redaction_policy = self.redaction_policy.take();
+        let hibernation_key = self.hibernation_key.take();
+        let default_versions = std::mem::take(&mut self.default_versions);
+        let owner_thread = self.owner_thread;
+        // release the old virtual machine and its memory before creating
+        // new ones
+        drop(self);
+
+        let mut sbox = Self::create_from_source(source, host_funcs)?;
+        sbox.guest_function_policy = guest_function_policy;
+        sbox.guest_call_interceptor = guest_call_interceptor;
+        sbox.retry_policy = retry_policy;
+        sbox.redaction_policy = redaction_policy;
+        sbox.hibernation_key = hibernation_key;
+        sbox.default_versions = default_versions;
+        sbox.owner_thread = owner_thread;
+        Ok(sbox)
+    }
+
+    /// Like `recreate`, but replace this sandbox with the new one, so the
+    /// old virtual machine is only released once the new one is created.
+    /// If creating the new sandbox fails, this sandbox is left as it was.
+    fn recreate_in_place(&mut self) -> Result<()> {
+        let mut sbox = Self::create_from_source(self.source.clone(), self._host_funcs.clone())?;
+        sbox.guest_function_policy = self.guest_function_policy.clone();
+        sbox.guest_call_interceptor = self.guest_call_interceptor.take();
+        sbox.retry_policy = self.retry_policy;
+        sbox.redaction_policy = self.redaction_policy.take();
+        sbox.hibernation_key = self.hibernation_key.clone();
+        sbox.default_versions = self.default_versions.clone();
+        sbox.owner_thread = self.owner_thread;
+        *self = sbox;
+        Ok(())
+    }
+
+    fn create_from_source(
+        source: SandboxSource,
+        host_funcs: Arc<Mutex<HostFuncsWrapper>>,
+    ) -> Result<MultiUseSandbox> {
+        let mut u_sbox = UninitializedSandbox::from_source(source, host_funcs.clone())?;
+        host_funcs
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+            .write_host_function_details(u_sbox.mgr.unwrap_mgr_mut())?;
+        u_sbox.evolve(Noop::default())
+    }
+
+    /// Return an error if this sandbox can't currently be used to call
+    /// guest functions, or can't be used from the current thread
+    pub(crate) fn check_ready(&self) -> Result<()> {
+        self.check_owner_thread()?;
+        match self.state {
+            SandboxState::Ready => Ok(()),
+            state => Err(HyperlightError::SandboxNotReady(state)),
+        }
+    }
+
+    /// Change the max log level used by the guest.
+    ///
+    /// Unlike `UninitializedSandbox::set_max_guest_log_level`, this can be
+    /// called on a live sandbox. The new level is passed to the guest
+    /// before the next guest function call and stays in effect for all
+    /// later calls, until it is changed again.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn set_max_guest_log_level(&mut self, log_level: LevelFilter) {
+        self.mem_mgr
+            .unwrap_mgr_mut()
+            .set_guest_max_log_level(log_level);
+    }
+
+    /// Restrict which guest functions can be called on this sandbox.
+    /// Calls to functions the policy does not permit fail with
+    /// `HyperlightError::GuestFunctionNotPermitted` without entering the
+    /// guest. The policy is kept when the sandbox is recreated.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn set_guest_function_policy(&mut self, policy: GuestFunctionPolicy) {
+        self.guest_function_policy = policy;
+    }
+
+    /// Retry guest function calls made with `call_guest_function_by_name`
+    /// that fail with a transient error as `policy` sets. The policy is
+    /// kept when the sandbox is recreated.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry_policy = Some(policy);
+    }
+
+    /// Stop retrying guest function calls that fail
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn clear_retry_policy(&mut self) {
+        self.retry_policy = None;
+    }
+
+    /// Cancel calls to the guest function `func_name` once they have run
+    /// for `timeout`, instead of after the timeout the guest declared in
+    /// its metadata, if any. Calls are still cancelled at the sandbox's
+    /// maximum execution time, or their deadline, if that is sooner. The
+    /// timeout is kept when the sandbox is recreated.
+    #[instrument(skip(self), parent = Span::current())]
+    pub fn set_function_timeout(&mut self, func_name: &str, timeout: Duration) {
+        self.source
+            .function_timeouts
+            .insert(func_name.to_string(), timeout);
+    }
+
+    /// Remove the timeout of calls to the guest function `func_name`,
+    /// including the one the guest declared in its metadata, so that they
+    /// run until the sandbox's maximum execution time
+    #[instrument(skip(self), parent = Span::current())]
+    pub fn clear_function_timeout(&mut self, func_name: &str) {
+        self.source.function_timeouts.remove(func_name);
+    }
+
+    /// The timeout of calls to the guest function `func_name`, if it has
+    /// one
+    pub fn function_timeout(&self, func_name: &str) -> Option<Duration> {
+        self.source.function_timeouts.get(func_name).copied()
+    }
+
+    /// Redact the parameters of guest function calls that `policy` matches,
+    /// such as passwords or personal data, wherever this sandbox shows
+    /// them. The `guest_call` and `host_call` spans created with the
+    /// `boundary_spans` feature only show the arguments of calls once a
+    /// policy is set, and never show the `Sensitive` parameters of host
+    /// functions. Calls can be logged with their arguments redacted with
+    /// `RedactionPolicy::apply`, e.g. in a `GuestCallInterceptor`. The
+    /// policy is kept when the sandbox is recreated.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn set_redaction_policy(&mut self, policy: RedactionPolicy) {
+        self._host_funcs
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .set_redaction_policy(Some(policy.clone()));
+        self.redaction_policy = Some(policy);
+    }
+
+    /// The policy set with `set_redaction_policy`, if any
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn redaction_policy(&self) -> Option<&RedactionPolicy> {
+        self.redaction_policy.as_ref()
+    }
+
+    /// Encrypt the file this sandbox's memory is written to when it
+    /// hibernates with `key`, so its guest memory never reaches the disk
+    /// in plaintext. See `set_hibernation_key_provider` to fetch the key
+    /// when it is needed instead. The key is kept when the sandbox is
+    /// recreated.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn set_hibernation_key(&mut self, key: SnapshotKey) {
+        self.set_hibernation_key_provider(move |_| Ok(key.clone()));
+    }
+
+    /// Encrypt the file this sandbox's memory is written to when it
+    /// hibernates with the key `provider` returns, given the sandbox's ID,
+    /// e.g. by asking a key management service for the tenant's key.
+    ///
+    /// The provider is called each time the sandbox hibernates, and again
+    /// when it resumes; if it fails, so does hibernating or resuming, and
+    /// the sandbox stays as it was. The provider is kept when the sandbox
+    /// is recreated.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn set_hibernation_key_provider(
+        &mut self,
+        provider: impl Fn(u64) -> Result<SnapshotKey> + Send + Sync + 'static,
+    ) {
+        self.hibernation_key = Some(Arc::new(provider));
+    }
+
+    /// Fetch the key this sandbox's hibernation file is encrypted with, if
+    /// one is set
+    fn hibernation_key(&self) -> Result<Option<SnapshotKey>> {
+        self.hibernation_key
+            .as_ref()
+            .map(|provider| provider(self.id()))
+            .transpose()
+    }
+
+    /// Intercept every guest function call made on this sandbox, including
+    /// those made through a `MultiUseGuestCallContext`, with `interceptor`,
+    /// replacing any interceptor set before. Calls are intercepted after
+    /// the guest function policy permits them, so caching or policy layers
+    /// can be added without wrapping the sandbox. The interceptor is kept
+    /// when the sandbox is recreated.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn set_guest_call_interceptor(&mut self, interceptor: impl GuestCallInterceptor + 'static) {
+        self.guest_call_interceptor = Some(Box::new(interceptor));
+    }
+
+    /// Stop intercepting guest function calls, returning the interceptor
+    /// that was set, if any
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn take_guest_call_interceptor(&mut self) -> Option<Box<dyn GuestCallInterceptor>> {
+        self.guest_call_interceptor.take()
+    }
+
+    /// Start tracing the guest function calls made on this sandbox, the
+    /// host function calls the guest makes during them and their results,
+    /// discarding any trace already being recorded. The trace carries on
+    /// when the sandbox is recreated. See the `call_trace` module for
+    /// which calls are traced.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn start_trace(&mut self) -> Result<()> {
+        self.call_tracer()?.start();
+        Ok(())
+    }
+
+    /// Stop tracing, and return the trace recorded since `start_trace`,
+    /// or `None` if this sandbox wasn't tracing. The trace can be saved
+    /// with `CallTrace::write_to`.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn finish_trace(&mut self) -> Result<Option<CallTrace>> {
+        Ok(self.call_tracer()?.finish())
+    }
+
+    /// Make the guest function calls in `trace` on this sandbox, resetting
+    /// it where the trace did, then restore the sandbox's state. The host
+    /// function calls the guest makes are answered from the trace instead
+    /// of calling the host functions.
+    ///
+    /// Returns `HyperlightError::TraceDivergence` as soon as the guest
+    /// makes a host function call that isn't the next one in the trace,
+    /// doesn't make one that is, or a guest function call returns a
+    /// different value than it did. Failed calls only need to fail again,
+    /// as error messages can differ from one run to the next.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn replay_trace(&mut self, trace: &CallTrace) -> Result<()> {
+        self.check_ready()?;
+        let tracer = self.call_tracer()?;
+        if tracer.is_recording() {
+            log_then_return!("Cannot replay a trace on a sandbox that is tracing");
+        }
+        let res = self.replay_trace_events(&tracer, trace);
+        self.restore_state()?;
+        res
+    }
+
+    fn replay_trace_events(&mut self, tracer: &CallTracer, trace: &CallTrace) -> Result<()> {
+        let mut call_index = 0;
+        for event in &trace.events {
+            let call = match event {
+                TraceEvent::GuestCall(call) => call,
+                TraceEvent::Reset => {
+                    self.reset()?;
+                    continue;
+                }
+            };
+            tracer.start_replay(call.host_calls.clone());
+            let res = self.call_guest_function_no_reset(
+                &call.function_name,
+                call.return_type,
+                call.args.clone(),
+            );
+            let divergence = tracer
+                .finish_replay()
+                .or_else(|| match (&call.result, &res) {
+                    (Ok(traced), Ok(value)) if traced != value => Some(format!(
+                        "the call returned {:?}, the trace has {:?}",
+                        value, traced
+                    )),
+                    (Ok(traced), Err(e)) => Some(format!(
+                        "the call failed with {:?}, the trace has {:?}",
+                        e, traced
+                    )),
+                    (Err(traced), Ok(value)) => Some(format!(
+                        "the call returned {:?}, the trace has it failing with {}",
+                        value, traced
+                    )),
+                    _ => None,
+                });
+            if let Some(divergence) = divergence {
+                log_then_return!(HyperlightError::TraceDivergence(call_index, divergence));
+            }
+            call_index += 1;
+        }
+        Ok(())
+    }
+
+    /// The sorted names of the functions the guest registered in
+    /// `namespace`, including those in namespaces nested in it, or of all
+    /// of the guest's functions if `namespace` is empty.
+    ///
+    /// Functions the guest handles in its `guest_dispatch_function`, or
+    /// registered through the C API, are not known to the host and are
+    /// not included.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn guest_function_names(&self, namespace: &str) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .guest_signatures
+            .iter()
+            .map(|s| &s.function_name)
+            .filter(|name| namespace.is_empty() || is_in_namespace(name, namespace))
+            .cloned()
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// The signatures of the functions the guest registered, sorted by
+    /// name, e.g. to generate a typed client for the guest with
+    /// `hyperlight_host::func::client_gen`.
+    ///
+    /// Functions the guest handles in its `guest_dispatch_function`, or
+    /// registered through the C API, are not known to the host and are
+    /// not included.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn guest_function_signatures(&self) -> Vec<GuestFunctionSignature> {
+        let mut signatures: Vec<GuestFunctionSignature> =
+            self.guest_signatures.iter().cloned().collect();
+        signatures.sort_by(|a, b| a.function_name.cmp(&b.function_name));
+        signatures
+    }
+
+    /// What the guest declared about the behaviour of the guest function
+    /// `func_name` when it registered it: whether it is pure, idempotent
+    /// or read-only. Functions the guest didn't register with the guest
+    /// library have no flags.
+    ///
+    /// The flags pick safe defaults elsewhere: calls to read-only
+    /// functions made with
+    /// `MultiUseGuestCallContext::call_guest_function_transactional` take
+    /// no snapshot, `GuestCallCache::cache_pure_functions` caches the
+    /// results of pure functions, and a `RetryPolicy` set with
+    /// `set_idempotent_only` only retries calls to idempotent functions.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn guest_function_flags(&self, func_name: &str) -> FunctionFlags {
+        match self.resolve_function_name(func_name) {
+            Ok(func_name) => self.guest_signatures.flags(&func_name),
+            Err(_) => FunctionFlags::NONE,
+        }
+    }
+
+    /// The largest size in bytes the guest declared the serialized result
+    /// of `func_name` can be, with
+    /// `hyperlight_guest::guest_function_definition::GuestFunctionDefinition::with_max_result_size`,
+    /// which can be used to size the output data buffer with
+    /// `SandboxConfiguration::set_output_data_size`. If the declared size
+    /// doesn't fit in the output data buffer, or exceeds the maximum
+    /// payload size, calls to the function fail with
+    /// `HyperlightError::GuestFunctionResultTooLarge` before the guest runs.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn guest_function_max_result_size(&self, func_name: &str) -> Option<u64> {
+        let func_name = self.resolve_function_name(func_name).ok()?;
+        self.guest_signatures.max_result_size(&func_name)
+    }
+
+    /// The versions of the guest function `func_name` the guest registered
+    /// with `hyperlight_guest::guest_function_register::register_versioned_function`,
+    /// oldest first, e.g. `[1, 2]` if it registered `Compute@v1` and
+    /// `Compute@v2` for `Compute`.
+    ///
+    /// A call can pick a version by its name, `Compute@v1`. A call without
+    /// a version is made to the default version set with
+    /// `set_default_guest_function_version`, or else to the function the
+    /// guest registered without a version, or else to the newest version.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn guest_function_versions(&self, func_name: &str) -> Vec<u32> {
+        self.guest_signatures.versions(func_name)
+    }
+
+    /// Make calls to the guest function `func_name` that don't name a
+    /// version call `version` of it, e.g. to keep calling `Compute@v1`
+    /// while some of the guests a host runs are upgraded to `Compute@v2`.
+    /// Returns `HyperlightError::GuestFunctionVersionNotFound` if the guest
+    /// didn't register that version. The default is kept when the sandbox
+    /// is recreated.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn set_default_guest_function_version(
+        &mut self,
+        func_name: &str,
+        version: u32,
+    ) -> Result<()> {
+        let versions = self.guest_signatures.versions(func_name);
+        if !versions.contains(&version) {
+            log_then_return!(HyperlightError::GuestFunctionVersionNotFound(
+                func_name.to_string(),
+                version,
+                versions
+            ));
+        }
+        self.default_versions.insert(func_name.to_string(), version);
+        Ok(())
+    }
+
+    /// Stop calling a default version of `func_name`, set with
+    /// `set_default_guest_function_version`
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn clear_default_guest_function_version(&mut self, func_name: &str) {
+        self.default_versions.remove(func_name);
+    }
+
+    /// The ID this sandbox is identified by in the guest log records it
+    /// forwards to the host (the `sandbox_id` field of the `guest_log` span),
+    /// and in the candidates passed to memory pressure handlers, see
+    /// `memory_pressure::set_memory_pressure_handler`.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn id(&self) -> u64 {
+        self.mem_mgr.unwrap_mgr().guest_log_forwarder().sandbox_id()
+    }
+
+    /// The table of host objects the guest holds handles to, see
+    /// `UninitializedSandbox::host_handles`
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn host_handles(&self) -> Result<HostHandles> {
+        Ok(self
+            ._host_funcs
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+            .host_handles())
+    }
+
+    /// The number of guest log records dropped so far because this sandbox
+    /// exceeded the rate set by
+    /// `SandboxConfiguration::set_max_guest_log_records_per_second`.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn dropped_guest_log_records(&self) -> u64 {
+        self.mem_mgr
+            .unwrap_mgr()
+            .guest_log_forwarder()
+            .dropped_records()
+    }
+
+    /// The number of guest log records dropped so far because this
+    /// sandbox's guest log ring buffer, configured with
+    /// `SandboxConfiguration::set_guest_log_ring_size`, was full.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn overflowed_guest_log_records(&self) -> u64 {
+        self.mem_mgr
+            .unwrap_mgr()
+            .guest_log_forwarder()
+            .overflowed_records()
+    }
+
+    /// The time the guest last called the `HostHeartbeat` host function,
+    /// or `None` if it never has.
+    ///
+    /// Combined with `SandboxConfiguration::set_heartbeat_timeout`, this
+    /// lets the host tell a guest that is working slowly, and keeps
+    /// sending heartbeats, apart from one that is hung.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn last_heartbeat(&self) -> Option<Instant> {
+        self.source.heartbeat.last()
+    }
+
+    /// The handle to increment the epoch with, to stop guest function calls
+    /// that run past the deadline set with
+    /// `SandboxConfiguration::set_epoch_deadline`. The handle is kept when
+    /// the sandbox is recreated.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn epoch_handle(&self) -> EpochHandle {
+        self.source.epoch.clone()
+    }
+
+    /// The handle to pause this sandbox's vCPU with, and to resume it,
+    /// from another thread, including while a guest function call is in
+    /// progress. The handle is kept when the sandbox is recreated.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn pause_handle(&self) -> PauseHandle {
+        self.source.pause.clone()
+    }
+
+    /// The CPU time this sandbox has used so far, running the guest's
+    /// initialisation and guest function calls, including the host
+    /// functions the guest called, e.g. to bill tenants for the CPU their
+    /// calls used or to find tenants that use too much of it. Time spent
+    /// waiting, such as in a host function that sleeps, isn't counted. The
+    /// time starts from zero again when the sandbox is recreated.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn cpu_time(&self) -> Duration {
+        self.hv_handler.cpu_time()
+    }
+
+    /// Call `handler` with every progress report the guest sends with
+    /// `hyperlight_guest::progress::hl_report_progress` from now on, e.g.
+    /// to show the progress of long running guest function calls.
+    ///
+    /// Handlers are called on the thread running the guest, while the
+    /// guest waits, with the same seccomp filter as other host functions
+    /// when the `seccomp` feature is enabled, so they should be quick and
+    /// hand reports that need more work to another thread, e.g. over a
+    /// channel. Reports are throttled as set with
+    /// `SandboxConfiguration::set_min_progress_step`. Each report also
+    /// counts as a heartbeat. Handlers are kept when the sandbox is
+    /// recreated.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn subscribe_progress(
+        &mut self,
+        handler: impl FnMut(&ProgressReport) + Send + 'static,
+    ) -> Result<()> {
+        self.source.progress.subscribe(handler)
+    }
+
+    /// The heap profile the guest sent at the end of the last guest
+    /// function call, or `None` if the guest wasn't built with the
+    /// `heap_profiler` feature of `hyperlight_guest`.
+    ///
+    /// The profile counts the allocations the guest made at each allocation
+    /// site since it was initialised, and those still live when the call
+    /// returned. Guest memory is restored after every call, so allocations
+    /// a call leaves live are leaks within that call, but allocations made
+    /// by the guest's initialisation are included in every profile.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn last_heap_profile(&self) -> Option<HeapProfile> {
+        let mut profile = self.source.heap_profile.get()?;
+        profile.resolve_sites(|address| self.symbolize(address));
+        Some(profile)
+    }
+
+    /// The malloc trace the guest sent at the end of the last guest
+    /// function call, or `None` if the guest wasn't built with the
+    /// `malloc_trace` feature of `hyperlight_guest`.
+    ///
+    /// The trace holds every allocation and free the guest made during the
+    /// call, so `MallocTrace::leaks` are the allocations the call didn't
+    /// free. Tests can check for them with `assert_no_guest_leaks!`. Leaks
+    /// only accumulate when the guest memory is kept after a call, as with
+    /// `run_housekeeping` or the calls made in a `MultiUseGuestCallContext`,
+    /// but a leak in any call is one there too.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn last_malloc_trace(&self) -> Option<MallocTrace> {
+        self.malloc_trace.get()
+    }
+
+    /// The guest function or static `address` is in, and how far into it,
+    /// e.g. `simpleguest::echo+0x1c`, or `None` if the guest binary's
+    /// symbol map wasn't loaded or has no symbol at `address`.
+    ///
+    /// The symbol map is written next to the guest binary by the
+    /// `cargo hyperlight-guest` subcommand of `hyperlight-guest-build`, and
+    /// loaded with the binary, or set with
+    /// `UninitializedSandbox::set_symbol_map`.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn symbolize(&self, address: u64) -> Option<String> {
+        let load_addr = u64::from(&self.mem_mgr.unwrap_mgr().load_addr);
+        let map = self.source.symbol_map.as_ref()?;
+        map.symbolize(address.checked_sub(load_addr)?)
+    }
+
+    /// The fingerprint of the guest crash `err` returned by a guest
+    /// function call on this sandbox, or `None` if `err` isn't a guest
+    /// crash. Crashes with the same kind of fault in the same guest
+    /// functions have the same fingerprint in every sandbox, so crash
+    /// dashboards can group them, e.g. by `CrashFingerprint::id`.
+    ///
+    /// Every guest crash is also logged with its fingerprint, and counted
+    /// in the `guest_crash_count` metric, labelled with its fault and id,
+    /// and crashes whose error has a stack trace or a panic location are
+    /// returned with the fingerprint on the last line of the error's
+    /// message. The frames are symbolized as by `symbolize`.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn crash_fingerprint(&self, err: &HyperlightError) -> Option<CrashFingerprint> {
+        let load_addr = u64::from(&self.mem_mgr.unwrap_mgr().load_addr);
+        CrashFingerprint::from_error(err, load_addr, |address| self.symbolize(address))
+    }
+
+    /// Whether the memory shared with this sandbox's guest is populated
+    /// lazily, on first touch, or has all been populated up front, either
+    /// because the sandbox was created with `MemoryPopulation::Prefault` or
+    /// because `prefault` was called.
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn memory_population(&self) -> MemoryPopulation {
+        self.mem_mgr.unwrap_mgr().shared_mem.memory_population()
+    }
+
+    /// Populate all of the memory shared with this sandbox's guest now,
+    /// rather than on first touch, so that later guest function calls do
+    /// not pay for page faults on memory they have not used before.
+    /// Does nothing if the memory has already been populated.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn prefault(&mut self) -> Result<()> {
+        self.resume()?;
+        self.mem_mgr
+            .unwrap_mgr_mut()
+            .shared_mem
+            .with_exclusivity(|e| e.prefault())?
+    }
+
+    /// The regions of this sandbox's guest memory, in the order of their
+    /// guest addresses, with the guest's permissions to each, e.g. for
+    /// interpreting a dump of the guest memory
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn memory_layout(&self) -> Result<Vec<GuestMemoryRegion>> {
+        let mgr = self.mem_mgr.unwrap_mgr();
+        let regions = mgr.layout.get_memory_regions(&mgr.shared_mem)?;
+        Ok(regions.iter().map(GuestMemoryRegion::from).collect())
+    }
+
+    /// Digests of this sandbox's guest heap, stack and static data, for
+    /// checking that two sandboxes have reached the same guest state. The
+    /// digests do not depend on where the guest memory is loaded, so
+    /// sandboxes created from the same guest binary and configuration that
+    /// made the same guest function calls have the same digests whether
+    /// they run in a hypervisor or in-process.
+    ///
+    /// The guest memory is restored after every call made with
+    /// `call_guest_function_by_name`, use
+    /// `MultiUseGuestCallContext::guest_memory_digest` to see the memory as
+    /// a sequence of calls leaves it.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn guest_memory_digest(&mut self) -> Result<GuestMemoryDigest> {
+        self.resume()?;
+        self.mem_mgr.unwrap_mgr().guest_memory_digest()
+    }
+
+    /// Free the host memory used by this sandbox while it is idle, by
+    /// compressing its guest memory and memory snapshots into a new file in
+    /// `dir` and releasing them. Does nothing if the sandbox is already
+    /// hibernated.
+    ///
+    /// The sandbox resumes transparently on the next guest function call,
+    /// or anything else that needs its memory, such as `reset`, `evolve` or
+    /// `devolve`. The file is deleted once the sandbox resumes or is dropped.
+    /// It is encrypted if a key is set with `set_hibernation_key` or
+    /// `set_hibernation_key_provider`.
+    ///
+    /// Under KVM, the memory shared with the guest is released as well as
+    /// the snapshots. Other hypervisors keep that memory pinned for as long
+    /// as the virtual machine exists, so only the snapshots are released.
+    ///
+    /// If the sandbox is ready to call guest functions, the guest is asked
+    /// to trim its memory first, see `trim_memory`; the sandbox hibernates
+    /// even if that fails. A hibernated sandbox's memory doesn't count
+    /// towards the total memory pressure handlers are called for, see
+    /// `memory_pressure`.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn hibernate(&mut self, dir: impl AsRef<Path>) -> Result<()> {
+        if self.hibernated.is_none() {
+            if self.state == SandboxState::Ready {
+                if let Err(e) = self.trim_memory() {
+                    log::warn!(
+                        "Sandbox {} failed to trim its memory before hibernating: {:?}",
+                        self.id(),
+                        e
+                    );
+                }
+            }
+            let key = self.hibernation_key()?;
+            let hibernated = self
+                .mem_mgr
+                .unwrap_mgr_mut()
+                .hibernate(dir.as_ref(), key.as_ref())?;
+            self.hibernated = Some(hibernated);
+            MemoryRegistry::global().set_resident(self.id(), false);
+        }
+        Ok(())
+    }
+
+    /// Ask the guest to release its caches and compact its heap, by calling
+    /// the `TRIM_MEMORY_FUNCTION_NAME` guest function, and keep the memory
+    /// it leaves behind as the state the sandbox is restored to after each
+    /// guest function call. The pages the guest leaves zeroed are then
+    /// given back to the host, under KVM, which faults them back in when
+    /// the guest next touches them. Returns whether the guest registered
+    /// the function and so was asked; guests whose functions are not
+    /// known to the host are never asked.
+    ///
+    /// Call this between guest function calls, for example when the host
+    /// is under memory pressure. Sandboxes also call it themselves after
+    /// their next standalone guest function call when a memory pressure
+    /// threshold is crossed, see `memory_pressure`. The call bypasses any
+    /// guest function policy or guest call interceptor. If it fails, the
+    /// sandbox's state is restored as it would be after any other failed
+    /// call.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn trim_memory(&mut self) -> Result<bool> {
+        if !self.guest_signatures.contains(TRIM_MEMORY_FUNCTION_NAME) {
+            return Ok(false);
+        }
+        self.check_ready()?;
+        self.call_keeping_state(TRIM_MEMORY_FUNCTION_NAME)?;
+        self.mem_mgr.unwrap_mgr_mut().release_zero_pages()?;
+        Ok(true)
+    }
+
+    /// Call the guest function `function_name`, which takes no parameters
+    /// and returns nothing, to do background maintenance such as expiring
+    /// cached entries, and keep the memory it leaves behind as the state
+    /// the sandbox is restored to after each guest function call, like
+    /// `trim_memory`. See `HousekeepingScheduler` to call it periodically.
+    ///
+    /// The call bypasses any guest function policy or guest call
+    /// interceptor. If it fails, the sandbox's state is restored as it
+    /// would be after any other failed call.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn run_housekeeping(&mut self, function_name: &str) -> Result<()> {
+        self.check_ready()?;
+        self.call_keeping_state(function_name)
+    }
+
+    /// Trim the sandbox's memory if a memory pressure threshold was crossed
+    /// since it last did, see `memory_pressure`.
+    ///
+    /// Only called once a standalone guest function call has restored the
+    /// sandbox's state, so that the memory kept as its snapshot is the
+    /// snapshot's own, never in a call context, session or pipeline, where
+    /// it would keep a tenant's state. The request stays pending until
+    /// then. Trimming only gives memory back, so a failure is logged.
+    pub(crate) fn trim_if_requested(&mut self) {
+        if self.check_ready().is_err() || !self.usage.take_trim_request() {
+            return;
+        }
+        if let Err(e) = self.trim_memory() {
+            log::warn!("Sandbox {} failed to trim its memory: {:?}", self.id(), e);
+        }
+    }
+
+    /// Call the guest function `function_name` and, if it succeeds, keep
+    /// the memory it leaves behind as the snapshot later calls are
+    /// restored to
+    fn call_keeping_state(&mut self, function_name: &str) -> Result<()> {
+        let function_name = &*self.resolve_function_name(function_name)?;
+        let res: Result<ReturnValue> =
+            self.dispatch_guest_call(function_name, ReturnType::Void, &[]);
+        match res {
+            Ok(_) => self.mem_mgr.unwrap_mgr_mut().replace_last_snapshot(),
+            Err(e) => {
+                self.restore_state()?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Whether this sandbox is hibernated, see `hibernate`
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn is_hibernated(&self) -> bool {
+        self.hibernated.is_some()
+    }
+
+    /// Restore this sandbox's memory if it is hibernated. Guest function
+    /// calls do this automatically, call this ahead of a call to keep the
+    /// time it takes to resume out of the call.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn resume(&mut self) -> Result<()> {
+        if let Some(hibernated) = &self.hibernated {
+            let key = if hibernated.is_encrypted() {
+                self.hibernation_key()?
+            } else {
+                None
+            };
+            self.mem_mgr
+                .unwrap_mgr_mut()
+                .resume(hibernated, key.as_ref())?;
+            self.hibernated = None;
+            MemoryRegistry::global().set_resident(self.id(), true);
+        }
+        Ok(())
+    }
+
+    /// Write a snapshot of this sandbox's guest memory to `out` with
+    /// `encoder`, and return its header. The first snapshot written with an
+    /// encoder holds all of the memory, the following ones only the pages
+    /// that changed since the previous one. See
+    /// `docs/snapshot-file-format.md` for the format of the snapshots.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn write_snapshot(
+        &mut self,
+        encoder: &mut SnapshotEncoder,
+        out: impl Write,
+    ) -> Result<SnapshotHeader> {
+        self.resume()?;
+        self.mem_mgr
+            .unwrap_mgr_mut()
+            .shared_mem
+            .with_exclusivity(|e| encoder.encode(e.as_slice(), out))?
+    }
+
+    /// Create a `SnapshotDecoder` for the snapshots to restore to this
+    /// sandbox with `restore_snapshot`, which rejects snapshots of any
+    /// other amount of guest memory before allocating memory for them
+    #[instrument(skip_all, parent = Span::current())]
+    pub fn snapshot_decoder(&self) -> SnapshotDecoder {
+        let mut decoder = SnapshotDecoder::new();
+        decoder.set_memory_size(Some(self.mem_mgr.unwrap_mgr().shared_mem.mem_size()));
+        decoder
+    }
+
+    /// Replace this sandbox's guest memory with the memory read by
+    /// `decoder`, and make it the state the sandbox is restored to after
+    /// each guest function call, as `evolve` would.
+    ///
+    /// The snapshots must have been written from a sandbox created from the
+    /// same guest binary and configuration, and with the same host
+    /// functions registered.
+    #[instrument(err(Debug), skip_all, parent = Span::current())]
+    pub fn restore_snapshot(&mut self, decoder: &SnapshotDecoder) -> Result<()> {
+        let memory = decoder
+            .memory()
+            .ok_or_else(|| new_error!("No snapshot has been read by the decoder"))?;
+        self.resume()?;
+        let cookie = *self.mem_mgr.get_stack_cookie();
+        self.mem_mgr
+            .unwrap_mgr_mut()
+            .restore_memory(memory, &cookie)
+    }
+
+    /// Restore the Sandbox's state
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn restore_state(&mut self) -> Result<()> {
+        self.resume()?;
+        self.close_chunk_streams()?;
+        self.host_handles()?.release_unkept()?;
+        let mem_mgr = self.mem_mgr.unwrap_mgr_mut();
+        mem_mgr.restore_state_from_last_snapshot()?;
+        self.call_tracer()?.record_reset();
+        Ok(())
+    }
+
+    /// Take a copy of the Sandbox's memory that can be restored with
+    /// `restore_checkpoint`
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn checkpoint(&mut self) -> Result<MemoryCheckpoint> {
+        self.resume()?;
+        self.mem_mgr.unwrap_mgr_mut().checkpoint()
+    }
+
+    /// Restore the Sandbox's memory to `checkpoint`, making it ready for
+    /// guest function calls even if a later call poisoned it
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn restore_checkpoint(&mut self, checkpoint: &mut MemoryCheckpoint) -> Result<()> {
+        self.resume()?;
+        self.close_chunk_streams()?;
+        self.host_handles()?.release_unkept()?;
+        self.mem_mgr
+            .unwrap_mgr_mut()
+            .restore_checkpoint(checkpoint)?;
+        self.state = SandboxState::Ready;
+        Ok(())
+    }
+
+    /// The tracer shared with the host functions the guest calls
+    fn call_tracer(&self) -> Result<CallTracer> {
+        Ok(self
+            ._host_funcs
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+            .tracer())
+    }
+
+    /// The guest forgets the streams it was reading when its memory is
+    /// restored
+    fn close_chunk_streams(&mut self) -> Result<()> {
+        self._host_funcs
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+            .chunk_streams()
+            .close_all()
+    }
+}
+
+impl WrapperGetter for MultiUseSandbox {
+    fn get_mgr_wrapper(&self) -> &MemMgrWrapper<HostSharedMemory> {
+        &self.mem_mgr
+    }
+    fn get_mgr_wrapper_mut(&mut self) -> &mut MemMgrWrapper<HostSharedMemory> {
+        &mut self.mem_mgr
+    }
+    fn get_hv_handler(&self) -> &HypervisorHandler {
+        &self.hv_handler
+    }
+    fn get_hv_handler_mut(&mut self) -> &mut HypervisorHandler {
+        &mut self.hv_handler
+    }
+}
+
+impl Sandbox for MultiUseSandbox {
+    fn check_stack_guard(&self) -> Result<bool> {
+        self.mem_mgr.check_stack_guard()
+    }
+}
+
+impl std::fmt::Debug for MultiUseSandbox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MultiUseSandbox")
+            .field("stack_guard", &self.mem_mgr.get_stack_cookie())
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+impl DevolvableSandbox<MultiUseSandbox, MultiUseSandbox, Noop<MultiUseSandbox, MultiUseSandbox>>
+    for MultiUseSandbox
+{
+    /// Consume `self` and move it back to a `MultiUseSandbox` with previous state.
+    ///
+    /// The purpose of this function is to allow multiple states to be associated with a single MultiUseSandbox.
+    ///
+    /// An implementation such as HyperlightJs or HyperlightWasm can use this to call guest functions to load JS or WASM code and then evolve the sandbox causing state to be captured.
+    /// The new MultiUseSandbox can then be used to call guest functions to execute the loaded code.
+    /// The devolve can be used to return the MultiUseSandbox to the state before the code was loaded. Thus avoiding initialisation overhead
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn devolve(mut self, _tsn: Noop<MultiUseSandbox, MultiUseSandbox>) -> Result<MultiUseSandbox> {
+        self.check_ready()?;
+        self.resume()?;
+        self.mem_mgr
+            .unwrap_mgr_mut()
+            .pop_and_restore_state_from_snapshot()?;
+        Ok(self)
+    }
+}
+
+impl<'a, F>
+    EvolvableSandbox<
+        MultiUseSandbox,
+        MultiUseSandbox,
+        MultiUseContextCallback<'a, MultiUseSandbox, F>,
+    > for MultiUseSandbox
+where
+    F: FnOnce(&mut MultiUseGuestCallContext) -> Result<()> + 'a,
+{
+    /// The purpose of this function is to allow multiple states to be associated with a single MultiUseSandbox.
+    ///
+    /// An implementation such as HyperlightJs or HyperlightWasm can use this to call guest functions to load JS or WASM code and then evolve the sandbox causing state to be captured.
+    /// The new MultiUseSandbox can then be used to call guest functions to execute the loaded code.
+    ///
+    /// The evolve function creates a new MultiUseCallContext which is then passed to a callback function  allowing the
+    /// callback function to call guest functions as part of the evolve process, once the callback function  is complete
+    /// the context is finished using a crate internal method that does not restore the prior state of the Sanbbox.
+    /// It then creates a mew  memory snapshot on the snapshot stack and returns the MultiUseSandbox
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    fn evolve(
+        mut self,
+        transition_func: MultiUseContextCallback<'a, MultiUseSandbox, F>,
+    ) -> Result<MultiUseSandbox> {
+        self.check_ready()?;
+        self.resume()?;
+        let mut ctx = self.new_call_context();
+        transition_func.call(&mut ctx)?;
+        let mut sbox = ctx.finish_no_reset();
+        sbox.mem_mgr.unwrap_mgr_mut().push_state()?;
+        sbox.host_handles()?.keep_all()?;
+        Ok(sbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::flatbuffer_wrappers::function_types::{
+        ParameterValue, ReturnType, ReturnValue,
+    };
+    use hyperlight_testing::{callback_guest_as_string, simple_guest_as_string};
+
+    use crate::func::call_ctx::MultiUseGuestCallContext;
+    use crate::mem::memory_region::{MemoryRegionFlags, MemoryRegionType};
+    use crate::mem::snapshot_file::{SnapshotDecoder, SnapshotEncoder};
+    use crate::sandbox::{MemoryPopulation, SandboxConfiguration, SandboxState};
+    use crate::sandbox_state::sandbox::{DevolvableSandbox, EvolvableSandbox};
+    use crate::sandbox_state::transition::{MultiUseContextCallback, Noop};
+    use crate::{GuestBinary, HyperlightError, MultiUseSandbox, UninitializedSandbox};
+
+    /// A sandbox for the Rust simpleguest, with `cfg` or the default
+    /// configuration
+    fn new_sandbox(cfg: Option<SandboxConfiguration>) -> MultiUseSandbox {
+        let path = simple_guest_as_string().unwrap();
+        UninitializedSandbox::new(GuestBinary::FilePath(path), cfg, None, None)
+            .unwrap()
+            .evolve(Noop::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn result_buffer() {
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_result_buffer_size(0x10000);
+        let mut sbox = new_sandbox(Some(cfg));
+        let expected: Vec<u8> = (0..0x10000).map(|i| i as u8).collect();
+        let res = sbox
+            .call_guest_function_with_result_buffer(
+                "FillResultBuffer",
+                Some(vec![ParameterValue::Int(0x10000)]),
+                |result| result.to_vec(),
+            )
+            .unwrap();
+        assert_eq!(expected, res);
+
+        // a result that does not fit is rejected by the guest
+        let res = sbox.call_guest_function_with_result_buffer(
+            "FillResultBuffer",
+            Some(vec![ParameterValue::Int(0x10001)]),
+            |result| result.len(),
+        );
+        assert!(matches!(res, Err(HyperlightError::GuestError(_, _))));
+
+        // as is writing to the result buffer if none was configured
+        let mut sbox = new_sandbox(None);
+        let res = sbox.call_guest_function_with_result_buffer(
+            "FillResultBuffer",
+            Some(vec![ParameterValue::Int(1)]),
+            |result| result.len(),
+        );
+        assert!(matches!(res, Err(HyperlightError::GuestError(_, _))));
+    }
+
+    #[test]
+    fn read_only_data() {
+        let path = simple_guest_as_string().unwrap();
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_read_only_data_size(0x2000);
+        let mut sbox: MultiUseSandbox =
+            UninitializedSandbox::new(GuestBinary::FilePath(path), Some(cfg), None, None)
+                .unwrap()
+                .evolve(Noop::default())
+                .unwrap();
+        let get_read_only_data = |sbox: &mut MultiUseSandbox| {
+            sbox.call_guest_function_by_name("GetReadOnlyData", ReturnType::VecBytes, None)
+                .unwrap()
+        };
+        assert_eq!(ReturnValue::VecBytes(vec![]), get_read_only_data(&mut sbox));
+
+        // published data is seen by every following call
+        let table: Vec<u8> = (0..0x2000).map(|i| (i * 7) as u8).collect();
+        sbox.publish_read_only_data(&table).unwrap();
+        for _ in 0..2 {
+            assert_eq!(
+                ReturnValue::VecBytes(table.clone()),
+                get_read_only_data(&mut sbox)
+            );
+        }
+        sbox.publish_read_only_data(b"config").unwrap();
+        assert_eq!(
+            ReturnValue::VecBytes(b"config".to_vec()),
+            get_read_only_data(&mut sbox)
+        );
+        assert!(sbox.publish_read_only_data(&[0; 0x2001]).is_err());
+
+        // the guest can't write to the region, and resetting the sandbox
+        // keeps the published data
+        let res = sbox.call_guest_function_by_name("WriteReadOnlyData", ReturnType::Void, None);
+        assert!(res.is_err());
+        sbox.reset().unwrap();
+        assert_eq!(
+            ReturnValue::VecBytes(b"config".to_vec()),
+            get_read_only_data(&mut sbox)
+        );
+    }
+
+    #[test]
+    fn dropping_releases_memory() {
+        let sbox = new_sandbox(None);
+        let mapping = sbox.mem_mgr.unwrap_mgr().shared_mem.downgrade();
+        assert!(mapping.upgrade().is_some());
+
+        // the memory is unmapped on the reclamation thread, once the
+        // hypervisor handler thread has stopped using it
+        drop(sbox);
+        crate::sandbox::reclaim::flush();
+        assert!(mapping.upgrade().is_none());
+    }
+
+    #[test]
+    fn guest_output_is_flushed_after_each_call() {
+        let mut sbox = new_sandbox(None);
+        // a line without a newline is still logged when the call returns,
+        // rather than being joined to the next call's output
+        sbox.call_guest_function_by_name(
+            "DebugPrint",
+            ReturnType::Void,
+            Some(vec![ParameterValue::String("no newline".to_string())]),
+        )
+        .unwrap();
+        assert_eq!(
+            0,
+            sbox.mem_mgr
+                .unwrap_mgr()
+                .guest_log_forwarder()
+                .flush_debug_output()
+        );
+    }
+
+    #[test]
+    fn publish_read_only_data_while_hibernated() {
+        let path = simple_guest_as_string().unwrap();
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_read_only_data_size(0x1000);
+        let mut sbox: MultiUseSandbox =
+            UninitializedSandbox::new(GuestBinary::FilePath(path), Some(cfg), None, None)
+                .unwrap()
+                .evolve(Noop::default())
+                .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+
+        // publishing resumes the sandbox first, so resuming doesn't
+        // overwrite the data with the memory from before hibernation
+        sbox.hibernate(dir.path()).unwrap();
+        sbox.publish_read_only_data(b"published").unwrap();
+        assert!(!sbox.is_hibernated());
+        let res = sbox
+            .call_guest_function_by_name("GetReadOnlyData", ReturnType::VecBytes, None)
+            .unwrap();
+        assert_eq!(ReturnValue::VecBytes(b"published".to_vec()), res);
+
+        // and prefaulting populates the resumed memory
+        sbox.hibernate(dir.path()).unwrap();
+        sbox.prefault().unwrap();
+        assert!(!sbox.is_hibernated());
+        assert_eq!(MemoryPopulation::Prefault, sbox.memory_population());
+    }
+
+    #[test]
+    fn symbolize() {
+        use hyperlight_common::symbol_map::{Symbol, SymbolMap};
+
+        let path = simple_guest_as_string().unwrap();
+        let mut u_sbox =
+            UninitializedSandbox::new(GuestBinary::FilePath(path), None, None, None).unwrap();
+        assert!(u_sbox.set_symbol_map(b"not a symbol map").is_err());
+        let entrypoint = u64::from(u_sbox.mgr.unwrap_mgr().entrypoint_offset);
+        let map = SymbolMap::new(vec![Symbol {
+            offset: entrypoint,
+            size: 0x10,
+            name: "entrypoint".to_string(),
+        }]);
+        u_sbox.set_symbol_map(&map.to_bytes()).unwrap();
+        let sbox: MultiUseSandbox = u_sbox.evolve(Noop::default()).unwrap();
+
+        let load_addr = u64::from(&sbox.mem_mgr.unwrap_mgr().load_addr);
+        assert_eq!(
+            Some("entrypoint".to_string()),
+            sbox.symbolize(load_addr + entrypoint)
+        );
+        assert_eq!(
+            Some("entrypoint+0x4".to_string()),
+            sbox.symbolize(load_addr + entrypoint + 4)
+        );
+        assert_eq!(None, sbox.symbolize(load_addr + entrypoint + 0x10));
+
+        // the symbol map is kept when the sandbox is recreated
+        let sbox = sbox.recreate().unwrap();
+        assert_eq!(
+            Some("entrypoint".to_string()),
+            sbox.symbolize(load_addr + entrypoint)
+        );
+    }
+
+    #[test]
+    #[cfg(not(inprocess))]
+    fn crash_fingerprint() {
+        let crash = |sbox: &mut MultiUseSandbox| {
+            let err = sbox
+                .call_guest_function_by_name("TriggerException", ReturnType::Void, None)
+                .unwrap_err();
+            let crash = sbox.crash_fingerprint(&err).unwrap();
+            // the error the call returned has the fingerprint
+            let HyperlightError::GuestAborted(_, message) = &err else {
+                panic!("unexpected error {:?}", err);
+            };
+            assert!(message.ends_with(&format!("Fingerprint: {}", crash)));
+            crash
+        };
+
+        // the same crash has the same fingerprint in different sandboxes
+        let mut sbox1 = new_sandbox(None);
+        let mut sbox2 = new_sandbox(None);
+        let fingerprint = crash(&mut sbox1);
+        // `ud2` raises an invalid opcode exception
+        assert_eq!("guest_exception:0x6", fingerprint.fault());
+        assert!(!fingerprint.frames().is_empty());
+        assert_eq!(fingerprint, crash(&mut sbox2));
+        sbox1.reset().unwrap();
+        assert_eq!(fingerprint.id(), crash(&mut sbox1).id());
+
+        // errors that aren't guest crashes have no fingerprint
+        sbox1.reset().unwrap();
+        let err = sbox1
+            .call_guest_function_by_name("NonExistentFunction", ReturnType::Int, None)
+            .unwrap_err();
+        assert_eq!(None, sbox1.crash_fingerprint(&err));
+    }
+
+    #[test]
+    fn borrowed_parameters() {
+        let mut sbox = new_sandbox(None);
+
+        let message = "hello from a borrowed str";
+        let res = sbox
+            .call_guest_function_by_name_borrowed("Echo", ReturnType::String, &[message.into()])
+            .unwrap();
+        assert_eq!(ReturnValue::String(message.to_string()), res);
+
+        let bytes = [1u8, 2, 3];
+        let res = sbox
+            .call_guest_function_by_name_borrowed(
+                "SetByteArrayToZero",
+                ReturnType::VecBytes,
+                &[bytes.as_slice().into()],
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::VecBytes(vec![0; 3]), res);
+
+        // borrowed parameters are checked against the guest's signatures
+        let res = sbox.call_guest_function_by_name_borrowed(
+            "Echo",
+            ReturnType::String,
+            &[bytes.as_slice().into()],
+        );
+        assert!(matches!(
+            res,
+            Err(HyperlightError::GuestFunctionParameterTypeMismatch(..))
+        ));
+    }
+
+    #[test]
+    fn secrets() {
+        let path = simple_guest_as_string().unwrap();
+        let mut u_sbox =
+            UninitializedSandbox::new(GuestBinary::FilePath(path.clone()), None, None, None)
+                .unwrap();
+        assert!(u_sbox.add_secret("api-key", b"hunter2", false).is_err());
+
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_secrets_size(0x1000);
+        let mut u_sbox =
+            UninitializedSandbox::new(GuestBinary::FilePath(path), Some(cfg), None, None).unwrap();
+        u_sbox.add_secret("api-key", b"hunter2", false).unwrap();
+        u_sbox.add_secret("token", b"abc", true).unwrap();
+        assert!(u_sbox.add_secret("token", b"def", false).is_err());
+        assert!(u_sbox.add_secret("", b"def", false).is_err());
+        assert!(u_sbox.add_secret("big", &[0; 0x1000], false).is_err());
+        let sbox: MultiUseSandbox = u_sbox.evolve(Noop::default()).unwrap();
+
+        let mut ctx = sbox.new_call_context();
+        let mut take_secret = |name: &str| {
+            ctx.call(
+                "TakeSecret",
+                ReturnType::VecBytes,
+                Some(vec![ParameterValue::String(name.to_string())]),
+            )
+        };
+        for _ in 0..2 {
+            assert_eq!(
+                ReturnValue::VecBytes(b"hunter2".to_vec()),
+                take_secret("api-key").unwrap()
+            );
+        }
+        // a secret wiped after it is read can only be taken once
+        assert_eq!(
+            ReturnValue::VecBytes(b"abc".to_vec()),
+            take_secret("token").unwrap()
+        );
+        assert!(take_secret("token").is_err());
+        assert!(take_secret("missing").is_err());
+        let mut sbox = ctx.finish().unwrap();
+
+        // the secrets are not in guest memory between calls
+        let mut snapshot = Vec::new();
+        sbox.write_snapshot(&mut SnapshotEncoder::default(), &mut snapshot)
+            .unwrap();
+        assert!(!snapshot.windows(7).any(|w| w == b"hunter2"));
+
+        // and a wiped secret stays wiped when the memory is restored
+        for _ in 0..2 {
+            let res = sbox.call_guest_function_by_name(
+                "TakeSecret",
+                ReturnType::VecBytes,
+                Some(vec![ParameterValue::String("token".to_string())]),
+            );
+            assert!(res.is_err());
+            let res = sbox.call_guest_function_by_name(
+                "TakeSecret",
+                ReturnType::VecBytes,
+                Some(vec![ParameterValue::String("api-key".to_string())]),
+            );
+            assert_eq!(ReturnValue::VecBytes(b"hunter2".to_vec()), res.unwrap());
+        }
+    }
+
+    #[test]
+    fn memory_layout() {
+        let sbox = new_sandbox(None);
+        let regions = sbox.memory_layout().unwrap();
+
+        assert_eq!(MemoryRegionType::PageTables, regions[0].region_type);
+        assert_eq!("page_tables", regions[0].name);
+        // the regions are contiguous
+        for pair in regions.windows(2) {
+            assert_eq!(pair[0].guest_address + pair[0].size, pair[1].guest_address);
+        }
+        let code = regions
+            .iter()
+            .find(|r| r.region_type == MemoryRegionType::Code)
+            .unwrap();
+        assert!(code.permissions.contains(MemoryRegionFlags::EXECUTE));
+        let guard_page = regions
+            .iter()
+            .find(|r| r.region_type == MemoryRegionType::GuardPage)
+            .unwrap();
+        assert!(!guard_page.permissions.contains(MemoryRegionFlags::WRITE));
+        assert!(regions
+            .iter()
+            .any(|r| r.region_type == MemoryRegionType::Heap && r.size > 0));
+    }
+
+    #[test]
+    fn poisoned_sandbox_can_be_reset_or_recreated() {
+        let mut sbox = new_sandbox(None);
+        assert_eq!(SandboxState::Ready, sbox.state());
+
+        // a guest error returned by a guest function doesn't poison the sandbox
+        let res = sbox.call_guest_function_by_name("NonExistentFunction", ReturnType::Int, None);
+        assert!(matches!(res, Err(HyperlightError::GuestError(_, _))));
+        assert_eq!(SandboxState::Ready, sbox.state());
+
+        // being cancelled after the max execution time does
+        let res = sbox.call_guest_function_by_name("Spin", ReturnType::Void, None);
+        assert!(matches!(
+            res,
+            Err(HyperlightError::ExecutionCanceledByHost())
+        ));
+        assert_eq!(SandboxState::Poisoned, sbox.state());
+        let res = sbox.call_guest_function_by_name("GetStatic", ReturnType::Int, None);
+        assert!(matches!(
+            res,
+            Err(HyperlightError::SandboxNotReady(SandboxState::Poisoned))
+        ));
+
+        sbox.reset().unwrap();
+        assert_eq!(SandboxState::Ready, sbox.state());
+        let res = sbox
+            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+            .unwrap();
+        assert_eq!(ReturnValue::Int(0), res);
+
+        let mut ctx = sbox.new_call_context();
+        assert!(ctx.call("Spin", ReturnType::Void, None).is_err());
+        let res = ctx.call("GetStatic", ReturnType::Int, None);
+        assert!(matches!(res, Err(HyperlightError::SandboxNotReady(_))));
+
+        // the host functions registered on the original sandbox, such as
+        // `HostPrint`, are available to the recreated one
+        let mut sbox = ctx.finish().unwrap().recreate().unwrap();
+        assert_eq!(SandboxState::Ready, sbox.state());
+        let res = sbox
+            .call_guest_function_by_name(
+                "PrintOutput",
+                ReturnType::Int,
+                Some(vec![ParameterValue::String("recreated\n".to_string())]),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::Int(10), res);
+    }
+
+    #[test]
+    fn reset_discards_state_kept_by_a_call_context() {
+        let sbox = new_sandbox(None);
+
+        // a call context keeps the guest's state between calls until the
+        // sandbox is reset
+        let mut ctx = sbox.new_call_context();
+        ctx.call(
+            "AddToStatic",
+            ReturnType::Int,
+            Some(vec![ParameterValue::Int(5)]),
+        )
+        .unwrap();
+        let res = ctx.call("GetStatic", ReturnType::Int, None).unwrap();
+        assert_eq!(ReturnValue::Int(5), res);
+
+        let mut sbox = ctx.finish_no_reset();
+        sbox.reset().unwrap();
+        let res = sbox
+            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+            .unwrap();
+        assert_eq!(ReturnValue::Int(0), res);
+    }
+
+    #[test]
+    #[cfg(kvm)]
+    fn max_guest_instructions() {
+        use std::time::Duration;
+
+        use crate::sandbox::hypervisor::{get_available_hypervisor, HypervisorType};
+
+        if *get_available_hypervisor() != Some(HypervisorType::Kvm) {
+            return;
+        }
+
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_max_guest_instructions(2_000_000);
+        // single-stepping the guest is slow, make sure the instruction
+        // limit is reached before the time limits
+        cfg.set_max_initialization_time(Duration::from_secs(60));
+        cfg.set_max_execution_time(Duration::from_secs(60));
+        let mut sbox = new_sandbox(Some(cfg));
+
+        let res = sbox
+            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+            .unwrap();
+        assert_eq!(ReturnValue::Int(0), res);
+
+        let res = sbox.call_guest_function_by_name("Spin", ReturnType::Void, None);
+        assert!(matches!(
+            res,
+            Err(HyperlightError::GuestInstructionLimitExceeded(2_000_000))
+        ));
+        assert_eq!(SandboxState::Poisoned, sbox.state());
+
+        // the count starts again from 0 for each call
+        sbox.reset().unwrap();
+        let res = sbox
+            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+            .unwrap();
+        assert_eq!(ReturnValue::Int(0), res);
+    }
+
+    #[test]
+    fn retry_policy() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        use crate::error::ErrorCategory;
+        use crate::func::GuestCallAction;
+        use crate::sandbox::{RetryPolicy, RetryRecovery};
+
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_max_execution_time(Duration::from_millis(100));
+        let mut sbox = new_sandbox(Some(cfg));
+
+        let attempts = Arc::new(AtomicU32::new(0));
+        let counted = attempts.clone();
+        sbox.set_guest_call_interceptor(move |_: &str, args| {
+            counted.fetch_add(1, Ordering::SeqCst);
+            GuestCallAction::Proceed(args)
+        });
+        sbox.set_retry_policy(RetryPolicy::new(3));
+
+        // by default calls to functions that aren't idempotent, such as
+        // `Spin`, are not retried
+        let res = sbox.call_guest_function_by_name("Spin", ReturnType::Void, None);
+        assert!(matches!(
+            res,
+            Err(HyperlightError::ExecutionCanceledByHost())
+        ));
+        assert_eq!(1, attempts.swap(0, Ordering::SeqCst));
+        sbox.reset().unwrap();
+
+        let mut policy = RetryPolicy::new(3);
+        policy.set_idempotent_only(false);
+        sbox.set_retry_policy(policy);
+
+        // timeouts are retried until the attempts run out
+        let res = sbox.call_guest_function_by_name("Spin", ReturnType::Void, None);
+        let err = res.unwrap_err();
+        assert!(matches!(err, HyperlightError::ExecutionCanceledByHost()));
+        assert_eq!(ErrorCategory::Timeout, err.category());
+        assert_eq!(3, attempts.swap(0, Ordering::SeqCst));
+        assert_eq!(SandboxState::Poisoned, sbox.state());
+        sbox.reset().unwrap();
+
+        // guest errors are not retried
+        let res = sbox.call_guest_function_by_name("NonExistentFunction", ReturnType::Int, None);
+        assert!(matches!(res, Err(HyperlightError::GuestError(_, _))));
+        assert_eq!(1, attempts.swap(0, Ordering::SeqCst));
+
+        // recreating between attempts keeps the interceptor and the policy
+        let mut policy = RetryPolicy::new(3);
+        policy.set_idempotent_only(false);
+        policy.set_recovery(RetryRecovery::Recreate);
+        sbox.set_retry_policy(policy);
+        let res = sbox.call_guest_function_by_name("Spin", ReturnType::Void, None);
+        assert!(res.is_err());
+        assert_eq!(3, attempts.swap(0, Ordering::SeqCst));
+        sbox.reset().unwrap();
+
+        let res = sbox
+            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+            .unwrap();
+        assert_eq!(ReturnValue::Int(0), res);
+        assert_eq!(1, attempts.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn guest_function_flags() {
+        use hyperlight_common::flatbuffer_wrappers::host_function_definition::FunctionFlags;
+
+        use crate::sandbox::RetryPolicy;
+
+        let mut sbox = new_sandbox(None);
+
+        assert!(sbox.guest_function_flags("Echo").is_pure());
+        assert!(sbox.guest_function_flags("Echo").is_idempotent());
+        assert!(sbox.guest_function_flags("GetStatic").is_read_only());
+        assert!(!sbox.guest_function_flags("GetStatic").is_pure());
+        assert_eq!(
+            FunctionFlags::NONE,
+            sbox.guest_function_flags("AddToStatic")
+        );
+        assert_eq!(
+            FunctionFlags::NONE,
+            sbox.guest_function_flags("NoSuchFunction")
+        );
+
+        // a policy that only retries idempotent functions doesn't retry
+        // calls to other functions
+        let mut policy = RetryPolicy::new(3);
+        policy.set_idempotent_only(true);
+        sbox.set_retry_policy(policy);
+        let res = sbox
+            .call_guest_function_by_name(
+                "AddToStatic",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(5)]),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::Int(5), res);
+    }
+
+    #[test]
+    #[cfg(not(gdb))]
+    fn heartbeat_timeout() {
+        use std::time::{Duration, Instant};
+
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_heartbeat_timeout(Duration::from_millis(200));
+        cfg.set_max_execution_time(Duration::from_secs(30));
+        let mut sbox = new_sandbox(Some(cfg));
+        assert_eq!(None, sbox.last_heartbeat());
+
+        // a call that keeps sending heartbeats may run for longer than the
+        // heartbeat timeout
+        let start = Instant::now();
+        sbox.call_guest_function_by_name(
+            "SleepWithHeartbeats",
+            ReturnType::Void,
+            Some(vec![ParameterValue::Int(5), ParameterValue::ULong(100)]),
+        )
+        .unwrap();
+        assert!(sbox.last_heartbeat().unwrap() > start);
+
+        // a call that stops sending heartbeats is cancelled long before the
+        // max execution time
+        let start = Instant::now();
+        let res = sbox.call_guest_function_by_name("Spin", ReturnType::Void, None);
+        assert!(matches!(res, Err(HyperlightError::GuestHeartbeatLapsed(_))));
+        assert!(start.elapsed() < Duration::from_secs(30));
+        assert_eq!(SandboxState::Poisoned, sbox.state());
+    }
+
+    #[test]
+    #[cfg(not(gdb))]
+    fn cpu_time() {
+        use std::time::Duration;
+
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_max_execution_time(Duration::from_millis(300));
+        let mut sbox = new_sandbox(Some(cfg));
+        let initialised = sbox.cpu_time();
+
+        // sleeping in a host function uses no CPU time
+        sbox.call_guest_function_by_name(
+            "Sleep",
+            ReturnType::Void,
+            Some(vec![ParameterValue::ULong(200)]),
+        )
+        .unwrap();
+        let slept = sbox.cpu_time();
+        assert!(slept >= initialised);
+        assert!(slept - initialised < Duration::from_millis(100));
+
+        // spinning in the guest until the call is cancelled does
+        let res = sbox.call_guest_function_by_name("Spin", ReturnType::Void, None);
+        assert!(res.is_err());
+        assert!(sbox.cpu_time() - slept >= Duration::from_millis(150));
+    }
+
+    #[test]
+    #[cfg(not(gdb))]
+    fn max_time_between_host_calls() {
+        use std::time::{Duration, Instant};
+
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_max_time_between_host_calls(Duration::from_millis(200));
+        cfg.set_max_execution_time(Duration::from_secs(30));
+        let mut sbox = new_sandbox(Some(cfg));
+
+        // a call that keeps calling the host may run for longer than the
+        // limit, and the time spent in host functions doesn't count
+        sbox.call_guest_function_by_name(
+            "SleepWithHeartbeats",
+            ReturnType::Void,
+            Some(vec![ParameterValue::Int(5), ParameterValue::ULong(100)]),
+        )
+        .unwrap();
+        sbox.call_guest_function_by_name(
+            "Sleep",
+            ReturnType::Void,
+            Some(vec![ParameterValue::ULong(500)]),
+        )
+        .unwrap();
+
+        // a call that stops calling the host is cancelled long before the
+        // max execution time
+        let start = Instant::now();
+        let res = sbox.call_guest_function_by_name("Spin", ReturnType::Void, None);
+        assert!(matches!(
+            res,
+            Err(HyperlightError::GuestHostCallIntervalExceeded(max_time))
+                if max_time == Duration::from_millis(200)
+        ));
+        assert!(start.elapsed() < Duration::from_secs(30));
+        assert_eq!(SandboxState::Poisoned, sbox.state());
+    }
+
+    #[test]
+    fn epoch_deadline() {
+        use std::time::{Duration, Instant};
+
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_epoch_deadline(2);
+        cfg.set_max_execution_time(Duration::from_secs(30));
+        let mut sbox = new_sandbox(Some(cfg));
+        let epoch = sbox.epoch_handle();
+        assert_eq!(0, epoch.current());
+
+        // increments before a call don't count towards its deadline
+        epoch.increment();
+        epoch.increment();
+        let res = sbox.call_guest_function_by_name(
+            "Echo",
+            ReturnType::String,
+            Some(vec![ParameterValue::String("hi".to_string())]),
+        );
+        assert_eq!(ReturnValue::String("hi".to_string()), res.unwrap());
+
+        let ticker = {
+            let epoch = epoch.clone();
+            std::thread::spawn(move || {
+                for _ in 0..2 {
+                    std::thread::sleep(Duration::from_millis(100));
+                    epoch.increment();
+                }
+            })
+        };
+        let start = Instant::now();
+        let res = sbox.call_guest_function_by_name("SpinWithEpochChecks", ReturnType::Void, None);
+        ticker.join().unwrap();
+        assert!(matches!(res, Err(HyperlightError::EpochDeadlineReached())));
+        assert!(start.elapsed() < Duration::from_secs(30));
+        assert_eq!(4, epoch.current());
+        assert_eq!(SandboxState::Poisoned, sbox.state());
+
+        // the handle is kept when the sandbox is recreated
+        let mut sbox = sbox.recreate().unwrap();
+        assert_eq!(4, sbox.epoch_handle().current());
+
+        // a guest that never checks the epoch is stopped by the host
+        let ticker = {
+            let epoch = epoch.clone();
+            std::thread::spawn(move || {
+                for _ in 0..2 {
+                    std::thread::sleep(Duration::from_millis(100));
+                    epoch.increment();
+                }
+            })
+        };
+        let start = Instant::now();
+        let res = sbox.call_guest_function_by_name("Spin", ReturnType::Void, None);
+        ticker.join().unwrap();
+        assert!(matches!(res, Err(HyperlightError::EpochDeadlineReached())));
+        assert!(start.elapsed() < Duration::from_secs(30));
+        assert_eq!(6, epoch.current());
+        assert_eq!(SandboxState::Poisoned, sbox.state());
+    }
+
+    #[test]
+    fn guest_can_sleep() {
+        let mut sbox = new_sandbox(None);
+
+        let start = std::time::Instant::now();
+        let res = sbox
+            .call_guest_function_by_name(
+                "Sleep",
+                ReturnType::Void,
+                Some(vec![ParameterValue::ULong(100)]),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::Void, res);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(100));
+
+        // sleeping for 0 milliseconds yields
+        sbox.call_guest_function_by_name(
+            "Sleep",
+            ReturnType::Void,
+            Some(vec![ParameterValue::ULong(0)]),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn guest_async_tasks_call_host() {
+        let mut sbox = new_sandbox(None);
+
+        // two tasks each print the message three times
+        let res = sbox
+            .call_guest_function_by_name(
+                "PrintAsyncTasks",
+                ReturnType::Int,
+                Some(vec![ParameterValue::String("async\n".to_string())]),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::Int(36), res);
+    }
+
+    #[test]
+    fn guest_println_is_sent_to_host_print() {
+        use std::sync::{Arc, Mutex};
+
+        let output = Arc::new(Mutex::new(String::new()));
+        let writer_output = output.clone();
+        let writer = Arc::new(Mutex::new(move |msg: String| -> crate::Result<i32> {
+            writer_output.lock().unwrap().push_str(&msg);
+            Ok(msg.len() as i32)
+        }));
+
+        let path = simple_guest_as_string().unwrap();
+        let mut sbox: MultiUseSandbox =
+            UninitializedSandbox::new(GuestBinary::FilePath(path), None, None, Some(&writer))
+                .unwrap()
+                .evolve(Noop::default())
+                .unwrap();
+
+        sbox.call_guest_function_by_name(
+            "PrintFormatted",
+            ReturnType::Void,
+            Some(vec![
+                ParameterValue::String("answer".to_string()),
+                ParameterValue::Int(42),
+            ]),
+        )
+        .unwrap();
+        assert_eq!("answer: 42\n", *output.lock().unwrap());
+    }
+
+    #[test]
+    fn guest_calls_are_recorded_in_metrics() {
+        let mut sbox = new_sandbox(None);
+
+        // the metrics are shared by all sandboxes, so use a function name
+        // no other test calls
+        let missing_function = format!("Missing{}", uuid::Uuid::new_v4().simple());
+        for _ in 0..2 {
+            let res = sbox.call_guest_function_by_name(&missing_function, ReturnType::Int, None);
+            assert!(res.is_err());
+        }
+
+        let metrics = crate::metrics_snapshot()
+            .guest_functions
+            .remove(&missing_function)
+            .unwrap();
+        assert_eq!(2, metrics.call_count);
+        assert_eq!(2, metrics.error_count);
+        assert_eq!(2, metrics.latency_buckets.iter().sum::<u64>());
+        assert!(metrics.mean_latency().unwrap() <= metrics.max_latency);
+    }
+
+    #[test]
+    fn prefault_memory() {
+        let mut sbox = new_sandbox(None);
+        assert_eq!(MemoryPopulation::Lazy, sbox.memory_population());
+        sbox.prefault().unwrap();
+        assert_eq!(MemoryPopulation::Prefault, sbox.memory_population());
+        let res = sbox
+            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+            .unwrap();
+        assert_eq!(ReturnValue::Int(0), res);
+
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_memory_population(MemoryPopulation::Prefault);
+        let mut sbox = new_sandbox(Some(cfg));
+        assert_eq!(MemoryPopulation::Prefault, sbox.memory_population());
+        let res = sbox
+            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+            .unwrap();
+        assert_eq!(ReturnValue::Int(0), res);
+    }
+
+    // Tests to ensure that many (1000) function calls can be made in a call context with a small stack (1K) and heap(14K).
+    // This test effectively ensures that the stack is being properly reset after each call and we are not leaking memory in the Guest.
+    #[test]
+    fn test_with_small_stack_and_heap() {
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_heap_size(20 * 1024);
+        cfg.set_stack_size(16 * 1024);
+
+        let sbox1: MultiUseSandbox = {
+            let path = simple_guest_as_string().unwrap();
+            let u_sbox =
+                UninitializedSandbox::new(GuestBinary::FilePath(path), Some(cfg), None, None)
+                    .unwrap();
+            u_sbox.evolve(Noop::default())
+        }
+        .unwrap();
+
+        let mut ctx = sbox1.new_call_context();
+
+        for _ in 0..1000 {
+            ctx.call(
+                "Echo",
+                ReturnType::String,
+                Some(vec![ParameterValue::String("hello".to_string())]),
+            )
+            .unwrap();
+        }
+
+        let sbox2: MultiUseSandbox = {
+            let path = simple_guest_as_string().unwrap();
+            let u_sbox =
+                UninitializedSandbox::new(GuestBinary::FilePath(path), Some(cfg), None, None)
+                    .unwrap();
+            u_sbox.evolve(Noop::default())
+        }
+        .unwrap();
+
+        let mut ctx = sbox2.new_call_context();
+
+        for i in 0..1000 {
+            ctx.call(
+                "PrintUsingPrintf",
+                ReturnType::Int,
+                Some(vec![ParameterValue::String(
+                    format!("Hello World {}\n", i).to_string(),
+                )]),
+            )
+            .unwrap();
+        }
+    }
+
+    /// Tests that evolving from MultiUseSandbox to MultiUseSandbox creates a new state
+    /// and devolving from MultiUseSandbox to MultiUseSandbox restores the previous state
+    #[test]
+    fn evolve_devolve_handles_state_correctly() {
+        let sbox1: MultiUseSandbox = {
+            let path = simple_guest_as_string().unwrap();
+            let u_sbox =
+                UninitializedSandbox::new(GuestBinary::FilePath(path), None, None, None).unwrap();
+            u_sbox.evolve(Noop::default())
+        }
+        .unwrap();
+
+        let func = Box::new(|call_ctx: &mut MultiUseGuestCallContext| {
+            call_ctx.call(
+                "AddToStatic",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(5)]),
+            )?;
+            Ok(())
+        });
+        let transition_func = MultiUseContextCallback::from(func);
+        let mut sbox2 = sbox1.evolve(transition_func).unwrap();
+        let res = sbox2
+            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+            .unwrap();
+        assert_eq!(res, ReturnValue::Int(5));
+        let mut sbox3: MultiUseSandbox = sbox2.devolve(Noop::default()).unwrap();
+        let res = sbox3
+            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+            .unwrap();
+        assert_eq!(res, ReturnValue::Int(0));
+    }
+
+    #[test]
+    fn hibernate_and_resume() {
+        let sbox = new_sandbox(None);
+        let func = Box::new(|call_ctx: &mut MultiUseGuestCallContext| {
+            call_ctx.call(
+                "AddToStatic",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(5)]),
+            )?;
+            Ok(())
+        });
+        let mut sbox = sbox.evolve(MultiUseContextCallback::from(func)).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let files = || std::fs::read_dir(dir.path()).unwrap().count();
+
+        sbox.hibernate(dir.path()).unwrap();
+        assert!(sbox.is_hibernated());
+        assert_eq!(1, files());
+
+        // the next call resumes the sandbox, with the state it was evolved to
+        let res = sbox
+            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+            .unwrap();
+        assert_eq!(ReturnValue::Int(5), res);
+        assert!(!sbox.is_hibernated());
+        assert_eq!(0, files());
+
+        // the snapshot taken before the evolve is restored too
+        sbox.hibernate(dir.path()).unwrap();
+        let mut sbox: MultiUseSandbox = sbox.devolve(Noop::default()).unwrap();
+        let res = sbox
+            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+            .unwrap();
+        assert_eq!(ReturnValue::Int(0), res);
+
+        // dropping a hibernated sandbox deletes its file
+        sbox.hibernate(dir.path()).unwrap();
+        assert_eq!(1, files());
+        drop(sbox);
+        assert_eq!(0, files());
+    }
+
+    #[test]
+    fn hibernate_encrypted() {
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        use crate::mem::snapshot_encryption::SnapshotKey;
+        use crate::new_error;
+
+        let mut sbox = new_sandbox(None);
+        let key = SnapshotKey::generate();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let available = Arc::new(AtomicBool::new(true));
+        let id = sbox.id();
+        sbox.set_hibernation_key_provider({
+            let calls = calls.clone();
+            let available = available.clone();
+            move |sandbox_id| {
+                assert_eq!(id, sandbox_id);
+                calls.fetch_add(1, Ordering::SeqCst);
+                if !available.load(Ordering::SeqCst) {
+                    return Err(new_error!("the key service is unavailable"));
+                }
+                Ok(key.clone())
+            }
+        });
+        let dir = tempfile::tempdir().unwrap();
+
+        sbox.hibernate(dir.path()).unwrap();
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+
+        // the key is fetched again to resume, and the sandbox stays
+        // hibernated until it can be
+        available.store(false, Ordering::SeqCst);
+        assert!(sbox.resume().is_err());
+        assert!(sbox.is_hibernated());
+        available.store(true, Ordering::SeqCst);
+        let res = sbox.call_guest_function_by_name(
+            "Echo",
+            ReturnType::String,
+            Some(vec![ParameterValue::String("hi".to_string())]),
+        );
+        assert_eq!(ReturnValue::String("hi".to_string()), res.unwrap());
+        assert!(!sbox.is_hibernated());
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+
+        // a sandbox that can't fetch its key doesn't hibernate
+        available.store(false, Ordering::SeqCst);
+        assert!(sbox.hibernate(dir.path()).is_err());
+        assert!(!sbox.is_hibernated());
+    }
+
+    #[test]
+    fn busy_while_running_a_call() {
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_max_execution_time(Duration::from_millis(500));
+        let mut sbox = new_sandbox(Some(cfg));
+        let usage = sbox.usage.clone();
+        assert!(!usage.is_busy());
+
+        let call = thread::spawn(move || {
+            let res = sbox.call_guest_function_by_name("Spin", ReturnType::Void, None);
+            (sbox, res)
+        });
+        let start = Instant::now();
+        while !usage.is_busy() {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "the sandbox never became busy"
+            );
+            thread::yield_now();
+        }
+        let (_sbox, res) = call.join().unwrap();
+        assert!(matches!(
+            res,
+            Err(HyperlightError::ExecutionCanceledByHost())
+        ));
+
+        // the sandbox is idle again, from when the call finished
+        assert!(!usage.is_busy());
+        assert!(usage.idle_time(Instant::now()) < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn trim_memory() {
+        let sbox = new_sandbox(None);
+        let func = Box::new(|call_ctx: &mut MultiUseGuestCallContext| {
+            call_ctx.call(
+                "FillCache",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(64 * 1024)]),
+            )?;
+            Ok(())
+        });
+        let mut sbox = sbox.evolve(MultiUseContextCallback::from(func)).unwrap();
+        let cache_size = |sbox: &mut MultiUseSandbox| {
+            sbox.call_guest_function_by_name("GetCacheSize", ReturnType::Int, None)
+                .unwrap()
+        };
+        assert_eq!(ReturnValue::Int(64 * 1024), cache_size(&mut sbox));
+
+        // what the guest released stays released after later calls
+        assert!(sbox.trim_memory().unwrap());
+        assert_eq!(ReturnValue::Int(0), cache_size(&mut sbox));
+        assert_eq!(ReturnValue::Int(0), cache_size(&mut sbox));
+
+        // crossing a memory pressure threshold trims the memory after the
+        // next call
+        let func = Box::new(|call_ctx: &mut MultiUseGuestCallContext| {
+            call_ctx.call(
+                "FillCache",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(32 * 1024)]),
+            )?;
+            Ok(())
+        });
+        let mut sbox = sbox.evolve(MultiUseContextCallback::from(func)).unwrap();
+        sbox.usage.request_trim();
+        assert_eq!(ReturnValue::Int(32 * 1024), cache_size(&mut sbox));
+        assert!(!sbox.usage.take_trim_request());
+        assert_eq!(ReturnValue::Int(0), cache_size(&mut sbox));
+
+        // hibernating trims the memory first
+        let func = Box::new(|call_ctx: &mut MultiUseGuestCallContext| {
+            call_ctx.call(
+                "FillCache",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(16)]),
+            )?;
+            Ok(())
+        });
+        let mut sbox = sbox.evolve(MultiUseContextCallback::from(func)).unwrap();
+        assert_eq!(ReturnValue::Int(16), cache_size(&mut sbox));
+        let dir = tempfile::tempdir().unwrap();
+        sbox.hibernate(dir.path()).unwrap();
+        assert_eq!(ReturnValue::Int(0), cache_size(&mut sbox));
+
+        // guests that didn't register the function aren't asked
+        let path = callback_guest_as_string().unwrap();
+        let mut sbox: MultiUseSandbox =
+            UninitializedSandbox::new(GuestBinary::FilePath(path), None, None, None)
+                .unwrap()
+                .evolve(Noop::default())
+                .unwrap();
+        assert!(!sbox.trim_memory().unwrap());
+    }
+
+    #[test]
+    fn trim_requests_wait_for_standalone_calls() {
+        let sbox = new_sandbox(None);
+        let func = Box::new(|call_ctx: &mut MultiUseGuestCallContext| {
+            call_ctx.call(
+                "FillCache",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(64 * 1024)]),
+            )?;
+            Ok(())
+        });
+        let sbox = sbox.evolve(MultiUseContextCallback::from(func)).unwrap();
+
+        // a trim requested in a call context doesn't keep the context's
+        // state as the snapshot
+        let usage = sbox.usage.clone();
+        let mut ctx = sbox.new_call_context();
+        ctx.call(
+            "FillCache",
+            ReturnType::Int,
+            Some(vec![ParameterValue::Int(16)]),
+        )
+        .unwrap();
+        usage.request_trim();
+        assert_eq!(
+            ReturnValue::Int(16),
+            ctx.call("GetCacheSize", ReturnType::Int, None).unwrap()
+        );
+        let mut sbox = ctx.finish().unwrap();
+        let cache_size = |sbox: &mut MultiUseSandbox| {
+            sbox.call_guest_function_by_name("GetCacheSize", ReturnType::Int, None)
+                .unwrap()
+        };
+        assert_eq!(ReturnValue::Int(64 * 1024), cache_size(&mut sbox));
+
+        // the trim waited for that call
+        assert!(!sbox.usage.take_trim_request());
+        assert_eq!(ReturnValue::Int(0), cache_size(&mut sbox));
+    }
+
+    #[test]
+    fn write_and_restore_snapshots() {
+        let add_to_static = |ctx: &mut MultiUseGuestCallContext| -> crate::Result<()> {
+            ctx.call(
+                "AddToStatic",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(5)]),
+            )?;
+            Ok(())
+        };
+        let mut encoder = SnapshotEncoder::default();
+        let mut snapshots = Vec::new();
+
+        let mut sbox = new_sandbox(None);
+        for _ in 0..2 {
+            sbox = sbox
+                .evolve(MultiUseContextCallback::from(add_to_static))
+                .unwrap();
+            let mut snapshot = Vec::new();
+            sbox.write_snapshot(&mut encoder, &mut snapshot).unwrap();
+            snapshots.push(snapshot);
+        }
+        assert!(snapshots[1].len() < snapshots[0].len());
+
+        // restore into another sandbox from the same guest binary
+        let mut sbox = new_sandbox(None);
+        let mut decoder = sbox.snapshot_decoder();
+        for snapshot in &snapshots {
+            decoder.apply(snapshot.as_slice()).unwrap();
+        }
+        sbox.restore_snapshot(&decoder).unwrap();
+
+        // a sandbox with a different amount of memory rejects them
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_heap_size(0x123_000);
+        let res = new_sandbox(Some(cfg))
+            .snapshot_decoder()
+            .apply(snapshots[0].as_slice());
+        assert!(matches!(res, Err(HyperlightError::InvalidSnapshotFile(_))));
+        for _ in 0..2 {
+            let res = sbox
+                .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+                .unwrap();
+            assert_eq!(ReturnValue::Int(10), res);
+        }
+    }
+
+    #[test]
+    fn lenient_parameter_coercion() {
+        let configured_sandbox = |lenient: bool| -> MultiUseSandbox {
+            let mut cfg = SandboxConfiguration::default();
+            cfg.set_lenient_parameter_coercion(lenient);
+            new_sandbox(Some(cfg))
+        };
+        let args = || Some(vec![ParameterValue::Float(1.5)]);
+
+        let res = configured_sandbox(false).call_guest_function_by_name(
+            "EchoDouble",
+            ReturnType::Double,
+            args(),
+        );
+        assert!(matches!(
+            res,
+            Err(HyperlightError::GuestFunctionParameterTypeMismatch(
+                _,
+                _,
+                _,
+                0
+            ))
+        ));
+
+        let res = configured_sandbox(true)
+            .call_guest_function_by_name("EchoDouble", ReturnType::Double, args())
+            .unwrap();
+        assert_eq!(ReturnValue::Double(1.5), res);
+    }
+
+    #[test]
+    fn extended_cpu_state() {
+        let configured_sandbox = |enabled: bool| -> MultiUseSandbox {
+            let mut cfg = SandboxConfiguration::default();
+            cfg.set_extended_cpu_state(enabled);
+            new_sandbox(Some(cfg))
+        };
+        let args = || {
+            Some(vec![
+                ParameterValue::Double(1.5),
+                ParameterValue::Double(2.25),
+            ])
+        };
+
+        let res = configured_sandbox(false).call_guest_function_by_name(
+            "AddWithAvx",
+            ReturnType::Double,
+            args(),
+        );
+        assert!(matches!(
+            res,
+            Err(HyperlightError::GuestError(_, ref msg)) if msg == "AVX is not enabled"
+        ));
+
+        if !std::arch::is_x86_feature_detected!("avx") {
+            return;
+        }
+        let mut sbox = configured_sandbox(true);
+        // the state is enabled for every call, not just the first
+        for _ in 0..2 {
+            let res = sbox
+                .call_guest_function_by_name("AddWithAvx", ReturnType::Double, args())
+                .unwrap();
+            assert_eq!(ReturnValue::Double(3.75), res);
+        }
+    }
+
+    #[test]
+    fn extended_cpu_state_is_reset_between_calls() {
+        if !std::arch::is_x86_feature_detected!("avx") {
+            return;
+        }
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_extended_cpu_state(true);
+        let mut sbox = new_sandbox(Some(cfg));
+
+        // each call sees the upper lanes zeroed, not the value the previous
+        // call left in them
+        for value in [1.5, 2.5] {
+            let res = sbox
+                .call_guest_function_by_name(
+                    "SwapYmmUpper",
+                    ReturnType::Double,
+                    Some(vec![ParameterValue::Double(value)]),
+                )
+                .unwrap();
+            assert_eq!(ReturnValue::Double(0.0), res);
+        }
+    }
+
+    #[test]
+    #[cfg(kvm)]
+    fn cpuid_configuration() {
+        use crate::sandbox::hypervisor::{get_available_hypervisor, HypervisorType};
+        use crate::sandbox::{CpuFeatures, CpuidConfiguration};
+
+        if *get_available_hypervisor() != Some(HypervisorType::Kvm) {
+            return;
+        }
+
+        let mut cpuid = CpuidConfiguration::default();
+        cpuid.hide_features(CpuFeatures::RDRAND);
+        cpuid.set_vendor("HyperlightVM").unwrap();
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_cpuid(cpuid);
+        let mut sbox = new_sandbox(Some(cfg));
+
+        let mut guest_cpuid = |leaf: u32| -> Vec<u32> {
+            let res = sbox
+                .call_guest_function_by_name(
+                    "Cpuid",
+                    ReturnType::VecBytes,
+                    Some(vec![ParameterValue::UInt(leaf), ParameterValue::UInt(0)]),
+                )
+                .unwrap();
+            let ReturnValue::VecBytes(bytes) = res else {
+                panic!("Cpuid returned {:?}", res);
+            };
+            bytes
+                .chunks(4)
+                .map(|r| u32::from_le_bytes(r.try_into().unwrap()))
+                .collect()
+        };
+
+        let leaf0 = guest_cpuid(0);
+        let vendor: Vec<u8> = [leaf0[1], leaf0[3], leaf0[2]]
+            .iter()
+            .flat_map(|r| r.to_le_bytes())
+            .collect();
+        assert_eq!(b"HyperlightVM", vendor.as_slice());
+        assert_eq!(0, guest_cpuid(1)[2] & (1 << 30));
+    }
+
+    #[test]
+    fn entropy_policy() {
+        use crate::sandbox::EntropyPolicy;
+
+        let sandbox = |policy: EntropyPolicy| {
+            let mut cfg = SandboxConfiguration::default();
+            cfg.set_entropy_policy(policy);
+            let path = simple_guest_as_string().unwrap();
+            UninitializedSandbox::new(GuestBinary::FilePath(path), Some(cfg), None, None)
+                .unwrap()
+                .evolve(Noop::default())
+        };
+        // the guest gets the first random number of the sequence in each
+        // call, whether it asks the guest library for it or executes
+        // `RDRAND` or `RDSEED` itself
+        let random_numbers = |sbox: &mut MultiUseSandbox| {
+            [
+                sbox.call_guest_function_by_name("GetRandom", ReturnType::ULong, None),
+                sbox.call_guest_function_by_name(
+                    "ExecuteRdrand",
+                    ReturnType::ULong,
+                    Some(vec![ParameterValue::Bool(false)]),
+                ),
+                sbox.call_guest_function_by_name(
+                    "ExecuteRdrand",
+                    ReturnType::ULong,
+                    Some(vec![ParameterValue::Bool(true)]),
+                ),
+            ]
+        };
+
+        // only KVM on Intel processors can make the guest fault when it
+        // executes `RDRAND` or `RDSEED`, other hosts refuse to create the
+        // sandbox
+        let mut sbox = match sandbox(EntropyPolicy::Deterministic(42)) {
+            Err(HyperlightError::EntropyInstructionsNotTrapped(_)) => return,
+            sbox => sbox.unwrap(),
+        };
+
+        // sandboxes with the same seed get the same random numbers
+        let first = random_numbers(&mut sbox).map(Result::unwrap);
+        assert!(first.iter().all(|value| *value == first[0]), "{:?}", first);
+        let second = random_numbers(&mut sandbox(EntropyPolicy::Deterministic(42)).unwrap());
+        assert_eq!(first, second.map(Result::unwrap));
+        let other = random_numbers(&mut sandbox(EntropyPolicy::Deterministic(43)).unwrap());
+        assert_ne!(first, other.map(Result::unwrap));
+
+        for res in random_numbers(&mut sandbox(EntropyPolicy::Trap).unwrap()) {
+            assert!(
+                matches!(res, Err(HyperlightError::GuestError(_, _))),
+                "{:?}",
+                res
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(kvm)]
+    fn guest_time() {
+        use std::time::Duration;
+
+        use crate::sandbox::hypervisor::{get_available_hypervisor, HypervisorType};
+        use crate::sandbox::GuestTime;
+
+        if *get_available_hypervisor() != Some(HypervisorType::Kvm) {
+            return;
+        }
+
+        let sandbox = |guest_time: GuestTime| -> MultiUseSandbox {
+            let mut cfg = SandboxConfiguration::default();
+            cfg.set_guest_time(guest_time);
+            new_sandbox(Some(cfg))
+        };
+        let read_tsc = |sbox: &mut MultiUseSandbox| -> u64 {
+            match sbox.call_guest_function_by_name("ReadTsc", ReturnType::ULong, None) {
+                Ok(ReturnValue::ULong(tsc)) => tsc,
+                res => panic!("ReadTsc returned {:?}", res),
+            }
+        };
+
+        // the TSC doesn't advance while the host sleeps between calls,
+        // which would take at least 100 million cycles at 1 GHz
+        let mut sbox = sandbox(GuestTime::Virtual { tsc_khz: None });
+        let first = read_tsc(&mut sbox);
+        std::thread::sleep(Duration::from_millis(100));
+        let second = read_tsc(&mut sbox);
+        assert!(second > first);
+        assert!(second - first < 50_000_000, "{} - {}", second, first);
+
+        let mut sbox = sandbox(GuestTime::Frozen(1 << 40));
+        for _ in 0..2 {
+            let tsc = read_tsc(&mut sbox);
+            assert!((1 << 40..(1 << 40) + 50_000_000).contains(&tsc), "{}", tsc);
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn mmio_host_call_transport() {
+        use crate::sandbox::hypervisor::{get_available_hypervisor, HypervisorType};
+        use crate::sandbox::HostCallTransport;
+
+        let Some(hypervisor) = get_available_hypervisor() else {
+            return;
+        };
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_host_call_transport(HostCallTransport::Mmio);
+
+        // only KVM supports the doorbell, the others must refuse it rather
+        // than leave the guest's host calls unanswered
+        #[cfg(kvm)]
+        let supported = *hypervisor == HypervisorType::Kvm;
+        #[cfg(not(kvm))]
+        let supported = false;
+        if !supported {
+            let path = simple_guest_as_string().unwrap();
+            let res = UninitializedSandbox::new(GuestBinary::FilePath(path), Some(cfg), None, None)
+                .unwrap()
+                .evolve(Noop::<UninitializedSandbox, MultiUseSandbox>::default());
+            assert!(res.is_err());
+            return;
+        }
+        let mut sbox = new_sandbox(Some(cfg));
+
+        // printing calls the HostPrint host function
+        let res = sbox
+            .call_guest_function_by_name(
+                "PrintOutput",
+                ReturnType::Int,
+                Some(vec![ParameterValue::String("doorbell\n".to_string())]),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::Int(9), res);
+    }
+
+    #[test]
+    #[cfg(kvm)]
+    fn msr_policy() {
+        use crate::sandbox::hypervisor::{get_available_hypervisor, HypervisorType};
+        use crate::sandbox::{MsrAccess, MsrAction, MsrPolicy};
+
+        if *get_available_hypervisor() != Some(HypervisorType::Kvm) {
+            return;
+        }
+
+        const IA32_TIME_STAMP_COUNTER: u32 = 0x10;
+        const IA32_SYSENTER_CS: u32 = 0x174;
+        let mut policy = MsrPolicy::default();
+        policy
+            .set_action(IA32_TIME_STAMP_COUNTER, MsrAction::EmulateZero)
+            .unwrap();
+        policy
+            .set_action(IA32_SYSENTER_CS, MsrAction::Deny)
+            .unwrap();
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_msr_policy(policy);
+        let mut sbox = new_sandbox(Some(cfg));
+
+        let res = sbox
+            .call_guest_function_by_name(
+                "ReadMsr",
+                ReturnType::ULong,
+                Some(vec![ParameterValue::UInt(IA32_TIME_STAMP_COUNTER)]),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::ULong(0), res);
+
+        let res = sbox.call_guest_function_by_name(
+            "WriteMsr",
+            ReturnType::Void,
+            Some(vec![
+                ParameterValue::UInt(IA32_SYSENTER_CS),
+                ParameterValue::ULong(8),
+            ]),
+        );
+        assert!(matches!(
+            res,
+            Err(HyperlightError::GuestMsrAccessDenied(
+                IA32_SYSENTER_CS,
+                MsrAccess::Write(8)
+            ))
+        ));
+    }
+
+    #[test]
+    fn msr_policy_without_passthrough() {
+        use crate::sandbox::hypervisor::{get_available_hypervisor, HypervisorType};
+        use crate::sandbox::{MsrAccess, MsrAction, MsrPolicy};
+
+        const IA32_TIME_STAMP_COUNTER: u32 = 0x10;
+        const IA32_SYSENTER_CS: u32 = 0x174;
+        let configured_sandbox = |policy: MsrPolicy| -> crate::Result<MultiUseSandbox> {
+            let mut cfg = SandboxConfiguration::default();
+            cfg.set_msr_policy(policy);
+            let path = simple_guest_as_string().unwrap();
+            UninitializedSandbox::new(GuestBinary::FilePath(path), Some(cfg), None, None)?
+                .evolve(Noop::default())
+        };
+        let mut policy = MsrPolicy::new(MsrAction::Deny);
+        policy
+            .set_action(IA32_TIME_STAMP_COUNTER, MsrAction::EmulateZero)
+            .unwrap();
+
+        // WHP can't enforce any policy, and no hypervisor may silently
+        // ignore one
+        let Some(hypervisor) = get_available_hypervisor() else {
+            return;
+        };
+        let is_mshv = match hypervisor {
+            #[cfg(kvm)]
+            HypervisorType::Kvm => false,
+            #[cfg(mshv)]
+            HypervisorType::Mshv => true,
+            #[cfg(target_os = "windows")]
+            HypervisorType::Whp => {
+                assert!(configured_sandbox(policy).is_err());
+                return;
+            }
+        };
+
+        let mut sbox = configured_sandbox(policy).unwrap();
+        let res = sbox
+            .call_guest_function_by_name(
+                "ReadMsr",
+                ReturnType::ULong,
+                Some(vec![ParameterValue::UInt(IA32_TIME_STAMP_COUNTER)]),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::ULong(0), res);
+        let res = sbox.call_guest_function_by_name(
+            "ReadMsr",
+            ReturnType::ULong,
+            Some(vec![ParameterValue::UInt(IA32_SYSENTER_CS)]),
+        );
+        assert!(matches!(
+            res,
+            Err(HyperlightError::GuestMsrAccessDenied(
+                IA32_SYSENTER_CS,
+                MsrAccess::Read
+            ))
+        ));
+
+        // mshv can't pass some MSRs through while intercepting the others
+        policy
+            .set_action(IA32_SYSENTER_CS, MsrAction::Passthrough)
+            .unwrap();
+        assert_eq!(is_mshv, configured_sandbox(policy).is_err());
+    }
+
+    #[test]
+    fn port_handlers() {
+        use std::sync::{Arc, Mutex};
+
+        use hyperlight_common::transport::PortMap;
+
+        // move the host call channel, so that the guest has to find it in
+        // the PEB, and handle its old port here
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_port_map(PortMap {
+            call: 0x300,
+            ..PortMap::DEFAULT
+        });
+        let path = simple_guest_as_string().unwrap();
+        let mut usbox =
+            UninitializedSandbox::new(GuestBinary::FilePath(path), Some(cfg), None, None).unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        usbox
+            .register_port_handler(PortMap::DEFAULT.call, move |byte| {
+                sink.lock().unwrap().push(byte);
+                Ok(())
+            })
+            .unwrap();
+        assert!(usbox.register_port_handler(0x300, |_| Ok(())).is_err());
+        let mut sbox: MultiUseSandbox = usbox.evolve(Noop::default()).unwrap();
+
+        let res = sbox
+            .call_guest_function_by_name(
+                "PrintOutput",
+                ReturnType::Int,
+                Some(vec![ParameterValue::String("hello\n".to_string())]),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::Int(6), res);
+
+        sbox.call_guest_function_by_name(
+            "WriteToPort",
+            ReturnType::Void,
+            Some(vec![
+                ParameterValue::UInt(PortMap::DEFAULT.call as u32),
+                ParameterValue::VecBytes(vec![1, 2, 3]),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(vec![1, 2, 3], *received.lock().unwrap());
+
+        // a port without a handler fails the call
+        let res = sbox.call_guest_function_by_name(
+            "WriteToPort",
+            ReturnType::Void,
+            Some(vec![
+                ParameterValue::UInt(200),
+                ParameterValue::VecBytes(vec![1]),
+            ]),
+        );
+        assert!(res.is_err());
+
+        // two channels on the same port are rejected
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_port_map(PortMap {
+            call: PortMap::DEFAULT.log,
+            ..PortMap::DEFAULT
+        });
+        let path = simple_guest_as_string().unwrap();
+        let res = UninitializedSandbox::new(GuestBinary::FilePath(path), Some(cfg), None, None);
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn call_trace() {
+        use crate::func::call_trace::{CallTrace, HostCallOutcome, TraceEvent};
+
+        let mut sbox = new_sandbox(None);
+        sbox.start_trace().unwrap();
+        sbox.call_guest_function_by_name(
+            "PrintOutput",
+            ReturnType::Int,
+            Some(vec![ParameterValue::String("traced\n".to_string())]),
+        )
+        .unwrap();
+        // the guest's state is kept between the calls of a call context
+        let mut ctx = sbox.new_call_context();
+        for _ in 0..2 {
+            ctx.call(
+                "AddToStatic",
+                ReturnType::Int,
+                Some(vec![ParameterValue::Int(5)]),
+            )
+            .unwrap();
+        }
+        let mut sbox = ctx.finish().unwrap();
+        let trace = sbox.finish_trace().unwrap().unwrap();
+        assert_eq!(None, sbox.finish_trace().unwrap());
+
+        assert_eq!(5, trace.events.len());
+        assert_eq!(TraceEvent::Reset, trace.events[1]);
+        let calls: Vec<_> = trace.guest_calls().collect();
+        assert_eq!(3, calls.len());
+        assert_eq!("HostPrint", calls[0].host_calls[0].function_name);
+        assert_eq!(
+            HostCallOutcome::Returned(ReturnValue::Int(7)),
+            calls[0].host_calls[0].outcome
+        );
+        assert_eq!(Ok(ReturnValue::Int(10)), calls[2].result);
+
+        // the trace survives being written to a file, and replays on a new
+        // sandbox
+        let mut file = Vec::new();
+        trace.write_to(&mut file).unwrap();
+        let trace = CallTrace::read_from(file.as_slice()).unwrap();
+        let mut sbox = new_sandbox(None);
+        sbox.replay_trace(&trace).unwrap();
+
+        // the guest returns what the host function is traced to return
+        let mut changed = trace.clone();
+        if let TraceEvent::GuestCall(call) = &mut changed.events[0] {
+            call.host_calls[0].outcome = HostCallOutcome::Returned(ReturnValue::Int(8));
+        }
+        let res = sbox.replay_trace(&changed);
+        assert!(matches!(res, Err(HyperlightError::TraceDivergence(0, _))));
+
+        // and is checked against the traced host function calls
+        let mut changed = trace.clone();
+        if let TraceEvent::GuestCall(call) = &mut changed.events[0] {
+            call.args = Some(vec![ParameterValue::String("changed\n".to_string())]);
+        }
+        let res = sbox.replay_trace(&changed);
+        assert!(matches!(res, Err(HyperlightError::TraceDivergence(0, _))));
+
+        // replaying doesn't leave the guest's state behind
+        sbox.replay_trace(&trace).unwrap();
+    }
+
+    #[test]
+    fn host_handles() {
+        use std::sync::{Arc, Mutex};
+
+        use crate::func::{Handle, HostFunction1};
+
+        let path = simple_guest_as_string().unwrap();
+        let mut usbox =
+            UninitializedSandbox::new(GuestBinary::FilePath(path), None, None, None).unwrap();
+        let handles = usbox.host_handles().unwrap();
+        let make_handles = handles.clone();
+        Arc::new(Mutex::new(move |text: String| make_handles.insert(text)))
+            .register(&mut usbox, "HostMakeObject")
+            .unwrap();
+        let length_handles = handles.clone();
+        Arc::new(Mutex::new(move |handle: Handle| {
+            length_handles.with(handle, |text: &mut String| text.len() as u64)
+        }))
+        .register(&mut usbox, "HostObjectLength")
+        .unwrap();
+        let sbox: MultiUseSandbox = usbox.evolve(Noop::default()).unwrap();
+
+        // the guest holds on to the handle between the calls of a call
+        // context, and the object stays on the host
+        let mut ctx = sbox.new_call_context();
+        let handle = ctx
+            .call(
+                "MakeHostHandle",
+                ReturnType::ULong,
+                Some(vec![ParameterValue::String("hello".to_string())]),
+            )
+            .unwrap();
+        let ReturnValue::ULong(id) = handle else {
+            panic!("expected a ULong, got {:?}", handle);
+        };
+        let res = ctx
+            .call(
+                "HostHandleLength",
+                ReturnType::ULong,
+                Some(vec![ParameterValue::ULong(id)]),
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::ULong(5), res);
+        assert_eq!(1, handles.len().unwrap());
+
+        // the object is dropped when the guest's state is restored, and
+        // the handle doesn't work anymore
+        let mut sbox = ctx.finish().unwrap();
+        assert!(handles.is_empty().unwrap());
+        let res = sbox.call_guest_function_by_name(
+            "HostHandleLength",
+            ReturnType::ULong,
+            Some(vec![ParameterValue::ULong(id)]),
+        );
+        assert!(res.is_err());
     }
 }
@@ -0,0 +1,361 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! On Linux the thread running the vCPU is sent a real-time signal, which
+//! makes the hypervisor return from running the vCPU. A signal that
+//! arrives just before the thread enters the vCPU is missed, so the signal
+//! is sent again, every `InterruptPolicy::retry_interval` to begin with,
+//! until the vCPU stops. On Windows the vCPU is cancelled with
+//! `WHvCancelRunVirtualProcessor`.
+//!
+//! A guest that is running a host function can't be interrupted until the
+//! host function returns. If the guest hasn't stopped within the maximum
+//! time to wait for a cancellation (see
+//! `SandboxConfiguration::set_max_execution_cancel_wait_time`), the
+//! callback set with `UninitializedSandbox::set_interrupt_failure_callback`
+//! is called, and the policy's `InterruptEscalation` decides what happens
+//! next.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::HyperlightError;
+
+/// What happens when a guest hasn't stopped within the maximum time to wait
+/// for a cancellation
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub enum InterruptEscalation {
+    /// Stop interrupting the guest, and fail the guest function call with
+    /// `HyperlightError::GuestExecutionHungOnHostFunctionCall`. The sandbox
+    /// can't be used again.
+    #[default]
+    Fail,
+    /// Keep interrupting the guest, at most `max_retry_interval` apart, for
+    /// up to `max_keep_interrupting_time` longer. The guest function call
+    /// fails with `HyperlightError::ExecutionCanceledByHost` once it has
+    /// stopped, and the sandbox can be used again. If it still hasn't
+    /// stopped by then, the call is escalated as with `Recreate`.
+    KeepInterrupting,
+    /// Stop interrupting the guest, kill the thread running it along with
+    /// the sandbox's virtual machine, and recreate the sandbox from
+    /// scratch, as `MultiUseSandbox::recreate` does. The guest function
+    /// call fails with `HyperlightError::GuestExecutionHungOnHostFunctionCall`
+    /// and the sandbox can be used again. The host function the guest
+    /// called is left to return on the killed thread, after which the
+    /// thread exits.
+    Recreate,
+}
+
+/// How a running guest is interrupted when a guest function call is
+/// cancelled
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InterruptPolicy {
+    /// How long to wait after the first interruption before interrupting
+    /// the guest again
+    pub retry_interval: Duration,
+    /// How much longer to wait before each further interruption. A factor
+    /// of 1 interrupts the guest every `retry_interval`.
+    pub backoff_factor: u32,
+    /// The longest time to wait between two interruptions
+    pub max_retry_interval: Duration,
+    /// What happens when the guest hasn't stopped within the maximum time
+    /// to wait for a cancellation
+    pub escalation: InterruptEscalation,
+    /// How much longer than the maximum time to wait for a cancellation
+    /// the guest is interrupted with `InterruptEscalation::KeepInterrupting`
+    /// before it is escalated as with `InterruptEscalation::Recreate`
+    pub max_keep_interrupting_time: Duration,
+}
+
+impl InterruptPolicy {
+    /// The default time to wait between interruptions
+    pub const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_micros(500);
+    /// The default longest time to wait between interruptions
+    pub const DEFAULT_MAX_RETRY_INTERVAL: Duration = Duration::from_millis(10);
+    /// The default time to keep interrupting a guest with
+    /// `InterruptEscalation::KeepInterrupting`
+    pub const DEFAULT_MAX_KEEP_INTERRUPTING_TIME: Duration = Duration::from_secs(10);
+}
+
+impl Default for InterruptPolicy {
+    fn default() -> Self {
+        Self {
+            retry_interval: Self::DEFAULT_RETRY_INTERVAL,
+            backoff_factor: 1,
+            max_retry_interval: Self::DEFAULT_MAX_RETRY_INTERVAL,
+            escalation: InterruptEscalation::default(),
+            max_keep_interrupting_time: Self::DEFAULT_MAX_KEEP_INTERRUPTING_TIME,
+        }
+    }
+}
+
+/// A guest that hasn't stopped within the maximum time to wait for a
+/// cancellation
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InterruptFailure {
+    /// How many times the guest was interrupted
+    pub attempts: u32,
+    /// How long the host has waited for the guest to stop
+    pub waited: Duration,
+}
+
+type InterruptFailureHandler = Arc<dyn Fn(&InterruptFailure) + Send + Sync>;
+
+/// The callback for guests that fail to stop when interrupted, shared by
+/// every sandbox created from the same source
+#[derive(Clone, Default)]
+pub(crate) struct InterruptFailureCallback(Option<InterruptFailureHandler>);
+
+impl std::fmt::Debug for InterruptFailureCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InterruptFailureCallback")
+            .field("set", &self.0.is_some())
+            .finish()
+    }
+}
+
+impl InterruptFailureCallback {
+    pub(crate) fn new(callback: impl Fn(&InterruptFailure) + Send + Sync + 'static) -> Self {
+        Self(Some(Arc::new(callback)))
+    }
+
+    /// Call the callback, if there is one, with `failure`
+    pub(crate) fn notify(&self, failure: &InterruptFailure) {
+        if let Some(callback) = &self.0 {
+            callback(failure);
+        }
+    }
+}
+
+/// The interruptions of a guest whose function call is being cancelled,
+/// and how long the host has waited for it to stop
+pub(crate) struct InterruptAttempts {
+    policy: InterruptPolicy,
+    next_interval: Duration,
+    failure: InterruptFailure,
+    reported: bool,
+}
+
+impl InterruptAttempts {
+    pub(crate) fn new(policy: InterruptPolicy) -> Self {
+        Self {
+            policy,
+            next_interval: policy.retry_interval,
+            failure: InterruptFailure {
+                attempts: 0,
+                waited: Duration::ZERO,
+            },
+            reported: false,
+        }
+    }
+
+    /// Record an interruption of the guest, and return how long to wait
+    /// before checking whether it has stopped: `retry_interval` after the
+    /// first interruption, multiplied by `backoff_factor` after each
+    /// further one, up to `max_retry_interval`
+    pub(crate) fn record_attempt(&mut self) -> Duration {
+        let interval = self.next_interval;
+        let max_retry_interval = self
+            .policy
+            .max_retry_interval
+            .max(self.policy.retry_interval);
+        self.next_interval = interval
+            .checked_mul(self.policy.backoff_factor.max(1))
+            .map_or(max_retry_interval, |next| next.min(max_retry_interval));
+        self.failure.attempts = self.failure.attempts.saturating_add(1);
+        interval
+    }
+
+    /// Record waiting `duration` for the guest to stop
+    pub(crate) fn record_wait(&mut self, duration: Duration) {
+        self.failure.waited = self.failure.waited.saturating_add(duration);
+    }
+
+    /// Whether to stop interrupting a guest that hasn't stopped yet. Once
+    /// the host has waited `max_wait` for it, `callback` is called, only
+    /// the first time, and the policy's escalation decides.
+    pub(crate) fn give_up(
+        &mut self,
+        max_wait: Duration,
+        callback: &InterruptFailureCallback,
+    ) -> bool {
+        if self.failure.waited < max_wait {
+            return false;
+        }
+        if !self.reported {
+            self.reported = true;
+            callback.notify(&self.failure);
+        }
+        match self.policy.escalation {
+            InterruptEscalation::Fail | InterruptEscalation::Recreate => true,
+            InterruptEscalation::KeepInterrupting => {
+                self.failure.waited
+                    >= max_wait.saturating_add(self.policy.max_keep_interrupting_time)
+            }
+        }
+    }
+}
+
+/// Whether a sandbox whose guest function call failed with `error` has to
+/// be recreated under `policy`, because the thread running its guest is
+/// stuck in a host function
+pub(crate) fn needs_recreating(policy: &InterruptPolicy, error: &HyperlightError) -> bool {
+    matches!(
+        error,
+        HyperlightError::GuestExecutionHungOnHostFunctionCall()
+    ) && policy.escalation != InterruptEscalation::Fail
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::{
+        needs_recreating, InterruptAttempts, InterruptEscalation, InterruptFailure,
+        InterruptFailureCallback, InterruptPolicy,
+    };
+    use crate::HyperlightError;
+
+    fn retry_intervals(policy: InterruptPolicy, count: usize) -> Vec<Duration> {
+        let mut attempts = InterruptAttempts::new(policy);
+        (0..count).map(|_| attempts.record_attempt()).collect()
+    }
+
+    #[test]
+    fn retry_intervals_back_off() {
+        assert_eq!(
+            vec![Duration::from_micros(500); 3],
+            retry_intervals(InterruptPolicy::default(), 3)
+        );
+
+        let policy = InterruptPolicy {
+            retry_interval: Duration::from_millis(1),
+            backoff_factor: 2,
+            max_retry_interval: Duration::from_millis(5),
+            ..Default::default()
+        };
+        let intervals: Vec<_> = retry_intervals(policy, 5)
+            .iter()
+            .map(|interval| interval.as_millis())
+            .collect();
+        assert_eq!(vec![1, 2, 4, 5, 5], intervals);
+
+        // A maximum below the first interval doesn't shorten it
+        let policy = InterruptPolicy {
+            retry_interval: Duration::from_millis(3),
+            backoff_factor: 0,
+            max_retry_interval: Duration::from_millis(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![Duration::from_millis(3); 2],
+            retry_intervals(policy, 2)
+        );
+    }
+
+    #[test]
+    fn give_up_after_max_wait() {
+        let max_wait = Duration::from_millis(10);
+        for (escalation, give_up) in [
+            (InterruptEscalation::Fail, true),
+            (InterruptEscalation::Recreate, true),
+            (InterruptEscalation::KeepInterrupting, false),
+        ] {
+            let reported = Arc::new(AtomicU32::new(0));
+            let callback = {
+                let reported = reported.clone();
+                InterruptFailureCallback::new(move |failure| {
+                    assert_eq!(20, failure.attempts);
+                    assert_eq!(Duration::from_millis(10), failure.waited);
+                    reported.fetch_add(1, Ordering::SeqCst);
+                })
+            };
+            let mut attempts = InterruptAttempts::new(InterruptPolicy {
+                escalation,
+                ..Default::default()
+            });
+            while !attempts.give_up(max_wait, &callback) {
+                let interval = attempts.record_attempt();
+                attempts.record_wait(interval);
+                if attempts.failure.attempts == 25 {
+                    break;
+                }
+            }
+            assert_eq!(give_up, attempts.failure.attempts == 20);
+            assert_eq!(1, reported.load(Ordering::SeqCst));
+        }
+    }
+
+    #[test]
+    fn keep_interrupting_is_limited() {
+        let policy = InterruptPolicy {
+            escalation: InterruptEscalation::KeepInterrupting,
+            max_keep_interrupting_time: Duration::from_millis(5),
+            ..Default::default()
+        };
+        let mut attempts = InterruptAttempts::new(policy);
+        let callback = InterruptFailureCallback::default();
+        let max_wait = Duration::from_millis(10);
+        while !attempts.give_up(max_wait, &callback) {
+            let interval = attempts.record_attempt();
+            attempts.record_wait(interval);
+        }
+        assert_eq!(Duration::from_millis(15), attempts.failure.waited);
+        assert_eq!(30, attempts.failure.attempts);
+    }
+
+    #[test]
+    fn hung_guests_are_recreated_unless_the_call_fails() {
+        let hung = HyperlightError::GuestExecutionHungOnHostFunctionCall();
+        let mut policy = InterruptPolicy::default();
+        assert!(!needs_recreating(&policy, &hung));
+        for escalation in [
+            InterruptEscalation::KeepInterrupting,
+            InterruptEscalation::Recreate,
+        ] {
+            policy.escalation = escalation;
+            assert!(needs_recreating(&policy, &hung));
+            assert!(!needs_recreating(
+                &policy,
+                &HyperlightError::ExecutionCanceledByHost()
+            ));
+        }
+    }
+
+    #[test]
+    fn failure_callback() {
+        let failure = InterruptFailure {
+            attempts: 20,
+            waited: Duration::from_millis(10),
+        };
+        InterruptFailureCallback::default().notify(&failure);
+
+        let calls = Arc::new(AtomicU32::new(0));
+        let callback = {
+            let calls = calls.clone();
+            InterruptFailureCallback::new(move |f| {
+                assert_eq!(20, f.attempts);
+                calls.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+        callback.clone().notify(&failure);
+        callback.notify(&failure);
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,115 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use hyperlight_common::transport::PortMap;
+use tracing::{instrument, Span};
+
+use crate::{log_then_return, new_error, Result};
+
+type PortHandler = Box<dyn FnMut(u8) -> Result<()> + Send>;
+
+/// The handlers of the ports the guest signals that aren't in the
+/// sandbox's `PortMap`, shared between the sandbox and its outb handler
+#[derive(Clone, Default)]
+pub(crate) struct PortHandlers(Arc<Mutex<HashMap<u16, PortHandler>>>);
+
+impl std::fmt::Debug for PortHandlers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PortHandlers").finish_non_exhaustive()
+    }
+}
+
+impl PortHandlers {
+    /// Call `handler` with the byte the guest sends whenever it signals
+    /// `port`, which must not be one of `port_map`'s
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn register(
+        &self,
+        port: u16,
+        port_map: &PortMap,
+        handler: impl FnMut(u8) -> Result<()> + Send + 'static,
+    ) -> Result<()> {
+        if port >= PortMap::PORT_LIMIT {
+            log_then_return!(
+                "Port {} is not below the port limit {}",
+                port,
+                PortMap::PORT_LIMIT
+            );
+        }
+        if let Some(channel) = port_map.channel(port) {
+            log_then_return!("Port {} is the host's {:?} channel", port, channel);
+        }
+        let mut handlers = self
+            .0
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
+        if handlers.contains_key(&port) {
+            log_then_return!("Port {} already has a handler", port);
+        }
+        handlers.insert(port, Box::new(handler));
+        Ok(())
+    }
+
+    /// Pass `byte` to the handler of `port`
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn call(&self, port: u16, byte: u8) -> Result<()> {
+        let mut handlers = self
+            .0
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
+        match handlers.get_mut(&port) {
+            Some(handler) => handler(byte),
+            None => log_then_return!("Invalid OutB value: {}", port),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use hyperlight_common::transport::PortMap;
+
+    use super::PortHandlers;
+
+    #[test]
+    fn handlers_are_called_for_their_port() {
+        let handlers = PortHandlers::default();
+        let map = PortMap::default();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = received.clone();
+        handlers
+            .register(200, &map, move |byte| {
+                sink.lock().unwrap().push(byte);
+                Ok(())
+            })
+            .unwrap();
+        handlers.call(200, 1).unwrap();
+        handlers.call(200, 2).unwrap();
+        assert_eq!(vec![1, 2], *received.lock().unwrap());
+        assert!(handlers.call(201, 1).is_err());
+
+        // ports that are the host's, taken or out of range are rejected
+        assert!(handlers.register(map.call, &map, |_| Ok(())).is_err());
+        assert!(handlers.register(200, &map, |_| Ok(())).is_err());
+        assert!(handlers
+            .register(PortMap::PORT_LIMIT, &map, |_| Ok(()))
+            .is_err());
+    }
+}
@@ -0,0 +1,393 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+use tracing::{instrument, Span};
+
+use crate::{log_then_return, Result};
+
+/// The size of each event in the trace the guest sends
+const EVENT_RECORD_SIZE: usize = 2 * size_of::<u64>();
+/// The bit the guest sets in the size of the events that are frees
+const FREE_BIT: u64 = 1 << 63;
+/// The most leaks listed by `assert_no_guest_leaks!`
+const MAX_REPORTED_LEAKS: usize = 16;
+
+/// Whether a `MallocEvent` is an allocation or a free
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum MallocEventKind {
+    /// Memory was allocated
+    Alloc,
+    /// Memory was freed
+    Free,
+}
+
+/// An allocation or free a guest made
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MallocEvent {
+    /// Whether the memory was allocated or freed
+    pub kind: MallocEventKind,
+    /// The guest address of the memory
+    pub address: u64,
+    /// The size in bytes of the memory
+    pub size: u64,
+}
+
+/// Every allocation and free made during a guest function call by a guest
+/// built with the `malloc_trace` feature of `hyperlight_guest`, in the
+/// order they were made, except the allocation holding the call's result.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MallocTrace {
+    /// The allocations and frees
+    pub events: Vec<MallocEvent>,
+    /// How many events the guest dropped because its log was full. Those
+    /// are the last events of the call.
+    pub dropped_events: u64,
+}
+
+impl MallocTrace {
+    /// Whether the guest logged every allocation and free of the call, so
+    /// that `leaks` is exact
+    pub fn is_complete(&self) -> bool {
+        self.dropped_events == 0
+    }
+
+    /// The allocations made during the call that weren't freed by the end
+    /// of it, in the order they were made. Frees of memory allocated
+    /// before the call are ignored. If the trace isn't complete, memory
+    /// whose free was dropped is included.
+    pub fn leaks(&self) -> Vec<MallocEvent> {
+        // the index in `events` of the live allocation at each address
+        let mut live: HashMap<u64, usize> = HashMap::new();
+        for (index, event) in self.events.iter().enumerate() {
+            match event.kind {
+                MallocEventKind::Alloc => {
+                    live.insert(event.address, index);
+                }
+                MallocEventKind::Free => {
+                    live.remove(&event.address);
+                }
+            }
+        }
+        let mut leaks: Vec<usize> = live.into_values().collect();
+        leaks.sort_unstable();
+        leaks.into_iter().map(|index| self.events[index]).collect()
+    }
+
+    /// The bytes allocated during the call that weren't freed by the end
+    /// of it
+    pub fn leaked_bytes(&self) -> u64 {
+        self.leaks().iter().map(|leak| leak.size).sum()
+    }
+
+    /// Parse a chunk of the trace the guest sent with the
+    /// `HostRecordMallocTrace` host function
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn events_from_guest_bytes(bytes: &[u8]) -> Result<Vec<MallocEvent>> {
+        if bytes.len() % EVENT_RECORD_SIZE != 0 {
+            log_then_return!(
+                "Malloc trace of {} bytes is not made of {} byte event records",
+                bytes.len(),
+                EVENT_RECORD_SIZE
+            );
+        }
+        Ok(bytes
+            .chunks_exact(EVENT_RECORD_SIZE)
+            .map(|record| {
+                let field = |i: usize| {
+                    let mut le_bytes = [0u8; 8];
+                    le_bytes.copy_from_slice(&record[i * 8..(i + 1) * 8]);
+                    u64::from_le_bytes(le_bytes)
+                };
+                let size = field(1);
+                MallocEvent {
+                    kind: match size & FREE_BIT {
+                        0 => MallocEventKind::Alloc,
+                        _ => MallocEventKind::Free,
+                    },
+                    address: field(0),
+                    size: size & !FREE_BIT,
+                }
+            })
+            .collect())
+    }
+}
+
+/// The last malloc trace a sandbox's guest sent, shared between the
+/// sandbox and the `MallocTraceRecorder` it is attached to
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LastMallocTrace(Arc<Mutex<Option<MallocTrace>>>);
+
+impl LastMallocTrace {
+    /// Record the chunk `index` of a trace, the first of which replaces
+    /// the previous call's trace
+    pub(crate) fn record_chunk(&self, index: u64, dropped_events: u64, events: Vec<MallocEvent>) {
+        let mut last = self.lock();
+        match last.as_mut() {
+            Some(trace) if index > 0 => {
+                trace.events.extend(events);
+                trace.dropped_events = dropped_events;
+            }
+            _ => {
+                *last = Some(MallocTrace {
+                    events,
+                    dropped_events,
+                })
+            }
+        }
+    }
+
+    pub(crate) fn get(&self) -> Option<MallocTrace> {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<MallocTrace>> {
+        // A trace is only ever extended with whole chunks, so it is always
+        // valid even if a thread panicked while holding the lock
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Records the chunks the `HostRecordMallocTrace` host function receives
+/// in the trace of the sandbox attached to it, so that the sandboxes
+/// recreated from the same source, which share the host function, each
+/// keep their own trace
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MallocTraceRecorder(Arc<Mutex<RecorderState>>);
+
+#[derive(Debug, Default)]
+struct RecorderState {
+    next_id: u64,
+    /// The trace of the sandbox currently calling guest functions, and the
+    /// id it was attached with
+    attached: Option<(u64, LastMallocTrace)>,
+}
+
+impl MallocTraceRecorder {
+    /// Record the chunks received from now on in `trace`, returning the id
+    /// to detach it with
+    pub(crate) fn attach(&self, trace: LastMallocTrace) -> u64 {
+        let mut state = self.lock();
+        state.next_id += 1;
+        let id = state.next_id;
+        state.attached = Some((id, trace));
+        id
+    }
+
+    /// Stop recording chunks in the trace attached as `id`, if it is still
+    /// attached
+    pub(crate) fn detach(&self, id: u64) {
+        let mut state = self.lock();
+        if state.attached.as_ref().is_some_and(|(a, _)| *a == id) {
+            state.attached = None;
+        }
+    }
+
+    /// Record the chunk `index` of a trace in the attached sandbox's trace,
+    /// or drop it if no sandbox is attached
+    pub(crate) fn record_chunk(&self, index: u64, dropped_events: u64, events: Vec<MallocEvent>) {
+        if let Some((_, trace)) = &self.lock().attached {
+            trace.record_chunk(index, dropped_events, events);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, RecorderState> {
+        self.0
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Why `assert_no_guest_leaks!` fails for `trace`, if it does
+#[doc(hidden)]
+pub fn guest_leak_report(trace: Option<MallocTrace>) -> Option<String> {
+    let Some(trace) = trace else {
+        return Some(
+            "the guest sent no malloc trace, it must be built with the malloc_trace feature \
+             of hyperlight_guest"
+                .to_string(),
+        );
+    };
+    if !trace.is_complete() {
+        return Some(format!(
+            "the guest dropped the last {} events of its malloc trace, so its leaks can't be found",
+            trace.dropped_events
+        ));
+    }
+    let leaks = trace.leaks();
+    if leaks.is_empty() {
+        return None;
+    }
+    let mut report = format!(
+        "the guest leaked {} bytes in {} allocations:",
+        leaks.iter().map(|leak| leak.size).sum::<u64>(),
+        leaks.len()
+    );
+    for leak in leaks.iter().take(MAX_REPORTED_LEAKS) {
+        let _ = write!(report, "\n  {} bytes at {:#x}", leak.size, leak.address);
+    }
+    if leaks.len() > MAX_REPORTED_LEAKS {
+        let _ = write!(report, "\n  ...");
+    }
+    Some(report)
+}
+
+/// Assert that the last guest function call made on a `MultiUseSandbox`
+/// freed every allocation it made, other than the one holding its result.
+/// The guest must be built with the `malloc_trace` feature of
+/// `hyperlight_guest`. Fails listing the leaked allocations, or if the
+/// guest's trace of the call is incomplete. Calls whose memory is kept
+/// afterwards, e.g. with `MultiUseSandbox::run_housekeeping`, are the ones
+/// whose leaks accumulate in a long-lived sandbox.
+///
+/// ```ignore
+/// sbox.call_guest_function_by_name("Echo", ReturnType::String, args)?;
+/// hyperlight_host::assert_no_guest_leaks!(sbox);
+/// hyperlight_host::assert_no_guest_leaks!(sbox, "after echoing {}", "hi");
+/// ```
+#[macro_export]
+macro_rules! assert_no_guest_leaks {
+    ($sbox:expr) => {
+        if let Some(report) =
+            $crate::sandbox::malloc_trace::guest_leak_report($sbox.last_malloc_trace())
+        {
+            panic!("{}", report);
+        }
+    };
+    ($sbox:expr, $($arg:tt)+) => {
+        if let Some(report) =
+            $crate::sandbox::malloc_trace::guest_leak_report($sbox.last_malloc_trace())
+        {
+            panic!("{}: {}", format_args!($($arg)+), report);
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        guest_leak_report, LastMallocTrace, MallocEvent, MallocEventKind, MallocTrace,
+        MallocTraceRecorder, FREE_BIT,
+    };
+
+    fn record(address: u64, size: u64) -> Vec<u8> {
+        [address, size]
+            .iter()
+            .flat_map(|f| f.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn leaks_are_allocations_never_freed() {
+        let mut bytes = record(0x1000, 32);
+        bytes.extend(record(0x2000, 64));
+        bytes.extend(record(0x1000, 32 | FREE_BIT));
+        // freed before the call
+        bytes.extend(record(0x9000, 8 | FREE_BIT));
+        // the address is reused
+        bytes.extend(record(0x1000, 16));
+        let events = MallocTrace::events_from_guest_bytes(&bytes).unwrap();
+        assert_eq!(
+            MallocEvent {
+                kind: MallocEventKind::Free,
+                address: 0x1000,
+                size: 32
+            },
+            events[2]
+        );
+
+        let trace = LastMallocTrace::default();
+        trace.record_chunk(0, 0, events[..3].to_vec());
+        trace.record_chunk(1, 0, events[3..].to_vec());
+        let trace = trace.get().unwrap();
+        let leaks: Vec<(u64, u64)> = trace.leaks().iter().map(|l| (l.address, l.size)).collect();
+        assert_eq!(vec![(0x2000, 64), (0x1000, 16)], leaks);
+        assert_eq!(80, trace.leaked_bytes());
+
+        assert!(MallocTrace::events_from_guest_bytes(&bytes[..15]).is_err());
+    }
+
+    #[test]
+    fn first_chunk_replaces_the_last_trace() {
+        let trace = LastMallocTrace::default();
+        assert_eq!(None, trace.get());
+        let events = MallocTrace::events_from_guest_bytes(&record(0x1000, 8)).unwrap();
+        trace.record_chunk(0, 0, events);
+        trace.record_chunk(0, 3, vec![]);
+        assert_eq!(
+            Some(MallocTrace {
+                events: vec![],
+                dropped_events: 3
+            }),
+            trace.get()
+        );
+    }
+
+    #[test]
+    fn recorder_records_in_the_attached_trace() {
+        let recorder = MallocTraceRecorder::default();
+        let events = MallocTrace::events_from_guest_bytes(&record(0x1000, 8)).unwrap();
+        // chunks received with no sandbox attached are dropped
+        recorder.record_chunk(0, 0, events.clone());
+
+        let old = LastMallocTrace::default();
+        let old_id = recorder.attach(old.clone());
+        recorder.record_chunk(0, 0, events.clone());
+        // a sandbox recreated from the same source gets its own trace
+        let new = LastMallocTrace::default();
+        let new_id = recorder.attach(new.clone());
+        recorder.record_chunk(0, 0, vec![]);
+        recorder.record_chunk(1, 0, events.clone());
+        // dropping the old sandbox doesn't detach the new one
+        recorder.detach(old_id);
+        recorder.record_chunk(2, 0, events.clone());
+
+        assert_eq!(Some(events.clone()), old.get().map(|trace| trace.events));
+        assert_eq!(
+            Some(vec![events[0], events[0]]),
+            new.get().map(|trace| trace.events)
+        );
+
+        recorder.detach(new_id);
+        recorder.record_chunk(0, 0, vec![]);
+        assert_eq!(2, new.get().unwrap().events.len());
+    }
+
+    #[test]
+    fn leak_report() {
+        assert!(guest_leak_report(None).is_some());
+        assert_eq!(None, guest_leak_report(Some(MallocTrace::default())));
+        let incomplete = MallocTrace {
+            events: vec![],
+            dropped_events: 1,
+        };
+        assert!(guest_leak_report(Some(incomplete)).is_some());
+
+        let leaky = MallocTrace {
+            events: MallocTrace::events_from_guest_bytes(&record(0x1000, 24)).unwrap(),
+            dropped_events: 0,
+        };
+        assert_eq!(
+            Some("the guest leaked 24 bytes in 1 allocations:\n  24 bytes at 0x1000".to_string()),
+            guest_leak_report(Some(leaky))
+        );
+    }
+}
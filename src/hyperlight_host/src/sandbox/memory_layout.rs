@@ -0,0 +1,357 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use crate::mem::memory_region::MemoryRegionFlags;
+use crate::{new_error, Result};
+
+/// A region of guest memory whose position in the sandbox's memory
+/// layout can be chosen.
+///
+/// These regions are placed, in order, between the PEB and the guard page
+/// below the guest stack. The page tables and the guest code always come
+/// first, and the stacks always come last.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub enum LayoutRegion {
+    /// The host function definitions the guest reads
+    HostFunctionDefinitions,
+    /// The details of any host function call error
+    HostExceptionData,
+    /// The details of any guest error
+    GuestErrorData,
+    /// The buffer for data sent to the guest
+    InputData,
+    /// The buffer for data sent from the guest
+    OutputData,
+    /// The buffer for large results, which is only mapped if it has a size
+    ResultBuffer,
+    /// The data the host publishes for the guest to read, which is only
+    /// mapped if it has a size
+    ReadOnlyData,
+    /// The ring buffer the guest writes log records into, which is only
+    /// mapped if it has a size
+    GuestLogRing,
+    /// The secrets the host adds for the guest to take, which is only
+    /// mapped if it has a size
+    Secrets,
+    /// The region the guest can write code into and run it, which is only
+    /// mapped if it has a size
+    JitScratch,
+    /// The context of any guest panic
+    PanicContext,
+    /// The guest heap
+    Heap,
+}
+
+impl LayoutRegion {
+    /// The number of regions
+    pub const COUNT: usize = 12;
+
+    /// The regions in their default order
+    pub const DEFAULT_ORDER: [LayoutRegion; LayoutRegion::COUNT] = [
+        LayoutRegion::HostFunctionDefinitions,
+        LayoutRegion::HostExceptionData,
+        LayoutRegion::GuestErrorData,
+        LayoutRegion::InputData,
+        LayoutRegion::OutputData,
+        LayoutRegion::ResultBuffer,
+        LayoutRegion::ReadOnlyData,
+        LayoutRegion::GuestLogRing,
+        LayoutRegion::Secrets,
+        LayoutRegion::JitScratch,
+        LayoutRegion::PanicContext,
+        LayoutRegion::Heap,
+    ];
+
+    /// The permissions the guest has to this region unless they are set
+    /// with `MemoryLayoutBuilder::region_permissions`. The host function
+    /// definitions, host exception data and read-only data are read-only,
+    /// the JIT scratch region is executable, the heap is executable if the
+    /// `executable_heap` feature is enabled, and every other region is
+    /// readable and writable.
+    pub fn default_permissions(self) -> RegionPermissions {
+        match self {
+            LayoutRegion::HostFunctionDefinitions
+            | LayoutRegion::HostExceptionData
+            | LayoutRegion::ReadOnlyData => RegionPermissions::ReadOnly,
+            LayoutRegion::JitScratch => RegionPermissions::ReadWriteExecute,
+            LayoutRegion::Heap if cfg!(feature = "executable_heap") => {
+                RegionPermissions::ReadWriteExecute
+            }
+            _ => RegionPermissions::ReadWrite,
+        }
+    }
+
+    /// The default permissions of every region, indexed by region
+    pub(crate) fn default_permissions_table() -> [RegionPermissions; LayoutRegion::COUNT] {
+        let mut table = [RegionPermissions::ReadWrite; LayoutRegion::COUNT];
+        for region in LayoutRegion::DEFAULT_ORDER {
+            table[region as usize] = region.default_permissions();
+        }
+        table
+    }
+}
+
+/// The access the guest has to a region of its memory. The host can
+/// always read and write every region.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub enum RegionPermissions {
+    /// The guest can only read the region
+    ReadOnly,
+    /// The guest can read and write the region
+    ReadWrite,
+    /// The guest can read and write the region, and run code in it, e.g.
+    /// code it compiled at runtime
+    ReadWriteExecute,
+}
+
+impl RegionPermissions {
+    /// Whether the guest can write to the region
+    pub fn writable(self) -> bool {
+        self != RegionPermissions::ReadOnly
+    }
+
+    /// Whether the guest can run code in the region
+    pub fn executable(self) -> bool {
+        self == RegionPermissions::ReadWriteExecute
+    }
+
+    /// The flags the region is mapped into the virtual machine with
+    pub(crate) fn region_flags(self) -> MemoryRegionFlags {
+        let mut flags = MemoryRegionFlags::READ;
+        if self.writable() {
+            flags |= MemoryRegionFlags::WRITE;
+        }
+        if self.executable() {
+            flags |= MemoryRegionFlags::EXECUTE;
+        }
+        flags
+    }
+}
+
+/// The sizes and order of guest memory regions, built by a
+/// `MemoryLayoutBuilder` and applied with
+/// `SandboxConfiguration::set_memory_layout`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MemoryLayout {
+    pub(crate) heap_size: Option<u64>,
+    pub(crate) stack_size: Option<u64>,
+    pub(crate) input_data_size: Option<usize>,
+    pub(crate) output_data_size: Option<usize>,
+    pub(crate) region_order: [LayoutRegion; LayoutRegion::COUNT],
+    pub(crate) region_permissions: [RegionPermissions; LayoutRegion::COUNT],
+}
+
+/// Builds a `MemoryLayout`. Sizes that aren't set are left as they are in
+/// the `SandboxConfiguration` the layout is applied to, regions are in
+/// their default order unless `region_order` is called, and have their
+/// `LayoutRegion::default_permissions` unless `region_permissions` is
+/// called for them.
+///
+/// ```
+/// # use hyperlight_host::sandbox::{
+/// #     LayoutRegion, MemoryLayoutBuilder, RegionPermissions, SandboxConfiguration,
+/// # };
+/// # fn main() -> hyperlight_host::Result<()> {
+/// let layout = MemoryLayoutBuilder::new()
+///     .heap_size(16 * 1024 * 1024 * 1024)
+///     .input_data_size(0x100000)
+///     .region_order(&[
+///         LayoutRegion::InputData,
+///         LayoutRegion::OutputData,
+///         LayoutRegion::HostFunctionDefinitions,
+///         LayoutRegion::HostExceptionData,
+///         LayoutRegion::GuestErrorData,
+///         LayoutRegion::ResultBuffer,
+///         LayoutRegion::ReadOnlyData,
+///         LayoutRegion::GuestLogRing,
+///         LayoutRegion::Secrets,
+///         LayoutRegion::JitScratch,
+///         LayoutRegion::PanicContext,
+///         LayoutRegion::Heap,
+///     ])
+///     .region_permissions(LayoutRegion::Heap, RegionPermissions::ReadWriteExecute)
+///     .build()?;
+/// let mut cfg = SandboxConfiguration::default();
+/// cfg.set_memory_layout(layout);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct MemoryLayoutBuilder {
+    heap_size: Option<u64>,
+    stack_size: Option<u64>,
+    input_data_size: Option<usize>,
+    output_data_size: Option<usize>,
+    region_order: Option<Vec<LayoutRegion>>,
+    region_permissions: Vec<(LayoutRegion, RegionPermissions)>,
+}
+
+impl MemoryLayoutBuilder {
+    /// Create a builder that leaves every size unchanged
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the size of the guest heap
+    pub fn heap_size(&mut self, heap_size: u64) -> &mut Self {
+        self.heap_size = Some(heap_size);
+        self
+    }
+
+    /// Set the size of the guest stack
+    pub fn stack_size(&mut self, stack_size: u64) -> &mut Self {
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    /// Set the size of the buffer for data sent to the guest
+    pub fn input_data_size(&mut self, input_data_size: usize) -> &mut Self {
+        self.input_data_size = Some(input_data_size);
+        self
+    }
+
+    /// Set the size of the buffer for data sent from the guest
+    pub fn output_data_size(&mut self, output_data_size: usize) -> &mut Self {
+        self.output_data_size = Some(output_data_size);
+        self
+    }
+
+    /// Place the regions in guest memory in the order given, which must
+    /// contain every `LayoutRegion` exactly once
+    pub fn region_order(&mut self, region_order: &[LayoutRegion]) -> &mut Self {
+        self.region_order = Some(region_order.to_vec());
+        self
+    }
+
+    /// Give the guest `permissions` to `region`, e.g. to make the guest
+    /// heap executable for guests that compile code at runtime, or to
+    /// keep guests from writing to a region they only need to read
+    pub fn region_permissions(
+        &mut self,
+        region: LayoutRegion,
+        permissions: RegionPermissions,
+    ) -> &mut Self {
+        self.region_permissions.push((region, permissions));
+        self
+    }
+
+    /// Build the layout, failing if the region order doesn't contain
+    /// every region exactly once
+    pub fn build(&self) -> Result<MemoryLayout> {
+        let region_order = match &self.region_order {
+            None => LayoutRegion::DEFAULT_ORDER,
+            Some(order) => {
+                let complete = LayoutRegion::DEFAULT_ORDER
+                    .iter()
+                    .all(|region| order.iter().filter(|r| *r == region).count() == 1);
+                if !complete || order.len() != LayoutRegion::COUNT {
+                    return Err(new_error!(
+                        "The region order {:?} does not contain every region exactly once",
+                        order
+                    ));
+                }
+                let mut region_order = LayoutRegion::DEFAULT_ORDER;
+                region_order.copy_from_slice(order);
+                region_order
+            }
+        };
+        let mut region_permissions = LayoutRegion::default_permissions_table();
+        for (region, permissions) in &self.region_permissions {
+            region_permissions[*region as usize] = *permissions;
+        }
+        Ok(MemoryLayout {
+            heap_size: self.heap_size,
+            stack_size: self.stack_size,
+            input_data_size: self.input_data_size,
+            output_data_size: self.output_data_size,
+            region_order,
+            region_permissions,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LayoutRegion, MemoryLayoutBuilder, RegionPermissions};
+
+    #[test]
+    fn build() {
+        let layout = MemoryLayoutBuilder::new().build().unwrap();
+        assert_eq!(LayoutRegion::DEFAULT_ORDER, layout.region_order);
+        assert_eq!(None, layout.heap_size);
+
+        let mut order = LayoutRegion::DEFAULT_ORDER;
+        order.reverse();
+        let layout = MemoryLayoutBuilder::new()
+            .heap_size(0x1_0000_0000)
+            .region_order(&order)
+            .build()
+            .unwrap();
+        assert_eq!(order, layout.region_order);
+        assert_eq!(Some(0x1_0000_0000), layout.heap_size);
+    }
+
+    #[test]
+    fn region_permissions() {
+        let layout = MemoryLayoutBuilder::new().build().unwrap();
+        for region in LayoutRegion::DEFAULT_ORDER {
+            assert_eq!(
+                region.default_permissions(),
+                layout.region_permissions[region as usize]
+            );
+        }
+        assert_eq!(
+            RegionPermissions::ReadOnly,
+            LayoutRegion::ReadOnlyData.default_permissions()
+        );
+        assert_eq!(
+            RegionPermissions::ReadWriteExecute,
+            LayoutRegion::JitScratch.default_permissions()
+        );
+
+        let layout = MemoryLayoutBuilder::new()
+            .region_permissions(LayoutRegion::Heap, RegionPermissions::ReadWriteExecute)
+            .region_permissions(LayoutRegion::InputData, RegionPermissions::ReadOnly)
+            .build()
+            .unwrap();
+        assert_eq!(
+            RegionPermissions::ReadWriteExecute,
+            layout.region_permissions[LayoutRegion::Heap as usize]
+        );
+        assert_eq!(
+            RegionPermissions::ReadOnly,
+            layout.region_permissions[LayoutRegion::InputData as usize]
+        );
+        assert!(!RegionPermissions::ReadOnly.writable());
+        assert!(RegionPermissions::ReadWriteExecute.executable());
+    }
+
+    #[test]
+    fn incomplete_order() {
+        let mut order = LayoutRegion::DEFAULT_ORDER;
+        assert!(MemoryLayoutBuilder::new()
+            .region_order(&order[1..])
+            .build()
+            .is_err());
+        order[0] = LayoutRegion::Heap;
+        assert!(MemoryLayoutBuilder::new()
+            .region_order(&order)
+            .build()
+            .is_err());
+    }
+}
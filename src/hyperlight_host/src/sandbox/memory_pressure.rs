@@ -0,0 +1,429 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Telling the embedder when the guest memory of all the sandboxes in the
+//! process grows past a threshold, so that it can hibernate or drop some
+//! of them.
+//!
+//! Every `MultiUseSandbox` counts towards the total from when it is
+//! created until it is dropped, except while it is hibernated. When the
+//! total grows past one of the thresholds passed to
+//! `set_memory_pressure_handler`, the handler is called with the sandboxes
+//! that aren't running a guest function call or hibernated, longest idle
+//! first. Hyperlight doesn't keep hold of the sandboxes themselves, the
+//! candidates are identified by `MultiUseSandbox::id`.
+//!
+//! Every sandbox that isn't hibernated is also asked to trim its memory,
+//! see `MultiUseSandbox::trim_memory`, before its next guest function
+//! call, so that sandboxes in use give memory back even if the handler
+//! only hibernates or drops idle ones.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+/// A sandbox that could be hibernated or dropped to relieve memory
+/// pressure
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MemoryPressureCandidate {
+    /// The sandbox's ID, see `MultiUseSandbox::id`
+    pub sandbox_id: u64,
+    /// The size of the sandbox's guest memory in bytes
+    pub memory_size: usize,
+    /// How long since the sandbox's last guest function call finished, or
+    /// since it was created or resumed if it hasn't made one since
+    pub idle_time: Duration,
+}
+
+/// What a memory pressure handler is called with
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MemoryPressure {
+    /// The threshold the total guest memory grew past, the highest one if
+    /// it grew past several at once
+    pub threshold: usize,
+    /// The total size in bytes of the guest memory of the sandboxes in
+    /// the process that aren't hibernated
+    pub total_memory: usize,
+    /// The sandboxes that could be hibernated or dropped, longest idle
+    /// first
+    pub candidates: Vec<MemoryPressureCandidate>,
+}
+
+type MemoryPressureCallback = Arc<dyn Fn(&MemoryPressure) + Send + Sync>;
+
+struct Handler {
+    /// In ascending order
+    thresholds: Vec<usize>,
+    callback: MemoryPressureCallback,
+}
+
+/// Whether a sandbox is running a guest function call and when it was
+/// last used, shared between the sandbox and the registry so that guest
+/// function calls don't take the registry's lock
+#[derive(Debug)]
+pub(crate) struct SandboxUsage {
+    busy: AtomicBool,
+    /// When the sandbox last started or finished a guest function call,
+    /// or was created or resumed, in nanoseconds since `origin()`
+    last_used: AtomicU64,
+    /// Set to ask the sandbox to trim its memory before its next guest
+    /// function call
+    trim_requested: AtomicBool,
+}
+
+impl Default for SandboxUsage {
+    fn default() -> Self {
+        Self {
+            busy: AtomicBool::new(false),
+            last_used: AtomicU64::new(Self::now()),
+            trim_requested: AtomicBool::new(false),
+        }
+    }
+}
+
+impl SandboxUsage {
+    /// The instant `last_used` is measured from
+    fn origin() -> Instant {
+        static ORIGIN: OnceLock<Instant> = OnceLock::new();
+        *ORIGIN.get_or_init(Instant::now)
+    }
+
+    fn now() -> u64 {
+        u64::try_from(Self::origin().elapsed().as_nanos()).unwrap_or(u64::MAX)
+    }
+
+    fn touch(&self) {
+        self.last_used.store(Self::now(), Ordering::Relaxed);
+    }
+
+    /// Mark the sandbox as running a guest function call, or as idle once
+    /// it has finished, so it isn't a candidate while it is running one
+    pub(crate) fn set_busy(&self, busy: bool) {
+        self.busy.store(busy, Ordering::Release);
+        self.touch();
+    }
+
+    /// Whether the sandbox is running a guest function call
+    pub(crate) fn is_busy(&self) -> bool {
+        self.busy.load(Ordering::Acquire)
+    }
+
+    /// How long since the sandbox was last used, as of `now`
+    pub(crate) fn idle_time(&self, now: Instant) -> Duration {
+        let last_used = Duration::from_nanos(self.last_used.load(Ordering::Relaxed));
+        now.saturating_duration_since(Self::origin() + last_used)
+    }
+
+    /// Ask the sandbox to trim its memory after its next standalone guest
+    /// function call
+    pub(crate) fn request_trim(&self) {
+        self.trim_requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether the sandbox was asked to trim its memory since this was
+    /// last called
+    pub(crate) fn take_trim_request(&self) -> bool {
+        self.trim_requested.swap(false, Ordering::Relaxed)
+    }
+}
+
+struct Entry {
+    memory_size: usize,
+    resident: bool,
+    usage: Arc<SandboxUsage>,
+}
+
+#[derive(Default)]
+struct State {
+    sandboxes: HashMap<u64, Entry>,
+    /// The total size of the memory of the resident sandboxes
+    total_memory: usize,
+    handler: Option<Handler>,
+}
+
+/// The guest memory of the sandboxes in the process
+#[derive(Default)]
+pub(crate) struct MemoryRegistry {
+    state: Mutex<State>,
+}
+
+impl MemoryRegistry {
+    /// The registry of every sandbox in the process
+    pub(crate) fn global() -> &'static Self {
+        static REGISTRY: OnceLock<MemoryRegistry> = OnceLock::new();
+        REGISTRY.get_or_init(Self::default)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn set_handler(&self, handler: Option<Handler>) {
+        self.lock().handler = handler;
+    }
+
+    fn total_memory(&self) -> usize {
+        self.lock().total_memory
+    }
+
+    /// Count a new sandbox's memory, calling the handler if that makes
+    /// the total grow past a threshold. The sandbox keeps `usage` up to
+    /// date, and is asked through it to trim its memory.
+    pub(crate) fn register(&self, sandbox_id: u64, memory_size: usize, usage: Arc<SandboxUsage>) {
+        self.update(|state| {
+            let entry = Entry {
+                memory_size,
+                resident: true,
+                usage,
+            };
+            if let Some(old) = state.sandboxes.insert(sandbox_id, entry) {
+                if old.resident {
+                    state.total_memory -= old.memory_size;
+                }
+            }
+            state.total_memory += memory_size;
+        });
+    }
+
+    /// Stop counting a dropped sandbox's memory
+    pub(crate) fn unregister(&self, sandbox_id: u64) {
+        let mut state = self.lock();
+        if let Some(entry) = state.sandboxes.remove(&sandbox_id) {
+            if entry.resident {
+                state.total_memory -= entry.memory_size;
+            }
+        }
+    }
+
+    /// Stop counting the memory of a sandbox that is hibernated, or count
+    /// it again when it resumes, calling the handler if that makes the
+    /// total grow past a threshold
+    pub(crate) fn set_resident(&self, sandbox_id: u64, resident: bool) {
+        self.update(|state| {
+            let Some(entry) = state.sandboxes.get_mut(&sandbox_id) else {
+                return;
+            };
+            if entry.resident == resident {
+                return;
+            }
+            entry.resident = resident;
+            entry.usage.touch();
+            let memory_size = entry.memory_size;
+            if resident {
+                state.total_memory += memory_size;
+            } else {
+                state.total_memory -= memory_size;
+            }
+        });
+    }
+
+    /// Apply `change` to the state, then ask the resident sandboxes to trim
+    /// their memory and call the handler if the total grew past a
+    /// threshold. The handler is called without the lock held, so that it
+    /// can hibernate or drop sandboxes.
+    fn update(&self, change: impl FnOnce(&mut State)) {
+        let (pressure, callback) = {
+            let mut state = self.lock();
+            let before = state.total_memory;
+            change(&mut state);
+            let after = state.total_memory;
+            let Some(handler) = &state.handler else {
+                return;
+            };
+            let Some(threshold) = handler
+                .thresholds
+                .iter()
+                .rev()
+                .find(|threshold| before < **threshold && **threshold <= after)
+                .copied()
+            else {
+                return;
+            };
+            let callback = handler.callback.clone();
+            for entry in state.sandboxes.values().filter(|entry| entry.resident) {
+                entry.usage.request_trim();
+            }
+            (state.pressure(threshold), callback)
+        };
+        callback(&pressure);
+    }
+}
+
+impl State {
+    fn pressure(&self, threshold: usize) -> MemoryPressure {
+        let now = Instant::now();
+        let mut candidates: Vec<_> = self
+            .sandboxes
+            .iter()
+            .filter(|(_, entry)| entry.resident && !entry.usage.is_busy())
+            .map(|(sandbox_id, entry)| MemoryPressureCandidate {
+                sandbox_id: *sandbox_id,
+                memory_size: entry.memory_size,
+                idle_time: entry.usage.idle_time(now),
+            })
+            .collect();
+        candidates.sort_by(|a, b| {
+            b.idle_time
+                .cmp(&a.idle_time)
+                .then(a.sandbox_id.cmp(&b.sandbox_id))
+        });
+        MemoryPressure {
+            threshold,
+            total_memory: self.total_memory,
+            candidates,
+        }
+    }
+}
+
+/// Call `handler` whenever the total guest memory of the sandboxes in the
+/// process grows past one of `thresholds`, in bytes, replacing the
+/// previous handler. Every sandbox that isn't hibernated is then also
+/// asked to trim its memory after its next standalone guest function
+/// call. The total grows when a sandbox is created or resumes from
+/// hibernation, and the handler is called on the thread that did so,
+/// before the sandbox is returned or used, so it should return quickly,
+/// for example by handing the candidates to another thread to hibernate
+/// or drop.
+pub fn set_memory_pressure_handler(
+    thresholds: &[usize],
+    handler: impl Fn(&MemoryPressure) + Send + Sync + 'static,
+) {
+    let mut thresholds = thresholds.to_vec();
+    thresholds.sort_unstable();
+    thresholds.dedup();
+    MemoryRegistry::global().set_handler(Some(Handler {
+        thresholds,
+        callback: Arc::new(handler),
+    }));
+}
+
+/// Remove the handler set with `set_memory_pressure_handler`
+pub fn clear_memory_pressure_handler() {
+    MemoryRegistry::global().set_handler(None);
+}
+
+/// The total size in bytes of the guest memory of the sandboxes in the
+/// process that aren't hibernated
+pub fn total_guest_memory() -> usize {
+    MemoryRegistry::global().total_memory()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::{Handler, MemoryPressure, MemoryRegistry, SandboxUsage};
+
+    fn registry(thresholds: &[usize]) -> (MemoryRegistry, Arc<Mutex<Vec<MemoryPressure>>>) {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let registry = MemoryRegistry::default();
+        let recorded = calls.clone();
+        registry.set_handler(Some(Handler {
+            thresholds: thresholds.to_vec(),
+            callback: Arc::new(move |pressure: &MemoryPressure| {
+                recorded.lock().unwrap().push(pressure.clone())
+            }),
+        }));
+        (registry, calls)
+    }
+
+    #[test]
+    fn handler_is_called_when_a_threshold_is_crossed() {
+        let (registry, calls) = registry(&[100, 200]);
+        registry.register(1, 60, Arc::default());
+        let busy = Arc::new(SandboxUsage::default());
+        registry.register(2, 30, busy.clone());
+        busy.set_busy(true);
+        assert!(calls.lock().unwrap().is_empty());
+        registry.register(3, 20, Arc::default());
+        {
+            let calls = calls.lock().unwrap();
+            assert_eq!(1, calls.len());
+            assert_eq!(100, calls[0].threshold);
+            assert_eq!(110, calls[0].total_memory);
+            // the sandbox running a call isn't a candidate
+            let ids: Vec<_> = calls[0]
+                .candidates
+                .iter()
+                .map(|candidate| candidate.sandbox_id)
+                .collect();
+            assert_eq!(vec![1, 3], ids);
+        }
+        assert_eq!(110, registry.total_memory());
+
+        // growing past both thresholds at once reports the highest
+        registry.unregister(1);
+        registry.unregister(2);
+        registry.register(4, 300, Arc::default());
+        assert_eq!(2, calls.lock().unwrap().len());
+        assert_eq!(200, calls.lock().unwrap()[1].threshold);
+    }
+
+    #[test]
+    fn hibernated_sandboxes_are_not_counted() {
+        let (registry, calls) = registry(&[100]);
+        registry.register(1, 80, Arc::default());
+        registry.set_resident(1, false);
+        assert_eq!(0, registry.total_memory());
+        registry.register(2, 80, Arc::default());
+        assert!(calls.lock().unwrap().is_empty());
+
+        // resuming crosses the threshold, and the hibernated sandbox is
+        // only a candidate once it has resumed
+        registry.set_resident(2, false);
+        registry.set_resident(1, true);
+        registry.set_resident(2, true);
+        let calls = calls.lock().unwrap();
+        assert_eq!(1, calls.len());
+        assert_eq!(160, calls[0].total_memory);
+        assert_eq!(2, calls[0].candidates.len());
+        assert_eq!(1, calls[0].candidates[0].sandbox_id);
+    }
+
+    #[test]
+    fn resident_sandboxes_are_asked_to_trim() {
+        let (registry, _calls) = registry(&[100]);
+        let busy = Arc::new(SandboxUsage::default());
+        let hibernated = Arc::new(SandboxUsage::default());
+        registry.register(1, 40, busy.clone());
+        busy.set_busy(true);
+        registry.register(2, 40, hibernated.clone());
+        registry.set_resident(2, false);
+
+        let created = Arc::new(SandboxUsage::default());
+        registry.register(3, 80, created.clone());
+        // sandboxes running a call trim before their next one, hibernated
+        // ones hold no memory to trim
+        assert!(busy.take_trim_request());
+        assert!(!busy.take_trim_request());
+        assert!(created.take_trim_request());
+        assert!(!hibernated.take_trim_request());
+    }
+
+    #[test]
+    fn usage_tracks_idle_time() {
+        let usage = SandboxUsage::default();
+        assert!(!usage.is_busy());
+        usage.set_busy(true);
+        assert!(usage.is_busy());
+        usage.set_busy(false);
+        let now = Instant::now();
+        assert!(usage.idle_time(now) < Duration::from_secs(1));
+        assert!(usage.idle_time(now + Duration::from_secs(5)) >= Duration::from_secs(5));
+    }
+}
@@ -42,6 +42,13 @@ static SANDBOX_METRIC_DEFINITIONS: &[HyperlightMetricDefinition] = &[
         labels: &["error_code", "error_message"],
         buckets: &[],
     },
+    HyperlightMetricDefinition {
+        name: "guest_crash_count",
+        help: "Number of guest crashes, by fault and crash fingerprint",
+        metric_type: HyperlightMetricType::IntCounterVec,
+        labels: &["fault", "fingerprint"],
+        buckets: &[],
+    },
     #[cfg(feature = "function_call_metrics")]
     HyperlightMetricDefinition {
         name: "guest_function_call_duration_microseconds",
@@ -91,6 +98,7 @@ static SANDBOX_METRIC_DEFINITIONS: &[HyperlightMetricDefinition] = &[
 #[strum(serialize_all = "snake_case")]
 pub(crate) enum SandboxMetric {
     GuestErrorCount,
+    GuestCrashCount,
     #[cfg(feature = "function_call_metrics")]
     GuestFunctionCallDurationMicroseconds,
     #[cfg(feature = "function_call_metrics")]
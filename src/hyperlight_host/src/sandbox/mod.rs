@@ -14,30 +14,95 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+/// Fair scheduling of guest calls queued by several tenants
+pub mod call_scheduler;
+/// Per guest function limits on the number of concurrent and queued calls
+pub mod concurrency_limits;
 /// Configuration needed to establish a sandbox.
 pub mod config;
+/// Double and triple faults of the guest's vCPU
+pub mod cpu_fault;
+/// Accounting for the CPU time sandboxes use
+pub(crate) mod cpu_time;
+/// The CPUID leaves exposed to the guest
+pub mod cpuid;
+/// Fingerprints that group identical guest crashes
+pub mod crash_fingerprint;
+/// Backoff and quarantine for guests that keep crashing
+pub mod crash_loop;
+/// The deadlines of guest function calls
+pub(crate) mod deadline;
+/// Where the guest's random numbers come from
+pub mod entropy;
+/// Epoch-based interruption of guest function calls
+pub mod epoch;
+/// Checking guest binaries without creating a sandbox
+pub mod guest_binary_report;
+/// Identification and rate limiting for guest log records forwarded
+/// to the host
+pub(crate) mod guest_log;
+/// How the guest's time stamp counter advances
+pub mod guest_time;
+/// Heap profiles sent by guests built with the `heap_profiler` feature
+pub mod heap_profile;
+/// The liveness signals watched during long guest function calls: the
+/// heartbeats cooperating guests send, and the host calls guests make
+pub(crate) mod heartbeat;
 /// Functionality for reading, but not modifying host functions
 mod host_funcs;
+/// Periodic guest maintenance calls on idle sandboxes
+pub mod housekeeping;
 /// Functionality for dealing with `Sandbox`es that contain Hypervisors
 pub(crate) mod hypervisor;
 /// Functionality for dealing with initialized sandboxes that can
 /// call 0 or more guest functions
 pub mod initialized_multi_use;
+/// How a running guest is interrupted when a guest function call is
+/// cancelled
+pub mod interrupt;
+/// The handlers of the I/O ports embedders use for their own channels
+/// with the guest
+pub(crate) mod io_ports;
 /// A container to leak, store and manage outb handlers for in-process
 /// executions. On non-in-process executions (e.g. windows without
 /// in-process mode turned on, or linux), the same container is just
 /// a no-op
 #[cfg(inprocess)]
 pub(crate) mod leaked_outb;
+/// Malloc traces sent by guests built with the `malloc_trace` feature, and
+/// the `assert_no_guest_leaks!` test helper
+pub mod malloc_trace;
 /// Functionality for dealing with memory access from the VM guest
 /// executable
 pub(crate) mod mem_access;
 /// Functionality for interacting with a sandbox's internally-stored
 /// `SandboxMemoryManager`
 pub(crate) mod mem_mgr;
+/// The sizes and placement of the guest's memory regions
+pub mod memory_layout;
+/// Telling the embedder when the guest memory of all sandboxes grows past
+/// a threshold
+pub mod memory_pressure;
+/// How guest accesses to model specific registers are handled
+pub mod msr;
 pub(crate) mod outb;
+/// Destinations for the output a guest prints to the host
+pub mod output_sink;
+/// Pausing and resuming a sandbox's vCPU
+pub mod pause;
+/// Progress reports sent by long running guest function calls
+pub mod progress;
+/// Tearing sandboxes down on a background thread
+pub mod reclaim;
+/// Retrying guest function calls that fail with transient errors
+pub mod retry;
 /// Options for configuring a sandbox
 mod run_options;
+/// Tracing spans around every crossing of the host-guest boundary
+#[cfg(feature = "boundary_spans")]
+pub(crate) mod spans;
+/// Handing sandboxes over from one thread to another
+pub mod transfer;
 /// Functionality for creating uninitialized sandboxes, manipulating them,
 /// and converting them to initialized sandboxes.
 pub mod uninitialized;
@@ -50,13 +115,103 @@ pub(crate) mod metrics;
 
 use std::collections::HashMap;
 
+/// Re-export for `CallScheduler` type
+pub use call_scheduler::CallScheduler;
+/// Re-export for `ConcurrencyLimit` type
+pub use concurrency_limits::ConcurrencyLimit;
+/// Re-export for `FunctionConcurrencyLimits` type
+pub use concurrency_limits::FunctionConcurrencyLimits;
+/// Re-export for `FunctionLoad` type
+pub use concurrency_limits::FunctionLoad;
+/// Re-export for `MemoryPopulation` type
+pub use config::MemoryPopulation;
 /// Re-export for `SandboxConfiguration` type
 pub use config::SandboxConfiguration;
+/// Re-export for `CpuFaultAction` type
+pub use cpu_fault::CpuFaultAction;
+/// Re-export for `CpuFaultContext` type
+pub use cpu_fault::CpuFaultContext;
+/// Re-export for `CpuFeatures` type
+pub use cpuid::CpuFeatures;
+/// Re-export for `CpuidConfiguration` type
+pub use cpuid::CpuidConfiguration;
+/// Re-export for `CrashFingerprint` type
+pub use crash_fingerprint::CrashFingerprint;
+/// Re-export for `CrashLoopDetector` type
+pub use crash_loop::CrashLoopDetector;
+/// Re-export for `CrashLoopState` type
+pub use crash_loop::CrashLoopState;
+/// Re-export for `EntropyPolicy` type
+pub use entropy::EntropyPolicy;
+/// Re-export for `EpochHandle` type
+pub use epoch::EpochHandle;
+/// Re-export for `GuestBinaryFormat` type
+pub use guest_binary_report::GuestBinaryFormat;
+/// Re-export for `GuestBinaryReport` type
+pub use guest_binary_report::GuestBinaryReport;
+/// Re-export for `GuestTime` type
+pub use guest_time::GuestTime;
+/// Re-export for `HeapProfile` type
+pub use heap_profile::HeapProfile;
+/// Re-export for `HeapProfileSite` type
+pub use heap_profile::HeapProfileSite;
+/// Re-export for `HousekeepingOutcome` type
+pub use housekeeping::HousekeepingOutcome;
+/// Re-export for `HousekeepingScheduler` type
+pub use housekeeping::HousekeepingScheduler;
+/// Re-export for `HousekeepingThread` type
+pub use housekeeping::HousekeepingThread;
+/// Re-export for `HostCallTransport` type
+pub use hyperlight_common::transport::HostCallTransport;
 /// Re-export for the `MultiUseSandbox` type
 pub use initialized_multi_use::MultiUseSandbox;
+/// Re-export for the `SandboxState` type
+pub use initialized_multi_use::SandboxState;
+/// Re-export for `InterruptEscalation` type
+pub use interrupt::InterruptEscalation;
+/// Re-export for `InterruptFailure` type
+pub use interrupt::InterruptFailure;
+/// Re-export for `InterruptPolicy` type
+pub use interrupt::InterruptPolicy;
+/// Re-export for `MallocEvent` type
+pub use malloc_trace::MallocEvent;
+/// Re-export for `MallocEventKind` type
+pub use malloc_trace::MallocEventKind;
+/// Re-export for `MallocTrace` type
+pub use malloc_trace::MallocTrace;
+/// Re-export for `LayoutRegion` type
+pub use memory_layout::LayoutRegion;
+/// Re-export for `MemoryLayout` type
+pub use memory_layout::MemoryLayout;
+/// Re-export for `MemoryLayoutBuilder` type
+pub use memory_layout::MemoryLayoutBuilder;
+/// Re-export for `RegionPermissions` type
+pub use memory_layout::RegionPermissions;
+/// Re-export for `MemoryPressure` type
+pub use memory_pressure::MemoryPressure;
+/// Re-export for `MemoryPressureCandidate` type
+pub use memory_pressure::MemoryPressureCandidate;
+/// Re-export for `MsrAccess` type
+pub use msr::MsrAccess;
+/// Re-export for `MsrAction` type
+pub use msr::MsrAction;
+/// Re-export for `MsrPolicy` type
+pub use msr::MsrPolicy;
+/// Re-export for `GuestOutputSink` trait
+pub use output_sink::GuestOutputSink;
+/// Re-export for `PauseHandle` type
+pub use pause::PauseHandle;
+/// Re-export for `ProgressReport` type
+pub use progress::ProgressReport;
+/// Re-export for `RetryPolicy` type
+pub use retry::RetryPolicy;
+/// Re-export for `RetryRecovery` type
+pub use retry::RetryRecovery;
 /// Re-export for `SandboxRunOptions` type
 pub use run_options::SandboxRunOptions;
 use tracing::{instrument, Span};
+/// Re-export for `SandboxTransfer` type
+pub use transfer::SandboxTransfer;
 /// Re-export for `GuestBinary` type
 pub use uninitialized::GuestBinary;
 /// Re-export for `UninitializedSandbox` type
@@ -0,0 +1,177 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::fmt::{Display, Formatter};
+
+use crate::{new_error, Result};
+
+/// What happens when the guest reads or writes a model specific register
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[repr(C)]
+pub enum MsrAction {
+    /// The hypervisor handles the access as it does for any VM
+    #[default]
+    Passthrough,
+    /// Reads return 0, and writes are ignored
+    EmulateZero,
+    /// The guest is stopped, and the guest function call fails with
+    /// `HyperlightError::GuestMsrAccessDenied`
+    Deny,
+}
+
+/// A guest access to a model specific register
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MsrAccess {
+    /// The guest read the register
+    Read,
+    /// The guest wrote the value to the register
+    Write(u64),
+}
+
+impl Display for MsrAccess {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MsrAccess::Read => write!(f, "read"),
+            MsrAccess::Write(value) => write!(f, "wrote {:#x} to", value),
+        }
+    }
+}
+
+/// How guest reads and writes of model specific registers (MSRs) are
+/// handled: a default action, and the actions for up to
+/// `MsrPolicy::MAX_OVERRIDES` individual MSRs.
+///
+/// The default policy passes every access through to the hypervisor.
+/// A policy of `MsrPolicy::new(MsrAction::Deny)` with a few MSRs set to
+/// `MsrAction::Passthrough` stops guests from depending on, or probing,
+/// the MSRs of the host they happen to run on.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MsrPolicy {
+    default_action: MsrAction,
+    overrides: [(u32, MsrAction); MsrPolicy::MAX_OVERRIDES],
+    override_count: usize,
+}
+
+impl MsrPolicy {
+    /// The maximum number of MSRs whose action can differ from the
+    /// default action
+    pub const MAX_OVERRIDES: usize = 16;
+
+    /// Create a policy that applies `default_action` to every MSR
+    pub fn new(default_action: MsrAction) -> Self {
+        Self {
+            default_action,
+            ..Default::default()
+        }
+    }
+
+    /// Apply `action` to accesses to `msr`, instead of the default action.
+    /// Fails if `MsrPolicy::MAX_OVERRIDES` other MSRs already have an
+    /// action.
+    pub fn set_action(&mut self, msr: u32, action: MsrAction) -> Result<()> {
+        let overrides = &mut self.overrides[..self.override_count];
+        if let Some(entry) = overrides.iter_mut().find(|(m, _)| *m == msr) {
+            entry.1 = action;
+            return Ok(());
+        }
+        if self.override_count == Self::MAX_OVERRIDES {
+            return Err(new_error!(
+                "An MSR policy can set the action of at most {} MSRs",
+                Self::MAX_OVERRIDES
+            ));
+        }
+        self.overrides[self.override_count] = (msr, action);
+        self.override_count += 1;
+        Ok(())
+    }
+
+    /// The action applied to accesses to `msr`
+    pub fn action(&self, msr: u32) -> MsrAction {
+        self.overrides()
+            .find(|(m, _)| *m == msr)
+            .map_or(self.default_action, |(_, action)| action)
+    }
+
+    /// The action applied to MSRs without an action of their own
+    pub(crate) fn default_action(&self) -> MsrAction {
+        self.default_action
+    }
+
+    /// The MSRs with an action of their own
+    pub(crate) fn overrides(&self) -> impl Iterator<Item = (u32, MsrAction)> + '_ {
+        self.overrides[..self.override_count].iter().copied()
+    }
+
+    /// Whether every access is passed through to the hypervisor, so the
+    /// hypervisor doesn't need to intercept any
+    pub(crate) fn is_passthrough(&self) -> bool {
+        self.default_action == MsrAction::Passthrough
+            && self
+                .overrides()
+                .all(|(_, action)| action == MsrAction::Passthrough)
+    }
+
+    /// Whether any access is passed through to the hypervisor, which
+    /// hypervisors that intercept either every MSR access or none can't
+    /// combine with the other actions
+    pub(crate) fn passes_any_through(&self) -> bool {
+        self.default_action == MsrAction::Passthrough
+            || self
+                .overrides()
+                .any(|(_, action)| action == MsrAction::Passthrough)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MsrAccess, MsrAction, MsrPolicy};
+
+    #[test]
+    fn actions() {
+        assert!(MsrPolicy::default().is_passthrough());
+
+        let mut policy = MsrPolicy::new(MsrAction::Deny);
+        assert!(!policy.is_passthrough());
+        policy.set_action(0x10, MsrAction::Passthrough).unwrap();
+        policy.set_action(0x1b, MsrAction::EmulateZero).unwrap();
+        policy.set_action(0x10, MsrAction::EmulateZero).unwrap();
+        assert_eq!(MsrAction::EmulateZero, policy.action(0x10));
+        assert_eq!(MsrAction::EmulateZero, policy.action(0x1b));
+        assert_eq!(MsrAction::Deny, policy.action(0xc000_0080));
+        assert_eq!(2, policy.overrides().count());
+        assert!(!policy.passes_any_through());
+        policy.set_action(0x1b, MsrAction::Passthrough).unwrap();
+        assert!(policy.passes_any_through());
+    }
+
+    #[test]
+    fn limit_overrides() {
+        let mut policy = MsrPolicy::new(MsrAction::EmulateZero);
+        for msr in 0..MsrPolicy::MAX_OVERRIDES as u32 {
+            policy.set_action(msr, MsrAction::Passthrough).unwrap();
+        }
+        assert!(policy.set_action(0x1000, MsrAction::Deny).is_err());
+        // changing an MSR that already has an action still works
+        policy.set_action(0, MsrAction::Deny).unwrap();
+        assert_eq!(MsrAction::Deny, policy.action(0));
+    }
+
+    #[test]
+    fn display_access() {
+        assert_eq!("read", MsrAccess::Read.to_string());
+        assert_eq!("wrote 0x2a to", MsrAccess::Write(42).to_string());
+    }
+}
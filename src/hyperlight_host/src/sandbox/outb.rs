@@ -19,36 +19,24 @@ use std::sync::{Arc, Mutex};
 use hyperlight_common::flatbuffer_wrappers::function_types::ParameterValue;
 use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
 use hyperlight_common::flatbuffer_wrappers::guest_log_data::GuestLogData;
+use hyperlight_common::guest_diagnostic::GuestDiagnostic;
+use hyperlight_common::transport::{Channel, PortMap};
 use log::{Level, Record};
 use tracing::{instrument, Span};
 use tracing_log::format_trace;
 
+use super::cpu_time::CpuTimeCounter;
+use super::guest_log::GuestLogForwarder;
+use super::heartbeat::HostCallTracker;
 use super::host_funcs::HostFuncsWrapper;
+use super::io_ports::PortHandlers;
 use super::mem_mgr::MemMgrWrapper;
 use crate::hypervisor::handlers::{OutBHandler, OutBHandlerFunction, OutBHandlerWrapper};
+use crate::mem::memory_region::MemoryRegionFlags;
 use crate::mem::mgr::SandboxMemoryManager;
 use crate::mem::shared_mem::HostSharedMemory;
 use crate::{new_error, HyperlightError, Result};
 
-pub(super) enum OutBAction {
-    Log,
-    CallFunction,
-    Abort,
-}
-
-impl TryFrom<u16> for OutBAction {
-    type Error = HyperlightError;
-    #[instrument(skip_all, parent = Span::current(), level= "Trace")]
-    fn try_from(val: u16) -> Result<Self> {
-        match val {
-            99 => Ok(OutBAction::Log),
-            101 => Ok(OutBAction::CallFunction),
-            102 => Ok(OutBAction::Abort),
-            _ => Err(new_error!("Invalid OutB value: {}", val)),
-        }
-    }
-}
-
 #[instrument(err(Debug), skip_all, parent = Span::current(), level="Trace")]
 pub(super) fn outb_log(mgr: &mut SandboxMemoryManager<HostSharedMemory>) -> Result<()> {
     // This code will create either a logging record or a tracing record for the GuestLogData depending on if the host has set up a tracing subscriber.
@@ -60,6 +48,21 @@ pub(super) fn outb_log(mgr: &mut SandboxMemoryManager<HostSharedMemory>) -> Resu
 
     let log_data: GuestLogData = mgr.read_guest_log_data()?;
 
+    // The record has to be read even if it is going to be dropped so that
+    // the output buffer stays consistent.
+    forward_guest_log(mgr.guest_log_forwarder(), &log_data)
+}
+
+/// Forward a guest log record to the host's tracing subscriber or logger,
+/// unless the sandbox's rate limit drops it.
+#[instrument(err(Debug), skip_all, parent = Span::current(), level="Trace")]
+pub(crate) fn forward_guest_log(
+    forwarder: &GuestLogForwarder,
+    log_data: &GuestLogData,
+) -> Result<()> {
+    if !forwarder.try_forward() {
+        return Ok(());
+    }
     let record_level: Level = (&log_data.level).into();
 
     // Work out if we need to log or trace
@@ -79,6 +82,30 @@ pub(super) fn outb_log(mgr: &mut SandboxMemoryManager<HostSharedMemory>) -> Resu
     // See https://github.com/rust-lang/rust/issues/42253 for the reason this has to be done this way
 
     if should_trace {
+        // The event created below can only carry the fields of a log record, so the
+        // structured fields identifying the sandbox and guest module are attached to
+        // a span (at the same level as the record) that the event is emitted in.
+        let sandbox_id = forwarder.sandbox_id();
+        macro_rules! guest_log_span {
+            ($level:expr) => {
+                tracing::span!(
+                    $level,
+                    "guest_log",
+                    sandbox_id,
+                    guest_module = log_data.source.as_str(),
+                    level = %record_level
+                )
+            };
+        }
+        let span = match record_level {
+            Level::Error => guest_log_span!(tracing::Level::ERROR),
+            Level::Warn => guest_log_span!(tracing::Level::WARN),
+            Level::Info => guest_log_span!(tracing::Level::INFO),
+            Level::Debug => guest_log_span!(tracing::Level::DEBUG),
+            Level::Trace => guest_log_span!(tracing::Level::TRACE),
+        };
+        let _entered = span.enter();
+
         // Create a tracing event for the GuestLogData
         // Ideally we would create tracing metadata based on the Guest Log Data
         // but tracing derives the metadata at compile time
@@ -116,28 +143,86 @@ pub(super) fn outb_log(mgr: &mut SandboxMemoryManager<HostSharedMemory>) -> Resu
 fn handle_outb_impl(
     mem_mgr: &mut MemMgrWrapper<HostSharedMemory>,
     host_funcs: Arc<Mutex<HostFuncsWrapper>>,
+    host_calls: &HostCallTracker,
+    cpu_time: &CpuTimeCounter,
+    port_map: &PortMap,
+    port_handlers: &PortHandlers,
     port: u16,
     byte: u64,
 ) -> Result<()> {
-    match port.try_into()? {
-        OutBAction::Log => outb_log(mem_mgr.as_mut()),
-        OutBAction::CallFunction => {
+    // Forward the records the guest logged to the ring buffer before it
+    // exited, ahead of anything this exit logs
+    mem_mgr.as_mut().drain_guest_log_ring()?;
+    let Some(channel) = port_map.channel(port) else {
+        return port_handlers.call(port, byte as u8);
+    };
+    match channel {
+        Channel::Log => outb_log(mem_mgr.as_mut()),
+        Channel::Debug => {
+            mem_mgr
+                .unwrap_mgr()
+                .guest_log_forwarder()
+                .push_debug_output(byte as u8);
+            Ok(())
+        }
+        Channel::Call => {
             let call = mem_mgr.as_mut().get_host_function_call()?; // pop output buffer
             let name = call.function_name.clone();
+            let return_type = call.expected_return_type;
             let args: Vec<ParameterValue> = call.parameters.unwrap_or(vec![]);
+            #[cfg(feature = "boundary_spans")]
+            let span = super::spans::host_call_span(
+                mem_mgr.unwrap_mgr().guest_log_forwarder().sandbox_id(),
+                &name,
+                &args,
+            );
+            #[cfg(feature = "boundary_spans")]
+            let _entered = span.enter();
+            host_calls.enter();
+            // the lock is only held to take a copy of the host functions, so
+            // that a host function that never returns doesn't stop other
+            // sandboxes sharing them from calling them, or this sandbox
+            // from being recreated
             let res = host_funcs
                 .try_lock()
-                .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
-                .call_host_function(&name, args)?;
-            mem_mgr
-                .as_mut()
-                .write_response_from_host_method_call(&res)?; // push input buffers
-
-            Ok(())
+                .map(|funcs| funcs.clone())
+                .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))
+                .and_then(|funcs| {
+                    #[cfg(feature = "boundary_spans")]
+                    if let Some(redacted) = funcs.redacted_args(&name, &args) {
+                        super::spans::record_args(&span, &redacted);
+                    }
+                    funcs.call_host_function_traced(&name, args, return_type, cpu_time)
+                });
+            host_calls.exit();
+            #[cfg(feature = "boundary_spans")]
+            super::spans::record_result(&span, &res);
+            match res {
+                Ok(res) => mem_mgr.as_mut().write_response_from_host_method_call(&res), // push input buffers
+                // a panicking host function doesn't stop the guest, which is
+                // handed the panic as an error instead of a return value
+                Err(e @ HyperlightError::HostFunctionPanicked(_, _)) => mem_mgr
+                    .as_mut()
+                    .write_host_function_error(ErrorCode::HostFunctionError, &e.to_string()),
+                // as is an argument the host function's validation rejected
+                Err(e @ HyperlightError::HostFunctionArgumentInvalid(_, _, _)) => {
+                    mem_mgr.as_mut().write_host_function_error(
+                        ErrorCode::HostFunctionArgumentInvalid,
+                        &e.to_string(),
+                    )
+                }
+                Err(e) => Err(e),
+            }
         }
-        OutBAction::Abort => {
+        Channel::Abort => {
             let guest_error = ErrorCode::from(byte);
             let panic_context = mem_mgr.as_mut().read_guest_panic_context_data()?;
+            // `hl_assert!` and `hl_trap!` write a record rather than a message
+            if let Some(diagnostic) = GuestDiagnostic::from_bytes(&panic_context)
+                .map_err(|e| new_error!("Invalid guest diagnostic record: {}", e))?
+            {
+                return Err(HyperlightError::GuestTrapped(diagnostic));
+            }
             // trim off trailing \0 bytes if they exist
             let index_opt = panic_context.iter().position(|&x| x == 0x00);
             let trimmed = match index_opt {
@@ -147,6 +232,18 @@ fn handle_outb_impl(
             let s = String::from_utf8_lossy(trimmed);
             match guest_error {
                 ErrorCode::StackOverflow => Err(HyperlightError::StackOverflow()),
+                ErrorCode::EpochInterrupted => Err(HyperlightError::EpochDeadlineReached()),
+                ErrorCode::ControlFlowViolation => Err(HyperlightError::GuestControlFlowViolation(
+                    s.trim().to_string(),
+                )),
+                ErrorCode::DoubleFault => {
+                    Err(HyperlightError::GuestDoubleFault(s.trim().to_string()))
+                }
+                ErrorCode::PageProtectionViolation => Err(page_protection_violation(
+                    mem_mgr.as_mut(),
+                    byte as u8,
+                    s.trim(),
+                )),
                 _ => Err(HyperlightError::GuestAborted(
                     byte as u8,
                     s.trim().to_string(),
@@ -156,6 +253,58 @@ fn handle_outb_impl(
     }
 }
 
+/// The bit of a page fault's error code that is set when the fault was
+/// caused by a write
+const PAGE_FAULT_WRITE: u64 = 1 << 1;
+
+/// The bit of a page fault's error code that is set when the fault was
+/// caused by an instruction fetch
+const PAGE_FAULT_INSTRUCTION_FETCH: u64 = 1 << 4;
+
+/// The error for the guest's report of a page fault on a page whose
+/// protection doesn't allow the access. Writing to the guest's code is a
+/// `GuestCodeModificationAttempt`, and any other access a
+/// `MemoryAccessViolation` with the flags of the region the page is in.
+fn page_protection_violation(
+    mgr: &SandboxMemoryManager<HostSharedMemory>,
+    code: u8,
+    message: &str,
+) -> HyperlightError {
+    let field = |name: &str| {
+        message
+            .lines()
+            .find_map(|line| line.trim().strip_prefix(name))
+            .and_then(|value| u64::from_str_radix(value.trim().trim_start_matches("0x"), 16).ok())
+    };
+    let (Some(address), Some(error_code)) = (field("Page Fault Address:"), field("Error Code:"))
+    else {
+        return HyperlightError::GuestAborted(code, message.to_string());
+    };
+    let tried = if error_code & PAGE_FAULT_INSTRUCTION_FETCH != 0 {
+        MemoryRegionFlags::EXECUTE
+    } else if error_code & PAGE_FAULT_WRITE != 0 {
+        MemoryRegionFlags::WRITE
+    } else {
+        MemoryRegionFlags::READ
+    };
+    let region_flags = mgr
+        .layout
+        .get_memory_regions(&mgr.shared_mem)
+        .ok()
+        .and_then(|regions| {
+            regions
+                .into_iter()
+                .find(|region| region.guest_region.contains(&(address as usize)))
+        })
+        .map_or(MemoryRegionFlags::NONE, |region| region.flags);
+    if tried == MemoryRegionFlags::WRITE
+        && region_flags.contains(MemoryRegionFlags::WRITE_PROTECTED_CODE)
+    {
+        return HyperlightError::GuestCodeModificationAttempt(address);
+    }
+    HyperlightError::MemoryAccessViolation(address, tried, region_flags)
+}
+
 /// Given a `MemMgrWrapper` and ` HostFuncsWrapper` -- both passed by _value_
 ///  -- return an `OutBHandlerWrapper` wrapping the core OUTB handler logic.
 ///
@@ -164,11 +313,19 @@ fn handle_outb_impl(
 pub(crate) fn outb_handler_wrapper(
     mut mem_mgr_wrapper: MemMgrWrapper<HostSharedMemory>,
     host_funcs_wrapper: Arc<Mutex<HostFuncsWrapper>>,
+    host_calls: HostCallTracker,
+    cpu_time: CpuTimeCounter,
+    port_map: PortMap,
+    port_handlers: PortHandlers,
 ) -> OutBHandlerWrapper {
     let outb_func: OutBHandlerFunction = Box::new(move |port, payload| {
         handle_outb_impl(
             &mut mem_mgr_wrapper,
             host_funcs_wrapper.clone(),
+            &host_calls,
+            &cpu_time,
+            &port_map,
+            &port_handlers,
             port,
             payload,
         )
@@ -398,14 +555,41 @@ mod tests {
 
                     // We cannot get the parent span using the `current_span()` method as by the time we get to this point that span has been exited so there is no current span
                     // We need to make sure that the span that we created is in the spans map instead
-                    // We expect to have created 21 spans at this point. We are only interested in the first one that was created when calling outb_log.
+                    // We expect to have created 22 spans at this point. We are only interested in the first one that was created when calling outb_log
+                    // and the guest_log span that carries the structured fields for the guest log record.
 
                     assert!(
-                        spans.len() == 21,
-                        "expected 21 spans, found {}",
+                        spans.len() == 22,
+                        "expected 22 spans, found {}",
                         spans.len()
                     );
 
+                    let guest_log_span = spans
+                        .values()
+                        .map(|span| {
+                            span.as_object()
+                                .unwrap()
+                                .get("span")
+                                .unwrap()
+                                .get("attributes")
+                                .unwrap()
+                                .as_object()
+                                .unwrap()
+                        })
+                        .find(|attributes| {
+                            attributes
+                                .get("metadata")
+                                .and_then(|metadata| metadata.get("name"))
+                                .and_then(|name| name.as_str())
+                                == Some("guest_log")
+                        })
+                        .expect("guest_log span not found");
+                    assert_eq!(
+                        Some(mgr.guest_log_forwarder().sandbox_id()),
+                        guest_log_span.get("sandbox_id").and_then(|id| id.as_u64())
+                    );
+                    test_value_as_str(guest_log_span, "guest_module", "test source");
+
                     let span_value = spans
                         .get(&1)
                         .unwrap()
@@ -0,0 +1,172 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::io::{IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+
+use crossbeam_channel::{Receiver, Sender};
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+use tracing::{instrument, Span};
+
+use crate::{new_error, Result};
+
+/// A destination for the output a guest prints with `HostPrint`.
+///
+/// Each sandbox owns its own sink, set with
+/// `UninitializedSandbox::set_output_sink`. The guest is blocked until
+/// `write` returns, so a sink that can't keep up with the guest applies
+/// backpressure to it simply by blocking in `write`.
+pub trait GuestOutputSink: Send {
+    /// Write `output`, as printed by the guest, to this sink. Returning an
+    /// error fails the guest's `HostPrint` call.
+    fn write(&mut self, output: &[u8]) -> Result<()>;
+}
+
+/// A `GuestOutputSink` shared between a sandbox and its `HostPrint` host
+/// function, so that the sink can be replaced after the function has been
+/// registered
+pub(crate) type SharedOutputSink = Arc<Mutex<Box<dyn GuestOutputSink>>>;
+
+/// Write `output` to `sink`, returning the number of bytes written as
+/// `HostPrint` does
+#[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+pub(super) fn write_to_sink(sink: &SharedOutputSink, output: String) -> Result<i32> {
+    sink.lock()
+        .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+        .write(output.as_bytes())?;
+    Ok(output.len() as i32)
+}
+
+/// The default `GuestOutputSink`, which writes guest output to stdout, in
+/// green if stdout is a terminal
+#[derive(Debug, Default)]
+pub struct StdoutSink;
+
+impl GuestOutputSink for StdoutSink {
+    fn write(&mut self, output: &[u8]) -> Result<()> {
+        match std::io::stdout().is_terminal() {
+            false => {
+                std::io::stdout().write_all(output)?;
+            }
+            true => {
+                let mut stdout = StandardStream::stdout(ColorChoice::Auto);
+                let mut color_spec = ColorSpec::new();
+                color_spec.set_fg(Some(Color::Green));
+                stdout.set_color(&color_spec)?;
+                stdout.write_all(output)?;
+                stdout.reset()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A `GuestOutputSink` that writes guest output synchronously to any
+/// `std::io::Write`, such as a file, a socket or a pipe.
+///
+/// Each write is passed straight to the writer, wrap it in a
+/// `std::io::BufWriter` to batch small writes.
+#[derive(Debug)]
+pub struct WriterSink<W> {
+    writer: W,
+}
+
+impl<W: Write + Send> WriterSink<W> {
+    /// Create a new `WriterSink` writing to `writer`
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Return the writer this sink writes to
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write + Send> GuestOutputSink for WriterSink<W> {
+    fn write(&mut self, output: &[u8]) -> Result<()> {
+        self.writer.write_all(output)?;
+        Ok(())
+    }
+}
+
+/// A `GuestOutputSink` that hands guest output to another thread or task
+/// over a bounded channel, so that it can be consumed asynchronously.
+///
+/// Once `capacity` writes are waiting to be received, further writes block
+/// the guest until the receiver catches up. Writes fail once the receiver
+/// has been dropped.
+#[derive(Debug, Clone)]
+pub struct ChannelSink {
+    sender: Sender<Vec<u8>>,
+}
+
+impl ChannelSink {
+    /// Create a new `ChannelSink` that holds at most `capacity` pending
+    /// writes, along with the receiving end of its channel
+    pub fn bounded(capacity: usize) -> (Self, Receiver<Vec<u8>>) {
+        let (sender, receiver) = crossbeam_channel::bounded(capacity);
+        (Self { sender }, receiver)
+    }
+}
+
+impl GuestOutputSink for ChannelSink {
+    fn write(&mut self, output: &[u8]) -> Result<()> {
+        self.sender
+            .send(output.to_vec())
+            .map_err(|_| new_error!("The receiver of the guest output channel was dropped"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::{write_to_sink, ChannelSink, GuestOutputSink, SharedOutputSink, WriterSink};
+
+    #[test]
+    fn writer_sink_writes_output() {
+        let mut sink = WriterSink::new(Vec::new());
+        sink.write(b"hello ").unwrap();
+        sink.write(b"world").unwrap();
+        assert_eq!(b"hello world".to_vec(), sink.into_inner());
+    }
+
+    #[test]
+    fn channel_sink_applies_backpressure() {
+        let (sink, receiver) = ChannelSink::bounded(1);
+        let sink: SharedOutputSink = Arc::new(Mutex::new(Box::new(sink)));
+
+        assert_eq!(5, write_to_sink(&sink, "first".to_string()).unwrap());
+        let writer = {
+            let sink = sink.clone();
+            thread::spawn(move || write_to_sink(&sink, "second".to_string()).unwrap())
+        };
+        // the channel is full, so the second write waits for the first to
+        // be received
+        thread::sleep(Duration::from_millis(100));
+        assert!(!writer.is_finished());
+
+        assert_eq!(b"first".to_vec(), receiver.recv().unwrap());
+        assert_eq!(6, writer.join().unwrap());
+        assert_eq!(b"second".to_vec(), receiver.recv().unwrap());
+
+        drop(receiver);
+        assert!(write_to_sink(&sink, "third".to_string()).is_err());
+    }
+}
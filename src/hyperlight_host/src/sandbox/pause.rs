@@ -0,0 +1,247 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Pausing and resuming a sandbox's vCPU from another thread.
+//!
+//! The thread running the vCPU checks whether the sandbox is paused every
+//! time it is about to enter the guest, and parks until it is resumed.
+//! Pausing interrupts the vCPU the same way cancelling a guest function
+//! call does, so that a running guest exits to the host, but the vCPU's
+//! registers and the guest's memory are left as they were, and the guest
+//! carries on where it stopped once the sandbox is resumed.
+//!
+//! A guest that is running a host function is only paused once the host
+//! function returns. The time a guest function call spends paused doesn't
+//! count towards its maximum execution time or timeout, so a call can be
+//! paused for as long as needed, and still has the time it had left once
+//! it is resumed.
+
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::interrupt::InterruptPolicy;
+use crate::hypervisor::hypervisor_handler::HypervisorHandler;
+use crate::Result;
+
+#[derive(Default)]
+struct PauseState {
+    paused: bool,
+    /// When the thread running the vCPU parked, if it is parked
+    parked_since: Option<Instant>,
+    /// How long the guest function call in progress has been parked for,
+    /// before it last parked
+    paused_time: Duration,
+    /// Whether the guest function call in progress is being cancelled, in
+    /// which case the vCPU isn't parked, so it can be stopped
+    cancelling: bool,
+    next_id: u64,
+    /// The hypervisor handler of the sandbox currently using the handle,
+    /// and the id it was attached with
+    attached: Option<(u64, HypervisorHandler)>,
+}
+
+#[derive(Default)]
+struct PauseShared {
+    state: Mutex<PauseState>,
+    /// Signalled whenever the sandbox is resumed, the vCPU is parked, or a
+    /// call is cancelled
+    changed: Condvar,
+}
+
+/// A handle to pause a sandbox's vCPU, e.g. to throttle guests doing
+/// background work while the host is under load, and to resume it later
+/// without losing the guest function call in progress.
+///
+/// The handle is shared by every sandbox recreated from the same
+/// sandbox, and can be cloned and sent to other threads.
+#[derive(Clone, Default)]
+pub struct PauseHandle(Arc<PauseShared>);
+
+impl PauseHandle {
+    /// Pause the sandbox's vCPU, returning once it has stopped. If no
+    /// guest function call is in progress, the next call stops before it
+    /// enters the guest, and waits until the sandbox is resumed.
+    pub fn pause(&self) -> Result<()> {
+        let mut state = self.lock();
+        state.paused = true;
+        loop {
+            let Some((_, hv_handler)) = &state.attached else {
+                return Ok(());
+            };
+            if state.parked_since.is_some() || state.cancelling || !hv_handler.is_running() {
+                return Ok(());
+            }
+            // The vCPU may be about to enter the guest, or running a host
+            // function, when it is interrupted, so keep interrupting it
+            // until it parks
+            hv_handler.interrupt_vcpu()?;
+            state = self
+                .0
+                .changed
+                .wait_timeout(state, InterruptPolicy::DEFAULT_RETRY_INTERVAL)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+    }
+
+    /// Resume the sandbox's vCPU where it was paused
+    pub fn resume(&self) {
+        self.lock().paused = false;
+        self.0.changed.notify_all();
+    }
+
+    /// Whether the sandbox is paused
+    pub fn is_paused(&self) -> bool {
+        self.lock().paused
+    }
+
+    /// Interrupt the vCPUs run by `hv_handler` when pausing from now on,
+    /// returning an id to detach it with
+    pub(crate) fn attach(&self, hv_handler: HypervisorHandler) -> u64 {
+        let mut state = self.lock();
+        state.next_id += 1;
+        let id = state.next_id;
+        state.attached = Some((id, hv_handler));
+        id
+    }
+
+    /// Stop interrupting the vCPUs run by the hypervisor handler attached
+    /// as `id`, if it is still attached
+    pub(crate) fn detach(&self, id: u64) {
+        let mut state = self.lock();
+        if state.attached.as_ref().is_some_and(|(a, _)| *a == id) {
+            state.attached = None;
+        }
+    }
+
+    /// Called before each guest function call, as the call can't be
+    /// cancelling yet, and hasn't been paused
+    pub(crate) fn start_call(&self) {
+        let mut state = self.lock();
+        state.cancelling = false;
+        state.paused_time = Duration::ZERO;
+    }
+
+    /// How long the guest function call in progress has been paused for,
+    /// including the time it has been parked for so far if it is parked
+    pub(crate) fn paused_time(&self) -> Duration {
+        let state = self.lock();
+        let parked = state
+            .parked_since
+            .map_or(Duration::ZERO, |since| since.elapsed());
+        state.paused_time + parked
+    }
+
+    /// Called before the guest function call in progress is cancelled, so
+    /// a parked vCPU runs again to be stopped
+    pub(crate) fn cancel(&self) {
+        self.lock().cancelling = true;
+        self.0.changed.notify_all();
+    }
+
+    /// Whether the guest function call in progress is being cancelled, as
+    /// opposed to the vCPU having been interrupted to pause it
+    pub(crate) fn is_cancelling(&self) -> bool {
+        self.lock().cancelling
+    }
+
+    /// Called by the thread running the vCPU before it enters the guest:
+    /// park until the sandbox is resumed or the call is cancelled, and
+    /// return how long it was parked for
+    pub(crate) fn wait_while_paused(&self) -> Duration {
+        let mut state = self.lock();
+        if !state.paused || state.cancelling {
+            return Duration::ZERO;
+        }
+        let since = Instant::now();
+        state.parked_since = Some(since);
+        self.0.changed.notify_all();
+        while state.paused && !state.cancelling {
+            state = self
+                .0
+                .changed
+                .wait(state)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        state.parked_since = None;
+        let parked = since.elapsed();
+        state.paused_time += parked;
+        parked
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PauseState> {
+        // Every field is a flag that is valid on its own, so the state is
+        // valid even if a thread panicked while holding the lock
+        self.0
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Debug for PauseHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PauseHandle")
+            .field("paused", &self.is_paused())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::PauseHandle;
+
+    #[test]
+    fn parks_until_resumed_or_cancelled() {
+        let handle = PauseHandle::default();
+        // pausing a sandbox that isn't attached doesn't wait
+        handle.pause().unwrap();
+        assert!(handle.is_paused());
+
+        let vcpu = {
+            let handle = handle.clone();
+            thread::spawn(move || handle.wait_while_paused())
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!vcpu.is_finished());
+        // the time spent parked is counted while the vCPU is parked
+        assert!(handle.paused_time() >= Duration::from_millis(50));
+        handle.resume();
+        let parked = vcpu.join().unwrap();
+        assert!(parked >= Duration::from_millis(50));
+        assert!(handle.paused_time() >= parked);
+        assert!(!handle.is_paused());
+
+        handle.pause().unwrap();
+        let vcpu = {
+            let handle = handle.clone();
+            thread::spawn(move || handle.wait_while_paused())
+        };
+        handle.cancel();
+        vcpu.join().unwrap();
+        assert!(handle.is_cancelling());
+        // a cancelled call doesn't park again
+        handle.wait_while_paused();
+        handle.start_call();
+        assert!(!handle.is_cancelling());
+        assert_eq!(Duration::ZERO, handle.paused_time());
+        handle.resume();
+    }
+}
@@ -0,0 +1,127 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use std::sync::{Arc, Mutex};
+
+use tracing::{instrument, Span};
+
+use crate::{new_error, Result};
+
+/// The progress of a guest function call, reported by the guest with
+/// `hyperlight_guest::progress::hl_report_progress`
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ProgressReport {
+    /// How much of its work the guest function has done, from 0 to 100
+    pub percent: u8,
+    /// What the guest function is doing
+    pub message: String,
+}
+
+type ProgressHandler = Box<dyn FnMut(&ProgressReport) + Send>;
+
+/// The handlers subscribed to the progress reports of a sandbox's guest,
+/// shared between the `HostReportProgress` host function and the sandbox
+#[derive(Clone, Default)]
+pub(crate) struct ProgressSubscribers(Arc<Mutex<Vec<ProgressHandler>>>);
+
+impl std::fmt::Debug for ProgressSubscribers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProgressSubscribers")
+            .finish_non_exhaustive()
+    }
+}
+
+impl ProgressSubscribers {
+    /// Call `handler` with every progress report the guest sends from now
+    /// on
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn subscribe(
+        &self,
+        handler: impl FnMut(&ProgressReport) + Send + 'static,
+    ) -> Result<()> {
+        self.0
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+            .push(Box::new(handler));
+        Ok(())
+    }
+
+    /// Pass a progress report from the guest to every subscribed handler,
+    /// and return the percentage the guest should next report progress at,
+    /// so that it doesn't exit to the host for every small step. Once the
+    /// guest reports 100 percent, it doesn't report again during the call.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(crate) fn report(&self, percent: u32, message: String, min_step: u8) -> Result<u32> {
+        let report = ProgressReport {
+            percent: percent.min(100) as u8,
+            message,
+        };
+        for handler in self
+            .0
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+            .iter_mut()
+        {
+            handler(&report);
+        }
+        Ok(match report.percent {
+            100 => 101,
+            percent => (u32::from(percent) + u32::from(min_step.max(1))).min(100),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::{ProgressReport, ProgressSubscribers};
+
+    #[test]
+    fn reports_reach_subscribers_and_are_throttled() {
+        let subscribers = ProgressSubscribers::default();
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let seen = reports.clone();
+        subscribers
+            .subscribe(move |r| seen.lock().unwrap().push(r.clone()))
+            .unwrap();
+
+        assert_eq!(
+            10,
+            subscribers.report(0, "starting".to_string(), 10).unwrap()
+        );
+        assert_eq!(
+            100,
+            subscribers.report(95, "almost".to_string(), 10).unwrap()
+        );
+        assert_eq!(
+            101,
+            subscribers.report(250, "done".to_string(), 10).unwrap()
+        );
+        // a step of 0 is treated as 1
+        assert_eq!(43, subscribers.report(42, String::new(), 0).unwrap());
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(4, reports.len());
+        assert_eq!(
+            ProgressReport {
+                percent: 100,
+                message: "done".to_string()
+            },
+            reports[2]
+        );
+    }
+}
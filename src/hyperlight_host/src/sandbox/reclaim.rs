@@ -0,0 +1,176 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Tearing sandboxes down on a background thread.
+//!
+//! Dropping a `MultiUseSandbox` has to stop its hypervisor handler thread,
+//! close the virtual machine's file descriptors or return its surrogate
+//! process, and unmap its memory. When a sandbox is dropped after every
+//! guest function call, that would add to the latency of every request, so
+//! the sandbox is only detached when it is dropped, and torn down by a
+//! single reclamation thread shared by all sandboxes. When sandboxes are
+//! dropped faster than the thread tears them down, and
+//! `MAX_PENDING_TEARDOWNS` are already waiting, the sandbox being dropped
+//! is torn down straight away instead, so that the memory of dropped
+//! sandboxes can't pile up.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread;
+
+use crossbeam_channel::{bounded, Sender, TrySendError};
+
+type Teardown = Box<dyn FnOnce() + Send>;
+
+/// The maximum number of teardowns waiting for the reclamation thread
+const MAX_PENDING_TEARDOWNS: usize = 64;
+
+struct Reclaimer {
+    sender: Sender<Teardown>,
+    /// The number of teardowns sent to the reclamation thread that haven't
+    /// finished, and the condition signalled when one finishes
+    pending: Arc<(Mutex<u64>, Condvar)>,
+}
+
+/// The reclamation thread, or `None` if it couldn't be started, in which
+/// case sandboxes are torn down when they are dropped
+static RECLAIMER: OnceLock<Option<Reclaimer>> = OnceLock::new();
+
+fn reclaimer() -> Option<&'static Reclaimer> {
+    RECLAIMER
+        .get_or_init(|| {
+            let (sender, receiver) = bounded::<Teardown>(MAX_PENDING_TEARDOWNS);
+            let pending = Arc::new((Mutex::new(0), Condvar::new()));
+            let finished = pending.clone();
+            let spawned = thread::Builder::new()
+                .name("Hyperlight Reclaimer".to_string())
+                .spawn(move || {
+                    for teardown in receiver {
+                        if catch_unwind(AssertUnwindSafe(teardown)).is_err() {
+                            log::error!("Tearing down a sandbox panicked");
+                        }
+                        let (count, done) = &*finished;
+                        *count.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+                        done.notify_all();
+                    }
+                });
+            match spawned {
+                Ok(_) => Some(Reclaimer { sender, pending }),
+                Err(e) => {
+                    log::error!("Failed to start the sandbox reclamation thread: {:?}", e);
+                    None
+                }
+            }
+        })
+        .as_ref()
+}
+
+/// Run `teardown` on the reclamation thread, or straight away if the
+/// thread isn't running or is already `MAX_PENDING_TEARDOWNS` behind.
+pub(crate) fn defer_teardown(teardown: impl FnOnce() + Send + 'static) {
+    let Some(reclaimer) = reclaimer() else {
+        return teardown();
+    };
+    let (count, _) = &*reclaimer.pending;
+    *count.lock().unwrap_or_else(|e| e.into_inner()) += 1;
+    if let Err(e) = reclaimer.sender.try_send(Box::new(teardown)) {
+        *count.lock().unwrap_or_else(|e| e.into_inner()) -= 1;
+        let teardown = match e {
+            TrySendError::Full(teardown) | TrySendError::Disconnected(teardown) => teardown,
+        };
+        teardown();
+    }
+}
+
+/// Wait until every sandbox dropped before this call has been torn down,
+/// for example so that a test can check that a sandbox's resources were
+/// released.
+pub fn flush() {
+    let Some(reclaimer) = reclaimer() else {
+        return;
+    };
+    let (count, done) = &*reclaimer.pending;
+    let mut count = count.lock().unwrap_or_else(|e| e.into_inner());
+    while *count > 0 {
+        count = done.wait(count).unwrap_or_else(|e| e.into_inner());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread::{self, ThreadId};
+
+    use super::{defer_teardown, flush, MAX_PENDING_TEARDOWNS};
+
+    #[test]
+    fn flush_waits_for_teardowns() {
+        let torn_down = Arc::new(AtomicUsize::new(0));
+        for _ in 0..10 {
+            let torn_down = torn_down.clone();
+            defer_teardown(move || {
+                std::thread::sleep(std::time::Duration::from_millis(5));
+                torn_down.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        flush();
+        assert_eq!(10, torn_down.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn panicking_teardown_does_not_stop_reclamation() {
+        defer_teardown(|| panic!("teardown failed"));
+        let torn_down = Arc::new(AtomicUsize::new(0));
+        let counter = torn_down.clone();
+        defer_teardown(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        flush();
+        assert_eq!(1, torn_down.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn full_queue_tears_down_inline() {
+        // hold the reclamation thread up, so that the teardowns queued
+        // after this one wait for it
+        let (release, released) = mpsc::channel::<()>();
+        defer_teardown(move || {
+            let _ = released.recv();
+        });
+
+        let threads = Arc::new(Mutex::new(Vec::<ThreadId>::new()));
+        for _ in 0..=MAX_PENDING_TEARDOWNS {
+            let threads = threads.clone();
+            defer_teardown(move || {
+                threads.lock().unwrap().push(thread::current().id());
+            });
+        }
+        // at least the teardown that didn't fit in the queue has already
+        // run, on this thread
+        let inline = threads
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|id| **id == thread::current().id())
+            .count();
+        assert!(inline >= 1);
+
+        release.send(()).unwrap();
+        flush();
+        assert_eq!(MAX_PENDING_TEARDOWNS + 1, threads.lock().unwrap().len());
+    }
+}
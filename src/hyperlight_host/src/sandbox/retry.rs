@@ -0,0 +1,167 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use crate::error::ErrorCategory;
+use crate::HyperlightError;
+
+/// What is done to a sandbox before a failed guest function call is tried
+/// again
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum RetryRecovery {
+    /// Restore the sandbox's state, as with `MultiUseSandbox::reset`
+    #[default]
+    Reset,
+    /// Create the sandbox again from scratch, as with
+    /// `MultiUseSandbox::recreate`
+    Recreate,
+}
+
+/// How guest function calls made with
+/// `MultiUseSandbox::call_guest_function_by_name` are retried when they
+/// fail, set with `MultiUseSandbox::set_retry_policy`.
+///
+/// A call is tried up to `max_attempts` times, as long as it fails with an
+/// error whose `ErrorCategory` is retryable. Before each new attempt the
+/// sandbox is recovered as set with `set_recovery`. By default only
+/// `ErrorCategory::Timeout` and `ErrorCategory::Hypervisor` errors are
+/// retried, the sandbox is reset between attempts, and only calls to
+/// functions the guest declared idempotent or pure are retried.
+///
+/// A failed attempt may have called host functions before it failed, so
+/// only turn off `set_idempotent_only` if every guest function called
+/// with the policy can safely be called more than once.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    retryable: [bool; RetryPolicy::CATEGORIES.len()],
+    recovery: RetryRecovery,
+    idempotent_only: bool,
+}
+
+impl RetryPolicy {
+    const CATEGORIES: [ErrorCategory; 5] = [
+        ErrorCategory::Timeout,
+        ErrorCategory::GuestCrash,
+        ErrorCategory::Hypervisor,
+        ErrorCategory::GuestError,
+        ErrorCategory::Other,
+    ];
+
+    /// Create a policy that tries calls up to `max_attempts` times. A
+    /// policy of 0 or 1 attempts never retries.
+    pub fn new(max_attempts: u32) -> Self {
+        let mut policy = Self {
+            max_attempts,
+            retryable: [false; Self::CATEGORIES.len()],
+            recovery: RetryRecovery::default(),
+            idempotent_only: true,
+        };
+        policy.set_retryable(ErrorCategory::Timeout, true);
+        policy.set_retryable(ErrorCategory::Hypervisor, true);
+        policy
+    }
+
+    /// Set whether calls that fail with errors of `category` are retried
+    pub fn set_retryable(&mut self, category: ErrorCategory, retryable: bool) {
+        self.retryable[Self::index(category)] = retryable;
+    }
+
+    /// Set what is done to the sandbox before a failed call is tried again
+    pub fn set_recovery(&mut self, recovery: RetryRecovery) {
+        self.recovery = recovery;
+    }
+
+    /// Set whether only calls to guest functions the guest declared
+    /// idempotent or pure are retried, see
+    /// `MultiUseSandbox::guest_function_flags`. Defaults to `true`.
+    pub fn set_idempotent_only(&mut self, idempotent_only: bool) {
+        self.idempotent_only = idempotent_only;
+    }
+
+    /// Whether only calls to idempotent guest functions are retried
+    pub fn idempotent_only(&self) -> bool {
+        self.idempotent_only
+    }
+
+    /// The most times a call is tried
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// What is done to the sandbox before a failed call is tried again
+    pub fn recovery(&self) -> RetryRecovery {
+        self.recovery
+    }
+
+    /// Whether a call that failed with `error` on its `attempt`th attempt,
+    /// counting from 1, should be tried again
+    pub fn should_retry(&self, error: &HyperlightError, attempt: u32) -> bool {
+        attempt < self.max_attempts && self.retryable[Self::index(error.category())]
+    }
+
+    fn index(category: ErrorCategory) -> usize {
+        match category {
+            ErrorCategory::Timeout => 0,
+            ErrorCategory::GuestCrash => 1,
+            ErrorCategory::Hypervisor => 2,
+            ErrorCategory::GuestError => 3,
+            ErrorCategory::Other => 4,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+
+    use super::{RetryPolicy, RetryRecovery};
+    use crate::error::ErrorCategory;
+    use crate::HyperlightError;
+
+    #[test]
+    fn retryable_categories() {
+        let timeout = HyperlightError::ExecutionCanceledByHost();
+        let crash = HyperlightError::StackOverflow();
+        let guest_error = HyperlightError::GuestError(ErrorCode::GuestError, "no".to_string());
+        let other = HyperlightError::GuestFunctionNotPermitted("f".to_string());
+        assert_eq!(ErrorCategory::Timeout, timeout.category());
+        assert_eq!(ErrorCategory::GuestCrash, crash.category());
+        assert_eq!(ErrorCategory::GuestError, guest_error.category());
+        assert_eq!(ErrorCategory::Other, other.category());
+        assert_eq!(
+            ErrorCategory::Hypervisor,
+            HyperlightError::HypervisorHandlerCommunicationFailure().category()
+        );
+
+        let mut policy = RetryPolicy::new(3);
+        assert_eq!(RetryRecovery::Reset, policy.recovery());
+        assert!(policy.idempotent_only());
+        assert!(policy.should_retry(&timeout, 1));
+        assert!(policy.should_retry(&timeout, 2));
+        assert!(!policy.should_retry(&timeout, 3));
+        assert!(!policy.should_retry(&crash, 1));
+        assert!(!policy.should_retry(&guest_error, 1));
+        assert!(!policy.should_retry(&other, 1));
+
+        policy.set_retryable(ErrorCategory::Timeout, false);
+        policy.set_retryable(ErrorCategory::GuestCrash, true);
+        assert!(!policy.should_retry(&timeout, 1));
+        assert!(policy.should_retry(&crash, 1));
+
+        assert!(!RetryPolicy::new(0).should_retry(&timeout, 1));
+        assert!(!RetryPolicy::new(1).should_retry(&timeout, 1));
+    }
+}
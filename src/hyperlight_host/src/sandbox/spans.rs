@@ -0,0 +1,156 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! The `info` level spans created with the `boundary_spans` feature around
+//! every crossing of the boundary between the host and the guest, so that
+//! the whole lifecycle of a sandbox and its calls can be followed in a
+//! tracing backend such as Jaeger:
+//!
+//! - `sandbox_create` and `sandbox_initialise`, around creating a sandbox
+//!   and running the guest's initialisation
+//! - `guest_call`, around each guest function call made by the host
+//! - `host_call`, around each host function call made by the guest
+//!
+//! Every span has a `sandbox_id` field, the ID returned by
+//! `MultiUseSandbox::id`, and the call spans have a `function` field, an
+//! `args_bytes` field with the size of the arguments, and a
+//! `result_bytes` or `error` field once the call returns. The call spans
+//! also have an `args` field with the arguments if the sandbox has a
+//! `RedactionPolicy`, with the parameters it redacts and the `Sensitive`
+//! parameters of host functions left out.
+
+use std::fmt::Debug;
+
+use hyperlight_common::flatbuffer_wrappers::function_types::{
+    ParameterRef, ParameterValue, ReturnValue,
+};
+use tracing::field::Empty;
+use tracing::{info_span, Span};
+
+use crate::func::guest_dispatch::GuestCallOutput;
+use crate::func::redaction::RedactedArgs;
+use crate::Result;
+
+/// The number of bytes of data in `arg`
+fn arg_size(arg: &ParameterRef<'_>) -> usize {
+    match arg {
+        ParameterRef::Int(_) | ParameterRef::UInt(_) | ParameterRef::Float(_) => 4,
+        ParameterRef::Long(_) | ParameterRef::ULong(_) | ParameterRef::Double(_) => 8,
+        ParameterRef::Short(_) | ParameterRef::UShort(_) => 2,
+        ParameterRef::Byte(_) | ParameterRef::UByte(_) | ParameterRef::Bool(_) => 1,
+        ParameterRef::Str(s) => s.len(),
+        ParameterRef::Bytes(v) => v.len(),
+        ParameterRef::BytesSegments(segments) => segments.iter().map(Vec::len).sum(),
+    }
+}
+
+/// The number of bytes of data in `args`
+fn args_size(args: &[ParameterValue]) -> usize {
+    args.iter().map(|arg| arg_size(&arg.into())).sum()
+}
+
+/// The number of bytes of data in `result`
+pub(crate) fn result_size(result: &ReturnValue) -> usize {
+    match result {
+        ReturnValue::Int(_) | ReturnValue::UInt(_) | ReturnValue::Float(_) => 4,
+        ReturnValue::Long(_) | ReturnValue::ULong(_) | ReturnValue::Double(_) => 8,
+        ReturnValue::Short(_) | ReturnValue::UShort(_) => 2,
+        ReturnValue::Byte(_) | ReturnValue::UByte(_) | ReturnValue::Bool(_) => 1,
+        ReturnValue::Void => 0,
+        ReturnValue::String(s) => s.len(),
+        ReturnValue::VecBytes(v) => v.len(),
+    }
+}
+
+/// The span around creating a sandbox. Its `sandbox_id` is recorded with
+/// `record_sandbox_id` once the sandbox has one.
+pub(crate) fn sandbox_create_span() -> Span {
+    info_span!("sandbox_create", sandbox_id = Empty)
+}
+
+/// The span around running the guest's initialisation
+pub(crate) fn sandbox_initialise_span(sandbox_id: u64) -> Span {
+    info_span!("sandbox_initialise", sandbox_id)
+}
+
+pub(crate) fn record_sandbox_id(span: &Span, sandbox_id: u64) {
+    span.record("sandbox_id", sandbox_id);
+}
+
+/// The span around a guest function call made by the host
+pub(crate) fn guest_call_span(sandbox_id: u64, function: &str, args: &[ParameterRef<'_>]) -> Span {
+    info_span!(
+        "guest_call",
+        sandbox_id,
+        function,
+        args_bytes = args.iter().map(arg_size).sum::<usize>(),
+        args = Empty,
+        result_bytes = Empty,
+        error = Empty
+    )
+}
+
+/// The span around a host function call made by the guest
+pub(crate) fn host_call_span(sandbox_id: u64, function: &str, args: &[ParameterValue]) -> Span {
+    info_span!(
+        "host_call",
+        sandbox_id,
+        function,
+        args_bytes = args_size(args),
+        args = Empty,
+        result_bytes = Empty,
+        error = Empty
+    )
+}
+
+/// Record the arguments of the call `span` is around
+pub(crate) fn record_args<T: Debug>(span: &Span, args: &RedactedArgs<'_, T>) {
+    span.record("args", tracing::field::display(args));
+}
+
+/// Record the result of the call `span` is around
+pub(crate) fn record_result<T: GuestCallOutput>(span: &Span, result: &Result<T>) {
+    match result {
+        Ok(value) => span.record("result_bytes", value.size()),
+        Err(e) => span.record("error", tracing::field::display(e)),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterValue, ReturnValue};
+
+    use super::{args_size, result_size};
+
+    #[test]
+    fn payload_sizes() {
+        assert_eq!(0, args_size(&[]));
+        assert_eq!(
+            4 + 8 + 1 + 5 + 3 + 4,
+            args_size(&[
+                ParameterValue::Int(1),
+                ParameterValue::Double(1.0),
+                ParameterValue::Bool(true),
+                ParameterValue::String("hello".to_string()),
+                ParameterValue::VecBytes(vec![1, 2, 3]),
+                ParameterValue::VecBytesSegments(vec![vec![1], vec![2, 3, 4]]),
+            ])
+        );
+        assert_eq!(0, result_size(&ReturnValue::Void));
+        assert_eq!(8, result_size(&ReturnValue::ULong(1)));
+        assert_eq!(2, result_size(&ReturnValue::VecBytes(vec![1, 2])));
+    }
+}
@@ -0,0 +1,159 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! Moving sandboxes between threads, e.g. between the worker threads of a
+//! pool.
+//!
+//! `UninitializedSandbox` and `MultiUseSandbox` are `Send` but not `Sync`:
+//! a sandbox can be moved to another thread, but only the thread that owns
+//! it can use it. The guest runs on the sandbox's own hypervisor handler
+//! thread rather than on the thread calling it, so a sandbox works the
+//! same whichever thread it was created on, and the host functions
+//! registered with it are `Send` for the same reason.
+//!
+//! Sandboxes can simply be moved, but a pool that wants every move to be
+//! deliberate can hand sandboxes over with
+//! `MultiUseSandbox::transfer_to_thread` and `SandboxTransfer::receive`.
+//! A sandbox that was received on a thread belongs to it: calling a guest
+//! function on it from any other thread fails with
+//! `HyperlightError::SandboxOwnedByAnotherThread` until it is transferred
+//! again, which catches sandboxes a pool handed to two workers, or that a
+//! worker kept after returning them.
+
+use std::thread::{self, ThreadId};
+
+use tracing::{instrument, Span};
+
+use crate::{HyperlightError, MultiUseSandbox, Result, UninitializedSandbox};
+
+// Sandboxes are moved between threads, but never shared by them
+const _: () = {
+    const fn assert_send<T: Send>() {}
+    assert_send::<UninitializedSandbox>();
+    assert_send::<MultiUseSandbox>();
+    assert_send::<SandboxTransfer>();
+};
+
+/// A `MultiUseSandbox` on its way from one thread to another, returned by
+/// `MultiUseSandbox::transfer_to_thread`. The sandbox belongs to no thread
+/// until it is received.
+#[derive(Debug)]
+pub struct SandboxTransfer(MultiUseSandbox);
+
+impl SandboxTransfer {
+    /// Take the sandbox on the current thread, which only it can use the
+    /// sandbox from until it is transferred again
+    #[instrument(skip_all, parent = Span::current(), level = "Trace")]
+    pub fn receive(self) -> MultiUseSandbox {
+        let mut sbox = self.0;
+        sbox.owner_thread = Some(thread::current().id());
+        sbox
+    }
+
+    /// The id of the sandbox being transferred
+    pub fn id(&self) -> u64 {
+        self.0.id()
+    }
+}
+
+impl MultiUseSandbox {
+    /// Release this sandbox from the thread it belongs to, if any, so it
+    /// can be moved to and received on another thread. See
+    /// `sandbox::transfer`.
+    #[instrument(skip_all, parent = Span::current(), level = "Trace")]
+    pub fn transfer_to_thread(mut self) -> SandboxTransfer {
+        self.owner_thread = None;
+        SandboxTransfer(self)
+    }
+
+    /// The thread this sandbox was last received on with
+    /// `SandboxTransfer::receive`, if it was ever transferred
+    pub fn owner_thread(&self) -> Option<ThreadId> {
+        self.owner_thread
+    }
+
+    /// Return an error if this sandbox belongs to a thread other than the
+    /// current one
+    pub(crate) fn check_owner_thread(&self) -> Result<()> {
+        match self.owner_thread {
+            Some(owner) if owner != thread::current().id() => Err(
+                HyperlightError::SandboxOwnedByAnotherThread(owner, thread::current().id()),
+            ),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use hyperlight_common::flatbuffer_wrappers::function_types::{
+        ParameterValue, ReturnType, ReturnValue,
+    };
+    use hyperlight_testing::simple_guest_as_string;
+
+    use crate::sandbox::uninitialized::GuestBinary;
+    use crate::sandbox::SandboxState;
+    use crate::sandbox_state::sandbox::EvolvableSandbox;
+    use crate::sandbox_state::transition::Noop;
+    use crate::{HyperlightError, MultiUseSandbox, UninitializedSandbox};
+
+    fn echo(sbox: &mut MultiUseSandbox) -> crate::Result<ReturnValue> {
+        sbox.call_guest_function_by_name(
+            "Echo",
+            ReturnType::String,
+            Some(vec![ParameterValue::String("hi".to_string())]),
+        )
+    }
+
+    #[test]
+    fn transferred_sandboxes_belong_to_the_receiving_thread() {
+        let path = simple_guest_as_string().unwrap();
+        let sbox: MultiUseSandbox =
+            UninitializedSandbox::new(GuestBinary::FilePath(path), None, None, None)
+                .unwrap()
+                .evolve(Noop::default())
+                .unwrap();
+        // a sandbox that was never transferred belongs to no thread
+        assert_eq!(None, sbox.owner_thread());
+
+        let transfer = sbox.transfer_to_thread();
+        let id = transfer.id();
+        let (sbox, worker) = thread::spawn(move || {
+            let mut sbox = transfer.receive();
+            assert!(echo(&mut sbox).is_ok());
+            (sbox, thread::current().id())
+        })
+        .join()
+        .unwrap();
+        assert_eq!(id, sbox.id());
+        assert_eq!(Some(worker), sbox.owner_thread());
+
+        // the sandbox was moved back without being transferred
+        let mut sbox = sbox;
+        let res = echo(&mut sbox);
+        assert!(matches!(
+            res,
+            Err(HyperlightError::SandboxOwnedByAnotherThread(owner, _)) if owner == worker
+        ));
+        assert_eq!(SandboxState::Ready, sbox.state());
+
+        let mut sbox = sbox.transfer_to_thread().receive();
+        assert_eq!(Some(thread::current().id()), sbox.owner_thread());
+        assert!(echo(&mut sbox).is_ok());
+    }
+}
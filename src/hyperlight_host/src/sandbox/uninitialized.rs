@@ -14,23 +14,53 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::option::Option;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use hyperlight_common::flatbuffer_wrappers::function_types::{
+    ParameterType, ParameterValue, ReturnType, ReturnValue,
+};
+use hyperlight_common::flatbuffer_wrappers::host_function_definition::HostFunctionDefinition;
+use hyperlight_common::symbol_map::{SymbolMap, SYMBOL_MAP_EXTENSION};
+use hyperlight_common::transport::{HostCallTransport, PortMap};
 use log::LevelFilter;
 use tracing::{instrument, Span};
 
 #[cfg(gdb)]
 use super::config::DebugInfo;
-use super::host_funcs::{default_writer_func, HostFuncsWrapper};
+use super::cpu_fault::CpuFaultAction;
+use super::cpuid::CpuidConfiguration;
+use super::deadline::CallDeadline;
+use super::entropy::EntropyPolicy;
+use super::epoch::EpochHandle;
+use super::guest_binary_report::GuestBinaryReport;
+use super::guest_time::GuestTime;
+use super::heap_profile::{HeapProfile, LastHeapProfile};
+use super::heartbeat::{Heartbeat, HostCallTracker};
+use super::host_funcs::{sleep_func, HostFuncsWrapper, HostFunctionPanicCallback};
+use super::interrupt::{InterruptFailure, InterruptFailureCallback, InterruptPolicy};
+use super::io_ports::PortHandlers;
+use super::malloc_trace::{MallocTrace, MallocTraceRecorder};
 use super::mem_mgr::MemMgrWrapper;
+use super::msr::MsrPolicy;
+use super::output_sink::{write_to_sink, GuestOutputSink, SharedOutputSink, StdoutSink};
+use super::pause::PauseHandle;
+use super::progress::ProgressSubscribers;
 use super::run_options::SandboxRunOptions;
 use super::uninitialized_evolve::evolve_impl_multi_use;
 use crate::error::HyperlightError::GuestBinaryShouldBeAFile;
-use crate::func::host_functions::HostFunction1;
+use crate::func::arg_validation::ArgumentValidation;
+use crate::func::host_functions::{HostFunction0, HostFunction1, HostFunction2, HostFunction3};
+use crate::func::host_handles::HostHandles;
+use crate::func::host_service::HostService;
+use crate::func::host_stream::{max_chunk_size, ChunkStream, READ_NEXT_CHUNK_FUNCTION_NAME};
+use crate::func::HyperlightFunction;
+use crate::hypervisor::backend::{HypervisorBackend, HypervisorPartition};
+use crate::hypervisor::Hypervisor;
 use crate::mem::exe::ExeInfo;
 use crate::mem::mgr::{SandboxMemoryManager, STACK_COOKIE_LEN};
 use crate::mem::shared_mem::ExclusiveSharedMemory;
@@ -56,8 +86,25 @@ pub struct UninitializedSandbox {
     pub(crate) max_execution_time: Duration,
     pub(crate) max_wait_for_cancellation: Duration,
     pub(crate) max_guest_log_level: Option<LevelFilter>,
+    pub(crate) max_guest_instructions: u64,
+    pub(crate) extended_cpu_state: bool,
+    pub(crate) cpuid: CpuidConfiguration,
+    pub(crate) entropy_policy: EntropyPolicy,
+    pub(crate) guest_time: GuestTime,
+    pub(crate) msr_policy: MsrPolicy,
+    pub(crate) interrupt_policy: InterruptPolicy,
+    pub(crate) cpu_fault_action: CpuFaultAction,
+    pub(crate) host_call_transport: HostCallTransport,
+    pub(crate) heartbeat_timeout: Option<Duration>,
+    pub(crate) max_time_between_host_calls: Option<Duration>,
+    /// What this sandbox was created from, kept so that it can be created
+    /// again from scratch by `MultiUseSandbox::recreate`
+    pub(crate) source: SandboxSource,
     #[cfg(gdb)]
     pub(crate) debug_info: Option<DebugInfo>,
+    /// Where the default `HostPrint` function writes guest output, or
+    /// `None` if `HostPrint` was provided by the caller
+    output_sink: Option<SharedOutputSink>,
 }
 
 impl crate::sandbox_state::sandbox::UninitializedSandbox for UninitializedSandbox {
@@ -111,10 +158,101 @@ pub enum GuestBinary {
     FilePath(String),
 }
 
+impl GuestBinary {
+    /// Check that a sandbox can be created for the guest binary with
+    /// `cfg`, or the default configuration if it is `None`, without
+    /// creating one: that it can be parsed, loaded and relocated, that its
+    /// metadata is valid and its ABI version is supported, and how much
+    /// guest memory the sandbox would need. Problems are reported in the
+    /// returned report rather than as an error.
+    #[instrument(skip_all, parent = Span::current(), level = "Trace")]
+    pub fn validate(&self, cfg: Option<SandboxConfiguration>) -> GuestBinaryReport {
+        GuestBinaryReport::new(self, cfg.unwrap_or_default())
+    }
+}
+
+/// Everything needed to create a new sandbox for the same guest binary,
+/// with the same configuration, as an existing one.
+///
+/// The handles in it are shared by every sandbox created from this source,
+/// so a sandbox recreated by `MultiUseSandbox::recreate` keeps the
+/// subscribers, callbacks and handlers of the sandbox it replaces.
+#[derive(Debug, Clone)]
+pub(crate) struct SandboxSource {
+    pub(crate) guest_binary: Arc<GuestBinary>,
+    pub(crate) cfg: SandboxConfiguration,
+    pub(crate) run_options: SandboxRunOptions,
+    pub(crate) max_guest_log_level: Option<LevelFilter>,
+    /// Updated by the `HostHeartbeat` host function
+    pub(crate) heartbeat: Heartbeat,
+    /// Updated around every host function call the guest makes
+    pub(crate) host_calls: HostCallTracker,
+    /// Called by the `HostReportProgress` host function
+    pub(crate) progress: ProgressSubscribers,
+    /// Called when a guest fails to stop when interrupted
+    pub(crate) interrupt_failure: InterruptFailureCallback,
+    /// Creates the hypervisor the guest runs on, if the embedder replaced
+    /// the built-in drivers
+    pub(crate) hypervisor_backend: HypervisorBackend,
+    /// Read by the `HostRemainingTime` host function
+    pub(crate) deadline: CallDeadline,
+    /// Set by the `HostRecordHeapProfile` host function
+    pub(crate) heap_profile: LastHeapProfile,
+    /// Called by the `HostRecordMallocTrace` host function, records the
+    /// trace of the sandbox created from this source
+    pub(crate) malloc_trace: MallocTraceRecorder,
+    /// Incremented by the host to interrupt guest function calls
+    pub(crate) epoch: EpochHandle,
+    /// Pauses the vCPU of the sandbox created from this source
+    pub(crate) pause: PauseHandle,
+    /// The handlers of the ports registered with `register_port_handler`
+    pub(crate) port_handlers: PortHandlers,
+    /// The symbols of the guest binary, used to show guest addresses by
+    /// name
+    pub(crate) symbol_map: Option<Arc<SymbolMap>>,
+    /// The timeouts of calls to guest functions, from the guest's metadata
+    /// unless the host overrode them
+    pub(crate) function_timeouts: HashMap<String, Duration>,
+    /// The guest functions called after the guest is initialised, before
+    /// the sandbox's state is captured
+    pub(crate) warm_up_calls: Vec<WarmUpCall>,
+}
+
+/// A guest function call made after the guest is initialised, see
+/// `UninitializedSandbox::add_warm_up_call`
+#[derive(Debug, Clone)]
+pub(crate) struct WarmUpCall {
+    pub(crate) function_name: String,
+    pub(crate) return_type: ReturnType,
+    pub(crate) args: Option<Vec<ParameterValue>>,
+}
+
 impl UninitializedSandbox {
     /// Create a new sandbox configured to run the binary at path
     /// `bin_path`.
     ///
+    /// If `host_print_writer` is `None`, guest output is written to
+    /// stdout, or to the sink set with `set_output_sink`.
+    ///
+    /// The `HostSleep` host function, which the guest can call to be
+    /// suspended without using the CPU, the `HostHeartbeat` host
+    /// function, which the guest can call to show it is making progress,
+    /// the `HostReportProgress` host function, which passes the guest's
+    /// progress reports to the handlers subscribed with
+    /// `MultiUseSandbox::subscribe_progress`, the `HostRemainingTime`
+    /// host function, which returns the microseconds the guest function
+    /// call in progress has left before it is cancelled, the
+    /// `HostRecordHeapProfile` host function, which keeps the heap profile
+    /// sent by guests built with the `heap_profiler` feature for
+    /// `MultiUseSandbox::last_heap_profile`, the `HostRecordMallocTrace`
+    /// host function, which keeps the malloc trace sent by guests built
+    /// with the `malloc_trace` feature for `MultiUseSandbox::last_malloc_trace`,
+    /// the `HostListFunctions` host function, which returns the names
+    /// of every registered host function separated by newlines, and the
+    /// `HostReadNextChunk` host function, which the guest reads the
+    /// streams returned by streaming host functions with, are always
+    /// registered.
+    ///
     /// The instrument attribute is used to generate tracing spans and also to emit an error should the Result be an error.
     /// The skip attribute is used to skip the guest binary from being printed in the tracing span.
     /// The name attribute is used to name the tracing span.
@@ -130,6 +268,11 @@ impl UninitializedSandbox {
         sandbox_run_options: Option<SandboxRunOptions>,
         host_print_writer: Option<&dyn HostFunction1<String, i32>>,
     ) -> Result<Self> {
+        #[cfg(feature = "boundary_spans")]
+        let span = super::spans::sandbox_create_span();
+        #[cfg(feature = "boundary_spans")]
+        let _entered = span.enter();
+
         log_build_details();
 
         // hyperlight is only supported on Windows 11 and Windows Server 2022 and later
@@ -150,6 +293,15 @@ impl UninitializedSandbox {
             }
             buffer @ GuestBinary::Buffer(_) => buffer,
         };
+        let symbol_map = match &guest_binary {
+            GuestBinary::FilePath(path) => load_symbol_map(path),
+            GuestBinary::Buffer(_) => None,
+        };
+        let function_timeouts = Self::exe_info(&guest_binary)?
+            .metadata()
+            .function_timeouts()
+            .map(|(name, timeout)| (name.to_string(), timeout))
+            .collect();
 
         let run_opts = sandbox_run_options.unwrap_or_default();
 
@@ -166,41 +318,38 @@ impl UninitializedSandbox {
             log_then_return!("Inprocess mode with LoadLibrary is only available on Windows")
         }
 
-        let sandbox_cfg = cfg.unwrap_or_default();
-
-        #[cfg(gdb)]
-        let debug_info = sandbox_cfg.get_guest_debug_info();
-        let mut mem_mgr_wrapper = {
-            let mut mgr = UninitializedSandbox::load_guest_binary(
-                sandbox_cfg,
-                &guest_binary,
-                run_inprocess,
-                use_loadlib,
-            )?;
-            let stack_guard = Self::create_stack_guard();
-            mgr.set_stack_guard(&stack_guard)?;
-            MemMgrWrapper::new(mgr, stack_guard)
-        };
-
-        mem_mgr_wrapper.write_memory_layout(run_inprocess)?;
-
-        let host_funcs = Arc::new(Mutex::new(HostFuncsWrapper::default()));
+        let cfg = cfg.unwrap_or_default();
+        let port_map = cfg.get_port_map();
+        if !port_map.is_valid() {
+            log_then_return!(
+                "Invalid port map {:?}, each channel needs its own port below {}",
+                port_map,
+                PortMap::PORT_LIMIT
+            );
+        }
 
-        let mut sandbox = Self {
-            host_funcs,
-            mgr: mem_mgr_wrapper,
-            run_inprocess,
-            max_initialization_time: Duration::from_millis(
-                sandbox_cfg.get_max_initialization_time() as u64,
-            ),
-            max_execution_time: Duration::from_millis(sandbox_cfg.get_max_execution_time() as u64),
-            max_wait_for_cancellation: Duration::from_millis(
-                sandbox_cfg.get_max_wait_for_cancellation() as u64,
-            ),
+        let source = SandboxSource {
+            guest_binary: Arc::new(guest_binary),
+            cfg,
+            run_options: run_opts,
             max_guest_log_level: None,
-            #[cfg(gdb)]
-            debug_info,
+            heartbeat: Heartbeat::default(),
+            host_calls: HostCallTracker::default(),
+            progress: ProgressSubscribers::default(),
+            interrupt_failure: InterruptFailureCallback::default(),
+            hypervisor_backend: HypervisorBackend::default(),
+            deadline: CallDeadline::default(),
+            heap_profile: LastHeapProfile::default(),
+            malloc_trace: MallocTraceRecorder::default(),
+            epoch: EpochHandle::default(),
+            pause: PauseHandle::default(),
+            port_handlers: PortHandlers::default(),
+            symbol_map,
+            function_timeouts,
+            warm_up_calls: Vec::new(),
         };
+        let host_funcs = Arc::new(Mutex::new(HostFuncsWrapper::default()));
+        let mut sandbox = Self::from_source(source, host_funcs)?;
 
         // TODO: These only here to accommodate some writer functions.
         // We should modify the `UninitializedSandbox` to follow the builder pattern we use in
@@ -244,7 +393,11 @@ impl UninitializedSandbox {
                     )?;
             }
             None => {
-                let default_writer = Arc::new(Mutex::new(default_writer_func));
+                let output_sink: SharedOutputSink = Arc::new(Mutex::new(Box::new(StdoutSink)));
+                let default_writer = {
+                    let output_sink = output_sink.clone();
+                    Arc::new(Mutex::new(move |s: String| write_to_sink(&output_sink, s)))
+                };
 
                 #[cfg(any(target_os = "windows", not(feature = "seccomp")))]
                 default_writer.register(&mut sandbox, "HostPrint")?;
@@ -255,19 +408,512 @@ impl UninitializedSandbox {
                     "HostPrint",
                     extra_allowed_syscalls_for_writer_func,
                 )?;
+
+                sandbox.output_sink = Some(output_sink);
             }
         }
 
+        let sleep_func = Arc::new(Mutex::new(sleep_func));
+
+        #[cfg(any(target_os = "windows", not(feature = "seccomp")))]
+        sleep_func.register(&mut sandbox, "HostSleep")?;
+
+        #[cfg(all(target_os = "linux", feature = "seccomp"))]
+        sleep_func.register_with_extra_allowed_syscalls(
+            &mut sandbox,
+            "HostSleep",
+            vec![libc::SYS_clock_nanosleep],
+        )?;
+
+        let heartbeat = sandbox.source.heartbeat.clone();
+        let heartbeat_func = Arc::new(Mutex::new(move || heartbeat.beat()));
+
+        #[cfg(any(target_os = "windows", not(feature = "seccomp")))]
+        heartbeat_func.register(&mut sandbox, "HostHeartbeat")?;
+
+        #[cfg(all(target_os = "linux", feature = "seccomp"))]
+        heartbeat_func.register_with_extra_allowed_syscalls(
+            &mut sandbox,
+            "HostHeartbeat",
+            vec![libc::SYS_clock_gettime],
+        )?;
+
+        let deadline = sandbox.source.deadline.clone();
+        let remaining_time_func = Arc::new(Mutex::new(move || {
+            Ok(deadline.remaining().map_or(0, |remaining| {
+                u64::try_from(remaining.as_micros()).unwrap_or(u64::MAX)
+            }))
+        }));
+
+        #[cfg(any(target_os = "windows", not(feature = "seccomp")))]
+        remaining_time_func.register(&mut sandbox, "HostRemainingTime")?;
+
+        #[cfg(all(target_os = "linux", feature = "seccomp"))]
+        remaining_time_func.register_with_extra_allowed_syscalls(
+            &mut sandbox,
+            "HostRemainingTime",
+            vec![libc::SYS_clock_gettime],
+        )?;
+
+        let heap_profile = sandbox.source.heap_profile.clone();
+        let heap_profile_func = Arc::new(Mutex::new(move |profile: Vec<u8>| {
+            heap_profile.record(HeapProfile::from_guest_bytes(&profile)?);
+            Ok(())
+        }));
+
+        #[cfg(any(target_os = "windows", not(feature = "seccomp")))]
+        heap_profile_func.register(&mut sandbox, "HostRecordHeapProfile")?;
+
+        #[cfg(all(target_os = "linux", feature = "seccomp"))]
+        heap_profile_func.register_with_extra_allowed_syscalls(
+            &mut sandbox,
+            "HostRecordHeapProfile",
+            vec![libc::SYS_mmap, libc::SYS_brk],
+        )?;
+
+        let malloc_trace = sandbox.source.malloc_trace.clone();
+        let malloc_trace_func = Arc::new(Mutex::new(
+            move |index: u64, dropped_events: u64, events: Vec<u8>| {
+                let events = MallocTrace::events_from_guest_bytes(&events)?;
+                malloc_trace.record_chunk(index, dropped_events, events);
+                Ok(())
+            },
+        ));
+
+        #[cfg(any(target_os = "windows", not(feature = "seccomp")))]
+        malloc_trace_func.register(&mut sandbox, "HostRecordMallocTrace")?;
+
+        #[cfg(all(target_os = "linux", feature = "seccomp"))]
+        malloc_trace_func.register_with_extra_allowed_syscalls(
+            &mut sandbox,
+            "HostRecordMallocTrace",
+            vec![libc::SYS_mmap, libc::SYS_brk],
+        )?;
+
+        let heartbeat = sandbox.source.heartbeat.clone();
+        let progress = sandbox.source.progress.clone();
+        let min_progress_step = sandbox.source.cfg.get_min_progress_step();
+        let progress_func = Arc::new(Mutex::new(move |percent: u32, message: String| {
+            // a guest that reports progress is making progress
+            heartbeat.beat()?;
+            progress.report(percent, message, min_progress_step)
+        }));
+
+        #[cfg(any(target_os = "windows", not(feature = "seccomp")))]
+        progress_func.register(&mut sandbox, "HostReportProgress")?;
+
+        #[cfg(all(target_os = "linux", feature = "seccomp"))]
+        progress_func.register_with_extra_allowed_syscalls(
+            &mut sandbox,
+            "HostReportProgress",
+            vec![libc::SYS_clock_gettime],
+        )?;
+
+        let function_names = sandbox
+            .host_funcs
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+            .function_names();
+        let list_functions_func = Arc::new(Mutex::new(move || -> Result<String> {
+            Ok(function_names
+                .try_lock()
+                .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+                .join("\n"))
+        }));
+        list_functions_func.register(&mut sandbox, "HostListFunctions")?;
+
+        let chunk_streams = sandbox
+            .host_funcs
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+            .chunk_streams();
+        let max_chunk_size = max_chunk_size(&sandbox.source.cfg);
+        let read_next_chunk_func = Arc::new(Mutex::new(move |id: u64| {
+            chunk_streams.read_next_chunk(id, max_chunk_size)
+        }));
+
+        #[cfg(any(target_os = "windows", not(feature = "seccomp")))]
+        read_next_chunk_func.register(&mut sandbox, READ_NEXT_CHUNK_FUNCTION_NAME)?;
+
+        // Streams are read lazily, so the syscalls a stream needs to produce
+        // its chunks, such as reading from a file, are made by this function
+        #[cfg(all(target_os = "linux", feature = "seccomp"))]
+        read_next_chunk_func.register_with_extra_allowed_syscalls(
+            &mut sandbox,
+            READ_NEXT_CHUNK_FUNCTION_NAME,
+            vec![
+                libc::SYS_read,
+                libc::SYS_pread64,
+                libc::SYS_lseek,
+                libc::SYS_close,
+                libc::SYS_mmap,
+                libc::SYS_mremap,
+                libc::SYS_brk,
+            ],
+        )?;
+
         crate::debug!("Sandbox created:  {:#?}", sandbox);
 
+        #[cfg(feature = "boundary_spans")]
+        super::spans::record_sandbox_id(
+            &span,
+            sandbox.mgr.unwrap_mgr().guest_log_forwarder().sandbox_id(),
+        );
+
         Ok(sandbox)
     }
 
+    /// Create a new sandbox from `source`, with the host functions in
+    /// `host_funcs` available to the guest.
+    ///
+    /// Unlike `new`, this does not register the `HostPrint`, `HostSleep`,
+    /// `HostHeartbeat` and `HostListFunctions` functions, and does not
+    /// write the details of
+    /// `host_funcs` to guest memory.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub(super) fn from_source(
+        source: SandboxSource,
+        host_funcs: Arc<Mutex<HostFuncsWrapper>>,
+    ) -> Result<Self> {
+        let sandbox_cfg = source.cfg;
+        let run_inprocess = source.run_options.in_process();
+        let use_loadlib = source.run_options.use_loadlib();
+
+        #[cfg(gdb)]
+        let debug_info = sandbox_cfg.get_guest_debug_info();
+        let mut mem_mgr_wrapper = {
+            let mut mgr = UninitializedSandbox::load_guest_binary(
+                sandbox_cfg,
+                &source.guest_binary,
+                run_inprocess,
+                use_loadlib,
+            )?;
+            let stack_guard = Self::create_stack_guard();
+            mgr.set_stack_guard(&stack_guard)?;
+            MemMgrWrapper::new(mgr, stack_guard)
+        };
+
+        mem_mgr_wrapper.write_memory_layout(run_inprocess)?;
+
+        Ok(Self {
+            host_funcs,
+            mgr: mem_mgr_wrapper,
+            run_inprocess,
+            max_initialization_time: Duration::from_millis(
+                sandbox_cfg.get_max_initialization_time() as u64,
+            ),
+            max_execution_time: Duration::from_millis(sandbox_cfg.get_max_execution_time() as u64),
+            max_wait_for_cancellation: Duration::from_millis(
+                sandbox_cfg.get_max_wait_for_cancellation() as u64,
+            ),
+            max_guest_log_level: source.max_guest_log_level,
+            max_guest_instructions: sandbox_cfg.get_max_guest_instructions(),
+            extended_cpu_state: sandbox_cfg.get_extended_cpu_state(),
+            cpuid: sandbox_cfg
+                .get_entropy_policy()
+                .apply(sandbox_cfg.get_cpuid()),
+            entropy_policy: sandbox_cfg.get_entropy_policy(),
+            guest_time: sandbox_cfg.get_guest_time(),
+            msr_policy: sandbox_cfg.get_msr_policy(),
+            interrupt_policy: sandbox_cfg.get_interrupt_policy(),
+            cpu_fault_action: sandbox_cfg.get_cpu_fault_action(),
+            host_call_transport: sandbox_cfg.get_host_call_transport(),
+            heartbeat_timeout: sandbox_cfg.get_heartbeat_timeout(),
+            max_time_between_host_calls: sandbox_cfg.get_max_time_between_host_calls(),
+            source,
+            #[cfg(gdb)]
+            debug_info,
+            output_sink: None,
+        })
+    }
+
+    /// Call `callback` whenever a guest of this sandbox, or of a sandbox
+    /// recreated from it, hasn't stopped within the maximum time to wait
+    /// for a cancellation after it was interrupted, e.g. to alert or to
+    /// count hung guests. What happens to the guest function call is set
+    /// with `SandboxConfiguration::set_interrupt_policy`.
+    ///
+    /// The callback is called on the thread that cancelled the call.
+    #[instrument(skip_all, parent = Span::current(), level = "Trace")]
+    pub fn set_interrupt_failure_callback(
+        &mut self,
+        callback: impl Fn(&InterruptFailure) + Send + Sync + 'static,
+    ) {
+        self.source.interrupt_failure = InterruptFailureCallback::new(callback);
+    }
+
+    /// Run the guest of this sandbox, and of the sandboxes recreated from
+    /// it, on the `Hypervisor` `factory` creates rather than on KVM, MSHV
+    /// or WHP, e.g. to try an experimental VMM without forking Hyperlight.
+    /// `factory` is called on the thread that runs the vCPU when the
+    /// sandbox is evolved, with the guest memory to map and the registers
+    /// to start the vCPU with, see the `hypervisor::backend` module.
+    ///
+    /// The factory isn't called in in-process mode.
+    #[instrument(skip_all, parent = Span::current(), level = "Trace")]
+    pub fn set_hypervisor_backend(
+        &mut self,
+        factory: impl Fn(HypervisorPartition) -> Result<Box<dyn Hypervisor>> + Send + Sync + 'static,
+    ) {
+        self.source.hypervisor_backend = HypervisorBackend::new(factory);
+    }
+
+    /// Call `callback` with the name of the host function and the panic's
+    /// message whenever a host function of this sandbox, or of a sandbox
+    /// recreated from it, panics.
+    ///
+    /// A panicking host function doesn't fail the guest function call or
+    /// poison the sandbox, the guest is handed the panic as an error with
+    /// `ErrorCode::HostFunctionError` instead of the function's return
+    /// value. The callback is called on the thread running the guest.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub fn set_host_function_panic_callback(
+        &mut self,
+        callback: impl Fn(&str, &str) + Send + Sync + 'static,
+    ) -> Result<()> {
+        self.host_funcs
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+            .set_panic_callback(HostFunctionPanicCallback::new(callback));
+        Ok(())
+    }
+
+    /// Check the arguments the guest calls the host function `name` with
+    /// against `validation` before calling it, rather than in the host
+    /// function. If an argument breaks a rule, the host function isn't
+    /// called and the guest is handed an error with
+    /// `ErrorCode::HostFunctionArgumentInvalid` instead of the function's
+    /// return value, without failing the guest function call.
+    ///
+    /// Returns an error if the host function isn't registered or a rule is
+    /// for a parameter it doesn't have.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub fn set_host_function_argument_validation(
+        &mut self,
+        name: &str,
+        validation: ArgumentValidation,
+    ) -> Result<()> {
+        self.host_funcs
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+            .set_argument_validation(name, validation)
+    }
+
+    /// Add the secret `value` called `name`, such as an API key, for the
+    /// guest to take with `hyperlight_guest::secrets::hl_take_secret`,
+    /// rather than passing it as a guest function call parameter, which
+    /// may be logged or traced.
+    ///
+    /// The secret is only copied into the secrets region configured with
+    /// `SandboxConfiguration::set_secrets_size` while the guest runs, so
+    /// it is never part of a snapshot of guest memory. It isn't kept by the
+    /// sandbox's source, so a sandbox created again with
+    /// `MultiUseSandbox::recreate` has no secrets. If `wipe_after_read` is
+    /// set, the secret is zeroed the first time the guest takes it, and
+    /// can't be taken again by any later guest function call.
+    ///
+    /// Returns an error if there is no secrets region, `name` is empty or
+    /// already used, or the secret doesn't fit in the region.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub fn add_secret(&mut self, name: &str, value: &[u8], wipe_after_read: bool) -> Result<()> {
+        self.mgr
+            .unwrap_mgr_mut()
+            .add_secret(name, value, wipe_after_read)
+    }
+
+    /// Declare that the host provides the optional capability `name`, such
+    /// as "network", "vfs" or "kv", for the guest to check for with
+    /// `hyperlight_guest::has_feature` rather than by calling the host
+    /// functions that make it up and handling their failure. The features
+    /// are written to guest memory with the details of the host functions
+    /// before the guest is initialised, and kept by the sandboxes created
+    /// again from this one with `MultiUseSandbox::recreate`.
+    ///
+    /// Declaring a feature again does nothing. Returns an error if `name`
+    /// is empty, or the features don't fit in the host function
+    /// definitions buffer.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub fn declare_feature(&mut self, name: &str) -> Result<()> {
+        self.host_funcs
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+            .declare_feature(self.mgr.unwrap_mgr_mut(), name)
+    }
+
+    /// Call `handler` with the byte the guest sends whenever it signals
+    /// `port`, e.g. with `hyperlight_guest::host_function_call::outb`, for
+    /// a low-level channel between the guest and the embedder that
+    /// doesn't go through host functions. Returning an error fails the
+    /// guest function call.
+    ///
+    /// The handler is called on the thread running the guest, while the
+    /// guest waits for it, and is shared by the sandboxes created again
+    /// from this one with `MultiUseSandbox::recreate`.
+    ///
+    /// Returns an error if `port` isn't below `PortMap::PORT_LIMIT`, is
+    /// one of the host's ports set with `SandboxConfiguration::set_port_map`,
+    /// or already has a handler.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub fn register_port_handler(
+        &mut self,
+        port: u16,
+        handler: impl FnMut(u8) -> Result<()> + Send + 'static,
+    ) -> Result<()> {
+        self.source
+            .port_handlers
+            .register(port, &self.source.cfg.get_port_map(), handler)
+    }
+
+    /// Use the guest symbol map `bytes`, written by the
+    /// `cargo hyperlight-guest` subcommand of `hyperlight-guest-build`, to
+    /// show guest addresses by name, e.g. with
+    /// `MultiUseSandbox::symbolize`. The symbol map written next to a guest
+    /// binary loaded from a file is used without calling this.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub fn set_symbol_map(&mut self, bytes: &[u8]) -> Result<()> {
+        let map = SymbolMap::from_bytes(bytes)
+            .map_err(|e| new_error!("Invalid guest symbol map: {}", e))?;
+        self.source.symbol_map = Some(Arc::new(map));
+        Ok(())
+    }
+
+    /// Write the output the guest prints with `HostPrint` to `sink`,
+    /// instead of to stdout.
+    ///
+    /// The sink is called on the thread running the guest, with the same
+    /// seccomp filter as `HostPrint` when the `seccomp` feature is enabled.
+    /// Sinks that need other syscalls, e.g. to write to a file or a socket,
+    /// can hand the output to another thread with `ChannelSink`.
+    ///
+    /// Returns an error if a `host_print_writer` was passed to `new`, since
+    /// that function then handles all guest output itself.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub fn set_output_sink(&mut self, sink: impl GuestOutputSink + 'static) -> Result<()> {
+        match &self.output_sink {
+            Some(output_sink) => {
+                *output_sink
+                    .lock()
+                    .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))? =
+                    Box::new(sink);
+                Ok(())
+            }
+            None => {
+                log_then_return!(
+                    "The output sink can't be set on a sandbox with a custom HostPrint function"
+                );
+            }
+        }
+    }
+
+    /// Register a host function that returns its data to the guest in
+    /// chunks, so that a host function can hand the guest more data than
+    /// fits in the input data buffer, such as a large file, without
+    /// reading it all into memory at once.
+    ///
+    /// `func` is called with the parameters the guest called `name` with,
+    /// which must match `parameter_types`, and returns the chunks to hand
+    /// to the guest. The guest calls the function with
+    /// `hyperlight_guest::host_stream::call_streaming_host_function` and
+    /// reads the chunks with `read_next_chunk`. Chunks too large for the
+    /// input data buffer are split, and chunks are only produced as the
+    /// guest reads them. The chunks are produced by the
+    /// `HostReadNextChunk` host function, so when the `seccomp` feature
+    /// is enabled they are produced under its seccomp filter. Streams the
+    /// guest doesn't finish reading are dropped when the sandbox's state
+    /// is restored after a guest function call.
+    #[instrument(err(Debug), skip(self, func), parent = Span::current(), level = "Trace")]
+    pub fn register_streaming_host_function<F>(
+        &mut self,
+        name: &str,
+        parameter_types: Option<Vec<ParameterType>>,
+        mut func: F,
+    ) -> Result<()>
+    where
+        F: FnMut(Vec<ParameterValue>) -> Result<ChunkStream> + Send + 'static,
+    {
+        let mut host_funcs = self
+            .host_funcs
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?;
+        let chunk_streams = host_funcs.chunk_streams();
+        host_funcs.register_host_function(
+            self.mgr.as_mut(),
+            &HostFunctionDefinition::new(name.to_string(), parameter_types, ReturnType::ULong),
+            HyperlightFunction::new(move |args| {
+                Ok(ReturnValue::ULong(chunk_streams.open(func(args)?)?))
+            }),
+        )
+    }
+
+    /// The table of host objects the guest holds handles to. Host
+    /// functions registered with the sandbox capture it to hand the guest
+    /// a `Handle` to an object, by returning the handle `insert` returns,
+    /// and to use the object when the guest passes the handle back, so
+    /// that large host objects don't have to be copied into the guest.
+    /// `Handle` can be used as a parameter and return type of host
+    /// functions, and is a `ULong` to the guest. See the
+    /// `func::host_handles` module for how long the objects are kept.
+    #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
+    pub fn host_handles(&self) -> Result<HostHandles> {
+        Ok(self
+            .host_funcs
+            .try_lock()
+            .map_err(|e| new_error!("Error locking at {}:{}: {}", file!(), line!(), e))?
+            .host_handles())
+    }
+
+    /// Register each of the methods of `service` as a host function named
+    /// after the method in `namespace`, e.g. `fs::read` for the `read`
+    /// method in the namespace `fs`. Implement `HostService` for the
+    /// service, or for `dyn Trait + Send` for a trait the service
+    /// implements, with the `host_service!` macro, see the
+    /// `func::host_service` module for examples.
+    #[instrument(err(Debug), skip(self, service), parent = Span::current(), level = "Trace")]
+    pub fn register_host_service<S: HostService + ?Sized>(
+        &mut self,
+        namespace: &str,
+        service: Arc<Mutex<S>>,
+    ) -> Result<()> {
+        S::register_methods(service, self, namespace)
+    }
+
+    /// Call the guest function `function_name` with `args` right after the
+    /// guest is initialised, before the sandbox is ready to use, e.g. to
+    /// populate caches or compile code inside the guest.
+    ///
+    /// Warm-up calls are made in the order they were added, and the state
+    /// they leave the guest in is the state the sandbox is restored to
+    /// after each guest function call. If one of them fails, `evolve`
+    /// returns its error. They are made again when the sandbox is
+    /// recreated with `MultiUseSandbox::recreate`.
+    #[instrument(skip(self, args), parent = Span::current(), level = "Trace")]
+    pub fn add_warm_up_call(
+        &mut self,
+        function_name: &str,
+        return_type: ReturnType,
+        args: Option<Vec<ParameterValue>>,
+    ) {
+        self.source.warm_up_calls.push(WarmUpCall {
+            function_name: function_name.to_string(),
+            return_type,
+            args,
+        });
+    }
+
     #[instrument(skip_all, parent = Span::current(), level = "Trace")]
     fn create_stack_guard() -> [u8; STACK_COOKIE_LEN] {
         rand::random::<[u8; STACK_COOKIE_LEN]>()
     }
 
+    /// Parse `guest_binary`. Parsing is cached, so this is cheap for a
+    /// binary that has already been parsed.
+    fn exe_info(guest_binary: &GuestBinary) -> Result<ExeInfo> {
+        match guest_binary {
+            GuestBinary::FilePath(bin_path_str) => ExeInfo::from_file(bin_path_str),
+            GuestBinary::Buffer(buffer) => ExeInfo::from_buf(buffer),
+        }
+    }
+
     /// Load the file at `bin_path_str` into a PE file, then attempt to
     /// load the PE file into a `SandboxMemoryManager` and return it.
     ///
@@ -285,10 +931,7 @@ impl UninitializedSandbox {
         inprocess: bool,
         use_loadlib: bool,
     ) -> Result<SandboxMemoryManager<ExclusiveSharedMemory>> {
-        let mut exe_info = match guest_binary {
-            GuestBinary::FilePath(bin_path_str) => ExeInfo::from_file(bin_path_str)?,
-            GuestBinary::Buffer(buffer) => ExeInfo::from_buf(buffer)?,
-        };
+        let mut exe_info = Self::exe_info(guest_binary)?;
 
         if use_loadlib {
             let path = match guest_binary {
@@ -335,15 +978,33 @@ fn check_windows_version() -> Result<()> {
     Ok(())
 }
 
+/// The symbol map written next to the guest binary at `path` by
+/// `cargo hyperlight-guest`, if there is one. A map that can't be read is
+/// ignored, as guest addresses can still be shown without names.
+fn load_symbol_map(path: &str) -> Option<Arc<SymbolMap>> {
+    let map_path = format!("{}.{}", path, SYMBOL_MAP_EXTENSION);
+    let bytes = std::fs::read(&map_path).ok()?;
+    match SymbolMap::from_bytes(&bytes) {
+        Ok(map) => Some(Arc::new(map)),
+        Err(e) => {
+            log::warn!("Ignoring guest symbol map {}: {}", map_path, e);
+            None
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::path::PathBuf;
     use std::sync::{Arc, Mutex};
     use std::time::Duration;
     use std::{fs, thread};
 
     use crossbeam_queue::ArrayQueue;
-    use hyperlight_common::flatbuffer_wrappers::function_types::{ParameterValue, ReturnValue};
+    use hyperlight_common::flatbuffer_wrappers::function_types::{
+        ParameterValue, ReturnType, ReturnValue,
+    };
     use hyperlight_testing::logger::{Logger as TestLogger, LOGGER as TEST_LOGGER};
     use hyperlight_testing::tracing_subscriber::TracingSubscriber as TestSubscriber;
     use hyperlight_testing::{simple_guest_as_string, simple_guest_exe_as_string};
@@ -356,6 +1017,7 @@ mod tests {
     use uuid::Uuid;
 
     use crate::func::{HostFunction1, HostFunction2};
+    use crate::sandbox::output_sink::ChannelSink;
     use crate::sandbox::uninitialized::GuestBinary;
     use crate::sandbox::SandboxConfiguration;
     use crate::sandbox_state::sandbox::EvolvableSandbox;
@@ -615,6 +1277,184 @@ mod tests {
         }
     }
 
+    struct Counter {
+        count: i32,
+    }
+
+    impl Counter {
+        fn add(&mut self, n: i32) -> Result<i32> {
+            self.count += n;
+            Ok(self.count)
+        }
+
+        fn describe(&self, prefix: String, suffix: String) -> Result<String> {
+            Ok(format!("{prefix}{}{suffix}", self.count))
+        }
+
+        fn reset(&mut self) -> Result<()> {
+            self.count = 0;
+            Ok(())
+        }
+    }
+
+    crate::host_service! {
+        impl HostService for Counter {
+            fn add(n: i32) -> i32;
+            fn describe(prefix: String, suffix: String) -> String;
+            fn reset();
+        }
+    }
+
+    crate::host_service! {
+        /// A key-value store the guest can use
+        trait Store {
+            fn put(&mut self, key: String, value: String) -> Result<()>;
+            fn get(&self, key: String) -> Result<String>;
+            fn count(&self) -> Result<i32>;
+        }
+    }
+
+    impl Store for HashMap<String, String> {
+        fn put(&mut self, key: String, value: String) -> Result<()> {
+            self.insert(key, value);
+            Ok(())
+        }
+
+        fn get(&self, key: String) -> Result<String> {
+            Ok(HashMap::get(self, &key).cloned().unwrap_or_default())
+        }
+
+        fn count(&self) -> Result<i32> {
+            Ok(self.len() as i32)
+        }
+    }
+
+    #[test]
+    fn test_warm_up_calls() {
+        let mut usbox = UninitializedSandbox::new(
+            GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        usbox.add_warm_up_call(
+            "AddToStatic",
+            ReturnType::Int,
+            Some(vec![ParameterValue::Int(5)]),
+        );
+        usbox.add_warm_up_call(
+            "AddToStatic",
+            ReturnType::Int,
+            Some(vec![ParameterValue::Int(2)]),
+        );
+        let mut sandbox: MultiUseSandbox = usbox.evolve(Noop::default()).unwrap();
+
+        // the state the warm-up calls left is kept after each call
+        for _ in 0..2 {
+            let res = sandbox
+                .call_guest_function_by_name(
+                    "AddToStatic",
+                    ReturnType::Int,
+                    Some(vec![ParameterValue::Int(1)]),
+                )
+                .unwrap();
+            assert_eq!(ReturnValue::Int(8), res);
+        }
+
+        // and the warm-up calls are made again when recreating
+        let mut sandbox = sandbox.recreate().unwrap();
+        let res = sandbox
+            .call_guest_function_by_name("GetStatic", ReturnType::Int, None)
+            .unwrap();
+        assert_eq!(ReturnValue::Int(7), res);
+
+        // a failing warm-up call fails evolve
+        let mut usbox = UninitializedSandbox::new(
+            GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        usbox.add_warm_up_call("ThisFunctionDoesNotExist", ReturnType::Void, None);
+        assert!(usbox.evolve(Noop::default()).is_err());
+    }
+
+    #[test]
+    fn test_host_service() {
+        let mut usbox = UninitializedSandbox::new(
+            GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        let counter = Arc::new(Mutex::new(Counter { count: 0 }));
+        usbox
+            .register_host_service("counter", counter.clone())
+            .unwrap();
+        let sandbox: MultiUseSandbox = usbox.evolve(Noop::default()).unwrap();
+
+        let mut host_funcs = sandbox._host_funcs.try_lock().unwrap();
+        let res = host_funcs
+            .call_host_function("counter::add", vec![ParameterValue::Int(5)])
+            .unwrap();
+        assert_eq!(ReturnValue::Int(5), res);
+        let res = host_funcs
+            .call_host_function(
+                "counter::describe",
+                vec![
+                    ParameterValue::String("[".to_string()),
+                    ParameterValue::String("]".to_string()),
+                ],
+            )
+            .unwrap();
+        assert_eq!(ReturnValue::String("[5]".to_string()), res);
+        // the methods aren't registered outside the namespace
+        assert!(host_funcs
+            .call_host_function("add", vec![ParameterValue::Int(1)])
+            .is_err());
+
+        host_funcs
+            .call_host_function("counter::reset", vec![])
+            .unwrap();
+        assert_eq!(0, counter.lock().unwrap().count);
+    }
+
+    #[test]
+    fn test_host_service_from_trait() {
+        let mut usbox = UninitializedSandbox::new(
+            GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        // every method of the trait is registered, for any implementation
+        let store: Arc<Mutex<dyn Store + Send>> = Arc::new(Mutex::new(HashMap::new()));
+        usbox.register_host_service("kv", store.clone()).unwrap();
+        let sandbox: MultiUseSandbox = usbox.evolve(Noop::default()).unwrap();
+
+        let mut host_funcs = sandbox._host_funcs.try_lock().unwrap();
+        host_funcs
+            .call_host_function(
+                "kv::put",
+                vec![
+                    ParameterValue::String("a".to_string()),
+                    ParameterValue::String("b".to_string()),
+                ],
+            )
+            .unwrap();
+        let res = host_funcs
+            .call_host_function("kv::get", vec![ParameterValue::String("a".to_string())])
+            .unwrap();
+        assert_eq!(ReturnValue::String("b".to_string()), res);
+        let res = host_funcs.call_host_function("kv::count", vec![]).unwrap();
+        assert_eq!(ReturnValue::Int(1), res);
+        assert_eq!(1, store.lock().unwrap().count().unwrap());
+    }
+
     #[test]
     #[serial]
     fn test_load_guest_binary_load_lib() {
@@ -643,6 +1483,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_output_sink() {
+        let mut sandbox = UninitializedSandbox::new(
+            GuestBinary::FilePath(simple_guest_as_string().expect("Guest Binary Missing")),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+
+        let (sink, receiver) = ChannelSink::bounded(4);
+        sandbox.set_output_sink(sink).unwrap();
+        let res = sandbox
+            .host_funcs
+            .try_lock()
+            .unwrap()
+            .host_print("to the sink".to_string())
+            .unwrap();
+        assert_eq!(11, res);
+        assert_eq!(b"to the sink".to_vec(), receiver.try_recv().unwrap());
+
+        // a custom HostPrint function handles all output itself
+        let writer = Arc::new(Mutex::new(|msg: String| -> Result<i32> {
+            Ok(msg.len() as i32)
+        }));
+        let mut sandbox = UninitializedSandbox::new(
+            GuestBinary::FilePath(simple_guest_as_string().expect("Guest Binary Missing")),
+            None,
+            None,
+            Some(&writer),
+        )
+        .unwrap();
+        let (sink, _receiver) = ChannelSink::bounded(4);
+        assert!(sandbox.set_output_sink(sink).is_err());
+    }
+
     #[test]
     fn test_host_print() {
         // writer as a FnMut closure mutating a captured variable and then trying to access the captured variable
@@ -17,12 +17,14 @@ limitations under the License.
 use core::time::Duration;
 use std::sync::{Arc, Mutex};
 
+use hyperlight_common::transport::{HostCallTransport, PortMap};
 use log::LevelFilter;
 use rand::Rng;
 use tracing::{instrument, Span};
 
 #[cfg(gdb)]
 use super::mem_access::dbg_mem_access_handler_wrapper;
+use crate::hypervisor::backend::HypervisorBackend;
 use crate::hypervisor::hypervisor_handler::{
     HvHandlerConfig, HypervisorHandler, HypervisorHandlerAction,
 };
@@ -31,9 +33,22 @@ use crate::mem::ptr::RawPtr;
 use crate::mem::shared_mem::GuestSharedMemory;
 #[cfg(gdb)]
 use crate::sandbox::config::DebugInfo;
+use crate::sandbox::cpu_fault::CpuFaultAction;
+use crate::sandbox::cpu_time::CpuTimeCounter;
+use crate::sandbox::cpuid::CpuidConfiguration;
+use crate::sandbox::deadline::CallDeadline;
+use crate::sandbox::entropy::EntropyPolicy;
+use crate::sandbox::epoch::EpochHandle;
+use crate::sandbox::guest_time::GuestTime;
+use crate::sandbox::heartbeat::{Heartbeat, HostCallTracker};
 use crate::sandbox::host_funcs::HostFuncsWrapper;
+use crate::sandbox::interrupt::{InterruptFailureCallback, InterruptPolicy};
+use crate::sandbox::io_ports::PortHandlers;
 use crate::sandbox::mem_access::mem_access_handler_wrapper;
+use crate::sandbox::msr::MsrPolicy;
 use crate::sandbox::outb::outb_handler_wrapper;
+use crate::sandbox::pause::PauseHandle;
+use crate::sandbox::uninitialized::SandboxSource;
 use crate::sandbox::{HostSharedMemory, MemMgrWrapper};
 use crate::sandbox_state::sandbox::Sandbox;
 use crate::{new_error, MultiUseSandbox, Result, UninitializedSandbox};
@@ -72,6 +87,26 @@ where
             u_sbox.max_execution_time,
             u_sbox.max_wait_for_cancellation,
             u_sbox.max_guest_log_level,
+            u_sbox.max_guest_instructions,
+            u_sbox.extended_cpu_state,
+            u_sbox.cpuid,
+            u_sbox.entropy_policy,
+            u_sbox.guest_time,
+            u_sbox.msr_policy,
+            u_sbox.interrupt_policy,
+            u_sbox.cpu_fault_action,
+            u_sbox.source.interrupt_failure.clone(),
+            u_sbox.source.hypervisor_backend.clone(),
+            u_sbox.source.deadline.clone(),
+            u_sbox.host_call_transport,
+            u_sbox.source.heartbeat.clone(),
+            u_sbox.heartbeat_timeout,
+            u_sbox.source.host_calls.clone(),
+            u_sbox.max_time_between_host_calls,
+            u_sbox.source.pause.clone(),
+            u_sbox.source.epoch.clone(),
+            u_sbox.source.cfg.get_port_map(),
+            u_sbox.source.port_handlers.clone(),
             #[cfg(gdb)]
             u_sbox.debug_info,
         )?;
@@ -92,11 +127,36 @@ where
 
 #[instrument(err(Debug), skip_all, parent = Span::current(), level = "Trace")]
 pub(super) fn evolve_impl_multi_use(u_sbox: UninitializedSandbox) -> Result<MultiUseSandbox> {
+    #[cfg(feature = "boundary_spans")]
+    let _entered = crate::sandbox::spans::sandbox_initialise_span(
+        u_sbox.mgr.unwrap_mgr().guest_log_forwarder().sandbox_id(),
+    )
+    .entered();
+    let source = SandboxSource {
+        max_guest_log_level: u_sbox.max_guest_log_level,
+        ..u_sbox.source.clone()
+    };
     evolve_impl(u_sbox, |hf, mut hshm, hv_handler| {
         {
             hshm.as_mut().push_state()?;
         }
-        Ok(MultiUseSandbox::from_uninit(hf, hshm, hv_handler))
+        let mut sbox = MultiUseSandbox::from_uninit(hf, hshm, hv_handler, source.clone());
+        sbox.load_guest_signatures()?;
+        sbox.load_payload_compression()?;
+        if !source.warm_up_calls.is_empty() {
+            for call in &source.warm_up_calls {
+                sbox.call_guest_function_no_reset(
+                    &call.function_name,
+                    call.return_type,
+                    call.args.clone(),
+                )?;
+            }
+            sbox.mem_mgr.unwrap_mgr_mut().replace_last_snapshot()?;
+        }
+        // the objects the guest was handed while it was initialised are
+        // part of the state it is restored to
+        sbox.host_handles()?.keep_all()?;
+        Ok(sbox)
     })
 }
 
@@ -110,17 +170,45 @@ fn hv_init(
     max_exec_time: Duration,
     max_wait_for_cancellation: Duration,
     max_guest_log_level: Option<LevelFilter>,
+    max_guest_instructions: u64,
+    extended_cpu_state: bool,
+    cpuid: CpuidConfiguration,
+    entropy_policy: EntropyPolicy,
+    guest_time: GuestTime,
+    msr_policy: MsrPolicy,
+    interrupt_policy: InterruptPolicy,
+    cpu_fault_action: CpuFaultAction,
+    interrupt_failure: InterruptFailureCallback,
+    hypervisor_backend: HypervisorBackend,
+    call_deadline: CallDeadline,
+    host_call_transport: HostCallTransport,
+    heartbeat: Heartbeat,
+    heartbeat_timeout: Option<Duration>,
+    host_calls: HostCallTracker,
+    max_time_between_host_calls: Option<Duration>,
+    pause: PauseHandle,
+    epoch: EpochHandle,
+    port_map: PortMap,
+    port_handlers: PortHandlers,
     #[cfg(gdb)] debug_info: Option<DebugInfo>,
 ) -> Result<HypervisorHandler> {
-    let outb_hdl = outb_handler_wrapper(hshm.clone(), host_funcs);
+    let cpu_time = CpuTimeCounter::default();
+    let outb_hdl = outb_handler_wrapper(
+        hshm.clone(),
+        host_funcs,
+        host_calls.clone(),
+        cpu_time.clone(),
+        port_map,
+        port_handlers,
+    );
     let mem_access_hdl = mem_access_handler_wrapper(hshm.clone());
     #[cfg(gdb)]
     let dbg_mem_access_hdl = dbg_mem_access_handler_wrapper(hshm.clone());
 
-    let seed = {
+    let seed = entropy_policy.seed().unwrap_or_else(|| {
         let mut rng = rand::rng();
         rng.random::<u64>()
-    };
+    });
     let peb_addr = {
         let peb_u64 = u64::try_from(gshm.layout.peb_address)?;
         RawPtr::from(peb_u64)
@@ -139,6 +227,26 @@ fn hv_init(
         max_exec_time,
         max_wait_for_cancellation,
         max_guest_log_level,
+        max_guest_instructions,
+        extended_cpu_state,
+        shadow_stack: gshm.layout.get_shadow_stack_size() > 0,
+        cpuid,
+        trap_entropy_instructions: entropy_policy != EntropyPolicy::Hardware,
+        guest_time,
+        msr_policy,
+        interrupt_policy,
+        cpu_fault_action,
+        interrupt_failure,
+        call_deadline,
+        host_call_transport,
+        heartbeat,
+        heartbeat_timeout,
+        host_calls,
+        max_time_between_host_calls,
+        pause,
+        epoch,
+        cpu_time,
+        hypervisor_backend,
     };
     // Note: `dispatch_function_addr` is set by the Hyperlight guest library, and so it isn't in
     // shared memory at this point in time. We will set it after the execution of `hv_init`.
@@ -21,6 +21,9 @@ limitations under the License.
 /// needed for execution of guest code within Hyperlight through a syscalls allow-list.
 pub(crate) mod guest;
 
+/// A pool of reusable host function worker threads with seccomp filters applied.
+pub(crate) mod worker_pool;
+
 // The credit on the creation of the macros below goes to the cloud-hypervisor team
 // (https://github.com/cloud-hypervisor/cloud-hypervisor/blob/main/vmm/src/seccomp_filters.rs)
 
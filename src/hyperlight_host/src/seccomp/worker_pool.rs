@@ -0,0 +1,187 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+//! A pool of host function worker threads with seccomp filters applied.
+//!
+//! Host functions run on a worker thread so that the seccomp filter that
+//! restricts them doesn't apply to the rest of the host. Spawning a thread
+//! and installing a filter on every host function call is expensive, so
+//! workers are kept after a call and reused by any sandbox calling a host
+//! function with the same set of extra allowed syscalls. Everything a call
+//! needs is sent along with it, so a worker carries no state from one
+//! sandbox to the next.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+
+use crossbeam_channel::{bounded, unbounded, Sender};
+
+use crate::sandbox::ExtraAllowedSyscall;
+use crate::{new_error, Result};
+
+/// The most idle workers kept for each set of extra allowed syscalls
+const MAX_IDLE_WORKERS_PER_FILTER: usize = 16;
+
+/// A job for a worker, returning whether the worker may be reused
+type Job = Box<dyn FnOnce() -> bool + Send>;
+
+/// The key a worker is pooled under: its extra allowed syscalls, sorted and
+/// deduplicated so that equivalent filters share workers
+type FilterKey = Option<Vec<ExtraAllowedSyscall>>;
+
+struct Worker {
+    jobs: Sender<Job>,
+}
+
+static IDLE_WORKERS: OnceLock<Mutex<HashMap<FilterKey, Vec<Worker>>>> = OnceLock::new();
+
+fn idle_workers() -> &'static Mutex<HashMap<FilterKey, Vec<Worker>>> {
+    IDLE_WORKERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn filter_key(extra_allowed_syscalls: Option<Vec<ExtraAllowedSyscall>>) -> FilterKey {
+    extra_allowed_syscalls.map(|mut syscalls| {
+        syscalls.sort_unstable();
+        syscalls.dedup();
+        syscalls
+    })
+}
+
+impl Worker {
+    /// Spawns a worker thread and waits for it to apply the seccomp filter
+    /// for `key`.
+    fn spawn(key: &FilterKey) -> Result<Self> {
+        let filter = super::guest::get_seccomp_filter_for_host_function_worker_thread(key.clone())?;
+        let (jobs, receiver) = unbounded::<Job>();
+        let (ready_sender, ready) = bounded::<Result<()>>(1);
+        thread::Builder::new()
+            .name("Host Function Worker Thread".to_string())
+            .spawn(move || {
+                let applied: Result<()> = seccompiler::apply_filter(&filter).map_err(Into::into);
+                let failed = applied.is_err();
+                // The receiver is only dropped if the spawning thread gave up.
+                let _ = ready_sender.send(applied);
+                if failed {
+                    return;
+                }
+                for job in receiver {
+                    if !job() {
+                        break;
+                    }
+                }
+            })?;
+        ready
+            .recv()
+            .map_err(|_| new_error!("Host function worker thread exited before starting"))??;
+        Ok(Self { jobs })
+    }
+}
+
+/// Runs `f` on a worker thread with the seccomp filter for
+/// `extra_allowed_syscalls` applied, reusing an idle worker if there is one.
+///
+/// If `f` panics, the panic payload is returned and the worker is retired
+/// rather than returned to the pool, since a worker that was stopped in
+/// the middle of a host function, e.g. because it issued a disallowed
+/// syscall, may be left in an inconsistent state.
+pub(crate) fn run_on_worker<T, F>(
+    extra_allowed_syscalls: Option<Vec<ExtraAllowedSyscall>>,
+    f: F,
+) -> Result<std::result::Result<T, Box<dyn Any + Send>>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let key = filter_key(extra_allowed_syscalls);
+    let idle = idle_workers()
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_mut(&key)
+        .and_then(Vec::pop);
+    let worker = match idle {
+        Some(worker) => worker,
+        None => Worker::spawn(&key)?,
+    };
+
+    let (result_sender, result) = bounded(1);
+    let job: Job = Box::new(move || {
+        let outcome = catch_unwind(AssertUnwindSafe(f));
+        let reusable = outcome.is_ok();
+        let _ = result_sender.send(outcome);
+        reusable
+    });
+    worker
+        .jobs
+        .send(job)
+        .map_err(|_| new_error!("Host function worker thread exited unexpectedly"))?;
+    let outcome = result
+        .recv()
+        .map_err(|_| new_error!("Host function worker thread exited unexpectedly"))?;
+
+    if outcome.is_ok() {
+        let mut idle_workers = idle_workers().lock().unwrap_or_else(|e| e.into_inner());
+        let workers = idle_workers.entry(key).or_default();
+        if workers.len() < MAX_IDLE_WORKERS_PER_FILTER {
+            workers.push(worker);
+        }
+    }
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn current_thread_id() -> thread::ThreadId {
+        thread::current().id()
+    }
+
+    #[test]
+    fn workers_are_reused() {
+        // A syscall list unique to this test, so that other tests don't take
+        // its worker from the pool.
+        let syscalls = Some(vec![libc::SYS_getpid, libc::SYS_getppid]);
+        let first = run_on_worker(syscalls.clone(), current_thread_id)
+            .unwrap()
+            .unwrap();
+        assert_ne!(first, current_thread_id());
+        let reordered = Some(vec![libc::SYS_getppid, libc::SYS_getpid]);
+        let second = run_on_worker(reordered, current_thread_id)
+            .unwrap()
+            .unwrap();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn panicking_workers_are_retired() {
+        let syscalls = Some(vec![libc::SYS_getuid]);
+        let first = run_on_worker(syscalls.clone(), || -> thread::ThreadId {
+            panic!("host function panicked")
+        })
+        .unwrap();
+        assert!(first.is_err());
+        let panicked_workers = idle_workers()
+            .lock()
+            .unwrap()
+            .get(&filter_key(syscalls.clone()))
+            .map_or(0, Vec::len);
+        assert_eq!(panicked_workers, 0);
+        run_on_worker(syscalls, current_thread_id).unwrap().unwrap();
+    }
+}
@@ -0,0 +1,63 @@
+// Generated by hyperlight_host::func::client_gen. Do not edit.
+
+/// A typed client for the functions of a hyperlight guest
+pub struct CalculatorClient<'a> {
+    sandbox: &'a mut hyperlight_host::MultiUseSandbox,
+}
+
+impl<'a> CalculatorClient<'a> {
+    /// Create a client that calls guest functions on `sandbox`
+    pub fn new(sandbox: &'a mut hyperlight_host::MultiUseSandbox) -> Self {
+        Self { sandbox }
+    }
+
+    /// Call the guest function `calculator::Add`
+    pub fn calculator_add(&mut self, a: i32, b: i64) -> hyperlight_host::Result<i64> {
+        let result = self.sandbox.call_guest_function_by_name(
+            "calculator::Add",
+            hyperlight_host::func::ReturnType::Long,
+            Some(vec![hyperlight_host::func::ParameterValue::Int(a), hyperlight_host::func::ParameterValue::Long(b)]),
+        )?;
+        Ok(<i64>::try_from(result)?)
+    }
+
+    /// Call the guest function `calculator::Repeat`
+    pub fn calculator_repeat(&mut self, text: String, times: u32) -> hyperlight_host::Result<String> {
+        let result = self.sandbox.call_guest_function_by_name(
+            "calculator::Repeat",
+            hyperlight_host::func::ReturnType::String,
+            Some(vec![hyperlight_host::func::ParameterValue::String(text), hyperlight_host::func::ParameterValue::UInt(times)]),
+        )?;
+        Ok(<String>::try_from(result)?)
+    }
+
+    /// Call the guest function `calculator::Checksum`
+    pub fn calculator_checksum(&mut self, data: Vec<u8>) -> hyperlight_host::Result<u8> {
+        let result = self.sandbox.call_guest_function_by_name(
+            "calculator::Checksum",
+            hyperlight_host::func::ReturnType::UByte,
+            Some(vec![hyperlight_host::func::ParameterValue::VecBytes(data)]),
+        )?;
+        Ok(<u8>::try_from(result)?)
+    }
+
+    /// Call the guest function `calculator::IsEven`
+    pub fn calculator_is_even(&mut self, n: u64) -> hyperlight_host::Result<bool> {
+        let result = self.sandbox.call_guest_function_by_name(
+            "calculator::IsEven",
+            hyperlight_host::func::ReturnType::Bool,
+            Some(vec![hyperlight_host::func::ParameterValue::ULong(n)]),
+        )?;
+        Ok(<bool>::try_from(result)?)
+    }
+
+    /// Call the guest function `calculator::Ping`
+    pub fn calculator_ping(&mut self) -> hyperlight_host::Result<()> {
+        let result = self.sandbox.call_guest_function_by_name(
+            "calculator::Ping",
+            hyperlight_host::func::ReturnType::Void,
+            None,
+        )?;
+        Ok(<()>::try_from(result)?)
+    }
+}
@@ -14,7 +14,19 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
-use hyperlight_host::func::HostFunction1;
+// Generated from the simpleguest's calculator.hlidl, and compared with the
+// generator's output by `generated_calculator_client_is_up_to_date`
+#[rustfmt::skip]
+pub mod calculator_client;
+// Generated from the signatures of the simpleguest's calculator functions,
+// and compared with the generator's output by
+// `generated_client_from_guest_signatures`
+#[rustfmt::skip]
+pub mod calculator_signatures_client;
+
+use hyperlight_host::func::call_ctx::MultiUseGuestCallContext;
+use hyperlight_host::func::{HostFunction1, ParameterValue, ReturnType};
+use hyperlight_host::sandbox::SandboxConfiguration;
 use hyperlight_host::sandbox_state::sandbox::EvolvableSandbox;
 use hyperlight_host::sandbox_state::transition::Noop;
 use hyperlight_host::{GuestBinary, MultiUseSandbox, Result, UninitializedSandbox};
@@ -45,6 +57,24 @@ pub fn new_uninit_rust() -> Result<UninitializedSandbox> {
     )
 }
 
+/// A sandbox for the rust simpleguest with `cfg`
+pub fn new_rust_sandbox(cfg: SandboxConfiguration) -> MultiUseSandbox {
+    new_uninit_rust_with_config(cfg)
+        .unwrap()
+        .evolve(Noop::default())
+        .unwrap()
+}
+
+/// Like `new_uninit_rust`, with `cfg` rather than the default configuration
+pub fn new_uninit_rust_with_config(cfg: SandboxConfiguration) -> Result<UninitializedSandbox> {
+    UninitializedSandbox::new(
+        GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+        Some(cfg),
+        None,
+        None,
+    )
+}
+
 pub fn get_simpleguest_sandboxes(
     writer: Option<&dyn HostFunction1<String, i32>>, // An optional writer to make sure correct info is passed to the host printer
 ) -> Vec<MultiUseSandbox> {
@@ -141,6 +171,99 @@ pub fn get_callbackguest_uninit_sandboxes(
     ]
 }
 
+/// A guest function call made by `assert_backends_agree`
+pub struct DifferentialCall {
+    pub name: &'static str,
+    pub ret: ReturnType,
+    pub args: Option<Vec<ParameterValue>>,
+}
+
+/// The backends the simpleguest can be run on in this build, by name. When
+/// only the hypervisor is available it is run twice, so that the comparison
+/// still catches guest state that depends on more than the calls made.
+/// Stacks are scrubbed after every call, as an in-process guest runs on the
+/// host thread's stack and leaves nothing on its own.
+pub fn get_differential_backends() -> Vec<(&'static str, UninitializedSandbox)> {
+    let path = get_c_or_rust_simpleguest_path();
+    let mut cfg = SandboxConfiguration::default();
+    cfg.set_scrub_stacks(true);
+    let mut backends = vec![(
+        "hypervisor",
+        UninitializedSandbox::new(GuestBinary::FilePath(path.clone()), Some(cfg), None, None)
+            .unwrap(),
+    )];
+    #[cfg(inprocess)]
+    backends.push((
+        "in-process",
+        UninitializedSandbox::new(
+            GuestBinary::FilePath(path.clone()),
+            Some(cfg),
+            Some(hyperlight_host::SandboxRunOptions::RunInProcess(false)),
+            None,
+        )
+        .unwrap(),
+    ));
+    #[cfg(not(inprocess))]
+    backends.push((
+        "hypervisor (second sandbox)",
+        UninitializedSandbox::new(GuestBinary::FilePath(path), Some(cfg), None, None).unwrap(),
+    ));
+    backends
+}
+
+/// Make the same sequence of guest function `calls` on every one of
+/// `backends`, keeping the guest state between calls, and assert that each
+/// call returns the same result and leaves the guest heap, stack and static
+/// data with the same digests on every backend as on the first one.
+pub fn assert_backends_agree(
+    backends: Vec<(&'static str, UninitializedSandbox)>,
+    calls: &[DifferentialCall],
+) {
+    let mut ctxs: Vec<(&str, MultiUseGuestCallContext)> = backends
+        .into_iter()
+        .map(|(name, u_sbox)| {
+            let sbox: MultiUseSandbox = u_sbox.evolve(Noop::default()).unwrap();
+            (name, sbox.new_call_context())
+        })
+        .collect();
+    assert!(ctxs.len() > 1, "need at least two backends to compare");
+
+    for (i, call) in calls.iter().enumerate() {
+        let mut expected = None;
+        for (backend, ctx) in ctxs.iter_mut() {
+            // errors are compared by their debug output, as
+            // `HyperlightError` can't be compared directly
+            let res = format!("{:?}", ctx.call(call.name, call.ret, call.args.clone()));
+            let digest = ctx.guest_memory_digest().unwrap();
+            match &expected {
+                None => expected = Some((*backend, res, digest)),
+                Some((first, first_res, first_digest)) => {
+                    assert_eq!(
+                        first_res, &res,
+                        "call {i} to {} returned differently on {first} and {backend}",
+                        call.name
+                    );
+                    for (region, first_digest, digest) in [
+                        ("heap", &first_digest.heap, &digest.heap),
+                        ("stack", &first_digest.stack, &digest.stack),
+                        ("static data", &first_digest.data, &digest.data),
+                    ] {
+                        assert_eq!(
+                            first_digest, digest,
+                            "call {i} to {} left the guest {region} differently on {first} and {backend}",
+                            call.name
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    for (_, ctx) in ctxs {
+        ctx.finish().unwrap();
+    }
+}
+
 // returns the the path of simpleguest binary. Picks rust/c version depending on environment variable GUEST (or rust by default if unset)
 pub(crate) fn get_c_or_rust_simpleguest_path() -> String {
     let guest_type = std::env::var("GUEST").unwrap_or("rust".to_string());
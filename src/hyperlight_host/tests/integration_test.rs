@@ -14,19 +14,32 @@ See the License for the specific language governing permissions and
 limitations under the License.
 */
 
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hyperlight_common::flatbuffer_wrappers::function_types::ParameterType;
 use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+use hyperlight_common::guest_diagnostic::DiagnosticKind;
 use hyperlight_common::mem::PAGE_SIZE;
 use hyperlight_host::func::{ParameterValue, ReturnType, ReturnValue};
-use hyperlight_host::sandbox::SandboxConfiguration;
+use hyperlight_host::mem::memory_region::MemoryRegionFlags;
+use hyperlight_host::sandbox::malloc_trace::guest_leak_report;
+use hyperlight_host::sandbox::{SandboxConfiguration, SandboxState};
 use hyperlight_host::sandbox_state::sandbox::EvolvableSandbox;
 use hyperlight_host::sandbox_state::transition::Noop;
 use hyperlight_host::{GuestBinary, HyperlightError, MultiUseSandbox, UninitializedSandbox};
 use hyperlight_testing::simplelogger::{SimpleLogger, LOGGER};
-use hyperlight_testing::{c_simple_guest_as_string, simple_guest_as_string};
+use hyperlight_testing::{
+    c_simple_guest_as_string, simple_guest_as_string, simple_guest_with_heap_profiler_as_string,
+    simple_guest_with_malloc_trace_as_string,
+};
 use log::LevelFilter;
 
 pub mod common; // pub to disable dead_code warning
-use crate::common::{new_uninit, new_uninit_rust};
+use crate::common::{
+    assert_backends_agree, get_differential_backends, new_rust_sandbox, new_uninit,
+    new_uninit_rust, new_uninit_rust_with_config, DifferentialCall,
+};
 
 #[test]
 fn print_four_args_c_guest() {
@@ -87,6 +100,53 @@ fn guest_abort_with_context1() {
     );
 }
 
+#[test]
+fn guest_assert_and_trap() {
+    let mut sbox1 = new_uninit_rust().unwrap().evolve(Noop::default()).unwrap();
+    sbox1
+        .call_guest_function_by_name(
+            "GuestAssert",
+            ReturnType::Void,
+            Some(vec![ParameterValue::Int(1)]),
+        )
+        .unwrap();
+    let res = sbox1
+        .call_guest_function_by_name(
+            "GuestAssert",
+            ReturnType::Void,
+            Some(vec![ParameterValue::Int(-1)]),
+        )
+        .unwrap_err();
+    println!("{:?}", res);
+    let HyperlightError::GuestTrapped(diagnostic) = res else {
+        panic!("expected a guest trap, got {:?}", res);
+    };
+    assert_eq!(DiagnosticKind::Assertion, diagnostic.kind);
+    assert_eq!("value > 0", diagnostic.condition);
+    assert_eq!("value is -1", diagnostic.message);
+    assert!(diagnostic.file.ends_with("main.rs"));
+    assert!(diagnostic.line > 0);
+
+    let mut sbox1 = new_uninit_rust().unwrap().evolve(Noop::default()).unwrap();
+    let res = sbox1
+        .call_guest_function_by_name(
+            "GuestTrap",
+            ReturnType::Void,
+            Some(vec![
+                ParameterValue::UInt(42),
+                ParameterValue::String("bad input".to_string()),
+            ]),
+        )
+        .unwrap_err();
+    println!("{:?}", res);
+    let HyperlightError::GuestTrapped(diagnostic) = res else {
+        panic!("expected a guest trap, got {:?}", res);
+    };
+    assert_eq!(DiagnosticKind::Trap, diagnostic.kind);
+    assert_eq!(42, diagnostic.code);
+    assert_eq!("bad input", diagnostic.message);
+}
+
 #[test]
 fn guest_abort_with_context2() {
     let mut sbox1 = new_uninit().unwrap().evolve(Noop::default()).unwrap();
@@ -380,6 +440,76 @@ fn guard_page_check_2() {
     assert!(matches!(result, HyperlightError::StackOverflow()));
 }
 
+#[test]
+#[cfg(not(inprocess))]
+fn write_protected_code() {
+    // this test is rust-guest only
+    let mut sbox1 = new_uninit_rust().unwrap().evolve(Noop::default()).unwrap();
+    let result = sbox1
+        .call_guest_function_by_name("WriteToCode", ReturnType::Void, None)
+        .unwrap_err();
+    assert!(matches!(
+        result,
+        HyperlightError::GuestCodeModificationAttempt(_)
+    ));
+
+    // guests that patch their own code can turn the protection off
+    let mut cfg = SandboxConfiguration::default();
+    cfg.set_write_protect_code(false);
+    let mut sbox2: MultiUseSandbox = UninitializedSandbox::new(
+        GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+        Some(cfg),
+        None,
+        None,
+    )
+    .unwrap()
+    .evolve(Noop::default())
+    .unwrap();
+    sbox2
+        .call_guest_function_by_name("WriteToCode", ReturnType::Void, None)
+        .unwrap();
+}
+
+#[test]
+#[cfg(not(inprocess))]
+fn shadow_stack() {
+    // this test is rust-guest only
+    let mut sbox1 = new_uninit_rust().unwrap().evolve(Noop::default()).unwrap();
+    sbox1
+        .call_guest_function_by_name("ReturnToUnexpectedAddress", ReturnType::Void, None)
+        .unwrap();
+
+    let mut cfg = SandboxConfiguration::default();
+    cfg.set_shadow_stack_size(0x2000);
+    let sbox2: hyperlight_host::Result<MultiUseSandbox> = UninitializedSandbox::new(
+        GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+        Some(cfg),
+        None,
+        None,
+    )
+    .unwrap()
+    .evolve(Noop::default());
+    // only hosts with CET shadow stacks can run the guest with one
+    let mut sbox2 = match sbox2 {
+        Err(HyperlightError::ShadowStackNotSupported(_)) => return,
+        sbox2 => sbox2.unwrap(),
+    };
+    sbox2
+        .call_guest_function_by_name(
+            "Echo",
+            ReturnType::String,
+            Some(vec![ParameterValue::String("hi".to_string())]),
+        )
+        .unwrap();
+    let result = sbox2
+        .call_guest_function_by_name("ReturnToUnexpectedAddress", ReturnType::Void, None)
+        .unwrap_err();
+    assert!(matches!(
+        result,
+        HyperlightError::GuestControlFlowViolation(_)
+    ));
+}
+
 #[test]
 fn execute_on_stack() {
     let mut sbox1 = new_uninit().unwrap().evolve(Noop::default()).unwrap();
@@ -402,15 +532,103 @@ fn execute_on_stack() {
     }
 
     #[cfg(not(inprocess))]
-    {
-        let err = result.to_string();
-        assert!(
-            // exception that indicates a page fault
-            err.contains("EXCEPTION: 0xe")
-        );
+    assert_executed_non_executable_memory(result);
+}
+
+/// Check that a guest function call failed because the guest jumped to
+/// memory that is mapped non-executable
+#[cfg(not(inprocess))]
+#[track_caller]
+fn assert_executed_non_executable_memory(err: HyperlightError) {
+    match err {
+        HyperlightError::MemoryAccessViolation(_, tried, flags) => {
+            assert_eq!(MemoryRegionFlags::EXECUTE, tried);
+            assert!(!flags.contains(MemoryRegionFlags::EXECUTE));
+        }
+        err => panic!("Unexpected error: {:?}", err),
     }
 }
 
+#[test]
+#[cfg(not(inprocess))]
+fn execute_data() {
+    // this test is rust-guest only
+    let mut sbox1 = new_uninit_rust().unwrap().evolve(Noop::default()).unwrap();
+    let result = sbox1
+        .call_guest_function_by_name("ExecuteData", ReturnType::String, None)
+        .unwrap_err();
+    assert_executed_non_executable_memory(result);
+}
+
+#[test]
+#[cfg(not(inprocess))]
+fn jit_scratch() {
+    // this test is rust-guest only
+    let mut sbox1 = new_uninit_rust().unwrap().evolve(Noop::default()).unwrap();
+    sbox1
+        .call_guest_function_by_name("RunJitCode", ReturnType::Int, None)
+        .unwrap_err();
+
+    let mut cfg = SandboxConfiguration::default();
+    cfg.set_jit_scratch_size(0x1000);
+    let mut sbox2: MultiUseSandbox = UninitializedSandbox::new(
+        GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+        Some(cfg),
+        None,
+        None,
+    )
+    .unwrap()
+    .evolve(Noop::default())
+    .unwrap();
+    let result = sbox2
+        .call_guest_function_by_name("RunJitCode", ReturnType::Int, None)
+        .unwrap();
+    assert_eq!(ReturnValue::Int(42), result);
+
+    // the code the guest wrote is still read-only to it
+    let result = sbox2
+        .call_guest_function_by_name("WriteToCode", ReturnType::Void, None)
+        .unwrap_err();
+    assert!(matches!(
+        result,
+        HyperlightError::GuestCodeModificationAttempt(_)
+    ));
+}
+
+#[test]
+#[cfg(not(inprocess))]
+fn double_and_triple_faults() {
+    // this test is rust-guest only
+    let mut sbox: MultiUseSandbox = new_uninit_rust().unwrap().evolve(Noop::default()).unwrap();
+
+    let res = sbox
+        .call_guest_function_by_name("DoubleFault", ReturnType::Void, None)
+        .unwrap_err();
+    println!("{:?}", res);
+    let HyperlightError::GuestDoubleFault(message) = res else {
+        panic!("expected a double fault, got {:?}", res);
+    };
+    assert!(message.contains("Stack Pointer:"));
+    assert_eq!(SandboxState::Poisoned, sbox.state());
+
+    let mut sbox = sbox.recreate().unwrap();
+    let res = sbox
+        .call_guest_function_by_name("TripleFault", ReturnType::Void, None)
+        .unwrap_err();
+    println!("{:?}", res);
+    assert!(matches!(res, HyperlightError::GuestTripleFault(_)));
+    assert_eq!(SandboxState::Poisoned, sbox.state());
+
+    // the guest replaced its IDT, so only a new guest can run again
+    let mut sbox = sbox.recreate().unwrap();
+    sbox.call_guest_function_by_name(
+        "Echo",
+        ReturnType::String,
+        Some(vec![ParameterValue::String("hi".to_string())]),
+    )
+    .unwrap();
+}
+
 #[test]
 #[ignore] // ran from Justfile because requires feature "executable_heap"
 fn execute_on_heap() {
@@ -423,12 +641,10 @@ fn execute_on_heap() {
     assert!(result.is_ok());
 
     #[cfg(not(feature = "executable_heap"))]
-    {
-        assert!(result.is_err());
-        let err = result.unwrap_err();
+    assert!(result.is_err());
 
-        assert!(err.to_string().contains("EXCEPTION: 0xe"));
-    }
+    #[cfg(all(not(feature = "executable_heap"), not(inprocess)))]
+    assert_executed_non_executable_memory(result.unwrap_err());
 }
 
 #[test]
@@ -509,6 +725,10 @@ fn log_message() {
         assert_eq!(expected, LOGGER.num_log_calls());
 
         std::env::remove_var("RUST_LOG");
+
+        // Test changing the max log level on an already initialized sandbox
+        log_test_messages_live(level);
+        assert_eq!(expected, LOGGER.num_log_calls());
     }
 
     // Test that if no log level is set, the default is error
@@ -540,3 +760,533 @@ fn log_test_messages(levelfilter: Option<log::LevelFilter>) {
             .unwrap();
     }
 }
+
+fn log_test_messages_live(levelfilter: log::LevelFilter) {
+    let mut sbox = new_uninit().unwrap();
+    sbox.set_max_guest_log_level(log::LevelFilter::Off);
+    let mut sbox1 = sbox.evolve(Noop::default()).unwrap();
+    sbox1.set_max_guest_log_level(levelfilter);
+
+    LOGGER.clear_log_calls();
+    assert_eq!(0, LOGGER.num_log_calls());
+    for level in log::LevelFilter::iter() {
+        let message = format!("Hello from log_message level {}", level as i32);
+        sbox1
+            .call_guest_function_by_name(
+                "LogMessage",
+                ReturnType::Void,
+                Some(vec![
+                    ParameterValue::String(message.to_string()),
+                    ParameterValue::Int(level as i32),
+                ]),
+            )
+            .unwrap();
+    }
+}
+
+#[test]
+fn guest_log_records_are_rate_limited() {
+    let mut cfg = SandboxConfiguration::default();
+    cfg.set_max_guest_log_records_per_second(1);
+    let mut uninit = UninitializedSandbox::new(
+        GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+        Some(cfg),
+        None,
+        None,
+    )
+    .unwrap();
+    uninit.set_max_guest_log_level(LevelFilter::Info);
+    let mut sbox: MultiUseSandbox = uninit.evolve(Noop::default()).unwrap();
+    assert_eq!(0, sbox.dropped_guest_log_records());
+
+    for _ in 0..5 {
+        sbox.call_guest_function_by_name(
+            "LogMessage",
+            ReturnType::Void,
+            Some(vec![
+                ParameterValue::String("chatty guest".to_string()),
+                ParameterValue::Int(LevelFilter::Info as i32),
+            ]),
+        )
+        .unwrap();
+    }
+
+    // Only one record per second is forwarded, all the calls above should
+    // complete well within a second
+    assert!(sbox.dropped_guest_log_records() >= 4);
+}
+
+#[test]
+fn guest_log_ring_buffer() {
+    let new_sbox = |cfg: SandboxConfiguration| -> MultiUseSandbox {
+        let mut uninit = new_uninit_rust_with_config(cfg).unwrap();
+        uninit.set_max_guest_log_level(LevelFilter::Info);
+        uninit.evolve(Noop::default()).unwrap()
+    };
+    let log_repeatedly = |sbox: &mut MultiUseSandbox, count: i32| {
+        sbox.call_guest_function_by_name(
+            "LogRepeatedly",
+            ReturnType::Void,
+            Some(vec![
+                ParameterValue::String("ring".to_string()),
+                ParameterValue::Int(count),
+            ]),
+        )
+        .unwrap();
+    };
+
+    // the records in the ring reach the host, where all but one are
+    // dropped by the rate limit
+    let mut cfg = SandboxConfiguration::default();
+    cfg.set_guest_log_ring_size(0x10000);
+    cfg.set_max_guest_log_records_per_second(1);
+    let mut sbox = new_sbox(cfg);
+    log_repeatedly(&mut sbox, 5);
+    assert!(sbox.dropped_guest_log_records() >= 4);
+    assert_eq!(0, sbox.overflowed_guest_log_records());
+
+    // records that don't fit in the ring are dropped and counted
+    let mut cfg = SandboxConfiguration::default();
+    cfg.set_guest_log_ring_size(0x1000);
+    let mut sbox = new_sbox(cfg);
+    log_repeatedly(&mut sbox, 1000);
+    let overflowed = sbox.overflowed_guest_log_records();
+    assert!(overflowed > 0 && overflowed < 1000);
+
+    // the ring is drained after every call
+    log_repeatedly(&mut sbox, 1);
+    assert_eq!(overflowed, sbox.overflowed_guest_log_records());
+}
+
+#[test]
+fn call_payload_size_limits_are_enforced() {
+    let echo = |sbox: &mut MultiUseSandbox, len: usize| {
+        sbox.call_guest_function_by_name(
+            "Echo",
+            ReturnType::String,
+            Some(vec![ParameterValue::String("a".repeat(len))]),
+        )
+    };
+
+    let mut cfg = SandboxConfiguration::default();
+    cfg.set_max_parameter_size(64);
+    let mut sbox = new_rust_sandbox(cfg);
+    assert!(echo(&mut sbox, 64).is_ok());
+    let res = echo(&mut sbox, 65).unwrap_err();
+    assert!(
+        matches!(res, HyperlightError::PayloadTooLarge(_, size, max_size) if size == 65 && max_size == 64)
+    );
+
+    let mut cfg = SandboxConfiguration::default();
+    cfg.set_max_call_payload_size(256);
+    let mut sbox = new_rust_sandbox(cfg);
+    assert!(echo(&mut sbox, 16).is_ok());
+    let res = echo(&mut sbox, 512).unwrap_err();
+    assert!(matches!(res, HyperlightError::PayloadTooLarge(_, _, 256)));
+
+    // Without an explicit limit, calls larger than the input buffer get the same error
+    let mut sbox = new_rust_sandbox(SandboxConfiguration::default());
+    let res = echo(&mut sbox, SandboxConfiguration::DEFAULT_INPUT_SIZE).unwrap_err();
+    assert!(matches!(res, HyperlightError::PayloadTooLarge(..)));
+}
+
+#[test]
+fn streaming_host_function() {
+    const CHUNK_SIZE: usize = 1024 * 1024;
+    let chunk = |i: usize| -> Vec<u8> { (0..CHUNK_SIZE).map(|j| ((i + j) % 251) as u8).collect() };
+
+    let mut uninit = new_uninit_rust().unwrap();
+    uninit
+        .register_streaming_host_function(
+            "StreamFile",
+            Some(vec![ParameterType::Int]),
+            move |args| {
+                let Some(ParameterValue::Int(chunks)) = args.first() else {
+                    return Err(hyperlight_host::new_error!("expected a chunk count"));
+                };
+                Ok(Box::new((0..*chunks as usize).map(move |i| Ok(chunk(i)))))
+            },
+        )
+        .unwrap();
+    let mut sbox: MultiUseSandbox = uninit.evolve(Noop::default()).unwrap();
+
+    // 4MiB is far more than fits in the input data buffer at once
+    let expected: u64 = (0..4).flat_map(chunk).map(u64::from).sum();
+    let res = sbox
+        .call_guest_function_by_name(
+            "SumHostStream",
+            ReturnType::ULong,
+            Some(vec![
+                ParameterValue::String("StreamFile".to_string()),
+                ParameterValue::Int(4),
+            ]),
+        )
+        .unwrap();
+    assert_eq!(ReturnValue::ULong(expected), res);
+
+    // an empty stream finishes straight away
+    let res = sbox
+        .call_guest_function_by_name(
+            "SumHostStream",
+            ReturnType::ULong,
+            Some(vec![
+                ParameterValue::String("StreamFile".to_string()),
+                ParameterValue::Int(0),
+            ]),
+        )
+        .unwrap();
+    assert_eq!(ReturnValue::ULong(0), res);
+}
+
+#[test]
+fn guest_progress_reports() {
+    let mut cfg = SandboxConfiguration::default();
+    cfg.set_min_progress_step(10);
+    let mut sbox: MultiUseSandbox = UninitializedSandbox::new(
+        GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+        Some(cfg),
+        None,
+        None,
+    )
+    .unwrap()
+    .evolve(Noop::default())
+    .unwrap();
+
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let seen = reports.clone();
+    sbox.subscribe_progress(move |report| seen.lock().unwrap().push(report.percent))
+        .unwrap();
+    assert_eq!(None, sbox.last_heartbeat());
+
+    // the guest reports every percent, but only exits to the host every 10
+    let report_progress = |sbox: &mut MultiUseSandbox| {
+        sbox.call_guest_function_by_name(
+            "ReportProgress",
+            ReturnType::Void,
+            Some(vec![ParameterValue::Int(100)]),
+        )
+        .unwrap();
+    };
+    report_progress(&mut sbox);
+    let expected: Vec<u8> = (0..=100).step_by(10).collect();
+    assert_eq!(expected, *reports.lock().unwrap());
+    assert!(sbox.last_heartbeat().is_some());
+
+    // throttling starts again with each call
+    report_progress(&mut sbox);
+    assert_eq!(2 * expected.len(), reports.lock().unwrap().len());
+
+    // including calls that don't restore the guest's memory in between
+    let mut ctx = sbox.new_call_context();
+    for _ in 0..2 {
+        ctx.call(
+            "ReportProgress",
+            ReturnType::Void,
+            Some(vec![ParameterValue::Int(100)]),
+        )
+        .unwrap();
+    }
+    ctx.finish().unwrap();
+    assert_eq!(4 * expected.len(), reports.lock().unwrap().len());
+}
+
+#[test]
+fn heap_profile_finds_the_leaking_guest_function() {
+    // the symbol map written next to the guest by `cargo hyperlight-guest`
+    // is loaded with it, so sites are found past the allocator
+    let mut sbox: MultiUseSandbox = UninitializedSandbox::new(
+        GuestBinary::FilePath(simple_guest_with_heap_profiler_as_string().unwrap()),
+        None,
+        None,
+        None,
+    )
+    .unwrap()
+    .evolve(Noop::default())
+    .unwrap();
+
+    let leaks_in = |sbox: &MultiUseSandbox, function: &str| {
+        let profile = sbox.last_heap_profile().unwrap();
+        profile
+            .sites
+            .iter()
+            .filter(|site| {
+                site.symbol
+                    .as_deref()
+                    .is_some_and(|symbol| symbol.starts_with(function))
+            })
+            .map(|site| site.live_bytes)
+            .sum::<u64>()
+    };
+
+    sbox.call_guest_function_by_name(
+        "MallocAndFree",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(1000)]),
+    )
+    .unwrap();
+    assert_eq!(0, leaks_in(&sbox, "simpleguest::malloc_and_free"));
+
+    sbox.call_guest_function_by_name(
+        "LeakMemory",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(1000)]),
+    )
+    .unwrap();
+    assert_eq!(1000, leaks_in(&sbox, "simpleguest::leak_memory"));
+}
+
+#[test]
+fn malloc_trace_finds_the_leaking_guest_function() {
+    let mut sbox: MultiUseSandbox = UninitializedSandbox::new(
+        GuestBinary::FilePath(simple_guest_with_malloc_trace_as_string().unwrap()),
+        None,
+        None,
+        None,
+    )
+    .unwrap()
+    .evolve(Noop::default())
+    .unwrap();
+
+    sbox.call_guest_function_by_name(
+        "MallocAndFree",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(1000)]),
+    )
+    .unwrap();
+    hyperlight_host::assert_no_guest_leaks!(sbox);
+
+    sbox.call_guest_function_by_name(
+        "LeakMemory",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(1000)]),
+    )
+    .unwrap();
+    let trace = sbox.last_malloc_trace().unwrap();
+    assert!(trace.is_complete());
+    let leaks = trace.leaks();
+    assert_eq!(
+        vec![1000],
+        leaks.iter().map(|leak| leak.size).collect::<Vec<_>>()
+    );
+    let report = guest_leak_report(sbox.last_malloc_trace()).unwrap();
+    assert!(report.starts_with("the guest leaked 1000 bytes in 1 allocations"));
+
+    // a sandbox recreated from the same source keeps its own trace
+    let mut recreated = sbox.recreate().unwrap();
+    recreated
+        .call_guest_function_by_name(
+            "MallocAndFree",
+            ReturnType::Int,
+            Some(vec![ParameterValue::Int(1000)]),
+        )
+        .unwrap();
+    hyperlight_host::assert_no_guest_leaks!(recreated);
+}
+
+#[test]
+fn guest_call_deadline() {
+    let mut cfg = SandboxConfiguration::default();
+    cfg.set_max_execution_time(Duration::from_secs(10));
+    let mut sbox: MultiUseSandbox = UninitializedSandbox::new(
+        GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+        Some(cfg),
+        None,
+        None,
+    )
+    .unwrap()
+    .evolve(Noop::default())
+    .unwrap();
+
+    // a cooperative guest returns once the deadline gets close
+    let start = Instant::now();
+    let res = sbox
+        .call_guest_function_by_name_with_deadline(
+            "WorkUntilDeadline",
+            ReturnType::ULong,
+            Some(vec![ParameterValue::ULong(200_000)]),
+            start + Duration::from_millis(300),
+        )
+        .unwrap();
+    assert!(matches!(res, ReturnValue::ULong(steps) if steps > 0));
+    assert!(start.elapsed() < Duration::from_millis(300));
+
+    // one that doesn't is cancelled at the deadline, well before its
+    // maximum execution time
+    let start = Instant::now();
+    let res = sbox.call_guest_function_by_name_with_deadline(
+        "Spin",
+        ReturnType::Void,
+        None,
+        start + Duration::from_millis(100),
+    );
+    assert!(matches!(
+        res,
+        Err(HyperlightError::ExecutionCanceledByHost())
+    ));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    sbox.reset().unwrap();
+
+    // without a deadline, the guest has until its maximum execution time
+    let res = sbox
+        .call_guest_function_by_name(
+            "WorkUntilDeadline",
+            ReturnType::ULong,
+            Some(vec![ParameterValue::ULong(9_900_000)]),
+        )
+        .unwrap();
+    assert!(matches!(res, ReturnValue::ULong(steps) if steps > 0));
+}
+
+#[test]
+fn guest_function_timeout() {
+    let mut cfg = SandboxConfiguration::default();
+    cfg.set_max_execution_time(Duration::from_secs(10));
+    let mut sbox: MultiUseSandbox = UninitializedSandbox::new(
+        GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+        Some(cfg),
+        None,
+        None,
+    )
+    .unwrap()
+    .evolve(Noop::default())
+    .unwrap();
+    // simpleguest declares no timeouts in its metadata
+    assert_eq!(None, sbox.function_timeout("Spin"));
+
+    sbox.set_function_timeout("Spin", Duration::from_millis(100));
+    let start = Instant::now();
+    let res = sbox.call_guest_function_by_name("Spin", ReturnType::Void, None);
+    assert!(matches!(
+        res,
+        Err(HyperlightError::ExecutionCanceledByHost())
+    ));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // the timeout only applies to the function it was set for, and is
+    // kept when the sandbox is recreated
+    let mut sbox = sbox.recreate().unwrap();
+    assert_eq!(
+        Some(Duration::from_millis(100)),
+        sbox.function_timeout("Spin")
+    );
+    let res = sbox
+        .call_guest_function_by_name(
+            "WorkUntilDeadline",
+            ReturnType::ULong,
+            Some(vec![ParameterValue::ULong(9_900_000)]),
+        )
+        .unwrap();
+    assert!(matches!(res, ReturnValue::ULong(steps) if steps > 0));
+
+    // the guest sees the timeout as the time the call has left, so it
+    // returns before it is cancelled, rather than working towards the
+    // maximum execution time
+    sbox.set_function_timeout("WorkUntilDeadline", Duration::from_millis(300));
+    let start = Instant::now();
+    let res = sbox.call_guest_function_by_name(
+        "WorkUntilDeadline",
+        ReturnType::ULong,
+        Some(vec![ParameterValue::ULong(200_000)]),
+    );
+    assert!(matches!(res, Ok(ReturnValue::ULong(steps)) if steps > 0));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    sbox.clear_function_timeout("Spin");
+    assert_eq!(None, sbox.function_timeout("Spin"));
+}
+
+#[test]
+fn pause_and_resume() {
+    let mut cfg = SandboxConfiguration::default();
+    cfg.set_max_execution_time(Duration::from_secs(2));
+    let mut sbox: MultiUseSandbox = UninitializedSandbox::new(
+        GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+        Some(cfg),
+        None,
+        None,
+    )
+    .unwrap()
+    .evolve(Noop::default())
+    .unwrap();
+    let pause = sbox.pause_handle();
+
+    // a call made while the sandbox is paused waits until it is resumed
+    pause.pause().unwrap();
+    std::thread::scope(|s| {
+        let call = s.spawn(|| {
+            sbox.call_guest_function_by_name(
+                "Echo",
+                ReturnType::String,
+                Some(vec![ParameterValue::String("paused".to_string())]),
+            )
+        });
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(!call.is_finished());
+        pause.resume();
+        assert_eq!(
+            ReturnValue::String("paused".to_string()),
+            call.join().unwrap().unwrap()
+        );
+    });
+
+    // a running guest stops where it is and carries on once resumed, and
+    // the time it is paused for, even longer than its maximum execution
+    // time, doesn't count towards it
+    std::thread::scope(|s| {
+        let call = s.spawn(|| {
+            sbox.call_guest_function_by_name(
+                "WorkUntilDeadline",
+                ReturnType::ULong,
+                Some(vec![ParameterValue::ULong(1_000_000)]),
+            )
+        });
+        std::thread::sleep(Duration::from_millis(100));
+        pause.pause().unwrap();
+        assert!(pause.is_paused());
+        std::thread::sleep(Duration::from_secs(3));
+        assert!(!call.is_finished());
+        pause.resume();
+        let res = call.join().unwrap();
+        assert!(matches!(res, Ok(ReturnValue::ULong(steps)) if steps > 0));
+    });
+    let res = sbox
+        .call_guest_function_by_name(
+            "Echo",
+            ReturnType::String,
+            Some(vec![ParameterValue::String("resumed".to_string())]),
+        )
+        .unwrap();
+    assert_eq!(ReturnValue::String("resumed".to_string()), res);
+}
+
+#[test]
+fn backends_agree() {
+    let calls = [
+        DifferentialCall {
+            name: "Echo",
+            ret: ReturnType::String,
+            args: Some(vec![ParameterValue::String("hello".to_string())]),
+        },
+        DifferentialCall {
+            name: "CallMalloc",
+            ret: ReturnType::Int,
+            args: Some(vec![ParameterValue::Int(4096)]),
+        },
+        DifferentialCall {
+            name: "PrintOutput",
+            ret: ReturnType::Int,
+            args: Some(vec![ParameterValue::String("differential\n".to_string())]),
+        },
+        DifferentialCall {
+            name: "NonExistentFunction",
+            ret: ReturnType::Int,
+            args: None,
+        },
+        DifferentialCall {
+            name: "Echo",
+            ret: ReturnType::String,
+            args: Some(vec![ParameterValue::String("goodbye".to_string())]),
+        },
+    ];
+    assert_backends_agree(get_differential_backends(), &calls);
+}
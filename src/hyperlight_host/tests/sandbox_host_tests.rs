@@ -17,9 +17,22 @@ limitations under the License.
 use core::f64;
 use std::sync::{Arc, Mutex};
 
-use common::new_uninit;
-use hyperlight_host::func::{HostFunction1, ParameterValue, ReturnType, ReturnValue};
-use hyperlight_host::sandbox::SandboxConfiguration;
+use common::calculator_client::CalculatorClient;
+use common::calculator_signatures_client::CalculatorSignaturesClient;
+use common::{new_rust_sandbox, new_uninit, new_uninit_rust};
+use hyperlight_common::flatbuffer_wrappers::function_types::ParameterType;
+use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
+use hyperlight_common::interface::InterfaceDefinition;
+use hyperlight_common::payload_compression::PayloadCompression;
+use hyperlight_host::func::client_gen::{
+    generate_guest_client, generate_guest_client_from_interface,
+};
+use hyperlight_host::func::{
+    ArgRule, ArgumentValidation, CachePolicy, GuestCallAction, GuestCallCache, GuestFunctionPolicy,
+    HostFunction1, HostFunction2, ParameterValue, RedactionPolicy, ReturnType, ReturnValue,
+    Sensitive,
+};
+use hyperlight_host::sandbox::{SandboxConfiguration, SandboxState};
 use hyperlight_host::sandbox_state::sandbox::EvolvableSandbox;
 use hyperlight_host::sandbox_state::transition::Noop;
 use hyperlight_host::{
@@ -353,13 +366,26 @@ fn incorrect_parameter_type() {
             ]),
         );
 
-        assert!(matches!(
-            res.unwrap_err(),
+        match res.unwrap_err() {
+            // the rust guest reports its function signatures, so the host
+            // rejects the call without entering the guest
+            HyperlightError::GuestFunctionParameterTypeMismatch(name, expected, actual, index) => {
+                assert_eq!("Echo", name);
+                assert_eq!(vec![ParameterType::String], expected);
+                assert_eq!(vec![ParameterType::Int], actual);
+                assert_eq!(0, index);
+            }
+            // functions registered through the C API are only checked by
+            // the guest
             HyperlightError::GuestError(
                 hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode::GuestFunctionParameterTypeMismatch,
+                msg,
+            ) => assert_eq!(
+                "Expected parameter type String for parameter index 0 of function Echo but got Int.",
                 msg
-            ) if msg == "Expected parameter type String for parameter index 0 of function Echo but got Int."
-        ));
+            ),
+            e => panic!("Unexpected error {:?}", e),
+        }
     }
 }
 
@@ -375,13 +401,19 @@ fn incorrect_parameter_num() {
                 ParameterValue::Int(2),
             ]),
         );
-        assert!(matches!(
-            res.unwrap_err(),
+        match res.unwrap_err() {
+            HyperlightError::GuestFunctionParameterTypeMismatch(name, expected, actual, index) => {
+                assert_eq!("Echo", name);
+                assert_eq!(vec![ParameterType::String], expected);
+                assert_eq!(vec![ParameterType::String, ParameterType::Int], actual);
+                assert_eq!(1, index);
+            }
             HyperlightError::GuestError(
                 hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode::GuestFunctionIncorrecNoOfParameters,
-                msg
-            ) if msg == "Called function Echo with 2 parameters but it takes 1."
-        ));
+                msg,
+            ) => assert_eq!("Called function Echo with 2 parameters but it takes 1.", msg),
+            e => panic!("Unexpected error {:?}", e),
+        }
     }
 }
 
@@ -629,3 +661,651 @@ fn host_function_error() -> Result<()> {
     }
     Ok(())
 }
+
+#[test]
+fn guest_can_discover_host_functions() -> Result<()> {
+    let mut sandbox = new_uninit_rust()?;
+    let host_func1 = Arc::new(Mutex::new(|msg: String| -> Result<i32> {
+        Ok(msg.len() as i32)
+    }));
+    host_func1.register(&mut sandbox, "HostMethod1")?;
+    let mut init_sandbox: MultiUseSandbox = sandbox.evolve(Noop::default())?;
+
+    for (name, expected) in [
+        ("HostMethod1", true),
+        ("HostPrint", true),
+        ("HostListFunctions", true),
+        ("NotAHostFunction", false),
+    ] {
+        let res = init_sandbox.call_guest_function_by_name(
+            "HasHostFunction",
+            ReturnType::Bool,
+            Some(vec![ParameterValue::String(name.to_string())]),
+        )?;
+        assert_eq!(ReturnValue::Bool(expected), res, "{}", name);
+    }
+    Ok(())
+}
+
+#[test]
+fn guest_std_layer_runs_in_the_guest() -> Result<()> {
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let writer = {
+        let messages = messages.clone();
+        Arc::new(Mutex::new(move |msg: String| -> Result<i32> {
+            let len = msg.len();
+            messages.lock().unwrap().push(msg);
+            Ok(len as i32)
+        }))
+    };
+    let mut sandbox: MultiUseSandbox = UninitializedSandbox::new(
+        GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+        None,
+        None,
+        Some(&writer),
+    )?
+    .evolve(Noop::default())?;
+
+    let res = sandbox.call_guest_function_by_name(
+        "CountDistinctWords",
+        ReturnType::Int,
+        Some(vec![ParameterValue::String(
+            "the cat and the dog and the bird".to_string(),
+        )]),
+    )?;
+    assert_eq!(ReturnValue::Int(5), res);
+    assert_eq!(
+        "most common word: the (3)\n",
+        messages.lock().unwrap().concat()
+    );
+    Ok(())
+}
+
+#[test]
+fn guest_can_check_host_features() -> Result<()> {
+    let mut sandbox = new_uninit_rust()?;
+    sandbox.declare_feature("network")?;
+    sandbox.declare_feature("clock")?;
+    sandbox.declare_feature("network")?;
+    assert!(sandbox.declare_feature("").is_err());
+    let mut init_sandbox: MultiUseSandbox = sandbox.evolve(Noop::default())?;
+
+    for (name, expected) in [("network", true), ("clock", true), ("gpu", false)] {
+        let res = init_sandbox.call_guest_function_by_name(
+            "HostHasFeature",
+            ReturnType::Bool,
+            Some(vec![ParameterValue::String(name.to_string())]),
+        )?;
+        assert_eq!(ReturnValue::Bool(expected), res, "{}", name);
+    }
+    Ok(())
+}
+
+#[test]
+fn hypervisor_backend_replaces_the_built_in_drivers() -> Result<()> {
+    let mut sandbox = new_uninit_rust()?;
+    let partitions = Arc::new(Mutex::new(Vec::new()));
+    {
+        let partitions = partitions.clone();
+        sandbox.set_hypervisor_backend(move |partition| {
+            partitions.lock().unwrap().push(partition);
+            Err(new_error!("out-of-tree backend has no vCPU"))
+        });
+    }
+    let res: Result<MultiUseSandbox> = sandbox.evolve(Noop::default());
+    assert!(
+        matches!(&res, Err(HyperlightError::Error(msg)) if msg.contains("no vCPU")),
+        "{:?}",
+        res.err()
+    );
+
+    let partitions = partitions.lock().unwrap();
+    assert_eq!(1, partitions.len());
+    let partition = &partitions[0];
+    assert!(!partition.regions.is_empty());
+    assert!(partition.entrypoint_addr > partition.pml4_addr);
+    assert!(partition.regions.iter().any(|region| region
+        .guest_region()
+        .contains(&(partition.entrypoint_addr as usize))));
+    Ok(())
+}
+
+#[test]
+fn small_integer_parameters_and_return_values() -> Result<()> {
+    let mut sandbox = new_uninit_rust()?;
+    let host_add = Arc::new(Mutex::new(|a: i16, b: i8| -> Result<i16> {
+        Ok(a.wrapping_add(b.into()))
+    }));
+    host_add.register(&mut sandbox, "HostAddSmallIntegers")?;
+    let mut init_sandbox: MultiUseSandbox = sandbox.evolve(Noop::default())?;
+
+    let res = init_sandbox.call_guest_function_by_name(
+        "AddSmallIntegers",
+        ReturnType::Short,
+        Some(vec![ParameterValue::Short(-300), ParameterValue::Byte(-5)]),
+    )?;
+    assert_eq!(ReturnValue::Short(-305), res);
+    let res = init_sandbox.call_guest_function_by_name(
+        "AddSmallIntegers",
+        ReturnType::Short,
+        Some(vec![
+            ParameterValue::Short(i16::MAX),
+            ParameterValue::Byte(1),
+        ]),
+    )?;
+    assert_eq!(ReturnValue::Short(i16::MIN), res);
+
+    let res = init_sandbox.call_guest_function_by_name(
+        "PackBytes",
+        ReturnType::UShort,
+        Some(vec![
+            ParameterValue::UByte(0xab),
+            ParameterValue::UByte(0xcd),
+        ]),
+    )?;
+    assert_eq!(ReturnValue::UShort(0xabcd), res);
+
+    // small integers are not widened, so passing an i32 fails
+    let res = init_sandbox.call_guest_function_by_name(
+        "PackBytes",
+        ReturnType::UShort,
+        Some(vec![ParameterValue::Int(0xab), ParameterValue::UByte(0xcd)]),
+    );
+    assert!(matches!(
+        res,
+        Err(HyperlightError::GuestFunctionParameterTypeMismatch(..))
+    ));
+    Ok(())
+}
+
+#[test]
+fn host_function_panic_is_a_guest_error() -> Result<()> {
+    let mut sandbox = new_uninit_rust()?;
+    let host_add = Arc::new(Mutex::new(|a: i32, b: i32| -> Result<i32> {
+        if a == 0 {
+            panic!("can't add to zero");
+        }
+        Ok(a + b)
+    }));
+    host_add.register(&mut sandbox, "HostAdd")?;
+    let panics = Arc::new(Mutex::new(Vec::new()));
+    let panics_clone = panics.clone();
+    sandbox.set_host_function_panic_callback(move |name, message| {
+        panics_clone
+            .lock()
+            .unwrap()
+            .push(format!("{name}: {message}"));
+    })?;
+    let mut init_sandbox: MultiUseSandbox = sandbox.evolve(Noop::default())?;
+
+    let res = init_sandbox.call_guest_function_by_name(
+        "Add",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(0), ParameterValue::Int(1)]),
+    );
+    assert!(matches!(
+        res,
+        Err(HyperlightError::GuestError(ErrorCode::HostFunctionError, msg))
+            if msg.contains("can't add to zero")
+    ));
+    assert_eq!(
+        vec!["HostAdd: can't add to zero".to_string()],
+        *panics.lock().unwrap()
+    );
+    assert_eq!(SandboxState::Ready, init_sandbox.state());
+
+    // both the sandbox and the host function that panicked can still be
+    // used
+    let res = init_sandbox.call_guest_function_by_name(
+        "Add",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(1), ParameterValue::Int(2)]),
+    )?;
+    assert_eq!(ReturnValue::Int(3), res);
+    Ok(())
+}
+
+#[test]
+fn invalid_host_function_arguments_are_a_guest_error() -> Result<()> {
+    let mut sandbox = new_uninit_rust()?;
+    let calls = Arc::new(Mutex::new(0));
+    let calls_clone = calls.clone();
+    let host_add = Arc::new(Mutex::new(move |a: i32, b: i32| -> Result<i32> {
+        *calls_clone.lock().unwrap() += 1;
+        Ok(a + b)
+    }));
+    host_add.register(&mut sandbox, "HostAdd")?;
+    let validation = ArgumentValidation::new().param(0, ArgRule::range(0, 100));
+    sandbox.set_host_function_argument_validation("HostAdd", validation)?;
+    // rules must be for a registered function and one of its parameters
+    assert!(sandbox
+        .set_host_function_argument_validation(
+            "HostAdd",
+            ArgumentValidation::new().param(2, ArgRule::range(0, 1))
+        )
+        .is_err());
+    assert!(sandbox
+        .set_host_function_argument_validation("HostSubtract", ArgumentValidation::new())
+        .is_err());
+    let mut init_sandbox: MultiUseSandbox = sandbox.evolve(Noop::default())?;
+
+    let res = init_sandbox.call_guest_function_by_name(
+        "Add",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(101), ParameterValue::Int(1)]),
+    );
+    assert!(matches!(
+        res,
+        Err(HyperlightError::GuestError(ErrorCode::HostFunctionArgumentInvalid, msg))
+            if msg.contains("101 is not in the range 0..=100")
+    ));
+    // the host function isn't called, and the sandbox can still be used
+    assert_eq!(0, *calls.lock().unwrap());
+    assert_eq!(SandboxState::Ready, init_sandbox.state());
+
+    let res = init_sandbox.call_guest_function_by_name(
+        "Add",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(100), ParameterValue::Int(1)]),
+    )?;
+    assert_eq!(ReturnValue::Int(101), res);
+    assert_eq!(1, *calls.lock().unwrap());
+    Ok(())
+}
+
+#[test]
+fn sensitive_host_function_arguments_are_redacted() -> Result<()> {
+    let mut sandbox = new_uninit_rust()?;
+    let host_add = Arc::new(Mutex::new(|a: Sensitive<i32>, b: i32| -> Result<i32> {
+        Ok(*a + b)
+    }));
+    host_add.register(&mut sandbox, "HostAdd")?;
+    let validation = ArgumentValidation::new().param(0, ArgRule::range(0, 100));
+    sandbox.set_host_function_argument_validation("HostAdd", validation)?;
+    let mut init_sandbox: MultiUseSandbox = sandbox.evolve(Noop::default())?;
+    init_sandbox.set_redaction_policy(RedactionPolicy::default());
+
+    // the host function is called with the value the guest passed
+    let res = init_sandbox.call_guest_function_by_name(
+        "Add",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(40), ParameterValue::Int(2)]),
+    )?;
+    assert_eq!(ReturnValue::Int(42), res);
+
+    // but the value isn't in the error for an invalid argument
+    let res = init_sandbox.call_guest_function_by_name(
+        "Add",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(101), ParameterValue::Int(1)]),
+    );
+    assert!(matches!(
+        res,
+        Err(HyperlightError::GuestError(ErrorCode::HostFunctionArgumentInvalid, msg))
+            if msg.contains("argument 0 is invalid: <redacted>") && !msg.contains("101")
+    ));
+    Ok(())
+}
+
+#[test]
+fn namespaced_guest_functions() -> Result<()> {
+    let mut sandbox: MultiUseSandbox = new_uninit_rust()?.evolve(Noop::default())?;
+
+    assert_eq!(
+        vec!["math::EchoDouble", "math::EchoFloat"],
+        sandbox.guest_function_names("math")
+    );
+    assert!(sandbox
+        .guest_function_names("")
+        .contains(&"strings::Echo".to_string()));
+
+    let mut policy = GuestFunctionPolicy::deny_all();
+    policy.allow("math::*");
+    policy.deny("math::EchoFloat");
+    sandbox.set_guest_function_policy(policy);
+
+    let res = sandbox.call_guest_function_by_name(
+        "math::EchoDouble",
+        ReturnType::Double,
+        Some(vec![ParameterValue::Double(2.5)]),
+    )?;
+    assert_eq!(ReturnValue::Double(2.5), res);
+
+    for name in ["math::EchoFloat", "strings::Echo", "Echo"] {
+        let res = sandbox.call_guest_function_by_name(
+            name,
+            ReturnType::String,
+            Some(vec![ParameterValue::String("hello".to_string())]),
+        );
+        assert!(
+            matches!(res, Err(HyperlightError::GuestFunctionNotPermitted(ref n)) if n == name),
+            "{}",
+            name
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn versioned_guest_functions() -> Result<()> {
+    let mut sandbox: MultiUseSandbox = new_uninit_rust()?.evolve(Noop::default())?;
+    assert_eq!(vec![1, 2], sandbox.guest_function_versions("Compute"));
+
+    // a call picks a version by name
+    let res = sandbox.call_guest_function_by_name(
+        "Compute@v1",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(21)]),
+    )?;
+    assert_eq!(ReturnValue::Int(42), res);
+
+    // without a version, the newest one is called
+    let res = sandbox.call_guest_function_by_name(
+        "Compute",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(6), ParameterValue::Int(7)]),
+    )?;
+    assert_eq!(ReturnValue::Int(42), res);
+
+    // unless another default is set
+    sandbox.set_default_guest_function_version("Compute", 1)?;
+    let res = sandbox.call_guest_function_by_name(
+        "Compute",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(4)]),
+    )?;
+    assert_eq!(ReturnValue::Int(8), res);
+    let mut sandbox = sandbox.recreate()?;
+    let res = sandbox.call_guest_function_by_name(
+        "Compute",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(5)]),
+    )?;
+    assert_eq!(ReturnValue::Int(10), res);
+    sandbox.clear_default_guest_function_version("Compute");
+
+    // versions the guest didn't register are rejected without a call
+    let res = sandbox.set_default_guest_function_version("Compute", 3);
+    assert!(matches!(
+        res,
+        Err(HyperlightError::GuestFunctionVersionNotFound(ref name, 3, ref versions))
+            if name == "Compute" && versions == &[1, 2]
+    ));
+    let res = sandbox.call_guest_function_by_name(
+        "Compute@v3",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(1)]),
+    );
+    assert!(matches!(
+        res,
+        Err(HyperlightError::GuestFunctionVersionNotFound(_, 3, _))
+    ));
+
+    // allowing a function allows each of its versions, which can still
+    // be denied one by one
+    let mut policy = GuestFunctionPolicy::deny_all();
+    policy.allow("Compute");
+    policy.deny("Compute@v2");
+    sandbox.set_guest_function_policy(policy);
+    let res = sandbox.call_guest_function_by_name(
+        "Compute",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(6), ParameterValue::Int(7)]),
+    );
+    assert!(matches!(
+        res,
+        Err(HyperlightError::GuestFunctionNotPermitted(ref n)) if n == "Compute@v2"
+    ));
+    let res = sandbox.call_guest_function_by_name(
+        "Compute@v1",
+        ReturnType::Int,
+        Some(vec![ParameterValue::Int(1)]),
+    )?;
+    assert_eq!(ReturnValue::Int(2), res);
+    Ok(())
+}
+
+#[test]
+fn guest_function_result_size_is_checked_before_the_call() -> Result<()> {
+    let mut sandbox: MultiUseSandbox = new_uninit_rust()?.evolve(Noop::default())?;
+    assert_eq!(
+        Some(0x6100),
+        sandbox.guest_function_max_result_size("MakeBuffer")
+    );
+    assert_eq!(None, sandbox.guest_function_max_result_size("Echo"));
+
+    // even a small result fails, since the function declared it can return
+    // more than the default output data buffer holds
+    let res = sandbox.call_guest_function_by_name(
+        "MakeBuffer",
+        ReturnType::VecBytes,
+        Some(vec![ParameterValue::Int(16)]),
+    );
+    assert!(matches!(
+        res,
+        Err(HyperlightError::GuestFunctionResultTooLarge(ref name, 0x6100, max))
+            if name == "MakeBuffer" && max == SandboxConfiguration::DEFAULT_OUTPUT_SIZE
+    ));
+    assert_eq!(SandboxState::Ready, sandbox.state());
+
+    // an output data buffer sized from the declaration fits the result
+    let mut cfg = SandboxConfiguration::default();
+    cfg.set_output_data_size(0x8000);
+    let mut sandbox: MultiUseSandbox = UninitializedSandbox::new(
+        GuestBinary::FilePath(simple_guest_as_string().unwrap()),
+        Some(cfg),
+        None,
+        None,
+    )?
+    .evolve(Noop::default())?;
+    let res = sandbox.call_guest_function_by_name(
+        "MakeBuffer",
+        ReturnType::VecBytes,
+        Some(vec![ParameterValue::Int(0x6000)]),
+    )?;
+    assert_eq!(ReturnValue::VecBytes(vec![0; 0x6000]), res);
+    Ok(())
+}
+
+#[test]
+fn compressed_payloads_can_be_larger_than_the_buffers() -> Result<()> {
+    const LEN: usize = 4 * SandboxConfiguration::DEFAULT_INPUT_SIZE;
+    let new_sandbox = |compression| {
+        let mut cfg = SandboxConfiguration::default();
+        cfg.set_payload_compression(compression, 0x400);
+        // room for the compressed and uncompressed payloads
+        cfg.set_heap_size(0x40_0000);
+        new_rust_sandbox(cfg)
+    };
+    let zero = |sandbox: &mut MultiUseSandbox| {
+        sandbox.call_guest_function_by_name(
+            "SetByteArrayToZero",
+            ReturnType::VecBytes,
+            Some(vec![ParameterValue::VecBytes(vec![7; LEN])]),
+        )
+    };
+
+    let mut sandbox = new_sandbox(PayloadCompression::None);
+    assert!(matches!(
+        zero(&mut sandbox),
+        Err(HyperlightError::PayloadTooLarge(..))
+    ));
+
+    for compression in [PayloadCompression::Lz4, PayloadCompression::Zstd] {
+        let mut sandbox = new_sandbox(compression);
+        assert_eq!(ReturnValue::VecBytes(vec![0; LEN]), zero(&mut sandbox)?);
+        // calls and results below the threshold aren't compressed
+        let res = sandbox.call_guest_function_by_name(
+            "Echo",
+            ReturnType::String,
+            Some(vec![ParameterValue::String("hello".to_string())]),
+        )?;
+        assert_eq!(ReturnValue::String("hello".to_string()), res);
+        // a declared maximum result size is checked against the maximum
+        // uncompressed size
+        let res = sandbox.call_guest_function_by_name(
+            "MakeBuffer",
+            ReturnType::VecBytes,
+            Some(vec![ParameterValue::Int(0x6000)]),
+        )?;
+        assert_eq!(ReturnValue::VecBytes(vec![0; 0x6000]), res);
+    }
+    Ok(())
+}
+
+#[test]
+fn guest_call_interceptor() -> Result<()> {
+    let mut sandbox: MultiUseSandbox = new_uninit_rust()?.evolve(Noop::default())?;
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let seen_by_interceptor = seen.clone();
+    sandbox.set_guest_call_interceptor(move |name: &str, args: Option<Vec<ParameterValue>>| {
+        seen_by_interceptor.lock().unwrap().push(name.to_string());
+        match name {
+            "Echo" => GuestCallAction::Proceed(Some(vec![ParameterValue::String(
+                "rewritten".to_string(),
+            )])),
+            "math::EchoDouble" => GuestCallAction::Return(ReturnValue::Double(1.5)),
+            "math::EchoFloat" => GuestCallAction::Deny("floats are not allowed".to_string()),
+            _ => GuestCallAction::Proceed(args),
+        }
+    });
+
+    let hello = Some(vec![ParameterValue::String("hello".to_string())]);
+    let res = sandbox.call_guest_function_by_name("Echo", ReturnType::String, hello.clone())?;
+    assert_eq!(ReturnValue::String("rewritten".to_string()), res);
+    let res = sandbox.call_guest_function_by_name("strings::Echo", ReturnType::String, hello)?;
+    assert_eq!(ReturnValue::String("hello".to_string()), res);
+
+    let res = sandbox.call_guest_function_by_name(
+        "math::EchoDouble",
+        ReturnType::Double,
+        Some(vec![ParameterValue::Double(2.5)]),
+    )?;
+    assert_eq!(ReturnValue::Double(1.5), res);
+    // answering a call doesn't skip checking its arguments
+    let res = sandbox.call_guest_function_by_name(
+        "math::EchoDouble",
+        ReturnType::Double,
+        Some(vec![ParameterValue::String("2.5".to_string())]),
+    );
+    assert!(matches!(
+        res,
+        Err(HyperlightError::GuestFunctionParameterTypeMismatch(ref name, _, _, 0))
+            if name == "math::EchoDouble"
+    ));
+
+    let res = sandbox.call_guest_function_by_name(
+        "math::EchoFloat",
+        ReturnType::Float,
+        Some(vec![ParameterValue::Float(2.5)]),
+    );
+    assert!(matches!(
+        res,
+        Err(HyperlightError::GuestCallDenied(ref name, ref reason))
+            if name == "math::EchoFloat" && reason == "floats are not allowed"
+    ));
+
+    assert_eq!(
+        vec![
+            "Echo",
+            "strings::Echo",
+            "math::EchoDouble",
+            "math::EchoDouble",
+            "math::EchoFloat"
+        ],
+        *seen.lock().unwrap()
+    );
+
+    assert!(sandbox.take_guest_call_interceptor().is_some());
+    let res = sandbox.call_guest_function_by_name(
+        "math::EchoDouble",
+        ReturnType::Double,
+        Some(vec![ParameterValue::Double(2.5)]),
+    )?;
+    assert_eq!(ReturnValue::Double(2.5), res);
+    assert_eq!(5, seen.lock().unwrap().len());
+    Ok(())
+}
+
+#[test]
+fn guest_call_cache() -> Result<()> {
+    let mut sandbox: MultiUseSandbox = new_uninit_rust()?.evolve(Noop::default())?;
+    let cache = GuestCallCache::new();
+    cache.cache("Echo", CachePolicy::default());
+    sandbox.set_guest_call_interceptor(cache.clone());
+
+    for message in ["hello", "hello", "world", "hello"] {
+        let res = sandbox.call_guest_function_by_name(
+            "Echo",
+            ReturnType::String,
+            Some(vec![ParameterValue::String(message.to_string())]),
+        )?;
+        assert_eq!(ReturnValue::String(message.to_string()), res);
+    }
+    assert_eq!((2, 2), (cache.hits(), cache.misses()));
+    assert_eq!(2, cache.len());
+
+    // a cached value is still checked against the type the call expects
+    let res = sandbox.call_guest_function_by_name(
+        "Echo",
+        ReturnType::Int,
+        Some(vec![ParameterValue::String("hello".to_string())]),
+    );
+    assert!(matches!(
+        res,
+        Err(HyperlightError::UnexpectedReturnValueType(
+            ReturnValue::String(_),
+            _
+        ))
+    ));
+    Ok(())
+}
+
+#[test]
+fn generated_calculator_client_is_up_to_date() {
+    let definition = include_str!("../../tests/rust_guests/simpleguest/calculator.hlidl");
+    let interface = InterfaceDefinition::parse(definition).unwrap();
+    let client = generate_guest_client_from_interface("CalculatorClient", &interface).unwrap();
+    assert_eq!(
+        include_str!("common/calculator_client.rs"),
+        client,
+        "tests/common/calculator_client.rs is out of date, regenerate it with generate_guest_client_from_interface"
+    );
+}
+
+#[test]
+fn generated_client_from_guest_signatures() -> Result<()> {
+    let mut sandbox: MultiUseSandbox = new_uninit_rust()?.evolve(Noop::default())?;
+    let signatures: Vec<_> = sandbox
+        .guest_function_signatures()
+        .into_iter()
+        .filter(|s| s.function_name.starts_with("calculator::"))
+        .collect();
+    let client = generate_guest_client("CalculatorSignaturesClient", &signatures)?;
+    assert_eq!(
+        include_str!("common/calculator_signatures_client.rs"),
+        client,
+        "tests/common/calculator_signatures_client.rs is out of date, regenerate it with generate_guest_client"
+    );
+
+    let mut client = CalculatorSignaturesClient::new(&mut sandbox);
+    assert_eq!(5, client.calculator_add(2, 3)?);
+    assert_eq!("xxx", client.calculator_repeat("x".to_string(), 3)?);
+    Ok(())
+}
+
+#[test]
+fn generated_interface_code_calls_the_guest() -> Result<()> {
+    // the simpleguest registers these functions with the code generated
+    // from the same definition by its build script
+    let mut sandbox: MultiUseSandbox = new_uninit_rust()?.evolve(Noop::default())?;
+    let mut client = CalculatorClient::new(&mut sandbox);
+    assert_eq!(3_000_000_002, client.calculator_add(2, 3_000_000_000)?);
+    assert_eq!("abab", client.calculator_repeat("ab".to_string(), 2)?);
+    assert_eq!(6, client.calculator_checksum(vec![1, 2, 255, 4])?);
+    assert!(client.calculator_is_even(u64::MAX - 1)?);
+    assert!(!client.calculator_is_even(7)?);
+    client.calculator_ping()?;
+    Ok(())
+}
@@ -72,6 +72,24 @@ pub fn simple_guest_as_string() -> Result<String> {
         .ok_or_else(|| anyhow!("couldn't convert simple guest PathBuf to string"))
 }
 
+/// Get a fully qualified OS-specific path to the simpleguest elf binary
+/// built with the `heap_profiler` feature of `hyperlight_guest`
+pub fn simple_guest_with_heap_profiler_as_string() -> Result<String> {
+    let buf = rust_guest_as_pathbuf("heap_profiler").join("simpleguest");
+    buf.to_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| anyhow!("couldn't convert simple guest PathBuf to string"))
+}
+
+/// Get a fully qualified OS-specific path to the simpleguest elf binary
+/// built with the `malloc_trace` feature of `hyperlight_guest`
+pub fn simple_guest_with_malloc_trace_as_string() -> Result<String> {
+    let buf = rust_guest_as_pathbuf("malloc_trace").join("simpleguest");
+    buf.to_str()
+        .map(|s| s.to_string())
+        .ok_or_else(|| anyhow!("couldn't convert simple guest PathBuf to string"))
+}
+
 /// Get a fully qualified OS-specific path to the simpleguest.exe PE binary
 pub fn simple_guest_exe_as_string() -> Result<String> {
     let buf = rust_guest_as_pathbuf("simpleguest.exe");
@@ -0,0 +1,36 @@
+/*
+Copyright 2024 The Hyperlight Authors.
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+    http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.
+*/
+
+use hyperlight_common::interface::InterfaceDefinition;
+use hyperlight_guest_build::GuestBuild;
+
+// Generate the registration code of the functions in calculator.hlidl, as
+// described in docs/interface-definitions.md, so that building this guest
+// checks that the generated code compiles
+fn main() {
+    let definition = std::fs::read_to_string("calculator.hlidl").unwrap();
+    let skeleton = InterfaceDefinition::parse(&definition)
+        .unwrap()
+        .generate_guest_skeleton("Calculator")
+        .unwrap();
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    std::fs::write(format!("{out_dir}/calculator.rs"), skeleton).unwrap();
+    println!("cargo:rerun-if-changed=calculator.hlidl");
+
+    // Built like any other guest, so that the host tests check the
+    // metadata hyperlight-guest-build embeds
+    GuestBuild::new().run();
+}
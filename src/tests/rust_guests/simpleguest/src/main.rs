@@ -26,28 +26,52 @@ const MAX_BUFFER_SIZE: usize = 1024;
 extern crate alloc;
 
 use alloc::boxed::Box;
+use alloc::rc::Rc;
 use alloc::string::ToString;
 use alloc::vec::Vec;
 use alloc::{format, vec};
+use core::cell::Cell;
 use core::ffi::c_char;
 use core::hint::black_box;
 use core::ptr::write_volatile;
+use core::time::Duration;
 
-use hyperlight_common::flatbuffer_wrappers::function_call::{FunctionCall, FunctionCallType};
+use hyperlight_common::flatbuffer_wrappers::function_call::{
+    FunctionCall, FunctionCallType, TRIM_MEMORY_FUNCTION_NAME,
+};
 use hyperlight_common::flatbuffer_wrappers::function_types::{
     ParameterType, ParameterValue, ReturnType,
 };
 use hyperlight_common::flatbuffer_wrappers::guest_error::ErrorCode;
 use hyperlight_common::flatbuffer_wrappers::guest_log_level::LogLevel;
+use hyperlight_common::flatbuffer_wrappers::host_function_definition::FunctionFlags;
 use hyperlight_common::flatbuffer_wrappers::util::get_flatbuffer_result;
 use hyperlight_common::mem::PAGE_SIZE;
+use hyperlight_guest::deadline::hl_remaining_time;
 use hyperlight_guest::entrypoint::{abort_with_code, abort_with_code_and_message};
+use hyperlight_guest::epoch::check_epoch;
 use hyperlight_guest::error::{HyperlightGuestError, Result};
+use hyperlight_guest::exceptions::{
+    register_exception_handler, Exception, ExceptionAction, ExceptionContext, ExceptionHandler,
+};
+use hyperlight_guest::executor::{print_async, Executor};
 use hyperlight_guest::guest_function_definition::GuestFunctionDefinition;
-use hyperlight_guest::guest_function_register::register_function;
-use hyperlight_guest::host_function_call::{call_host_function, get_host_return_value};
+use hyperlight_guest::guest_function_register::{
+    register_function, register_namespaced_function, register_versioned_function,
+};
+use hyperlight_guest::heartbeat::heartbeat;
+use hyperlight_guest::host_function_call::{call_host_function, get_host_return_value, outb};
+use hyperlight_guest::host_functions::host_has_function;
+use hyperlight_guest::host_stream::call_streaming_host_function;
+use hyperlight_guest::jit_scratch::jit_scratch;
 use hyperlight_guest::memory::malloc;
-use hyperlight_guest::{logging, MIN_STACK_ADDRESS};
+use hyperlight_guest::print::debug_print;
+use hyperlight_guest::progress::hl_report_progress;
+use hyperlight_guest::read_only_data::read_only_data;
+use hyperlight_guest::result_buffer::with_result_buffer;
+use hyperlight_guest::secrets::hl_take_secret;
+use hyperlight_guest::sleep::hl_sleep;
+use hyperlight_guest::{has_feature, hl_assert, hl_println, hl_trap, logging, MIN_STACK_ADDRESS};
 use log::{error, LevelFilter};
 
 extern crate hyperlight_guest;
@@ -85,6 +109,31 @@ fn echo_float(function_call: &FunctionCall) -> Result<Vec<u8>> {
     }
 }
 
+fn compute_v1(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::Int(value) = function_call.parameters.clone().unwrap()[0].clone() {
+        Ok(get_flatbuffer_result(value * 2))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to compute_v1".to_string(),
+        ))
+    }
+}
+
+fn compute_v2(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let (ParameterValue::Int(value), ParameterValue::Int(factor)) = (
+        function_call.parameters.clone().unwrap()[0].clone(),
+        function_call.parameters.clone().unwrap()[1].clone(),
+    ) {
+        Ok(get_flatbuffer_result(value * factor))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to compute_v2".to_string(),
+        ))
+    }
+}
+
 fn print_output(message: &str) -> Result<Vec<u8>> {
     call_host_function(
         "HostPrint",
@@ -106,6 +155,18 @@ fn simple_print_output(function_call: &FunctionCall) -> Result<Vec<u8>> {
     }
 }
 
+fn simple_debug_print(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::String(message) = function_call.parameters.clone().unwrap()[0].clone() {
+        debug_print(&message);
+        Ok(get_flatbuffer_result(()))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to simple_debug_print".to_string(),
+        ))
+    }
+}
+
 fn set_byte_array_to_zero(function_call: &FunctionCall) -> Result<Vec<u8>> {
     if let ParameterValue::VecBytes(mut vec) = function_call.parameters.clone().unwrap()[0].clone()
     {
@@ -119,6 +180,23 @@ fn set_byte_array_to_zero(function_call: &FunctionCall) -> Result<Vec<u8>> {
     }
 }
 
+fn fill_result_buffer(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::Int(len) = function_call.parameters.clone().unwrap()[0].clone() {
+        with_result_buffer(|result_buffer| {
+            let len = len as usize;
+            for (i, byte) in result_buffer.iter_mut().take(len).enumerate() {
+                *byte = i as u8;
+            }
+            Ok(len)
+        })
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to fill_result_buffer".to_string(),
+        ))
+    }
+}
+
 fn print_two_args(function_call: &FunctionCall) -> Result<Vec<u8>> {
     if let (ParameterValue::String(arg1), ParameterValue::Int(arg2)) = (
         function_call.parameters.clone().unwrap()[0].clone(),
@@ -493,6 +571,22 @@ fn malloc_and_free(function_call: &FunctionCall) -> Result<Vec<u8>> {
     }
 }
 
+// not inlined, so that heap profiles and malloc traces find this function
+// as the site of the leaked allocation
+#[inline(never)]
+fn leak_memory(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::Int(size) = function_call.parameters.clone().unwrap()[0].clone() {
+        let leaked = vec![0u8; size.clamp(0, MAX_BUFFER_SIZE as i32) as usize];
+        core::mem::forget(black_box(leaked));
+        Ok(get_flatbuffer_result(size))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to leak_memory".to_string(),
+        ))
+    }
+}
+
 fn echo(function_call: &FunctionCall) -> Result<Vec<u8>> {
     if let ParameterValue::String(value) = function_call.parameters.clone().unwrap()[0].clone() {
         Ok(get_flatbuffer_result(&*value))
@@ -528,6 +622,157 @@ fn spin(_: &FunctionCall) -> Result<Vec<u8>> {
     Ok(get_flatbuffer_result(()))
 }
 
+fn spin_with_epoch_checks(_: &FunctionCall) -> Result<Vec<u8>> {
+    loop {
+        check_epoch();
+    }
+}
+
+fn sleep(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::ULong(ms) = function_call.parameters.clone().unwrap()[0].clone() {
+        hl_sleep(ms)?;
+        Ok(get_flatbuffer_result(()))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to sleep".to_string(),
+        ))
+    }
+}
+
+fn sleep_with_heartbeats(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let (ParameterValue::Int(count), ParameterValue::ULong(ms)) = (
+        function_call.parameters.clone().unwrap()[0].clone(),
+        function_call.parameters.clone().unwrap()[1].clone(),
+    ) {
+        for _ in 0..count {
+            hl_sleep(ms)?;
+            heartbeat()?;
+        }
+        Ok(get_flatbuffer_result(()))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to sleep_with_heartbeats".to_string(),
+        ))
+    }
+}
+
+fn print_async_tasks(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::String(message) = function_call.parameters.clone().unwrap()[0].clone() {
+        let printed = Rc::new(Cell::new(0));
+        let mut executor = Executor::new();
+        for _ in 0..2 {
+            let message = message.clone();
+            let printed = printed.clone();
+            executor.spawn(async move {
+                for _ in 0..3 {
+                    let len = print_async(&message).await.unwrap_or(0);
+                    printed.set(printed.get() + len);
+                }
+            });
+        }
+        executor.run()?;
+        Ok(get_flatbuffer_result(printed.get()))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to print_async_tasks".to_string(),
+        ))
+    }
+}
+
+// Written against the std-like API of hyperlight-guest-std, as ported
+// code would be
+fn count_distinct_words(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    use hyperlight_guest_std::collections::HashMap;
+    use hyperlight_guest_std::println;
+
+    if let ParameterValue::String(text) = function_call.parameters.clone().unwrap()[0].clone() {
+        let mut counts: HashMap<&str, i32> = HashMap::new();
+        for word in text.split_whitespace() {
+            *counts.entry(word).or_insert(0) += 1;
+        }
+        if let Some((word, count)) = counts.iter().max_by_key(|(word, count)| (**count, **word)) {
+            println!("most common word: {} ({})", word, count);
+        }
+        Ok(get_flatbuffer_result(counts.len() as i32))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to count_distinct_words".to_string(),
+        ))
+    }
+}
+
+// The registration code for the functions in calculator.hlidl, generated
+// by build.rs
+include!(concat!(env!("OUT_DIR"), "/calculator.rs"));
+
+// The metadata hyperlight-guest-build generates in build.rs
+include!(concat!(env!("OUT_DIR"), "/hyperlight_guest_metadata.rs"));
+
+struct CalculatorGuest;
+
+impl Calculator for CalculatorGuest {
+    fn calculator_add(a: i32, b: i64) -> Result<i64> {
+        Ok(a as i64 + b)
+    }
+
+    fn calculator_repeat(text: alloc::string::String, times: u32) -> Result<alloc::string::String> {
+        Ok(text.repeat(times as usize))
+    }
+
+    fn calculator_checksum(data: Vec<u8>) -> Result<u8> {
+        Ok(data.iter().fold(0, |sum, b| sum.wrapping_add(*b)))
+    }
+
+    fn calculator_is_even(n: u64) -> Result<bool> {
+        Ok(n % 2 == 0)
+    }
+
+    fn calculator_ping() -> Result<()> {
+        Ok(())
+    }
+}
+
+fn host_has_feature(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::String(name) = function_call.parameters.clone().unwrap()[0].clone() {
+        Ok(get_flatbuffer_result(has_feature(&name)))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to host_has_feature".to_string(),
+        ))
+    }
+}
+
+fn has_host_function(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::String(name) = function_call.parameters.clone().unwrap()[0].clone() {
+        Ok(get_flatbuffer_result(host_has_function(&name)))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to has_host_function".to_string(),
+        ))
+    }
+}
+
+fn print_formatted(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let (ParameterValue::String(message), ParameterValue::Int(value)) = (
+        function_call.parameters.clone().unwrap()[0].clone(),
+        function_call.parameters.clone().unwrap()[1].clone(),
+    ) {
+        hl_println!("{}: {}", message, value);
+        Ok(get_flatbuffer_result(()))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to print_formatted".to_string(),
+        ))
+    }
+}
+
 fn test_abort(function_call: &FunctionCall) -> Result<Vec<u8>> {
     if let ParameterValue::Int(code) = function_call.parameters.clone().unwrap()[0].clone() {
         abort_with_code(code);
@@ -547,6 +792,23 @@ fn test_abort_with_code_and_message(function_call: &FunctionCall) -> Result<Vec<
     Ok(get_flatbuffer_result(()))
 }
 
+fn test_assert(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::Int(value) = function_call.parameters.clone().unwrap()[0].clone() {
+        hl_assert!(value > 0, "value is {}", value);
+    }
+    Ok(get_flatbuffer_result(()))
+}
+
+fn test_trap(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let (ParameterValue::UInt(code), ParameterValue::String(message)) = (
+        function_call.parameters.clone().unwrap()[0].clone(),
+        function_call.parameters.clone().unwrap()[1].clone(),
+    ) {
+        hl_trap!(code, "{}", message);
+    }
+    Ok(get_flatbuffer_result(()))
+}
+
 fn test_guest_panic(function_call: &FunctionCall) -> Result<Vec<u8>> {
     if let ParameterValue::String(message) = function_call.parameters.clone().unwrap()[0].clone() {
         panic!("{}", message);
@@ -632,89 +894,599 @@ fn log_message(function_call: &FunctionCall) -> Result<Vec<u8>> {
     }
 }
 
-fn trigger_exception(_: &FunctionCall) -> Result<Vec<u8>> {
-    unsafe {
-        core::arch::asm!("ud2");
-    } // trigger an undefined instruction exception
-    Ok(get_flatbuffer_result(()))
+fn log_repeatedly(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let (ParameterValue::String(message), ParameterValue::Int(count)) = (
+        function_call.parameters.clone().unwrap()[0].clone(),
+        function_call.parameters.clone().unwrap()[1].clone(),
+    ) {
+        for i in 0..count {
+            log::info!("{} {}", &message, i);
+        }
+        Ok(get_flatbuffer_result(()))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to log_repeatedly".to_string(),
+        ))
+    }
 }
 
-static mut COUNTER: i32 = 0;
+fn sum_host_stream(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let (ParameterValue::String(function_name), ParameterValue::Int(size)) = (
+        function_call.parameters.clone().unwrap()[0].clone(),
+        function_call.parameters.clone().unwrap()[1].clone(),
+    ) {
+        let mut stream = call_streaming_host_function(
+            &function_name,
+            Some(Vec::from(&[ParameterValue::Int(size)])),
+        )?;
+        let mut sum: u64 = 0;
+        while let Some(chunk) = stream.read_next_chunk()? {
+            sum += chunk.iter().map(|b| *b as u64).sum::<u64>();
+        }
+        Ok(get_flatbuffer_result(sum))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to sum_host_stream".to_string(),
+        ))
+    }
+}
 
-fn add_to_static(function_call: &FunctionCall) -> Result<Vec<u8>> {
-    if let ParameterValue::Int(i) = function_call.parameters.clone().unwrap()[0].clone() {
-        let res = unsafe {
-            COUNTER += i;
-            COUNTER
-        };
-        Ok(get_flatbuffer_result(res))
+fn report_progress(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::Int(steps) = function_call.parameters.clone().unwrap()[0].clone() {
+        for step in 0..=steps {
+            let percent = (step * 100 / steps.max(1)) as u8;
+            hl_report_progress(percent, &format!("step {}", step))?;
+        }
+        Ok(get_flatbuffer_result(()))
     } else {
         Err(HyperlightGuestError::new(
             ErrorCode::GuestFunctionParameterTypeMismatch,
-            "Invalid parameters passed to add_to_static".to_string(),
+            "Invalid parameters passed to report_progress".to_string(),
         ))
     }
 }
 
-fn get_static(function_call: &FunctionCall) -> Result<Vec<u8>> {
-    if function_call.parameters.is_none() {
-        Ok(get_flatbuffer_result(unsafe { COUNTER }))
+fn work_until_deadline(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::ULong(margin_micros) =
+        function_call.parameters.clone().unwrap()[0].clone()
+    {
+        let margin = Duration::from_micros(margin_micros);
+        let mut steps: u64 = 0;
+        while hl_remaining_time()? > margin {
+            steps += 1;
+        }
+        Ok(get_flatbuffer_result(steps))
     } else {
         Err(HyperlightGuestError::new(
             ErrorCode::GuestFunctionParameterTypeMismatch,
-            "Invalid parameters passed to get_static".to_string(),
+            "Invalid parameters passed to work_until_deadline".to_string(),
         ))
     }
 }
 
-fn add_to_static_and_fail(_: &FunctionCall) -> Result<Vec<u8>> {
+fn trigger_exception(_: &FunctionCall) -> Result<Vec<u8>> {
     unsafe {
-        COUNTER += 10;
-    };
-    Err(HyperlightGuestError::new(
-        ErrorCode::GuestError,
-        "Crash on purpose".to_string(),
-    ))
+        core::arch::asm!("ud2");
+    } // trigger an undefined instruction exception
+    Ok(get_flatbuffer_result(()))
 }
 
-fn violate_seccomp_filters(function_call: &FunctionCall) -> Result<Vec<u8>> {
-    if function_call.parameters.is_none() {
-        call_host_function("MakeGetpidSyscall", None, ReturnType::ULong)?;
+fn double_fault(_: &FunctionCall) -> Result<Vec<u8>> {
+    // the CPU can't push the invalid opcode exception, or the page fault
+    // that raises, to an unmapped stack, so it raises a double fault
+    unsafe {
+        core::arch::asm!("mov rsp, 0", "ud2", options(noreturn));
+    }
+}
 
-        let res = get_host_return_value::<u64>()?;
+fn triple_fault(_: &FunctionCall) -> Result<Vec<u8>> {
+    // with an empty IDT, no exception can be delivered, not even a double
+    // fault
+    #[repr(C, packed)]
+    struct Idtr {
+        limit: u16,
+        base: u64,
+    }
+    let idtr = Idtr { limit: 0, base: 0 };
+    unsafe {
+        core::arch::asm!("lidt [{}]", "ud2", in(reg) &idtr, options(noreturn));
+    }
+}
 
-        Ok(get_flatbuffer_result(res))
+fn write_to_code(_: &FunctionCall) -> Result<Vec<u8>> {
+    // rewrite the first byte of this function with the value it already has
+    let code = write_to_code as usize as *mut u8;
+    unsafe {
+        code.write_volatile(code.read_volatile());
+    }
+    Ok(get_flatbuffer_result(()))
+}
+
+static mut DATA_CODE: [u8; 1] = [0xC3]; // RET
+
+fn execute_data(_: &FunctionCall) -> Result<Vec<u8>> {
+    unsafe {
+        let data_fn: fn() = core::mem::transmute(core::ptr::addr_of_mut!(DATA_CODE));
+        data_fn();
+        black_box(data_fn);
+    }
+    // will only reach this point if static data is executable
+    Ok(get_flatbuffer_result("fail"))
+}
+
+fn run_jit_code(_: &FunctionCall) -> Result<Vec<u8>> {
+    // mov eax, 42; ret
+    const CODE: [u8; 6] = [0xB8, 0x2A, 0x00, 0x00, 0x00, 0xC3];
+    let scratch = jit_scratch()?;
+    scratch[..CODE.len()].copy_from_slice(&CODE);
+    let value = unsafe {
+        let jit_fn: extern "C" fn() -> i32 = core::mem::transmute(scratch.as_ptr());
+        jit_fn()
+    };
+    Ok(get_flatbuffer_result(value))
+}
+
+fn get_random(_: &FunctionCall) -> Result<Vec<u8>> {
+    let value = hyperlight_guest::entropy::random_u64()?;
+    Ok(get_flatbuffer_result(value))
+}
+
+fn execute_rdrand(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::Bool(rdseed) = function_call.parameters.clone().unwrap()[0].clone() {
+        // execute the instruction without checking the CPUID, so the
+        // entropy policy is only enforced by trapping it
+        let value: u64;
+        unsafe {
+            if rdseed {
+                core::arch::asm!("rdseed {}", out(reg) value, options(nomem, nostack));
+            } else {
+                core::arch::asm!("rdrand {}", out(reg) value, options(nomem, nostack));
+            }
+        }
+        Ok(get_flatbuffer_result(value))
     } else {
         Err(HyperlightGuestError::new(
             ErrorCode::GuestFunctionParameterTypeMismatch,
-            "Invalid parameters passed to violate_seccomp_filters".to_string(),
+            "Invalid parameters passed to execute_rdrand".to_string(),
         ))
     }
 }
 
-fn add(function_call: &FunctionCall) -> Result<Vec<u8>> {
-    if let (ParameterValue::Int(a), ParameterValue::Int(b)) = (
+fn read_tsc(_: &FunctionCall) -> Result<Vec<u8>> {
+    let tsc = unsafe { core::arch::x86_64::_rdtsc() };
+    Ok(get_flatbuffer_result(tsc))
+}
+
+fn write_to_port(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let (ParameterValue::UInt(port), ParameterValue::VecBytes(data)) = (
         function_call.parameters.clone().unwrap()[0].clone(),
         function_call.parameters.clone().unwrap()[1].clone(),
     ) {
+        for byte in data {
+            outb(port as u16, byte);
+        }
+        Ok(get_flatbuffer_result(()))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to write_to_port".to_string(),
+        ))
+    }
+}
+
+fn make_host_handle(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::String(text) = function_call.parameters.clone().unwrap()[0].clone() {
         call_host_function(
-            "HostAdd",
-            Some(Vec::from(&[ParameterValue::Int(a), ParameterValue::Int(b)])),
-            ReturnType::Int,
+            "HostMakeObject",
+            Some(Vec::from(&[ParameterValue::String(text)])),
+            ReturnType::ULong,
         )?;
+        let handle = get_host_return_value::<u64>()?;
+        Ok(get_flatbuffer_result(handle))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to make_host_handle".to_string(),
+        ))
+    }
+}
 
-        let res = get_host_return_value::<i32>()?;
-
-        Ok(get_flatbuffer_result(res))
+fn host_handle_length(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::ULong(handle) = function_call.parameters.clone().unwrap()[0].clone() {
+        call_host_function(
+            "HostObjectLength",
+            Some(Vec::from(&[ParameterValue::ULong(handle)])),
+            ReturnType::ULong,
+        )?;
+        let length = get_host_return_value::<u64>()?;
+        Ok(get_flatbuffer_result(length))
     } else {
         Err(HyperlightGuestError::new(
             ErrorCode::GuestFunctionParameterTypeMismatch,
-            "Invalid parameters passed to add".to_string(),
+            "Invalid parameters passed to host_handle_length".to_string(),
         ))
     }
 }
 
-#[no_mangle]
+fn return_to_unexpected_address(_: &FunctionCall) -> Result<Vec<u8>> {
+    // return to the next instruction, which isn't the return address on
+    // the shadow stack, if there is one
+    unsafe {
+        core::arch::asm!(
+            "lea {target}, [rip + 2f]",
+            "push {target}",
+            "ret",
+            "2:",
+            target = out(reg) _,
+        );
+    }
+    Ok(get_flatbuffer_result(()))
+}
+
+fn skip_invalid_opcode(_: u8, _: u64, context: &mut ExceptionContext) -> ExceptionAction {
+    // ud2 is 2 bytes long
+    context.rip += 2;
+    ExceptionAction::Resume
+}
+
+fn fail_on_invalid_opcode(_: u8, _: u64, context: &mut ExceptionContext) -> ExceptionAction {
+    ExceptionAction::Fail(HyperlightGuestError::new(
+        ErrorCode::GuestError,
+        format!("Invalid opcode at {:#x}", context.rip),
+    ))
+}
+
+fn trigger_handled_exception(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::Bool(resume) = function_call.parameters.clone().unwrap()[0].clone() {
+        let handler: ExceptionHandler = if resume {
+            skip_invalid_opcode
+        } else {
+            fail_on_invalid_opcode
+        };
+        register_exception_handler(Exception::InvalidOpcode, handler);
+        unsafe {
+            core::arch::asm!("ud2");
+        }
+        Ok(get_flatbuffer_result(1))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to trigger_handled_exception".to_string(),
+        ))
+    }
+}
+
+/// Whether the host has enabled XSAVE and the AVX state for the guest
+fn avx_enabled() -> bool {
+    // CPUID.1:ECX.OSXSAVE reflects CR4.OSXSAVE
+    #[allow(unused_unsafe)]
+    let osxsave = unsafe { core::arch::x86_64::__cpuid(1) }.ecx & (1 << 27) != 0;
+    if !osxsave {
+        return false;
+    }
+    let xcr0: u32;
+    unsafe {
+        core::arch::asm!("xgetbv", in("ecx") 0, out("eax") xcr0, out("edx") _);
+    }
+    // the SSE and AVX state components
+    xcr0 & 0b110 == 0b110
+}
+
+fn cpuid(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let (ParameterValue::UInt(leaf), ParameterValue::UInt(subleaf)) = (
+        function_call.parameters.clone().unwrap()[0].clone(),
+        function_call.parameters.clone().unwrap()[1].clone(),
+    ) {
+        #[allow(unused_unsafe)]
+        let result = unsafe { core::arch::x86_64::__cpuid_count(leaf, subleaf) };
+        let registers: Vec<u8> = [result.eax, result.ebx, result.ecx, result.edx]
+            .iter()
+            .flat_map(|r| r.to_le_bytes())
+            .collect();
+        Ok(get_flatbuffer_result(registers.as_slice()))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to cpuid".to_string(),
+        ))
+    }
+}
+
+fn read_msr(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::UInt(msr) = function_call.parameters.clone().unwrap()[0].clone() {
+        let (low, high): (u32, u32);
+        unsafe {
+            core::arch::asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high);
+        }
+        Ok(get_flatbuffer_result(((high as u64) << 32) | low as u64))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to read_msr".to_string(),
+        ))
+    }
+}
+
+fn write_msr(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let (ParameterValue::UInt(msr), ParameterValue::ULong(value)) = (
+        function_call.parameters.clone().unwrap()[0].clone(),
+        function_call.parameters.clone().unwrap()[1].clone(),
+    ) {
+        unsafe {
+            core::arch::asm!(
+                "wrmsr",
+                in("ecx") msr,
+                in("eax") value as u32,
+                in("edx") (value >> 32) as u32,
+            );
+        }
+        Ok(get_flatbuffer_result(()))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to write_msr".to_string(),
+        ))
+    }
+}
+
+fn get_read_only_data(_: &FunctionCall) -> Result<Vec<u8>> {
+    Ok(get_flatbuffer_result(read_only_data()?))
+}
+
+fn take_secret(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::String(name) = function_call.parameters.clone().unwrap()[0].clone() {
+        Ok(get_flatbuffer_result(hl_take_secret(&name)?.as_slice()))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to take_secret".to_string(),
+        ))
+    }
+}
+
+fn write_read_only_data(_: &FunctionCall) -> Result<Vec<u8>> {
+    let data = read_only_data()?;
+    unsafe {
+        core::ptr::write_volatile(data.as_ptr() as *mut u8, 0);
+    }
+    Ok(get_flatbuffer_result(()))
+}
+
+/// Add `a` and `b` in the upper 128 bits of an AVX register. Only call
+/// this when `avx_enabled` returns true.
+fn add_in_upper_lanes(a: f64, b: f64) -> f64 {
+    let mut sum: f64 = 0.0;
+    // The guest is built without SSE, so the compiler never uses the vector
+    // registers itself, and they don't need to be declared as clobbered
+    unsafe {
+        core::arch::asm!(
+            "vbroadcastsd ymm0, qword ptr [{a}]",
+            "vbroadcastsd ymm1, qword ptr [{b}]",
+            "vaddpd ymm0, ymm0, ymm1",
+            "vextractf128 xmm0, ymm0, 1",
+            "vmovsd qword ptr [{sum}], xmm0",
+            "vzeroall",
+            a = in(reg) &a,
+            b = in(reg) &b,
+            sum = in(reg) &mut sum,
+        );
+    }
+    sum
+}
+
+fn add_with_avx(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let (ParameterValue::Double(a), ParameterValue::Double(b)) = (
+        function_call.parameters.clone().unwrap()[0].clone(),
+        function_call.parameters.clone().unwrap()[1].clone(),
+    ) {
+        if !avx_enabled() {
+            return Err(HyperlightGuestError::new(
+                ErrorCode::GuestError,
+                "AVX is not enabled".to_string(),
+            ));
+        }
+        Ok(get_flatbuffer_result(add_in_upper_lanes(a, b)))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to add_with_avx".to_string(),
+        ))
+    }
+}
+
+/// Replace the upper 128 bits of an AVX register with `value`, returning
+/// the value the previous call left in them, which is zero when they are
+/// reset between calls.
+fn swap_ymm_upper(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::Double(value) = function_call.parameters.clone().unwrap()[0].clone() {
+        if !avx_enabled() {
+            return Err(HyperlightGuestError::new(
+                ErrorCode::GuestError,
+                "AVX is not enabled".to_string(),
+            ));
+        }
+        let mut previous: f64 = 0.0;
+        // ymm2 is deliberately left set when the call returns, see
+        // `add_in_upper_lanes` for why it needn't be declared as clobbered
+        unsafe {
+            core::arch::asm!(
+                "vextractf128 xmm3, ymm2, 1",
+                "vmovsd qword ptr [{previous}], xmm3",
+                "vbroadcastsd ymm2, qword ptr [{value}]",
+                value = in(reg) &value,
+                previous = in(reg) &mut previous,
+            );
+        }
+        Ok(get_flatbuffer_result(previous))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to swap_ymm_upper".to_string(),
+        ))
+    }
+}
+
+static mut COUNTER: i32 = 0;
+
+fn add_to_static(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::Int(i) = function_call.parameters.clone().unwrap()[0].clone() {
+        let res = unsafe {
+            COUNTER += i;
+            COUNTER
+        };
+        Ok(get_flatbuffer_result(res))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to add_to_static".to_string(),
+        ))
+    }
+}
+
+fn get_static(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if function_call.parameters.is_none() {
+        Ok(get_flatbuffer_result(unsafe { COUNTER }))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to get_static".to_string(),
+        ))
+    }
+}
+
+fn add_to_static_and_fail(_: &FunctionCall) -> Result<Vec<u8>> {
+    unsafe {
+        COUNTER += 10;
+    };
+    Err(HyperlightGuestError::new(
+        ErrorCode::GuestError,
+        "Crash on purpose".to_string(),
+    ))
+}
+
+static mut CACHE: Vec<u8> = Vec::new();
+
+fn fill_cache(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::Int(size) = function_call.parameters.clone().unwrap()[0].clone() {
+        let len = unsafe {
+            let cache = &mut *core::ptr::addr_of_mut!(CACHE);
+            cache.resize(size as usize, 0xca);
+            cache.len()
+        };
+        Ok(get_flatbuffer_result(len as i32))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to fill_cache".to_string(),
+        ))
+    }
+}
+
+fn get_cache_size(_: &FunctionCall) -> Result<Vec<u8>> {
+    let len = unsafe { (*core::ptr::addr_of!(CACHE)).len() };
+    Ok(get_flatbuffer_result(len as i32))
+}
+
+fn trim_memory(_: &FunctionCall) -> Result<Vec<u8>> {
+    unsafe {
+        let cache = &mut *core::ptr::addr_of_mut!(CACHE);
+        // zeroed pages are given back to the host
+        cache.fill(0);
+        *cache = Vec::new();
+    }
+    Ok(get_flatbuffer_result(()))
+}
+
+fn violate_seccomp_filters(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if function_call.parameters.is_none() {
+        call_host_function("MakeGetpidSyscall", None, ReturnType::ULong)?;
+
+        let res = get_host_return_value::<u64>()?;
+
+        Ok(get_flatbuffer_result(res))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to violate_seccomp_filters".to_string(),
+        ))
+    }
+}
+
+fn add_small_integers(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let (ParameterValue::Short(a), ParameterValue::Byte(b)) = (
+        function_call.parameters.clone().unwrap()[0].clone(),
+        function_call.parameters.clone().unwrap()[1].clone(),
+    ) {
+        call_host_function(
+            "HostAddSmallIntegers",
+            Some(Vec::from(&[
+                ParameterValue::Short(a),
+                ParameterValue::Byte(b),
+            ])),
+            ReturnType::Short,
+        )?;
+
+        let res = get_host_return_value::<i16>()?;
+
+        Ok(get_flatbuffer_result(res))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to add_small_integers".to_string(),
+        ))
+    }
+}
+
+fn pack_bytes(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let (ParameterValue::UByte(high), ParameterValue::UByte(low)) = (
+        function_call.parameters.clone().unwrap()[0].clone(),
+        function_call.parameters.clone().unwrap()[1].clone(),
+    ) {
+        Ok(get_flatbuffer_result(u16::from_be_bytes([high, low])))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to pack_bytes".to_string(),
+        ))
+    }
+}
+
+fn make_buffer(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let ParameterValue::Int(len) = function_call.parameters.clone().unwrap()[0].clone() {
+        Ok(get_flatbuffer_result(vec![0u8; len as usize].as_slice()))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to make_buffer".to_string(),
+        ))
+    }
+}
+
+fn add(function_call: &FunctionCall) -> Result<Vec<u8>> {
+    if let (ParameterValue::Int(a), ParameterValue::Int(b)) = (
+        function_call.parameters.clone().unwrap()[0].clone(),
+        function_call.parameters.clone().unwrap()[1].clone(),
+    ) {
+        call_host_function(
+            "HostAdd",
+            Some(Vec::from(&[ParameterValue::Int(a), ParameterValue::Int(b)])),
+            ReturnType::Int,
+        )?;
+
+        let res = get_host_return_value::<i32>()?;
+
+        Ok(get_flatbuffer_result(res))
+    } else {
+        Err(HyperlightGuestError::new(
+            ErrorCode::GuestFunctionParameterTypeMismatch,
+            "Invalid parameters passed to add".to_string(),
+        ))
+    }
+}
+
+#[no_mangle]
 pub extern "C" fn hyperlight_main() {
     let set_static_def = GuestFunctionDefinition::new(
         "SetStatic".to_string(),
@@ -733,6 +1505,14 @@ pub extern "C" fn hyperlight_main() {
     );
     register_function(simple_print_output_def);
 
+    let debug_print_def = GuestFunctionDefinition::new(
+        "DebugPrint".to_string(),
+        Vec::from(&[ParameterType::String]),
+        ReturnType::Void,
+        simple_debug_print as usize,
+    );
+    register_function(debug_print_def);
+
     let print_using_printf_def = GuestFunctionDefinition::new(
         "PrintUsingPrintf".to_string(),
         Vec::from(&[ParameterType::String]),
@@ -789,6 +1569,14 @@ pub extern "C" fn hyperlight_main() {
     );
     register_function(malloc_and_free_def);
 
+    let leak_memory_def = GuestFunctionDefinition::new(
+        "LeakMemory".to_string(),
+        Vec::from(&[ParameterType::Int]),
+        ReturnType::Int,
+        leak_memory as usize,
+    );
+    register_function(leak_memory_def);
+
     let print_two_args_def = GuestFunctionDefinition::new(
         "PrintTwoArgs".to_string(),
         Vec::from(&[ParameterType::String, ParameterType::Int]),
@@ -949,12 +1737,21 @@ pub extern "C" fn hyperlight_main() {
     );
     register_function(set_byte_array_to_zero_def);
 
+    let fill_result_buffer_def = GuestFunctionDefinition::new(
+        "FillResultBuffer".to_string(),
+        Vec::from(&[ParameterType::Int]),
+        ReturnType::ULong,
+        fill_result_buffer as usize,
+    );
+    register_function(fill_result_buffer_def);
+
     let echo_def = GuestFunctionDefinition::new(
         "Echo".to_string(),
         Vec::from(&[ParameterType::String]),
         ReturnType::String,
         echo as usize,
-    );
+    )
+    .with_flags(FunctionFlags::PURE);
     register_function(echo_def);
 
     let get_size_prefixed_buffer_def = GuestFunctionDefinition::new(
@@ -973,6 +1770,64 @@ pub extern "C" fn hyperlight_main() {
     );
     register_function(spin_def);
 
+    let sleep_def = GuestFunctionDefinition::new(
+        "Sleep".to_string(),
+        Vec::from(&[ParameterType::ULong]),
+        ReturnType::Void,
+        sleep as usize,
+    );
+    register_function(sleep_def);
+
+    let sleep_with_heartbeats_def = GuestFunctionDefinition::new(
+        "SleepWithHeartbeats".to_string(),
+        Vec::from(&[ParameterType::Int, ParameterType::ULong]),
+        ReturnType::Void,
+        sleep_with_heartbeats as usize,
+    );
+    register_function(sleep_with_heartbeats_def);
+
+    let print_async_tasks_def = GuestFunctionDefinition::new(
+        "PrintAsyncTasks".to_string(),
+        Vec::from(&[ParameterType::String]),
+        ReturnType::Int,
+        print_async_tasks as usize,
+    );
+    register_function(print_async_tasks_def);
+
+    let has_host_function_def = GuestFunctionDefinition::new(
+        "HasHostFunction".to_string(),
+        Vec::from(&[ParameterType::String]),
+        ReturnType::Bool,
+        has_host_function as usize,
+    );
+    register_function(has_host_function_def);
+
+    let host_has_feature_def = GuestFunctionDefinition::new(
+        "HostHasFeature".to_string(),
+        Vec::from(&[ParameterType::String]),
+        ReturnType::Bool,
+        host_has_feature as usize,
+    );
+    register_function(host_has_feature_def);
+
+    let count_distinct_words_def = GuestFunctionDefinition::new(
+        "CountDistinctWords".to_string(),
+        Vec::from(&[ParameterType::String]),
+        ReturnType::Int,
+        count_distinct_words as usize,
+    );
+    register_function(count_distinct_words_def);
+
+    register_calculator::<CalculatorGuest>();
+
+    let print_formatted_def = GuestFunctionDefinition::new(
+        "PrintFormatted".to_string(),
+        Vec::from(&[ParameterType::String, ParameterType::Int]),
+        ReturnType::Void,
+        print_formatted as usize,
+    );
+    register_function(print_formatted_def);
+
     let abort_def = GuestFunctionDefinition::new(
         "GuestAbortWithCode".to_string(),
         Vec::from(&[ParameterType::Int]),
@@ -989,6 +1844,22 @@ pub extern "C" fn hyperlight_main() {
     );
     register_function(abort_with_code_message_def);
 
+    let assert_def = GuestFunctionDefinition::new(
+        "GuestAssert".to_string(),
+        Vec::from(&[ParameterType::Int]),
+        ReturnType::Void,
+        test_assert as usize,
+    );
+    register_function(assert_def);
+
+    let trap_def = GuestFunctionDefinition::new(
+        "GuestTrap".to_string(),
+        Vec::from(&[ParameterType::UInt, ParameterType::String]),
+        ReturnType::Void,
+        test_trap as usize,
+    );
+    register_function(trap_def);
+
     let guest_panic_def = GuestFunctionDefinition::new(
         "guest_panic".to_string(),
         Vec::from(&[ParameterType::String]),
@@ -1013,6 +1884,14 @@ pub extern "C" fn hyperlight_main() {
     );
     register_function(log_message_def);
 
+    let log_repeatedly_def = GuestFunctionDefinition::new(
+        "LogRepeatedly".to_string(),
+        Vec::from(&[ParameterType::String, ParameterType::Int]),
+        ReturnType::Void,
+        log_repeatedly as usize,
+    );
+    register_function(log_repeatedly_def);
+
     let infinite_recursion_def = GuestFunctionDefinition::new(
         "InfiniteRecursion".to_string(),
         Vec::new(),
@@ -1053,12 +1932,37 @@ pub extern "C" fn hyperlight_main() {
     );
     register_function(add_to_static_def);
 
+    let fill_cache_def = GuestFunctionDefinition::new(
+        "FillCache".to_string(),
+        Vec::from(&[ParameterType::Int]),
+        ReturnType::Int,
+        fill_cache as usize,
+    );
+    register_function(fill_cache_def);
+
+    let get_cache_size_def = GuestFunctionDefinition::new(
+        "GetCacheSize".to_string(),
+        Vec::new(),
+        ReturnType::Int,
+        get_cache_size as usize,
+    );
+    register_function(get_cache_size_def);
+
+    let trim_memory_def = GuestFunctionDefinition::new(
+        TRIM_MEMORY_FUNCTION_NAME.to_string(),
+        Vec::new(),
+        ReturnType::Void,
+        trim_memory as usize,
+    );
+    register_function(trim_memory_def);
+
     let get_static_def = GuestFunctionDefinition::new(
         "GetStatic".to_string(),
         Vec::new(),
         ReturnType::Int,
         get_static as usize,
-    );
+    )
+    .with_flags(FunctionFlags::READ_ONLY);
     register_function(get_static_def);
 
     let add_to_static_and_fail_def = GuestFunctionDefinition::new(
@@ -1093,6 +1997,102 @@ pub extern "C" fn hyperlight_main() {
     );
     register_function(echo_double_def);
 
+    let cpuid_def = GuestFunctionDefinition::new(
+        "Cpuid".to_string(),
+        Vec::from(&[ParameterType::UInt, ParameterType::UInt]),
+        ReturnType::VecBytes,
+        cpuid as usize,
+    );
+    register_function(cpuid_def);
+
+    let read_msr_def = GuestFunctionDefinition::new(
+        "ReadMsr".to_string(),
+        Vec::from(&[ParameterType::UInt]),
+        ReturnType::ULong,
+        read_msr as usize,
+    );
+    register_function(read_msr_def);
+
+    let write_msr_def = GuestFunctionDefinition::new(
+        "WriteMsr".to_string(),
+        Vec::from(&[ParameterType::UInt, ParameterType::ULong]),
+        ReturnType::Void,
+        write_msr as usize,
+    );
+    register_function(write_msr_def);
+
+    let get_read_only_data_def = GuestFunctionDefinition::new(
+        "GetReadOnlyData".to_string(),
+        Vec::new(),
+        ReturnType::VecBytes,
+        get_read_only_data as usize,
+    );
+    register_function(get_read_only_data_def);
+
+    let write_read_only_data_def = GuestFunctionDefinition::new(
+        "WriteReadOnlyData".to_string(),
+        Vec::new(),
+        ReturnType::Void,
+        write_read_only_data as usize,
+    );
+    register_function(write_read_only_data_def);
+
+    let take_secret_def = GuestFunctionDefinition::new(
+        "TakeSecret".to_string(),
+        Vec::from(&[ParameterType::String]),
+        ReturnType::VecBytes,
+        take_secret as usize,
+    );
+    register_function(take_secret_def);
+
+    let spin_with_epoch_checks_def = GuestFunctionDefinition::new(
+        "SpinWithEpochChecks".to_string(),
+        Vec::new(),
+        ReturnType::Void,
+        spin_with_epoch_checks as usize,
+    );
+    register_function(spin_with_epoch_checks_def);
+
+    let sum_host_stream_def = GuestFunctionDefinition::new(
+        "SumHostStream".to_string(),
+        Vec::from(&[ParameterType::String, ParameterType::Int]),
+        ReturnType::ULong,
+        sum_host_stream as usize,
+    );
+    register_function(sum_host_stream_def);
+
+    let report_progress_def = GuestFunctionDefinition::new(
+        "ReportProgress".to_string(),
+        Vec::from(&[ParameterType::Int]),
+        ReturnType::Void,
+        report_progress as usize,
+    );
+    register_function(report_progress_def);
+
+    let work_until_deadline_def = GuestFunctionDefinition::new(
+        "WorkUntilDeadline".to_string(),
+        Vec::from(&[ParameterType::ULong]),
+        ReturnType::ULong,
+        work_until_deadline as usize,
+    );
+    register_function(work_until_deadline_def);
+
+    let add_with_avx_def = GuestFunctionDefinition::new(
+        "AddWithAvx".to_string(),
+        Vec::from(&[ParameterType::Double, ParameterType::Double]),
+        ReturnType::Double,
+        add_with_avx as usize,
+    );
+    register_function(add_with_avx_def);
+
+    let swap_ymm_upper_def = GuestFunctionDefinition::new(
+        "SwapYmmUpper".to_string(),
+        Vec::from(&[ParameterType::Double]),
+        ReturnType::Double,
+        swap_ymm_upper as usize,
+    );
+    register_function(swap_ymm_upper_def);
+
     let add_def = GuestFunctionDefinition::new(
         "Add".to_string(),
         Vec::from(&[ParameterType::Int, ParameterType::Int]),
@@ -1101,6 +2101,33 @@ pub extern "C" fn hyperlight_main() {
     );
     register_function(add_def);
 
+    let add_small_integers_def = GuestFunctionDefinition::new(
+        "AddSmallIntegers".to_string(),
+        Vec::from(&[ParameterType::Short, ParameterType::Byte]),
+        ReturnType::Short,
+        add_small_integers as usize,
+    );
+    register_function(add_small_integers_def);
+
+    let pack_bytes_def = GuestFunctionDefinition::new(
+        "PackBytes".to_string(),
+        Vec::from(&[ParameterType::UByte, ParameterType::UByte]),
+        ReturnType::UShort,
+        pack_bytes as usize,
+    );
+    register_function(pack_bytes_def);
+
+    // room for 0x6000 bytes and the flatbuffer around them, more than fit
+    // in the default output data buffer
+    let make_buffer_def = GuestFunctionDefinition::new(
+        "MakeBuffer".to_string(),
+        Vec::from(&[ParameterType::Int]),
+        ReturnType::VecBytes,
+        make_buffer as usize,
+    )
+    .with_max_result_size(0x6100);
+    register_function(make_buffer_def);
+
     let trigger_exception_def = GuestFunctionDefinition::new(
         "TriggerException".to_string(),
         Vec::new(),
@@ -1108,6 +2135,159 @@ pub extern "C" fn hyperlight_main() {
         trigger_exception as usize,
     );
     register_function(trigger_exception_def);
+
+    let double_fault_def = GuestFunctionDefinition::new(
+        "DoubleFault".to_string(),
+        Vec::new(),
+        ReturnType::Void,
+        double_fault as usize,
+    );
+    register_function(double_fault_def);
+
+    let triple_fault_def = GuestFunctionDefinition::new(
+        "TripleFault".to_string(),
+        Vec::new(),
+        ReturnType::Void,
+        triple_fault as usize,
+    );
+    register_function(triple_fault_def);
+
+    let write_to_code_def = GuestFunctionDefinition::new(
+        "WriteToCode".to_string(),
+        Vec::new(),
+        ReturnType::Void,
+        write_to_code as usize,
+    );
+    register_function(write_to_code_def);
+
+    let execute_data_def = GuestFunctionDefinition::new(
+        "ExecuteData".to_string(),
+        Vec::new(),
+        ReturnType::String,
+        execute_data as usize,
+    );
+    register_function(execute_data_def);
+
+    let run_jit_code_def = GuestFunctionDefinition::new(
+        "RunJitCode".to_string(),
+        Vec::new(),
+        ReturnType::Int,
+        run_jit_code as usize,
+    );
+    register_function(run_jit_code_def);
+
+    let return_to_unexpected_address_def = GuestFunctionDefinition::new(
+        "ReturnToUnexpectedAddress".to_string(),
+        Vec::new(),
+        ReturnType::Void,
+        return_to_unexpected_address as usize,
+    );
+    register_function(return_to_unexpected_address_def);
+
+    let get_random_def = GuestFunctionDefinition::new(
+        "GetRandom".to_string(),
+        Vec::new(),
+        ReturnType::ULong,
+        get_random as usize,
+    );
+    register_function(get_random_def);
+
+    let execute_rdrand_def = GuestFunctionDefinition::new(
+        "ExecuteRdrand".to_string(),
+        Vec::from(&[ParameterType::Bool]),
+        ReturnType::ULong,
+        execute_rdrand as usize,
+    );
+    register_function(execute_rdrand_def);
+
+    let read_tsc_def = GuestFunctionDefinition::new(
+        "ReadTsc".to_string(),
+        Vec::new(),
+        ReturnType::ULong,
+        read_tsc as usize,
+    );
+    register_function(read_tsc_def);
+
+    let write_to_port_def = GuestFunctionDefinition::new(
+        "WriteToPort".to_string(),
+        Vec::from(&[ParameterType::UInt, ParameterType::VecBytes]),
+        ReturnType::Void,
+        write_to_port as usize,
+    );
+    register_function(write_to_port_def);
+
+    let make_host_handle_def = GuestFunctionDefinition::new(
+        "MakeHostHandle".to_string(),
+        Vec::from(&[ParameterType::String]),
+        ReturnType::ULong,
+        make_host_handle as usize,
+    );
+    register_function(make_host_handle_def);
+
+    let host_handle_length_def = GuestFunctionDefinition::new(
+        "HostHandleLength".to_string(),
+        Vec::from(&[ParameterType::ULong]),
+        ReturnType::ULong,
+        host_handle_length as usize,
+    );
+    register_function(host_handle_length_def);
+
+    let trigger_handled_exception_def = GuestFunctionDefinition::new(
+        "TriggerHandledException".to_string(),
+        Vec::from(&[ParameterType::Bool]),
+        ReturnType::Int,
+        trigger_handled_exception as usize,
+    );
+    register_function(trigger_handled_exception_def);
+
+    // the same functions again, namespaced, for the namespace tests
+    register_namespaced_function(
+        "math",
+        GuestFunctionDefinition::new(
+            "EchoDouble".to_string(),
+            Vec::from(&[ParameterType::Double]),
+            ReturnType::Double,
+            echo_double as usize,
+        ),
+    );
+    register_namespaced_function(
+        "math",
+        GuestFunctionDefinition::new(
+            "EchoFloat".to_string(),
+            Vec::from(&[ParameterType::Float]),
+            ReturnType::Float,
+            echo_float as usize,
+        ),
+    );
+    register_namespaced_function(
+        "strings",
+        GuestFunctionDefinition::new(
+            "Echo".to_string(),
+            Vec::from(&[ParameterType::String]),
+            ReturnType::String,
+            echo as usize,
+        ),
+    );
+
+    // two versions of the same function, for the versioning tests
+    register_versioned_function(
+        1,
+        GuestFunctionDefinition::new(
+            "Compute".to_string(),
+            Vec::from(&[ParameterType::Int]),
+            ReturnType::Int,
+            compute_v1 as usize,
+        ),
+    );
+    register_versioned_function(
+        2,
+        GuestFunctionDefinition::new(
+            "Compute".to_string(),
+            Vec::from(&[ParameterType::Int, ParameterType::Int]),
+            ReturnType::Int,
+            compute_v2 as usize,
+        ),
+    );
 }
 
 #[no_mangle]